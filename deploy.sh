@@ -51,8 +51,11 @@ fi
 # Set database URL if not provided
 export DATABASE_URL=${DATABASE_URL:-"sqlite:todos.db"}
 
-# Set JWT secret if not provided (use a secure random key in production)
-export JWT_SECRET=${JWT_SECRET:-"your-secure-jwt-secret-change-in-production"}
+# The release build refuses to start without a JWT secret
+if [ -z "$JWT_SECRET" ] && [ -z "$JWT_SECRET_FILE" ]; then
+    echo "❌ Set JWT_SECRET (e.g. \$(openssl rand -base64 32)) or JWT_SECRET_FILE"
+    exit 1
+fi
 
 echo "📊 Configuration:"
 echo "   Database: $DATABASE_URL"
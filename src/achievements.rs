@@ -0,0 +1,114 @@
+use axum::async_trait;
+use chrono::{DateTime, Utc};
+use serde::Serialize;
+use sqlx::{Row, SqlitePool};
+use std::collections::HashMap;
+
+use crate::gamification;
+use crate::hooks::{Event, Hook, HookError};
+use crate::settings;
+use crate::simple_db::Database;
+
+enum Goal {
+    /// Todos completed, counted like gamification points: once per todo
+    Completed(i64),
+    /// Days in a row with a completion
+    Streak(u32),
+    /// Completing the last open personal todo
+    InboxZero,
+}
+
+struct Achievement {
+    id: &'static str,
+    name: &'static str,
+    description: &'static str,
+    goal: Goal,
+}
+
+/// Every achievement there is, in the order they're listed.
+const ACHIEVEMENTS: &[Achievement] = &[
+    Achievement { id: "first_completion", name: "Off the mark", description: "Complete your first todo", goal: Goal::Completed(1) },
+    Achievement { id: "completed_10", name: "Getting things done", description: "Complete 10 todos", goal: Goal::Completed(10) },
+    Achievement { id: "completed_100", name: "Centurion", description: "Complete 100 todos", goal: Goal::Completed(100) },
+    Achievement { id: "completed_1000", name: "Unstoppable", description: "Complete 1,000 todos", goal: Goal::Completed(1000) },
+    Achievement { id: "streak_7", name: "Week streak", description: "Complete a todo 7 days in a row", goal: Goal::Streak(7) },
+    Achievement { id: "streak_30", name: "Month streak", description: "Complete a todo 30 days in a row", goal: Goal::Streak(30) },
+    Achievement { id: "inbox_zero", name: "Inbox zero", description: "Complete your last open personal todo", goal: Goal::InboxZero },
+];
+
+/// An achievement as listed by `GET /achievements`.
+#[derive(Debug, Serialize)]
+pub struct Status {
+    pub id: &'static str,
+    pub name: &'static str,
+    pub description: &'static str,
+    /// `null` while still locked
+    pub unlocked_at: Option<DateTime<Utc>>,
+}
+
+/// Unlocks achievements as todos are completed. Registered after
+/// `GamificationHook`, whose awards it counts.
+pub struct AchievementHook;
+
+#[async_trait]
+impl Hook for AchievementHook {
+    fn name(&self) -> &'static str {
+        "achievements"
+    }
+
+    async fn handle(&self, db: &Database, event: &Event<'_>) -> Result<(), HookError> {
+        let Event::TodoCompleted { todo, actor_id } = *event else {
+            return Ok(());
+        };
+
+        let tz = settings::get_settings(db.get_pool(), actor_id).await?.tz();
+        let summary = gamification::summary(db.get_pool(), actor_id, Utc::now().with_timezone(&tz).date_naive()).await?;
+        let inbox_zero = todo.workspace_id.is_none() && {
+            let open: i64 = sqlx::query("SELECT COUNT(*) FROM todos WHERE user_id = ? AND workspace_id IS NULL AND completed = FALSE")
+                .bind(actor_id)
+                .fetch_one(db.get_pool())
+                .await?
+                .get(0);
+            open == 0
+        };
+
+        let now = Utc::now();
+        for achievement in ACHIEVEMENTS {
+            let reached = match achievement.goal {
+                Goal::Completed(count) => summary.completed >= count,
+                Goal::Streak(days) => summary.longest_streak >= days,
+                Goal::InboxZero => inbox_zero,
+            };
+            if reached {
+                sqlx::query("INSERT OR IGNORE INTO achievements (user_id, achievement, unlocked_at) VALUES (?, ?, ?)")
+                    .bind(actor_id)
+                    .bind(achievement.id)
+                    .bind(now)
+                    .execute(&mut *db.write().await?)
+                    .await?;
+            }
+        }
+        Ok(())
+    }
+}
+
+/// Every achievement, with when the user unlocked it.
+pub async fn list(pool: &SqlitePool, user_id: &str) -> Result<Vec<Status>, sqlx::Error> {
+    let unlocked: HashMap<String, DateTime<Utc>> = sqlx::query("SELECT achievement, unlocked_at FROM achievements WHERE user_id = ?")
+        .bind(user_id)
+        .fetch_all(pool)
+        .await?
+        .iter()
+        .map(|row| (row.get("achievement"), row.get("unlocked_at")))
+        .collect();
+
+    Ok(ACHIEVEMENTS
+        .iter()
+        .map(|achievement| Status {
+            id: achievement.id,
+            name: achievement.name,
+            description: achievement.description,
+            unlocked_at: unlocked.get(achievement.id).copied(),
+        })
+        .collect())
+}
@@ -0,0 +1,470 @@
+use base64::{engine::general_purpose::URL_SAFE_NO_PAD, Engine};
+use chrono::{DateTime, Duration, NaiveDateTime, Utc};
+use ring::{
+    digest::{digest, SHA256},
+    rand::SystemRandom,
+    signature::{EcdsaKeyPair, KeyPair, ECDSA_P256_SHA256_FIXED_SIGNING},
+};
+use rustls::{
+    server::{ClientHello, ResolvesServerCert},
+    sign::CertifiedKey,
+    Certificate, PrivateKey, ServerConfig,
+};
+use serde::Deserialize;
+use serde_json::{json, Value};
+use std::{
+    fs, io,
+    path::{Path, PathBuf},
+    sync::{Arc, RwLock},
+};
+
+pub const LETS_ENCRYPT_DIRECTORY: &str = "https://acme-v02.api.letsencrypt.org/directory";
+
+/// ALPN protocol the ACME server uses for tls-alpn-01 validation (RFC 8737)
+const ACME_TLS_ALPN: &[u8] = b"acme-tls/1";
+
+/// Certificates are renewed once they are this close to expiring
+const RENEW_BEFORE_DAYS: i64 = 30;
+
+type AcmeResult<T> = Result<T, Box<dyn std::error::Error + Send + Sync>>;
+
+pub struct AcmeConfig {
+    pub domain: String,
+    pub contact: Option<String>,
+    pub directory_url: String,
+    /// Where the account key and issued certificate are kept between restarts
+    pub cache_dir: PathBuf,
+}
+
+/// Serves the ACME-issued certificate, or the tls-alpn-01 challenge certificate
+/// when the validation server connects with the `acme-tls/1` protocol.
+#[derive(Default)]
+pub struct AcmeResolver {
+    certificate: RwLock<Option<Arc<CertifiedKey>>>,
+    challenge: RwLock<Option<Arc<CertifiedKey>>>,
+}
+
+impl ResolvesServerCert for AcmeResolver {
+    fn resolve(&self, client_hello: ClientHello) -> Option<Arc<CertifiedKey>> {
+        let is_challenge = client_hello
+            .alpn()
+            .is_some_and(|mut protocols| protocols.any(|protocol| protocol == ACME_TLS_ALPN));
+
+        if is_challenge {
+            self.challenge.read().unwrap().clone()
+        } else {
+            self.certificate.read().unwrap().clone()
+        }
+    }
+}
+
+pub fn server_config(resolver: Arc<AcmeResolver>) -> Arc<ServerConfig> {
+    let mut config = ServerConfig::builder()
+        .with_safe_defaults()
+        .with_no_client_auth()
+        .with_cert_resolver(resolver);
+    config.alpn_protocols = vec![b"h2".to_vec(), b"http/1.1".to_vec(), ACME_TLS_ALPN.to_vec()];
+    Arc::new(config)
+}
+
+/// Provisions the certificate in the background and keeps renewing it.
+/// Until the first certificate is available, regular TLS handshakes fail.
+pub fn spawn(config: AcmeConfig, resolver: Arc<AcmeResolver>) {
+    tokio::spawn(async move {
+        loop {
+            let wait = match ensure_certificate(&config, &resolver).await {
+                Ok(()) => std::time::Duration::from_secs(12 * 60 * 60),
+                Err(e) => {
+                    tracing::error!(domain = %config.domain, error = %e, "ACME certificate provisioning failed");
+                    std::time::Duration::from_secs(60 * 60)
+                }
+            };
+            tokio::time::sleep(wait).await;
+        }
+    });
+}
+
+async fn ensure_certificate(config: &AcmeConfig, resolver: &AcmeResolver) -> AcmeResult<()> {
+    fs::create_dir_all(&config.cache_dir)?;
+    let cert_path = config.cache_dir.join(format!("{}.crt.pem", config.domain));
+    let key_path = config.cache_dir.join(format!("{}.key.pem", config.domain));
+
+    // Serve a cached certificate straight away, and keep it unless it expires soon
+    if let (Ok(chain_pem), Ok(key_pem)) = (fs::read_to_string(&cert_path), fs::read_to_string(&key_path)) {
+        let (certified_key, not_after) = certified_key_from_pem(&chain_pem, &key_pem)?;
+        *resolver.certificate.write().unwrap() = Some(certified_key);
+
+        if not_after - Utc::now() > Duration::days(RENEW_BEFORE_DAYS) {
+            return Ok(());
+        }
+        tracing::info!(domain = %config.domain, %not_after, "Certificate expires soon, renewing");
+    }
+
+    let (chain_pem, key_pem) = obtain_certificate(config, resolver).await?;
+    let (certified_key, not_after) = certified_key_from_pem(&chain_pem, &key_pem)?;
+    fs::write(&cert_path, &chain_pem)?;
+    write_private(&key_path, key_pem.as_bytes())?;
+    *resolver.certificate.write().unwrap() = Some(certified_key);
+
+    tracing::info!(domain = %config.domain, %not_after, "Obtained certificate");
+    Ok(())
+}
+
+#[derive(Deserialize)]
+#[serde(rename_all = "camelCase")]
+struct Directory {
+    new_nonce: String,
+    new_account: String,
+    new_order: String,
+}
+
+#[derive(Deserialize)]
+struct Order {
+    status: String,
+    authorizations: Vec<String>,
+    finalize: String,
+    certificate: Option<String>,
+}
+
+#[derive(Deserialize)]
+struct Authorization {
+    status: String,
+    challenges: Vec<Challenge>,
+}
+
+#[derive(Deserialize)]
+struct Challenge {
+    #[serde(rename = "type")]
+    kind: String,
+    url: String,
+    token: String,
+}
+
+/// Minimal RFC 8555 client signing requests with an ES256 account key.
+struct AcmeClient {
+    http: reqwest::Client,
+    directory: Directory,
+    key: EcdsaKeyPair,
+    rng: SystemRandom,
+    kid: Option<String>,
+}
+
+impl AcmeClient {
+    fn jwk(&self) -> Value {
+        // Uncompressed P-256 point: 0x04 || x || y
+        let point = self.key.public_key().as_ref();
+        json!({
+            "crv": "P-256",
+            "kty": "EC",
+            "x": URL_SAFE_NO_PAD.encode(&point[1..33]),
+            "y": URL_SAFE_NO_PAD.encode(&point[33..65]),
+        })
+    }
+
+    /// RFC 7638 thumbprint; serde_json keeps object keys sorted, as the RFC requires.
+    fn thumbprint(&self) -> String {
+        URL_SAFE_NO_PAD.encode(digest(&SHA256, self.jwk().to_string().as_bytes()))
+    }
+
+    async fn nonce(&self) -> AcmeResult<String> {
+        let response = self.http.head(&self.directory.new_nonce).send().await?;
+        let nonce = response
+            .headers()
+            .get("replay-nonce")
+            .and_then(|value| value.to_str().ok())
+            .ok_or("ACME server returned no nonce")?;
+        Ok(nonce.to_string())
+    }
+
+    /// Signed POST; `None` as payload makes it a POST-as-GET.
+    async fn post(&self, url: &str, payload: Option<&Value>) -> AcmeResult<reqwest::Response> {
+        let mut protected = json!({ "alg": "ES256", "nonce": self.nonce().await?, "url": url });
+        match &self.kid {
+            Some(kid) => protected["kid"] = json!(kid),
+            None => protected["jwk"] = self.jwk(),
+        }
+
+        let protected = URL_SAFE_NO_PAD.encode(protected.to_string());
+        let payload = payload
+            .map(|payload| URL_SAFE_NO_PAD.encode(payload.to_string()))
+            .unwrap_or_default();
+        let signature = self
+            .key
+            .sign(&self.rng, format!("{}.{}", protected, payload).as_bytes())
+            .map_err(|_| "failed to sign ACME request")?;
+        let body = json!({
+            "protected": protected,
+            "payload": payload,
+            "signature": URL_SAFE_NO_PAD.encode(signature.as_ref()),
+        });
+
+        let response = self
+            .http
+            .post(url)
+            .header("Content-Type", "application/jose+json")
+            .body(body.to_string())
+            .send()
+            .await?;
+        if !response.status().is_success() {
+            return Err(format!("ACME request to {} failed: {}", url, response.text().await?).into());
+        }
+        Ok(response)
+    }
+
+    async fn post_json<T: serde::de::DeserializeOwned>(&self, url: &str, payload: Option<&Value>) -> AcmeResult<T> {
+        let response = self.post(url, payload).await?;
+        Ok(serde_json::from_str(&response.text().await?)?)
+    }
+}
+
+fn location(response: &reqwest::Response) -> AcmeResult<String> {
+    let location = response
+        .headers()
+        .get("location")
+        .and_then(|value| value.to_str().ok())
+        .ok_or("ACME response is missing the Location header")?;
+    Ok(location.to_string())
+}
+
+/// Runs a full order for `config.domain` and returns the PEM certificate chain and private key.
+async fn obtain_certificate(config: &AcmeConfig, resolver: &AcmeResolver) -> AcmeResult<(String, String)> {
+    let http = reqwest::Client::new();
+    let directory: Directory = serde_json::from_str(&http.get(&config.directory_url).send().await?.error_for_status()?.text().await?)?;
+    let key = load_or_create_account_key(config)?;
+    let mut client = AcmeClient { http, directory, key, rng: SystemRandom::new(), kid: None };
+
+    let mut account = json!({ "termsOfServiceAgreed": true });
+    if let Some(contact) = &config.contact {
+        account["contact"] = json!([format!("mailto:{}", contact)]);
+    }
+    let response = client.post(&client.directory.new_account, Some(&account)).await?;
+    client.kid = Some(location(&response)?);
+
+    let identifiers = json!({ "identifiers": [{ "type": "dns", "value": config.domain }] });
+    let response = client.post(&client.directory.new_order, Some(&identifiers)).await?;
+    let order_url = location(&response)?;
+    let order: Order = serde_json::from_str(&response.text().await?)?;
+
+    for authorization_url in &order.authorizations {
+        let authorization: Authorization = client.post_json(authorization_url, None).await?;
+        if authorization.status == "valid" {
+            continue;
+        }
+
+        let challenge = authorization
+            .challenges
+            .iter()
+            .find(|challenge| challenge.kind == "tls-alpn-01")
+            .ok_or("ACME server offered no tls-alpn-01 challenge")?;
+        let key_authorization = format!("{}.{}", challenge.token, client.thumbprint());
+        *resolver.challenge.write().unwrap() = Some(challenge_certificate(&config.domain, &key_authorization)?);
+
+        client.post(&challenge.url, Some(&json!({}))).await?;
+        let result = poll(|| async {
+            let authorization: Authorization = client.post_json(authorization_url, None).await?;
+            match authorization.status.as_str() {
+                "valid" => Ok(Some(())),
+                "pending" | "processing" => Ok(None),
+                status => Err(format!("authorization for {} is {}", config.domain, status).into()),
+            }
+        })
+        .await;
+        *resolver.challenge.write().unwrap() = None;
+        result?;
+    }
+
+    let mut params = rcgen::CertificateParams::new(vec![config.domain.clone()]);
+    params.distinguished_name = rcgen::DistinguishedName::new();
+    let certificate_key = rcgen::Certificate::from_params(params)?;
+    let csr = certificate_key.serialize_request_der()?;
+    client.post(&order.finalize, Some(&json!({ "csr": URL_SAFE_NO_PAD.encode(csr) }))).await?;
+
+    let certificate_url = poll(|| async {
+        let order: Order = client.post_json(&order_url, None).await?;
+        match order.status.as_str() {
+            "valid" => Ok(Some(order.certificate.ok_or("valid order has no certificate URL")?)),
+            "pending" | "ready" | "processing" => Ok(None),
+            status => Err(format!("order for {} is {}", config.domain, status).into()),
+        }
+    })
+    .await?;
+
+    let chain_pem = client.post(&certificate_url, None).await?.text().await?;
+    Ok((chain_pem, certificate_key.serialize_private_key_pem()))
+}
+
+/// Retries `check` every two seconds until it yields a value, giving up after a minute.
+async fn poll<T, F, Fut>(check: F) -> AcmeResult<T>
+where
+    F: Fn() -> Fut,
+    Fut: std::future::Future<Output = AcmeResult<Option<T>>>,
+{
+    for _ in 0..30 {
+        if let Some(value) = check().await? {
+            return Ok(value);
+        }
+        tokio::time::sleep(std::time::Duration::from_secs(2)).await;
+    }
+    Err("timed out waiting for the ACME server".into())
+}
+
+fn load_or_create_account_key(config: &AcmeConfig) -> AcmeResult<EcdsaKeyPair> {
+    let path = config.cache_dir.join("account.key");
+    let rng = SystemRandom::new();
+
+    let pkcs8 = match fs::read(&path) {
+        Ok(pkcs8) => pkcs8,
+        Err(e) if e.kind() == io::ErrorKind::NotFound => {
+            let pkcs8 = EcdsaKeyPair::generate_pkcs8(&ECDSA_P256_SHA256_FIXED_SIGNING, &rng)
+                .map_err(|_| "failed to generate ACME account key")?;
+            write_private(&path, pkcs8.as_ref())?;
+            pkcs8.as_ref().to_vec()
+        }
+        // Anything else, e.g. a permissions problem, would lose the account if we made a new key
+        Err(e) => return Err(format!("failed to read ACME account key {}: {}", path.display(), e).into()),
+    };
+
+    Ok(EcdsaKeyPair::from_pkcs8(&ECDSA_P256_SHA256_FIXED_SIGNING, &pkcs8, &rng).map_err(|_| "invalid ACME account key")?)
+}
+
+/// Writes a private key readable by its owner only, like the JWT secret. A
+/// file an earlier version left readable by others is narrowed too, since
+/// the mode only applies when a file is created.
+fn write_private(path: &Path, contents: &[u8]) -> io::Result<()> {
+    let mut options = fs::OpenOptions::new();
+    options.write(true).create(true).truncate(true);
+    #[cfg(unix)]
+    std::os::unix::fs::OpenOptionsExt::mode(&mut options, 0o600);
+    let mut file = options.open(path)?;
+    #[cfg(unix)]
+    file.set_permissions(std::os::unix::fs::PermissionsExt::from_mode(0o600))?;
+    io::Write::write_all(&mut file, contents)
+}
+
+/// Self-signed certificate carrying the acmeIdentifier extension for tls-alpn-01.
+fn challenge_certificate(domain: &str, key_authorization: &str) -> AcmeResult<Arc<CertifiedKey>> {
+    let mut params = rcgen::CertificateParams::new(vec![domain.to_string()]);
+    params.custom_extensions = vec![rcgen::CustomExtension::new_acme_identifier(
+        digest(&SHA256, key_authorization.as_bytes()).as_ref(),
+    )];
+    let certificate = rcgen::Certificate::from_params(params)?;
+
+    let key = rustls::sign::any_supported_type(&PrivateKey(certificate.serialize_private_key_der()))?;
+    Ok(Arc::new(CertifiedKey::new(vec![Certificate(certificate.serialize_der()?)], key)))
+}
+
+fn certified_key_from_pem(chain_pem: &str, key_pem: &str) -> AcmeResult<(Arc<CertifiedKey>, DateTime<Utc>)> {
+    let chain: Vec<Certificate> = rustls_pemfile::certs(&mut chain_pem.as_bytes())?
+        .into_iter()
+        .map(Certificate)
+        .collect();
+    let key_der = rustls_pemfile::pkcs8_private_keys(&mut key_pem.as_bytes())?
+        .into_iter()
+        .next()
+        .ok_or("no PKCS8 private key found")?;
+
+    let not_after = chain
+        .first()
+        .and_then(|leaf| not_after(&leaf.0))
+        .ok_or("could not read certificate expiry")?;
+    let key = rustls::sign::any_supported_type(&PrivateKey(key_der))?;
+    Ok((Arc::new(CertifiedKey::new(chain, key)), not_after))
+}
+
+/// Splits one DER element off `input`, returning its tag, contents and the remaining bytes.
+fn der_element(input: &[u8]) -> Option<(u8, &[u8], &[u8])> {
+    let (&tag, rest) = input.split_first()?;
+    let (&first, rest) = rest.split_first()?;
+
+    let (len, rest) = if first < 0x80 {
+        (first as usize, rest)
+    } else {
+        let count = (first & 0x7f) as usize;
+        if count > std::mem::size_of::<usize>() || rest.len() < count {
+            return None;
+        }
+        let len = rest[..count].iter().fold(0usize, |len, &byte| (len << 8) | byte as usize);
+        (len, &rest[count..])
+    };
+
+    (rest.len() >= len).then(|| (tag, &rest[..len], &rest[len..]))
+}
+
+/// Reads the validity end out of a DER-encoded X.509 certificate.
+fn not_after(cert_der: &[u8]) -> Option<DateTime<Utc>> {
+    let (_, certificate, _) = der_element(cert_der)?;
+    let (_, mut tbs, _) = der_element(certificate)?;
+
+    // Skip the optional [0] version, then serialNumber, signature and issuer
+    if tbs.first() == Some(&0xa0) {
+        tbs = der_element(tbs)?.2;
+    }
+    for _ in 0..3 {
+        tbs = der_element(tbs)?.2;
+    }
+
+    let (_, validity, _) = der_element(tbs)?;
+    let (_, _, validity) = der_element(validity)?;
+    let (tag, time, _) = der_element(validity)?;
+    let time = std::str::from_utf8(time).ok()?;
+
+    let format = match tag {
+        0x17 => "%y%m%d%H%M%SZ",
+        0x18 => "%Y%m%d%H%M%SZ",
+        _ => return None,
+    };
+    NaiveDateTime::parse_from_str(time, format).ok().map(|time| time.and_utc())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn certificate_expiring(year: i32, month: u8, day: u8) -> Vec<u8> {
+        let mut params = rcgen::CertificateParams::new(vec!["example.com".to_string()]);
+        params.not_after = rcgen::date_time_ymd(year, month, day);
+        rcgen::Certificate::from_params(params).unwrap().serialize_der().unwrap()
+    }
+
+    #[test]
+    fn splits_der_elements() {
+        assert_eq!(der_element(&[0x04, 0x02, 0xaa, 0xbb, 0xff]), Some((0x04, &[0xaa, 0xbb][..], &[0xff][..])));
+
+        // Long form: 0x81 says one length byte follows
+        let mut long = vec![0x30, 0x81, 0x80];
+        long.extend([0x01; 0x80]);
+        let (tag, contents, rest) = der_element(&long).unwrap();
+        assert_eq!((tag, contents.len(), rest.len()), (0x30, 0x80, 0));
+
+        // Shorter than the length says, or with a length that can't fit
+        assert_eq!(der_element(&[0x04, 0x03, 0xaa]), None);
+        assert_eq!(der_element(&[0x30, 0x82, 0x01]), None);
+        assert_eq!(der_element(&[0x30, 0x89, 1, 1, 1, 1, 1, 1, 1, 1, 1]), None);
+        assert_eq!(der_element(&[0x04]), None);
+    }
+
+    #[test]
+    fn reads_certificate_expiry() {
+        // UTCTime until 2049, GeneralizedTime from 2050 on
+        assert_eq!(not_after(&certificate_expiring(2031, 3, 4)), Some("2031-03-04T00:00:00Z".parse().unwrap()));
+        assert_eq!(not_after(&certificate_expiring(2051, 12, 31)), Some("2051-12-31T00:00:00Z".parse().unwrap()));
+
+        let der = certificate_expiring(2031, 3, 4);
+        assert_eq!(not_after(&der[..der.len() / 2]), None);
+        assert_eq!(not_after(b"not a certificate"), None);
+    }
+
+    #[test]
+    fn challenge_certificate_carries_the_key_authorization_digest() {
+        let key_authorization = "token.thumbprint";
+        let challenge = challenge_certificate("example.com", key_authorization).unwrap();
+        let der = &challenge.cert[0].0;
+
+        // id-pe-acmeIdentifier (1.3.6.1.5.5.7.1.31), critical, holding OCTET STRING(SHA-256(key authorization))
+        let mut extension = vec![0x06, 0x08, 0x2b, 0x06, 0x01, 0x05, 0x05, 0x07, 0x01, 0x1f, 0x01, 0x01, 0xff, 0x04, 0x22, 0x04, 0x20];
+        extension.extend_from_slice(digest(&SHA256, key_authorization.as_bytes()).as_ref());
+        assert!(der.windows(extension.len()).any(|window| window == extension));
+        assert!(der.windows(b"example.com".len()).any(|window| window == b"example.com"));
+        assert!(not_after(der).is_some());
+    }
+}
@@ -0,0 +1,141 @@
+use chrono::{DateTime, Duration, Utc};
+use serde::Serialize;
+use sqlx::{Row, SqlitePool};
+use std::collections::HashMap;
+
+use crate::jobs::Scheduler;
+
+/// What `GET /admin/stats` reports about the whole deployment.
+#[derive(Debug, Serialize)]
+pub struct AdminStats {
+    pub generated_at: DateTime<Utc>,
+    pub users: UserCounts,
+    pub active_users: ActiveUsers,
+    pub todos: TodoCounts,
+    pub database: DatabaseSize,
+    pub jobs: Vec<JobHealth>,
+}
+
+#[derive(Debug, Serialize)]
+pub struct UserCounts {
+    pub total: i64,
+    pub admins: i64,
+    pub disabled: i64,
+    pub new_last_7_days: i64,
+    pub new_last_30_days: i64,
+}
+
+/// Users who signed in or created or edited a todo in the period.
+#[derive(Debug, Serialize)]
+pub struct ActiveUsers {
+    pub last_day: i64,
+    pub last_7_days: i64,
+    pub last_30_days: i64,
+}
+
+#[derive(Debug, Serialize)]
+pub struct TodoCounts {
+    pub total: i64,
+    pub open: i64,
+    pub completed: i64,
+    pub overdue: i64,
+    pub created_last_7_days: i64,
+}
+
+/// The main database file; the WAL file comes on top until checkpointed.
+#[derive(Debug, Serialize)]
+pub struct DatabaseSize {
+    pub size_bytes: i64,
+    /// Space held by deleted rows, which `VACUUM` would give back
+    pub free_bytes: i64,
+}
+
+#[derive(Debug, Serialize)]
+pub struct JobHealth {
+    pub name: &'static str,
+    pub schedule: Option<String>,
+    pub running: bool,
+    /// Status of the latest run, `null` if it never ran
+    pub last_status: Option<String>,
+    pub last_run_at: Option<DateTime<Utc>>,
+    pub failures_last_day: i64,
+    /// The latest run didn't fail
+    pub healthy: bool,
+}
+
+pub async fn collect(pool: &SqlitePool, scheduler: &Scheduler) -> Result<AdminStats, sqlx::Error> {
+    let now = Utc::now();
+    let (day_ago, week_ago, month_ago) = (now - Duration::days(1), now - Duration::days(7), now - Duration::days(30));
+
+    let row = sqlx::query("SELECT COUNT(*) AS total, COALESCE(SUM(is_admin), 0) AS admins, COUNT(disabled_at) AS disabled, COALESCE(SUM(created_at >= ?1), 0) AS new_week, COALESCE(SUM(created_at >= ?2), 0) AS new_month FROM users")
+        .bind(week_ago)
+        .bind(month_ago)
+        .fetch_one(pool)
+        .await?;
+    let users = UserCounts {
+        total: row.get("total"),
+        admins: row.get("admins"),
+        disabled: row.get("disabled"),
+        new_last_7_days: row.get("new_week"),
+        new_last_30_days: row.get("new_month"),
+    };
+
+    // Failed logins and lockouts are attempts, not activity
+    let row = sqlx::query("SELECT COUNT(DISTINCT IIF(at >= ?1, user_id, NULL)) AS day, COUNT(DISTINCT IIF(at >= ?2, user_id, NULL)) AS week, COUNT(DISTINCT user_id) AS month FROM (SELECT user_id, created_at AS at FROM auth_events WHERE created_at >= ?3 AND kind NOT IN ('login_failed', 'account_locked') UNION ALL SELECT user_id, updated_at AS at FROM todos WHERE updated_at >= ?3)")
+        .bind(day_ago)
+        .bind(week_ago)
+        .bind(month_ago)
+        .fetch_one(pool)
+        .await?;
+    let active_users = ActiveUsers {
+        last_day: row.get("day"),
+        last_7_days: row.get("week"),
+        last_30_days: row.get("month"),
+    };
+
+    let row = sqlx::query("SELECT COUNT(*) AS total, COALESCE(SUM(completed), 0) AS completed, COALESCE(SUM(NOT completed AND due_date < ?1), 0) AS overdue, COALESCE(SUM(created_at >= ?2), 0) AS new_week FROM todos")
+        .bind(now)
+        .bind(week_ago)
+        .fetch_one(pool)
+        .await?;
+    let (total, completed): (i64, i64) = (row.get("total"), row.get("completed"));
+    let todos = TodoCounts {
+        total,
+        open: total - completed,
+        completed,
+        overdue: row.get("overdue"),
+        created_last_7_days: row.get("new_week"),
+    };
+
+    let row = sqlx::query("SELECT page_count * page_size AS size, freelist_count * page_size AS free FROM pragma_page_count(), pragma_page_size(), pragma_freelist_count()")
+        .fetch_one(pool)
+        .await?;
+    let database = DatabaseSize { size_bytes: row.get("size"), free_bytes: row.get("free") };
+
+    let failures: HashMap<String, i64> = sqlx::query("SELECT job, COUNT(*) AS failures FROM job_runs WHERE status = 'failed' AND started_at >= ? GROUP BY job")
+        .bind(day_ago)
+        .fetch_all(pool)
+        .await?
+        .iter()
+        .map(|row| (row.get("job"), row.get("failures")))
+        .collect();
+    let jobs = scheduler
+        .list(pool)
+        .await?
+        .into_iter()
+        .map(|job| {
+            let last_status = job.last_run.as_ref().map(|run| run.status.clone());
+            JobHealth {
+                name: job.name,
+                schedule: job.schedule,
+                running: job.running,
+                healthy: last_status.as_deref() != Some("failed"),
+                last_status,
+                last_run_at: job.last_run.map(|run| run.started_at),
+                failures_last_day: failures.get(job.name).copied().unwrap_or(0),
+            }
+        })
+        .collect();
+
+    Ok(AdminStats { generated_at: now, users, active_users, todos, database, jobs })
+}
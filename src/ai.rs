@@ -0,0 +1,120 @@
+use chrono::{DateTime, Utc};
+use chrono_tz::Tz;
+use serde::{Deserialize, Serialize};
+use serde_json::{json, Value};
+use std::time::Duration;
+
+use crate::simple_db::Priority;
+
+/// Longest text accepted for parsing, in characters.
+pub const MAX_INPUT_CHARS: usize = 4000;
+
+/// At most this many suggestions are returned for one text.
+const MAX_SUGGESTIONS: usize = 20;
+
+const DEFAULT_API_URL: &str = "https://api.openai.com/v1";
+const DEFAULT_MODEL: &str = "gpt-4o-mini";
+
+const INSTRUCTIONS: &str = "You turn free-form notes into todo items. Reply with a JSON object \
+{\"todos\": [...]}, one entry per distinct task, each with \"text\" (short and imperative) and, \
+only when the notes say so, \"notes\", \"category\", \"tags\" (array of single words), \
+\"priority\" (\"high\", \"medium\" or \"low\") and \"due_date\" (RFC 3339, resolved against the \
+current time given, in the user's timezone). Do not invent details.";
+
+/// A todo the model read out of the text, shaped like the body of
+/// `POST /todos` so clients can send it back once the user confirms it.
+#[derive(Debug, Serialize, Deserialize)]
+pub struct Suggestion {
+    pub text: String,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub notes: Option<String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub category: Option<String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub tags: Option<Vec<String>>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub priority: Option<Priority>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub due_date: Option<DateTime<Utc>>,
+}
+
+#[derive(Debug)]
+pub struct ParseError(pub String);
+
+impl std::fmt::Display for ParseError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.write_str(&self.0)
+    }
+}
+
+/// Reads todos out of free-form text with an OpenAI-compatible chat
+/// completions API:
+///
+/// - `AI_API_KEY`: required; without it `POST /todos/parse` is 404
+/// - `AI_API_URL`: the API base (default `https://api.openai.com/v1`), for
+///   other providers or a local server
+/// - `AI_MODEL`: default `gpt-4o-mini`
+#[derive(Clone)]
+pub struct Parser {
+    http: reqwest::Client,
+    api_url: String,
+    api_key: String,
+    model: String,
+}
+
+impl Parser {
+    pub fn from_env() -> Option<Self> {
+        let api_key = std::env::var("AI_API_KEY").ok().filter(|key| !key.is_empty())?;
+        let api_url = std::env::var("AI_API_URL").ok().filter(|url| !url.is_empty()).unwrap_or_else(|| DEFAULT_API_URL.to_string());
+        let model = std::env::var("AI_MODEL").ok().filter(|model| !model.is_empty()).unwrap_or_else(|| DEFAULT_MODEL.to_string());
+        let http = reqwest::Client::builder()
+            .timeout(Duration::from_secs(30))
+            .build()
+            .expect("Failed to build HTTP client");
+        Some(Parser { http, api_url: api_url.trim_end_matches('/').to_string(), api_key, model })
+    }
+
+    /// Suggested todos for `text`, with due dates resolved relative to `now`.
+    /// Nothing is saved. Suggestions without text are dropped.
+    pub async fn parse(&self, text: &str, now: DateTime<Tz>) -> Result<Vec<Suggestion>, ParseError> {
+        let request = json!({
+            "model": self.model,
+            "response_format": { "type": "json_object" },
+            "temperature": 0,
+            "messages": [
+                { "role": "system", "content": INSTRUCTIONS },
+                { "role": "user", "content": format!("Current time: {} ({})\n\n{}", now.to_rfc3339(), now.timezone().name(), text) },
+            ],
+        });
+        let response = self
+            .http
+            .post(format!("{}/chat/completions", self.api_url))
+            .bearer_auth(&self.api_key)
+            .header("Content-Type", "application/json")
+            .body(request.to_string())
+            .send()
+            .await
+            .and_then(|response| response.error_for_status())
+            .map_err(|e| ParseError(e.without_url().to_string()))?;
+        let body: Value = response
+            .bytes()
+            .await
+            .ok()
+            .and_then(|bytes| serde_json::from_slice(&bytes).ok())
+            .ok_or_else(|| ParseError("unreadable response".to_string()))?;
+
+        let content = body["choices"][0]["message"]["content"]
+            .as_str()
+            .ok_or_else(|| ParseError("no completion in response".to_string()))?;
+        let content: Value = serde_json::from_str(content).map_err(|e| ParseError(format!("completion is not JSON: {}", e)))?;
+        let todos = content["todos"].as_array().cloned().unwrap_or_default();
+
+        // Entries that don't fit are skipped rather than failing the rest
+        Ok(todos
+            .into_iter()
+            .filter_map(|todo| serde_json::from_value::<Suggestion>(todo).ok())
+            .filter(|suggestion| !suggestion.text.trim().is_empty())
+            .take(MAX_SUGGESTIONS)
+            .collect())
+    }
+}
@@ -0,0 +1,101 @@
+use axum::{
+    async_trait,
+    extract::{ConnectInfo, FromRequestParts},
+    http::{header::USER_AGENT, request::Parts},
+};
+use chrono::{DateTime, Utc};
+use serde::Serialize;
+use sqlx::{sqlite::SqliteRow, Row, SqlitePool};
+use std::convert::Infallible;
+use std::net::SocketAddr;
+use uuid::Uuid;
+
+use crate::pagination::Cursor;
+use crate::rate_limit;
+use crate::simple_db::Database;
+
+/// Where a request came from, for the audit log. Both parts are `None` for
+/// events from the command line.
+#[derive(Clone, Debug, Default)]
+pub struct Client {
+    pub ip: Option<String>,
+    pub user_agent: Option<String>,
+}
+
+#[async_trait]
+impl<S: Send + Sync> FromRequestParts<S> for Client {
+    type Rejection = Infallible;
+
+    async fn from_request_parts(parts: &mut Parts, _state: &S) -> Result<Self, Self::Rejection> {
+        let connect_info = parts.extensions.get::<ConnectInfo<SocketAddr>>();
+        Ok(Client {
+            ip: rate_limit::client_ip(&parts.headers, connect_info, rate_limit::trust_forwarded_for()),
+            user_agent: parts.headers.get(USER_AGENT).and_then(|value| value.to_str().ok()).map(String::from),
+        })
+    }
+}
+
+/// An entry in the `auth_events` audit log.
+#[derive(Debug, Serialize)]
+pub struct AuthEvent {
+    pub id: String,
+    /// What happened: `register`, `login`, `login_failed`, `logout`,
+    /// `password_changed`, `password_reset`, `workspace_switched`,
+    /// `device_approved`, `device_denied`, `device_login`, `data_exported`, `account_disabled`, `account_enabled`,
+    /// `admin_granted`, `admin_revoked`, `account_locked` or `ip_locked`
+    pub kind: String,
+    /// Absent for events naming no existing account
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub user_id: Option<String>,
+    pub ip: Option<String>,
+    pub user_agent: Option<String>,
+    pub created_at: DateTime<Utc>,
+}
+
+impl AuthEvent {
+    fn from_row(row: &SqliteRow) -> Self {
+        AuthEvent {
+            id: row.get("id"),
+            kind: row.get("kind"),
+            user_id: row.get("user_id"),
+            ip: row.get("ip"),
+            user_agent: row.get("user_agent"),
+            created_at: row.get("created_at"),
+        }
+    }
+}
+
+/// Appends to the `auth_events` audit log. `user_id` is `None` when the
+/// event names no existing account (e.g. a failed login as an unknown
+/// username). Failures are logged rather than returned: losing an audit
+/// entry shouldn't fail the request it describes.
+pub async fn record(db: &Database, kind: &str, user_id: Option<&str>, client: &Client) {
+    let result = async {
+        sqlx::query("INSERT INTO auth_events (id, user_id, kind, ip, user_agent, created_at) VALUES (?, ?, ?, ?, ?, ?)")
+            .bind(Uuid::new_v4().to_string())
+            .bind(user_id)
+            .bind(kind)
+            .bind(&client.ip)
+            .bind(&client.user_agent)
+            .bind(Utc::now())
+            .execute(&mut *db.write().await?)
+            .await
+    }
+    .await;
+    if let Err(e) = result {
+        tracing::warn!(kind, error = %e, "Failed to record auth event");
+    }
+}
+
+/// Events newest first, for one user or (`None`) everyone.
+pub async fn list(pool: &SqlitePool, user_id: Option<&str>, after: Option<&Cursor>, limit: u32) -> Result<Vec<AuthEvent>, sqlx::Error> {
+    let rows = sqlx::query("SELECT id, kind, user_id, ip, user_agent, created_at FROM auth_events WHERE (?1 IS NULL OR user_id = ?1) AND (?2 IS NULL OR created_at < ?2 OR (created_at = ?2 AND id < ?3)) ORDER BY created_at DESC, id DESC LIMIT ?4")
+        .bind(user_id)
+        .bind(after.map(|after| after.created_at))
+        .bind(after.map(|after| &after.id))
+        .bind(limit)
+        .fetch_all(pool)
+        .await?;
+
+    Ok(rows.iter().map(AuthEvent::from_row).collect())
+}
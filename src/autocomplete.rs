@@ -0,0 +1,40 @@
+use serde::Deserialize;
+
+use crate::simple_db::{TermCount, Terms};
+
+const DEFAULT_LIMIT: usize = 8;
+const MAX_LIMIT: usize = 20;
+
+#[derive(Clone, Copy, Debug, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum Kind {
+    Tag,
+    Category,
+}
+
+/// `?q=wor&kind=tag&limit=8`. An empty `q` gives the most used terms.
+#[derive(Debug, Deserialize)]
+pub struct AutocompleteQuery {
+    #[serde(default)]
+    pub q: String,
+    pub kind: Kind,
+    pub limit: Option<usize>,
+}
+
+/// The terms of `query.kind` starting with `query.q`, ignoring case, most
+/// used first. Just the strings, to keep per-keystroke responses small.
+pub fn suggest(terms: &Terms, query: &AutocompleteQuery) -> Vec<String> {
+    let terms: &[TermCount] = match query.kind {
+        Kind::Tag => &terms.tags,
+        Kind::Category => &terms.categories,
+    };
+    let prefix = query.q.trim().to_lowercase();
+    let limit = query.limit.unwrap_or(DEFAULT_LIMIT).clamp(1, MAX_LIMIT);
+
+    terms
+        .iter()
+        .filter(|count| count.term.to_lowercase().starts_with(&prefix))
+        .take(limit)
+        .map(|count| count.term.clone())
+        .collect()
+}
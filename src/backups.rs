@@ -0,0 +1,125 @@
+use axum::async_trait;
+use chrono::Utc;
+use sqlx::SqlitePool;
+use std::path::{Path, PathBuf};
+use std::time::{Duration, SystemTime};
+
+use crate::jobs::{Job, JobError, Trigger};
+use crate::simple_db::Database;
+
+const PREFIX: &str = "todos-";
+const SUFFIX: &str = ".db";
+
+/// Read from `BACKUP_DIR` (default `data/backups`), `BACKUP_INTERVAL_HOURS`
+/// (default 24, `0` disables scheduled backups) and `BACKUP_KEEP` (default 7).
+#[derive(Clone, Debug)]
+pub struct BackupConfig {
+    pub dir: PathBuf,
+    pub interval: Option<Duration>,
+    pub keep: usize,
+}
+
+impl BackupConfig {
+    pub fn from_env() -> Self {
+        let hours: u64 = std::env::var("BACKUP_INTERVAL_HOURS")
+            .ok()
+            .and_then(|hours| hours.parse().ok())
+            .unwrap_or(24);
+
+        BackupConfig {
+            dir: std::env::var("BACKUP_DIR").map(PathBuf::from).unwrap_or_else(|_| {
+                PathBuf::from(std::env::var("DATA_DIR").unwrap_or_else(|_| "data".to_string())).join("backups")
+            }),
+            interval: (hours > 0).then(|| Duration::from_secs(hours * 60 * 60)),
+            keep: std::env::var("BACKUP_KEEP")
+                .ok()
+                .and_then(|keep| keep.parse().ok())
+                .unwrap_or(7),
+        }
+    }
+}
+
+/// Writes a consistent copy of the database to `dir/todos-<timestamp>.db` with
+/// `VACUUM INTO`, which is safe while the app keeps serving requests.
+pub async fn create_backup(pool: &SqlitePool, dir: &Path) -> Result<PathBuf, Box<dyn std::error::Error>> {
+    std::fs::create_dir_all(dir)?;
+    let path = dir.join(format!("{}{}{}", PREFIX, Utc::now().format("%Y%m%dT%H%M%SZ"), SUFFIX));
+    let target = path.to_str().ok_or("backup path is not valid UTF-8")?;
+
+    sqlx::query("VACUUM INTO ?").bind(target).execute(pool).await?;
+    Ok(path)
+}
+
+/// Backups in `dir`, oldest first (the timestamped names sort chronologically).
+fn list_backups(dir: &Path) -> std::io::Result<Vec<PathBuf>> {
+    let mut backups: Vec<PathBuf> = std::fs::read_dir(dir)?
+        .filter_map(|entry| entry.ok().map(|entry| entry.path()))
+        .filter(|path| {
+            path.file_name()
+                .and_then(|name| name.to_str())
+                .is_some_and(|name| name.starts_with(PREFIX) && name.ends_with(SUFFIX))
+        })
+        .collect();
+    backups.sort();
+    Ok(backups)
+}
+
+/// Deletes all but the newest `keep` backups and returns how many were removed.
+pub fn prune(dir: &Path, keep: usize) -> std::io::Result<usize> {
+    let backups = list_backups(dir)?;
+    let excess = backups.len().saturating_sub(keep);
+    for path in &backups[..excess] {
+        std::fs::remove_file(path)?;
+    }
+    Ok(excess)
+}
+
+fn newest_backup_age(dir: &Path) -> Option<Duration> {
+    let newest = list_backups(dir).ok()?.pop()?;
+    let modified = std::fs::metadata(newest).ok()?.modified().ok()?;
+    SystemTime::now().duration_since(modified).ok()
+}
+
+/// Backs up the database once the newest backup is older than the
+/// configured interval, then prunes old ones. Runs hourly, so restarts
+/// neither skip a backup nor trigger an extra one; manual runs always back up.
+pub struct BackupJob {
+    config: BackupConfig,
+}
+
+impl BackupJob {
+    pub fn new(config: BackupConfig) -> Self {
+        BackupJob { config }
+    }
+}
+
+#[async_trait]
+impl Job for BackupJob {
+    fn name(&self) -> &'static str {
+        "backup"
+    }
+
+    fn default_schedule(&self) -> Option<&'static str> {
+        self.config.interval.map(|_| "0 * * * *")
+    }
+
+    async fn run(&self, db: &Database, trigger: Trigger) -> Result<String, JobError> {
+        if trigger == Trigger::Schedule
+            && let Some(interval) = self.config.interval
+            && let Some(age) = newest_backup_age(&self.config.dir)
+            && age < interval
+        {
+            return Ok(format!("Newest backup is {} minutes old; nothing to do", age.as_secs() / 60));
+        }
+
+        let path = create_backup(db.get_pool(), &self.config.dir).await.map_err(|e| e.to_string())?;
+        let pruned = match prune(&self.config.dir, self.config.keep) {
+            Ok(pruned) => pruned,
+            Err(e) => {
+                tracing::warn!(error = %e, "Failed to prune old database backups");
+                0
+            }
+        };
+        Ok(format!("Wrote {} (pruned {} old backups)", path.display(), pruned))
+    }
+}
@@ -0,0 +1,287 @@
+use axum::async_trait;
+use chrono::{DateTime, Utc};
+use serde::{Deserialize, Serialize};
+use sqlx::{sqlite::SqliteRow, Connection, Row, SqlitePool};
+use uuid::Uuid;
+
+use crate::colors;
+use crate::hooks::{Event, Hook, HookError};
+use crate::outbox;
+use crate::simple_db::{retry, Database, Todo, TODO_COLUMNS};
+
+/// Longest icon accepted: an emoji or the name of one in the client's set.
+const MAX_ICON_CHARS: usize = 32;
+
+/// A category a user files todos under. Todos keep the name itself in
+/// `category`, so renaming one renames it on the user's todos too.
+#[derive(Debug, Serialize)]
+pub struct Category {
+    pub id: String,
+    pub name: String,
+    pub color: Option<String>,
+    pub icon: Option<String>,
+    pub position: i64,
+    pub created_at: DateTime<Utc>,
+    pub updated_at: DateTime<Utc>,
+}
+
+impl Category {
+    fn from_row(row: &SqliteRow) -> Self {
+        Category {
+            id: row.get("id"),
+            name: row.get("name"),
+            color: row.get("color"),
+            icon: row.get("icon"),
+            position: row.get("position"),
+            created_at: row.get("created_at"),
+            updated_at: row.get("updated_at"),
+        }
+    }
+}
+
+#[derive(Debug, Deserialize)]
+pub struct NewCategory {
+    pub name: String,
+    pub color: Option<String>,
+    pub icon: Option<String>,
+}
+
+/// Fields left out keep their value; `""` clears the color or icon.
+#[derive(Debug, Deserialize)]
+pub struct UpdateCategory {
+    pub name: Option<String>,
+    pub color: Option<String>,
+    pub icon: Option<String>,
+}
+
+#[derive(Debug)]
+pub enum CategoryError {
+    Invalid,
+    /// The user already has a category by that name
+    Exists,
+    Database(sqlx::Error),
+}
+
+impl From<sqlx::Error> for CategoryError {
+    fn from(e: sqlx::Error) -> Self {
+        match &e {
+            sqlx::Error::Database(db) if db.is_unique_violation() => CategoryError::Exists,
+            _ => CategoryError::Database(e),
+        }
+    }
+}
+
+fn valid_name(name: &str) -> Result<String, CategoryError> {
+    let name = name.trim();
+    if name.is_empty() {
+        return Err(CategoryError::Invalid);
+    }
+    Ok(name.to_string())
+}
+
+/// `None` stays unset, `""` clears, anything else must be a color.
+fn valid_color(color: Option<&str>) -> Result<Option<String>, CategoryError> {
+    match color {
+        None | Some("") => Ok(color.map(str::to_string)),
+        Some(color) => colors::normalize(color).map(Some).ok_or(CategoryError::Invalid),
+    }
+}
+
+fn valid_icon(icon: Option<&str>) -> Result<Option<String>, CategoryError> {
+    match icon.map(str::trim) {
+        Some(icon) if icon.chars().count() > MAX_ICON_CHARS => Err(CategoryError::Invalid),
+        icon => Ok(icon.map(str::to_string)),
+    }
+}
+
+const CATEGORY_COLUMNS: &str = "id, name, color, icon, position, created_at, updated_at";
+
+/// A user's categories in their order.
+pub async fn get_categories(pool: &SqlitePool, user_id: &str) -> Result<Vec<Category>, sqlx::Error> {
+    let rows = sqlx::query(&format!("SELECT {} FROM categories WHERE user_id = ? ORDER BY position, name", CATEGORY_COLUMNS))
+        .bind(user_id)
+        .fetch_all(pool)
+        .await?;
+
+    Ok(rows.iter().map(Category::from_row).collect())
+}
+
+async fn get_category(pool: &SqlitePool, user_id: &str, id: &str) -> Result<Option<Category>, sqlx::Error> {
+    let row = sqlx::query(&format!("SELECT {} FROM categories WHERE id = ? AND user_id = ?", CATEGORY_COLUMNS))
+        .bind(id)
+        .bind(user_id)
+        .fetch_optional(pool)
+        .await?;
+
+    Ok(row.as_ref().map(Category::from_row))
+}
+
+/// Adds a category after the user's others.
+pub async fn create_category(db: &Database, user_id: &str, new_category: NewCategory) -> Result<Category, CategoryError> {
+    let name = valid_name(&new_category.name)?;
+    let color = valid_color(new_category.color.as_deref())?.filter(|color| !color.is_empty());
+    let icon = valid_icon(new_category.icon.as_deref())?.filter(|icon| !icon.is_empty());
+    let id = Uuid::new_v4().to_string();
+    let now = Utc::now();
+
+    let position: i64 = retry(|| async {
+        sqlx::query("INSERT INTO categories (id, user_id, name, color, icon, position, created_at, updated_at) SELECT ?1, ?2, ?3, ?4, ?5, COALESCE(MAX(position) + 1, 0), ?6, ?6 FROM categories WHERE user_id = ?2 RETURNING position")
+            .bind(&id)
+            .bind(user_id)
+            .bind(&name)
+            .bind(&color)
+            .bind(&icon)
+            .bind(now)
+            .fetch_one(&mut *db.write().await?)
+            .await
+    })
+    .await?
+    .get("position");
+
+    Ok(Category { id, name, color, icon, position, created_at: now, updated_at: now })
+}
+
+/// Renames or restyles a category; a new name is applied to the todos the
+/// user filed under the old one, each recording a `todo_updated` event.
+/// `None` if the user has no such category.
+pub async fn update_category(db: &Database, user_id: &str, id: &str, update: UpdateCategory) -> Result<Option<Category>, CategoryError> {
+    let name = update.name.as_deref().map(valid_name).transpose()?;
+    let color = valid_color(update.color.as_deref())?;
+    let icon = valid_icon(update.icon.as_deref())?;
+
+    let updated = retry(|| async {
+        let mut conn = db.write().await?;
+        let mut tx = conn.begin().await?;
+        let Some(old_name) = sqlx::query("SELECT name FROM categories WHERE id = ? AND user_id = ?")
+            .bind(id)
+            .bind(user_id)
+            .fetch_optional(&mut *tx)
+            .await?
+            .map(|row| row.get::<String, _>("name"))
+        else {
+            return Ok(false);
+        };
+
+        sqlx::query("UPDATE categories SET name = COALESCE(?, name), color = IIF(? IS NULL, color, NULLIF(?, '')), icon = IIF(? IS NULL, icon, NULLIF(?, '')), updated_at = ? WHERE id = ?")
+            .bind(&name)
+            .bind(&color)
+            .bind(&color)
+            .bind(&icon)
+            .bind(&icon)
+            .bind(Utc::now())
+            .bind(id)
+            .execute(&mut *tx)
+            .await?;
+        if let Some(name) = name.as_ref().filter(|name| **name != old_name) {
+            let rows = sqlx::query(&format!("UPDATE todos SET category = ?, updated_at = ?, version = version + 1 WHERE user_id = ? AND category = ? RETURNING {}", TODO_COLUMNS))
+                .bind(name)
+                .bind(Utc::now())
+                .bind(user_id)
+                .bind(&old_name)
+                .fetch_all(&mut *tx)
+                .await?;
+            for row in &rows {
+                outbox::record(&mut tx, &Event::TodoUpdated { todo: &Todo::from_row(row), actor_id: user_id, text_changed: false }).await?;
+            }
+        }
+        tx.commit().await?;
+        Ok(true)
+    })
+    .await?;
+
+    if !updated {
+        return Ok(None);
+    }
+    db.events_recorded();
+    Ok(get_category(db.get_pool(), user_id, id).await?)
+}
+
+/// Deletes a category and takes it off the user's todos, each recording a
+/// `todo_updated` event. `false` if the user has no such category.
+pub async fn delete_category(db: &Database, user_id: &str, id: &str) -> Result<bool, sqlx::Error> {
+    let deleted = retry(|| async {
+        let mut conn = db.write().await?;
+        let mut tx = conn.begin().await?;
+        let Some(row) = sqlx::query("DELETE FROM categories WHERE id = ? AND user_id = ? RETURNING name")
+            .bind(id)
+            .bind(user_id)
+            .fetch_optional(&mut *tx)
+            .await?
+        else {
+            return Ok(false);
+        };
+
+        let rows = sqlx::query(&format!("UPDATE todos SET category = NULL, updated_at = ?, version = version + 1 WHERE user_id = ? AND category = ? RETURNING {}", TODO_COLUMNS))
+            .bind(Utc::now())
+            .bind(user_id)
+            .bind(row.get::<String, _>("name"))
+            .fetch_all(&mut *tx)
+            .await?;
+        for row in &rows {
+            outbox::record(&mut tx, &Event::TodoUpdated { todo: &Todo::from_row(row), actor_id: user_id, text_changed: false }).await?;
+        }
+        tx.commit().await?;
+        Ok(true)
+    })
+    .await?;
+    if deleted {
+        db.events_recorded();
+    }
+    Ok(deleted)
+}
+
+/// Puts the given categories first, in the given order; the user's others
+/// follow in their current order. Ids of other users' categories are ignored.
+pub async fn reorder_categories(db: &Database, user_id: &str, ids: &[String]) -> Result<Vec<Category>, sqlx::Error> {
+    let current = get_categories(db.get_pool(), user_id).await?;
+    let rest = current.iter().map(|category| &category.id).filter(|id| !ids.contains(id));
+    let order: Vec<&String> = ids.iter().chain(rest).collect();
+
+    retry(|| async {
+        let mut conn = db.write().await?;
+        let mut tx = conn.begin().await?;
+        for (position, id) in order.iter().enumerate() {
+            sqlx::query("UPDATE categories SET position = ? WHERE id = ? AND user_id = ?")
+                .bind(position as i64)
+                .bind(id)
+                .bind(user_id)
+                .execute(&mut *tx)
+                .await?;
+        }
+        tx.commit().await
+    })
+    .await?;
+
+    get_categories(db.get_pool(), user_id).await
+}
+
+/// Adds the category a user files a todo under to their categories, if it's
+/// new to them.
+pub struct CategoryHook;
+
+#[async_trait]
+impl Hook for CategoryHook {
+    fn name(&self) -> &'static str {
+        "categories"
+    }
+
+    async fn handle(&self, db: &Database, event: &Event<'_>) -> Result<(), HookError> {
+        let (todo, actor_id) = match *event {
+            Event::TodoCreated { todo, actor_id } | Event::TodoUpdated { todo, actor_id, .. } => (todo, actor_id),
+            _ => return Ok(()),
+        };
+        let Some(name) = &todo.category else {
+            return Ok(());
+        };
+
+        let now = Utc::now();
+        sqlx::query("INSERT OR IGNORE INTO categories (id, user_id, name, position, created_at, updated_at) SELECT ?1, ?2, ?3, COALESCE(MAX(position) + 1, 0), ?4, ?4 FROM categories WHERE user_id = ?2")
+            .bind(Uuid::new_v4().to_string())
+            .bind(actor_id)
+            .bind(name)
+            .bind(now)
+            .execute(&mut *db.write().await?)
+            .await?;
+        Ok(())
+    }
+}
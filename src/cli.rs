@@ -0,0 +1,390 @@
+use clap::{Parser, Subcommand};
+use serde::{Deserialize, Serialize};
+use std::collections::HashMap;
+use std::io::IsTerminal;
+use std::path::PathBuf;
+
+use crate::auth_events::{self, Client};
+use crate::backups::{self, BackupConfig};
+use crate::encryption;
+use crate::flags::{self, Flags};
+use crate::plans;
+use crate::retention::{self, RetentionPolicy};
+use crate::seed;
+use crate::simple_auth::{AuthService, RegisterRequest, UserRecord};
+use crate::simple_db::{Database, Todo};
+use crate::tombstones::{self, Tombstone};
+
+#[derive(Parser)]
+#[command(name = "todo-app", about = "Rust Todo App server and administration tool")]
+pub struct Cli {
+    #[command(subcommand)]
+    pub command: Option<Command>,
+}
+
+#[derive(Subcommand)]
+pub enum Command {
+    /// Run the web server (default)
+    Serve,
+    /// Manage user accounts
+    #[command(subcommand)]
+    User(UserCommand),
+    /// Database maintenance
+    #[command(subcommand)]
+    Db(DbCommand),
+    /// Turn feature flags on or off for individual users
+    #[command(subcommand)]
+    Flag(FlagCommand),
+    /// Write users and todos as JSON
+    Export {
+        /// Only export this user and their todos
+        #[arg(long)]
+        user: Option<String>,
+        /// Output file (defaults to stdout)
+        #[arg(short, long)]
+        output: Option<PathBuf>,
+    },
+    /// Load users and todos from an export; existing records are kept
+    Import {
+        file: PathBuf,
+    },
+    /// Fill the database with fake users and todos for development and demos
+    Seed {
+        #[arg(long, default_value_t = 5)]
+        users: usize,
+        #[arg(long, default_value_t = 200)]
+        todos: usize,
+        /// Random seed, for repeatable data (random by default)
+        #[arg(long)]
+        seed: Option<u64>,
+    },
+}
+
+#[derive(Subcommand)]
+pub enum UserCommand {
+    /// Create a user account
+    Create {
+        #[arg(long)]
+        username: String,
+        #[arg(long)]
+        email: String,
+    },
+    /// List user accounts
+    List,
+    /// Delete a user account and all of its data
+    Delete {
+        username: String,
+    },
+    /// Set a new password for a user
+    ResetPassword {
+        username: String,
+    },
+    /// Deactivate an account: it can't log in, but its data is kept
+    Disable {
+        username: String,
+    },
+    /// Re-enable a deactivated account
+    Enable {
+        username: String,
+    },
+    /// Allow a user to use the /admin endpoints
+    GrantAdmin {
+        username: String,
+    },
+    /// Take away a user's admin access
+    RevokeAdmin {
+        username: String,
+    },
+    /// Move a user to another plan by hand, e.g. a complimentary upgrade
+    SetPlan {
+        username: String,
+        #[arg(value_parser = ["free", "pro"])]
+        plan: String,
+    },
+    /// Show the authentication audit log, newest first
+    Activity {
+        /// Only this user's events (defaults to everyone's)
+        username: Option<String>,
+        #[arg(long, default_value_t = 50)]
+        limit: u32,
+    },
+}
+
+#[derive(Subcommand)]
+pub enum DbCommand {
+    /// Create missing tables, columns and indexes
+    Migrate,
+    /// Write a backup now and prune old ones (BACKUP_DIR, BACKUP_KEEP)
+    Backup {
+        /// Backup directory (defaults to BACKUP_DIR)
+        #[arg(long)]
+        dir: Option<PathBuf>,
+    },
+    /// Delete records older than the retention policy allows (RETENTION_*_DAYS)
+    Purge {
+        /// Only report what would be deleted
+        #[arg(long)]
+        dry_run: bool,
+    },
+    /// Encrypt todo text and notes still stored in plaintext (TODO_ENCRYPTION_KEY)
+    Encrypt,
+}
+
+#[derive(Subcommand)]
+pub enum FlagCommand {
+    /// Show every flag as a user gets it
+    List {
+        username: String,
+    },
+    /// Turn a flag on for a user, whatever the default
+    Enable {
+        name: String,
+        username: String,
+    },
+    /// Turn a flag off for a user, whatever the default
+    Disable {
+        name: String,
+        username: String,
+    },
+    /// Put a user back on the flag's default
+    Reset {
+        name: String,
+        username: String,
+    },
+}
+
+#[derive(Serialize, Deserialize)]
+struct Export {
+    users: Vec<UserRecord>,
+    todos: Vec<Todo>,
+    /// What was deleted, so an import doesn't bring it back from an older export
+    #[serde(default)]
+    deleted: Vec<Tombstone>,
+}
+
+type CliResult = Result<(), Box<dyn std::error::Error>>;
+
+/// Where scripts hand the CLI a password. Passwords are never taken as
+/// arguments, which end up in shell history and `ps` output.
+const PASSWORD_ENV: &str = "TODO_APP_PASSWORD";
+
+/// Every seeded user's password, unless `TODO_APP_PASSWORD` is set.
+const SEED_PASSWORD: &str = "seeded-demo-pass-2024";
+
+/// The password for `user create` and `user reset-password`: from
+/// `TODO_APP_PASSWORD`, typed twice at a prompt that doesn't echo, or the
+/// first line of stdin when that isn't a terminal.
+fn read_password() -> Result<String, Box<dyn std::error::Error>> {
+    if let Ok(password) = std::env::var(PASSWORD_ENV) {
+        return Ok(password);
+    }
+    if std::io::stdin().is_terminal() {
+        let password = rpassword::prompt_password("Password: ")?;
+        if rpassword::prompt_password("Repeat password: ")? != password {
+            return Err("the passwords don't match".into());
+        }
+        return Ok(password);
+    }
+    let mut line = String::new();
+    std::io::stdin().read_line(&mut line)?;
+    let password = line.trim_end_matches(['\r', '\n']);
+    if password.is_empty() {
+        return Err(format!("no password given; type it at the prompt, pipe it in or set {}", PASSWORD_ENV).into());
+    }
+    Ok(password.to_string())
+}
+
+/// Runs an administrative command against the database in `DATABASE_URL`.
+pub async fn run(command: Command, db: &Database, auth_service: &AuthService) -> CliResult {
+    match command {
+        Command::Serve => unreachable!("serve is handled by main"),
+        Command::User(UserCommand::Create { username, email }) => {
+            let request = RegisterRequest { username: username.clone(), email, password: read_password()? };
+            let response = auth_service.register(request).await?;
+            println!("Created user {} ({})", username, response.user_id);
+        }
+        Command::User(UserCommand::List) => {
+            let users = auth_service.list_users().await?;
+            for user in users {
+                let status = match (user.disabled_at.is_some(), user.is_admin) {
+                    (true, _) => "disabled",
+                    (false, true) => "admin",
+                    (false, false) => "active",
+                };
+                println!("{}\t{}\t{}\t{}\t{}", user.id, user.username, user.email, user.created_at.to_rfc3339(), status);
+            }
+        }
+        Command::User(UserCommand::Delete { username }) => {
+            let user_id = auth_service.find_user_id(&username).await?;
+            db.delete_user_data(&user_id).await?;
+            auth_service.delete_user(&user_id).await?;
+            println!("Deleted user {} and their data", username);
+        }
+        Command::User(UserCommand::ResetPassword { username }) => {
+            auth_service.reset_password(&username, &read_password()?).await?;
+            let user_id = auth_service.find_user_id(&username).await?;
+            auth_events::record(db, "password_reset", Some(&user_id), &Client::default()).await;
+            println!("Password updated for {}", username);
+        }
+        Command::User(UserCommand::Disable { username }) => {
+            let user_id = auth_service.find_user_id(&username).await?;
+            auth_service.set_disabled(&user_id, true).await?;
+            auth_events::record(db, "account_disabled", Some(&user_id), &Client::default()).await;
+            println!("Disabled {}; their data is kept", username);
+        }
+        Command::User(UserCommand::Enable { username }) => {
+            let user_id = auth_service.find_user_id(&username).await?;
+            auth_service.set_disabled(&user_id, false).await?;
+            auth_events::record(db, "account_enabled", Some(&user_id), &Client::default()).await;
+            println!("Enabled {}", username);
+        }
+        Command::User(UserCommand::GrantAdmin { username }) => {
+            let user_id = auth_service.find_user_id(&username).await?;
+            auth_service.set_admin(&user_id, true).await?;
+            auth_events::record(db, "admin_granted", Some(&user_id), &Client::default()).await;
+            println!("{} is now an admin", username);
+        }
+        Command::User(UserCommand::RevokeAdmin { username }) => {
+            let user_id = auth_service.find_user_id(&username).await?;
+            auth_service.set_admin(&user_id, false).await?;
+            auth_events::record(db, "admin_revoked", Some(&user_id), &Client::default()).await;
+            println!("{} is no longer an admin", username);
+        }
+        Command::User(UserCommand::SetPlan { username, plan }) => {
+            let user_id = auth_service.find_user_id(&username).await?;
+            let plan = plans::Plan::parse(&plan).ok_or("unknown plan")?;
+            plans::set_plan(db, &user_id, plan).await?;
+            println!("{} is now on {}", username, plan.as_str());
+        }
+        Command::User(UserCommand::Activity { username, limit }) => {
+            let user_id = match &username {
+                Some(username) => Some(auth_service.find_user_id(username).await?),
+                None => None,
+            };
+            let usernames: HashMap<String, String> =
+                auth_service.list_users().await?.into_iter().map(|user| (user.id, user.username)).collect();
+            for event in auth_events::list(db.get_pool(), user_id.as_deref(), None, limit).await? {
+                let username = event.user_id.as_ref().and_then(|id| usernames.get(id)).map_or("-", String::as_str);
+                println!(
+                    "{}\t{}\t{}\t{}\t{}",
+                    event.created_at.to_rfc3339(),
+                    event.kind,
+                    username,
+                    event.ip.as_deref().unwrap_or("-"),
+                    event.user_agent.as_deref().unwrap_or("-")
+                );
+            }
+        }
+        Command::Db(DbCommand::Migrate) => {
+            // Opening the database already brings the schema up to date
+            println!("Database schema is up to date");
+        }
+        Command::Db(DbCommand::Backup { dir }) => {
+            let config = BackupConfig::from_env();
+            let dir = dir.unwrap_or(config.dir);
+            let path = backups::create_backup(db.get_pool(), &dir).await?;
+            let removed = backups::prune(&dir, config.keep)?;
+            println!("Wrote {} (pruned {} old backups)", path.display(), removed);
+        }
+        Command::Db(DbCommand::Purge { dry_run }) => {
+            let policy = RetentionPolicy::from_env();
+            let dry_run = dry_run || policy.dry_run;
+            let report = retention::purge(db, &policy, dry_run).await?;
+            if report.is_empty() {
+                println!("No retention rules are enabled");
+            }
+            for purged in &report {
+                let verb = if dry_run { "Would delete" } else { "Deleted" };
+                println!("{:<20} {} {} older than {} days", purged.name, verb, purged.count, purged.older_than_days);
+            }
+        }
+        Command::Db(DbCommand::Encrypt) => {
+            if !encryption::is_enabled() {
+                return Err("set TODO_ENCRYPTION_KEY to encrypt todos".into());
+            }
+            for (table, encrypted) in encryption::encrypt_existing(db).await? {
+                println!("{:<20} Encrypted {}", table, encrypted);
+            }
+        }
+        Command::Flag(FlagCommand::List { username }) => {
+            let user_id = auth_service.find_user_id(&username).await?;
+            for (name, enabled) in Flags::from_env().for_user(db.get_pool(), &user_id).await? {
+                println!("{}\t{}", name, if enabled { "on" } else { "off" });
+            }
+        }
+        Command::Flag(command) => {
+            let (name, username, enabled) = match command {
+                FlagCommand::Enable { name, username } => (name, username, Some(true)),
+                FlagCommand::Disable { name, username } => (name, username, Some(false)),
+                FlagCommand::Reset { name, username } => (name, username, None),
+                FlagCommand::List { .. } => unreachable!("handled above"),
+            };
+            if !flags::exists(&name) {
+                return Err(format!("no flag named {}", name).into());
+            }
+            let user_id = auth_service.find_user_id(&username).await?;
+            flags::set(db, &name, &user_id, enabled).await?;
+            match enabled {
+                Some(true) => println!("Turned {} on for {}", name, username),
+                Some(false) => println!("Turned {} off for {}", name, username),
+                None => println!("{} gets the default for {} again", username, name),
+            }
+        }
+        Command::Export { user, output } => {
+            let mut users = auth_service.list_users().await?;
+            let mut owner = None;
+            if let Some(username) = &user {
+                users.retain(|u| &u.username == username);
+                owner = Some(users.first().ok_or_else(|| format!("no user named {}", username))?.id.clone());
+            }
+            let todos = db.all_todos(owner.as_deref()).await?;
+            let deleted = tombstones::list(db.get_pool(), owner.as_deref()).await?;
+
+            let json = serde_json::to_string_pretty(&Export { users, todos, deleted })?;
+            match output {
+                Some(path) => std::fs::write(path, json)?,
+                None => println!("{}", json),
+            }
+        }
+        Command::Import { file } => {
+            let export: Export = serde_json::from_str(&std::fs::read_to_string(file)?)?;
+
+            let mut imported_users = 0;
+            for user in &export.users {
+                if auth_service.import_user(user).await? {
+                    imported_users += 1;
+                }
+            }
+            // Before the todos, which are skipped if deleted here
+            for tombstone in &export.deleted {
+                tombstones::import(db, tombstone).await?;
+            }
+            let mut imported_todos = 0;
+            for todo in &export.todos {
+                if db.import_todo(todo).await? {
+                    imported_todos += 1;
+                }
+            }
+            println!(
+                "Imported {} of {} users and {} of {} todos",
+                imported_users,
+                export.users.len(),
+                imported_todos,
+                export.todos.len()
+            );
+        }
+        Command::Seed { users, todos, seed } => {
+            let password = std::env::var(PASSWORD_ENV).unwrap_or_else(|_| SEED_PASSWORD.to_string());
+            let seeded = seed::seed(db, auth_service, users, todos, &password, seed.unwrap_or_else(|| fastrand::u64(..))).await?;
+            println!(
+                "Created {} users ({}) with password {:?}, and {} todos ({} completed)",
+                seeded.usernames.len(),
+                seeded.usernames.join(", "),
+                password,
+                seeded.todos,
+                seeded.completed
+            );
+        }
+    }
+    Ok(())
+}
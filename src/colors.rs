@@ -0,0 +1,34 @@
+/// Named colors clients can offer as a palette, in display order.
+pub const PALETTE: &[&str] = &["red", "orange", "yellow", "green", "teal", "blue", "purple", "pink", "gray"];
+
+/// `color` as stored: a palette name, or a hex code like `#3b82f6` (or the
+/// short `#38f`), lowercased. `None` if it's neither.
+pub fn normalize(color: &str) -> Option<String> {
+    let color = color.trim().to_ascii_lowercase();
+    let is_hex = color
+        .strip_prefix('#')
+        .is_some_and(|hex| matches!(hex.len(), 3 | 6) && hex.bytes().all(|b| b.is_ascii_hexdigit()));
+    (is_hex || PALETTE.contains(&color.as_str())).then_some(color)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn accepts_palette_names_and_hex_codes() {
+        for name in PALETTE {
+            assert_eq!(normalize(name).as_deref(), Some(*name));
+        }
+        assert_eq!(normalize(" Blue ").as_deref(), Some("blue"));
+        assert_eq!(normalize("#3B82F6").as_deref(), Some("#3b82f6"));
+        assert_eq!(normalize("#38f").as_deref(), Some("#38f"));
+    }
+
+    #[test]
+    fn rejects_anything_else() {
+        for color in ["", "#", "crimson", "3b82f6", "#3b82f", "#3b82f6ff", "#ggg", "# 38f", "rgb(0, 0, 255)", "##38f"] {
+            assert_eq!(normalize(color), None, "{:?}", color);
+        }
+    }
+}
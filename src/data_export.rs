@@ -0,0 +1,93 @@
+use chrono::{DateTime, Utc};
+use serde::Serialize;
+use sqlx::Row;
+
+use crate::achievements::{self, Status};
+use crate::auth_events::{self, AuthEvent};
+use crate::categories::{self, Category};
+use crate::filters::{self, SavedFilter};
+use crate::gamification::{self, Award};
+use crate::inbox::{self, Inbox, InboxConfig};
+use crate::mentions::{self, Mention};
+use crate::myday::{self, PlanEntry};
+use crate::notifications::{self, Notification};
+use crate::notify::{self, Channel, Dispatcher};
+use crate::reports::{self, WeeklyReport};
+use crate::rules::{self, Rule};
+use crate::settings::{self, UserSettings};
+use crate::simple_auth::Profile;
+use crate::simple_db::{Database, Todo};
+use crate::tombstones::{self, Tombstone};
+use crate::workspaces::{self, Workspace};
+
+/// Everything stored about one user, for `GET /auth/me/export` (the GDPR
+/// right of access). Secrets that only protect the account, like the
+/// password hash, are left out.
+#[derive(Debug, Serialize)]
+pub struct UserExport {
+    pub exported_at: DateTime<Utc>,
+    pub profile: Profile,
+    pub settings: UserSettings,
+    /// Todos the user created, personal and in workspaces
+    pub todos: Vec<Todo>,
+    pub workspaces: Vec<Workspace>,
+    pub categories: Vec<Category>,
+    pub saved_filters: Vec<SavedFilter>,
+    pub rules: Vec<Rule>,
+    /// Personal todos, categories, filters and rules deleted lately
+    pub deleted: Vec<Tombstone>,
+    pub weekly_reports: Vec<WeeklyReport>,
+    /// My Day plans, one entry per todo and day
+    pub my_day: Vec<PlanEntry>,
+    /// Gamification points, one award per completed todo
+    pub points: Vec<Award>,
+    /// Every achievement, unlocked or not
+    pub achievements: Vec<Status>,
+    pub notifications: Vec<Notification>,
+    pub notification_channels: Vec<Channel>,
+    pub mentions: Vec<Mention>,
+    pub auth_events: Vec<AuthEvent>,
+    /// Local dates a daily digest was sent for
+    pub digests_sent: Vec<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub inbox: Option<Inbox>,
+}
+
+pub async fn collect(db: &Database, dispatcher: &Dispatcher, inbox_config: Option<&InboxConfig>, profile: Profile) -> Result<UserExport, sqlx::Error> {
+    let pool = db.get_pool();
+    let user_id = profile.id.as_str();
+
+    let digests_sent = sqlx::query("SELECT local_date FROM digests WHERE user_id = ? ORDER BY local_date")
+        .bind(user_id)
+        .fetch_all(pool)
+        .await?
+        .iter()
+        .map(|row| row.get("local_date"))
+        .collect();
+    let inbox = match inbox_config {
+        Some(config) => inbox::find_inbox(pool, config, user_id).await?,
+        None => None,
+    };
+
+    Ok(UserExport {
+        exported_at: Utc::now(),
+        settings: settings::get_settings(pool, user_id).await?,
+        todos: db.all_todos(Some(user_id)).await?,
+        workspaces: workspaces::get_workspaces(pool, user_id).await?,
+        categories: categories::get_categories(pool, user_id).await?,
+        saved_filters: filters::get_filters(pool, user_id).await?,
+        rules: rules::get_rules(pool, user_id).await?,
+        deleted: tombstones::list(pool, Some(user_id)).await?,
+        weekly_reports: reports::get_reports(pool, user_id).await?,
+        my_day: myday::get_entries(pool, user_id).await?,
+        points: gamification::get_awards(pool, user_id).await?,
+        achievements: achievements::list(pool, user_id).await?,
+        notifications: notifications::get_notifications(pool, user_id, false, None, u32::MAX).await?,
+        notification_channels: notify::get_channels(pool, dispatcher, user_id).await?,
+        mentions: mentions::get_mentions(pool, user_id, None, u32::MAX).await?,
+        auth_events: auth_events::list(pool, Some(user_id), None, u32::MAX).await?,
+        digests_sent,
+        inbox,
+        profile,
+    })
+}
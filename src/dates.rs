@@ -0,0 +1,314 @@
+use chrono::{DateTime, Datelike, Duration, NaiveDate, NaiveTime, TimeZone, Utc, Weekday};
+
+/// Resolves a due date phrase such as "tomorrow 5pm", "next friday", "in 3 days"
+/// or "2025-12-31 09:30" relative to `now`, interpreting wall-clock times in
+/// `now`'s timezone. Dates without a time resolve to the end of that day.
+pub fn parse_due<Tz: TimeZone>(input: &str, now: DateTime<Tz>) -> Option<DateTime<Utc>> {
+    let input = input.trim();
+    if let Ok(dt) = DateTime::parse_from_rfc3339(input) {
+        return Some(dt.with_timezone(&Utc));
+    }
+
+    let input = input
+        .to_lowercase()
+        .replace(" am", "am")
+        .replace(" pm", "pm");
+    let words: Vec<&str> = input
+        .split_whitespace()
+        .filter(|w| !matches!(*w, "at" | "on" | "by" | "due"))
+        .collect();
+
+    if let ["in", amount, unit] = words.as_slice() {
+        let amount: i64 = amount.parse().ok()?;
+        // Out of range for chrono, e.g. "in 99999999 days", is no date at all
+        let offset = match *unit {
+            "minute" | "minutes" | "min" | "mins" => Duration::try_minutes(amount),
+            "hour" | "hours" => Duration::try_hours(amount),
+            "day" | "days" => Duration::try_days(amount),
+            "week" | "weeks" => Duration::try_weeks(amount),
+            _ => return None,
+        }?;
+        return now.checked_add_signed(offset).map(|due| due.with_timezone(&Utc));
+    }
+
+    let today = now.date_naive();
+    let mut date = None;
+    let mut time = None;
+    let mut words = words.into_iter();
+
+    while let Some(word) = words.next() {
+        if let Some(t) = parse_time(word) {
+            time = Some(t);
+            continue;
+        }
+
+        date = Some(match word {
+            "today" => today,
+            "tonight" => {
+                time.get_or_insert(NaiveTime::from_hms_opt(20, 0, 0)?);
+                today
+            }
+            "tomorrow" | "tmrw" => today + Duration::days(1),
+            "next" => match words.next()? {
+                "week" => next_weekday(today, Weekday::Mon, true),
+                "month" => NaiveDate::from_ymd_opt(today.year(), today.month(), 1)?
+                    .checked_add_months(chrono::Months::new(1))?,
+                other => next_weekday(today, other.parse().ok()?, true),
+            },
+            other => match other.parse::<Weekday>() {
+                Ok(weekday) => next_weekday(today, weekday, false),
+                Err(_) => NaiveDate::parse_from_str(other, "%Y-%m-%d").ok()?,
+            },
+        });
+    }
+
+    let date = match (date, time) {
+        (None, None) => return None,
+        (Some(date), _) => date,
+        // A bare time means the next time the clock shows it
+        (None, Some(t)) if t <= now.time() => today + Duration::days(1),
+        (None, Some(_)) => today,
+    };
+    let time = time.unwrap_or(NaiveTime::from_hms_opt(23, 59, 59)?);
+
+    now.timezone()
+        .from_local_datetime(&date.and_time(time))
+        .earliest()
+        .map(|dt| dt.with_timezone(&Utc))
+}
+
+/// Words that introduce a deadline, as in "pay rent by friday".
+const DEADLINE_WORDS: &[&str] = &["by", "on", "due", "before", "until", "this"];
+
+/// The first due date phrase in free text, such as "by March 3", "next
+/// tuesday", "tomorrow at 5pm" or "on 2025-04-01", resolved like `parse_due`.
+/// A weekday alone only counts after "by", "on" and the like, since it is as
+/// often part of a name ("Friday standup"). Dates already past are ignored.
+pub fn extract_due<Tz: TimeZone>(text: &str, now: DateTime<Tz>) -> Option<DateTime<Utc>> {
+    let text = text.to_lowercase().replace(" am", "am").replace(" pm", "pm");
+    let words: Vec<&str> = text
+        .split_whitespace()
+        .map(|word| word.trim_matches(|c: char| !c.is_alphanumeric()))
+        .collect();
+    let today = now.date_naive();
+
+    for (i, &word) in words.iter().enumerate() {
+        let after_deadline_word = i > 0 && DEADLINE_WORDS.contains(&words[i - 1]);
+        let (mut phrase, used) = match word {
+            "today" | "tonight" | "tomorrow" | "tmrw" => (word.to_string(), 1),
+            "next" => match words.get(i + 1) {
+                Some(&unit) if unit == "week" || unit == "month" || unit.parse::<Weekday>().is_ok() => (format!("next {}", unit), 2),
+                _ => continue,
+            },
+            "in" => match (words.get(i + 1), words.get(i + 2)) {
+                (Some(amount), Some(unit)) if amount.parse::<u32>().is_ok() && matches!(*unit, "day" | "days" | "week" | "weeks") => {
+                    (format!("in {} {}", amount, unit), 3)
+                }
+                _ => continue,
+            },
+            _ if after_deadline_word && word.parse::<Weekday>().is_ok() => (word.to_string(), 1),
+            _ if NaiveDate::parse_from_str(word, "%Y-%m-%d").is_ok() => (word.to_string(), 1),
+            _ => match month_day(&words[i..], today) {
+                Some((date, used)) => (date.format("%Y-%m-%d").to_string(), used),
+                None => continue,
+            },
+        };
+
+        // "tomorrow 5pm", "by friday at noon"
+        let mut rest = words[i + used..].iter().copied();
+        if let Some(time) = rest.next().and_then(|word| if word == "at" { rest.next() } else { Some(word) })
+            && parse_time(time).is_some()
+        {
+            phrase = format!("{} {}", phrase, time);
+        }
+
+        if let Some(due) = parse_due(&phrase, now.clone())
+            && due > now.with_timezone(&Utc)
+        {
+            return Some(due);
+        }
+    }
+    None
+}
+
+/// "march 3", "3rd mar" or "march 3 2026" at the start of `words`, with how
+/// many words it took. Without a year, the next such date on or after `today`.
+fn month_day(words: &[&str], today: NaiveDate) -> Option<(NaiveDate, usize)> {
+    let (first, second) = (*words.first()?, *words.get(1)?);
+    let (month, day) = match (month_number(first), day_number(second)) {
+        (Some(month), Some(day)) => (month, day),
+        _ => (month_number(second)?, day_number(first)?),
+    };
+
+    if let Some(year) = words.get(2).and_then(|year| year.parse::<i32>().ok()).filter(|year| (2000..=2100).contains(year)) {
+        return Some((NaiveDate::from_ymd_opt(year, month, day)?, 3));
+    }
+    let date = NaiveDate::from_ymd_opt(today.year(), month, day)?;
+    if date >= today {
+        Some((date, 2))
+    } else {
+        Some((NaiveDate::from_ymd_opt(today.year() + 1, month, day)?, 2))
+    }
+}
+
+fn month_number(word: &str) -> Option<u32> {
+    Some(match word {
+        "jan" | "january" => 1,
+        "feb" | "february" => 2,
+        "mar" | "march" => 3,
+        "apr" | "april" => 4,
+        "may" => 5,
+        "jun" | "june" => 6,
+        "jul" | "july" => 7,
+        "aug" | "august" => 8,
+        "sep" | "sept" | "september" => 9,
+        "oct" | "october" => 10,
+        "nov" | "november" => 11,
+        "dec" | "december" => 12,
+        _ => return None,
+    })
+}
+
+/// "3", "3rd", "21st"
+fn day_number(word: &str) -> Option<u32> {
+    let digits = ["st", "nd", "rd", "th"].iter().find_map(|suffix| word.strip_suffix(suffix)).unwrap_or(word);
+    digits.parse().ok().filter(|day| (1..=31).contains(day))
+}
+
+fn parse_time(word: &str) -> Option<NaiveTime> {
+    if word == "noon" {
+        return NaiveTime::from_hms_opt(12, 0, 0);
+    }
+    if let Ok(t) = NaiveTime::parse_from_str(word, "%H:%M") {
+        return Some(t);
+    }
+
+    let (clock, pm) = if let Some(clock) = word.strip_suffix("pm") {
+        (clock, true)
+    } else {
+        (word.strip_suffix("am")?, false)
+    };
+    let (hour, minute) = match clock.split_once(':') {
+        Some((h, m)) => (h.parse::<u32>().ok()?, m.parse::<u32>().ok()?),
+        None => (clock.parse::<u32>().ok()?, 0),
+    };
+    if !(1..=12).contains(&hour) {
+        return None;
+    }
+    NaiveTime::from_hms_opt(hour % 12 + if pm { 12 } else { 0 }, minute, 0)
+}
+
+/// `today` or `tomorrow` (relative to the user's `today`), or a `YYYY-MM-DD` date.
+pub fn parse_day(day: &str, today: NaiveDate) -> Option<NaiveDate> {
+    match day {
+        "today" => Some(today),
+        "tomorrow" => today.succ_opt(),
+        _ => NaiveDate::parse_from_str(day, "%Y-%m-%d").ok(),
+    }
+}
+
+/// Start of `date` in `tz`. Midnight may not exist on DST change days, so
+/// this takes the earliest valid instant at or after it.
+pub fn start_of_day<Tz: TimeZone>(date: NaiveDate, tz: &Tz) -> DateTime<Utc> {
+    let mut time = date.and_time(NaiveTime::MIN);
+    loop {
+        if let Some(start) = tz.from_local_datetime(&time).earliest() {
+            return start.with_timezone(&Utc);
+        }
+        time += Duration::minutes(30);
+    }
+}
+
+/// End of the day `days` days after `now`'s date in `now`'s timezone (`0` is
+/// the end of today). Date-only due dates sit at 23:59:59 local time, so
+/// "within 7 days" has to run to local midnight rather than 7 × 24 hours on.
+pub fn end_of_days_ahead<Tz: TimeZone>(now: DateTime<Tz>, days: u32) -> DateTime<Utc> {
+    start_of_day(now.date_naive() + Duration::days(days as i64 + 1), &now.timezone())
+}
+
+/// The first `weekday` on or after `from`; `strict` skips `from` itself.
+fn next_weekday(from: NaiveDate, weekday: Weekday, strict: bool) -> NaiveDate {
+    let mut days_ahead = (7 + weekday.num_days_from_monday() - from.weekday().num_days_from_monday()) % 7;
+    if days_ahead == 0 && strict {
+        days_ahead = 7;
+    }
+    from + Duration::days(days_ahead as i64)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// A Wednesday morning
+    fn now() -> DateTime<Utc> {
+        Utc.with_ymd_and_hms(2025, 3, 5, 10, 0, 0).unwrap()
+    }
+
+    fn due(input: &str) -> Option<String> {
+        parse_due(input, now()).map(|due| due.to_rfc3339())
+    }
+
+    #[test]
+    fn parses_relative_offsets() {
+        assert_eq!(due("in 3 days").as_deref(), Some("2025-03-08T10:00:00+00:00"));
+        assert_eq!(due("in 2 hours").as_deref(), Some("2025-03-05T12:00:00+00:00"));
+        assert_eq!(due("in 1 week").as_deref(), Some("2025-03-12T10:00:00+00:00"));
+        assert_eq!(due("in 15 mins").as_deref(), Some("2025-03-05T10:15:00+00:00"));
+        assert_eq!(due("in 3 fortnights"), None);
+        assert_eq!(due("in three days"), None);
+    }
+
+    #[test]
+    fn rejects_offsets_out_of_range() {
+        assert_eq!(due("in 99999999 days"), None);
+        assert_eq!(due("in 9223372036854775807 minutes"), None);
+        assert_eq!(due("in 99999999999999 weeks"), None);
+        assert_eq!(due("in 99999999999 hours"), None);
+    }
+
+    #[test]
+    fn parses_days_and_times() {
+        assert_eq!(due("today").as_deref(), Some("2025-03-05T23:59:59+00:00"));
+        assert_eq!(due("tomorrow 5pm").as_deref(), Some("2025-03-06T17:00:00+00:00"));
+        assert_eq!(due("tomorrow at 5:30 pm").as_deref(), Some("2025-03-06T17:30:00+00:00"));
+        assert_eq!(due("tonight").as_deref(), Some("2025-03-05T20:00:00+00:00"));
+        assert_eq!(due("by friday noon").as_deref(), Some("2025-03-07T12:00:00+00:00"));
+        assert_eq!(due("2025-12-31 09:30").as_deref(), Some("2025-12-31T09:30:00+00:00"));
+        assert_eq!(due("2025-05-01T09:30:00+02:00").as_deref(), Some("2025-05-01T07:30:00+00:00"));
+        assert_eq!(due("someday"), None);
+        assert_eq!(due(""), None);
+    }
+
+    #[test]
+    fn weekdays_and_next() {
+        // "wednesday" is today; "next wednesday" is a week on
+        assert_eq!(due("wednesday").as_deref(), Some("2025-03-05T23:59:59+00:00"));
+        assert_eq!(due("next wednesday").as_deref(), Some("2025-03-12T23:59:59+00:00"));
+        assert_eq!(due("next week").as_deref(), Some("2025-03-10T23:59:59+00:00"));
+        assert_eq!(due("next month").as_deref(), Some("2025-04-01T23:59:59+00:00"));
+    }
+
+    #[test]
+    fn bare_time_means_the_next_time_the_clock_shows_it() {
+        assert_eq!(due("9am").as_deref(), Some("2025-03-06T09:00:00+00:00"));
+        assert_eq!(due("11am").as_deref(), Some("2025-03-05T11:00:00+00:00"));
+        assert_eq!(due("13pm"), None);
+    }
+
+    #[test]
+    fn reads_wall_clock_times_in_the_timezone() {
+        let now = now().with_timezone(&chrono_tz::America::New_York);
+        assert_eq!(parse_due("tomorrow 5pm", now).map(|due| due.to_rfc3339()).as_deref(), Some("2025-03-06T22:00:00+00:00"));
+    }
+
+    #[test]
+    fn extracts_due_dates_from_text() {
+        let extract = |text| extract_due(text, now()).map(|due| due.to_rfc3339());
+        assert_eq!(extract("Pay rent by friday").as_deref(), Some("2025-03-07T23:59:59+00:00"));
+        assert_eq!(extract("Call mom tomorrow at 5pm").as_deref(), Some("2025-03-06T17:00:00+00:00"));
+        assert_eq!(extract("Renew passport march 20").as_deref(), Some("2025-03-20T23:59:59+00:00"));
+        // A weekday alone is often a name; past dates don't count
+        assert_eq!(extract("Prep the Friday standup"), None);
+        assert_eq!(extract("Taxes were due 2024-04-15"), None);
+    }
+}
@@ -0,0 +1,131 @@
+use axum::http::StatusCode;
+use chrono::Utc;
+use serde::{Deserialize, Serialize};
+use sqlx::{Row, SqlitePool};
+
+use crate::simple_db::{Database, Scope, Todo, TODO_COLUMNS};
+
+/// A todo together with the todos blocking it and the todos it blocks.
+#[derive(Debug, Serialize)]
+pub struct TodoDetail {
+    #[serde(flatten)]
+    pub todo: Todo,
+    pub blockers: Vec<Todo>,
+    pub dependents: Vec<Todo>,
+}
+
+#[derive(Debug, Deserialize)]
+pub struct NewDependency {
+    pub blocker_id: String,
+}
+
+#[derive(Debug)]
+pub enum DependencyError {
+    NotFound,
+    SelfReference,
+    Cycle,
+    DatabaseError,
+}
+
+impl From<DependencyError> for StatusCode {
+    fn from(error: DependencyError) -> Self {
+        match error {
+            DependencyError::NotFound => StatusCode::NOT_FOUND,
+            DependencyError::SelfReference => StatusCode::UNPROCESSABLE_ENTITY,
+            DependencyError::Cycle => StatusCode::CONFLICT,
+            DependencyError::DatabaseError => StatusCode::INTERNAL_SERVER_ERROR,
+        }
+    }
+}
+
+impl From<sqlx::Error> for DependencyError {
+    fn from(_: sqlx::Error) -> Self {
+        DependencyError::DatabaseError
+    }
+}
+
+pub async fn get_blockers(pool: &SqlitePool, todo_id: &str) -> Result<Vec<Todo>, sqlx::Error> {
+    let rows = sqlx::query(&format!("SELECT {} FROM todos WHERE id IN (SELECT blocker_id FROM todo_dependencies WHERE todo_id = ?) ORDER BY created_at", TODO_COLUMNS))
+        .bind(todo_id)
+        .fetch_all(pool)
+        .await?;
+
+    Ok(rows.iter().map(Todo::from_row).collect())
+}
+
+pub async fn get_dependents(pool: &SqlitePool, todo_id: &str) -> Result<Vec<Todo>, sqlx::Error> {
+    let rows = sqlx::query(&format!("SELECT {} FROM todos WHERE id IN (SELECT todo_id FROM todo_dependencies WHERE blocker_id = ?) ORDER BY created_at", TODO_COLUMNS))
+        .bind(todo_id)
+        .fetch_all(pool)
+        .await?;
+
+    Ok(rows.iter().map(Todo::from_row).collect())
+}
+
+pub async fn count_open_blockers(pool: &SqlitePool, todo_id: &str) -> Result<i64, sqlx::Error> {
+    let row = sqlx::query("SELECT COUNT(*) AS n FROM todo_dependencies d JOIN todos t ON t.id = d.blocker_id WHERE d.todo_id = ? AND t.completed = FALSE")
+        .bind(todo_id)
+        .fetch_one(pool)
+        .await?;
+
+    Ok(row.get("n"))
+}
+
+/// Marks `todo_id` as blocked by `blocker_id`. Both todos must belong to `scope`
+/// and the link must not close a cycle. The checks run on the writer
+/// connection, so no other link can be added between them and the insert.
+pub async fn add_blocker(db: &Database, scope: &Scope, todo_id: &str, blocker_id: &str) -> Result<(), DependencyError> {
+    if todo_id == blocker_id {
+        return Err(DependencyError::SelfReference);
+    }
+
+    let mut conn = db.write().await?;
+    let (condition, value) = scope.condition();
+    let owned: i64 = sqlx::query(&format!("SELECT COUNT(*) AS n FROM todos WHERE {}? AND id IN (?, ?)", condition))
+        .bind(value)
+        .bind(todo_id)
+        .bind(blocker_id)
+        .fetch_one(&mut *conn)
+        .await?
+        .get("n");
+    if owned != 2 {
+        return Err(DependencyError::NotFound);
+    }
+
+    // Walk everything the blocker (transitively) waits on; finding todo_id there means a cycle
+    let cycle = sqlx::query(
+        "WITH RECURSIVE chain(id) AS (
+            SELECT blocker_id FROM todo_dependencies WHERE todo_id = ?
+            UNION
+            SELECT d.blocker_id FROM todo_dependencies d JOIN chain ON d.todo_id = chain.id
+        ) SELECT 1 FROM chain WHERE id = ?",
+    )
+    .bind(blocker_id)
+    .bind(todo_id)
+    .fetch_optional(&mut *conn)
+    .await?;
+    if cycle.is_some() {
+        return Err(DependencyError::Cycle);
+    }
+
+    sqlx::query("INSERT OR IGNORE INTO todo_dependencies (todo_id, blocker_id, created_at) VALUES (?, ?, ?)")
+        .bind(todo_id)
+        .bind(blocker_id)
+        .bind(Utc::now())
+        .execute(&mut *conn)
+        .await?;
+
+    Ok(())
+}
+
+pub async fn remove_blocker(db: &Database, scope: &Scope, todo_id: &str, blocker_id: &str) -> Result<bool, sqlx::Error> {
+    let (condition, value) = scope.condition();
+    let result = sqlx::query(&format!("DELETE FROM todo_dependencies WHERE todo_id = ? AND blocker_id = ? AND todo_id IN (SELECT id FROM todos WHERE {}?)", condition))
+        .bind(todo_id)
+        .bind(blocker_id)
+        .bind(value)
+        .execute(&mut *db.write().await?)
+        .await?;
+
+    Ok(result.rows_affected() > 0)
+}
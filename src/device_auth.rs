@@ -0,0 +1,233 @@
+use axum::{
+    http::StatusCode,
+    response::{IntoResponse, Response},
+    Json,
+};
+use base64::{engine::general_purpose::URL_SAFE_NO_PAD, Engine};
+use chrono::{DateTime, Duration, Utc};
+use ring::{
+    digest::{digest, SHA256},
+    rand::{SecureRandom, SystemRandom},
+};
+use serde::{Deserialize, Serialize};
+use sqlx::Row;
+
+use crate::mailer;
+use crate::simple_db::{self, Database};
+
+/// How long a client has to get its code approved.
+const CODE_TTL_SECONDS: i64 = 600;
+/// How often a client may poll for its token; `slow_down` adds 5 each time
+/// it polls sooner.
+const POLL_INTERVAL_SECONDS: i64 = 5;
+/// Letters users can read off a terminal and type without mixing them up:
+/// no vowels (so no words) and none of the look-alikes.
+const USER_CODE_ALPHABET: &[u8] = b"BCDFGHJKLMNPQRSTVWXZ";
+
+/// Returned by `POST /auth/device/code`. The client shows `user_code` and
+/// `verification_uri` (or a QR code of `verification_uri_complete`) and
+/// polls `POST /auth/device/token` with `device_code` every `interval`
+/// seconds.
+#[derive(Debug, Serialize)]
+pub struct DeviceCode {
+    pub device_code: String,
+    pub user_code: String,
+    pub verification_uri: String,
+    pub verification_uri_complete: String,
+    pub expires_in: i64,
+    pub interval: i64,
+}
+
+/// Body of `POST /auth/device/token`. RFC 8628 clients also send
+/// `grant_type`, which is ignored.
+#[derive(Debug, Deserialize)]
+pub struct TokenRequest {
+    pub device_code: String,
+}
+
+/// Body of `POST /auth/device/approve`.
+#[derive(Debug, Deserialize)]
+pub struct Decision {
+    pub user_code: String,
+    /// `false` denies the client instead
+    pub approve: bool,
+}
+
+fn hash_code(code: &str) -> String {
+    URL_SAFE_NO_PAD.encode(digest(&SHA256, code.as_bytes()))
+}
+
+fn random_bytes<const N: usize>() -> Result<[u8; N], sqlx::Error> {
+    let mut bytes = [0u8; N];
+    SystemRandom::new()
+        .fill(&mut bytes)
+        .map_err(|_| sqlx::Error::Protocol("Failed to generate a device code".to_string()))?;
+    Ok(bytes)
+}
+
+/// Eight letters as `XXXX-XXXX`. The modulo bias of 256 over 20 letters
+/// doesn't matter for a code that lives ten minutes.
+fn new_user_code() -> Result<String, sqlx::Error> {
+    let letters: Vec<char> = random_bytes::<8>()?
+        .iter()
+        .map(|b| USER_CODE_ALPHABET[*b as usize % USER_CODE_ALPHABET.len()] as char)
+        .collect();
+    Ok(format!("{}-{}", letters[..4].iter().collect::<String>(), letters[4..].iter().collect::<String>()))
+}
+
+/// A user code as typed: any case, with or without the dash or spaces.
+fn normalize_user_code(user_code: &str) -> String {
+    let letters: String = user_code.chars().filter(char::is_ascii_alphanumeric).map(|c| c.to_ascii_uppercase()).collect();
+    match letters.len() {
+        8 => format!("{}-{}", &letters[..4], &letters[4..]),
+        _ => letters,
+    }
+}
+
+/// Starts a device authorization. Only a hash of the device code is
+/// stored; the user code is shown to the user and needn't be secret.
+pub async fn create(db: &Database) -> Result<DeviceCode, sqlx::Error> {
+    let device_code = URL_SAFE_NO_PAD.encode(random_bytes::<32>()?);
+    let now = Utc::now();
+    // Retry the rare clash with another code
+    let mut attempts = 0;
+    let user_code = loop {
+        let user_code = new_user_code()?;
+        let result = sqlx::query("INSERT INTO device_codes (device_code_hash, user_code, status, interval, created_at, expires_at) VALUES (?, ?, 'pending', ?, ?, ?)")
+            .bind(hash_code(&device_code))
+            .bind(&user_code)
+            .bind(POLL_INTERVAL_SECONDS)
+            .bind(now)
+            .bind(now + Duration::seconds(CODE_TTL_SECONDS))
+            .execute(&mut *db.write().await?)
+            .await;
+        match result {
+            Ok(_) => break user_code,
+            Err(sqlx::Error::Database(db)) if db.is_unique_violation() && attempts < 3 => attempts += 1,
+            Err(e) => return Err(e),
+        }
+    };
+
+    let verification_uri = format!("{}/device", mailer::public_url());
+    Ok(DeviceCode {
+        verification_uri_complete: format!("{}?user_code={}", verification_uri, user_code),
+        verification_uri,
+        device_code,
+        user_code,
+        expires_in: CODE_TTL_SECONDS,
+        interval: POLL_INTERVAL_SECONDS,
+    })
+}
+
+/// Approves or denies the pending code `user_code` on behalf of `user_id`.
+/// Unknown, expired and already decided codes are `NotFound`.
+pub async fn decide(db: &Database, user_id: &str, decision: &Decision) -> Result<(), DeviceError> {
+    let status = if decision.approve { "approved" } else { "denied" };
+    let result = sqlx::query("UPDATE device_codes SET status = ?, user_id = ? WHERE user_code = ? AND status = 'pending' AND expires_at > ?")
+        .bind(status)
+        .bind(user_id)
+        .bind(normalize_user_code(&decision.user_code))
+        .bind(Utc::now())
+        .execute(&mut *db.write().await?)
+        .await?;
+    if result.rows_affected() == 0 {
+        return Err(DeviceError::NotFound);
+    }
+    tracing::info!(user_id, status, "Decided on a device authorization");
+    Ok(())
+}
+
+/// One poll of `POST /auth/device/token`: the id of the user who approved
+/// the code, which is used up by this. Until then, the error says whether
+/// to keep polling.
+pub async fn poll(db: &Database, device_code: &str) -> Result<String, DeviceError> {
+    let hash = hash_code(device_code);
+    let now = Utc::now();
+    let row = sqlx::query("SELECT status, interval, expires_at, last_polled_at FROM device_codes WHERE device_code_hash = ?")
+        .bind(&hash)
+        .fetch_optional(db.get_pool())
+        .await?
+        .ok_or(DeviceError::InvalidGrant)?;
+
+    if row.get::<DateTime<Utc>, _>("expires_at") <= now {
+        sqlx::query("DELETE FROM device_codes WHERE device_code_hash = ?").bind(&hash).execute(&mut *db.write().await?).await?;
+        return Err(DeviceError::ExpiredToken);
+    }
+    let interval: i64 = row.get("interval");
+    let too_soon = row
+        .get::<Option<DateTime<Utc>>, _>("last_polled_at")
+        .is_some_and(|last| now < last + Duration::seconds(interval));
+    let status: String = row.get("status");
+    if too_soon && status == "pending" {
+        sqlx::query("UPDATE device_codes SET interval = interval + ?, last_polled_at = ? WHERE device_code_hash = ?")
+            .bind(POLL_INTERVAL_SECONDS)
+            .bind(now)
+            .bind(&hash)
+            .execute(&mut *db.write().await?)
+            .await?;
+        return Err(DeviceError::SlowDown);
+    }
+
+    match status.as_str() {
+        "pending" => {
+            sqlx::query("UPDATE device_codes SET last_polled_at = ? WHERE device_code_hash = ?").bind(now).bind(&hash).execute(&mut *db.write().await?).await?;
+            Err(DeviceError::AuthorizationPending)
+        }
+        "approved" => {
+            // Deleted as it's read, so two polls racing can't both get a token
+            let user_id: Option<String> = sqlx::query_scalar("DELETE FROM device_codes WHERE device_code_hash = ? AND status = 'approved' RETURNING user_id")
+                .bind(&hash)
+                .fetch_optional(&mut *db.write().await?)
+                .await?
+                .flatten();
+            user_id.ok_or(DeviceError::InvalidGrant)
+        }
+        _ => {
+            sqlx::query("DELETE FROM device_codes WHERE device_code_hash = ?").bind(&hash).execute(&mut *db.write().await?).await?;
+            Err(DeviceError::AccessDenied)
+        }
+    }
+}
+
+#[derive(Debug)]
+pub enum DeviceError {
+    /// The user hasn't decided yet; poll again after the interval
+    AuthorizationPending,
+    /// Polled sooner than the interval, which is now 5 seconds longer
+    SlowDown,
+    AccessDenied,
+    ExpiredToken,
+    /// Not a device code we issued, or one already exchanged
+    InvalidGrant,
+    /// No pending code with that user code
+    NotFound,
+    Database(sqlx::Error),
+}
+
+impl From<sqlx::Error> for DeviceError {
+    fn from(error: sqlx::Error) -> Self {
+        DeviceError::Database(error)
+    }
+}
+
+#[derive(Serialize)]
+struct TokenError {
+    error: &'static str,
+}
+
+/// The token endpoint's errors are `400` with an RFC 8628 error code, e.g.
+/// `{"error": "authorization_pending"}`.
+impl IntoResponse for DeviceError {
+    fn into_response(self) -> Response {
+        let error = match self {
+            DeviceError::AuthorizationPending => "authorization_pending",
+            DeviceError::SlowDown => "slow_down",
+            DeviceError::AccessDenied => "access_denied",
+            DeviceError::ExpiredToken => "expired_token",
+            DeviceError::InvalidGrant => "invalid_grant",
+            DeviceError::NotFound => return StatusCode::NOT_FOUND.into_response(),
+            DeviceError::Database(e) => return simple_db::error_status(&e).into_response(),
+        };
+        (StatusCode::BAD_REQUEST, Json(TokenError { error })).into_response()
+    }
+}
@@ -0,0 +1,170 @@
+use axum::async_trait;
+use chrono::{DateTime, Duration, NaiveDate, Timelike, Utc};
+use chrono_tz::Tz;
+use sqlx::{Row, SqlitePool};
+
+use crate::dates;
+use crate::i18n::Message;
+use crate::jobs::{Job, JobError, Trigger};
+use crate::mailer::{self, Mailer};
+use crate::settings::{UserSettings, SETTINGS_COLUMNS};
+use crate::simple_db::{Database, Todo, TODO_COLUMNS};
+
+/// What goes into one user's morning email.
+#[derive(Debug)]
+pub struct Digest {
+    pub date: NaiveDate,
+    pub due_today: Vec<Todo>,
+    pub overdue: Vec<Todo>,
+    pub completed_yesterday: Vec<Todo>,
+}
+
+impl Digest {
+    fn is_empty(&self) -> bool {
+        self.due_today.is_empty() && self.overdue.is_empty() && self.completed_yesterday.is_empty()
+    }
+
+    /// The email body in the user's language.
+    fn render(&self, settings: &UserSettings) -> String {
+        let language = settings.language;
+        let mut body = format!("{}\n", Message::DigestHeading { date: self.date }.text(language));
+        for (title, todos) in [
+            (Message::DigestDueToday, &self.due_today),
+            (Message::DigestOverdue, &self.overdue),
+            (Message::DigestCompletedYesterday, &self.completed_yesterday),
+        ] {
+            if todos.is_empty() {
+                continue;
+            }
+            body.push_str(&format!("\n{} ({})\n", title.text(language), todos.len()));
+            for todo in todos {
+                match todo.due_date.filter(|_| !todo.completed) {
+                    Some(due) => {
+                        let due = Message::Due { due: &settings.format_datetime(due) }.text(language);
+                        body.push_str(&format!("- {} ({})\n", todo.text, due));
+                    }
+                    None => body.push_str(&format!("- {}\n", todo.text)),
+                }
+            }
+        }
+        body.push_str(&format!("\n{}\n\n{}\n", mailer::public_url(), Message::DigestFooter.text(language)));
+        body
+    }
+}
+
+/// The user's personal todos plus workspace todos assigned to them.
+const DIGEST_TODOS: &str = "((user_id = ?1 AND workspace_id IS NULL) OR assignee_id = ?1)";
+
+pub async fn compute_digest(pool: &SqlitePool, user_id: &str, date: NaiveDate, tz: Tz) -> Result<Digest, sqlx::Error> {
+    let today = dates::start_of_day(date, &tz);
+    let tomorrow = dates::start_of_day(date + Duration::days(1), &tz);
+    let yesterday = dates::start_of_day(date - Duration::days(1), &tz);
+
+    let due_today = sqlx::query(&format!("SELECT {} FROM todos WHERE {} AND completed = FALSE AND due_date >= ?2 AND due_date < ?3 ORDER BY due_date", TODO_COLUMNS, DIGEST_TODOS))
+        .bind(user_id)
+        .bind(today)
+        .bind(tomorrow)
+        .fetch_all(pool)
+        .await?;
+    let overdue = sqlx::query(&format!("SELECT {} FROM todos WHERE {} AND completed = FALSE AND due_date < ?2 ORDER BY due_date", TODO_COLUMNS, DIGEST_TODOS))
+        .bind(user_id)
+        .bind(today)
+        .fetch_all(pool)
+        .await?;
+    let completed_yesterday = sqlx::query(&format!("SELECT {} FROM todos WHERE {} AND completed = TRUE AND completed_at >= ?2 AND completed_at < ?3 ORDER BY completed_at", TODO_COLUMNS, DIGEST_TODOS))
+        .bind(user_id)
+        .bind(yesterday)
+        .bind(today)
+        .fetch_all(pool)
+        .await?;
+
+    Ok(Digest {
+        date,
+        due_today: due_today.iter().map(Todo::from_row).collect(),
+        overdue: overdue.iter().map(Todo::from_row).collect(),
+        completed_yesterday: completed_yesterday.iter().map(Todo::from_row).collect(),
+    })
+}
+
+/// Emails today's digest to every subscribed user for whom it is past
+/// `hour` local time and who hasn't had one today. Returns how many were sent.
+pub async fn send_due_digests(db: &Database, mailer: &Mailer, hour: u32, now: DateTime<Utc>) -> Result<usize, sqlx::Error> {
+    let rows = sqlx::query(&format!("SELECT u.id, u.email, {} FROM users u JOIN user_settings s ON s.user_id = u.id WHERE s.daily_digest = TRUE AND u.disabled_at IS NULL", SETTINGS_COLUMNS))
+        .fetch_all(db.get_pool())
+        .await?;
+
+    let mut sent = 0;
+    for row in rows {
+        let user_id: String = row.get("id");
+        let Some(email): Option<String> = row.get("email") else {
+            continue;
+        };
+        let settings = UserSettings::from_row(&row);
+        let tz = settings.tz();
+
+        let local = now.with_timezone(&tz);
+        if local.hour() < hour {
+            continue;
+        }
+        let date = local.date_naive();
+
+        // Claim the day first so a second instance (or a slow mail server) can't send twice
+        let claimed = sqlx::query("INSERT OR IGNORE INTO digests (user_id, local_date, sent_at) VALUES (?, ?, ?)")
+            .bind(&user_id)
+            .bind(date)
+            .bind(now)
+            .execute(&mut *db.write().await?)
+            .await?;
+        if claimed.rows_affected() == 0 {
+            continue;
+        }
+
+        let digest = compute_digest(db.get_pool(), &user_id, date, tz).await?;
+        if digest.is_empty() {
+            continue;
+        }
+        let subject = Message::DigestSubject { date }.text(settings.language);
+        match mailer.send(&email, &subject, digest.render(&settings)).await {
+            Ok(()) => sent += 1,
+            Err(e) => tracing::warn!(error = %e, user_id, "Failed to send daily digest"),
+        }
+    }
+    Ok(sent)
+}
+
+/// Sends daily digests at `DIGEST_HOUR` (default 7) in each user's
+/// timezone. Runs every 15 minutes, so a digest goes out at most a quarter
+/// of an hour late, and still goes out later that day after downtime.
+pub struct DigestJob {
+    mailer: Mailer,
+    hour: u32,
+}
+
+impl DigestJob {
+    pub fn from_env(mailer: Mailer) -> Self {
+        DigestJob {
+            mailer,
+            hour: std::env::var("DIGEST_HOUR")
+                .ok()
+                .and_then(|hour| hour.parse().ok())
+                .filter(|hour| *hour < 24)
+                .unwrap_or(7),
+        }
+    }
+}
+
+#[async_trait]
+impl Job for DigestJob {
+    fn name(&self) -> &'static str {
+        "daily_digest"
+    }
+
+    fn default_schedule(&self) -> Option<&'static str> {
+        Some("*/15 * * * *")
+    }
+
+    async fn run(&self, db: &Database, _trigger: Trigger) -> Result<String, JobError> {
+        let sent = send_due_digests(db, &self.mailer, self.hour, Utc::now()).await?;
+        Ok(format!("Sent {} digests", sent))
+    }
+}
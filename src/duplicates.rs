@@ -0,0 +1,66 @@
+use serde::Serialize;
+use std::collections::HashSet;
+
+use crate::simple_db::Todo;
+
+/// How alike two texts must be, from 0 to 1, to count as possible duplicates.
+const THRESHOLD: f64 = 0.6;
+
+/// At most this many are reported, most alike first.
+const MAX_REPORTED: usize = 5;
+
+/// An open todo that looks like the one being added.
+#[derive(Debug, Serialize)]
+pub struct Duplicate {
+    pub id: String,
+    pub text: String,
+    /// 1 means the same text, ignoring case, punctuation and spacing
+    pub similarity: f64,
+}
+
+/// Lowercase words, without punctuation: "Buy  milk!" and "buy milk" match.
+fn normalize(text: &str) -> String {
+    text.to_lowercase()
+        .split(|c: char| !c.is_alphanumeric())
+        .filter(|word| !word.is_empty())
+        .collect::<Vec<_>>()
+        .join(" ")
+}
+
+/// The three-character windows of ` text `, padded so short words and word
+/// boundaries count too.
+fn trigrams(normalized: &str) -> HashSet<[char; 3]> {
+    let chars: Vec<char> = format!("  {} ", normalized).chars().collect();
+    chars.windows(3).map(|window| [window[0], window[1], window[2]]).collect()
+}
+
+/// Jaccard similarity of the texts' trigrams. Tolerates typos, plurals and
+/// small rewordings, unlike an exact comparison.
+fn similarity(a: &str, b: &str) -> f64 {
+    let (a, b) = (normalize(a), normalize(b));
+    if a == b {
+        return 1.0;
+    }
+    let (a, b) = (trigrams(&a), trigrams(&b));
+    let shared = a.intersection(&b).count();
+    shared as f64 / (a.len() + b.len() - shared) as f64
+}
+
+/// The open todos among `todos` whose text is close to `text`.
+pub fn find(text: &str, todos: &[Todo]) -> Vec<Duplicate> {
+    let mut duplicates: Vec<Duplicate> = todos
+        .iter()
+        .filter(|todo| !todo.completed)
+        .filter_map(|todo| {
+            let similarity = similarity(text, &todo.text);
+            (similarity >= THRESHOLD).then(|| Duplicate {
+                id: todo.id.clone(),
+                text: todo.text.clone(),
+                similarity: (similarity * 100.0).round() / 100.0,
+            })
+        })
+        .collect();
+    duplicates.sort_by(|a, b| b.similarity.total_cmp(&a.similarity));
+    duplicates.truncate(MAX_REPORTED);
+    duplicates
+}
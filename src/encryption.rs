@@ -0,0 +1,196 @@
+use base64::{engine::general_purpose::STANDARD, Engine};
+use ring::aead::{Aad, LessSafeKey, Nonce, UnboundKey, AES_256_GCM, NONCE_LEN};
+use ring::rand::{SecureRandom, SystemRandom};
+use sqlx::{Connection, Row};
+use std::sync::OnceLock;
+
+use crate::simple_db::Database;
+
+/// Marks a stored value as encrypted; what follows is base64 of the nonce
+/// and the sealed text. Values without it are plaintext, from before
+/// encryption was turned on, and read as they are.
+pub const PREFIX: &str = "enc:v1:";
+
+/// Rows `encrypt_existing` rewrites per transaction
+const BATCH: i64 = 500;
+
+struct Cipher {
+    key: LessSafeKey,
+    raw: Vec<u8>,
+}
+
+/// Set once at startup. Todos are read in many places that don't go
+/// through `Database`, so the key is process-wide rather than threaded
+/// through each of them.
+static CIPHER: OnceLock<Cipher> = OnceLock::new();
+
+/// Turns on encryption of todo text and notes with `key` (`TODO_ENCRYPTION_KEY`:
+/// 32 bytes, base64) for the rest of the process.
+pub fn enable(key: &str) -> Result<(), String> {
+    let raw = STANDARD.decode(key.trim()).map_err(|_| "TODO_ENCRYPTION_KEY isn't valid base64".to_string())?;
+    let unbound = UnboundKey::new(&AES_256_GCM, &raw).map_err(|_| "TODO_ENCRYPTION_KEY must be 32 bytes".to_string())?;
+    let cipher = CIPHER.get_or_init(|| Cipher { key: LessSafeKey::new(unbound), raw: raw.clone() });
+    if cipher.raw != raw {
+        return Err("a different TODO_ENCRYPTION_KEY is already in use".to_string());
+    }
+    Ok(())
+}
+
+pub fn is_enabled() -> bool {
+    CIPHER.get().is_some()
+}
+
+/// `value` as it should be stored in `column` of the todo `todo_id`:
+/// sealed with AES-256-GCM when encryption is on, as is otherwise. The
+/// todo and column are bound in, so a value copied to another row or
+/// column doesn't decrypt.
+pub fn encrypt(todo_id: &str, column: &str, value: &str) -> String {
+    let Some(cipher) = CIPHER.get() else {
+        return value.to_string();
+    };
+    let mut nonce = [0u8; NONCE_LEN];
+    SystemRandom::new().fill(&mut nonce).expect("system randomness is available");
+    let mut sealed = value.as_bytes().to_vec();
+    cipher
+        .key
+        .seal_in_place_append_tag(Nonce::assume_unique_for_key(nonce), aad(todo_id, column), &mut sealed)
+        .expect("todo text fits in one AES-GCM message");
+    let mut stored = nonce.to_vec();
+    stored.append(&mut sealed);
+    format!("{}{}", PREFIX, STANDARD.encode(stored))
+}
+
+/// Like `encrypt`, for a column that may be empty.
+pub fn encrypt_optional(todo_id: &str, column: &str, value: Option<&str>) -> Option<String> {
+    value.map(|value| encrypt(todo_id, column, value))
+}
+
+/// The plaintext of a value read from `column` of `todo_id`. A value that
+/// can't be decrypted (no key, or the wrong one) is logged and returned as
+/// stored rather than failing the whole read.
+pub fn decrypt(todo_id: &str, column: &str, stored: String) -> String {
+    let Some(encoded) = stored.strip_prefix(PREFIX) else {
+        return stored;
+    };
+    match CIPHER.get().and_then(|cipher| open(cipher, todo_id, column, encoded)) {
+        Some(value) => value,
+        None => {
+            tracing::warn!(todo_id, column, "Can't decrypt todo field; is TODO_ENCRYPTION_KEY set to the key it was written with?");
+            stored
+        }
+    }
+}
+
+pub fn decrypt_optional(todo_id: &str, column: &str, stored: Option<String>) -> Option<String> {
+    stored.map(|stored| decrypt(todo_id, column, stored))
+}
+
+fn open(cipher: &Cipher, todo_id: &str, column: &str, encoded: &str) -> Option<String> {
+    let mut stored = STANDARD.decode(encoded).ok()?;
+    if stored.len() < NONCE_LEN {
+        return None;
+    }
+    let mut sealed = stored.split_off(NONCE_LEN);
+    let nonce = Nonce::try_assume_unique_for_key(&stored).ok()?;
+    let value = cipher.key.open_in_place(nonce, aad(todo_id, column), &mut sealed).ok()?;
+    String::from_utf8(value.to_vec()).ok()
+}
+
+fn aad(todo_id: &str, column: &str) -> Aad<Vec<u8>> {
+    Aad::from(format!("{}:{}", column, todo_id).into_bytes())
+}
+
+/// Tables other than `todos` that copy todo text into one column: the
+/// table, the column, and the column whose value it's sealed with along with
+/// the table's name. Notifications and deliveries are bound to their own id;
+/// the outbox and the event log to the todo or user the event is about, as
+/// `outbox::record` and `events::append` seal them.
+const SEALED_COLUMNS: &[(&str, &str, &str)] = &[
+    ("notifications", "message", "id"),
+    ("webhook_deliveries", "payload", "id"),
+    ("outbox", "payload", "subject_id"),
+    ("events", "data", "subject_id"),
+];
+
+/// Encrypts the text and notes of todos still stored in plaintext, e.g.
+/// from before encryption was turned on, and drops their search text; then
+/// does the same for the copies in `SEALED_COLUMNS`. Returns how many rows
+/// changed in each table.
+/// Versions are left alone, since the todos read the same as before. Does
+/// nothing while encryption is off.
+pub async fn encrypt_existing(db: &Database) -> Result<Vec<(&'static str, u64)>, sqlx::Error> {
+    if !is_enabled() {
+        return Ok(Vec::new());
+    }
+    let mut report = vec![("todos", encrypt_todos(db).await?)];
+    for &(table, column, bound_to) in SEALED_COLUMNS {
+        report.push((table, encrypt_column(db, table, column, bound_to).await?));
+    }
+    Ok(report)
+}
+
+async fn encrypt_todos(db: &Database) -> Result<u64, sqlx::Error> {
+    let pattern = format!("{}%", PREFIX);
+    let mut encrypted = 0;
+    loop {
+        let mut conn = db.write().await?;
+        let mut tx = conn.begin().await?;
+        let rows = sqlx::query("SELECT id, text, notes FROM todos WHERE (text IS NOT NULL AND text NOT LIKE ?1) OR (notes IS NOT NULL AND notes NOT LIKE ?1) LIMIT ?2")
+            .bind(&pattern)
+            .bind(BATCH)
+            .fetch_all(&mut *tx)
+            .await?;
+        if rows.is_empty() {
+            return Ok(encrypted);
+        }
+
+        for row in &rows {
+            let id: String = row.get("id");
+            let text: Option<String> = row.get("text");
+            let notes: Option<String> = row.get("notes");
+            let seal = |column, value: Option<String>| match value {
+                Some(value) if !value.starts_with(PREFIX) => Some(encrypt(&id, column, &value)),
+                value => value,
+            };
+            sqlx::query("UPDATE todos SET text = ?, notes = ?, search_text = NULL WHERE id = ?")
+                .bind(seal("text", text))
+                .bind(seal("notes", notes))
+                .bind(&id)
+                .execute(&mut *tx)
+                .await?;
+        }
+        tx.commit().await?;
+        encrypted += rows.len() as u64;
+    }
+}
+
+/// Encrypts `column` of the rows of `table` still in plaintext, bound to
+/// their `bound_to`. Rows are found again by `rowid`, since the tables'
+/// keys differ in name and type.
+async fn encrypt_column(db: &Database, table: &str, column: &str, bound_to: &str) -> Result<u64, sqlx::Error> {
+    let pattern = format!("{}%", PREFIX);
+    let mut encrypted = 0;
+    loop {
+        let mut conn = db.write().await?;
+        let mut tx = conn.begin().await?;
+        let rows = sqlx::query(&format!("SELECT rowid AS row, {2} AS bound_to, {1} AS value FROM {0} WHERE {1} NOT LIKE ?1 LIMIT ?2", table, column, bound_to))
+            .bind(&pattern)
+            .bind(BATCH)
+            .fetch_all(&mut *tx)
+            .await?;
+        if rows.is_empty() {
+            return Ok(encrypted);
+        }
+
+        for row in &rows {
+            let bound_to: String = row.get("bound_to");
+            sqlx::query(&format!("UPDATE {} SET {} = ? WHERE rowid = ?", table, column))
+                .bind(encrypt(&bound_to, table, row.get("value")))
+                .bind(row.get::<i64, _>("row"))
+                .execute(&mut *tx)
+                .await?;
+        }
+        tx.commit().await?;
+        encrypted += rows.len() as u64;
+    }
+}
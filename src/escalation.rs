@@ -0,0 +1,157 @@
+use axum::async_trait;
+use chrono::{DateTime, Duration, Utc};
+use serde::Serialize;
+use sqlx::{Connection, Row, SqlitePool};
+use std::sync::Arc;
+use uuid::Uuid;
+
+use crate::hooks::Event;
+use crate::i18n::Message;
+use crate::jobs::{Job, JobError, Trigger};
+use crate::notifications;
+use crate::notify::Dispatcher;
+use crate::outbox;
+use crate::simple_db::{Database, Priority, Todo, TODO_COLUMNS};
+
+/// The `actor_id` of the `todo_updated` events escalations record, since no
+/// user made the change.
+const ACTOR_ID: &str = "escalation";
+
+/// An entry in a todo's escalation history, from `GET /todos/:id/escalations`.
+#[derive(Debug, Serialize)]
+pub struct Escalation {
+    /// `null` when the todo had no priority
+    pub from_priority: Option<Priority>,
+    pub to_priority: Priority,
+    /// The due date that prompted it
+    pub due_date: DateTime<Utc>,
+    pub escalated_at: DateTime<Utc>,
+}
+
+/// One step up; todos without a priority count as low.
+fn raised(priority: Option<Priority>) -> Option<Priority> {
+    match priority {
+        None | Some(Priority::Low) => Some(Priority::Medium),
+        Some(Priority::Medium) => Some(Priority::High),
+        Some(Priority::High) => None,
+    }
+}
+
+/// Raises the priority of open todos due within `ESCALATION_WINDOW_HOURS`
+/// (default 24, `0` turns the job off) by one step and notifies whoever is
+/// responsible: the assignee, or else the owner. Each todo is escalated once
+/// per due date, so moving the due date lets it happen again. Todos with
+/// `auto_escalate: false` are left alone.
+pub struct EscalationJob {
+    db: Arc<Database>,
+    dispatcher: Dispatcher,
+    window: Option<Duration>,
+}
+
+impl EscalationJob {
+    pub fn from_env(db: Arc<Database>, dispatcher: Dispatcher) -> Self {
+        let hours: i64 = std::env::var("ESCALATION_WINDOW_HOURS")
+            .ok()
+            .and_then(|hours| hours.parse().ok())
+            .unwrap_or(24);
+        EscalationJob { db, dispatcher, window: (hours > 0).then(|| Duration::hours(hours)) }
+    }
+
+    /// Raises `todo`'s priority and records it, unless it changed since it
+    /// was loaded. Returns the new priority if it was escalated.
+    async fn escalate(&self, todo: &Todo, due_date: DateTime<Utc>) -> Result<Option<Priority>, sqlx::Error> {
+        let Some(priority) = raised(todo.priority) else {
+            return Ok(None);
+        };
+        let now = Utc::now();
+        let mut conn = self.db.write().await?;
+        let mut tx = conn.begin().await?;
+
+        let query = format!("UPDATE todos SET priority = ?, updated_at = ?, version = version + 1 WHERE id = ? AND version = ? RETURNING {}", TODO_COLUMNS);
+        let Some(row) = sqlx::query(&query)
+            .bind(priority.as_str())
+            .bind(now)
+            .bind(&todo.id)
+            .bind(todo.version)
+            .fetch_optional(&mut *tx)
+            .await?
+        else {
+            return Ok(None);
+        };
+        let updated = Todo::from_row(&row);
+        outbox::record(&mut tx, &Event::TodoUpdated { todo: &updated, actor_id: ACTOR_ID, text_changed: false }).await?;
+        sqlx::query("INSERT INTO todo_escalations (id, todo_id, from_priority, to_priority, due_date, escalated_at) VALUES (?, ?, ?, ?, ?, ?)")
+            .bind(Uuid::new_v4().to_string())
+            .bind(&todo.id)
+            .bind(todo.priority.map(Priority::as_str))
+            .bind(priority.as_str())
+            .bind(due_date)
+            .bind(now)
+            .execute(&mut *tx)
+            .await?;
+
+        tx.commit().await?;
+        self.db.todo_changed(todo).await;
+        self.db.events_recorded();
+        Ok(Some(priority))
+    }
+}
+
+#[async_trait]
+impl Job for EscalationJob {
+    fn name(&self) -> &'static str {
+        "escalation"
+    }
+
+    fn default_schedule(&self) -> Option<&'static str> {
+        self.window.map(|_| "*/15 * * * *")
+    }
+
+    async fn run(&self, db: &Database, _trigger: Trigger) -> Result<String, JobError> {
+        let window = self.window.unwrap_or_else(|| Duration::hours(24));
+        let rows = sqlx::query(&format!("SELECT {} FROM todos WHERE completed = FALSE AND auto_escalate AND due_date <= ? AND (priority IS NULL OR priority != 'high') AND NOT EXISTS (SELECT 1 FROM todo_escalations e WHERE e.todo_id = todos.id AND e.due_date = todos.due_date) ORDER BY due_date", TODO_COLUMNS))
+            .bind(Utc::now() + window)
+            .fetch_all(db.get_pool())
+            .await?;
+
+        let mut escalated = 0;
+        for todo in rows.iter().map(Todo::from_row) {
+            let Some(due_date) = todo.due_date else {
+                continue;
+            };
+            let Some(priority) = self.escalate(&todo, due_date).await? else {
+                continue;
+            };
+            escalated += 1;
+
+            let Some(recipient) = todo.assignee_id.as_deref().or(todo.user_id.as_deref()) else {
+                continue;
+            };
+            let message = Message::Escalated { todo: &todo.text, priority };
+            if let Err(e) = notifications::notify(db, &self.dispatcher, recipient, "escalated", message, Some(&todo.id)).await {
+                tracing::warn!(todo_id = %todo.id, error = %e, "Failed to notify about escalation");
+            }
+        }
+        Ok(format!("Escalated {} todos", escalated))
+    }
+}
+
+/// A todo's escalations, newest first. The caller checks the todo is visible.
+pub async fn get_escalations(pool: &SqlitePool, todo_id: &str) -> Result<Vec<Escalation>, sqlx::Error> {
+    let rows = sqlx::query("SELECT from_priority, to_priority, due_date, escalated_at FROM todo_escalations WHERE todo_id = ? ORDER BY escalated_at DESC")
+        .bind(todo_id)
+        .fetch_all(pool)
+        .await?;
+
+    Ok(rows
+        .iter()
+        .filter_map(|row| {
+            Some(Escalation {
+                from_priority: row.get::<Option<String>, _>("from_priority").and_then(|p| p.parse().ok()),
+                to_priority: row.get::<String, _>("to_priority").parse().ok()?,
+                due_date: row.get("due_date"),
+                escalated_at: row.get("escalated_at"),
+            })
+        })
+        .collect())
+}
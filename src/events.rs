@@ -0,0 +1,122 @@
+use chrono::{DateTime, Utc};
+use serde::{Deserialize, Serialize};
+use serde_json::{json, Value};
+use sqlx::{Row, SqliteConnection, SqlitePool};
+
+use crate::encryption;
+use crate::hooks::Event;
+use crate::simple_db::Scope;
+
+const DEFAULT_LIMIT: u32 = 100;
+const MAX_LIMIT: u32 = 1000;
+
+/// `?since=<seq>&limit=100`. Without `since`, from the first event.
+#[derive(Debug, Deserialize)]
+pub struct EventsQuery {
+    #[serde(default)]
+    pub since: i64,
+    pub limit: Option<u32>,
+}
+
+impl EventsQuery {
+    /// 1 to 1000, default 100.
+    pub fn limit(&self) -> u32 {
+        self.limit.unwrap_or(DEFAULT_LIMIT).clamp(1, MAX_LIMIT)
+    }
+}
+
+/// One entry in the event log.
+#[derive(Debug, Serialize)]
+pub struct LoggedEvent {
+    /// Increases with every event, in the order they happened
+    pub seq: i64,
+    pub event: String,
+    /// The todo the event is about, or the user for `user_registered`
+    pub subject_id: String,
+    /// Who made the change
+    pub actor_id: String,
+    /// The todo as it was right after the change, plus `text_changed` for
+    /// `todo_updated`; `username` for `user_registered`
+    pub data: Value,
+    pub created_at: DateTime<Utc>,
+}
+
+/// A page of the event log.
+#[derive(Debug, Serialize)]
+pub struct EventLog {
+    pub events: Vec<LoggedEvent>,
+    /// The last `seq` here, or `since` if there were none; pass it as
+    /// `since` to read on
+    pub last_seq: i64,
+    /// More events are waiting; read on from `last_seq` right away
+    pub has_more: bool,
+}
+
+/// Appends `event` to the log, on the transaction that makes the change.
+/// Todo events go in the todo's scope, like its sync changes; a new user's
+/// in their personal scope.
+pub async fn append(conn: &mut SqliteConnection, event: &Event<'_>) -> Result<(), sqlx::Error> {
+    let (subject_id, actor_id, user_id, workspace_id, data) = match *event {
+        Event::TodoCreated { todo, actor_id }
+        | Event::TodoCompleted { todo, actor_id }
+        | Event::TodoReopened { todo, actor_id }
+        | Event::TodoAssigned { todo, actor_id } => (todo.id.as_str(), actor_id, todo.user_id.as_deref(), todo.workspace_id.as_deref(), json!({ "todo": todo })),
+        Event::TodoUpdated { todo, actor_id, text_changed } => {
+            (todo.id.as_str(), actor_id, todo.user_id.as_deref(), todo.workspace_id.as_deref(), json!({ "todo": todo, "text_changed": text_changed }))
+        }
+        Event::UserRegistered { user_id, username } => (user_id, user_id, Some(user_id), None, json!({ "username": username })),
+    };
+    // Todo text is in `data`, so it's sealed like the todo's own columns
+    sqlx::query("INSERT INTO events (event, subject_id, actor_id, user_id, workspace_id, data, created_at) VALUES (?, ?, ?, ?, ?, ?, ?)")
+        .bind(event.kind())
+        .bind(subject_id)
+        .bind(actor_id)
+        .bind(if workspace_id.is_none() { user_id } else { None })
+        .bind(workspace_id)
+        .bind(encryption::encrypt(subject_id, "events", &data.to_string()))
+        .bind(Utc::now())
+        .execute(conn)
+        .await?;
+    Ok(())
+}
+
+/// The events in `scope` after `since`, oldest first.
+///
+/// SQLite commits writes one at a time and each event is appended with its
+/// change, so an event can't appear behind a `seq` a reader has already
+/// seen.
+pub async fn list(pool: &SqlitePool, scope: &Scope, since: i64, limit: u32) -> Result<EventLog, sqlx::Error> {
+    let (condition, value) = match &scope.workspace_id {
+        Some(workspace_id) => ("workspace_id = ?", workspace_id),
+        None => ("workspace_id IS NULL AND user_id = ?", &scope.user_id),
+    };
+    let rows = sqlx::query(&format!(
+        "SELECT seq, event, subject_id, actor_id, data, created_at FROM events WHERE {} AND seq > ? ORDER BY seq LIMIT ?",
+        condition
+    ))
+    .bind(value)
+    .bind(since)
+    .bind(limit + 1)
+    .fetch_all(pool)
+    .await?;
+
+    let has_more = rows.len() > limit as usize;
+    let events: Vec<LoggedEvent> = rows
+        .iter()
+        .take(limit as usize)
+        .map(|row| {
+            let subject_id: String = row.get("subject_id");
+            let data = encryption::decrypt(&subject_id, "events", row.get("data"));
+            LoggedEvent {
+                seq: row.get("seq"),
+                event: row.get("event"),
+                actor_id: row.get("actor_id"),
+                data: serde_json::from_str(&data).unwrap_or_default(),
+                subject_id,
+                created_at: row.get("created_at"),
+            }
+        })
+        .collect();
+    let last_seq = events.last().map_or(since, |event| event.seq);
+    Ok(EventLog { events, last_seq, has_more })
+}
@@ -0,0 +1,98 @@
+use axum::{
+    http::StatusCode,
+    response::{IntoResponse, Response},
+    Json,
+};
+use serde::Deserialize;
+use serde_json::{Map, Value};
+
+use crate::simple_db::Todo;
+
+/// The keys of a todo in JSON, which `?fields=` picks from.
+const TODO_FIELDS: &[&str] = &[
+    "id",
+    "public_id",
+    "text",
+    "notes",
+    "notes_html",
+    "links",
+    "completed",
+    "category",
+    "tags",
+    "color",
+    "latitude",
+    "longitude",
+    "location_name",
+    "priority",
+    "due_date",
+    "user_id",
+    "workspace_id",
+    "assignee_id",
+    "estimate_minutes",
+    "spent_minutes",
+    "completed_at",
+    "created_at",
+    "updated_at",
+    "version",
+    "auto_escalate",
+    "is_overdue",
+    "_links",
+];
+
+/// `?fields=id,text,completed,due_date` on todo lists: only those keys of
+/// each todo, to keep responses small on slow mobile connections.
+#[derive(Debug, Default, Deserialize)]
+pub struct FieldsQuery {
+    /// JSON:API clients send it as `fields[todos]`
+    #[serde(alias = "fields[todos]")]
+    pub fields: Option<String>,
+}
+
+impl FieldsQuery {
+    /// `None` for whole todos; `400` for a name that isn't a todo field.
+    pub fn todo_fields(&self) -> Result<Option<Fields>, StatusCode> {
+        let Some(fields) = &self.fields else {
+            return Ok(None);
+        };
+        // `id` always comes along, so every item can still be addressed
+        let mut names = vec!["id"];
+        for name in fields.split(',').map(str::trim).filter(|name| !name.is_empty()) {
+            let name = TODO_FIELDS.iter().find(|field| **field == name).ok_or(StatusCode::BAD_REQUEST)?;
+            if !names.contains(name) {
+                names.push(name);
+            }
+        }
+        Ok(Some(Fields { names }))
+    }
+}
+
+#[derive(Clone, Debug)]
+pub struct Fields {
+    names: Vec<&'static str>,
+}
+
+impl Fields {
+    /// Whether `name` was asked for, so work that only fills in other fields
+    /// (link previews, rendered notes, `_links`) can be skipped.
+    pub fn contains(&self, name: &str) -> bool {
+        self.names.contains(&name)
+    }
+
+    /// `todo` with just the chosen keys. Those the whole todo leaves out when
+    /// unset (`notes_html`, `links`, `_links`) stay out.
+    pub fn select(&self, todo: &Todo) -> Value {
+        let Ok(Value::Object(mut object)) = serde_json::to_value(todo) else {
+            return Value::Null;
+        };
+        let selected: Map<String, Value> = self.names.iter().filter_map(|name| object.remove_entry(*name)).collect();
+        Value::Object(selected)
+    }
+}
+
+/// A plain list of todos as JSON, trimmed to `fields` if given.
+pub fn respond(todos: Vec<Todo>, fields: Option<&Fields>) -> Response {
+    match fields {
+        Some(fields) => Json(todos.iter().map(|todo| fields.select(todo)).collect::<Vec<_>>()).into_response(),
+        None => Json(todos).into_response(),
+    }
+}
@@ -0,0 +1,87 @@
+use chrono::{DateTime, Utc};
+use serde::{Deserialize, Serialize};
+use sqlx::{sqlite::SqliteRow, Row, SqlitePool};
+use uuid::Uuid;
+
+use crate::simple_db::{Database, TodoFilter};
+
+/// A named, persisted `TodoFilter` ("High priority work due this week").
+#[derive(Clone, Debug, Serialize)]
+pub struct SavedFilter {
+    pub id: String,
+    pub name: String,
+    pub filter: TodoFilter,
+    pub created_at: DateTime<Utc>,
+    pub updated_at: DateTime<Utc>,
+}
+
+impl SavedFilter {
+    fn from_row(row: &SqliteRow) -> Self {
+        SavedFilter {
+            id: row.get("id"),
+            name: row.get("name"),
+            filter: serde_json::from_str(row.get("filter")).unwrap_or_default(),
+            created_at: row.get("created_at"),
+            updated_at: row.get("updated_at"),
+        }
+    }
+}
+
+#[derive(Debug, Deserialize)]
+pub struct NewSavedFilter {
+    pub name: String,
+    pub filter: TodoFilter,
+}
+
+pub async fn create_filter(db: &Database, user_id: &str, new_filter: NewSavedFilter) -> Result<SavedFilter, sqlx::Error> {
+    let id = Uuid::new_v4().to_string();
+    let now = Utc::now();
+    let filter_json = serde_json::to_string(&new_filter.filter).unwrap_or_default();
+
+    sqlx::query("INSERT INTO saved_filters (id, user_id, name, filter, created_at, updated_at) VALUES (?, ?, ?, ?, ?, ?)")
+        .bind(&id)
+        .bind(user_id)
+        .bind(&new_filter.name)
+        .bind(&filter_json)
+        .bind(now)
+        .bind(now)
+        .execute(&mut *db.write().await?)
+        .await?;
+
+    Ok(SavedFilter {
+        id,
+        name: new_filter.name,
+        filter: new_filter.filter,
+        created_at: now,
+        updated_at: now,
+    })
+}
+
+pub async fn get_filters(pool: &SqlitePool, user_id: &str) -> Result<Vec<SavedFilter>, sqlx::Error> {
+    let rows = sqlx::query("SELECT id, name, filter, created_at, updated_at FROM saved_filters WHERE user_id = ? ORDER BY name")
+        .bind(user_id)
+        .fetch_all(pool)
+        .await?;
+
+    Ok(rows.iter().map(SavedFilter::from_row).collect())
+}
+
+pub async fn get_filter(pool: &SqlitePool, user_id: &str, id: &str) -> Result<Option<SavedFilter>, sqlx::Error> {
+    let row = sqlx::query("SELECT id, name, filter, created_at, updated_at FROM saved_filters WHERE id = ? AND user_id = ?")
+        .bind(id)
+        .bind(user_id)
+        .fetch_optional(pool)
+        .await?;
+
+    Ok(row.as_ref().map(SavedFilter::from_row))
+}
+
+pub async fn delete_filter(db: &Database, user_id: &str, id: &str) -> Result<bool, sqlx::Error> {
+    let result = sqlx::query("DELETE FROM saved_filters WHERE id = ? AND user_id = ?")
+        .bind(id)
+        .bind(user_id)
+        .execute(&mut *db.write().await?)
+        .await?;
+
+    Ok(result.rows_affected() > 0)
+}
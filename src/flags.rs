@@ -0,0 +1,91 @@
+use sqlx::{Row, SqlitePool};
+use std::collections::BTreeMap;
+
+use crate::simple_db::Database;
+
+/// A feature that can be switched on for some users before everyone.
+struct Flag {
+    /// Used in `GET /flags`, the CLI and `FLAG_<NAME>`
+    name: &'static str,
+    /// Whether users without an override get it
+    default: bool,
+}
+
+const FLAGS: &[Flag] = &[
+    // `POST /todos/parse`, in builds with the `ai` feature
+    Flag { name: "llm_parser", default: false },
+];
+
+/// Which features each user gets: the default for everyone, from
+/// `FLAG_<NAME>=true|false` or else the built-in one, unless the user has
+/// an override in `feature_flags`.
+#[derive(Clone, Debug)]
+pub struct Flags {
+    defaults: Vec<(&'static str, bool)>,
+}
+
+impl Flags {
+    pub fn from_env() -> Self {
+        let defaults = FLAGS
+            .iter()
+            .map(|flag| {
+                let enabled = std::env::var(format!("FLAG_{}", flag.name.to_uppercase()))
+                    .ok()
+                    .and_then(|enabled| enabled.parse().ok())
+                    .unwrap_or(flag.default);
+                (flag.name, enabled)
+            })
+            .collect();
+        Flags { defaults }
+    }
+
+    /// Whether `user_id` gets the feature `name`; unknown flags are off.
+    // Every flagged feature is optional at build time so far
+    #[cfg_attr(not(feature = "ai"), allow(dead_code))]
+    pub async fn is_enabled(&self, pool: &SqlitePool, user_id: &str, name: &str) -> Result<bool, sqlx::Error> {
+        let Some(&(_, default)) = self.defaults.iter().find(|(flag, _)| *flag == name) else {
+            return Ok(false);
+        };
+        let enabled: Option<bool> = sqlx::query_scalar("SELECT enabled FROM feature_flags WHERE user_id = ? AND name = ?")
+            .bind(user_id)
+            .bind(name)
+            .fetch_optional(pool)
+            .await?;
+        Ok(enabled.unwrap_or(default))
+    }
+
+    /// Every flag, as `user_id` gets it.
+    pub async fn for_user(&self, pool: &SqlitePool, user_id: &str) -> Result<BTreeMap<&'static str, bool>, sqlx::Error> {
+        let mut flags: BTreeMap<&'static str, bool> = self.defaults.iter().copied().collect();
+        let rows = sqlx::query("SELECT name, enabled FROM feature_flags WHERE user_id = ?").bind(user_id).fetch_all(pool).await?;
+        for row in rows {
+            let name: String = row.get("name");
+            if let Some(enabled) = flags.get_mut(name.as_str()) {
+                *enabled = row.get("enabled");
+            }
+        }
+        Ok(flags)
+    }
+}
+
+pub fn exists(name: &str) -> bool {
+    FLAGS.iter().any(|flag| flag.name == name)
+}
+
+/// Overrides the flag `name` for `user_id`; `None` goes back to the default.
+pub async fn set(db: &Database, name: &str, user_id: &str, enabled: Option<bool>) -> Result<(), sqlx::Error> {
+    match enabled {
+        Some(enabled) => {
+            sqlx::query("INSERT INTO feature_flags (name, user_id, enabled) VALUES (?, ?, ?) ON CONFLICT (name, user_id) DO UPDATE SET enabled = excluded.enabled")
+                .bind(name)
+                .bind(user_id)
+                .bind(enabled)
+                .execute(&mut *db.write().await?)
+                .await?;
+        }
+        None => {
+            sqlx::query("DELETE FROM feature_flags WHERE name = ? AND user_id = ?").bind(name).bind(user_id).execute(&mut *db.write().await?).await?;
+        }
+    }
+    Ok(())
+}
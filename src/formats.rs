@@ -0,0 +1,269 @@
+#[cfg(feature = "msgpack")]
+use axum::{body::Body, extract::Request, middleware::Next};
+use axum::http::{header, HeaderMap, HeaderName, HeaderValue, StatusCode, Uri};
+use axum::response::{IntoResponse, Response};
+use axum::Json;
+use chrono::{DateTime, Utc};
+
+use crate::fields::Fields;
+use crate::jsonapi;
+use crate::pagination::{Listing, Page};
+use crate::simple_db::{Priority, Todo};
+
+/// Carries `next_cursor` for paginated lists in formats without a place for it.
+pub const X_NEXT_CURSOR: HeaderName = HeaderName::from_static("x-next-cursor");
+
+/// A representation of a todo list, chosen from the `Accept` header.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum Format {
+    Json,
+    Csv,
+    ICalendar,
+    /// JSON:API documents, for generic JSON:API client libraries
+    JsonApi,
+    /// JSON re-encoded by the `msgpack` middleware
+    #[cfg(feature = "msgpack")]
+    MessagePack,
+}
+
+impl Format {
+    const ALL: &[Format] = &[
+        Format::Json,
+        Format::Csv,
+        Format::ICalendar,
+        Format::JsonApi,
+        #[cfg(feature = "msgpack")]
+        Format::MessagePack,
+    ];
+
+    fn media_type(self) -> &'static str {
+        match self {
+            Format::Json => "application/json",
+            Format::Csv => "text/csv",
+            Format::ICalendar => "text/calendar",
+            Format::JsonApi => jsonapi::MEDIA_TYPE,
+            #[cfg(feature = "msgpack")]
+            Format::MessagePack => "application/msgpack",
+        }
+    }
+
+    /// The acceptable format with the highest `q`, earlier ones winning ties.
+    /// JSON without an `Accept` header or for `*/*`; 406 if nothing offered
+    /// is acceptable.
+    pub fn from_accept(headers: &HeaderMap) -> Result<Format, StatusCode> {
+        let Some(accept) = headers.get(header::ACCEPT) else {
+            return Ok(Format::Json);
+        };
+        let accept = accept.to_str().map_err(|_| StatusCode::BAD_REQUEST)?;
+
+        let mut best: Option<(Format, f32)> = None;
+        for range in accept.split(',') {
+            let mut params = range.split(';').map(str::trim);
+            let media_type = params.next().unwrap_or_default().to_ascii_lowercase();
+            let q = params
+                .find_map(|param| param.strip_prefix("q="))
+                .and_then(|q| q.parse().ok())
+                .unwrap_or(1.0);
+            if q <= 0.0 {
+                continue;
+            }
+            let format = match media_type.as_str() {
+                "*/*" | "application/*" => Some(Format::Json),
+                "text/*" => Some(Format::Csv),
+                _ => Format::ALL.iter().copied().find(|format| format.media_type() == media_type),
+            };
+            if let Some(format) = format
+                && best.is_none_or(|(_, best_q)| q > best_q)
+            {
+                best = Some((format, q));
+            }
+        }
+        best.map(|(format, _)| format).ok_or(StatusCode::NOT_ACCEPTABLE)
+    }
+
+    /// `listing` in this format, with JSON todos trimmed to `fields` if
+    /// given. CSV and iCalendar bodies hold just the todos, whole; a page's
+    /// `next_cursor` goes in `X-Next-Cursor`. `uri`, the request's, is for
+    /// JSON:API's pagination links.
+    pub fn respond(self, listing: Listing<Todo>, fields: Option<&Fields>, uri: &Uri) -> Response {
+        let render = match self {
+            Format::Csv => csv,
+            Format::ICalendar => icalendar,
+            Format::JsonApi => return with_vary(jsonapi::listing(listing, fields, uri)),
+            _ => match fields {
+                Some(fields) => return with_vary(Json(listing.map(|todo| fields.select(&todo))).into_response()),
+                None => return with_vary(Json(listing).into_response()),
+            },
+        };
+        let (todos, next_cursor) = match listing {
+            Listing::All(todos) => (todos, None),
+            Listing::Page(Page { items, next_cursor, .. }) => (items, next_cursor),
+        };
+
+        let body = render(&todos);
+        let content_type = format!("{}; charset=utf-8", self.media_type());
+        let mut response = ([(header::CONTENT_TYPE, content_type)], body).into_response();
+        if let Some(cursor) = next_cursor.and_then(|cursor| HeaderValue::from_str(&cursor).ok()) {
+            response.headers_mut().insert(X_NEXT_CURSOR, cursor);
+        }
+        with_vary(response)
+    }
+}
+
+/// Caches must not hand one format to a client asking for another.
+fn with_vary(mut response: Response) -> Response {
+    response.headers_mut().insert(header::VARY, HeaderValue::from_static("accept"));
+    response
+}
+
+/// Re-encodes JSON responses, errors included, as MessagePack when the
+/// client prefers `application/msgpack` to JSON. Smaller and quicker to
+/// parse on mobile; the handlers stay JSON-only.
+#[cfg(feature = "msgpack")]
+pub async fn msgpack(request: Request, next: Next) -> Response {
+    let wanted = Format::from_accept(request.headers()) == Ok(Format::MessagePack);
+    let response = next.run(request).await;
+    let is_json = response
+        .headers()
+        .get(header::CONTENT_TYPE)
+        .is_some_and(|content_type| content_type.as_bytes().starts_with(b"application/json"));
+    if !wanted || !is_json {
+        return response;
+    }
+
+    let (mut parts, body) = response.into_parts();
+    let Ok(json) = axum::body::to_bytes(body, usize::MAX).await else {
+        return StatusCode::INTERNAL_SERVER_ERROR.into_response();
+    };
+    let encoded = serde_json::from_slice::<serde_json::Value>(&json)
+        .ok()
+        .and_then(|value| rmp_serde::to_vec_named(&value).ok());
+    let Some(encoded) = encoded else {
+        return Response::from_parts(parts, Body::from(json));
+    };
+    parts.headers.remove(header::CONTENT_LENGTH);
+    parts.headers.insert(header::CONTENT_TYPE, HeaderValue::from_static("application/msgpack"));
+    parts.headers.insert(header::VARY, HeaderValue::from_static("accept"));
+    Response::from_parts(parts, Body::from(encoded))
+}
+
+fn rfc3339(date: Option<DateTime<Utc>>) -> String {
+    date.map(|date| date.to_rfc3339()).unwrap_or_default()
+}
+
+/// RFC 4180 CSV with a header row. Tags are joined with `;`.
+pub fn csv(todos: &[Todo]) -> String {
+    let mut out = String::from("id,text,notes,completed,category,tags,priority,due_date,estimate_minutes,spent_minutes,created_at,completed_at\r\n");
+    for todo in todos {
+        let fields = [
+            todo.id.clone(),
+            todo.text.clone(),
+            todo.notes.clone().unwrap_or_default(),
+            todo.completed.to_string(),
+            todo.category.clone().unwrap_or_default(),
+            todo.tag_list().join(";"),
+            todo.priority.map(|priority| priority.as_str().to_string()).unwrap_or_default(),
+            rfc3339(todo.due_date),
+            todo.estimate_minutes.map(|minutes| minutes.to_string()).unwrap_or_default(),
+            todo.spent_minutes.map(|minutes| minutes.to_string()).unwrap_or_default(),
+            rfc3339(Some(todo.created_at)),
+            rfc3339(todo.completed_at),
+        ];
+        let row: Vec<String> = fields.iter().map(|field| csv_field(field)).collect();
+        out.push_str(&row.join(","));
+        out.push_str("\r\n");
+    }
+    out
+}
+
+fn csv_field(value: &str) -> String {
+    // Spreadsheets run cells starting with these as formulas
+    let value = if value.starts_with(['=', '+', '-', '@']) { format!("'{}", value) } else { value.to_string() };
+    if value.contains([',', '"', '\r', '\n']) {
+        format!("\"{}\"", value.replace('"', "\"\""))
+    } else {
+        value
+    }
+}
+
+/// An iCalendar (RFC 5545) calendar with a `VTODO` per todo, for calendar
+/// and task apps that import `.ics` files.
+pub fn icalendar(todos: &[Todo]) -> String {
+    let mut lines = vec![
+        "BEGIN:VCALENDAR".to_string(),
+        "VERSION:2.0".to_string(),
+        "PRODID:-//todo-app//EN".to_string(),
+    ];
+    for todo in todos {
+        lines.push("BEGIN:VTODO".to_string());
+        lines.push(format!("UID:{}", todo.id));
+        lines.push(format!("DTSTAMP:{}", ical_date(todo.updated_at)));
+        lines.push(format!("CREATED:{}", ical_date(todo.created_at)));
+        lines.push(format!("SUMMARY:{}", ical_text(&todo.text)));
+        if let Some(notes) = &todo.notes {
+            lines.push(format!("DESCRIPTION:{}", ical_text(notes)));
+        }
+        if let Some(due_date) = todo.due_date {
+            lines.push(format!("DUE:{}", ical_date(due_date)));
+        }
+        if let Some(location_name) = &todo.location_name {
+            lines.push(format!("LOCATION:{}", ical_text(location_name)));
+        }
+        if let (Some(latitude), Some(longitude)) = (todo.latitude, todo.longitude) {
+            lines.push(format!("GEO:{};{}", latitude, longitude));
+        }
+        if let Some(priority) = todo.priority {
+            // 1 is the highest, 9 the lowest
+            let level = match priority {
+                Priority::High => 1,
+                Priority::Medium => 5,
+                Priority::Low => 9,
+            };
+            lines.push(format!("PRIORITY:{}", level));
+        }
+        let categories: Vec<String> = todo.category.iter().cloned().chain(todo.tag_list()).map(|category| ical_text(&category)).collect();
+        if !categories.is_empty() {
+            lines.push(format!("CATEGORIES:{}", categories.join(",")));
+        }
+        if todo.completed {
+            lines.push("STATUS:COMPLETED".to_string());
+            if let Some(completed_at) = todo.completed_at {
+                lines.push(format!("COMPLETED:{}", ical_date(completed_at)));
+            }
+        } else {
+            lines.push("STATUS:NEEDS-ACTION".to_string());
+        }
+        lines.push("END:VTODO".to_string());
+    }
+    lines.push("END:VCALENDAR".to_string());
+
+    lines.iter().map(|line| fold(line) + "\r\n").collect()
+}
+
+fn ical_date(date: DateTime<Utc>) -> String {
+    date.format("%Y%m%dT%H%M%SZ").to_string()
+}
+
+fn ical_text(text: &str) -> String {
+    text.replace('\\', "\\\\")
+        .replace(';', "\\;")
+        .replace(',', "\\,")
+        .replace("\r\n", "\\n")
+        .replace('\n', "\\n")
+}
+
+/// Splits `line` into lines of at most 75 bytes, continuations starting with
+/// a space, without breaking a UTF-8 character.
+fn fold(line: &str) -> String {
+    let mut out = String::with_capacity(line.len());
+    let mut width = 0;
+    for c in line.chars() {
+        if width + c.len_utf8() > 75 {
+            out.push_str("\r\n ");
+            width = 1;
+        }
+        out.push(c);
+        width += c.len_utf8();
+    }
+    out
+}
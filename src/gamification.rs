@@ -0,0 +1,118 @@
+use axum::async_trait;
+use chrono::{DateTime, Duration, NaiveDate, Utc};
+use serde::Serialize;
+use sqlx::{Row, SqlitePool};
+
+use crate::hooks::{Event, Hook, HookError};
+use crate::settings;
+use crate::simple_db::{Database, Priority};
+
+/// Points for completing a todo; important ones are worth more.
+fn points(priority: Option<Priority>) -> i64 {
+    match priority {
+        Some(Priority::High) => 5,
+        Some(Priority::Medium) => 3,
+        Some(Priority::Low) | None => 1,
+    }
+}
+
+/// Points earned for one completed todo, on the day (in the user's timezone)
+/// it was completed.
+#[derive(Debug, Serialize)]
+pub struct Award {
+    pub todo_id: String,
+    pub points: i64,
+    pub local_date: NaiveDate,
+    pub awarded_at: DateTime<Utc>,
+}
+
+/// `GET /gamification/me`.
+#[derive(Debug, Serialize)]
+pub struct Summary {
+    pub points: i64,
+    pub points_today: i64,
+    pub completed: i64,
+    /// Days in a row with a completion, up to today; a streak survives until
+    /// the end of the day after its last completion
+    pub current_streak: u32,
+    pub longest_streak: u32,
+    pub last_completed_on: Option<NaiveDate>,
+}
+
+/// Awards points for each completion, once per todo: completing, reopening
+/// and completing again earns nothing more.
+pub struct GamificationHook;
+
+#[async_trait]
+impl Hook for GamificationHook {
+    fn name(&self) -> &'static str {
+        "gamification"
+    }
+
+    async fn handle(&self, db: &Database, event: &Event<'_>) -> Result<(), HookError> {
+        let Event::TodoCompleted { todo, actor_id } = *event else {
+            return Ok(());
+        };
+
+        let now = Utc::now();
+        let tz = settings::get_settings(db.get_pool(), actor_id).await?.tz();
+        sqlx::query("INSERT OR IGNORE INTO points_awards (user_id, todo_id, points, local_date, awarded_at) VALUES (?, ?, ?, ?, ?)")
+            .bind(actor_id)
+            .bind(&todo.id)
+            .bind(points(todo.priority))
+            .bind(now.with_timezone(&tz).date_naive())
+            .bind(now)
+            .execute(&mut *db.write().await?)
+            .await?;
+        Ok(())
+    }
+}
+
+/// The user's points and streaks as of `today`, their local date.
+pub async fn summary(pool: &SqlitePool, user_id: &str, today: NaiveDate) -> Result<Summary, sqlx::Error> {
+    let rows = sqlx::query("SELECT local_date, SUM(points) AS points, COUNT(*) AS completed FROM points_awards WHERE user_id = ? GROUP BY local_date ORDER BY local_date")
+        .bind(user_id)
+        .fetch_all(pool)
+        .await?;
+
+    let mut summary = Summary { points: 0, points_today: 0, completed: 0, current_streak: 0, longest_streak: 0, last_completed_on: None };
+    let mut streak = 0;
+    for row in &rows {
+        let date: NaiveDate = row.get("local_date");
+        let points: i64 = row.get("points");
+        summary.points += points;
+        summary.completed += row.get::<i64, _>("completed");
+        if date == today {
+            summary.points_today = points;
+        }
+
+        streak = match summary.last_completed_on {
+            Some(previous) if date - previous == Duration::days(1) => streak + 1,
+            _ => 1,
+        };
+        summary.longest_streak = summary.longest_streak.max(streak);
+        summary.last_completed_on = Some(date);
+    }
+    if summary.last_completed_on.is_some_and(|last| today - last <= Duration::days(1)) {
+        summary.current_streak = streak;
+    }
+    Ok(summary)
+}
+
+/// Every award, oldest first, for the data export.
+pub async fn get_awards(pool: &SqlitePool, user_id: &str) -> Result<Vec<Award>, sqlx::Error> {
+    let rows = sqlx::query("SELECT todo_id, points, local_date, awarded_at FROM points_awards WHERE user_id = ? ORDER BY awarded_at")
+        .bind(user_id)
+        .fetch_all(pool)
+        .await?;
+
+    Ok(rows
+        .iter()
+        .map(|row| Award {
+            todo_id: row.get("todo_id"),
+            points: row.get("points"),
+            local_date: row.get("local_date"),
+            awarded_at: row.get("awarded_at"),
+        })
+        .collect())
+}
@@ -0,0 +1,720 @@
+use axum::async_trait;
+use base64::{engine::general_purpose::URL_SAFE_NO_PAD, Engine};
+use chrono::{DateTime, Duration, NaiveDate, Utc};
+use chrono_tz::Tz;
+use ring::{
+    digest::{digest, SHA256},
+    rand::{SecureRandom, SystemRandom},
+};
+use serde::Serialize;
+use serde_json::{json, Value};
+use sqlx::{sqlite::SqliteRow, Connection as _, Row, SqlitePool};
+use std::sync::Arc;
+
+use crate::dates;
+use crate::encryption;
+use crate::dependencies;
+use crate::hooks::{Event, Hooks};
+use crate::jobs::{Job, JobError, Trigger};
+use crate::outbox;
+use crate::search;
+use crate::settings;
+use crate::simple_db::{Database, Scope, Todo, TODO_COLUMNS};
+
+const AUTH_URL: &str = "https://accounts.google.com/o/oauth2/v2/auth";
+const TOKEN_URL: &str = "https://oauth2.googleapis.com/token";
+const REVOKE_URL: &str = "https://oauth2.googleapis.com/revoke";
+const API_URL: &str = "https://www.googleapis.com/calendar/v3";
+
+/// Only calendars the app creates itself, not the user's others.
+const SCOPE: &str = "https://www.googleapis.com/auth/calendar.app.created";
+
+/// How long the user has to finish the consent screen.
+const STATE_TTL_MINUTES: i64 = 10;
+
+const CALENDAR_NAME: &str = "Todos";
+
+/// Marks a completed todo's event; typing it in front of an event's title
+/// in Google Calendar completes the todo.
+const DONE_MARK: char = '✓';
+
+/// Length of an event for a todo without an estimate.
+const DEFAULT_EVENT_MINUTES: i64 = 30;
+
+#[derive(Debug)]
+pub enum SyncError {
+    /// Google refused the refresh token (access revoked, or expired); the
+    /// user has to connect again
+    Unauthorized,
+    /// Google answered with this status
+    Status(u16),
+    Http(String),
+    Database(sqlx::Error),
+}
+
+impl std::fmt::Display for SyncError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            SyncError::Unauthorized => f.write_str("Google Calendar access was revoked; connect again"),
+            SyncError::Status(status) => write!(f, "Google Calendar answered {}", status),
+            SyncError::Http(message) => f.write_str(message),
+            SyncError::Database(e) => write!(f, "database error: {}", e),
+        }
+    }
+}
+
+impl From<sqlx::Error> for SyncError {
+    fn from(e: sqlx::Error) -> Self {
+        SyncError::Database(e)
+    }
+}
+
+/// `GET /integrations/google`.
+#[derive(Debug, Serialize)]
+pub struct Connection {
+    /// `false` until the consent screen is done, and after access was revoked
+    pub connected: bool,
+    pub calendar_id: Option<String>,
+    pub connected_at: Option<DateTime<Utc>>,
+    pub last_synced_at: Option<DateTime<Utc>>,
+    /// Why the last sync failed; cleared by the next one that works
+    pub last_error: Option<String>,
+    /// Todos with an event in the calendar
+    pub events: i64,
+}
+
+#[derive(Debug, Default, Serialize)]
+pub struct SyncReport {
+    /// Todos changed from their events
+    pub pulled: u32,
+    /// Events created, updated or deleted from todos
+    pub pushed: u32,
+}
+
+/// The stored side of a user's connection.
+struct Account {
+    user_id: String,
+    refresh_token: String,
+    access_token: Option<String>,
+    access_expires_at: Option<DateTime<Utc>>,
+    calendar_id: String,
+    sync_token: Option<String>,
+}
+
+impl Account {
+    fn from_row(row: &SqliteRow) -> Self {
+        Account {
+            user_id: row.get("user_id"),
+            refresh_token: row.get("refresh_token"),
+            access_token: row.get("access_token"),
+            access_expires_at: row.get("access_expires_at"),
+            calendar_id: row.get("calendar_id"),
+            sync_token: row.get("sync_token"),
+        }
+    }
+}
+
+/// A todo's event as last synced.
+struct Link {
+    todo_id: String,
+    etag: Option<String>,
+    synced_version: i64,
+}
+
+fn hash_state(state: &str) -> String {
+    URL_SAFE_NO_PAD.encode(digest(&SHA256, state.as_bytes()))
+}
+
+/// `calendars/<id>/events[/<event id>]` under the API, with ids escaped.
+fn events_url(calendar_id: &str, event_id: Option<&str>) -> reqwest::Url {
+    let mut url = reqwest::Url::parse(API_URL).expect("valid API URL");
+    {
+        let mut segments = url.path_segments_mut().expect("API URL has a path");
+        segments.extend(["calendars", calendar_id, "events"]);
+        if let Some(event_id) = event_id {
+            segments.push(event_id);
+        }
+    }
+    url
+}
+
+/// The event for a todo with a due date.
+fn event_body(todo: &Todo, due: DateTime<Utc>, tz: Tz) -> Value {
+    let summary = if todo.completed { format!("{} {}", DONE_MARK, todo.text) } else { todo.text.clone() };
+    let end = due + Duration::minutes(todo.estimate_minutes.filter(|minutes| *minutes > 0).unwrap_or(DEFAULT_EVENT_MINUTES));
+    json!({
+        "summary": summary,
+        "description": todo.notes.clone().unwrap_or_default(),
+        "start": { "dateTime": due.to_rfc3339(), "timeZone": tz.name() },
+        "end": { "dateTime": end.to_rfc3339(), "timeZone": tz.name() },
+        "extendedProperties": { "private": { "todo_id": todo.id } },
+    })
+}
+
+/// What an event says about its todo: the text, whether it's done, and when
+/// it's due. All-day events are due at the start of the day.
+fn read_event(event: &Value, tz: Tz) -> Option<(String, bool, DateTime<Utc>)> {
+    let summary = event["summary"].as_str().unwrap_or_default().trim();
+    let (text, done) = match summary.strip_prefix(DONE_MARK) {
+        Some(text) => (text.trim(), true),
+        None => (summary, false),
+    };
+    let due = match (event["start"]["dateTime"].as_str(), event["start"]["date"].as_str()) {
+        (Some(time), _) => DateTime::parse_from_rfc3339(time).ok()?.with_timezone(&Utc),
+        (None, Some(date)) => dates::start_of_day(NaiveDate::parse_from_str(date, "%Y-%m-%d").ok()?, &tz),
+        (None, None) => return None,
+    };
+    Some((text.to_string(), done, due))
+}
+
+/// Two-way sync of users' dated personal todos with a calendar the app
+/// creates in their Google account. Configured with an OAuth client from the
+/// Google Cloud console:
+///
+/// - `GOOGLE_CLIENT_ID`, `GOOGLE_CLIENT_SECRET`: required; without them the
+///   `/integrations/google` endpoints are 404
+/// - `GOOGLE_REDIRECT_URL`: this server's `/integrations/google/callback`,
+///   as registered with the client
+#[derive(Clone)]
+pub struct GoogleCalendar {
+    http: reqwest::Client,
+    client_id: String,
+    client_secret: String,
+    redirect_url: String,
+}
+
+impl GoogleCalendar {
+    pub fn from_env() -> Option<Self> {
+        let var = |name: &str| std::env::var(name).ok().filter(|value| !value.is_empty());
+        let (client_id, client_secret, redirect_url) = (var("GOOGLE_CLIENT_ID")?, var("GOOGLE_CLIENT_SECRET")?, var("GOOGLE_REDIRECT_URL")?);
+        let http = reqwest::Client::builder()
+            .timeout(std::time::Duration::from_secs(30))
+            .build()
+            .expect("Failed to build HTTP client");
+        Some(GoogleCalendar { http, client_id, client_secret, redirect_url })
+    }
+
+    /// The consent screen to send the user to. Its `state` is single use,
+    /// expires after 10 minutes and identifies the user on the way back.
+    pub async fn authorize_url(&self, db: &Database, user_id: &str) -> Result<String, SyncError> {
+        let mut bytes = [0u8; 32];
+        SystemRandom::new().fill(&mut bytes).map_err(|_| SyncError::Http("failed to generate a state".to_string()))?;
+        let state = URL_SAFE_NO_PAD.encode(bytes);
+
+        sqlx::query("INSERT INTO google_calendars (user_id, state_hash, state_expires_at) VALUES (?1, ?2, ?3) ON CONFLICT (user_id) DO UPDATE SET state_hash = ?2, state_expires_at = ?3")
+            .bind(user_id)
+            .bind(hash_state(&state))
+            .bind(Utc::now() + Duration::minutes(STATE_TTL_MINUTES))
+            .execute(&mut *db.write().await?)
+            .await?;
+
+        let url = reqwest::Url::parse_with_params(AUTH_URL, [
+            ("client_id", self.client_id.as_str()),
+            ("redirect_uri", self.redirect_url.as_str()),
+            ("response_type", "code"),
+            ("scope", SCOPE),
+            // A refresh token, every time, so the job can sync without the user
+            ("access_type", "offline"),
+            ("prompt", "consent"),
+            ("state", state.as_str()),
+        ])
+        .map_err(|e| SyncError::Http(e.to_string()))?;
+        Ok(url.to_string())
+    }
+
+    /// Finishes the consent flow: trades `code` for tokens and creates the
+    /// calendar, unless the user is reconnecting and already has one.
+    /// `false` for an unknown or expired `state`.
+    pub async fn connect(&self, db: &Database, code: &str, state: &str) -> Result<bool, SyncError> {
+        let Some(row) = sqlx::query("UPDATE google_calendars SET state_hash = NULL, state_expires_at = NULL WHERE state_hash = ? AND state_expires_at > ? RETURNING user_id, calendar_id")
+            .bind(hash_state(state))
+            .bind(Utc::now())
+            .fetch_optional(&mut *db.write().await?)
+            .await?
+        else {
+            return Ok(false);
+        };
+        let user_id: String = row.get("user_id");
+
+        let tokens = self
+            .token_request(&[
+                ("code", code),
+                ("client_id", &self.client_id),
+                ("client_secret", &self.client_secret),
+                ("redirect_uri", &self.redirect_url),
+                ("grant_type", "authorization_code"),
+            ])
+            .await?;
+        let (Some(access_token), Some(refresh_token)) = (tokens["access_token"].as_str(), tokens["refresh_token"].as_str()) else {
+            return Err(SyncError::Http("no tokens in Google's response".to_string()));
+        };
+        let expires_at = Utc::now() + Duration::seconds(tokens["expires_in"].as_i64().unwrap_or(0));
+
+        let calendar_id = match row.get::<Option<String>, _>("calendar_id") {
+            Some(calendar_id) => calendar_id,
+            None => {
+                let tz = settings::get_settings(db.get_pool(), &user_id).await?.tz();
+                let request = self
+                    .http
+                    .post(format!("{}/calendars", API_URL))
+                    .bearer_auth(access_token)
+                    .header("Content-Type", "application/json")
+                    .body(json!({ "summary": CALENDAR_NAME, "timeZone": tz.name() }).to_string());
+                let calendar = send(request).await?;
+                calendar["id"]
+                    .as_str()
+                    .ok_or_else(|| SyncError::Http("no id for the new calendar".to_string()))?
+                    .to_string()
+            }
+        };
+
+        sqlx::query("UPDATE google_calendars SET refresh_token = ?, access_token = ?, access_expires_at = ?, calendar_id = ?, connected_at = ?, last_error = NULL WHERE user_id = ?")
+            .bind(refresh_token)
+            .bind(access_token)
+            .bind(expires_at)
+            .bind(&calendar_id)
+            .bind(Utc::now())
+            .bind(&user_id)
+            .execute(&mut *db.write().await?)
+            .await?;
+        Ok(true)
+    }
+
+    /// Stops syncing and revokes the app's access. The calendar and its
+    /// events stay in the user's Google account. `false` if not connected.
+    pub async fn disconnect(&self, db: &Database, user_id: &str) -> Result<bool, sqlx::Error> {
+        let Some(row) = sqlx::query("DELETE FROM google_calendars WHERE user_id = ? RETURNING refresh_token")
+            .bind(user_id)
+            .fetch_optional(&mut *db.write().await?)
+            .await?
+        else {
+            return Ok(false);
+        };
+        sqlx::query("DELETE FROM google_calendar_events WHERE user_id = ?")
+            .bind(user_id)
+            .execute(&mut *db.write().await?)
+            .await?;
+
+        if let Some(refresh_token) = row.get::<Option<String>, _>("refresh_token") {
+            // Access ends here either way; a failed revocation only leaves the grant listed in the Google account
+            let revoked = self.http.post(REVOKE_URL).form(&[("token", refresh_token)]).send().await;
+            if let Err(e) = revoked.and_then(|response| response.error_for_status()) {
+                tracing::warn!(user_id, error = %e.without_url(), "Failed to revoke Google Calendar access");
+            }
+        }
+        Ok(true)
+    }
+
+    /// Pulls changes made in Google Calendar, then pushes todos changed
+    /// since their last sync. Records the outcome for `GET
+    /// /integrations/google`; a revoked grant disconnects the user.
+    pub async fn sync_user(&self, db: &Database, hooks: &Hooks, user_id: &str) -> Result<SyncReport, SyncError> {
+        let pool = db.get_pool();
+        let Some(account) = sqlx::query("SELECT user_id, refresh_token, access_token, access_expires_at, calendar_id, sync_token FROM google_calendars WHERE user_id = ? AND refresh_token IS NOT NULL AND calendar_id IS NOT NULL")
+            .bind(user_id)
+            .fetch_optional(pool)
+            .await?
+            .as_ref()
+            .map(Account::from_row)
+        else {
+            return Err(SyncError::Unauthorized);
+        };
+
+        let result = self.sync(db, hooks, account).await;
+        match &result {
+            Ok(_) => {
+                sqlx::query("UPDATE google_calendars SET last_synced_at = ?, last_error = NULL WHERE user_id = ?")
+                    .bind(Utc::now())
+                    .bind(user_id)
+                    .execute(&mut *db.write().await?)
+                    .await?;
+            }
+            Err(SyncError::Unauthorized) => {
+                sqlx::query("UPDATE google_calendars SET refresh_token = NULL, access_token = NULL, access_expires_at = NULL, last_error = ? WHERE user_id = ?")
+                    .bind(SyncError::Unauthorized.to_string())
+                    .bind(user_id)
+                    .execute(&mut *db.write().await?)
+                    .await?;
+            }
+            Err(e) => {
+                sqlx::query("UPDATE google_calendars SET last_error = ? WHERE user_id = ?")
+                    .bind(e.to_string())
+                    .bind(user_id)
+                    .execute(&mut *db.write().await?)
+                    .await?;
+            }
+        }
+        result
+    }
+
+    async fn sync(&self, db: &Database, hooks: &Hooks, account: Account) -> Result<SyncReport, SyncError> {
+        let pool = db.get_pool();
+        let token = self.access_token(db, &account).await?;
+        let tz = settings::get_settings(pool, &account.user_id).await?.tz();
+        let mut report = SyncReport::default();
+
+        // Pull first, so edits made in Google aren't overwritten by the push
+        let mut sync_token = account.sync_token.clone();
+        let mut page_token: Option<String> = None;
+        loop {
+            let mut url = events_url(&account.calendar_id, None);
+            {
+                let mut query = url.query_pairs_mut();
+                query.append_pair("maxResults", "250");
+                match &sync_token {
+                    Some(sync_token) => query.append_pair("syncToken", sync_token),
+                    None => query.append_pair("showDeleted", "true"),
+                };
+                if let Some(page_token) = &page_token {
+                    query.append_pair("pageToken", page_token);
+                }
+            }
+            let page = match send(self.http.get(url).bearer_auth(&token)).await {
+                // The sync token expired: start over with a full listing
+                Err(SyncError::Status(410)) if sync_token.is_some() => {
+                    (sync_token, page_token) = (None, None);
+                    continue;
+                }
+                page => page?,
+            };
+
+            for event in page["items"].as_array().into_iter().flatten() {
+                if self.pull(db, hooks, &account.user_id, event, tz).await? {
+                    report.pulled += 1;
+                }
+            }
+            if let Some(next) = page["nextPageToken"].as_str() {
+                page_token = Some(next.to_string());
+                continue;
+            }
+            sqlx::query("UPDATE google_calendars SET sync_token = ? WHERE user_id = ?")
+                .bind(page["nextSyncToken"].as_str())
+                .bind(&account.user_id)
+                .execute(&mut *db.write().await?)
+                .await?;
+            break;
+        }
+
+        report.pushed = self.push(db, &account, &token, tz).await?;
+        Ok(report)
+    }
+
+    /// A valid access token, refreshed when it's about to expire.
+    async fn access_token(&self, db: &Database, account: &Account) -> Result<String, SyncError> {
+        if let (Some(token), Some(expires_at)) = (&account.access_token, account.access_expires_at)
+            && expires_at > Utc::now() + Duration::minutes(1)
+        {
+            return Ok(token.clone());
+        }
+
+        let tokens = match self
+            .token_request(&[
+                ("refresh_token", &account.refresh_token),
+                ("client_id", &self.client_id),
+                ("client_secret", &self.client_secret),
+                ("grant_type", "refresh_token"),
+            ])
+            .await
+        {
+            // `invalid_grant`: revoked, or unused for six months
+            Err(SyncError::Status(400 | 401)) => return Err(SyncError::Unauthorized),
+            tokens => tokens?,
+        };
+        let token = tokens["access_token"]
+            .as_str()
+            .ok_or_else(|| SyncError::Http("no access token in Google's response".to_string()))?
+            .to_string();
+
+        sqlx::query("UPDATE google_calendars SET access_token = ?, access_expires_at = ? WHERE user_id = ?")
+            .bind(&token)
+            .bind(Utc::now() + Duration::seconds(tokens["expires_in"].as_i64().unwrap_or(0)))
+            .bind(&account.user_id)
+            .execute(&mut *db.write().await?)
+            .await?;
+        Ok(token)
+    }
+
+    async fn token_request(&self, form: &[(&str, &str)]) -> Result<Value, SyncError> {
+        send(self.http.post(TOKEN_URL).form(form)).await
+    }
+
+    /// Applies a changed event to its todo. Events the app didn't create,
+    /// and its own writes coming back, are skipped. A deleted event takes
+    /// the todo's due date away. When both sides changed, the later edit
+    /// wins. Returns whether the todo changed.
+    async fn pull(&self, db: &Database, hooks: &Hooks, user_id: &str, event: &Value, tz: Tz) -> Result<bool, SyncError> {
+        let pool = db.get_pool();
+        let Some(event_id) = event["id"].as_str() else {
+            return Ok(false);
+        };
+        let Some(link) = find_link(pool, user_id, event_id).await? else {
+            return Ok(false);
+        };
+        if link.etag.is_some() && event["etag"].as_str() == link.etag.as_deref() {
+            return Ok(false);
+        }
+        let scope = Scope { user_id: user_id.to_string(), workspace_id: None };
+        let Some(todo) = db.get_todo(&link.todo_id, &scope).await? else {
+            unlink(db, user_id, &link.todo_id).await?;
+            return Ok(false);
+        };
+
+        if event["status"].as_str() == Some("cancelled") {
+            unlink(db, user_id, &todo.id).await?;
+            let Some(todo) = set_text_and_due(db, &todo, &todo.text, None, user_id).await? else {
+                return Ok(false);
+            };
+            hooks.emit(db, Event::TodoUpdated { todo: &todo, actor_id: user_id, text_changed: false }).await;
+            return Ok(true);
+        }
+
+        let edited_here = todo.version != link.synced_version;
+        let edited_there = event["updated"]
+            .as_str()
+            .and_then(|updated| DateTime::parse_from_rfc3339(updated).ok())
+            .map(|updated| updated.with_timezone(&Utc));
+        if edited_here && edited_there.is_some_and(|updated| updated < todo.updated_at) {
+            return Ok(false);
+        }
+        let Some((text, done, due)) = read_event(event, tz) else {
+            return Ok(false);
+        };
+
+        let mut current = todo.clone();
+        let text = if text.is_empty() { todo.text.clone() } else { text };
+        let text_changed = text != todo.text;
+        if text_changed || Some(due) != todo.due_date {
+            match set_text_and_due(db, &current, &text, Some(due), user_id).await? {
+                Some(updated) => current = updated,
+                None => return Ok(false),
+            }
+            hooks.emit(db, Event::TodoUpdated { todo: &current, actor_id: user_id, text_changed }).await;
+        }
+        // Blocked todos stay open; the next push puts the event back as it was
+        if done != current.completed && (current.completed || dependencies::count_open_blockers(pool, &current.id).await? == 0) {
+            current = match db.toggle_todo(&current.id, user_id, Some(current.version)).await {
+                Ok(toggled) => toggled,
+                Err(_) => return Ok(current.version != todo.version),
+            };
+            let event = if current.completed {
+                Event::TodoCompleted { todo: &current, actor_id: user_id }
+            } else {
+                Event::TodoReopened { todo: &current, actor_id: user_id }
+            };
+            hooks.emit(db, event).await;
+        }
+
+        // A version no todo has makes the next push overwrite what couldn't be applied
+        let synced_version = if done == current.completed { current.version } else { 0 };
+        sqlx::query("UPDATE google_calendar_events SET etag = ?, synced_version = ? WHERE user_id = ? AND todo_id = ?")
+            .bind(event["etag"].as_str())
+            .bind(synced_version)
+            .bind(user_id)
+            .bind(&current.id)
+            .execute(&mut *db.write().await?)
+            .await?;
+        Ok(current.version != todo.version)
+    }
+
+    /// Creates, updates or deletes the events of todos changed since their
+    /// last sync, and deletes those of todos that are gone. Returns how many
+    /// events it wrote.
+    async fn push(&self, db: &Database, account: &Account, token: &str, tz: Tz) -> Result<u32, SyncError> {
+        let user_id = account.user_id.as_str();
+        let mut pushed = 0;
+
+        let orphans: Vec<String> = sqlx::query("SELECT event_id FROM google_calendar_events WHERE user_id = ? AND todo_id NOT IN (SELECT id FROM todos)")
+            .bind(user_id)
+            .fetch_all(db.get_pool())
+            .await?
+            .iter()
+            .map(|row| row.get("event_id"))
+            .collect();
+        for event_id in orphans {
+            self.delete_event(&account.calendar_id, &event_id, token).await?;
+            sqlx::query("DELETE FROM google_calendar_events WHERE user_id = ? AND event_id = ?")
+                .bind(user_id)
+                .bind(&event_id)
+                .execute(&mut *db.write().await?)
+                .await?;
+            pushed += 1;
+        }
+
+        let rows = sqlx::query(&format!("SELECT {}, e.event_id FROM todos LEFT JOIN (SELECT todo_id, event_id, synced_version FROM google_calendar_events WHERE user_id = ?1) e ON e.todo_id = todos.id WHERE todos.user_id = ?1 AND todos.workspace_id IS NULL AND (todos.due_date IS NOT NULL OR e.event_id IS NOT NULL) AND (e.synced_version IS NULL OR e.synced_version != todos.version)", TODO_COLUMNS))
+            .bind(user_id)
+            .fetch_all(db.get_pool())
+            .await?;
+        for row in &rows {
+            let todo = Todo::from_row(row);
+            let event_id: Option<String> = row.get("event_id");
+
+            let Some(due) = todo.due_date else {
+                if let Some(event_id) = &event_id {
+                    self.delete_event(&account.calendar_id, event_id, token).await?;
+                }
+                unlink(db, user_id, &todo.id).await?;
+                pushed += 1;
+                continue;
+            };
+
+            let body = event_body(&todo, due, tz).to_string();
+            let updated = match &event_id {
+                Some(event_id) => {
+                    let request = self.http.patch(events_url(&account.calendar_id, Some(event_id)));
+                    match send(request.bearer_auth(token).header("Content-Type", "application/json").body(body.clone())).await {
+                        // Deleted in Google since the pull: create it again
+                        Err(SyncError::Status(404 | 410)) => None,
+                        event => Some(event?),
+                    }
+                }
+                None => None,
+            };
+            let event = match updated {
+                Some(event) => event,
+                None => {
+                    let request = self.http.post(events_url(&account.calendar_id, None));
+                    send(request.bearer_auth(token).header("Content-Type", "application/json").body(body)).await?
+                }
+            };
+
+            sqlx::query("INSERT INTO google_calendar_events (user_id, todo_id, event_id, etag, synced_version) VALUES (?, ?, ?, ?, ?) ON CONFLICT (user_id, todo_id) DO UPDATE SET event_id = excluded.event_id, etag = excluded.etag, synced_version = excluded.synced_version")
+                .bind(user_id)
+                .bind(&todo.id)
+                .bind(event["id"].as_str())
+                .bind(event["etag"].as_str())
+                .bind(todo.version)
+                .execute(&mut *db.write().await?)
+                .await?;
+            pushed += 1;
+        }
+        Ok(pushed)
+    }
+
+    /// Deletes an event; one that's already gone counts as deleted.
+    async fn delete_event(&self, calendar_id: &str, event_id: &str, token: &str) -> Result<(), SyncError> {
+        match send(self.http.delete(events_url(calendar_id, Some(event_id))).bearer_auth(token)).await {
+            Ok(_) | Err(SyncError::Status(404 | 410)) => Ok(()),
+            Err(e) => Err(e),
+        }
+    }
+}
+
+/// Sends a request and reads the JSON answer; an empty one reads as `null`.
+async fn send(request: reqwest::RequestBuilder) -> Result<Value, SyncError> {
+    let response = request.send().await.map_err(|e| SyncError::Http(e.without_url().to_string()))?;
+    if !response.status().is_success() {
+        return Err(SyncError::Status(response.status().as_u16()));
+    }
+    let bytes = response.bytes().await.map_err(|e| SyncError::Http(e.without_url().to_string()))?;
+    if bytes.is_empty() {
+        return Ok(Value::Null);
+    }
+    serde_json::from_slice(&bytes).map_err(|_| SyncError::Http("unreadable response from Google".to_string()))
+}
+
+async fn find_link(pool: &SqlitePool, user_id: &str, event_id: &str) -> Result<Option<Link>, sqlx::Error> {
+    let row = sqlx::query("SELECT todo_id, etag, synced_version FROM google_calendar_events WHERE user_id = ? AND event_id = ?")
+        .bind(user_id)
+        .bind(event_id)
+        .fetch_optional(pool)
+        .await?;
+
+    Ok(row.map(|row| Link {
+        todo_id: row.get("todo_id"),
+        etag: row.get("etag"),
+        synced_version: row.get("synced_version"),
+    }))
+}
+
+async fn unlink(db: &Database, user_id: &str, todo_id: &str) -> Result<(), sqlx::Error> {
+    sqlx::query("DELETE FROM google_calendar_events WHERE user_id = ? AND todo_id = ?")
+        .bind(user_id)
+        .bind(todo_id)
+        .execute(&mut *db.write().await?)
+        .await?;
+    Ok(())
+}
+
+/// Sets a todo's text and due date unless it changed since it was loaded.
+async fn set_text_and_due(db: &Database, todo: &Todo, text: &str, due_date: Option<DateTime<Utc>>, actor_id: &str) -> Result<Option<Todo>, sqlx::Error> {
+    let mut conn = db.write().await?;
+    let mut tx = conn.begin().await?;
+    let row = sqlx::query(&format!("UPDATE todos SET text = ?, search_text = ?, due_date = ?, updated_at = ?, version = version + 1 WHERE id = ? AND version = ? RETURNING {}", TODO_COLUMNS))
+        .bind(encryption::encrypt(&todo.id, "text", text))
+        .bind(search::search_text(text))
+        .bind(due_date)
+        .bind(Utc::now())
+        .bind(&todo.id)
+        .bind(todo.version)
+        .fetch_optional(&mut *tx)
+        .await?;
+    let Some(updated) = row.as_ref().map(Todo::from_row) else {
+        return Ok(None);
+    };
+    let text_changed = updated.text != todo.text;
+    outbox::record(&mut tx, &Event::TodoUpdated { todo: &updated, actor_id, text_changed }).await?;
+    tx.commit().await?;
+    db.todo_changed(todo).await;
+    db.events_recorded();
+    Ok(Some(updated))
+}
+
+/// What `GET /integrations/google` shows; `None` if the user never started
+/// connecting.
+pub async fn get_connection(pool: &SqlitePool, user_id: &str) -> Result<Option<Connection>, sqlx::Error> {
+    let row = sqlx::query("SELECT refresh_token IS NOT NULL AS connected, calendar_id, connected_at, last_synced_at, last_error, (SELECT COUNT(*) FROM google_calendar_events e WHERE e.user_id = c.user_id) AS events FROM google_calendars c WHERE user_id = ?")
+        .bind(user_id)
+        .fetch_optional(pool)
+        .await?;
+
+    Ok(row.map(|row| Connection {
+        connected: row.get("connected"),
+        calendar_id: row.get("calendar_id"),
+        connected_at: row.get("connected_at"),
+        last_synced_at: row.get("last_synced_at"),
+        last_error: row.get("last_error"),
+        events: row.get("events"),
+    }))
+}
+
+/// Syncs every connected user's calendar.
+pub struct CalendarSyncJob {
+    pub google: GoogleCalendar,
+    pub db: Arc<Database>,
+    pub hooks: Hooks,
+}
+
+#[async_trait]
+impl Job for CalendarSyncJob {
+    fn name(&self) -> &'static str {
+        "calendar_sync"
+    }
+
+    fn default_schedule(&self) -> Option<&'static str> {
+        Some("*/5 * * * *")
+    }
+
+    async fn run(&self, db: &Database, _trigger: Trigger) -> Result<String, JobError> {
+        let user_ids: Vec<String> = sqlx::query("SELECT user_id FROM google_calendars WHERE refresh_token IS NOT NULL AND calendar_id IS NOT NULL")
+            .fetch_all(db.get_pool())
+            .await?
+            .iter()
+            .map(|row| row.get("user_id"))
+            .collect();
+
+        let (mut report, mut failed) = (SyncReport::default(), 0);
+        for user_id in &user_ids {
+            match self.google.sync_user(&self.db, &self.hooks, user_id).await {
+                Ok(synced) => {
+                    report.pulled += synced.pulled;
+                    report.pushed += synced.pushed;
+                }
+                Err(e) => {
+                    tracing::warn!(user_id, error = %e, "Google Calendar sync failed");
+                    failed += 1;
+                }
+            }
+        }
+        Ok(format!("Synced {} calendars ({} failed): {} todos pulled, {} events pushed", user_ids.len() - failed, failed, report.pulled, report.pushed))
+    }
+}
@@ -0,0 +1,128 @@
+use axum::async_trait;
+use std::sync::Arc;
+
+use crate::simple_db::{Database, Todo};
+
+/// Something that happened in the app that plugins may react to. Hooks that
+/// reach outside the process (webhooks, notifications, Slack) get it from
+/// `outbox::Relay`, which reads it back from the outbox row written in the
+/// change's transaction. The in-process hooks (categories, points,
+/// achievements, occurrences, link previews) are called by the handlers
+/// right after the change is saved.
+#[derive(Debug)]
+pub enum Event<'a> {
+    TodoCreated { todo: &'a Todo, actor_id: &'a str },
+    /// An edit through `PATCH /todos/:id`
+    TodoUpdated { todo: &'a Todo, actor_id: &'a str, text_changed: bool },
+    TodoCompleted { todo: &'a Todo, actor_id: &'a str },
+    TodoReopened { todo: &'a Todo, actor_id: &'a str },
+    /// `todo.assignee_id` is the new assignee, `None` when unassigned
+    TodoAssigned { todo: &'a Todo, actor_id: &'a str },
+    UserRegistered { user_id: &'a str, username: &'a str },
+}
+
+impl Event<'_> {
+    pub fn kind(&self) -> &'static str {
+        match self {
+            Event::TodoCreated { .. } => "todo_created",
+            Event::TodoUpdated { .. } => "todo_updated",
+            Event::TodoCompleted { .. } => "todo_completed",
+            Event::TodoReopened { .. } => "todo_reopened",
+            Event::TodoAssigned { .. } => "todo_assigned",
+            Event::UserRegistered { .. } => "user_registered",
+        }
+    }
+}
+
+#[derive(Debug)]
+pub struct HookError(String);
+
+impl std::fmt::Display for HookError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.write_str(&self.0)
+    }
+}
+
+impl std::error::Error for HookError {}
+
+impl From<sqlx::Error> for HookError {
+    fn from(e: sqlx::Error) -> Self {
+        HookError(e.to_string())
+    }
+}
+
+/// An in-process plugin. Hooks see every event and ignore the ones they
+/// don't care about.
+#[async_trait]
+pub trait Hook: Send + Sync {
+    /// For logs
+    fn name(&self) -> &'static str;
+
+    async fn handle(&self, db: &Database, event: &Event<'_>) -> Result<(), HookError>;
+
+    /// Like `handle`, for an event handed out by `outbox::Relay`. `event_id`
+    /// is the outbox row's, so it stays the same when the event is handed
+    /// out again.
+    async fn handle_recorded(&self, db: &Database, _event_id: &str, event: &Event<'_>) -> Result<(), HookError> {
+        self.handle(db, event).await
+    }
+}
+
+/// The hooks registered at startup, run in registration order for each
+/// event. The change that raised an event is already saved, so a failing
+/// hook is logged and doesn't fail the request or stop the others.
+#[derive(Clone, Default)]
+pub struct Hooks {
+    hooks: Vec<Arc<dyn Hook>>,
+}
+
+impl Hooks {
+    pub fn register(mut self, hook: impl Hook + 'static) -> Self {
+        self.hooks.push(Arc::new(hook));
+        self
+    }
+
+    pub async fn emit(&self, db: &Database, event: Event<'_>) {
+        for hook in &self.hooks {
+            if let Err(e) = hook.handle(db, &event).await {
+                tracing::warn!(hook = hook.name(), event = event.kind(), error = %e, "Hook failed");
+            }
+        }
+    }
+
+    /// Like `emit`, for an event recorded in the outbox as `event_id`.
+    pub async fn emit_recorded(&self, db: &Database, event_id: &str, event: Event<'_>) {
+        for hook in &self.hooks {
+            if let Err(e) = hook.handle_recorded(db, event_id, &event).await {
+                tracing::warn!(hook = hook.name(), event = event.kind(), event_id, error = %e, "Hook failed");
+            }
+        }
+    }
+}
+
+/// Logs every event at debug level (`RUST_LOG=todo_app::hooks=debug`), to see
+/// what plugins are being handed.
+pub struct LogHook;
+
+#[async_trait]
+impl Hook for LogHook {
+    fn name(&self) -> &'static str {
+        "log"
+    }
+
+    async fn handle(&self, _db: &Database, event: &Event<'_>) -> Result<(), HookError> {
+        match *event {
+            Event::TodoCreated { todo, actor_id }
+            | Event::TodoUpdated { todo, actor_id, .. }
+            | Event::TodoCompleted { todo, actor_id }
+            | Event::TodoReopened { todo, actor_id }
+            | Event::TodoAssigned { todo, actor_id } => {
+                tracing::debug!(event = event.kind(), todo_id = %todo.id, actor_id, "Todo event");
+            }
+            Event::UserRegistered { user_id, username } => {
+                tracing::debug!(event = event.kind(), user_id, username, "User event");
+            }
+        }
+        Ok(())
+    }
+}
@@ -1,51 +1,106 @@
-use rustls::{Certificate, PrivateKey, ServerConfig};
-use rustls_pemfile::{certs, pkcs8_private_keys};
-use std::{fs::File, io::BufReader, sync::Arc};
-use tokio_rustls::TlsAcceptor;
-
-pub fn load_tls_config(cert_path: &str, key_path: &str) -> Result<Arc<ServerConfig>, Box<dyn std::error::Error>> {
-    // Load certificate chain
-    let cert_file = File::open(cert_path)?;
-    let mut cert_reader = BufReader::new(cert_file);
-    let cert_chain = certs(&mut cert_reader)?
-        .into_iter()
-        .map(Certificate)
-        .collect::<Vec<_>>();
-
-    // Load private key
-    let key_file = File::open(key_path)?;
-    let mut key_reader = BufReader::new(key_file);
-    let mut keys = pkcs8_private_keys(&mut key_reader)?;
-    
-    if keys.is_empty() {
-        return Err("No PKCS8 private keys found".into());
-    }
-    
-    let private_key = PrivateKey(keys.remove(0));
+use axum::Router;
+use axum_server::tls_rustls::RustlsConfig;
+use rcgen::{Certificate, CertificateParams, DistinguishedName, DnType, SanType};
+use std::{
+    fs,
+    net::{IpAddr, SocketAddr},
+    path::Path,
+    time::Duration,
+};
+use tokio::signal::unix::{signal, SignalKind};
 
-    // Create TLS config
-    let config = ServerConfig::builder()
-        .with_safe_defaults()
-        .with_no_client_auth()
-        .with_single_cert(cert_chain, private_key)?;
+pub async fn load_tls_config(cert_path: &str, key_path: &str) -> Result<RustlsConfig, Box<dyn std::error::Error>> {
+    Ok(RustlsConfig::from_pem_file(cert_path, key_path).await?)
+}
 
-    Ok(Arc::new(config))
+/// Serves `app` over TLS until the process exits.
+pub async fn serve(addr: SocketAddr, config: RustlsConfig, app: Router) -> std::io::Result<()> {
+    axum_server::bind_rustls(addr, config)
+        .serve(app.into_make_service_with_connect_info::<SocketAddr>())
+        .await
 }
 
-pub fn create_tls_acceptor(config: Arc<ServerConfig>) -> TlsAcceptor {
-    TlsAcceptor::from(config)
+/// Re-reads the certificate and key from disk on SIGHUP and, when `interval` is set,
+/// periodically, so renewed certificates (e.g. from certbot) are picked up without a
+/// restart. A failed reload keeps serving the current certificate.
+pub fn spawn_reload(config: RustlsConfig, cert_path: String, key_path: String, interval: Option<Duration>) {
+    tokio::spawn(async move {
+        let mut hangup = match signal(SignalKind::hangup()) {
+            Ok(hangup) => hangup,
+            Err(e) => {
+                tracing::warn!(error = %e, "Failed to listen for SIGHUP, certificate reload on signal disabled");
+                return;
+            }
+        };
+        let mut timer = interval.map(|every| tokio::time::interval_at(tokio::time::Instant::now() + every, every));
+
+        loop {
+            tokio::select! {
+                _ = hangup.recv() => {}
+                _ = async {
+                    match timer.as_mut() {
+                        Some(timer) => {
+                            timer.tick().await;
+                        }
+                        None => std::future::pending().await,
+                    }
+                } => {}
+            }
+
+            match config.reload_from_pem_file(&cert_path, &key_path).await {
+                Ok(()) => tracing::info!(cert = %cert_path, "Reloaded TLS certificate"),
+                Err(e) => tracing::error!(error = %e, "Failed to reload TLS certificate, keeping the current one"),
+            }
+        }
+    });
 }
 
-pub fn generate_self_signed_cert() -> Result<(), Box<dyn std::error::Error>> {
-    // This is a placeholder for self-signed certificate generation
-    // In production, you should use proper certificates from Let's Encrypt or a CA
-    println!("To use HTTPS, provide certificate files:");
-    println!("  - Certificate: cert.pem");
-    println!("  - Private Key: key.pem");
-    println!("Or set CERT_PATH and KEY_PATH environment variables.");
-    println!();
-    println!("For development, you can generate self-signed certificates with:");
-    println!("  openssl req -x509 -newkey rsa:4096 -keyout key.pem -out cert.pem -days 365 -nodes");
-    
+/// Writes a self-signed certificate and PKCS8 private key covering `subject_alt_names`
+/// (DNS names or IP addresses). Intended for development only.
+pub fn generate_self_signed_cert(cert_path: &str, key_path: &str, subject_alt_names: &[String]) -> Result<(), Box<dyn std::error::Error>> {
+    let mut params = CertificateParams::default();
+    params.distinguished_name = DistinguishedName::new();
+    params.distinguished_name.push(DnType::CommonName, "todo-app self-signed");
+    params.subject_alt_names = subject_alt_names
+        .iter()
+        .map(|name| match name.parse::<IpAddr>() {
+            Ok(ip) => SanType::IpAddress(ip),
+            Err(_) => SanType::DnsName(name.clone()),
+        })
+        .collect();
+
+    let certificate = Certificate::from_params(params)?;
+    fs::write(cert_path, certificate.serialize_pem()?)?;
+    fs::write(key_path, certificate.serialize_private_key_pem())?;
+
     Ok(())
+}
+
+/// Generates a self-signed certificate when neither file exists yet, taking the
+/// SANs from the comma-separated `TLS_SANS` (default `localhost,127.0.0.1`).
+pub fn ensure_dev_certificate(cert_path: &str, key_path: &str) {
+    if Path::new(cert_path).exists() || Path::new(key_path).exists() {
+        return;
+    }
+
+    let sans: Vec<String> = std::env::var("TLS_SANS")
+        .unwrap_or_else(|_| "localhost,127.0.0.1".to_string())
+        .split(',')
+        .map(|san| san.trim().to_string())
+        .filter(|san| !san.is_empty())
+        .collect();
+
+    match generate_self_signed_cert(cert_path, key_path, &sans) {
+        Ok(()) => tracing::info!(cert = %cert_path, sans = %sans.join(", "), "Generated self-signed certificate"),
+        Err(e) => tracing::error!(error = %e, "Failed to generate self-signed certificate"),
+    }
+}
+
+pub fn print_certificate_help() {
+    tracing::info!("To use HTTPS, provide cert.pem and key.pem, or set CERT_PATH and KEY_PATH");
+    tracing::info!(
+        "For development, generate a self-signed pair with: \
+         openssl req -x509 -newkey rsa:4096 -keyout key.pem -out cert.pem -days 365 -nodes"
+    );
+    tracing::info!("Debug builds generate one automatically when both files are missing (SANs from TLS_SANS)");
 }
\ No newline at end of file
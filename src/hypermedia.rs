@@ -0,0 +1,74 @@
+use axum::http::{Method, Uri};
+use serde::{Deserialize, Serialize};
+use std::collections::BTreeMap;
+
+use crate::simple_db::Todo;
+
+// Paths of the todo routes, shared by `build_app` and the links below so a
+// link can't point at a route that was moved or removed
+pub const TODO: &str = "/todos/:id";
+pub const TOGGLE: &str = "/toggle/:id";
+pub const DUPLICATE: &str = "/todos/:id/duplicate";
+pub const BLOCKERS: &str = "/todos/:id/blockers";
+pub const OCCURRENCES: &str = "/todos/:id/occurrences";
+
+/// What a client can do with a todo: (relation, method, route).
+const TODO_LINKS: &[(&str, Method, &str)] = &[
+    ("self", Method::GET, TODO),
+    ("update", Method::PATCH, TODO),
+    ("toggle", Method::POST, TOGGLE),
+    ("duplicate", Method::POST, DUPLICATE),
+    ("blockers", Method::POST, BLOCKERS),
+    ("occurrences", Method::GET, OCCURRENCES),
+];
+
+/// A HAL-style link: `{"href": "/toggle/td_...", "method": "POST"}`.
+#[derive(Clone, Debug, Serialize, Deserialize)]
+pub struct Link {
+    pub href: String,
+    /// Left out for `GET`
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub method: Option<String>,
+}
+
+impl Link {
+    pub fn get(href: String) -> Self {
+        Link { href, method: None }
+    }
+}
+
+pub type Links = BTreeMap<String, Link>;
+
+/// Fills in `_links` on each todo, pointing at its public id.
+pub fn attach<'a>(todos: impl IntoIterator<Item = &'a mut Todo>) {
+    for todo in todos {
+        let links = TODO_LINKS
+            .iter()
+            .map(|(relation, method, route)| {
+                let href = route.replace(":id", &todo.public_id);
+                let method = (*method != Method::GET).then(|| method.to_string());
+                (relation.to_string(), Link { href, method })
+            })
+            .collect();
+        todo.hypermedia = Some(links);
+    }
+}
+
+/// `uri` without its `after` parameter, and with `after=<cursor>` if given:
+/// the links to the first and next pages of a list.
+pub fn page_link(uri: &Uri, after: Option<&str>) -> String {
+    let mut params: Vec<String> = uri
+        .query()
+        .unwrap_or_default()
+        .split('&')
+        .filter(|param| !param.is_empty() && !param.starts_with("after="))
+        .map(String::from)
+        .collect();
+    // Cursors are URL-safe base64, so they need no escaping
+    params.extend(after.map(|after| format!("after={}", after)));
+    if params.is_empty() {
+        uri.path().to_string()
+    } else {
+        format!("{}?{}", uri.path(), params.join("&"))
+    }
+}
@@ -0,0 +1,222 @@
+use axum::http::StatusCode;
+use chrono::{Datelike, NaiveDate};
+use serde::{Deserialize, Serialize};
+
+use crate::simple_db::Priority;
+
+/// Languages with a message catalog.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum Language {
+    #[default]
+    En,
+    Es,
+}
+
+impl Language {
+    pub fn as_str(self) -> &'static str {
+        match self {
+            Language::En => "en",
+            Language::Es => "es",
+        }
+    }
+
+    /// The supported language the client prefers most in an `Accept-Language`
+    /// header such as `es-MX,es;q=0.9,en;q=0.8`, if any.
+    pub fn from_accept_language(header: &str) -> Option<Language> {
+        let mut best: Option<(Language, f32)> = None;
+        for item in header.split(',') {
+            let mut parts = item.split(';');
+            let tag = parts.next().unwrap_or_default().trim();
+            let quality = parts
+                .find_map(|param| param.trim().strip_prefix("q="))
+                .map_or(Some(1.0), |q| q.trim().parse::<f32>().ok())
+                .unwrap_or(0.0);
+            let primary = tag.split('-').next().unwrap_or_default().to_ascii_lowercase();
+            let Ok(language) = primary.parse::<Language>() else {
+                continue;
+            };
+            if quality > 0.0 && best.is_none_or(|(_, best_quality)| quality > best_quality) {
+                best = Some((language, quality));
+            }
+        }
+        best.map(|(language, _)| language)
+    }
+}
+
+impl std::str::FromStr for Language {
+    type Err = ();
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "en" => Ok(Language::En),
+            "es" => Ok(Language::Es),
+            _ => Err(()),
+        }
+    }
+}
+
+/// The error message for `status`. English uses the standard reason phrase.
+pub fn status_message(status: StatusCode, language: Language) -> &'static str {
+    let english = status.canonical_reason().unwrap_or("Error");
+    match language {
+        Language::En => english,
+        Language::Es => match status {
+            StatusCode::BAD_REQUEST => "Solicitud incorrecta",
+            StatusCode::UNAUTHORIZED => "No autorizado",
+            StatusCode::PAYMENT_REQUIRED => "Pago requerido",
+            StatusCode::FORBIDDEN => "Prohibido",
+            StatusCode::NOT_FOUND => "No encontrado",
+            StatusCode::METHOD_NOT_ALLOWED => "Método no permitido",
+            StatusCode::NOT_ACCEPTABLE => "No aceptable",
+            StatusCode::REQUEST_TIMEOUT => "Tiempo de espera agotado",
+            StatusCode::CONFLICT => "Conflicto",
+            StatusCode::GONE => "Ya no está disponible",
+            StatusCode::PRECONDITION_FAILED => "La condición previa falló",
+            StatusCode::PAYLOAD_TOO_LARGE => "Contenido demasiado grande",
+            StatusCode::UNSUPPORTED_MEDIA_TYPE => "Tipo de contenido no admitido",
+            StatusCode::UNPROCESSABLE_ENTITY => "Entidad no procesable",
+            StatusCode::PRECONDITION_REQUIRED => "Se requiere una condición previa",
+            StatusCode::TOO_MANY_REQUESTS => "Demasiadas solicitudes",
+            StatusCode::INTERNAL_SERVER_ERROR => "Error interno del servidor",
+            StatusCode::NOT_IMPLEMENTED => "No implementado",
+            StatusCode::BAD_GATEWAY => "Puerta de enlace incorrecta",
+            StatusCode::SERVICE_UNAVAILABLE => "Servicio no disponible",
+            StatusCode::GATEWAY_TIMEOUT => "Tiempo de espera de la puerta de enlace agotado",
+            _ => english,
+        },
+    }
+}
+
+const MONTHS_ES: [&str; 12] = [
+    "enero", "febrero", "marzo", "abril", "mayo", "junio", "julio", "agosto", "septiembre", "octubre", "noviembre", "diciembre",
+];
+const WEEKDAYS_ES: [&str; 7] = ["lunes", "martes", "miércoles", "jueves", "viernes", "sábado", "domingo"];
+
+/// Abbreviated month name ("May", "may"), for dates in the `text` format.
+pub fn short_month(month: u32, language: Language) -> String {
+    match language {
+        Language::En => NaiveDate::from_ymd_opt(2000, month, 1).map(|date| date.format("%b").to_string()).unwrap_or_default(),
+        Language::Es => MONTHS_ES[month as usize - 1].chars().take(3).collect(),
+    }
+}
+
+/// "May 3" / "3 de mayo"
+fn day_and_month(date: NaiveDate, language: Language) -> String {
+    match language {
+        Language::En => date.format("%B %-d").to_string(),
+        Language::Es => format!("{} de {}", date.day(), MONTHS_ES[date.month0() as usize]),
+    }
+}
+
+/// "Friday, May 3" / "viernes, 3 de mayo"
+fn weekday_and_date(date: NaiveDate, language: Language) -> String {
+    match language {
+        Language::En => date.format("%A, %B %-d").to_string(),
+        Language::Es => format!(
+            "{}, {}",
+            WEEKDAYS_ES[date.weekday().num_days_from_monday() as usize],
+            day_and_month(date, language)
+        ),
+    }
+}
+
+/// Everything sent to users as prose, so each language covers all of it.
+pub enum Message<'a> {
+    Assigned { todo: &'a str },
+    Mentioned { todo: &'a str },
+    /// The escalation job raised a todo's priority
+    Escalated { todo: &'a str, priority: Priority },
+    DigestSubject { date: NaiveDate },
+    DigestHeading { date: NaiveDate },
+    DigestDueToday,
+    DigestOverdue,
+    DigestCompletedYesterday,
+    /// After a todo's text in the digest
+    Due { due: &'a str },
+    DigestFooter,
+    /// A weekly report, for the week from Monday `week_start` to Sunday `week_end`
+    ReportSubject { week_start: NaiveDate },
+    ReportBody { week_start: NaiveDate, week_end: NaiveDate, completed: i64, created: i64, overdue: i64, busiest_category: Option<&'a str> },
+    ReportFooter,
+    InviteSubject { workspace: &'a str },
+    InviteBody { workspace: &'a str, role: &'a str, link: &'a str, expires: &'a str },
+}
+
+impl Message<'_> {
+    pub fn text(&self, language: Language) -> String {
+        use Language::{En, Es};
+
+        match (self, language) {
+            (Message::Assigned { todo }, En) => format!("You were assigned \"{}\"", todo),
+            (Message::Assigned { todo }, Es) => format!("Se te asignó \"{}\"", todo),
+            (Message::Mentioned { todo }, En) => format!("You were mentioned in \"{}\"", todo),
+            (Message::Mentioned { todo }, Es) => format!("Te mencionaron en \"{}\"", todo),
+            (Message::Escalated { todo, priority }, En) => format!("\"{}\" is due soon; its priority was raised to {}", todo, priority.as_str()),
+            (Message::Escalated { todo, priority }, Es) => {
+                let priority = match priority {
+                    Priority::High => "alta",
+                    Priority::Medium => "media",
+                    Priority::Low => "baja",
+                };
+                format!("\"{}\" vence pronto; su prioridad subió a {}", todo, priority)
+            }
+            (Message::DigestSubject { date }, En) => format!("Your todos for {}", date.format("%b %-d")),
+            (Message::DigestSubject { date }, Es) => format!("Tus tareas del {}", day_and_month(*date, Es)),
+            (Message::DigestHeading { date }, En) => format!("Your todos for {}", weekday_and_date(*date, En)),
+            (Message::DigestHeading { date }, Es) => format!("Tus tareas para el {}", weekday_and_date(*date, Es)),
+            (Message::DigestDueToday, En) => "Due today".to_string(),
+            (Message::DigestDueToday, Es) => "Vencen hoy".to_string(),
+            (Message::DigestOverdue, En) => "Overdue".to_string(),
+            (Message::DigestOverdue, Es) => "Vencidas".to_string(),
+            (Message::DigestCompletedYesterday, En) => "Completed yesterday".to_string(),
+            (Message::DigestCompletedYesterday, Es) => "Completadas ayer".to_string(),
+            (Message::Due { due }, En) => format!("due {}", due),
+            (Message::Due { due }, Es) => format!("vence el {}", due),
+            (Message::DigestFooter, En) => "Turn this email off under settings (daily_digest: false).".to_string(),
+            (Message::DigestFooter, Es) => "Puedes desactivar este correo en los ajustes (daily_digest: false).".to_string(),
+            (Message::ReportSubject { week_start }, En) => format!("Your week of {}", day_and_month(*week_start, En)),
+            (Message::ReportSubject { week_start }, Es) => format!("Tu semana del {}", day_and_month(*week_start, Es)),
+            (Message::ReportBody { week_start, week_end, completed, created, overdue, busiest_category }, En) => {
+                let mut body = format!(
+                    "Your week from {} to {}\n\nCompleted: {}\nCreated: {}\nNot done by their due date: {}\n",
+                    weekday_and_date(*week_start, En),
+                    weekday_and_date(*week_end, En),
+                    completed,
+                    created,
+                    overdue
+                );
+                if let Some(category) = busiest_category {
+                    body.push_str(&format!("Busiest category: {}\n", category));
+                }
+                body
+            }
+            (Message::ReportBody { week_start, week_end, completed, created, overdue, busiest_category }, Es) => {
+                let mut body = format!(
+                    "Tu semana del {} al {}\n\nCompletadas: {}\nCreadas: {}\nSin terminar a tiempo: {}\n",
+                    weekday_and_date(*week_start, Es),
+                    weekday_and_date(*week_end, Es),
+                    completed,
+                    created,
+                    overdue
+                );
+                if let Some(category) = busiest_category {
+                    body.push_str(&format!("Categoría más activa: {}\n", category));
+                }
+                body
+            }
+            (Message::ReportFooter, En) => "Turn this email off under settings (weekly_report: false).".to_string(),
+            (Message::ReportFooter, Es) => "Puedes desactivar este correo en los ajustes (weekly_report: false).".to_string(),
+            (Message::InviteSubject { workspace }, En) => format!("Invitation to {}", workspace),
+            (Message::InviteSubject { workspace }, Es) => format!("Invitación a {}", workspace),
+            (Message::InviteBody { workspace, role, link, expires }, En) => format!(
+                "You have been invited to join the \"{}\" workspace as {}.\n\nSign in or register, then open this link to accept:\n{}\n\nThe invitation expires on {}.\n",
+                workspace, role, link, expires
+            ),
+            (Message::InviteBody { workspace, role, link, expires }, Es) => format!(
+                "Te han invitado a unirte al espacio de trabajo \"{}\" como {}.\n\nInicia sesión o regístrate y abre este enlace para aceptar:\n{}\n\nLa invitación vence el {}.\n",
+                workspace, role, link, expires
+            ),
+        }
+    }
+}
@@ -0,0 +1,89 @@
+use axum::http::{HeaderMap, HeaderName, StatusCode};
+use base64::{engine::general_purpose::URL_SAFE_NO_PAD, Engine};
+use chrono::{DateTime, Duration, Utc};
+use ring::digest::{digest, SHA256};
+use serde_json::Value;
+use sqlx::{Row, SqliteConnection, SqlitePool};
+
+pub const IDEMPOTENCY_KEY: HeaderName = HeaderName::from_static("idempotency-key");
+
+/// Set on responses replayed for a key seen before.
+pub const IDEMPOTENT_REPLAYED: HeaderName = HeaderName::from_static("idempotent-replayed");
+
+/// How long a key is remembered. The retention job deletes older keys; until
+/// it runs they are ignored.
+const KEY_TTL: Duration = Duration::hours(24);
+
+const MAX_KEY_LEN: usize = 255;
+
+/// The `Idempotency-Key` header, if given; 400 if it's empty, too long or
+/// not text.
+pub fn key(headers: &HeaderMap) -> Result<Option<String>, StatusCode> {
+    let Some(value) = headers.get(IDEMPOTENCY_KEY) else {
+        return Ok(None);
+    };
+    match value.to_str().map(str::trim) {
+        Ok(key) if !key.is_empty() && key.len() <= MAX_KEY_LEN => Ok(Some(key.to_string())),
+        _ => Err(StatusCode::BAD_REQUEST),
+    }
+}
+
+/// A request's `Idempotency-Key`, bound to the body it came with.
+pub struct Key {
+    pub key: String,
+    /// SHA-256 of the body, so a key reused for something else is caught
+    pub request_hash: String,
+}
+
+impl Key {
+    pub fn new(key: String, body: &Value) -> Self {
+        // Object keys serialize sorted, so the same fields in any order hash alike
+        let request_hash = URL_SAFE_NO_PAD.encode(digest(&SHA256, body.to_string().as_bytes()));
+        Self { key, request_hash }
+    }
+}
+
+/// What an earlier request with the same key did.
+pub enum Earlier {
+    /// It created the todo
+    Created { todo_id: String },
+    /// It came with a different body
+    DifferentRequest,
+}
+
+/// The earlier request `user_id` made with `key` in the last 24 hours, if any.
+pub async fn earlier(pool: &SqlitePool, user_id: &str, key: &Key) -> Result<Option<Earlier>, sqlx::Error> {
+    let row = sqlx::query("SELECT todo_id, request_hash FROM idempotency_keys WHERE user_id = ? AND key = ? AND todo_id IS NOT NULL AND created_at >= ?")
+        .bind(user_id)
+        .bind(&key.key)
+        .bind(Utc::now() - KEY_TTL)
+        .fetch_optional(pool)
+        .await?;
+    Ok(row.map(|row| match row.get::<Option<String>, _>("request_hash") {
+        // Keys stored before bodies were hashed match any body
+        Some(hash) if hash != key.request_hash => Earlier::DifferentRequest,
+        _ => Earlier::Created { todo_id: row.get("todo_id") },
+    }))
+}
+
+/// Records in the transaction creating `todo_id` that `key` created it,
+/// so the two are saved together. False if a request with the key got
+/// there first, in which case the transaction must not be committed.
+pub async fn claim(conn: &mut SqliteConnection, user_id: &str, key: &Key, todo_id: &str, now: DateTime<Utc>) -> Result<bool, sqlx::Error> {
+    // Expired keys may be reused, as may any left without a todo before keys were saved with it
+    sqlx::query("DELETE FROM idempotency_keys WHERE user_id = ? AND key = ? AND (created_at < ? OR todo_id IS NULL)")
+        .bind(user_id)
+        .bind(&key.key)
+        .bind(now - KEY_TTL)
+        .execute(&mut *conn)
+        .await?;
+    let inserted = sqlx::query("INSERT OR IGNORE INTO idempotency_keys (user_id, key, todo_id, request_hash, created_at) VALUES (?, ?, ?, ?, ?)")
+        .bind(user_id)
+        .bind(&key.key)
+        .bind(todo_id)
+        .bind(&key.request_hash)
+        .bind(now)
+        .execute(&mut *conn)
+        .await?;
+    Ok(inserted.rows_affected() > 0)
+}
@@ -0,0 +1,223 @@
+use chrono::Utc;
+use ring::rand::{SecureRandom, SystemRandom};
+use serde::{Deserialize, Serialize};
+use sqlx::{Row, SqlitePool};
+
+use crate::quotas::{QuotaError, Quotas};
+use crate::settings;
+use crate::simple_db::{Database, NewTodo, Scope, Todo};
+
+/// Longer bodies (long threads, signatures, disclaimers) are cut off here.
+const MAX_NOTES_CHARS: usize = 10_000;
+
+/// Email-to-todo settings, read from the environment. Both are needed:
+///
+/// - `INBOUND_EMAIL_DOMAIN`: the domain whose mail the provider forwards to us
+/// - `INBOUND_EMAIL_SECRET`: part of the webhook URL, so only the provider can post
+#[derive(Clone, Debug)]
+pub struct InboxConfig {
+    domain: String,
+    secret: String,
+}
+
+impl InboxConfig {
+    pub fn from_env() -> Option<Self> {
+        let domain = std::env::var("INBOUND_EMAIL_DOMAIN").ok().filter(|domain| !domain.is_empty())?;
+        let secret = std::env::var("INBOUND_EMAIL_SECRET").ok().filter(|secret| !secret.is_empty())?;
+        Some(InboxConfig { domain, secret })
+    }
+
+    pub fn accepts(&self, secret: &str) -> bool {
+        secret == self.secret
+    }
+
+    fn address(&self, token: &str) -> String {
+        format!("{}@{}", token, self.domain)
+    }
+}
+
+#[derive(Debug, Serialize)]
+pub struct Inbox {
+    /// Emails sent here become todos; keep it secret
+    pub address: String,
+}
+
+/// An email as posted by the inbound provider. Field names follow Mailgun's
+/// routes (`recipient`, `subject`, `body-plain`); Postmark's (`To`, `Subject`,
+/// `TextBody`) and the plain `to`/`text` used by others are accepted too.
+#[derive(Debug, Deserialize)]
+pub struct InboundEmail {
+    #[serde(alias = "to", alias = "To")]
+    pub recipient: String,
+    #[serde(default, alias = "Subject")]
+    pub subject: String,
+    #[serde(default, rename = "body-plain", alias = "text", alias = "TextBody")]
+    pub body: String,
+}
+
+#[derive(Debug)]
+pub enum InboxError {
+    /// No user has the address the email was sent to
+    UnknownAddress,
+    /// Neither a subject nor a body to make a todo from
+    Empty,
+    /// The user is at their todo quota
+    OverQuota,
+    DatabaseError,
+}
+
+impl From<InboxError> for axum::http::StatusCode {
+    fn from(error: InboxError) -> Self {
+        match error {
+            InboxError::UnknownAddress => axum::http::StatusCode::NOT_FOUND,
+            InboxError::Empty => axum::http::StatusCode::UNPROCESSABLE_ENTITY,
+            InboxError::OverQuota => axum::http::StatusCode::FORBIDDEN,
+            InboxError::DatabaseError => axum::http::StatusCode::INTERNAL_SERVER_ERROR,
+        }
+    }
+}
+
+impl From<QuotaError> for InboxError {
+    fn from(error: QuotaError) -> Self {
+        match error {
+            QuotaError::Exceeded { .. } => InboxError::OverQuota,
+            QuotaError::Database(_) => InboxError::DatabaseError,
+        }
+    }
+}
+
+impl From<sqlx::Error> for InboxError {
+    fn from(_: sqlx::Error) -> Self {
+        InboxError::DatabaseError
+    }
+}
+
+/// 20 random bytes in lowercase hex; mail servers may change the case of local parts.
+fn new_token() -> Result<String, sqlx::Error> {
+    let mut bytes = [0u8; 20];
+    SystemRandom::new()
+        .fill(&mut bytes)
+        .map_err(|_| sqlx::Error::Protocol("Failed to generate inbox address".to_string()))?;
+    Ok(bytes.iter().map(|b| format!("{:02x}", b)).collect())
+}
+
+/// The user's inbox address, created on first use.
+pub async fn get_inbox(db: &Database, config: &InboxConfig, user_id: &str) -> Result<Inbox, sqlx::Error> {
+    sqlx::query("INSERT OR IGNORE INTO inboxes (user_id, token, created_at) VALUES (?, ?, ?)")
+        .bind(user_id)
+        .bind(new_token()?)
+        .bind(Utc::now())
+        .execute(&mut *db.write().await?)
+        .await?;
+    let token: String = sqlx::query("SELECT token FROM inboxes WHERE user_id = ?")
+        .bind(user_id)
+        .fetch_one(db.get_pool())
+        .await?
+        .get("token");
+
+    Ok(Inbox { address: config.address(&token) })
+}
+
+/// The user's inbox address if they have one, without creating it.
+pub async fn find_inbox(pool: &SqlitePool, config: &InboxConfig, user_id: &str) -> Result<Option<Inbox>, sqlx::Error> {
+    let token: Option<String> = sqlx::query("SELECT token FROM inboxes WHERE user_id = ?")
+        .bind(user_id)
+        .fetch_optional(pool)
+        .await?
+        .map(|row| row.get("token"));
+
+    Ok(token.map(|token| Inbox { address: config.address(&token) }))
+}
+
+/// Replaces the user's inbox address, e.g. after it leaked; mail to the old one is rejected.
+pub async fn rotate_inbox(db: &Database, config: &InboxConfig, user_id: &str) -> Result<Inbox, sqlx::Error> {
+    let token = new_token()?;
+    sqlx::query("INSERT INTO inboxes (user_id, token, created_at) VALUES (?, ?, ?) ON CONFLICT (user_id) DO UPDATE SET token = excluded.token, created_at = excluded.created_at")
+        .bind(user_id)
+        .bind(&token)
+        .bind(Utc::now())
+        .execute(&mut *db.write().await?)
+        .await?;
+
+    Ok(Inbox { address: config.address(&token) })
+}
+
+/// Local parts of the recipients at our domain. `recipient` may be a list
+/// (`"Todo <abc@in.example.com>, other@example.com"`).
+fn tokens(recipient: &str, domain: &str) -> Vec<String> {
+    recipient
+        .split(',')
+        .filter_map(|address| {
+            let address = address.trim();
+            let address = match (address.rfind('<'), address.rfind('>')) {
+                (Some(start), Some(end)) if start < end => &address[start + 1..end],
+                _ => address,
+            };
+            let (local, address_domain) = address.rsplit_once('@')?;
+            address_domain.eq_ignore_ascii_case(domain).then(|| local.to_ascii_lowercase())
+        })
+        .collect()
+}
+
+/// "Re: Fwd: Call the plumber" -> "Call the plumber"
+fn strip_reply_prefixes(subject: &str) -> &str {
+    let mut subject = subject.trim();
+    loop {
+        let lower = subject.to_ascii_lowercase();
+        let Some(prefix) = ["re:", "fwd:", "fw:"].iter().find(|prefix| lower.starts_with(**prefix)) else {
+            return subject;
+        };
+        subject = subject[prefix.len()..].trim_start();
+    }
+}
+
+/// Turns an inbound email into a personal todo of the user it was addressed
+/// to: the subject becomes the text and the body the notes. Without a
+/// subject, the body's first line is used as the text.
+pub async fn receive(db: &Database, config: &InboxConfig, quotas: &Quotas, email: InboundEmail) -> Result<Todo, InboxError> {
+    let mut user_id = None;
+    for token in tokens(&email.recipient, &config.domain) {
+        if let Some(row) = sqlx::query("SELECT user_id FROM inboxes WHERE token = ?")
+            .bind(&token)
+            .fetch_optional(db.get_pool())
+            .await?
+        {
+            user_id = Some(row.get::<String, _>("user_id"));
+            break;
+        }
+    }
+    let user_id = user_id.ok_or(InboxError::UnknownAddress)?;
+    quotas.check_todos(db.get_pool(), &user_id, 1).await?;
+
+    let body = email.body.trim();
+    let mut text = strip_reply_prefixes(&email.subject).to_string();
+    let mut notes = body;
+    if text.is_empty() {
+        let (first_line, rest) = body.split_once('\n').unwrap_or((body, ""));
+        text = first_line.trim().to_string();
+        notes = rest.trim();
+    }
+    if text.is_empty() {
+        return Err(InboxError::Empty);
+    }
+
+    let settings = settings::get_settings(db.get_pool(), &user_id).await?;
+    let new_todo = NewTodo {
+        text,
+        notes: (!notes.is_empty()).then(|| notes.chars().take(MAX_NOTES_CHARS).collect()),
+        category: None,
+        tags: None,
+        color: None,
+        latitude: None,
+        longitude: None,
+        location_name: None,
+        priority: settings.default_priority,
+        due_date: None,
+        due: None,
+        estimate_minutes: None,
+        spent_minutes: None,
+        quick_add: false,
+    };
+    let scope = Scope { user_id, workspace_id: None };
+    Ok(db.create_todo(new_todo, &scope).await?)
+}
@@ -0,0 +1,493 @@
+use axum::async_trait;
+use chrono::{DateTime, Datelike, Duration, TimeZone, Timelike, Utc};
+use serde::Serialize;
+use sqlx::{sqlite::SqliteRow, Row, SqlitePool};
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::Arc;
+use uuid::Uuid;
+
+use crate::pagination::Cursor;
+use crate::simple_db::Database;
+
+/// A five-field cron expression (minute, hour, day of month, month, day of
+/// week) evaluated in UTC. Fields take `*`, numbers, ranges (`1-5`), steps
+/// (`*/15`, `0-30/10`) and lists (`1,15`); days of week run from 0, Sunday,
+/// to 6 (7 is Sunday too). As in cron, when both day fields are restricted a
+/// day matching either one counts. `@hourly`, `@daily`, `@weekly` and
+/// `@monthly` are accepted as shorthands.
+#[derive(Clone, Debug)]
+pub struct Schedule {
+    expression: String,
+    minutes: u64,
+    hours: u64,
+    days: u64,
+    months: u64,
+    weekdays: u64,
+    any_day: bool,
+    any_weekday: bool,
+}
+
+#[derive(Debug)]
+pub struct CronError(String);
+
+impl std::fmt::Display for CronError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.write_str(&self.0)
+    }
+}
+
+impl std::error::Error for CronError {}
+
+impl std::str::FromStr for Schedule {
+    type Err = CronError;
+
+    fn from_str(expression: &str) -> Result<Self, Self::Err> {
+        let expanded = match expression.trim() {
+            "@hourly" => "0 * * * *",
+            "@daily" => "0 0 * * *",
+            "@weekly" => "0 0 * * 0",
+            "@monthly" => "0 0 1 * *",
+            other => other,
+        };
+        let fields: Vec<&str> = expanded.split_whitespace().collect();
+        let [minute, hour, day, month, weekday] = fields[..] else {
+            return Err(CronError(format!("expected 5 fields in {:?}", expression)));
+        };
+
+        let mut weekdays = parse_field(weekday, 0, 7)?;
+        // 7 is another name for Sunday
+        if weekdays & (1 << 7) != 0 {
+            weekdays = (weekdays & !(1 << 7)) | 1;
+        }
+        Ok(Schedule {
+            expression: expression.trim().to_string(),
+            minutes: parse_field(minute, 0, 59)?,
+            hours: parse_field(hour, 0, 23)?,
+            days: parse_field(day, 1, 31)?,
+            months: parse_field(month, 1, 12)?,
+            weekdays,
+            any_day: day == "*",
+            any_weekday: weekday == "*",
+        })
+    }
+}
+
+/// The values a field matches, as bits.
+fn parse_field(field: &str, min: u32, max: u32) -> Result<u64, CronError> {
+    let invalid = || CronError(format!("invalid cron field {:?} (allowed {}-{})", field, min, max));
+    let number = |value: &str| value.parse::<u32>().map_err(|_| invalid());
+
+    let mut bits = 0u64;
+    for part in field.split(',') {
+        let (range, step) = match part.split_once('/') {
+            Some((range, step)) => (range, number(step)?),
+            None => (part, 1),
+        };
+        let (start, end) = match range.split_once('-') {
+            _ if range == "*" => (min, max),
+            Some((start, end)) => (number(start)?, number(end)?),
+            // `5/15` means from 5 to the end in steps of 15
+            None if part.contains('/') => (number(range)?, max),
+            None => (number(range)?, number(range)?),
+        };
+        if step == 0 || start < min || end > max || start > end {
+            return Err(invalid());
+        }
+        for value in (start..=end).step_by(step as usize) {
+            bits |= 1 << value;
+        }
+    }
+    Ok(bits)
+}
+
+fn has(bits: u64, value: u32) -> bool {
+    bits & (1 << value) != 0
+}
+
+impl Schedule {
+    fn day_matches(&self, time: DateTime<Utc>) -> bool {
+        let day = has(self.days, time.day());
+        let weekday = has(self.weekdays, time.weekday().num_days_from_sunday());
+        match (self.any_day, self.any_weekday) {
+            (false, false) => day || weekday,
+            _ => day && weekday,
+        }
+    }
+
+    /// The first matching minute after `after`, or `None` for expressions
+    /// that never match, like `0 0 30 2 *`.
+    pub fn next_after(&self, after: DateTime<Utc>) -> Option<DateTime<Utc>> {
+        let mut time = after.with_second(0)?.with_nanosecond(0)? + Duration::minutes(1);
+        // Leap days come around at least every 8 years
+        let give_up = time + Duration::days(8 * 366);
+
+        while time < give_up {
+            if !has(self.months, time.month()) {
+                let (year, month) = if time.month() == 12 { (time.year() + 1, 1) } else { (time.year(), time.month() + 1) };
+                time = Utc.with_ymd_and_hms(year, month, 1, 0, 0, 0).single()?;
+            } else if !self.day_matches(time) {
+                time = (time.date_naive() + chrono::Days::new(1)).and_hms_opt(0, 0, 0)?.and_utc();
+            } else if !has(self.hours, time.hour()) {
+                time = time.with_minute(0)? + Duration::hours(1);
+            } else if !has(self.minutes, time.minute()) {
+                time += Duration::minutes(1);
+            } else {
+                return Some(time);
+            }
+        }
+        None
+    }
+}
+
+/// What started a job run.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Trigger {
+    Schedule,
+    /// `POST /admin/jobs/:name/run`
+    Manual,
+}
+
+impl Trigger {
+    fn as_str(self) -> &'static str {
+        match self {
+            Trigger::Schedule => "schedule",
+            Trigger::Manual => "manual",
+        }
+    }
+}
+
+#[derive(Debug)]
+pub struct JobError(String);
+
+impl std::fmt::Display for JobError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.write_str(&self.0)
+    }
+}
+
+impl std::error::Error for JobError {}
+
+impl From<sqlx::Error> for JobError {
+    fn from(e: sqlx::Error) -> Self {
+        JobError(e.to_string())
+    }
+}
+
+impl From<String> for JobError {
+    fn from(message: String) -> Self {
+        JobError(message)
+    }
+}
+
+/// Recurring background work, run by the `Scheduler`.
+#[async_trait]
+pub trait Job: Send + Sync {
+    /// Stable name, used in `JOB_<NAME>_SCHEDULE`, the run history and the admin endpoints
+    fn name(&self) -> &'static str;
+
+    /// Cron expression used unless `JOB_<NAME>_SCHEDULE` overrides it;
+    /// `None` runs the job only when triggered by hand.
+    fn default_schedule(&self) -> Option<&'static str>;
+
+    /// Does the work and describes what was done, for the run history.
+    async fn run(&self, db: &Database, trigger: Trigger) -> Result<String, JobError>;
+}
+
+struct Registered {
+    job: Arc<dyn Job>,
+    schedule: Option<Schedule>,
+    /// Set while a run is in progress, so runs of one job never overlap
+    running: AtomicBool,
+}
+
+/// The registered jobs and when they run. Each job runs on its own
+/// schedule; a run that comes due while the previous one is still going is
+/// skipped. Runs are recorded in `job_runs`.
+#[derive(Clone, Default)]
+pub struct Scheduler {
+    jobs: Vec<Arc<Registered>>,
+}
+
+impl Scheduler {
+    /// Adds `job` with its default schedule, or the one in `JOB_<NAME>_SCHEDULE`
+    /// (`off` for manual runs only). Panics on an invalid expression, so a
+    /// typo is caught at startup rather than silently never running.
+    pub fn register(mut self, job: impl Job + 'static) -> Self {
+        let var = format!("JOB_{}_SCHEDULE", job.name().to_uppercase());
+        let expression = match std::env::var(&var) {
+            Ok(value) if value == "off" => None,
+            Ok(value) => Some(value),
+            Err(_) => job.default_schedule().map(String::from),
+        };
+        let schedule = expression.map(|expression| {
+            expression
+                .parse::<Schedule>()
+                .unwrap_or_else(|e| panic!("Invalid {}: {}", var, e))
+        });
+
+        self.jobs.push(Arc::new(Registered { job: Arc::new(job), schedule, running: AtomicBool::new(false) }));
+        self
+    }
+
+    fn find(&self, name: &str) -> Option<&Arc<Registered>> {
+        self.jobs.iter().find(|registered| registered.job.name() == name)
+    }
+
+    /// Starts every scheduled job in the background. Runs left `running` by
+    /// a previous process are marked failed first.
+    pub async fn start(&self, db: Arc<Database>) -> Result<(), sqlx::Error> {
+        sqlx::query("UPDATE job_runs SET status = 'failed', output = 'Interrupted by a restart', finished_at = ? WHERE status = 'running'")
+            .bind(Utc::now())
+            .execute(&mut *db.write().await?)
+            .await?;
+
+        for registered in &self.jobs {
+            let Some(schedule) = registered.schedule.clone() else {
+                continue;
+            };
+            let (registered, db) = (registered.clone(), db.clone());
+            tokio::spawn(async move {
+                while let Some(next) = schedule.next_after(Utc::now()) {
+                    let wait = (next - Utc::now()).to_std().unwrap_or_default();
+                    tokio::time::sleep(wait).await;
+                    if let Some(run) = claim(&db, &registered, Trigger::Schedule).await {
+                        execute(&db, &registered, run.id, Trigger::Schedule).await;
+                    } else {
+                        tracing::warn!(job = registered.job.name(), "Skipping job run; the previous one is still going");
+                    }
+                }
+                tracing::warn!(job = registered.job.name(), schedule = %schedule.expression, "Job schedule never matches; not scheduling it");
+            });
+        }
+        Ok(())
+    }
+
+    /// Starts a run of job `name` in the background and returns it.
+    pub async fn run_now(&self, db: &Arc<Database>, name: &str) -> Result<JobRun, RunError> {
+        let registered = self.find(name).ok_or(RunError::UnknownJob)?;
+        let run = claim(db, registered, Trigger::Manual).await.ok_or(RunError::AlreadyRunning)?;
+
+        let (registered, db, run_id) = (registered.clone(), db.clone(), run.id.clone());
+        tokio::spawn(async move { execute(&db, &registered, run_id, Trigger::Manual).await });
+        Ok(run)
+    }
+
+    /// Every job with its schedule and latest run.
+    pub async fn list(&self, pool: &SqlitePool) -> Result<Vec<JobInfo>, sqlx::Error> {
+        let now = Utc::now();
+        let mut jobs = Vec::with_capacity(self.jobs.len());
+        for registered in &self.jobs {
+            let name = registered.job.name();
+            jobs.push(JobInfo {
+                name,
+                schedule: registered.schedule.as_ref().map(|schedule| schedule.expression.clone()),
+                next_run_at: registered.schedule.as_ref().and_then(|schedule| schedule.next_after(now)),
+                running: registered.running.load(Ordering::SeqCst),
+                last_run: list_runs(pool, name, None, 1).await?.pop(),
+            });
+        }
+        Ok(jobs)
+    }
+
+    pub fn has_job(&self, name: &str) -> bool {
+        self.find(name).is_some()
+    }
+}
+
+/// Marks the job running and records the run; `None` if it already was.
+async fn claim(db: &Database, registered: &Registered, trigger: Trigger) -> Option<JobRun> {
+    if registered.running.swap(true, Ordering::SeqCst) {
+        return None;
+    }
+    let run = JobRun {
+        id: Uuid::new_v4().to_string(),
+        job: registered.job.name().to_string(),
+        trigger: trigger.as_str().to_string(),
+        status: "running".to_string(),
+        output: None,
+        started_at: Utc::now(),
+        finished_at: None,
+    };
+    let inserted = async {
+        sqlx::query("INSERT INTO job_runs (id, job, trigger, status, started_at) VALUES (?, ?, ?, ?, ?)")
+            .bind(&run.id)
+            .bind(&run.job)
+            .bind(&run.trigger)
+            .bind(&run.status)
+            .bind(run.started_at)
+            .execute(&mut *db.write().await?)
+            .await
+    }
+    .await;
+    if let Err(e) = inserted {
+        // Run anyway: losing the history entry is better than skipping the work
+        tracing::warn!(job = %run.job, error = %e, "Failed to record job run");
+    }
+    Some(run)
+}
+
+async fn execute(db: &Database, registered: &Registered, run_id: String, trigger: Trigger) {
+    let name = registered.job.name();
+    let (status, output) = match registered.job.run(db, trigger).await {
+        Ok(output) => {
+            tracing::info!(job = name, trigger = trigger.as_str(), %output, "Job finished");
+            ("succeeded", output)
+        }
+        Err(e) => {
+            tracing::error!(job = name, trigger = trigger.as_str(), error = %e, "Job failed");
+            ("failed", e.to_string())
+        }
+    };
+    registered.running.store(false, Ordering::SeqCst);
+
+    let result = async {
+        sqlx::query("UPDATE job_runs SET status = ?, output = ?, finished_at = ? WHERE id = ?")
+            .bind(status)
+            .bind(&output)
+            .bind(Utc::now())
+            .bind(&run_id)
+            .execute(&mut *db.write().await?)
+            .await
+    }
+    .await;
+    if let Err(e) = result {
+        tracing::warn!(job = name, error = %e, "Failed to record job result");
+    }
+}
+
+#[derive(Debug)]
+pub enum RunError {
+    UnknownJob,
+    AlreadyRunning,
+}
+
+impl From<RunError> for axum::http::StatusCode {
+    fn from(error: RunError) -> Self {
+        match error {
+            RunError::UnknownJob => axum::http::StatusCode::NOT_FOUND,
+            RunError::AlreadyRunning => axum::http::StatusCode::CONFLICT,
+        }
+    }
+}
+
+/// A job as listed by `GET /admin/jobs`.
+#[derive(Debug, Serialize)]
+pub struct JobInfo {
+    pub name: &'static str,
+    /// `null` for jobs that only run when triggered
+    pub schedule: Option<String>,
+    pub next_run_at: Option<DateTime<Utc>>,
+    pub running: bool,
+    pub last_run: Option<JobRun>,
+}
+
+/// An entry in the `job_runs` history.
+#[derive(Debug, Serialize)]
+pub struct JobRun {
+    pub id: String,
+    pub job: String,
+    /// `schedule` or `manual`
+    pub trigger: String,
+    /// `running`, `succeeded` or `failed`
+    pub status: String,
+    /// What the job did, or why it failed
+    pub output: Option<String>,
+    pub started_at: DateTime<Utc>,
+    pub finished_at: Option<DateTime<Utc>>,
+}
+
+impl JobRun {
+    fn from_row(row: &SqliteRow) -> Self {
+        JobRun {
+            id: row.get("id"),
+            job: row.get("job"),
+            trigger: row.get("trigger"),
+            status: row.get("status"),
+            output: row.get("output"),
+            started_at: row.get("started_at"),
+            finished_at: row.get("finished_at"),
+        }
+    }
+}
+
+/// Runs of `job` after `after`, newest first; at most `limit`.
+pub async fn list_runs(pool: &SqlitePool, job: &str, after: Option<&Cursor>, limit: u32) -> Result<Vec<JobRun>, sqlx::Error> {
+    let rows = sqlx::query("SELECT id, job, trigger, status, output, started_at, finished_at FROM job_runs WHERE job = ?1 AND (?2 IS NULL OR started_at < ?2 OR (started_at = ?2 AND id < ?3)) ORDER BY started_at DESC, id DESC LIMIT ?4")
+        .bind(job)
+        .bind(after.map(|after| after.created_at))
+        .bind(after.map(|after| &after.id))
+        .bind(limit)
+        .fetch_all(pool)
+        .await?;
+
+    Ok(rows.iter().map(JobRun::from_row).collect())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn at(time: &str) -> DateTime<Utc> {
+        time.parse().unwrap()
+    }
+
+    fn next(expression: &str, after: &str) -> Option<DateTime<Utc>> {
+        expression.parse::<Schedule>().unwrap().next_after(at(after))
+    }
+
+    #[test]
+    fn parses_fields() {
+        assert_eq!(parse_field("*", 0, 59).unwrap(), (1 << 60) - 1);
+        assert_eq!(parse_field("*/15", 0, 59).unwrap(), 1 | 1 << 15 | 1 << 30 | 1 << 45);
+        // A start with a step runs to the end of the field
+        assert_eq!(parse_field("5/15", 0, 59).unwrap(), 1 << 5 | 1 << 20 | 1 << 35 | 1 << 50);
+        assert_eq!(parse_field("1-5", 0, 7).unwrap(), 0b111110);
+        assert_eq!(parse_field("0-30/10", 0, 59).unwrap(), 1 | 1 << 10 | 1 << 20 | 1 << 30);
+        assert_eq!(parse_field("1,15", 1, 31).unwrap(), 1 << 1 | 1 << 15);
+    }
+
+    #[test]
+    fn rejects_invalid_fields() {
+        for (field, min, max) in [("*/0", 0, 59), ("5-1", 0, 59), ("60", 0, 59), ("0", 1, 31), ("1-13", 1, 12), ("", 0, 59), ("a", 0, 59), ("1,", 0, 59), ("-1", 0, 59)] {
+            assert!(parse_field(field, min, max).is_err(), "{:?}", field);
+        }
+        for expression in ["", "* * * *", "* * * * * *", "@yearly", "* * * * 8"] {
+            assert!(expression.parse::<Schedule>().is_err(), "{:?}", expression);
+        }
+    }
+
+    #[test]
+    fn finds_the_next_matching_minute() {
+        // 2025-03-05 is a Wednesday
+        assert_eq!(next("*/15 * * * *", "2025-03-05T10:07:30Z"), Some(at("2025-03-05T10:15:00Z")));
+        assert_eq!(next("*/15 * * * *", "2025-03-05T10:45:00Z"), Some(at("2025-03-05T11:00:00Z")));
+        assert_eq!(next("0 9 * * 1-5", "2025-03-07T09:00:00Z"), Some(at("2025-03-10T09:00:00Z")));
+        assert_eq!(next("@daily", "2025-12-31T23:59:00Z"), Some(at("2026-01-01T00:00:00Z")));
+        assert_eq!(next("@monthly", "2025-03-05T10:00:00Z"), Some(at("2025-04-01T00:00:00Z")));
+        // Only leap years have a 29 February
+        assert_eq!(next("0 0 29 2 *", "2025-03-05T10:00:00Z"), Some(at("2028-02-29T00:00:00Z")));
+    }
+
+    #[test]
+    fn never_matching_expressions_have_no_next_time() {
+        assert_eq!(next("0 0 30 2 *", "2025-03-05T10:00:00Z"), None);
+        assert_eq!(next("0 0 31 4,6,9,11 *", "2025-03-05T10:00:00Z"), None);
+    }
+
+    #[test]
+    fn restricted_day_fields_match_either_day() {
+        // The 13th, or any Friday
+        assert_eq!(next("0 0 13 * 5", "2025-03-05T10:00:00Z"), Some(at("2025-03-07T00:00:00Z")));
+        assert_eq!(next("0 0 13 * 5", "2025-03-08T10:00:00Z"), Some(at("2025-03-13T00:00:00Z")));
+        // With either field unrestricted, the other one decides
+        assert_eq!(next("0 0 * * 5", "2025-03-05T10:00:00Z"), Some(at("2025-03-07T00:00:00Z")));
+        assert_eq!(next("0 0 13 * *", "2025-03-05T10:00:00Z"), Some(at("2025-03-13T00:00:00Z")));
+    }
+
+    #[test]
+    fn seven_is_sunday() {
+        let sunday = "0 0 * * 7".parse::<Schedule>().unwrap();
+        assert_eq!(sunday.weekdays, "0 0 * * 0".parse::<Schedule>().unwrap().weekdays);
+        assert_eq!(sunday.next_after(at("2025-03-05T10:00:00Z")), Some(at("2025-03-09T00:00:00Z")));
+    }
+}
@@ -0,0 +1,89 @@
+use axum::{
+    http::{header, HeaderValue, Uri},
+    response::{IntoResponse, Response},
+    Json,
+};
+use serde_json::{json, Map, Value};
+
+use crate::dependencies::TodoDetail;
+use crate::fields::Fields;
+use crate::hypermedia;
+use crate::pagination::{Listing, Page};
+use crate::simple_db::Todo;
+
+/// The media type clients ask for with `Accept`, and responses are sent as.
+pub const MEDIA_TYPE: &str = "application/vnd.api+json";
+
+/// `{"type": ..., "id": ...}`, or `null` for an unset to-one relationship.
+fn identifier(kind: &str, id: Option<&str>) -> Value {
+    match id {
+        Some(id) => json!({ "type": kind, "id": id }),
+        None => Value::Null,
+    }
+}
+
+/// `todo` as a resource object. The attributes are trimmed to `fields` if
+/// given; the relationships are always there.
+fn resource(todo: &Todo, fields: Option<&Fields>) -> Value {
+    let mut attributes = match fields.map(|fields| fields.select(todo)).unwrap_or_else(|| json!(todo)) {
+        Value::Object(attributes) => attributes,
+        _ => Map::new(),
+    };
+    // The id is the resource's own, the others become relationships, and
+    // the resource has links of its own
+    for key in ["id", "user_id", "workspace_id", "assignee_id", "_links"] {
+        attributes.remove(key);
+    }
+    let relationships = json!({
+        "owner": { "data": identifier("users", todo.user_id.as_deref()) },
+        "workspace": { "data": identifier("workspaces", todo.workspace_id.as_deref()) },
+        "assignee": { "data": identifier("users", todo.assignee_id.as_deref()) },
+    });
+    json!({
+        "type": "todos",
+        "id": todo.id,
+        "attributes": attributes,
+        "relationships": relationships,
+        "links": { "self": format!("/todos/{}", todo.public_id) },
+    })
+}
+
+fn respond(document: Value) -> Response {
+    let mut response = Json(document).into_response();
+    response.headers_mut().insert(header::CONTENT_TYPE, HeaderValue::from_static(MEDIA_TYPE));
+    response
+}
+
+/// A todo list as a document whose `data` is an array of resources. Pages
+/// get `first` and `next` links; `next` is `null` on the last page.
+pub fn listing(listing: Listing<Todo>, fields: Option<&Fields>, uri: &Uri) -> Response {
+    let mut links = json!({ "self": uri.to_string() });
+    let todos = match listing {
+        Listing::All(todos) => todos,
+        Listing::Page(Page { items, next_cursor, .. }) => {
+            links["first"] = json!(hypermedia::page_link(uri, None));
+            links["next"] = json!(next_cursor.map(|cursor| hypermedia::page_link(uri, Some(&cursor))));
+            items
+        }
+    };
+    let data: Vec<Value> = todos.iter().map(|todo| resource(todo, fields)).collect();
+    respond(json!({ "data": data, "links": links, "jsonapi": { "version": "1.1" } }))
+}
+
+/// A single todo, with its blockers and dependents as to-many relationships
+/// and their resources under `included`.
+pub fn detail(detail: TodoDetail) -> Response {
+    let mut data = resource(&detail.todo, None);
+    let ids = |todos: &[Todo]| todos.iter().map(|todo| identifier("todos", Some(&todo.id))).collect::<Vec<_>>();
+    data["relationships"]["blockers"] = json!({ "data": ids(&detail.blockers) });
+    data["relationships"]["dependents"] = json!({ "data": ids(&detail.dependents) });
+
+    let mut included: Vec<Value> = Vec::new();
+    for todo in detail.blockers.iter().chain(&detail.dependents) {
+        // Cycles are refused, so no todo should be both; included once regardless
+        if !included.iter().any(|resource| resource["id"] == json!(todo.id)) {
+            included.push(resource(todo, None));
+        }
+    }
+    respond(json!({ "data": data, "included": included, "jsonapi": { "version": "1.1" } }))
+}
@@ -0,0 +1,70 @@
+use base64::{engine::general_purpose::STANDARD, Engine};
+use ring::rand::{SecureRandom, SystemRandom};
+use std::io;
+use std::path::{Path, PathBuf};
+
+/// The fallback secret of earlier versions. Anyone can forge tokens signed with it.
+const INSECURE_DEFAULT: &str = "your-secret-key";
+
+/// The key that signs session tokens, from the first of:
+///
+/// 1. `JWT_SECRET`
+/// 2. `JWT_SECRET_FILE`, e.g. a mounted Docker or Kubernetes secret
+/// 3. in debug builds only, `$DATA_DIR/jwt_secret` (default `data/jwt_secret`),
+///    generated on first run so sessions survive restarts
+///
+/// Release builds refuse to start without one of the first two.
+pub fn load() -> io::Result<String> {
+    let secret = if let Ok(secret) = std::env::var("JWT_SECRET") {
+        secret
+    } else if let Ok(path) = std::env::var("JWT_SECRET_FILE") {
+        std::fs::read_to_string(&path)
+            .map_err(|e| io::Error::new(e.kind(), format!("Failed to read JWT_SECRET_FILE {}: {}", path, e)))?
+            .trim_end_matches(['\r', '\n'])
+            .to_string()
+    } else if cfg!(debug_assertions) {
+        let dir = PathBuf::from(std::env::var("DATA_DIR").unwrap_or_else(|_| "data".to_string()));
+        load_or_generate(&dir.join("jwt_secret"))?
+    } else {
+        return Err(io::Error::new(
+            io::ErrorKind::NotFound,
+            "JWT_SECRET or JWT_SECRET_FILE must be set (e.g. JWT_SECRET=$(openssl rand -base64 32))",
+        ));
+    };
+
+    if secret.is_empty() || secret == INSECURE_DEFAULT {
+        return Err(io::Error::new(io::ErrorKind::InvalidInput, "Refusing to sign tokens with an empty or default JWT secret"));
+    }
+    if secret.len() < 32 {
+        tracing::warn!("JWT secret is shorter than 32 bytes; use a longer random value");
+    }
+    Ok(secret)
+}
+
+/// Reads the development secret at `path`, creating it (readable by the
+/// owner only) with 32 random bytes if it doesn't exist.
+fn load_or_generate(path: &Path) -> io::Result<String> {
+    match std::fs::read_to_string(path) {
+        Ok(secret) => return Ok(secret.trim_end().to_string()),
+        Err(e) if e.kind() != io::ErrorKind::NotFound => return Err(e),
+        Err(_) => {}
+    }
+
+    let mut bytes = [0u8; 32];
+    SystemRandom::new()
+        .fill(&mut bytes)
+        .map_err(|_| io::Error::other("Failed to generate JWT secret"))?;
+    let secret = STANDARD.encode(bytes);
+
+    if let Some(dir) = path.parent() {
+        std::fs::create_dir_all(dir)?;
+    }
+    let mut options = std::fs::OpenOptions::new();
+    options.write(true).create_new(true);
+    #[cfg(unix)]
+    std::os::unix::fs::OpenOptionsExt::mode(&mut options, 0o600);
+    io::Write::write_all(&mut options.open(path)?, secret.as_bytes())?;
+
+    tracing::info!(path = %path.display(), "Generated development JWT secret");
+    Ok(secret)
+}
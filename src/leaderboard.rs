@@ -0,0 +1,113 @@
+use chrono::{DateTime, Duration, Utc};
+use serde::{Deserialize, Serialize};
+use sqlx::{Row, SqlitePool};
+
+use crate::workspaces::{self, WorkspaceError};
+
+/// How far back the leaderboard looks, counted back from now.
+#[derive(Clone, Copy, Debug, Default, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum Period {
+    Day,
+    #[default]
+    Week,
+    Month,
+    Year,
+    All,
+}
+
+impl Period {
+    fn since(self, now: DateTime<Utc>) -> Option<DateTime<Utc>> {
+        let days = match self {
+            Period::Day => 1,
+            Period::Week => 7,
+            Period::Month => 30,
+            Period::Year => 365,
+            Period::All => return None,
+        };
+        Some(now - Duration::days(days))
+    }
+}
+
+#[derive(Clone, Copy, Debug, Default, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum Metric {
+    #[default]
+    Points,
+    Completions,
+}
+
+impl Metric {
+    fn score(self, entry: &Entry) -> i64 {
+        match self {
+            Metric::Points => entry.points,
+            Metric::Completions => entry.completions,
+        }
+    }
+}
+
+/// `?period=month&by=completions`.
+#[derive(Debug, Deserialize)]
+pub struct LeaderboardQuery {
+    #[serde(default)]
+    pub period: Period,
+    #[serde(default)]
+    pub by: Metric,
+}
+
+#[derive(Debug, Serialize)]
+pub struct Entry {
+    /// Members with the same score share a rank
+    pub rank: u32,
+    pub user_id: String,
+    pub username: String,
+    pub points: i64,
+    pub completions: i64,
+}
+
+#[derive(Debug, Serialize)]
+pub struct Leaderboard {
+    pub period: Period,
+    pub by: Metric,
+    /// `null` for `all`
+    pub since: Option<DateTime<Utc>>,
+    pub entries: Vec<Entry>,
+}
+
+/// Ranks the members of a workspace `user_id` belongs to by what they
+/// completed in it, counted like gamification points: once per todo, to
+/// whoever completed it. Members who opted out with the `leaderboard`
+/// setting aren't listed.
+pub async fn get_leaderboard(pool: &SqlitePool, user_id: &str, workspace_id: &str, query: LeaderboardQuery) -> Result<Leaderboard, WorkspaceError> {
+    if workspaces::role(pool, workspace_id, user_id).await?.is_none() {
+        return Err(WorkspaceError::NotFound);
+    }
+
+    let since = query.period.since(Utc::now());
+    let order = match query.by {
+        Metric::Points => "points DESC, completions DESC",
+        Metric::Completions => "completions DESC, points DESC",
+    };
+    let rows = sqlx::query(&format!("SELECT m.user_id, u.username, COALESCE(SUM(a.points), 0) AS points, COUNT(a.todo_id) AS completions FROM workspace_members m JOIN users u ON u.id = m.user_id LEFT JOIN user_settings s ON s.user_id = m.user_id LEFT JOIN (SELECT a.user_id, a.todo_id, a.points FROM points_awards a JOIN todos t ON t.id = a.todo_id WHERE t.workspace_id = ?1 AND (?2 IS NULL OR a.awarded_at >= ?2)) a ON a.user_id = m.user_id WHERE m.workspace_id = ?1 AND u.disabled_at IS NULL AND COALESCE(s.leaderboard, TRUE) GROUP BY m.user_id ORDER BY {}, u.username", order))
+        .bind(workspace_id)
+        .bind(since)
+        .fetch_all(pool)
+        .await?;
+
+    let mut entries: Vec<Entry> = Vec::with_capacity(rows.len());
+    for (position, row) in rows.iter().enumerate() {
+        let mut entry = Entry {
+            rank: position as u32 + 1,
+            user_id: row.get("user_id"),
+            username: row.get("username"),
+            points: row.get("points"),
+            completions: row.get("completions"),
+        };
+        if let Some(previous) = entries.last().filter(|previous| query.by.score(previous) == query.by.score(&entry)) {
+            entry.rank = previous.rank;
+        }
+        entries.push(entry);
+    }
+
+    Ok(Leaderboard { period: query.period, by: query.by, since, entries })
+}
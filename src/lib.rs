@@ -0,0 +1,2455 @@
+//! The todo app's server: configuration, state and the HTTP router. The
+//! `todo-app` binary adds the command line and the HTTP/HTTPS listeners.
+
+use axum::{
+    body::Body,
+    http::{header, HeaderMap, StatusCode},
+    middleware,
+    response::{Html, IntoResponse, Redirect, Response},
+    routing::{delete, get, patch, post, put},
+    Json, Router,
+};
+use serde::{Deserialize, Serialize};
+use std::sync::Arc;
+use tokio_stream::StreamExt;
+use tower::ServiceBuilder;
+use tower_http::request_id::{MakeRequestUuid, PropagateRequestIdLayer, SetRequestIdLayer};
+
+pub mod simple_auth;
+pub mod simple_db;
+mod writer;
+#[cfg(feature = "tls")]
+pub mod https;
+#[cfg(feature = "tls")]
+pub mod acme;
+mod reports;
+mod dates;
+mod quick_add;
+mod filters;
+mod dependencies;
+pub mod cli;
+pub mod logging;
+mod request_id;
+mod monitoring;
+pub mod shared_state;
+mod rate_limit;
+mod backups;
+mod workspaces;
+mod mailer;
+mod notifications;
+mod notify;
+mod mentions;
+mod slack;
+mod settings;
+mod digest;
+mod inbox;
+mod markdown;
+mod print;
+mod web;
+mod i18n;
+mod pagination;
+mod auth_events;
+mod login_guard;
+mod password_strength;
+pub mod jwt_secret;
+mod session;
+mod device_auth;
+mod data_export;
+mod hooks;
+mod jobs;
+mod escalation;
+mod retention;
+mod seed;
+mod idempotency;
+mod formats;
+mod fields;
+mod jsonapi;
+mod hypermedia;
+mod duplicates;
+mod colors;
+mod nearby;
+mod rules;
+mod categories;
+mod autocomplete;
+mod admin_stats;
+mod gamification;
+mod achievements;
+mod leaderboard;
+mod myday;
+mod google_calendar;
+mod sync;
+mod tombstones;
+mod occurrences;
+mod load_shed;
+mod encryption;
+mod search;
+mod link_previews;
+mod outbox;
+mod events;
+mod ssrf;
+mod webhooks;
+mod quotas;
+mod plans;
+mod flags;
+#[cfg(feature = "ai")]
+mod ai;
+use simple_auth::{AuthService, LoginRequest, RegisterRequest, TokenConfig};
+use shared_state::SharedState;
+use simple_db::{Database, DatabaseConfig, NewTodo, Scope, Todo, TodoStats, UpdateTodo};
+use pagination::{Cursor, Listing, Page, PageQuery};
+
+/// What `build_app` needs up front. Everything else (mail, rate limits,
+/// Slack, job schedules and so on) is read from its own environment variables.
+pub struct Config {
+    pub database: DatabaseConfig,
+    pub jwt_secret: String,
+    pub tokens: TokenConfig,
+    /// Run background jobs on their schedules; they can still be started
+    /// through the admin endpoints when this is off
+    pub background_jobs: bool,
+}
+
+impl Config {
+    /// `DATABASE_URL` and friends, the JWT secret (see `jwt_secret::load`) and
+    /// the token settings, with background jobs on.
+    pub fn from_env() -> std::io::Result<Self> {
+        Ok(Config {
+            database: DatabaseConfig::from_env(),
+            jwt_secret: jwt_secret::load()?,
+            tokens: TokenConfig::from_env(),
+            background_jobs: true,
+        })
+    }
+}
+
+/// The shared state (in Redis when `REDIS_URL` is set), the database with its
+/// schema brought up to date, and the auth service on top of them.
+pub struct Services {
+    pub db: Arc<Database>,
+    pub auth_service: Arc<AuthService>,
+    pub shared: SharedState,
+}
+
+pub async fn connect(config: &Config) -> Result<Services, Box<dyn std::error::Error>> {
+    let shared = SharedState::from_env().await.map_err(|e| format!("Failed to connect to Redis: {}", e))?;
+    let db = Database::new(&config.database, shared.clone())
+        .await
+        .map_err(|e| format!("Failed to initialize database: {}", e))?;
+    let db = Arc::new(db);
+    let auth_service = Arc::new(AuthService::new(&db, config.jwt_secret.clone(), config.tokens.clone(), shared.clone()));
+    Ok(Services { db, auth_service, shared })
+}
+
+/// Connects to the database, starts the background jobs (unless turned off)
+/// and returns the app with every route and middleware in place.
+pub async fn build_app(config: &Config) -> Result<Router, Box<dyn std::error::Error>> {
+    let Services { db, auth_service, shared } = connect(config).await?;
+    let mailer = mailer::Mailer::from_env().map_err(|e| format!("Invalid SMTP_URL or MAIL_FROM: {}", e))?;
+    let dispatcher = notify::Dispatcher::from_env(mailer.clone());
+    let hooks = hooks::Hooks::default()
+        .register(hooks::LogHook)
+        .register(categories::CategoryHook)
+        .register(gamification::GamificationHook)
+        .register(achievements::AchievementHook)
+        .register(occurrences::OccurrenceHook);
+    let hooks = match link_previews::LinkPreviewHook::from_env() {
+        Some(hook) => hooks.register(hook),
+        None => hooks,
+    };
+
+    // Hooks that reach outside the process get events from the outbox
+    // instead, so none are lost or sent for a change that didn't happen.
+    // Delivering them isn't optional, so this runs without background jobs too.
+    let webhooks = webhooks::Webhooks::from_env();
+    let relay = outbox::Relay::new(
+        hooks::Hooks::default()
+            .register(mentions::MentionHook { dispatcher: dispatcher.clone() })
+            .register(notifications::AssignmentHook { dispatcher: dispatcher.clone() })
+            .register(slack::SlackNotifier::from_env(shared.clone()))
+            .register(webhooks.clone()),
+    );
+    relay.start(db.clone());
+
+    let google_calendar = google_calendar::GoogleCalendar::from_env();
+    let retention_policy = retention::RetentionPolicy::from_env();
+
+    // Background jobs
+    let mut scheduler = jobs::Scheduler::default()
+        .register(reports::WeeklyReportsJob::new(mailer.clone()))
+        .register(digest::DigestJob::from_env(mailer.clone()))
+        .register(backups::BackupJob::new(backups::BackupConfig::from_env()))
+        .register(escalation::EscalationJob::from_env(db.clone(), dispatcher.clone()))
+        .register(retention::RetentionJob::new(db.clone(), retention_policy.clone()))
+        .register(myday::RolloverJob);
+    if let Some(google) = google_calendar.clone() {
+        scheduler = scheduler.register(google_calendar::CalendarSyncJob { google, db: db.clone(), hooks: hooks.clone() });
+    }
+    if config.background_jobs {
+        scheduler.start(db.clone()).await.map_err(|e| format!("Failed to start background jobs: {}", e))?;
+    }
+
+    let inbox_config = inbox::InboxConfig::from_env();
+    let login_guard = login_guard::LoginGuard::from_env(shared.clone());
+    let concurrency = load_shed::ConcurrencyLimits::from_env();
+
+    // Public routes
+    let mut auth_routes = Router::new()
+        .route("/auth/register", post(register))
+        .route("/auth/login", post(login))
+        .route("/auth/device/code", post(start_device_authorization));
+    if let Some(limit) = rate_limit::RateLimit::auth_from_env(shared) {
+        auth_routes = auth_routes.route_layer(middleware::from_fn_with_state(limit, rate_limit::limit));
+    }
+    let auth_routes = load_shed::limit(auth_routes, concurrency.auth);
+    let public_routes = Router::new()
+        .route("/", get(web::home))
+        .route("/static/*path", get(web::static_asset))
+        .route("/device", get(web::device))
+        // Polled every few seconds, so outside the auth rate limit; `slow_down` paces it
+        .route("/auth/device/token", post(device_token))
+        .route("/inbound/email/:secret", post(receive_email))
+        .route("/integrations/google/callback", get(google_callback))
+        .route("/billing/stripe/webhook", post(stripe_webhook))
+        .merge(auth_routes);
+
+    // Admin routes, for users granted admin with the CLI
+    let admin_routes = Router::new()
+        .route("/admin/stats", get(get_admin_stats))
+        .route("/admin/jobs", get(get_jobs))
+        .route("/admin/jobs/:name/runs", get(get_job_runs))
+        .route("/admin/jobs/:name/run", post(run_job))
+        .route_layer(middleware::from_fn_with_state(auth_service.clone(), simple_auth::require_admin));
+
+    // Protected routes
+    let protected_routes = Router::new()
+        .route("/auth/logout", post(logout))
+        .route("/auth/me", get(me))
+        .route("/auth/me/export", get(export_me))
+        .route("/auth/me/deactivate", post(deactivate_me))
+        .route("/auth/password", post(change_password))
+        .route("/auth/activity", get(get_auth_activity))
+        .route("/auth/device/approve", post(approve_device))
+        .route("/todos", get(get_todos))
+        .route("/todos", post(add_todo))
+        .route("/todos/batch", post(add_todos))
+        .route("/todos/complete", post(complete_todos))
+        .route("/todos/export.ndjson", get(export_todos))
+        .route("/todos/overdue", get(get_overdue_todos))
+        .route("/todos/nearby", get(get_nearby_todos))
+        .route("/todos/upcoming", get(get_upcoming_todos))
+        .route("/todos/print", get(print_todos))
+        .route("/fragments/todos", get(todo_rows_fragment).post(add_todo_fragment))
+        .route("/fragments/todos/:id/toggle", post(toggle_todo_fragment))
+        .route(hypermedia::TODO, get(get_todo))
+        .route(hypermedia::TODO, patch(update_todo))
+        .route("/todos/:id/assign", post(assign_todo))
+        .route(hypermedia::DUPLICATE, post(duplicate_todo))
+        .route("/todos/:id/escalations", get(get_todo_escalations))
+        .route(hypermedia::OCCURRENCES, get(get_todo_occurrences))
+        .route(hypermedia::BLOCKERS, post(add_blocker))
+        .route("/todos/:id/blockers/:blocker_id", delete(remove_blocker))
+        .route(hypermedia::TOGGLE, post(toggle_todo))
+        .route("/autocomplete", get(autocomplete))
+        .route("/categories", get(get_categories).post(create_category))
+        .route("/categories/order", put(reorder_categories))
+        .route("/categories/:id", patch(update_category).delete(delete_category))
+        .route("/filters", get(get_filters))
+        .route("/filters", post(create_filter))
+        .route("/filters/:id", delete(delete_filter))
+        .route("/filters/:id/todos", get(get_filter_todos))
+        .route("/rules", get(get_rules).post(create_rule))
+        .route("/rules/preview", post(preview_rule))
+        .route("/rules/:id", put(update_rule).delete(delete_rule))
+        .route("/stats", get(get_stats))
+        .route("/usage", get(get_usage))
+        .route("/flags", get(get_flags))
+        .route("/gamification/me", get(get_gamification))
+        .route("/achievements", get(get_achievements))
+        .route("/integrations/google", get(get_google_calendar).delete(disconnect_google_calendar))
+        .route("/integrations/google/connect", post(connect_google_calendar))
+        .route("/integrations/google/sync", post(sync_google_calendar))
+        .route("/sync", get(get_sync_changes).post(apply_sync_changes))
+        .route("/myday/:date", get(get_myday))
+        .route("/myday/:date/todos/:id", post(add_to_myday).delete(remove_from_myday))
+        .route("/reports/weekly", get(get_weekly_reports))
+        .route("/workspaces", get(get_workspaces))
+        .route("/workspaces", post(create_workspace))
+        .route("/workspaces/switch", post(switch_workspace))
+        .route("/workspaces/:id/members", get(get_workspace_members))
+        .route("/workspaces/:id/members", post(add_workspace_member))
+        .route("/workspaces/:id/members/:user_id", delete(remove_workspace_member))
+        .route("/workspaces/:id/leaderboard", get(get_workspace_leaderboard))
+        .route("/workspaces/:id/slack", put(set_workspace_slack))
+        .route("/workspaces/:id/invites", get(get_workspace_invites))
+        .route("/workspaces/:id/invites", post(create_workspace_invite))
+        .route("/workspaces/:id/invites/:invite_id", delete(revoke_workspace_invite))
+        .route("/invites/accept", post(accept_invite))
+        .route("/notifications", get(get_notifications))
+        .route("/mentions", get(get_mentions))
+        .route("/settings", get(get_settings).put(update_settings))
+        .route("/inbox", get(get_inbox))
+        .route("/inbox/rotate", post(rotate_inbox))
+        .route("/notifications/:id/read", post(mark_notification_read))
+        .route("/notifications/:id/deliveries", get(get_notification_deliveries))
+        .route("/notifications/channels", get(get_notification_channels))
+        .route("/notifications/channels/:channel", put(update_notification_channel).delete(delete_notification_channel))
+        .route("/events", get(get_events))
+        .route("/webhooks", get(get_webhooks).post(create_webhook))
+        .route("/webhooks/:id", delete(delete_webhook))
+        .route("/webhooks/:id/deliveries", get(get_webhook_deliveries))
+        .route("/webhooks/:id/deliveries/:delivery_id/redeliver", post(redeliver_webhook));
+    #[cfg(feature = "ai")]
+    let protected_routes = protected_routes.route("/todos/parse", post(parse_todos));
+    let protected_routes = protected_routes
+        .merge(admin_routes)
+        .route_layer(middleware::from_fn_with_state(auth_service.clone(), session::require_csrf))
+        .route_layer(middleware::from_fn_with_state(
+            auth_service.clone(),
+            simple_auth::auth_middleware,
+        ));
+    let protected_routes = load_shed::limit(protected_routes, concurrency.todos);
+
+    #[cfg(feature = "metrics")]
+    let db_pool = db.get_pool().clone();
+    let app = public_routes
+        .merge(protected_routes)
+        .with_state((db, auth_service))
+        .layer(axum::Extension(mailer))
+        .layer(axum::Extension(hooks))
+        .layer(axum::Extension(scheduler))
+        .layer(axum::Extension(dispatcher))
+        .layer(axum::Extension(webhooks))
+        .layer(axum::Extension(inbox_config))
+        .layer(axum::Extension(google_calendar))
+        .layer(axum::Extension(retention_policy))
+        .layer(axum::Extension(login_guard))
+        .layer(axum::Extension(quotas::Quotas::from_env()))
+        .layer(axum::Extension(plans::Billing::from_env()))
+        .layer(axum::Extension(flags::Flags::from_env()));
+    #[cfg(feature = "ai")]
+    let app = app.layer(axum::Extension(ai::Parser::from_env()));
+    #[cfg(feature = "metrics")]
+    let app = app
+        .merge(monitoring::router(db_pool))
+        .layer(middleware::from_fn(monitoring::track_requests));
+    let app = app.layer(
+        ServiceBuilder::new()
+            .layer(SetRequestIdLayer::new(request_id::X_REQUEST_ID, MakeRequestUuid))
+            .layer(logging::trace_layer())
+            .layer(PropagateRequestIdLayer::new(request_id::X_REQUEST_ID))
+            .layer(middleware::from_fn(request_id::error_body)),
+    );
+    #[cfg(feature = "msgpack")]
+    let app = app.layer(middleware::from_fn(formats::msgpack));
+
+    Ok(app)
+}
+
+async fn register(
+    axum::extract::State((db, auth_service)): axum::extract::State<(Arc<Database>, Arc<AuthService>)>,
+    axum::extract::Query(session): axum::extract::Query<session::SessionQuery>,
+    axum::Extension(hooks): axum::Extension<hooks::Hooks>,
+    client: auth_events::Client,
+    Json(req): Json<RegisterRequest>,
+) -> Result<Response, simple_auth::AuthError> {
+    let username = req.username.clone();
+    let response = auth_service.register(req).await?;
+    auth_events::record(&db, "register", Some(&response.user_id), &client).await;
+    hooks.emit(&db, hooks::Event::UserRegistered { user_id: &response.user_id, username: &username }).await;
+    Ok(session::respond(&auth_service, response, session.cookie))
+}
+
+async fn login(
+    axum::extract::State((db, auth_service)): axum::extract::State<(Arc<Database>, Arc<AuthService>)>,
+    axum::Extension(guard): axum::Extension<login_guard::LoginGuard>,
+    axum::extract::Query(session): axum::extract::Query<session::SessionQuery>,
+    client: auth_events::Client,
+    Json(req): Json<LoginRequest>,
+) -> Result<Response, Response> {
+    // The password isn't checked while locked, so guessing can't continue
+    if let Some(wait) = guard.wait(&req.username, client.ip.as_deref()).await {
+        return Err((StatusCode::TOO_MANY_REQUESTS, [(header::RETRY_AFTER, wait.as_secs().to_string())]).into_response());
+    }
+
+    let username = req.username.clone();
+    match auth_service.login(req).await {
+        Ok(response) => {
+            guard.record_success(&username).await;
+            auth_events::record(&db, "login", Some(&response.user_id), &client).await;
+            Ok(session::respond(&auth_service, response, session.cookie))
+        }
+        Err(simple_auth::AuthError::InvalidCredentials) => {
+            let user_id = auth_service.find_user_id(&username).await.ok();
+            auth_events::record(&db, "login_failed", user_id.as_deref(), &client).await;
+            guard.record_failure(&db, &username, user_id.as_deref(), &client).await;
+            Err(StatusCode::UNAUTHORIZED.into_response())
+        }
+        Err(err) => Err(StatusCode::from(err).into_response()),
+    }
+}
+
+async fn change_password(
+    axum::extract::State((db, auth_service)): axum::extract::State<(Arc<Database>, Arc<AuthService>)>,
+    axum::Extension(user_id): axum::Extension<String>,
+    client: auth_events::Client,
+    Json(req): Json<simple_auth::ChangePasswordRequest>,
+) -> Result<StatusCode, simple_auth::AuthError> {
+    auth_service.change_password(&user_id, req).await?;
+    auth_events::record(&db, "password_changed", Some(&user_id), &client).await;
+    Ok(StatusCode::NO_CONTENT)
+}
+
+async fn logout(
+    axum::extract::State((db, auth_service)): axum::extract::State<(Arc<Database>, Arc<AuthService>)>,
+    axum::Extension(user_id): axum::Extension<String>,
+    cookie_session: Option<axum::Extension<session::CookieSession>>,
+    client: auth_events::Client,
+    headers: HeaderMap,
+) -> Response {
+    let Some(token) = simple_auth::request_token(&headers) else {
+        return StatusCode::UNAUTHORIZED.into_response();
+    };
+    let result = auth_service.logout(token).await;
+    if result.is_ok() {
+        auth_events::record(&db, "logout", Some(&user_id), &client).await;
+    }
+    match result {
+        Ok(()) if cookie_session.is_some() => (StatusCode::NO_CONTENT, session::clear_cookies()).into_response(),
+        Ok(()) => StatusCode::NO_CONTENT.into_response(),
+        Err(err) => StatusCode::from(err).into_response(),
+    }
+}
+
+#[derive(Serialize)]
+struct MeResponse {
+    #[serde(flatten)]
+    profile: simple_auth::Profile,
+    /// The workspace the token works in; absent for personal todos
+    #[serde(skip_serializing_if = "Option::is_none")]
+    workspace_id: Option<String>,
+    settings: settings::UserSettings,
+}
+
+async fn me(
+    axum::extract::State((db, auth_service)): axum::extract::State<(Arc<Database>, Arc<AuthService>)>,
+    axum::Extension(scope): axum::Extension<Scope>,
+) -> Result<Json<MeResponse>, StatusCode> {
+    let profile = auth_service.get_profile(&scope.user_id).await?;
+    let settings = user_settings(&db, &scope.user_id).await?;
+    Ok(Json(MeResponse { profile, workspace_id: scope.workspace_id, settings }))
+}
+
+/// Disables the signed-in account; an admin can re-enable it with `todo-app user enable`.
+async fn deactivate_me(
+    axum::extract::State((db, auth_service)): axum::extract::State<(Arc<Database>, Arc<AuthService>)>,
+    axum::Extension(user_id): axum::Extension<String>,
+    cookie_session: Option<axum::Extension<session::CookieSession>>,
+    client: auth_events::Client,
+    Json(req): Json<simple_auth::DeactivateRequest>,
+) -> Result<Response, StatusCode> {
+    auth_service.deactivate(&user_id, req).await?;
+    auth_events::record(&db, "account_disabled", Some(&user_id), &client).await;
+    if cookie_session.is_some() {
+        return Ok((StatusCode::NO_CONTENT, session::clear_cookies()).into_response());
+    }
+    Ok(StatusCode::NO_CONTENT.into_response())
+}
+
+/// Downloads everything stored about the signed-in user as one JSON file.
+async fn export_me(
+    axum::extract::State((db, auth_service)): axum::extract::State<(Arc<Database>, Arc<AuthService>)>,
+    axum::Extension(user_id): axum::Extension<String>,
+    axum::Extension(inbox_config): axum::Extension<Option<inbox::InboxConfig>>,
+    axum::Extension(dispatcher): axum::Extension<notify::Dispatcher>,
+    client: auth_events::Client,
+) -> Result<Response, StatusCode> {
+    let profile = auth_service.get_profile(&user_id).await?;
+    let export = data_export::collect(&db, &dispatcher, inbox_config.as_ref(), profile)
+        .await
+        .map_err(|_| StatusCode::INTERNAL_SERVER_ERROR)?;
+    auth_events::record(&db, "data_exported", Some(&user_id), &client).await;
+
+    let filename = format!("todo-app-export-{}.json", export.exported_at.format("%Y-%m-%d"));
+    Ok((
+        [(header::CONTENT_DISPOSITION, format!("attachment; filename=\"{}\"", filename))],
+        Json(export),
+    )
+        .into_response())
+}
+
+#[derive(Deserialize)]
+struct TodoListQuery {
+    /// A user id, or `me`
+    assigned_to: Option<String>,
+    /// Only todos whose text contains this, ignoring case and accents
+    text: Option<String>,
+    render: Option<Render>,
+}
+
+/// `?render=html` adds `notes_html` to the returned todos.
+#[derive(Clone, Copy, Deserialize, PartialEq)]
+#[serde(rename_all = "lowercase")]
+enum Render {
+    Html,
+}
+
+#[derive(Deserialize)]
+struct RenderQuery {
+    render: Option<Render>,
+}
+
+/// JSON, CSV or iCalendar, by the `Accept` header.
+async fn get_todos(
+    axum::extract::State((db, _)): axum::extract::State<(Arc<Database>, Arc<AuthService>)>,
+    axum::Extension(scope): axum::Extension<Scope>,
+    axum::extract::Query(query): axum::extract::Query<TodoListQuery>,
+    axum::extract::Query(page): axum::extract::Query<PageQuery>,
+    axum::extract::Query(fields): axum::extract::Query<fields::FieldsQuery>,
+    uri: axum::http::Uri,
+    headers: HeaderMap,
+) -> Result<Response, StatusCode> {
+    let format = formats::Format::from_accept(&headers)?;
+    let fields = fields.todo_fields()?;
+    let assignee_id = query
+        .assigned_to
+        .map(|assignee| if assignee == "me" { scope.user_id.clone() } else { assignee });
+
+    let filter = simple_db::TodoFilter { assignee_id, text: query.text, ..Default::default() };
+
+    // No day-based criteria, so the timezone doesn't matter
+    let mut listing = if page.is_paginated() {
+        let todos = db
+            .get_todos_page(&scope, &filter, chrono_tz::UTC, page.cursor()?.as_ref(), page.limit())
+            .await
+            .map_err(|e| simple_db::error_status(&e))?;
+        Listing::Page(Page::new(todos, page.limit(), |todo| Cursor::new(todo.created_at, &todo.id)).with_links(&uri))
+    } else {
+        let todos = if filter.assignee_id.is_some() || filter.text.is_some() {
+            db.find_todos(&scope, &filter, chrono_tz::UTC).await
+        } else {
+            db.get_todos(&scope).await
+        };
+        Listing::All(todos.map_err(|e| simple_db::error_status(&e))?)
+    };
+
+    // Skipped when `?fields=` leaves out what they fill in
+    let wanted = |name| fields.as_ref().is_none_or(|fields: &fields::Fields| fields.contains(name));
+    if query.render == Some(Render::Html) && wanted("notes_html") {
+        markdown::render_notes(listing.items_mut());
+    }
+    if wanted("links") {
+        link_previews::attach(db.get_pool(), listing.items_mut()).await.map_err(|e| simple_db::error_status(&e))?;
+    }
+    if wanted("_links") {
+        hypermedia::attach(listing.items_mut());
+    }
+    Ok(format.respond(listing, fields.as_ref(), &uri))
+}
+
+/// Every todo in scope as newline-delimited JSON, one todo per line, streamed
+/// as it's read. A database error partway through cuts the response short.
+async fn export_todos(
+    axum::extract::State((db, _)): axum::extract::State<(Arc<Database>, Arc<AuthService>)>,
+    axum::Extension(scope): axum::Extension<Scope>,
+) -> Response {
+    let lines = tokio_stream::wrappers::ReceiverStream::new(db.stream_todos(&scope)).map(|todo| {
+        let todo = todo.inspect_err(|e| tracing::warn!(error = %e, "Todo export failed partway"))?;
+        let mut line = serde_json::to_vec(&todo)?;
+        line.push(b'\n');
+        Ok::<_, axum::BoxError>(line)
+    });
+    ([(header::CONTENT_TYPE, "application/x-ndjson")], Body::from_stream(lines)).into_response()
+}
+
+/// Open todos with a location within `radius` meters of `lat`,`lng`,
+/// nearest first, each with its `distance_meters`.
+async fn get_nearby_todos(
+    axum::extract::State((db, _)): axum::extract::State<(Arc<Database>, Arc<AuthService>)>,
+    axum::Extension(scope): axum::Extension<Scope>,
+    axum::extract::Query(query): axum::extract::Query<nearby::NearbyQuery>,
+) -> Result<Json<Vec<nearby::NearbyTodo>>, StatusCode> {
+    if !nearby::valid(Some(query.lat), Some(query.lng)) {
+        return Err(StatusCode::UNPROCESSABLE_ENTITY);
+    }
+    match nearby::find(db.get_pool(), &scope, &query).await {
+        Ok(todos) => Ok(Json(todos)),
+        Err(e) => Err(simple_db::error_status(&e)),
+    }
+}
+
+async fn get_overdue_todos(
+    axum::extract::State((db, _)): axum::extract::State<(Arc<Database>, Arc<AuthService>)>,
+    axum::Extension(scope): axum::Extension<Scope>,
+    axum::extract::Query(fields): axum::extract::Query<fields::FieldsQuery>,
+) -> Result<Response, StatusCode> {
+    let fields = fields.todo_fields()?;
+    match db.get_overdue_todos(&scope).await {
+        Ok(mut todos) => {
+            hypermedia::attach(&mut todos);
+            Ok(fields::respond(todos, fields.as_ref()))
+        }
+        Err(e) => Err(simple_db::error_status(&e)),
+    }
+}
+
+#[derive(Deserialize)]
+struct UpcomingQuery {
+    days: Option<u32>,
+}
+
+async fn get_upcoming_todos(
+    axum::extract::State((db, _)): axum::extract::State<(Arc<Database>, Arc<AuthService>)>,
+    axum::Extension(scope): axum::Extension<Scope>,
+    axum::extract::Query(query): axum::extract::Query<UpcomingQuery>,
+    axum::extract::Query(fields): axum::extract::Query<fields::FieldsQuery>,
+) -> Result<Response, StatusCode> {
+    let fields = fields.todo_fields()?;
+    let settings = user_settings(&db, &scope.user_id).await?;
+    match db.get_upcoming_todos(&scope, query.days.unwrap_or(7).min(simple_db::MAX_DAYS_AHEAD), settings.tz()).await {
+        Ok(mut todos) => {
+            hypermedia::attach(&mut todos);
+            Ok(fields::respond(todos, fields.as_ref()))
+        }
+        Err(e) => Err(simple_db::error_status(&e)),
+    }
+}
+
+/// Applies quick-add syntax, the natural-language `due` phrase, the user's
+/// rules and default priority, and checks the color and location, as every
+/// way of creating a todo does. Without a due date, one is looked for in the
+/// text; `Ok(true)` means it was found there.
+fn prepare_todo(new_todo: &mut NewTodo, settings: &settings::UserSettings, rules: &[rules::Rule]) -> Result<bool, StatusCode> {
+    if !nearby::valid(new_todo.latitude, new_todo.longitude) {
+        return Err(StatusCode::UNPROCESSABLE_ENTITY);
+    }
+    if let Some(color) = &new_todo.color {
+        new_todo.color = Some(colors::normalize(color).ok_or(StatusCode::UNPROCESSABLE_ENTITY)?);
+    }
+
+    if new_todo.quick_add {
+        quick_add::apply(new_todo);
+        if new_todo.text.is_empty() {
+            return Err(StatusCode::UNPROCESSABLE_ENTITY);
+        }
+    }
+
+    // Natural-language "due" phrases only apply when no explicit due_date is given
+    if let Some(due) = new_todo.due.take()
+        && new_todo.due_date.is_none()
+    {
+        new_todo.due_date = Some(parse_due(&due, settings).ok_or(StatusCode::UNPROCESSABLE_ENTITY)?);
+    }
+    let due_date_inferred = new_todo.due_date.is_none();
+    if due_date_inferred {
+        new_todo.due_date = dates::extract_due(&new_todo.text, chrono::Utc::now().with_timezone(&settings.tz()));
+    }
+
+    // Rules only fill in what the todo doesn't say itself
+    rules::apply(rules, new_todo);
+    if new_todo.priority.is_none() {
+        new_todo.priority = settings.default_priority;
+    }
+    Ok(due_date_inferred && new_todo.due_date.is_some())
+}
+
+#[derive(Deserialize)]
+struct AddTodoQuery {
+    /// Refuse to add a todo that looks like an open one
+    #[serde(default)]
+    strict: bool,
+}
+
+/// The body of a 201 from `POST /todos`.
+#[derive(Serialize)]
+struct Created {
+    id: String,
+    public_id: String,
+    /// The due date was read out of the text, for clients to offer undoing
+    /// it (`PATCH` with `"due": ""`)
+    due_date_inferred: bool,
+    /// Open todos with similar text, for clients to warn about
+    duplicates: Vec<duplicates::Duplicate>,
+}
+
+/// The body of a 409 from `POST /todos?strict=true`.
+#[derive(Serialize)]
+struct DuplicateRejected {
+    error: &'static str,
+    duplicates: Vec<duplicates::Duplicate>,
+}
+
+/// Answers 201 with the new todo's `Location`, its id and any open todos
+/// that look like it; with `?strict=true` those make it 409 instead.
+///
+/// With an `Idempotency-Key` header, a repeat of a request that created a
+/// todo in the last 24 hours creates nothing and gets the same `Location`
+/// and id, marked `Idempotent-Replayed: true`. The key is saved with the
+/// todo, and a repeat with a different body gets 422.
+async fn add_todo(
+    axum::extract::State((db, _)): axum::extract::State<(Arc<Database>, Arc<AuthService>)>,
+    axum::Extension(scope): axum::Extension<Scope>,
+    axum::Extension(hooks): axum::Extension<hooks::Hooks>,
+    axum::Extension(quotas): axum::Extension<quotas::Quotas>,
+    axum::extract::Query(query): axum::extract::Query<AddTodoQuery>,
+    headers: HeaderMap,
+    Json(body): Json<serde_json::Value>,
+) -> Result<Response, Response> {
+    let key = idempotency::key(&headers)
+        .map_err(IntoResponse::into_response)?
+        .map(|key| idempotency::Key::new(key, &body));
+    if let Some(key) = &key
+        && let Some(response) = replay(&db, &scope, key).await?
+    {
+        return Ok(response);
+    }
+
+    let new_todo: NewTodo = serde_json::from_value(body).map_err(|_| StatusCode::UNPROCESSABLE_ENTITY.into_response())?;
+    match create_todo(&db, &scope, &hooks, &quotas, new_todo, query.strict, key.as_ref()).await? {
+        Some(body) => Ok(created(body)),
+        // Another request with the key created its todo after the check above
+        None => {
+            let key = key.as_ref().expect("only an idempotency key stops a create");
+            Ok(replay(&db, &scope, key).await?.unwrap_or_else(|| StatusCode::CONFLICT.into_response()))
+        }
+    }
+}
+
+/// The answer to a request repeating `key`, if a request made with it in
+/// the last 24 hours created a todo: the same 201, or 422 for a different body.
+async fn replay(db: &Database, scope: &Scope, key: &idempotency::Key) -> Result<Option<Response>, Response> {
+    let earlier = idempotency::earlier(db.get_pool(), &scope.user_id, key)
+        .await
+        .map_err(|e| simple_db::error_status(&e).into_response())?;
+    let todo_id = match earlier {
+        None => return Ok(None),
+        Some(idempotency::Earlier::DifferentRequest) => return Err(StatusCode::UNPROCESSABLE_ENTITY.into_response()),
+        Some(idempotency::Earlier::Created { todo_id }) => todo_id,
+    };
+    let todo = db.get_todo(&todo_id, scope).await.map_err(|e| simple_db::error_status(&e).into_response())?;
+    // Deleted since; the internal id still names it
+    let public_id = todo.map_or_else(|| todo_id.clone(), |todo| todo.public_id);
+    let mut response = created(Created { id: todo_id, public_id, due_date_inferred: false, duplicates: Vec::new() });
+    response.headers_mut().insert(idempotency::IDEMPOTENT_REPLAYED, header::HeaderValue::from_static("true"));
+    Ok(Some(response))
+}
+
+fn created(body: Created) -> Response {
+    let location = format!("/todos/{}", body.public_id);
+    (StatusCode::CREATED, [(header::LOCATION, location)], Json(body)).into_response()
+}
+
+async fn create_todo(
+    db: &Database,
+    scope: &Scope,
+    hooks: &hooks::Hooks,
+    quotas: &quotas::Quotas,
+    mut new_todo: NewTodo,
+    strict: bool,
+    key: Option<&idempotency::Key>,
+) -> Result<Option<Created>, Response> {
+    quotas.check_todos(db.get_pool(), &scope.user_id, 1).await.map_err(IntoResponse::into_response)?;
+    let settings = user_settings(db, &scope.user_id).await.map_err(IntoResponse::into_response)?;
+    let rules = user_rules(db, &scope.user_id).await.map_err(IntoResponse::into_response)?;
+    let due_date_inferred = prepare_todo(&mut new_todo, &settings, &rules).map_err(IntoResponse::into_response)?;
+
+    let existing = db.get_todos(scope).await.map_err(|e| simple_db::error_status(&e).into_response())?;
+    let duplicates = duplicates::find(&new_todo.text, &existing);
+    if strict && !duplicates.is_empty() {
+        let body = DuplicateRejected { error: "Possible duplicate", duplicates };
+        return Err((StatusCode::CONFLICT, Json(body)).into_response());
+    }
+
+    let todo = match key {
+        Some(key) => db.create_todo_once(new_todo, scope, key).await,
+        None => db.create_todo(new_todo, scope).await.map(Some),
+    };
+    let Some(todo) = todo.map_err(|e| simple_db::error_status(&e).into_response())? else {
+        return Ok(None);
+    };
+    hooks.emit(db, hooks::Event::TodoCreated { todo: &todo, actor_id: &scope.user_id }).await;
+    Ok(Some(Created { id: todo.id, public_id: todo.public_id, due_date_inferred, duplicates }))
+}
+
+#[derive(Serialize)]
+struct BatchCreated {
+    /// In the order the todos were given
+    ids: Vec<String>,
+}
+
+/// Creates up to `MAX_BATCH` todos at once, all or none: one invalid todo
+/// rejects the whole batch.
+async fn add_todos(
+    axum::extract::State((db, _)): axum::extract::State<(Arc<Database>, Arc<AuthService>)>,
+    axum::Extension(scope): axum::Extension<Scope>,
+    axum::Extension(hooks): axum::Extension<hooks::Hooks>,
+    axum::Extension(quotas): axum::Extension<quotas::Quotas>,
+    Json(mut new_todos): Json<Vec<NewTodo>>,
+) -> Result<(StatusCode, Json<BatchCreated>), Response> {
+    if new_todos.len() > simple_db::MAX_BATCH {
+        return Err(StatusCode::PAYLOAD_TOO_LARGE.into_response());
+    }
+    let settings = user_settings(&db, &scope.user_id).await.map_err(IntoResponse::into_response)?;
+    let rules = user_rules(&db, &scope.user_id).await.map_err(IntoResponse::into_response)?;
+    for new_todo in &mut new_todos {
+        prepare_todo(new_todo, &settings, &rules).map_err(IntoResponse::into_response)?;
+    }
+    quotas
+        .check_todos(db.get_pool(), &scope.user_id, new_todos.len() as i64)
+        .await
+        .map_err(IntoResponse::into_response)?;
+
+    let todos = db
+        .create_todos(new_todos, &scope)
+        .await
+        .map_err(|e| simple_db::error_status(&e).into_response())?;
+    for todo in &todos {
+        hooks.emit(&db, hooks::Event::TodoCreated { todo, actor_id: &scope.user_id }).await;
+    }
+    let ids = todos.into_iter().map(|todo| todo.id).collect();
+    Ok((StatusCode::CREATED, Json(BatchCreated { ids })))
+}
+
+#[cfg(feature = "ai")]
+#[derive(Deserialize)]
+struct ParseRequest {
+    text: String,
+}
+
+#[cfg(feature = "ai")]
+#[derive(Serialize)]
+struct Parsed {
+    suggestions: Vec<ai::Suggestion>,
+}
+
+/// Todos the configured model reads out of free-form text, for the client to
+/// show, let the user edit and add with `POST /todos/batch`. Nothing is saved.
+#[cfg(feature = "ai")]
+async fn parse_todos(
+    axum::extract::State((db, _)): axum::extract::State<(Arc<Database>, Arc<AuthService>)>,
+    axum::Extension(user_id): axum::Extension<String>,
+    axum::Extension(parser): axum::Extension<Option<ai::Parser>>,
+    axum::Extension(flags): axum::Extension<flags::Flags>,
+    Json(request): Json<ParseRequest>,
+) -> Result<Json<Parsed>, StatusCode> {
+    let parser = parser.ok_or(StatusCode::NOT_FOUND)?;
+    if !flags.is_enabled(db.get_pool(), &user_id, "llm_parser").await.map_err(|e| simple_db::error_status(&e))? {
+        return Err(StatusCode::NOT_FOUND);
+    }
+    let text = request.text.trim();
+    if text.is_empty() {
+        return Err(StatusCode::UNPROCESSABLE_ENTITY);
+    }
+    if text.chars().count() > ai::MAX_INPUT_CHARS {
+        return Err(StatusCode::PAYLOAD_TOO_LARGE);
+    }
+
+    let settings = user_settings(&db, &user_id).await?;
+    match parser.parse(text, chrono::Utc::now().with_timezone(&settings.tz())).await {
+        Ok(suggestions) => Ok(Json(Parsed { suggestions })),
+        Err(e) => {
+            tracing::warn!(error = %e, "Failed to parse todos with the model");
+            Err(StatusCode::BAD_GATEWAY)
+        }
+    }
+}
+
+async fn update_todo(
+    TodoId(id): TodoId,
+    axum::extract::State((db, _)): axum::extract::State<(Arc<Database>, Arc<AuthService>)>,
+    axum::Extension(scope): axum::Extension<Scope>,
+    axum::Extension(hooks): axum::Extension<hooks::Hooks>,
+    headers: HeaderMap,
+    Json(update): Json<UpdateTodo>,
+) -> Result<([(header::HeaderName, String); 1], Json<Todo>), StatusCode> {
+    // Updates must say which version they were based on so concurrent edits can't clobber each other
+    let expected_version = if_match(&headers)?
+        .or(update.expected_version)
+        .ok_or(StatusCode::PRECONDITION_REQUIRED)?;
+
+    let todo = apply_update(&db, &hooks, &scope, &id, update, expected_version).await?;
+    Ok(([etag(&todo)], Json(todo)))
+}
+
+/// Checks and applies a `PATCH /todos/:id` body to a todo in `scope`.
+async fn apply_update(db: &Database, hooks: &hooks::Hooks, scope: &Scope, id: &str, mut update: UpdateTodo, expected_version: i64) -> Result<Todo, StatusCode> {
+    if !nearby::valid(update.latitude, update.longitude) {
+        return Err(StatusCode::UNPROCESSABLE_ENTITY);
+    }
+    if let Some(color) = update.color.as_deref()
+        && !color.is_empty()
+    {
+        update.color = Some(colors::normalize(color).ok_or(StatusCode::UNPROCESSABLE_ENTITY)?);
+    }
+
+    // An empty "due" clears the due date, which the database does
+    if let Some(due) = update.due.clone().filter(|due| !due.is_empty())
+        && update.due_date.is_none()
+    {
+        let settings = user_settings(db, &scope.user_id).await?;
+        update.due_date = Some(parse_due(&due, &settings).ok_or(StatusCode::UNPROCESSABLE_ENTITY)?);
+    }
+
+    let text_changed = update.text.is_some();
+    let todo = db.update_todo(id, scope, update, expected_version).await?;
+    hooks.emit(db, hooks::Event::TodoUpdated { todo: &todo, actor_id: &scope.user_id, text_changed }).await;
+    Ok(todo)
+}
+
+/// A natural-language due date, read in the user's timezone: "friday" is the
+/// end of Friday where they are, not in UTC.
+fn parse_due(due: &str, settings: &settings::UserSettings) -> Option<chrono::DateTime<chrono::Utc>> {
+    dates::parse_due(due, chrono::Utc::now().with_timezone(&settings.tz()))
+}
+
+/// The version named by an `If-Match` header (`"3"`, `W/"3"` or `3`), if present.
+fn if_match(headers: &HeaderMap) -> Result<Option<i64>, StatusCode> {
+    let Some(value) = headers.get(header::IF_MATCH) else {
+        return Ok(None);
+    };
+    let value = value.to_str().map_err(|_| StatusCode::BAD_REQUEST)?.trim();
+    let value = value.strip_prefix("W/").unwrap_or(value).trim_matches('"');
+    value.parse().map(Some).map_err(|_| StatusCode::BAD_REQUEST)
+}
+
+fn etag(todo: &Todo) -> (header::HeaderName, String) {
+    (header::ETAG, format!("\"{}\"", todo.version))
+}
+
+/// The todo named in a `/todos/:id` path, by its public id or its internal
+/// one; handlers get the internal id.
+struct TodoId(String);
+
+#[axum::async_trait]
+impl axum::extract::FromRequestParts<(Arc<Database>, Arc<AuthService>)> for TodoId {
+    type Rejection = Response;
+
+    async fn from_request_parts(parts: &mut axum::http::request::Parts, (db, _): &(Arc<Database>, Arc<AuthService>)) -> Result<Self, Self::Rejection> {
+        let axum::extract::Path(id) = axum::extract::Path::<String>::from_request_parts(parts, &()).await.map_err(IntoResponse::into_response)?;
+        db.todo_id(&id).await.map(TodoId).map_err(|e| simple_db::error_status(&e).into_response())
+    }
+}
+
+async fn get_todo(
+    TodoId(id): TodoId,
+    axum::extract::State((db, _)): axum::extract::State<(Arc<Database>, Arc<AuthService>)>,
+    axum::Extension(scope): axum::Extension<Scope>,
+    axum::extract::Query(query): axum::extract::Query<RenderQuery>,
+    headers: HeaderMap,
+) -> Result<Response, StatusCode> {
+    let format = formats::Format::from_accept(&headers)?;
+    let mut todo = match db.get_todo(&id, &scope).await {
+        Ok(Some(todo)) => todo,
+        Ok(None) => return Err(StatusCode::NOT_FOUND),
+        Err(_) => return Err(StatusCode::INTERNAL_SERVER_ERROR),
+    };
+
+    if query.render == Some(Render::Html) {
+        markdown::render_notes([&mut todo]);
+    }
+    link_previews::attach(db.get_pool(), [&mut todo]).await.map_err(|e| simple_db::error_status(&e))?;
+    hypermedia::attach([&mut todo]);
+
+    let blockers = dependencies::get_blockers(db.get_pool(), &id).await;
+    let dependents = dependencies::get_dependents(db.get_pool(), &id).await;
+    match (blockers, dependents) {
+        (Ok(blockers), Ok(dependents)) => {
+            let etag = etag(&todo);
+            let detail = dependencies::TodoDetail { todo, blockers, dependents };
+            let body = match format {
+                formats::Format::JsonApi => jsonapi::detail(detail),
+                _ => Json(detail).into_response(),
+            };
+            Ok(([etag], body).into_response())
+        }
+        _ => Err(StatusCode::INTERNAL_SERVER_ERROR),
+    }
+}
+
+#[derive(Default, Deserialize)]
+struct DuplicateTodo {
+    /// Moves the copy's due date this many days from the original's
+    shift_days: Option<i64>,
+}
+
+/// Adds an open copy of a todo: its text, notes (with checklists unticked),
+/// category, tags, priority, estimate and due date, optionally shifted.
+async fn duplicate_todo(
+    TodoId(id): TodoId,
+    axum::extract::State((db, _)): axum::extract::State<(Arc<Database>, Arc<AuthService>)>,
+    axum::Extension(scope): axum::Extension<Scope>,
+    axum::Extension(hooks): axum::Extension<hooks::Hooks>,
+    axum::Extension(quotas): axum::Extension<quotas::Quotas>,
+    body: Result<Json<DuplicateTodo>, axum::extract::rejection::JsonRejection>,
+) -> Result<(StatusCode, [(header::HeaderName, String); 1], Json<Todo>), Response> {
+    // The body is optional
+    let options = match body {
+        Ok(Json(options)) => options,
+        Err(axum::extract::rejection::JsonRejection::MissingJsonContentType(_)) => DuplicateTodo::default(),
+        Err(rejection) => return Err(rejection.into_response()),
+    };
+    let original = match db.get_todo(&id, &scope).await {
+        Ok(Some(todo)) => todo,
+        Ok(None) => return Err(StatusCode::NOT_FOUND.into_response()),
+        Err(e) => return Err(simple_db::error_status(&e).into_response()),
+    };
+
+    let due_date = match original.due_date {
+        Some(due_date) => Some(
+            chrono::Duration::try_days(options.shift_days.unwrap_or(0))
+                .and_then(|shift| due_date.checked_add_signed(shift))
+                .ok_or_else(|| StatusCode::UNPROCESSABLE_ENTITY.into_response())?,
+        ),
+        None => None,
+    };
+    let new_todo = NewTodo {
+        tags: original.tags.is_some().then(|| original.tag_list()),
+        color: original.color,
+        latitude: original.latitude,
+        longitude: original.longitude,
+        location_name: original.location_name,
+        text: original.text,
+        notes: original.notes.as_deref().map(markdown::uncheck_tasks),
+        category: original.category,
+        priority: original.priority,
+        due_date,
+        due: None,
+        estimate_minutes: original.estimate_minutes,
+        spent_minutes: None,
+        quick_add: false,
+    };
+
+    quotas.check_todos(db.get_pool(), &scope.user_id, 1).await.map_err(IntoResponse::into_response)?;
+    let todo = db
+        .create_todo(new_todo, &scope)
+        .await
+        .map_err(|e| simple_db::error_status(&e).into_response())?;
+    hooks.emit(&db, hooks::Event::TodoCreated { todo: &todo, actor_id: &scope.user_id }).await;
+    Ok((StatusCode::CREATED, [(header::LOCATION, format!("/todos/{}", todo.public_id))], Json(todo)))
+}
+
+async fn get_todo_escalations(
+    TodoId(id): TodoId,
+    axum::extract::State((db, _)): axum::extract::State<(Arc<Database>, Arc<AuthService>)>,
+    axum::Extension(scope): axum::Extension<Scope>,
+) -> Result<Json<Vec<escalation::Escalation>>, StatusCode> {
+    match db.get_todo(&id, &scope).await {
+        Ok(Some(_)) => {}
+        Ok(None) => return Err(StatusCode::NOT_FOUND),
+        Err(_) => return Err(StatusCode::INTERNAL_SERVER_ERROR),
+    }
+    match escalation::get_escalations(db.get_pool(), &id).await {
+        Ok(escalations) => Ok(Json(escalations)),
+        Err(_) => Err(StatusCode::INTERNAL_SERVER_ERROR),
+    }
+}
+
+async fn get_todo_occurrences(
+    TodoId(id): TodoId,
+    axum::extract::State((db, _)): axum::extract::State<(Arc<Database>, Arc<AuthService>)>,
+    axum::Extension(scope): axum::Extension<Scope>,
+) -> Result<Json<Vec<occurrences::Occurrence>>, StatusCode> {
+    match db.get_todo(&id, &scope).await {
+        Ok(Some(_)) => {}
+        Ok(None) => return Err(StatusCode::NOT_FOUND),
+        Err(e) => return Err(simple_db::error_status(&e)),
+    }
+    match occurrences::get_occurrences(db.get_pool(), &id).await {
+        Ok(occurrences) => Ok(Json(occurrences)),
+        Err(e) => Err(simple_db::error_status(&e)),
+    }
+}
+
+#[derive(Deserialize)]
+struct AssignRequest {
+    /// `null` unassigns
+    assignee_id: Option<String>,
+}
+
+async fn assign_todo(
+    TodoId(id): TodoId,
+    axum::extract::State((db, _)): axum::extract::State<(Arc<Database>, Arc<AuthService>)>,
+    axum::Extension(scope): axum::Extension<Scope>,
+    axum::Extension(hooks): axum::Extension<hooks::Hooks>,
+    Json(assign): Json<AssignRequest>,
+) -> Result<Json<Todo>, StatusCode> {
+    // Assignees must be able to see the todo: members of its workspace, or
+    // for personal todos only their owner
+    if let Some(assignee_id) = &assign.assignee_id {
+        let allowed = match &scope.workspace_id {
+            Some(workspace_id) => workspaces::role(db.get_pool(), workspace_id, assignee_id)
+                .await
+                .map_err(|_| StatusCode::INTERNAL_SERVER_ERROR)?
+                .is_some(),
+            None => *assignee_id == scope.user_id,
+        };
+        if !allowed {
+            return Err(StatusCode::UNPROCESSABLE_ENTITY);
+        }
+    }
+
+    let todo = db.assign_todo(&id, &scope, assign.assignee_id.as_deref()).await?;
+    hooks.emit(&db, hooks::Event::TodoAssigned { todo: &todo, actor_id: &scope.user_id }).await;
+    Ok(Json(todo))
+}
+
+async fn add_blocker(
+    TodoId(id): TodoId,
+    axum::extract::State((db, _)): axum::extract::State<(Arc<Database>, Arc<AuthService>)>,
+    axum::Extension(scope): axum::Extension<Scope>,
+    Json(dependency): Json<dependencies::NewDependency>,
+) -> StatusCode {
+    let blocker_id = match db.todo_id(&dependency.blocker_id).await {
+        Ok(blocker_id) => blocker_id,
+        Err(e) => return simple_db::error_status(&e),
+    };
+    match dependencies::add_blocker(&db, &scope, &id, &blocker_id).await {
+        Ok(()) => StatusCode::CREATED,
+        Err(err) => err.into(),
+    }
+}
+
+async fn remove_blocker(
+    axum::extract::Path((id, blocker_id)): axum::extract::Path<(String, String)>,
+    axum::extract::State((db, _)): axum::extract::State<(Arc<Database>, Arc<AuthService>)>,
+    axum::Extension(scope): axum::Extension<Scope>,
+) -> StatusCode {
+    let (id, blocker_id) = match (db.todo_id(&id).await, db.todo_id(&blocker_id).await) {
+        (Ok(id), Ok(blocker_id)) => (id, blocker_id),
+        (Err(e), _) | (_, Err(e)) => return simple_db::error_status(&e),
+    };
+    match dependencies::remove_blocker(&db, &scope, &id, &blocker_id).await {
+        Ok(true) => StatusCode::NO_CONTENT,
+        Ok(false) => StatusCode::NOT_FOUND,
+        Err(_) => StatusCode::INTERNAL_SERVER_ERROR,
+    }
+}
+
+async fn toggle_todo(
+    TodoId(id): TodoId,
+    axum::extract::State((db, _)): axum::extract::State<(Arc<Database>, Arc<AuthService>)>,
+    axum::Extension(scope): axum::Extension<Scope>,
+    axum::Extension(hooks): axum::Extension<hooks::Hooks>,
+    headers: HeaderMap,
+) -> StatusCode {
+    // If-Match is optional here so the one-click toggle in the web UI keeps working
+    let expected_version = match if_match(&headers) {
+        Ok(version) => version,
+        Err(status) => return status,
+    };
+    match toggle(&db, &hooks, &scope, &id, expected_version).await {
+        Ok(_) => StatusCode::OK,
+        Err(status) => status,
+    }
+}
+
+/// Toggles a todo in `scope`, refusing to complete it while anything blocking it is still open.
+async fn toggle(db: &Database, hooks: &hooks::Hooks, scope: &Scope, id: &str, expected_version: Option<i64>) -> Result<Todo, StatusCode> {
+    let todo = match db.get_todo(id, scope).await {
+        Ok(Some(todo)) => todo,
+        Ok(None) => return Err(StatusCode::NOT_FOUND),
+        Err(_) => return Err(StatusCode::INTERNAL_SERVER_ERROR),
+    };
+
+    if !todo.completed {
+        match dependencies::count_open_blockers(db.get_pool(), id).await {
+            Ok(0) => {}
+            Ok(_) => return Err(StatusCode::CONFLICT),
+            Err(_) => return Err(StatusCode::INTERNAL_SERVER_ERROR),
+        }
+    }
+
+    let todo = db.toggle_todo(id, &scope.user_id, expected_version).await?;
+    let event = if todo.completed {
+        hooks::Event::TodoCompleted { todo: &todo, actor_id: &scope.user_id }
+    } else {
+        hooks::Event::TodoReopened { todo: &todo, actor_id: &scope.user_id }
+    };
+    hooks.emit(db, event).await;
+    Ok(todo)
+}
+
+/// Which todos `POST /todos/complete` completes; every criterion given must
+/// match, and at least one is needed.
+#[derive(Deserialize)]
+struct CompleteQuery {
+    category: Option<String>,
+    tag: Option<String>,
+    priority: Option<simple_db::Priority>,
+    text: Option<String>,
+    overdue: Option<bool>,
+    due_within_days: Option<u32>,
+    /// `today`, `tomorrow` or `YYYY-MM-DD`: due before that day starts
+    due_before: Option<String>,
+    /// A user id, or `me`
+    assigned_to: Option<String>,
+}
+
+#[derive(Serialize)]
+struct BulkCompleted {
+    completed: usize,
+    ids: Vec<String>,
+}
+
+/// Completes every open todo in the scope matching the query in one go, for
+/// "clear my overdue errands". Todos still blocked by an open todo are left
+/// open. Days are the user's.
+async fn complete_todos(
+    axum::extract::State((db, _)): axum::extract::State<(Arc<Database>, Arc<AuthService>)>,
+    axum::Extension(scope): axum::Extension<Scope>,
+    axum::Extension(hooks): axum::Extension<hooks::Hooks>,
+    axum::extract::Query(query): axum::extract::Query<CompleteQuery>,
+) -> Result<Json<BulkCompleted>, StatusCode> {
+    let tz = user_settings(&db, &scope.user_id).await?.tz();
+    let due_before = match &query.due_before {
+        Some(day) => {
+            let today = chrono::Utc::now().with_timezone(&tz).date_naive();
+            Some(dates::start_of_day(dates::parse_day(day, today).ok_or(StatusCode::BAD_REQUEST)?, &tz))
+        }
+        None => None,
+    };
+    let filter = simple_db::TodoFilter {
+        completed: None,
+        category: query.category,
+        tag: query.tag,
+        priority: query.priority,
+        text: query.text,
+        due_within_days: query.due_within_days,
+        overdue: query.overdue,
+        assignee_id: query.assigned_to.map(|assignee| if assignee == "me" { scope.user_id.clone() } else { assignee }),
+        due_before,
+    };
+    // Completing the whole list by leaving out the query is too easy a mistake
+    if filter.category.is_none()
+        && filter.tag.is_none()
+        && filter.priority.is_none()
+        && filter.text.is_none()
+        && filter.due_within_days.is_none()
+        && filter.overdue.is_none()
+        && filter.assignee_id.is_none()
+        && filter.due_before.is_none()
+    {
+        return Err(StatusCode::BAD_REQUEST);
+    }
+
+    let todos = db.complete_todos(&scope, &filter, tz).await.map_err(|e| simple_db::error_status(&e))?;
+    for todo in &todos {
+        hooks.emit(&db, hooks::Event::TodoCompleted { todo, actor_id: &scope.user_id }).await;
+    }
+    let ids: Vec<String> = todos.into_iter().map(|todo| todo.id).collect();
+    Ok(Json(BulkCompleted { completed: ids.len(), ids }))
+}
+
+/// Delta sync for offline clients: what changed in the scope after `?since=`.
+async fn get_sync_changes(
+    axum::extract::State((db, _)): axum::extract::State<(Arc<Database>, Arc<AuthService>)>,
+    axum::Extension(scope): axum::Extension<Scope>,
+    axum::Extension(retention_policy): axum::Extension<retention::RetentionPolicy>,
+    axum::extract::Query(query): axum::extract::Query<sync::SyncQuery>,
+) -> Result<Json<sync::Changes>, StatusCode> {
+    let since = query.since(retention_policy.days("tombstones"))?;
+    match sync::get_changes(db.get_pool(), &scope, since, query.limit()).await {
+        Ok(changes) => Ok(Json(changes)),
+        Err(e) => Err(simple_db::error_status(&e)),
+    }
+}
+
+#[derive(Serialize)]
+struct SyncResults {
+    results: Vec<sync::ChangeResult>,
+}
+
+/// Applies changes a client made offline, up to `MAX_BATCH` at once, in
+/// order. Each stands on its own: one that is rejected or loses a conflict
+/// doesn't stop the rest.
+async fn apply_sync_changes(
+    axum::extract::State((db, _)): axum::extract::State<(Arc<Database>, Arc<AuthService>)>,
+    axum::Extension(scope): axum::Extension<Scope>,
+    axum::Extension(hooks): axum::Extension<hooks::Hooks>,
+    axum::Extension(quotas): axum::Extension<quotas::Quotas>,
+    Json(request): Json<sync::SyncRequest>,
+) -> Result<Json<SyncResults>, StatusCode> {
+    if request.changes.len() > simple_db::MAX_BATCH {
+        return Err(StatusCode::PAYLOAD_TOO_LARGE);
+    }
+
+    let mut results = Vec::with_capacity(request.changes.len());
+    for change in request.changes {
+        let (client_id, id) = (change.client_id.clone(), change.id.clone());
+        let applied = match &id {
+            Some(id) => sync_update(&db, &hooks, &scope, id, change).await,
+            None => sync_create(&db, &hooks, &quotas, &scope, change).await,
+        };
+        results.push(match applied {
+            Ok((outcome, todo)) => sync::ChangeResult { client_id, id: Some(todo.id.clone()), outcome, todo: Some(todo), error: None },
+            Err(status) => sync::ChangeResult { client_id, id, outcome: sync::Outcome::Rejected, todo: None, error: Some(status.as_u16()) },
+        });
+    }
+    Ok(Json(SyncResults { results }))
+}
+
+/// Creates a todo made offline. Its `client_id` works like an
+/// `Idempotency-Key`, so a sync retried within 24 hours finds the todo
+/// instead of adding it again.
+async fn sync_create(db: &Database, hooks: &hooks::Hooks, quotas: &quotas::Quotas, scope: &Scope, change: sync::ClientChange) -> Result<(sync::Outcome, Todo), StatusCode> {
+    let fields = serde_json::Value::Object(change.fields);
+    let key = change.client_id.map(|client_id| idempotency::Key::new(format!("sync:{}", client_id), &fields));
+    let new_todo: NewTodo = serde_json::from_value(fields).map_err(|_| StatusCode::UNPROCESSABLE_ENTITY)?;
+
+    if let Some(key) = &key
+        && let Some(todo) = sync_replay(db, scope, key).await?
+    {
+        return Ok((sync::Outcome::Created, todo));
+    }
+
+    let created = match create_todo(db, scope, hooks, quotas, new_todo, false, key.as_ref()).await {
+        Ok(Some(created)) => created,
+        // Another sync with the client id created the todo after the check above
+        Ok(None) => {
+            let key = key.as_ref().expect("only an idempotency key stops a create");
+            let todo = sync_replay(db, scope, key).await?.ok_or(StatusCode::CONFLICT)?;
+            return Ok((sync::Outcome::Created, todo));
+        }
+        Err(response) => return Err(response.status()),
+    };
+
+    let todo = db
+        .get_todo(&created.id, scope)
+        .await
+        .map_err(|e| simple_db::error_status(&e))?
+        .ok_or(StatusCode::NOT_FOUND)?;
+    if change.completed == Some(true) {
+        return Ok((sync::Outcome::Created, toggle(db, hooks, scope, &todo.id, Some(todo.version)).await?));
+    }
+    Ok((sync::Outcome::Created, todo))
+}
+
+/// The todo an earlier sync with the same client id created, or 422 if
+/// that sync sent different fields.
+async fn sync_replay(db: &Database, scope: &Scope, key: &idempotency::Key) -> Result<Option<Todo>, StatusCode> {
+    match idempotency::earlier(db.get_pool(), &scope.user_id, key).await.map_err(|e| simple_db::error_status(&e))? {
+        None => Ok(None),
+        Some(idempotency::Earlier::DifferentRequest) => Err(StatusCode::UNPROCESSABLE_ENTITY),
+        Some(idempotency::Earlier::Created { todo_id }) => {
+            let todo = db.get_todo(&todo_id, scope).await.map_err(|e| simple_db::error_status(&e))?;
+            todo.map(Some).ok_or(StatusCode::NOT_FOUND)
+        }
+    }
+}
+
+/// Applies an offline edit, unless the server's copy changed since and
+/// its change was the later one.
+async fn sync_update(db: &Database, hooks: &hooks::Hooks, scope: &Scope, id: &str, change: sync::ClientChange) -> Result<(sync::Outcome, Todo), StatusCode> {
+    let id = &db.todo_id(id).await.map_err(|e| simple_db::error_status(&e))?;
+    let mut todo = db
+        .get_todo(id, scope)
+        .await
+        .map_err(|e| simple_db::error_status(&e))?
+        .ok_or(StatusCode::NOT_FOUND)?;
+    if !sync::client_wins(&change, &todo) {
+        return Ok((sync::Outcome::Conflict, todo));
+    }
+
+    if !change.fields.is_empty() {
+        let update: UpdateTodo = serde_json::from_value(serde_json::Value::Object(change.fields)).map_err(|_| StatusCode::UNPROCESSABLE_ENTITY)?;
+        todo = apply_update(db, hooks, scope, id, update, todo.version).await?;
+    }
+    if change.completed.is_some_and(|completed| completed != todo.completed) {
+        todo = toggle(db, hooks, scope, id, Some(todo.version)).await?;
+    }
+    Ok((sync::Outcome::Applied, todo))
+}
+
+async fn get_categories(
+    axum::extract::State((db, _)): axum::extract::State<(Arc<Database>, Arc<AuthService>)>,
+    axum::Extension(user_id): axum::Extension<String>,
+) -> Result<Json<Vec<categories::Category>>, StatusCode> {
+    match categories::get_categories(db.get_pool(), &user_id).await {
+        Ok(categories) => Ok(Json(categories)),
+        Err(e) => Err(simple_db::error_status(&e)),
+    }
+}
+
+/// Tags or categories starting with what has been typed so far, for
+/// completing them as the user types.
+async fn autocomplete(
+    axum::extract::State((db, _)): axum::extract::State<(Arc<Database>, Arc<AuthService>)>,
+    axum::Extension(scope): axum::Extension<Scope>,
+    axum::extract::Query(query): axum::extract::Query<autocomplete::AutocompleteQuery>,
+) -> Result<Json<Vec<String>>, StatusCode> {
+    let terms = db.terms(&scope).await.map_err(|e| simple_db::error_status(&e))?;
+    Ok(Json(autocomplete::suggest(&terms, &query)))
+}
+
+fn category_error(e: categories::CategoryError) -> StatusCode {
+    match e {
+        categories::CategoryError::Invalid => StatusCode::UNPROCESSABLE_ENTITY,
+        categories::CategoryError::Exists => StatusCode::CONFLICT,
+        categories::CategoryError::Database(e) => simple_db::error_status(&e),
+    }
+}
+
+async fn create_category(
+    axum::extract::State((db, _)): axum::extract::State<(Arc<Database>, Arc<AuthService>)>,
+    axum::Extension(user_id): axum::Extension<String>,
+    Json(new_category): Json<categories::NewCategory>,
+) -> Result<(StatusCode, Json<categories::Category>), StatusCode> {
+    match categories::create_category(&db, &user_id, new_category).await {
+        Ok(category) => Ok((StatusCode::CREATED, Json(category))),
+        Err(e) => Err(category_error(e)),
+    }
+}
+
+/// Renaming a category renames it on your todos, in every workspace.
+async fn update_category(
+    axum::extract::Path(id): axum::extract::Path<String>,
+    axum::extract::State((db, _)): axum::extract::State<(Arc<Database>, Arc<AuthService>)>,
+    axum::Extension(user_id): axum::Extension<String>,
+    Json(update): Json<categories::UpdateCategory>,
+) -> Result<Json<categories::Category>, StatusCode> {
+    let renamed = update.name.is_some();
+    let category = categories::update_category(&db, &user_id, &id, update)
+        .await
+        .map_err(category_error)?
+        .ok_or(StatusCode::NOT_FOUND)?;
+    if renamed {
+        db.todos_changed().await;
+    }
+    Ok(Json(category))
+}
+
+/// Deleting a category leaves your todos in it without one.
+async fn delete_category(
+    axum::extract::Path(id): axum::extract::Path<String>,
+    axum::extract::State((db, _)): axum::extract::State<(Arc<Database>, Arc<AuthService>)>,
+    axum::Extension(user_id): axum::Extension<String>,
+) -> StatusCode {
+    match categories::delete_category(&db, &user_id, &id).await {
+        Ok(true) => {
+            db.todos_changed().await;
+            StatusCode::NO_CONTENT
+        }
+        Ok(false) => StatusCode::NOT_FOUND,
+        Err(e) => simple_db::error_status(&e),
+    }
+}
+
+#[derive(Deserialize)]
+struct CategoryOrder {
+    ids: Vec<String>,
+}
+
+async fn reorder_categories(
+    axum::extract::State((db, _)): axum::extract::State<(Arc<Database>, Arc<AuthService>)>,
+    axum::Extension(user_id): axum::Extension<String>,
+    Json(order): Json<CategoryOrder>,
+) -> Result<Json<Vec<categories::Category>>, StatusCode> {
+    match categories::reorder_categories(&db, &user_id, &order.ids).await {
+        Ok(categories) => Ok(Json(categories)),
+        Err(e) => Err(simple_db::error_status(&e)),
+    }
+}
+
+async fn get_filters(
+    axum::extract::State((db, _)): axum::extract::State<(Arc<Database>, Arc<AuthService>)>,
+    axum::Extension(user_id): axum::Extension<String>,
+) -> Result<Json<Vec<filters::SavedFilter>>, StatusCode> {
+    match filters::get_filters(db.get_pool(), &user_id).await {
+        Ok(saved) => Ok(Json(saved)),
+        Err(_) => Err(StatusCode::INTERNAL_SERVER_ERROR),
+    }
+}
+
+async fn create_filter(
+    axum::extract::State((db, _)): axum::extract::State<(Arc<Database>, Arc<AuthService>)>,
+    axum::Extension(user_id): axum::Extension<String>,
+    Json(new_filter): Json<filters::NewSavedFilter>,
+) -> Result<(StatusCode, Json<filters::SavedFilter>), StatusCode> {
+    match filters::create_filter(&db, &user_id, new_filter).await {
+        Ok(saved) => Ok((StatusCode::CREATED, Json(saved))),
+        Err(_) => Err(StatusCode::INTERNAL_SERVER_ERROR),
+    }
+}
+
+async fn delete_filter(
+    axum::extract::Path(id): axum::extract::Path<String>,
+    axum::extract::State((db, _)): axum::extract::State<(Arc<Database>, Arc<AuthService>)>,
+    axum::Extension(user_id): axum::Extension<String>,
+) -> StatusCode {
+    match filters::delete_filter(&db, &user_id, &id).await {
+        Ok(true) => StatusCode::NO_CONTENT,
+        Ok(false) => StatusCode::NOT_FOUND,
+        Err(_) => StatusCode::INTERNAL_SERVER_ERROR,
+    }
+}
+
+async fn get_filter_todos(
+    axum::extract::Path(id): axum::extract::Path<String>,
+    axum::extract::State((db, _)): axum::extract::State<(Arc<Database>, Arc<AuthService>)>,
+    axum::Extension(scope): axum::Extension<Scope>,
+    axum::extract::Query(fields): axum::extract::Query<fields::FieldsQuery>,
+) -> Result<Response, StatusCode> {
+    let fields = fields.todo_fields()?;
+    // Saved filters are personal, but apply to whichever workspace is selected
+    let saved = match filters::get_filter(db.get_pool(), &scope.user_id, &id).await {
+        Ok(Some(saved)) => saved,
+        Ok(None) => return Err(StatusCode::NOT_FOUND),
+        Err(_) => return Err(StatusCode::INTERNAL_SERVER_ERROR),
+    };
+
+    let settings = user_settings(&db, &scope.user_id).await?;
+    match db.find_todos(&scope, &saved.filter, settings.tz()).await {
+        Ok(mut todos) => {
+            hypermedia::attach(&mut todos);
+            Ok(fields::respond(todos, fields.as_ref()))
+        }
+        Err(_) => Err(StatusCode::INTERNAL_SERVER_ERROR),
+    }
+}
+
+async fn get_rules(
+    axum::extract::State((db, _)): axum::extract::State<(Arc<Database>, Arc<AuthService>)>,
+    axum::Extension(user_id): axum::Extension<String>,
+) -> Result<Json<Vec<rules::Rule>>, StatusCode> {
+    Ok(Json(user_rules(&db, &user_id).await?))
+}
+
+async fn create_rule(
+    axum::extract::State((db, _)): axum::extract::State<(Arc<Database>, Arc<AuthService>)>,
+    axum::Extension(user_id): axum::Extension<String>,
+    Json(new_rule): Json<rules::NewRule>,
+) -> Result<(StatusCode, Json<rules::Rule>), StatusCode> {
+    let new_rule = new_rule.validated().ok_or(StatusCode::UNPROCESSABLE_ENTITY)?;
+    match rules::create_rule(&db, &user_id, new_rule).await {
+        Ok(rule) => Ok((StatusCode::CREATED, Json(rule))),
+        Err(e) => Err(simple_db::error_status(&e)),
+    }
+}
+
+async fn update_rule(
+    axum::extract::Path(id): axum::extract::Path<String>,
+    axum::extract::State((db, _)): axum::extract::State<(Arc<Database>, Arc<AuthService>)>,
+    axum::Extension(user_id): axum::Extension<String>,
+    Json(rule): Json<rules::NewRule>,
+) -> Result<Json<rules::Rule>, StatusCode> {
+    let rule = rule.validated().ok_or(StatusCode::UNPROCESSABLE_ENTITY)?;
+    match rules::update_rule(&db, &user_id, &id, rule).await {
+        Ok(Some(rule)) => Ok(Json(rule)),
+        Ok(None) => Err(StatusCode::NOT_FOUND),
+        Err(e) => Err(simple_db::error_status(&e)),
+    }
+}
+
+async fn delete_rule(
+    axum::extract::Path(id): axum::extract::Path<String>,
+    axum::extract::State((db, _)): axum::extract::State<(Arc<Database>, Arc<AuthService>)>,
+    axum::Extension(user_id): axum::Extension<String>,
+) -> StatusCode {
+    match rules::delete_rule(&db, &user_id, &id).await {
+        Ok(true) => StatusCode::NO_CONTENT,
+        Ok(false) => StatusCode::NOT_FOUND,
+        Err(e) => simple_db::error_status(&e),
+    }
+}
+
+/// Dry run of a rule: the todos in scope it matches and what it would set on
+/// them, were they added now. Nothing is saved or changed.
+async fn preview_rule(
+    axum::extract::State((db, _)): axum::extract::State<(Arc<Database>, Arc<AuthService>)>,
+    axum::Extension(scope): axum::Extension<Scope>,
+    Json(rule): Json<rules::NewRule>,
+) -> Result<Json<Vec<rules::PreviewMatch>>, StatusCode> {
+    let rule = rule.validated().ok_or(StatusCode::UNPROCESSABLE_ENTITY)?;
+    let todos = db.get_todos(&scope).await.map_err(|e| simple_db::error_status(&e))?;
+    Ok(Json(rules::preview(&rule, &todos)))
+}
+
+/// Todo rows as HTML, filtered like saved filters (`?category=work&completed=false`).
+async fn todo_rows_fragment(
+    axum::extract::State((db, _)): axum::extract::State<(Arc<Database>, Arc<AuthService>)>,
+    axum::Extension(scope): axum::Extension<Scope>,
+    axum::extract::Query(filter): axum::extract::Query<simple_db::TodoFilter>,
+) -> Result<Html<String>, StatusCode> {
+    let settings = user_settings(&db, &scope.user_id).await?;
+    let todos = db
+        .find_todos(&scope, &filter, settings.tz())
+        .await
+        .map_err(|_| StatusCode::INTERNAL_SERVER_ERROR)?;
+    render_rows(&todos, &settings)
+}
+
+/// Creates a todo from the add form and returns its row.
+async fn add_todo_fragment(
+    axum::extract::State((db, _)): axum::extract::State<(Arc<Database>, Arc<AuthService>)>,
+    axum::Extension(scope): axum::Extension<Scope>,
+    axum::Extension(hooks): axum::Extension<hooks::Hooks>,
+    axum::Extension(quotas): axum::Extension<quotas::Quotas>,
+    axum::Form(form): axum::Form<web::TodoForm>,
+) -> Result<(StatusCode, Html<String>), StatusCode> {
+    let settings = user_settings(&db, &scope.user_id).await?;
+    let mut new_todo = form.into_new_todo(&settings).ok_or(StatusCode::UNPROCESSABLE_ENTITY)?;
+    prepare_todo(&mut new_todo, &settings, &user_rules(&db, &scope.user_id).await?)?;
+    quotas.check_todos(db.get_pool(), &scope.user_id, 1).await?;
+
+    let todo = db.create_todo(new_todo, &scope).await.map_err(|_| StatusCode::INTERNAL_SERVER_ERROR)?;
+    hooks.emit(&db, hooks::Event::TodoCreated { todo: &todo, actor_id: &scope.user_id }).await;
+    Ok((StatusCode::CREATED, render_rows(&[todo], &settings)?))
+}
+
+/// Toggles a todo and returns its updated row.
+async fn toggle_todo_fragment(
+    TodoId(id): TodoId,
+    axum::extract::State((db, _)): axum::extract::State<(Arc<Database>, Arc<AuthService>)>,
+    axum::Extension(scope): axum::Extension<Scope>,
+    axum::Extension(hooks): axum::Extension<hooks::Hooks>,
+) -> Result<Html<String>, StatusCode> {
+    let todo = toggle(&db, &hooks, &scope, &id, None).await?;
+    render_rows(&[todo], &user_settings(&db, &scope.user_id).await?)
+}
+
+async fn user_settings(db: &Database, user_id: &str) -> Result<settings::UserSettings, StatusCode> {
+    settings::get_settings(db.get_pool(), user_id)
+        .await
+        .map_err(|_| StatusCode::INTERNAL_SERVER_ERROR)
+}
+
+async fn user_rules(db: &Database, user_id: &str) -> Result<Vec<rules::Rule>, StatusCode> {
+    rules::get_rules(db.get_pool(), user_id)
+        .await
+        .map_err(|e| simple_db::error_status(&e))
+}
+
+fn render_rows(todos: &[Todo], settings: &settings::UserSettings) -> Result<Html<String>, StatusCode> {
+    match web::todo_rows(todos, settings) {
+        Ok(html) => Ok(Html(html)),
+        Err(_) => Err(StatusCode::INTERNAL_SERVER_ERROR),
+    }
+}
+
+#[derive(Deserialize)]
+struct PrintQuery {
+    /// A saved filter to print instead of the criteria in the query string
+    filter: Option<String>,
+    title: Option<String>,
+}
+
+/// Printable checklist of the todos matching the filter criteria in the query string.
+async fn print_todos(
+    axum::extract::State((db, _)): axum::extract::State<(Arc<Database>, Arc<AuthService>)>,
+    axum::Extension(scope): axum::Extension<Scope>,
+    axum::extract::Query(criteria): axum::extract::Query<simple_db::TodoFilter>,
+    axum::extract::Query(query): axum::extract::Query<PrintQuery>,
+) -> Result<Html<String>, StatusCode> {
+    let (filter, default_title) = match &query.filter {
+        Some(id) => match filters::get_filter(db.get_pool(), &scope.user_id, id).await {
+            Ok(Some(saved)) => (saved.filter, saved.name),
+            Ok(None) => return Err(StatusCode::NOT_FOUND),
+            Err(_) => return Err(StatusCode::INTERNAL_SERVER_ERROR),
+        },
+        None => (criteria, "Todos".to_string()),
+    };
+
+    let settings = user_settings(&db, &scope.user_id).await?;
+    let todos = db
+        .find_todos(&scope, &filter, settings.tz())
+        .await
+        .map_err(|_| StatusCode::INTERNAL_SERVER_ERROR)?;
+    match print::render(query.title.as_deref().unwrap_or(&default_title), &todos, &settings) {
+        Ok(html) => Ok(Html(html)),
+        Err(_) => Err(StatusCode::INTERNAL_SERVER_ERROR),
+    }
+}
+
+async fn get_stats(
+    axum::extract::State((db, _)): axum::extract::State<(Arc<Database>, Arc<AuthService>)>,
+    axum::Extension(scope): axum::Extension<Scope>,
+) -> Result<Json<TodoStats>, StatusCode> {
+    match db.get_stats(&scope).await {
+        Ok(stats) => Ok(Json(stats)),
+        Err(_) => Err(StatusCode::INTERNAL_SERVER_ERROR),
+    }
+}
+
+/// What the user has used of each quota, and the limits.
+async fn get_usage(
+    axum::extract::State((db, _)): axum::extract::State<(Arc<Database>, Arc<AuthService>)>,
+    axum::Extension(user_id): axum::Extension<String>,
+    axum::Extension(quotas): axum::Extension<quotas::Quotas>,
+) -> Result<Json<quotas::Usage>, StatusCode> {
+    match quotas.usage(db.get_pool(), &user_id).await {
+        Ok(usage) => Ok(Json(usage)),
+        Err(e) => Err(simple_db::error_status(&e)),
+    }
+}
+
+/// The feature flags as they apply to the signed-in user.
+async fn get_flags(
+    axum::extract::State((db, _)): axum::extract::State<(Arc<Database>, Arc<AuthService>)>,
+    axum::Extension(user_id): axum::Extension<String>,
+    axum::Extension(flags): axum::Extension<flags::Flags>,
+) -> Result<Json<std::collections::BTreeMap<&'static str, bool>>, StatusCode> {
+    match flags.for_user(db.get_pool(), &user_id).await {
+        Ok(flags) => Ok(Json(flags)),
+        Err(e) => Err(simple_db::error_status(&e)),
+    }
+}
+
+async fn get_weekly_reports(
+    axum::extract::State((db, _)): axum::extract::State<(Arc<Database>, Arc<AuthService>)>,
+    axum::Extension(user_id): axum::Extension<String>,
+) -> Result<Json<Vec<reports::WeeklyReport>>, StatusCode> {
+    match reports::get_reports(db.get_pool(), &user_id).await {
+        Ok(reports) => Ok(Json(reports)),
+        Err(_) => Err(StatusCode::INTERNAL_SERVER_ERROR),
+    }
+}
+
+async fn get_workspaces(
+    axum::extract::State((db, _)): axum::extract::State<(Arc<Database>, Arc<AuthService>)>,
+    axum::Extension(user_id): axum::Extension<String>,
+) -> Result<Json<Vec<workspaces::Workspace>>, StatusCode> {
+    match workspaces::get_workspaces(db.get_pool(), &user_id).await {
+        Ok(workspaces) => Ok(Json(workspaces)),
+        Err(_) => Err(StatusCode::INTERNAL_SERVER_ERROR),
+    }
+}
+
+async fn create_workspace(
+    axum::extract::State((db, _)): axum::extract::State<(Arc<Database>, Arc<AuthService>)>,
+    axum::Extension(user_id): axum::Extension<String>,
+    Json(new_workspace): Json<workspaces::NewWorkspace>,
+) -> Result<(StatusCode, Json<workspaces::Workspace>), StatusCode> {
+    if new_workspace.name.trim().is_empty() {
+        return Err(StatusCode::UNPROCESSABLE_ENTITY);
+    }
+    match workspaces::create_workspace(&db, &user_id, new_workspace).await {
+        Ok(workspace) => Ok((StatusCode::CREATED, Json(workspace))),
+        Err(_) => Err(StatusCode::INTERNAL_SERVER_ERROR),
+    }
+}
+
+async fn switch_workspace(
+    axum::extract::State((db, auth_service)): axum::extract::State<(Arc<Database>, Arc<AuthService>)>,
+    axum::Extension(user_id): axum::Extension<String>,
+    cookie_session: Option<axum::Extension<session::CookieSession>>,
+    client: auth_events::Client,
+    Json(req): Json<simple_auth::SwitchWorkspaceRequest>,
+) -> Result<Response, StatusCode> {
+    let response = auth_service.switch_workspace(&user_id, req.workspace_id).await?;
+    auth_events::record(&db, "workspace_switched", Some(&user_id), &client).await;
+    // A cookie session switches by replacing its cookie
+    Ok(session::respond(&auth_service, response, cookie_session.is_some()))
+}
+
+/// Starts a device authorization for a CLI or browser extension, which gets
+/// its token from `POST /auth/device/token` once the user approves.
+async fn start_device_authorization(
+    axum::extract::State((db, _)): axum::extract::State<(Arc<Database>, Arc<AuthService>)>,
+) -> Result<Json<device_auth::DeviceCode>, StatusCode> {
+    device_auth::create(&db).await.map(Json).map_err(|e| simple_db::error_status(&e))
+}
+
+async fn device_token(
+    axum::extract::State((db, auth_service)): axum::extract::State<(Arc<Database>, Arc<AuthService>)>,
+    client: auth_events::Client,
+    Json(req): Json<device_auth::TokenRequest>,
+) -> Result<Json<simple_auth::AuthResponse>, Response> {
+    let user_id = device_auth::poll(&db, &req.device_code).await.map_err(IntoResponse::into_response)?;
+    let response = auth_service.issue_token(&user_id).await.map_err(IntoResponse::into_response)?;
+    auth_events::record(&db, "device_login", Some(&user_id), &client).await;
+    Ok(Json(response))
+}
+
+/// Approves or denies a device's user code, from the `/device` page.
+async fn approve_device(
+    axum::extract::State((db, _)): axum::extract::State<(Arc<Database>, Arc<AuthService>)>,
+    axum::Extension(user_id): axum::Extension<String>,
+    client: auth_events::Client,
+    Json(decision): Json<device_auth::Decision>,
+) -> Result<StatusCode, device_auth::DeviceError> {
+    device_auth::decide(&db, &user_id, &decision).await?;
+    let kind = if decision.approve { "device_approved" } else { "device_denied" };
+    auth_events::record(&db, kind, Some(&user_id), &client).await;
+    Ok(StatusCode::NO_CONTENT)
+}
+
+/// The signed-in user's own logins, logouts, password changes and lockouts.
+async fn get_auth_activity(
+    axum::extract::State((db, _)): axum::extract::State<(Arc<Database>, Arc<AuthService>)>,
+    axum::Extension(user_id): axum::Extension<String>,
+    axum::extract::Query(page): axum::extract::Query<PageQuery>,
+    uri: axum::http::Uri,
+) -> Result<Json<Listing<auth_events::AuthEvent>>, StatusCode> {
+    // Unpaginated, this is the 100 most recent
+    let limit = if page.is_paginated() { page.limit() + 1 } else { 100 };
+    let events = auth_events::list(db.get_pool(), Some(&user_id), page.cursor()?.as_ref(), limit)
+        .await
+        .map_err(|_| StatusCode::INTERNAL_SERVER_ERROR)?;
+
+    if !page.is_paginated() {
+        return Ok(Json(Listing::All(events)));
+    }
+    Ok(Json(Listing::Page(Page::new(events, page.limit(), |event| Cursor::new(event.created_at, &event.id)).with_links(&uri))))
+}
+
+async fn get_workspace_members(
+    axum::extract::Path(id): axum::extract::Path<String>,
+    axum::extract::State((db, _)): axum::extract::State<(Arc<Database>, Arc<AuthService>)>,
+    axum::Extension(user_id): axum::Extension<String>,
+) -> Result<Json<Vec<workspaces::Member>>, StatusCode> {
+    match workspaces::get_members(db.get_pool(), &user_id, &id).await {
+        Ok(members) => Ok(Json(members)),
+        Err(err) => Err(err.into()),
+    }
+}
+
+async fn add_workspace_member(
+    axum::extract::Path(id): axum::extract::Path<String>,
+    axum::extract::State((db, auth_service)): axum::extract::State<(Arc<Database>, Arc<AuthService>)>,
+    axum::Extension(user_id): axum::Extension<String>,
+    Json(member): Json<workspaces::NewMember>,
+) -> StatusCode {
+    let member_id = match auth_service.find_user_id(&member.username).await {
+        Ok(member_id) => member_id,
+        Err(err) => return err.into(),
+    };
+    match workspaces::add_member(&db, &user_id, &id, &member_id).await {
+        Ok(()) => StatusCode::CREATED,
+        Err(err) => err.into(),
+    }
+}
+
+async fn remove_workspace_member(
+    axum::extract::Path((id, member_id)): axum::extract::Path<(String, String)>,
+    axum::extract::State((db, _)): axum::extract::State<(Arc<Database>, Arc<AuthService>)>,
+    axum::Extension(user_id): axum::Extension<String>,
+) -> StatusCode {
+    match workspaces::remove_member(&db, &user_id, &id, &member_id).await {
+        Ok(()) => StatusCode::NO_CONTENT,
+        Err(err) => err.into(),
+    }
+}
+
+async fn get_workspace_leaderboard(
+    axum::extract::Path(id): axum::extract::Path<String>,
+    axum::extract::State((db, _)): axum::extract::State<(Arc<Database>, Arc<AuthService>)>,
+    axum::Extension(user_id): axum::Extension<String>,
+    axum::extract::Query(query): axum::extract::Query<leaderboard::LeaderboardQuery>,
+) -> Result<Json<leaderboard::Leaderboard>, StatusCode> {
+    match leaderboard::get_leaderboard(db.get_pool(), &user_id, &id, query).await {
+        Ok(leaderboard) => Ok(Json(leaderboard)),
+        Err(err) => Err(err.into()),
+    }
+}
+
+async fn set_workspace_slack(
+    axum::extract::Path(id): axum::extract::Path<String>,
+    axum::extract::State((db, _)): axum::extract::State<(Arc<Database>, Arc<AuthService>)>,
+    axum::Extension(user_id): axum::Extension<String>,
+    axum::Extension(billing): axum::Extension<Option<plans::Billing>>,
+    Json(settings): Json<workspaces::SlackSettings>,
+) -> Response {
+    if settings.webhook_url.as_deref().is_some_and(|url| !slack::is_webhook_url(url)) {
+        return StatusCode::UNPROCESSABLE_ENTITY.into_response();
+    }
+    // Removing the webhook is allowed on any plan, e.g. after a downgrade
+    if settings.webhook_url.is_some()
+        && let Err(e) = plans::require(db.get_pool(), billing.as_ref(), &user_id, plans::Feature::Webhooks).await
+    {
+        return e.into_response();
+    }
+    match workspaces::set_slack_webhook(&db, &user_id, &id, settings.webhook_url.as_deref()).await {
+        Ok(()) => StatusCode::NO_CONTENT.into_response(),
+        Err(err) => StatusCode::from(err).into_response(),
+    }
+}
+
+async fn get_workspace_invites(
+    axum::extract::Path(id): axum::extract::Path<String>,
+    axum::extract::State((db, _)): axum::extract::State<(Arc<Database>, Arc<AuthService>)>,
+    axum::Extension(user_id): axum::Extension<String>,
+) -> Result<Json<Vec<workspaces::Invite>>, StatusCode> {
+    match workspaces::get_invites(db.get_pool(), &user_id, &id).await {
+        Ok(invites) => Ok(Json(invites)),
+        Err(err) => Err(err.into()),
+    }
+}
+
+async fn create_workspace_invite(
+    axum::extract::Path(id): axum::extract::Path<String>,
+    axum::extract::State((db, _)): axum::extract::State<(Arc<Database>, Arc<AuthService>)>,
+    axum::Extension(user_id): axum::Extension<String>,
+    axum::Extension(mailer): axum::Extension<mailer::Mailer>,
+    Json(new_invite): Json<workspaces::NewInvite>,
+) -> Result<(StatusCode, Json<workspaces::Invite>), StatusCode> {
+    let (invite, token) = workspaces::create_invite(&db, &user_id, &id, new_invite).await?;
+    let workspace = match workspaces::get_workspace(db.get_pool(), &user_id, &id).await {
+        Ok(Some(workspace)) => workspace,
+        Ok(None) => return Err(StatusCode::NOT_FOUND),
+        Err(_) => return Err(StatusCode::INTERNAL_SERVER_ERROR),
+    };
+
+    // The invitee may not have an account yet, so the invite goes out in the inviter's language
+    let language = user_settings(&db, &user_id).await?.language;
+    let body = i18n::Message::InviteBody {
+        workspace: &workspace.name,
+        role: &invite.role,
+        link: &format!("{}/?invite={}", mailer::public_url(), token),
+        expires: &invite.expires_at.format("%Y-%m-%d %H:%M UTC").to_string(),
+    }
+    .text(language);
+    let subject = i18n::Message::InviteSubject { workspace: &workspace.name }.text(language);
+    if let Err(e) = mailer.send(&invite.email, &subject, body).await {
+        tracing::warn!(error = %e, "Failed to send workspace invite");
+        // An invite nobody received is useless; let the owner retry
+        let _ = workspaces::revoke_invite(&db, &user_id, &id, &invite.id).await;
+        return Err(StatusCode::BAD_GATEWAY);
+    }
+
+    Ok((StatusCode::CREATED, Json(invite)))
+}
+
+async fn revoke_workspace_invite(
+    axum::extract::Path((id, invite_id)): axum::extract::Path<(String, String)>,
+    axum::extract::State((db, _)): axum::extract::State<(Arc<Database>, Arc<AuthService>)>,
+    axum::Extension(user_id): axum::Extension<String>,
+) -> StatusCode {
+    match workspaces::revoke_invite(&db, &user_id, &id, &invite_id).await {
+        Ok(()) => StatusCode::NO_CONTENT,
+        Err(err) => err.into(),
+    }
+}
+
+async fn accept_invite(
+    axum::extract::State((db, _)): axum::extract::State<(Arc<Database>, Arc<AuthService>)>,
+    axum::Extension(user_id): axum::Extension<String>,
+    Json(accept): Json<workspaces::AcceptInvite>,
+) -> Result<Json<workspaces::Workspace>, StatusCode> {
+    match workspaces::accept_invite(&db, &user_id, &accept.token).await {
+        Ok(workspace) => Ok(Json(workspace)),
+        Err(err) => Err(err.into()),
+    }
+}
+
+#[derive(Deserialize)]
+struct NotificationQuery {
+    #[serde(default)]
+    unread: bool,
+}
+
+async fn get_notifications(
+    axum::extract::State((db, _)): axum::extract::State<(Arc<Database>, Arc<AuthService>)>,
+    axum::Extension(user_id): axum::Extension<String>,
+    axum::extract::Query(query): axum::extract::Query<NotificationQuery>,
+    axum::extract::Query(page): axum::extract::Query<PageQuery>,
+    uri: axum::http::Uri,
+) -> Result<Json<Listing<notifications::Notification>>, StatusCode> {
+    // Unpaginated, this is the 100 most recent, as before pagination
+    let limit = if page.is_paginated() { page.limit() + 1 } else { 100 };
+    let notifications = notifications::get_notifications(db.get_pool(), &user_id, query.unread, page.cursor()?.as_ref(), limit)
+        .await
+        .map_err(|_| StatusCode::INTERNAL_SERVER_ERROR)?;
+
+    if !page.is_paginated() {
+        return Ok(Json(Listing::All(notifications)));
+    }
+    Ok(Json(Listing::Page(
+        Page::new(notifications, page.limit(), |notification| Cursor::new(notification.created_at, &notification.id)).with_links(&uri),
+    )))
+}
+
+async fn mark_notification_read(
+    axum::extract::Path(id): axum::extract::Path<String>,
+    axum::extract::State((db, _)): axum::extract::State<(Arc<Database>, Arc<AuthService>)>,
+    axum::Extension(user_id): axum::Extension<String>,
+) -> StatusCode {
+    match notifications::mark_read(&db, &user_id, &id).await {
+        Ok(true) => StatusCode::NO_CONTENT,
+        Ok(false) => StatusCode::NOT_FOUND,
+        Err(_) => StatusCode::INTERNAL_SERVER_ERROR,
+    }
+}
+
+async fn get_notification_deliveries(
+    axum::extract::Path(id): axum::extract::Path<String>,
+    axum::extract::State((db, _)): axum::extract::State<(Arc<Database>, Arc<AuthService>)>,
+    axum::Extension(user_id): axum::Extension<String>,
+) -> Result<Json<Vec<notify::Delivery>>, StatusCode> {
+    match notify::get_deliveries(db.get_pool(), &user_id, &id).await {
+        Ok(Some(deliveries)) => Ok(Json(deliveries)),
+        Ok(None) => Err(StatusCode::NOT_FOUND),
+        Err(_) => Err(StatusCode::INTERNAL_SERVER_ERROR),
+    }
+}
+
+async fn get_notification_channels(
+    axum::extract::State((db, _)): axum::extract::State<(Arc<Database>, Arc<AuthService>)>,
+    axum::Extension(user_id): axum::Extension<String>,
+    axum::Extension(dispatcher): axum::Extension<notify::Dispatcher>,
+) -> Result<Json<Vec<notify::Channel>>, StatusCode> {
+    match notify::get_channels(db.get_pool(), &dispatcher, &user_id).await {
+        Ok(channels) => Ok(Json(channels)),
+        Err(_) => Err(StatusCode::INTERNAL_SERVER_ERROR),
+    }
+}
+
+async fn update_notification_channel(
+    axum::extract::Path(channel): axum::extract::Path<String>,
+    axum::extract::State((db, _)): axum::extract::State<(Arc<Database>, Arc<AuthService>)>,
+    axum::Extension(user_id): axum::Extension<String>,
+    axum::Extension(dispatcher): axum::Extension<notify::Dispatcher>,
+    axum::Extension(billing): axum::Extension<Option<plans::Billing>>,
+    Json(update): Json<notify::UpdateChannel>,
+) -> Response {
+    // Email is on every plan; the rest post to webhooks. Turning one off is always allowed.
+    if channel != "email"
+        && update.enabled
+        && let Err(e) = plans::require(db.get_pool(), billing.as_ref(), &user_id, plans::Feature::Webhooks).await
+    {
+        return e.into_response();
+    }
+    match notify::update_channel(&db, &dispatcher, &user_id, &channel, update).await {
+        Ok(()) => StatusCode::NO_CONTENT.into_response(),
+        Err(err) => StatusCode::from(err).into_response(),
+    }
+}
+
+async fn delete_notification_channel(
+    axum::extract::Path(channel): axum::extract::Path<String>,
+    axum::extract::State((db, _)): axum::extract::State<(Arc<Database>, Arc<AuthService>)>,
+    axum::Extension(user_id): axum::Extension<String>,
+) -> StatusCode {
+    match notify::delete_channel(&db, &user_id, &channel).await {
+        Ok(true) => StatusCode::NO_CONTENT,
+        Ok(false) => StatusCode::NOT_FOUND,
+        Err(_) => StatusCode::INTERNAL_SERVER_ERROR,
+    }
+}
+
+async fn get_events(
+    axum::extract::State((db, _)): axum::extract::State<(Arc<Database>, Arc<AuthService>)>,
+    axum::Extension(scope): axum::Extension<Scope>,
+    axum::extract::Query(query): axum::extract::Query<events::EventsQuery>,
+) -> Result<Json<events::EventLog>, StatusCode> {
+    events::list(db.get_pool(), &scope, query.since, query.limit()).await.map(Json).map_err(|e| simple_db::error_status(&e))
+}
+
+async fn get_webhooks(
+    axum::extract::State((db, _)): axum::extract::State<(Arc<Database>, Arc<AuthService>)>,
+    axum::Extension(user_id): axum::Extension<String>,
+) -> Result<Json<Vec<webhooks::Webhook>>, StatusCode> {
+    webhooks::list(db.get_pool(), &user_id).await.map(Json).map_err(|e| simple_db::error_status(&e))
+}
+
+async fn create_webhook(
+    axum::extract::State((db, _)): axum::extract::State<(Arc<Database>, Arc<AuthService>)>,
+    axum::Extension(user_id): axum::Extension<String>,
+    axum::Extension(webhooks): axum::Extension<webhooks::Webhooks>,
+    axum::Extension(billing): axum::Extension<Option<plans::Billing>>,
+    Json(new_webhook): Json<webhooks::NewWebhook>,
+) -> Response {
+    if let Err(e) = plans::require(db.get_pool(), billing.as_ref(), &user_id, plans::Feature::Webhooks).await {
+        return e.into_response();
+    }
+    match webhooks.create(&db, &user_id, new_webhook).await {
+        Ok(webhook) => (StatusCode::CREATED, Json(webhook)).into_response(),
+        Err(err) => StatusCode::from(err).into_response(),
+    }
+}
+
+async fn delete_webhook(
+    axum::extract::Path(id): axum::extract::Path<String>,
+    axum::extract::State((db, _)): axum::extract::State<(Arc<Database>, Arc<AuthService>)>,
+    axum::Extension(user_id): axum::Extension<String>,
+) -> StatusCode {
+    match webhooks::delete(&db, &user_id, &id).await {
+        Ok(()) => StatusCode::NO_CONTENT,
+        Err(err) => StatusCode::from(err),
+    }
+}
+
+async fn get_webhook_deliveries(
+    axum::extract::Path(id): axum::extract::Path<String>,
+    axum::extract::State((db, _)): axum::extract::State<(Arc<Database>, Arc<AuthService>)>,
+    axum::Extension(user_id): axum::Extension<String>,
+    axum::extract::Query(page): axum::extract::Query<PageQuery>,
+    uri: axum::http::Uri,
+) -> Result<Json<Listing<webhooks::Delivery>>, StatusCode> {
+    // Unpaginated, this is the 100 most recent, like notifications
+    let limit = if page.is_paginated() { page.limit() + 1 } else { 100 };
+    let deliveries = webhooks::deliveries(db.get_pool(), &user_id, &id, page.cursor()?.as_ref(), limit).await?;
+
+    if !page.is_paginated() {
+        return Ok(Json(Listing::All(deliveries)));
+    }
+    Ok(Json(Listing::Page(
+        Page::new(deliveries, page.limit(), |delivery| Cursor::new(delivery.created_at, &delivery.id)).with_links(&uri),
+    )))
+}
+
+async fn redeliver_webhook(
+    axum::extract::Path((id, delivery_id)): axum::extract::Path<(String, String)>,
+    axum::extract::State((db, _)): axum::extract::State<(Arc<Database>, Arc<AuthService>)>,
+    axum::Extension(user_id): axum::Extension<String>,
+    axum::Extension(webhooks): axum::Extension<webhooks::Webhooks>,
+) -> Result<(StatusCode, Json<webhooks::Delivery>), StatusCode> {
+    let delivery = webhooks.redeliver(&db, &user_id, &id, &delivery_id).await?;
+    Ok((StatusCode::CREATED, Json(delivery)))
+}
+
+async fn get_mentions(
+    axum::extract::State((db, _)): axum::extract::State<(Arc<Database>, Arc<AuthService>)>,
+    axum::Extension(user_id): axum::Extension<String>,
+    axum::extract::Query(page): axum::extract::Query<PageQuery>,
+    uri: axum::http::Uri,
+) -> Result<Json<Listing<mentions::Mention>>, StatusCode> {
+    // Unpaginated, this is the 100 most recent, as before pagination
+    let limit = if page.is_paginated() { page.limit() + 1 } else { 100 };
+    let mentions = mentions::get_mentions(db.get_pool(), &user_id, page.cursor()?.as_ref(), limit)
+        .await
+        .map_err(|_| StatusCode::INTERNAL_SERVER_ERROR)?;
+
+    if !page.is_paginated() {
+        return Ok(Json(Listing::All(mentions)));
+    }
+    Ok(Json(Listing::Page(Page::new(mentions, page.limit(), |mention| Cursor::new(mention.created_at, &mention.todo_id)).with_links(&uri))))
+}
+
+async fn get_settings(
+    axum::extract::State((db, _)): axum::extract::State<(Arc<Database>, Arc<AuthService>)>,
+    axum::Extension(user_id): axum::Extension<String>,
+) -> Result<Json<settings::UserSettings>, StatusCode> {
+    match settings::get_settings(db.get_pool(), &user_id).await {
+        Ok(settings) => Ok(Json(settings)),
+        Err(_) => Err(StatusCode::INTERNAL_SERVER_ERROR),
+    }
+}
+
+async fn update_settings(
+    axum::extract::State((db, _)): axum::extract::State<(Arc<Database>, Arc<AuthService>)>,
+    axum::Extension(user_id): axum::Extension<String>,
+    Json(update): Json<settings::UpdateSettings>,
+) -> Result<Json<settings::UserSettings>, StatusCode> {
+    match settings::update_settings(&db, &user_id, update).await {
+        Ok(settings) => Ok(Json(settings)),
+        Err(err) => Err(err.into()),
+    }
+}
+
+async fn get_inbox(
+    axum::extract::State((db, _)): axum::extract::State<(Arc<Database>, Arc<AuthService>)>,
+    axum::Extension(user_id): axum::Extension<String>,
+    axum::Extension(config): axum::Extension<Option<inbox::InboxConfig>>,
+) -> Result<Json<inbox::Inbox>, StatusCode> {
+    let config = config.ok_or(StatusCode::NOT_FOUND)?;
+    match inbox::get_inbox(&db, &config, &user_id).await {
+        Ok(inbox) => Ok(Json(inbox)),
+        Err(_) => Err(StatusCode::INTERNAL_SERVER_ERROR),
+    }
+}
+
+async fn rotate_inbox(
+    axum::extract::State((db, _)): axum::extract::State<(Arc<Database>, Arc<AuthService>)>,
+    axum::Extension(user_id): axum::Extension<String>,
+    axum::Extension(config): axum::Extension<Option<inbox::InboxConfig>>,
+) -> Result<Json<inbox::Inbox>, StatusCode> {
+    let config = config.ok_or(StatusCode::NOT_FOUND)?;
+    match inbox::rotate_inbox(&db, &config, &user_id).await {
+        Ok(inbox) => Ok(Json(inbox)),
+        Err(_) => Err(StatusCode::INTERNAL_SERVER_ERROR),
+    }
+}
+
+/// Stripe's webhook for subscription changes; `404` until billing is set up.
+async fn stripe_webhook(
+    axum::extract::State((db, _)): axum::extract::State<(Arc<Database>, Arc<AuthService>)>,
+    axum::Extension(billing): axum::Extension<Option<plans::Billing>>,
+    headers: HeaderMap,
+    payload: axum::body::Bytes,
+) -> StatusCode {
+    let Some(billing) = billing else {
+        return StatusCode::NOT_FOUND;
+    };
+    plans::handle_webhook(&db, &billing, &headers, &payload).await
+}
+
+/// Inbound email webhook. Providers post either JSON or a urlencoded form.
+async fn receive_email(
+    axum::extract::Path(secret): axum::extract::Path<String>,
+    axum::extract::State((db, _)): axum::extract::State<(Arc<Database>, Arc<AuthService>)>,
+    axum::Extension(config): axum::Extension<Option<inbox::InboxConfig>>,
+    axum::Extension(hooks): axum::Extension<hooks::Hooks>,
+    axum::Extension(quotas): axum::Extension<quotas::Quotas>,
+    request: axum::extract::Request,
+) -> StatusCode {
+    use axum::extract::FromRequest;
+
+    let Some(config) = config.filter(|config| config.accepts(&secret)) else {
+        return StatusCode::NOT_FOUND;
+    };
+    let is_json = request
+        .headers()
+        .get(header::CONTENT_TYPE)
+        .and_then(|value| value.to_str().ok())
+        .is_some_and(|value| value.starts_with("application/json"));
+    let email = if is_json {
+        Json::<inbox::InboundEmail>::from_request(request, &()).await.map(|Json(email)| email).ok()
+    } else {
+        axum::Form::<inbox::InboundEmail>::from_request(request, &()).await.map(|axum::Form(email)| email).ok()
+    };
+    let Some(email) = email else {
+        return StatusCode::BAD_REQUEST;
+    };
+
+    match inbox::receive(&db, &config, &quotas, email).await {
+        Ok(todo) => {
+            tracing::info!(todo_id = %todo.id, "Created todo from email");
+            if let Some(owner_id) = &todo.user_id {
+                hooks.emit(&db, hooks::Event::TodoCreated { todo: &todo, actor_id: owner_id }).await;
+            }
+            StatusCode::CREATED
+        }
+        Err(err) => err.into(),
+    }
+}
+
+async fn get_gamification(
+    axum::extract::State((db, _)): axum::extract::State<(Arc<Database>, Arc<AuthService>)>,
+    axum::Extension(user_id): axum::Extension<String>,
+) -> Result<Json<gamification::Summary>, StatusCode> {
+    let settings = user_settings(&db, &user_id).await?;
+    let today = chrono::Utc::now().with_timezone(&settings.tz()).date_naive();
+    match gamification::summary(db.get_pool(), &user_id, today).await {
+        Ok(summary) => Ok(Json(summary)),
+        Err(e) => Err(simple_db::error_status(&e)),
+    }
+}
+
+async fn get_achievements(
+    axum::extract::State((db, _)): axum::extract::State<(Arc<Database>, Arc<AuthService>)>,
+    axum::Extension(user_id): axum::Extension<String>,
+) -> Result<Json<Vec<achievements::Status>>, StatusCode> {
+    match achievements::list(db.get_pool(), &user_id).await {
+        Ok(achievements) => Ok(Json(achievements)),
+        Err(e) => Err(simple_db::error_status(&e)),
+    }
+}
+
+async fn get_google_calendar(
+    axum::extract::State((db, _)): axum::extract::State<(Arc<Database>, Arc<AuthService>)>,
+    axum::Extension(user_id): axum::Extension<String>,
+    axum::Extension(google): axum::Extension<Option<google_calendar::GoogleCalendar>>,
+) -> Result<Json<google_calendar::Connection>, StatusCode> {
+    google.ok_or(StatusCode::NOT_FOUND)?;
+    match google_calendar::get_connection(db.get_pool(), &user_id).await {
+        Ok(Some(connection)) => Ok(Json(connection)),
+        Ok(None) => Err(StatusCode::NOT_FOUND),
+        Err(e) => Err(simple_db::error_status(&e)),
+    }
+}
+
+#[derive(Serialize)]
+struct ConnectResponse {
+    /// Google's consent screen, to open in the browser
+    url: String,
+}
+
+async fn connect_google_calendar(
+    axum::extract::State((db, _)): axum::extract::State<(Arc<Database>, Arc<AuthService>)>,
+    axum::Extension(user_id): axum::Extension<String>,
+    axum::Extension(google): axum::Extension<Option<google_calendar::GoogleCalendar>>,
+    axum::Extension(billing): axum::Extension<Option<plans::Billing>>,
+) -> Result<Json<ConnectResponse>, Response> {
+    let google = google.ok_or(StatusCode::NOT_FOUND.into_response())?;
+    plans::require(db.get_pool(), billing.as_ref(), &user_id, plans::Feature::Integrations)
+        .await
+        .map_err(IntoResponse::into_response)?;
+    match google.authorize_url(&db, &user_id).await {
+        Ok(url) => Ok(Json(ConnectResponse { url })),
+        Err(e) => {
+            tracing::error!(error = %e, "Failed to start Google Calendar consent");
+            Err(StatusCode::INTERNAL_SERVER_ERROR.into_response())
+        }
+    }
+}
+
+#[derive(Deserialize)]
+struct GoogleCallback {
+    code: Option<String>,
+    state: Option<String>,
+    /// Set instead of `code` when the user declined
+    error: Option<String>,
+}
+
+/// Where Google's consent screen sends the user back to. Signed in or not,
+/// the `state` says who they are; they land on the web UI either way.
+async fn google_callback(
+    axum::extract::State((db, _)): axum::extract::State<(Arc<Database>, Arc<AuthService>)>,
+    axum::Extension(google): axum::Extension<Option<google_calendar::GoogleCalendar>>,
+    axum::extract::Query(callback): axum::extract::Query<GoogleCallback>,
+) -> Result<Redirect, StatusCode> {
+    let google = google.ok_or(StatusCode::NOT_FOUND)?;
+    let outcome = match (callback.code, callback.state, callback.error) {
+        (Some(code), Some(state), None) => match google.connect(&db, &code, &state).await {
+            Ok(true) => "connected",
+            Ok(false) => "expired",
+            Err(e) => {
+                tracing::warn!(error = %e, "Failed to connect Google Calendar");
+                "failed"
+            }
+        },
+        _ => "denied",
+    };
+    Ok(Redirect::to(&format!("/?google_calendar={}", outcome)))
+}
+
+async fn sync_google_calendar(
+    axum::extract::State((db, _)): axum::extract::State<(Arc<Database>, Arc<AuthService>)>,
+    axum::Extension(user_id): axum::Extension<String>,
+    axum::Extension(google): axum::Extension<Option<google_calendar::GoogleCalendar>>,
+    axum::Extension(hooks): axum::Extension<hooks::Hooks>,
+) -> Result<Json<google_calendar::SyncReport>, StatusCode> {
+    let google = google.ok_or(StatusCode::NOT_FOUND)?;
+    match google.sync_user(&db, &hooks, &user_id).await {
+        Ok(report) => Ok(Json(report)),
+        // Not connected, or no longer
+        Err(google_calendar::SyncError::Unauthorized) => Err(StatusCode::CONFLICT),
+        Err(google_calendar::SyncError::Database(e)) => Err(simple_db::error_status(&e)),
+        Err(_) => Err(StatusCode::BAD_GATEWAY),
+    }
+}
+
+async fn disconnect_google_calendar(
+    axum::extract::State((db, _)): axum::extract::State<(Arc<Database>, Arc<AuthService>)>,
+    axum::Extension(user_id): axum::Extension<String>,
+    axum::Extension(google): axum::Extension<Option<google_calendar::GoogleCalendar>>,
+) -> StatusCode {
+    let Some(google) = google else {
+        return StatusCode::NOT_FOUND;
+    };
+    match google.disconnect(&db, &user_id).await {
+        Ok(true) => StatusCode::NO_CONTENT,
+        Ok(false) => StatusCode::NOT_FOUND,
+        Err(e) => simple_db::error_status(&e),
+    }
+}
+
+/// The `:date` of a My Day route, which can't be a past day in the user's
+/// timezone when planning.
+async fn myday_date(db: &Database, user_id: &str, date: &str, planning: bool) -> Result<chrono::NaiveDate, StatusCode> {
+    let today = chrono::Utc::now().with_timezone(&user_settings(db, user_id).await?.tz()).date_naive();
+    match dates::parse_day(date, today) {
+        Some(date) if planning && date < today => Err(StatusCode::UNPROCESSABLE_ENTITY),
+        Some(date) => Ok(date),
+        None => Err(StatusCode::BAD_REQUEST),
+    }
+}
+
+async fn get_myday(
+    axum::extract::Path(date): axum::extract::Path<String>,
+    axum::extract::State((db, _)): axum::extract::State<(Arc<Database>, Arc<AuthService>)>,
+    axum::Extension(user_id): axum::Extension<String>,
+) -> Result<Json<Vec<myday::PlannedTodo>>, StatusCode> {
+    let date = myday_date(&db, &user_id, &date, false).await?;
+    match myday::get_plan(db.get_pool(), &user_id, date).await {
+        Ok(plan) => Ok(Json(plan)),
+        Err(e) => Err(simple_db::error_status(&e)),
+    }
+}
+
+async fn add_to_myday(
+    axum::extract::Path((date, id)): axum::extract::Path<(String, String)>,
+    axum::extract::State((db, _)): axum::extract::State<(Arc<Database>, Arc<AuthService>)>,
+    axum::Extension(scope): axum::Extension<Scope>,
+) -> StatusCode {
+    let date = match myday_date(&db, &scope.user_id, &date, true).await {
+        Ok(date) => date,
+        Err(status) => return status,
+    };
+    let id = match db.todo_id(&id).await {
+        Ok(id) => id,
+        Err(e) => return simple_db::error_status(&e),
+    };
+    match db.get_todo(&id, &scope).await {
+        Ok(Some(_)) => {}
+        Ok(None) => return StatusCode::NOT_FOUND,
+        Err(e) => return simple_db::error_status(&e),
+    }
+    match myday::add(&db, &scope.user_id, date, &id).await {
+        Ok(true) => StatusCode::CREATED,
+        Ok(false) => StatusCode::OK,
+        Err(e) => simple_db::error_status(&e),
+    }
+}
+
+async fn remove_from_myday(
+    axum::extract::Path((date, id)): axum::extract::Path<(String, String)>,
+    axum::extract::State((db, _)): axum::extract::State<(Arc<Database>, Arc<AuthService>)>,
+    axum::Extension(user_id): axum::Extension<String>,
+) -> StatusCode {
+    let date = match myday_date(&db, &user_id, &date, false).await {
+        Ok(date) => date,
+        Err(status) => return status,
+    };
+    let id = match db.todo_id(&id).await {
+        Ok(id) => id,
+        Err(e) => return simple_db::error_status(&e),
+    };
+    match myday::remove(&db, &user_id, date, &id).await {
+        Ok(true) => StatusCode::NO_CONTENT,
+        Ok(false) => StatusCode::NOT_FOUND,
+        Err(e) => simple_db::error_status(&e),
+    }
+}
+
+async fn get_admin_stats(
+    axum::extract::State((db, _)): axum::extract::State<(Arc<Database>, Arc<AuthService>)>,
+    axum::Extension(scheduler): axum::Extension<jobs::Scheduler>,
+) -> Result<Json<admin_stats::AdminStats>, StatusCode> {
+    match admin_stats::collect(db.get_pool(), &scheduler).await {
+        Ok(stats) => Ok(Json(stats)),
+        Err(e) => Err(simple_db::error_status(&e)),
+    }
+}
+
+async fn get_jobs(
+    axum::extract::State((db, _)): axum::extract::State<(Arc<Database>, Arc<AuthService>)>,
+    axum::Extension(scheduler): axum::Extension<jobs::Scheduler>,
+) -> Result<Json<Vec<jobs::JobInfo>>, StatusCode> {
+    match scheduler.list(db.get_pool()).await {
+        Ok(jobs) => Ok(Json(jobs)),
+        Err(_) => Err(StatusCode::INTERNAL_SERVER_ERROR),
+    }
+}
+
+async fn get_job_runs(
+    axum::extract::Path(name): axum::extract::Path<String>,
+    axum::extract::State((db, _)): axum::extract::State<(Arc<Database>, Arc<AuthService>)>,
+    axum::Extension(scheduler): axum::Extension<jobs::Scheduler>,
+    axum::extract::Query(page): axum::extract::Query<PageQuery>,
+    uri: axum::http::Uri,
+) -> Result<Json<Listing<jobs::JobRun>>, StatusCode> {
+    if !scheduler.has_job(&name) {
+        return Err(StatusCode::NOT_FOUND);
+    }
+    let limit = if page.is_paginated() { page.limit() + 1 } else { 100 };
+    let runs = jobs::list_runs(db.get_pool(), &name, page.cursor()?.as_ref(), limit)
+        .await
+        .map_err(|_| StatusCode::INTERNAL_SERVER_ERROR)?;
+
+    if !page.is_paginated() {
+        return Ok(Json(Listing::All(runs)));
+    }
+    Ok(Json(Listing::Page(Page::new(runs, page.limit(), |run| Cursor::new(run.started_at, &run.id)).with_links(&uri))))
+}
+
+/// Starts a job now; the run continues in the background.
+async fn run_job(
+    axum::extract::Path(name): axum::extract::Path<String>,
+    axum::extract::State((db, _)): axum::extract::State<(Arc<Database>, Arc<AuthService>)>,
+    axum::Extension(scheduler): axum::Extension<jobs::Scheduler>,
+) -> Result<(StatusCode, Json<jobs::JobRun>), StatusCode> {
+    match scheduler.run_now(&db, &name).await {
+        Ok(run) => Ok((StatusCode::ACCEPTED, Json(run))),
+        Err(err) => Err(err.into()),
+    }
+}
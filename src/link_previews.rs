@@ -0,0 +1,288 @@
+use axum::async_trait;
+use chrono::{DateTime, Duration, Utc};
+use reqwest::{header, redirect, Url};
+use serde::{Deserialize, Serialize};
+use sqlx::{QueryBuilder, Row, Sqlite, SqlitePool};
+
+use crate::encryption;
+use crate::hooks::{Event, Hook, HookError};
+use crate::simple_db::{Database, Todo};
+use crate::ssrf;
+
+/// Links previewed per todo; the rest are left alone
+const MAX_LINKS: usize = 3;
+/// Longer URLs aren't fetched
+const MAX_URL_LEN: usize = 2048;
+/// Time for one page, redirects included
+const FETCH_TIMEOUT: std::time::Duration = std::time::Duration::from_secs(5);
+/// Only the start of a page is read; the title is in the head
+const MAX_BODY_BYTES: usize = 256 * 1024;
+const MAX_REDIRECTS: usize = 3;
+/// Titles are cut to this many characters
+const MAX_TITLE_CHARS: usize = 300;
+/// How long a fetched preview, or a failed fetch, is kept before the link is fetched again
+const REFRESH_DAYS: i64 = 7;
+
+/// The title and icon of a page linked from a todo's text.
+#[derive(Clone, Debug, Serialize, Deserialize)]
+pub struct LinkPreview {
+    pub url: String,
+    pub title: Option<String>,
+    pub favicon_url: Option<String>,
+}
+
+/// The `http` and `https` URLs in `text`, in order, without repeats.
+pub fn find_urls(text: &str) -> Vec<String> {
+    let mut urls: Vec<String> = Vec::new();
+    for word in text.split_whitespace() {
+        let start = match (word.find("https://"), word.find("http://")) {
+            (Some(a), Some(b)) => a.min(b),
+            (Some(a), None) | (None, Some(a)) => a,
+            (None, None) => continue,
+        };
+        // Punctuation around a link in a sentence isn't part of it
+        let url = word[start..].trim_end_matches(['.', ',', ';', ':', '!', '?', ')', ']', '>', '"', '\'']);
+        if url.len() <= MAX_URL_LEN && Url::parse(url).is_ok_and(|url| url.host().is_some()) && !urls.iter().any(|u| u == url) {
+            urls.push(url.to_string());
+        }
+    }
+    urls
+}
+
+/// Fills in `links` on each todo with the previews fetched so far for the
+/// URLs in its text.
+pub async fn attach<'a>(pool: &SqlitePool, todos: impl IntoIterator<Item = &'a mut Todo>) -> Result<(), sqlx::Error> {
+    let mut todos: Vec<(&mut Todo, Vec<String>)> = todos
+        .into_iter()
+        .map(|todo| {
+            let mut urls = find_urls(&todo.text);
+            urls.truncate(MAX_LINKS);
+            (todo, urls)
+        })
+        .filter(|(_, urls)| !urls.is_empty())
+        .collect();
+    if todos.is_empty() {
+        return Ok(());
+    }
+
+    let mut query = QueryBuilder::<Sqlite>::new("SELECT url, title, favicon_url FROM link_previews WHERE (title IS NOT NULL OR favicon_url IS NOT NULL) AND url IN (");
+    let mut separated = query.separated(", ");
+    for url in todos.iter().flat_map(|(_, urls)| urls) {
+        separated.push_bind(url.clone());
+    }
+    separated.push_unseparated(")");
+    let previews: Vec<LinkPreview> = query
+        .build()
+        .fetch_all(pool)
+        .await?
+        .iter()
+        .map(|row| LinkPreview { url: row.get("url"), title: row.get("title"), favicon_url: row.get("favicon_url") })
+        .collect();
+
+    for (todo, urls) in &mut todos {
+        let links: Vec<LinkPreview> = urls.iter().filter_map(|url| previews.iter().find(|preview| &preview.url == url).cloned()).collect();
+        todo.links = (!links.is_empty()).then_some(links);
+    }
+    Ok(())
+}
+
+/// Fetches previews of the links in new and edited todos, in the
+/// background. `LINK_PREVIEWS=false` turns it off; so does encryption, since
+/// the links and titles would be stored in the clear.
+///
+/// The server fetching URLs users give it is a way into the network it
+/// runs in, so only `http` and `https` on the default ports are fetched, a
+/// host must resolve to public addresses only, and the address checked is
+/// the one connected to. Redirects are checked the same way, a page gets
+/// 5 seconds and only its first 256 KiB are read.
+#[derive(Clone)]
+pub struct LinkPreviewHook;
+
+impl LinkPreviewHook {
+    pub fn from_env() -> Option<Self> {
+        let enabled = std::env::var("LINK_PREVIEWS").map_or(true, |value| value != "false");
+        (enabled && !encryption::is_enabled()).then_some(LinkPreviewHook)
+    }
+}
+
+#[async_trait]
+impl Hook for LinkPreviewHook {
+    fn name(&self) -> &'static str {
+        "link_previews"
+    }
+
+    async fn handle(&self, db: &Database, event: &Event<'_>) -> Result<(), HookError> {
+        let todo = match *event {
+            Event::TodoCreated { todo, .. } | Event::TodoUpdated { todo, text_changed: true, .. } => todo,
+            _ => return Ok(()),
+        };
+        let mut urls = find_urls(&todo.text);
+        urls.truncate(MAX_LINKS);
+        let mut stale = Vec::new();
+        let fresh_after = Utc::now() - Duration::days(REFRESH_DAYS);
+        for url in urls {
+            let fetched_at: Option<DateTime<Utc>> = sqlx::query_scalar("SELECT fetched_at FROM link_previews WHERE url = ?")
+                .bind(&url)
+                .fetch_optional(db.get_pool())
+                .await?;
+            if fetched_at.is_none_or(|fetched_at| fetched_at < fresh_after) {
+                stale.push(url);
+            }
+        }
+        if stale.is_empty() {
+            return Ok(());
+        }
+
+        let writer = db.writer();
+        tokio::spawn(async move {
+            for url in stale {
+                let (preview, error) = match tokio::time::timeout(FETCH_TIMEOUT, fetch(&url)).await {
+                    Ok(Ok(preview)) => (Some(preview), None),
+                    Ok(Err(e)) => (None, Some(e)),
+                    Err(_) => (None, Some("timed out".to_string())),
+                };
+                if let Some(error) = &error {
+                    tracing::debug!(url, error, "No link preview");
+                }
+                let result = async {
+                    sqlx::query("INSERT INTO link_previews (url, title, favicon_url, error, fetched_at) VALUES (?, ?, ?, ?, ?) ON CONFLICT (url) DO UPDATE SET title = excluded.title, favicon_url = excluded.favicon_url, error = excluded.error, fetched_at = excluded.fetched_at")
+                        .bind(&url)
+                        .bind(preview.as_ref().and_then(|preview| preview.title.as_deref()))
+                        .bind(preview.as_ref().and_then(|preview| preview.favicon_url.as_deref()))
+                        .bind(&error)
+                        .bind(Utc::now())
+                        .execute(&mut *writer.acquire().await?)
+                        .await
+                }
+                .await;
+                if let Err(e) = result {
+                    tracing::warn!(error = %e, "Failed to store link preview");
+                }
+            }
+        });
+        Ok(())
+    }
+}
+
+/// The preview of `url`, following up to `MAX_REDIRECTS` redirects.
+async fn fetch(url: &str) -> Result<LinkPreview, String> {
+    let mut current = Url::parse(url).map_err(|e| e.to_string())?;
+    for _ in 0..=MAX_REDIRECTS {
+        let address = ssrf::public_address(&current).await?;
+        let host = current.host_str().ok_or("no host")?.to_string();
+        // Connect to the address just checked, so a second DNS answer can't point elsewhere
+        let http = reqwest::Client::builder()
+            .redirect(redirect::Policy::none())
+            .no_proxy()
+            .resolve(&host, address)
+            .timeout(FETCH_TIMEOUT)
+            .build()
+            .map_err(|e| e.to_string())?;
+        let mut response = http
+            .get(current.clone())
+            .header(header::ACCEPT, "text/html")
+            .header(header::USER_AGENT, concat!("todo-app/", env!("CARGO_PKG_VERSION"), " (link preview)"))
+            .send()
+            .await
+            .map_err(|e| e.to_string())?;
+
+        if response.status().is_redirection() {
+            let location = response.headers().get(header::LOCATION).and_then(|value| value.to_str().ok()).ok_or("redirect without a location")?;
+            current = current.join(location).map_err(|e| e.to_string())?;
+            continue;
+        }
+        if !response.status().is_success() {
+            return Err(format!("status {}", response.status()));
+        }
+        let is_html = response
+            .headers()
+            .get(header::CONTENT_TYPE)
+            .and_then(|value| value.to_str().ok())
+            .is_some_and(|value| value.starts_with("text/html"));
+        if !is_html {
+            return Err("not an HTML page".to_string());
+        }
+
+        let mut body = Vec::new();
+        while body.len() < MAX_BODY_BYTES
+            && let Some(chunk) = response.chunk().await.map_err(|e| e.to_string())?
+        {
+            body.extend_from_slice(&chunk);
+        }
+        body.truncate(MAX_BODY_BYTES);
+        let html = String::from_utf8_lossy(&body);
+        return Ok(LinkPreview {
+            url: url.to_string(),
+            title: title(&html),
+            favicon_url: Some(favicon(&html, &current)),
+        });
+    }
+    Err("too many redirects".to_string())
+}
+
+/// The text of the page's `<title>`, with whitespace collapsed.
+fn title(html: &str) -> Option<String> {
+    let lower = html.to_ascii_lowercase();
+    let open = lower.find("<title")?;
+    let start = open + lower[open..].find('>')? + 1;
+    let end = start + lower[start..].find("</title")?;
+    let title = decode_entities(&html[start..end]).split_whitespace().collect::<Vec<_>>().join(" ");
+    (!title.is_empty()).then(|| title.chars().take(MAX_TITLE_CHARS).collect())
+}
+
+/// The icon a `<link rel="icon">` points to, or `/favicon.ico` on the
+/// page's host.
+fn favicon(html: &str, page: &Url) -> String {
+    let lower = html.to_ascii_lowercase();
+    let mut rest = 0;
+    while let Some(found) = lower[rest..].find("<link") {
+        let start = rest + found;
+        let Some(length) = lower[start..].find('>') else {
+            break;
+        };
+        let tag = &html[start..start + length];
+        rest = start + length;
+        let is_icon = attribute(tag, "rel").is_some_and(|rel| rel.split_whitespace().any(|rel| rel.eq_ignore_ascii_case("icon")));
+        if is_icon
+            && let Some(href) = attribute(tag, "href")
+            && let Ok(icon) = page.join(&decode_entities(href))
+            && matches!(icon.scheme(), "http" | "https")
+        {
+            return icon.to_string();
+        }
+    }
+    page.join("/favicon.ico").map_or_else(|_| page.to_string(), |icon| icon.to_string())
+}
+
+/// The value of `name="..."` (or single-quoted) in a tag.
+fn attribute<'a>(tag: &'a str, name: &str) -> Option<&'a str> {
+    let lower = tag.to_ascii_lowercase();
+    let mut from = 0;
+    while let Some(found) = lower[from..].find(name) {
+        let at = from + found;
+        from = at + name.len();
+        let preceded = at > 0 && lower.as_bytes()[at - 1].is_ascii_whitespace();
+        let rest = lower[from..].trim_start();
+        if !preceded || !rest.starts_with('=') {
+            continue;
+        }
+        let value_start = tag.len() - rest[1..].trim_start().len();
+        let quote = tag[value_start..].chars().next()?;
+        if quote != '"' && quote != '\'' {
+            continue;
+        }
+        let value = &tag[value_start + 1..];
+        return value.find(quote).map(|end| &value[..end]);
+    }
+    None
+}
+
+fn decode_entities(text: &str) -> String {
+    text.replace("&lt;", "<")
+        .replace("&gt;", ">")
+        .replace("&quot;", "\"")
+        .replace("&#39;", "'")
+        .replace("&#x27;", "'")
+        .replace("&nbsp;", " ")
+        .replace("&amp;", "&")
+}
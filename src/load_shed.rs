@@ -0,0 +1,53 @@
+use axum::{
+    error_handling::HandleErrorLayer,
+    http::{header, StatusCode},
+    response::{IntoResponse, Response},
+    BoxError, Router,
+};
+use tower::{limit::GlobalConcurrencyLimitLayer, load_shed::LoadShedLayer, ServiceBuilder};
+
+/// How many requests a group of routes handles at once: `CONCURRENCY_LIMIT_AUTH`
+/// for the auth endpoints (default 16, since hashing passwords is CPU-bound)
+/// and `CONCURRENCY_LIMIT_TODOS` for the signed-in API (default 128). `0`
+/// lifts a group's limit.
+pub struct ConcurrencyLimits {
+    pub auth: Option<usize>,
+    pub todos: Option<usize>,
+}
+
+impl ConcurrencyLimits {
+    pub fn from_env() -> Self {
+        ConcurrencyLimits {
+            auth: limit_from_env("CONCURRENCY_LIMIT_AUTH", 16),
+            todos: limit_from_env("CONCURRENCY_LIMIT_TODOS", 128),
+        }
+    }
+}
+
+fn limit_from_env(var: &str, default: usize) -> Option<usize> {
+    let limit = std::env::var(var).ok().and_then(|limit| limit.parse().ok()).unwrap_or(default);
+    (limit > 0).then_some(limit)
+}
+
+/// Caps the requests `router`'s routes handle at once at `max`, shared
+/// across all of them. Past that requests get `503` straight away instead
+/// of queueing, so a burst can't pile up behind the single SQLite writer
+/// and slow every other request down with it.
+pub fn limit<S>(router: Router<S>, max: Option<usize>) -> Router<S>
+where
+    S: Clone + Send + Sync + 'static,
+{
+    let Some(max) = max else {
+        return router;
+    };
+    router.route_layer(
+        ServiceBuilder::new()
+            .layer(HandleErrorLayer::new(overloaded))
+            .layer(LoadShedLayer::new())
+            .layer(GlobalConcurrencyLimitLayer::new(max)),
+    )
+}
+
+async fn overloaded(_: BoxError) -> Response {
+    (StatusCode::SERVICE_UNAVAILABLE, [(header::RETRY_AFTER, "1")]).into_response()
+}
@@ -0,0 +1,46 @@
+use axum::extract::Request;
+use std::io::IsTerminal;
+use tower_http::classify::{ServerErrorsAsFailures, SharedClassifier};
+use tower_http::trace::{DefaultOnFailure, DefaultOnResponse, TraceLayer};
+use tower_http::LatencyUnit;
+use tracing::{Level, Span};
+use tracing_subscriber::EnvFilter;
+
+use crate::request_id::request_id;
+
+const DEFAULT_FILTER: &str = "todo_app=info,tower_http=info";
+
+/// Installs the global subscriber. `RUST_LOG` overrides the default filter and
+/// `LOG_FORMAT=json` switches to one JSON object per line for log shippers.
+/// Logs go to stderr so CLI output on stdout stays machine-readable.
+pub fn init() {
+    let filter = EnvFilter::try_from_default_env().unwrap_or_else(|_| EnvFilter::new(DEFAULT_FILTER));
+    let builder = tracing_subscriber::fmt()
+        .with_env_filter(filter)
+        .with_writer(std::io::stderr)
+        .with_ansi(std::io::stderr().is_terminal());
+
+    if std::env::var("LOG_FORMAT").is_ok_and(|format| format == "json") {
+        builder.json().init();
+    } else {
+        builder.init();
+    }
+}
+
+/// Request logging: one span per request with method, path and request id,
+/// and an event with status and latency when the response is sent.
+pub fn trace_layer() -> TraceLayer<SharedClassifier<ServerErrorsAsFailures>, fn(&Request) -> Span> {
+    TraceLayer::new_for_http()
+        .make_span_with(make_span as fn(&Request) -> Span)
+        .on_response(DefaultOnResponse::new().level(Level::INFO).latency_unit(LatencyUnit::Millis))
+        .on_failure(DefaultOnFailure::new().latency_unit(LatencyUnit::Millis))
+}
+
+fn make_span(request: &Request) -> Span {
+    tracing::info_span!(
+        "request",
+        method = %request.method(),
+        uri = %request.uri(),
+        request_id = request_id(request),
+    )
+}
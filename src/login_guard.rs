@@ -0,0 +1,125 @@
+use chrono::Utc;
+use std::time::Duration;
+
+use crate::auth_events::{self, Client};
+use crate::shared_state::SharedState;
+use crate::simple_db::Database;
+
+/// Brute-force protection for `POST /auth/login`. Failed attempts are counted
+/// per username and per client IP in `SharedState`, so every instance sees
+/// them. After `LOGIN_FREE_ATTEMPTS` failures (default 3) each further one
+/// makes the next attempt wait twice as long, from 2 seconds up to a minute.
+/// At `LOGIN_LOCKOUT_THRESHOLD` failures for an account (default 10) or
+/// `LOGIN_IP_LOCKOUT_THRESHOLD` from one IP (default 50, since offices share
+/// addresses) logins are refused for `LOGIN_LOCKOUT_MINUTES` (default 15).
+/// Counts are forgotten that long after the last failure, and an account's
+/// count is reset by a successful login.
+#[derive(Clone)]
+pub struct LoginGuard {
+    shared: SharedState,
+    free_attempts: u64,
+    account_threshold: u64,
+    ip_threshold: u64,
+    lockout: Duration,
+}
+
+fn env_or(name: &str, default: u64) -> u64 {
+    std::env::var(name).ok().and_then(|value| value.parse().ok()).unwrap_or(default)
+}
+
+impl LoginGuard {
+    pub fn from_env(shared: SharedState) -> Self {
+        LoginGuard {
+            shared,
+            free_attempts: env_or("LOGIN_FREE_ATTEMPTS", 3),
+            account_threshold: env_or("LOGIN_LOCKOUT_THRESHOLD", 10).max(1),
+            ip_threshold: env_or("LOGIN_IP_LOCKOUT_THRESHOLD", 50).max(1),
+            lockout: Duration::from_secs(env_or("LOGIN_LOCKOUT_MINUTES", 15).max(1) * 60),
+        }
+    }
+
+    fn account_key(username: &str) -> String {
+        format!("login:user:{}", username.to_lowercase())
+    }
+
+    fn keys(username: &str, ip: Option<&str>) -> Vec<String> {
+        let mut keys = vec![Self::account_key(username)];
+        keys.extend(ip.map(|ip| format!("login:ip:{}", ip)));
+        keys
+    }
+
+    /// When the next attempt under `key` is allowed (Unix seconds), if
+    /// there's a wait. The lockout has a key of its own, so a shorter delay
+    /// written by a concurrent failure can't cut it short.
+    async fn blocked_until(&self, key: &str) -> Option<i64> {
+        let mut until = None;
+        for suffix in ["delayed_until", "locked_until"] {
+            match self.shared.get(&format!("{}:{}", key, suffix)).await {
+                Ok(value) => until = until.max(value.and_then(|value| value.parse().ok())),
+                // Fail open like the rate limiter: an unavailable store shouldn't lock everyone out
+                Err(e) => tracing::warn!(error = %e, "Login attempt check failed"),
+            }
+        }
+        until
+    }
+
+    /// How long the caller must wait before trying to log in as `username`
+    /// from `ip`, if at all.
+    pub async fn wait(&self, username: &str, ip: Option<&str>) -> Option<Duration> {
+        let now = Utc::now().timestamp();
+        let mut wait = 0;
+        for key in Self::keys(username, ip) {
+            if let Some(until) = self.blocked_until(&key).await {
+                wait = wait.max(until - now);
+            }
+        }
+        (wait > 0).then(|| Duration::from_secs(wait as u64))
+    }
+
+    /// Counts a failed attempt, recording an `account_locked` or `ip_locked`
+    /// event in the auth log when a threshold is reached. The count is an
+    /// atomic increment, so failures sent in parallel each count.
+    pub async fn record_failure(&self, db: &Database, username: &str, user_id: Option<&str>, client: &Client) {
+        let ip = client.ip.as_deref();
+        let now = Utc::now().timestamp();
+        for key in Self::keys(username, ip) {
+            let (threshold, event) = if key.starts_with("login:ip:") {
+                (self.ip_threshold, "ip_locked")
+            } else {
+                (self.account_threshold, "account_locked")
+            };
+            let failures = match self.shared.count(&format!("{}:failures", key), self.lockout).await {
+                Ok(failures) => failures,
+                Err(e) => {
+                    tracing::warn!(error = %e, "Failed to record login attempt");
+                    continue;
+                }
+            };
+            let (suffix, delay) = if failures >= threshold {
+                ("locked_until", self.lockout.as_secs())
+            } else if failures > self.free_attempts {
+                ("delayed_until", (1u64 << (failures - self.free_attempts).min(6)).min(60))
+            } else {
+                continue;
+            };
+
+            let until = (now + delay as i64).to_string();
+            if let Err(e) = self.shared.set(&format!("{}:{}", key, suffix), until, self.lockout).await {
+                tracing::warn!(error = %e, "Failed to record login attempt");
+            }
+            if failures == threshold {
+                tracing::warn!(username, ip, event, "Login locked after repeated failures");
+                auth_events::record(db, event, user_id, client).await;
+            }
+        }
+    }
+
+    pub async fn record_success(&self, username: &str) {
+        let key = Self::account_key(username);
+        for suffix in ["failures", "delayed_until", "locked_until"] {
+            if let Err(e) = self.shared.delete(&format!("{}:{}", key, suffix)).await {
+                tracing::warn!(error = %e, "Failed to reset login attempts");
+            }
+        }
+    }
+}
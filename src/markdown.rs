@@ -0,0 +1,57 @@
+use ammonia::Builder;
+use pulldown_cmark::{html, Options, Parser};
+
+use crate::simple_db::Todo;
+
+/// Renders Markdown to HTML that is safe to insert into a page: raw HTML in
+/// the source is kept only if it passes ammonia's allow-list, so `<script>`,
+/// event handlers and `javascript:` links are removed.
+pub fn render(markdown: &str) -> String {
+    let options = Options::ENABLE_TABLES | Options::ENABLE_STRIKETHROUGH | Options::ENABLE_TASKLISTS;
+    let mut unsafe_html = String::new();
+    html::push_html(&mut unsafe_html, Parser::new_ext(markdown, options));
+
+    // Task list items ("- [x] done") render as disabled checkboxes; no other inputs
+    Builder::default()
+        .add_tags(["input"])
+        .add_tag_attributes("input", ["type", "checked", "disabled"])
+        .attribute_filter(|element, attribute, value| match (element, attribute) {
+            ("input", "type") if value != "checkbox" => None,
+            _ => Some(value.into()),
+        })
+        .clean(&unsafe_html)
+        .to_string()
+}
+
+/// Fills in `notes_html` for todos that have notes.
+pub fn render_notes<'a>(todos: impl IntoIterator<Item = &'a mut Todo>) {
+    for todo in todos {
+        todo.notes_html = todo.notes.as_deref().map(render);
+    }
+}
+
+/// `markdown` with every task list item unticked ("- [x] done" becomes
+/// "- [ ] done"), for copying a checklist to start over.
+pub fn uncheck_tasks(markdown: &str) -> String {
+    markdown
+        .split_inclusive('\n')
+        .map(|line| {
+            let item = line.trim_start();
+            let indent = &line[..line.len() - item.len()];
+            let marker_len = ["- ", "* ", "+ "]
+                .iter()
+                .find(|marker| item.starts_with(**marker))
+                .map(|marker| marker.len())
+                .or_else(|| {
+                    let digits = item.bytes().take_while(u8::is_ascii_digit).count();
+                    (digits > 0 && item[digits..].starts_with(". ")).then_some(digits + 2)
+                });
+            match marker_len {
+                Some(len) if item[len..].starts_with("[x]") || item[len..].starts_with("[X]") => {
+                    format!("{}{}[ ]{}", indent, &item[..len], &item[len + 3..])
+                }
+                _ => line.to_string(),
+            }
+        })
+        .collect()
+}
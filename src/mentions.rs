@@ -0,0 +1,123 @@
+use axum::async_trait;
+use chrono::{DateTime, Utc};
+use serde::Serialize;
+use sqlx::{Row, SqlitePool};
+
+use crate::encryption;
+use crate::hooks::{Event, Hook, HookError};
+use crate::i18n::Message;
+use crate::notifications;
+use crate::notify::Dispatcher;
+use crate::pagination::Cursor;
+use crate::simple_db::{Database, Todo};
+
+#[derive(Debug, Serialize)]
+pub struct Mention {
+    pub todo_id: String,
+    pub todo_text: String,
+    pub mentioned_by: String,
+    pub created_at: DateTime<Utc>,
+}
+
+/// Usernames mentioned as `@name` at the start of a word, without trailing
+/// punctuation ("thanks @sam!" mentions `sam`). Duplicates are dropped.
+pub fn parse(text: &str) -> Vec<String> {
+    let mut usernames: Vec<String> = Vec::new();
+
+    for word in text.split_whitespace() {
+        let Some(name) = word.strip_prefix('@') else {
+            continue;
+        };
+        let name = name.trim_end_matches(|c: char| !(c.is_alphanumeric() || c == '_'));
+        if !name.is_empty() && !usernames.iter().any(|u| u == name) {
+            usernames.push(name.to_string());
+        }
+    }
+
+    usernames
+}
+
+/// Records the workspace members mentioned in `todo`'s text and notifies the
+/// ones mentioned for the first time. Mentions in personal todos, of
+/// non-members and of the author are ignored.
+pub async fn record(db: &Database, dispatcher: &Dispatcher, todo: &Todo, author_id: &str) -> Result<(), sqlx::Error> {
+    let Some(workspace_id) = &todo.workspace_id else {
+        return Ok(());
+    };
+
+    let now = Utc::now();
+    for username in parse(&todo.text) {
+        let Some(row) = sqlx::query("SELECT u.id FROM users u JOIN workspace_members m ON m.user_id = u.id WHERE m.workspace_id = ? AND u.username = ?")
+            .bind(workspace_id)
+            .bind(&username)
+            .fetch_optional(db.get_pool())
+            .await?
+        else {
+            continue;
+        };
+        let user_id: String = row.get("id");
+        if user_id == author_id {
+            continue;
+        }
+
+        let inserted = sqlx::query("INSERT OR IGNORE INTO mentions (todo_id, user_id, mentioned_by, created_at) VALUES (?, ?, ?, ?)")
+            .bind(&todo.id)
+            .bind(&user_id)
+            .bind(author_id)
+            .bind(now)
+            .execute(&mut *db.write().await?)
+            .await?;
+        if inserted.rows_affected() > 0 {
+            let message = Message::Mentioned { todo: &todo.text };
+            notifications::notify(db, dispatcher, &user_id, "mentioned", message, Some(&todo.id)).await?;
+        }
+    }
+
+    Ok(())
+}
+
+/// Records mentions when a todo is created or its text edited.
+pub struct MentionHook {
+    pub dispatcher: Dispatcher,
+}
+
+#[async_trait]
+impl Hook for MentionHook {
+    fn name(&self) -> &'static str {
+        "mentions"
+    }
+
+    async fn handle(&self, db: &Database, event: &Event<'_>) -> Result<(), HookError> {
+        match *event {
+            Event::TodoCreated { todo, actor_id } | Event::TodoUpdated { todo, actor_id, text_changed: true } => {
+                Ok(record(db, &self.dispatcher, todo, actor_id).await?)
+            }
+            _ => Ok(()),
+        }
+    }
+}
+
+/// Todos `user_id` was mentioned in after `after`, newest first, from
+/// workspaces they still belong to; at most `limit`. Cursors use the todo id.
+pub async fn get_mentions(pool: &SqlitePool, user_id: &str, after: Option<&Cursor>, limit: u32) -> Result<Vec<Mention>, sqlx::Error> {
+    let rows = sqlx::query("SELECT m.todo_id, t.text, u.username AS mentioned_by, m.created_at FROM mentions m JOIN todos t ON t.id = m.todo_id JOIN users u ON u.id = m.mentioned_by WHERE m.user_id = ?1 AND t.workspace_id IN (SELECT workspace_id FROM workspace_members WHERE user_id = m.user_id) AND (?2 IS NULL OR m.created_at < ?2 OR (m.created_at = ?2 AND m.todo_id < ?3)) ORDER BY m.created_at DESC, m.todo_id DESC LIMIT ?4")
+        .bind(user_id)
+        .bind(after.map(|after| after.created_at))
+        .bind(after.map(|after| &after.id))
+        .bind(limit)
+        .fetch_all(pool)
+        .await?;
+
+    Ok(rows
+        .into_iter()
+        .map(|row| {
+            let todo_id: String = row.get("todo_id");
+            Mention {
+                todo_text: encryption::decrypt(&todo_id, "text", row.get("text")),
+                todo_id,
+                mentioned_by: row.get("mentioned_by"),
+                created_at: row.get("created_at"),
+            }
+        })
+        .collect())
+}
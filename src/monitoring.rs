@@ -0,0 +1,141 @@
+#[cfg(feature = "metrics")]
+use axum::{
+    extract::{MatchedPath, Request, State},
+    http::{header, HeaderMap, StatusCode},
+    middleware::Next,
+    response::{IntoResponse, Response},
+    routing::get,
+    Router,
+};
+#[cfg(feature = "metrics")]
+use metrics_exporter_prometheus::{Matcher, PrometheusBuilder, PrometheusHandle};
+#[cfg(feature = "metrics")]
+use sqlx::{Row, SqlitePool};
+#[cfg(feature = "metrics")]
+use std::sync::OnceLock;
+#[cfg(feature = "metrics")]
+use std::time::Instant;
+
+#[cfg(feature = "metrics")]
+const LATENCY_BUCKETS: &[f64] = &[0.001, 0.005, 0.01, 0.025, 0.05, 0.1, 0.25, 0.5, 1.0, 2.5, 5.0, 10.0];
+
+#[cfg(feature = "metrics")]
+#[derive(Clone)]
+pub struct MetricsState {
+    handle: PrometheusHandle,
+    pool: SqlitePool,
+    token: Option<String>,
+}
+
+/// The global Prometheus recorder, installed by the first app built in this
+/// process; apps built after it (in tests) share it.
+#[cfg(feature = "metrics")]
+static RECORDER: OnceLock<PrometheusHandle> = OnceLock::new();
+
+/// Installs the global Prometheus recorder and returns the `/metrics` route.
+/// When `METRICS_TOKEN` is set, scrapers must send it as a bearer token.
+#[cfg(feature = "metrics")]
+pub fn router(pool: SqlitePool) -> Router {
+    let handle = RECORDER
+        .get_or_init(|| {
+            PrometheusBuilder::new()
+                .set_buckets_for_metric(Matcher::Suffix("duration_seconds".to_string()), LATENCY_BUCKETS)
+                .expect("latency buckets are not empty")
+                .install_recorder()
+                .expect("Failed to install Prometheus recorder")
+        })
+        .clone();
+    let token = std::env::var("METRICS_TOKEN").ok().filter(|token| !token.is_empty());
+
+    Router::new()
+        .route("/metrics", get(render))
+        .with_state(MetricsState { handle, pool, token })
+}
+
+#[cfg(feature = "metrics")]
+async fn render(State(state): State<MetricsState>, headers: HeaderMap) -> Response {
+    if let Some(token) = &state.token {
+        let bearer = headers
+            .get(header::AUTHORIZATION)
+            .and_then(|value| value.to_str().ok())
+            .and_then(|value| value.strip_prefix("Bearer "));
+        if bearer != Some(token.as_str()) {
+            return StatusCode::UNAUTHORIZED.into_response();
+        }
+    }
+
+    // Totals are cheap COUNT(*)s, so refresh them on every scrape
+    if let Err(e) = record_totals(&state.pool).await {
+        tracing::warn!(error = %e, "Failed to count users and todos for metrics");
+    }
+
+    state.handle.render().into_response()
+}
+
+#[cfg(feature = "metrics")]
+async fn record_totals(pool: &SqlitePool) -> Result<(), sqlx::Error> {
+    let users: i64 = sqlx::query("SELECT COUNT(*) FROM users").fetch_one(pool).await?.get(0);
+    let row = sqlx::query("SELECT COUNT(*) AS total, COALESCE(SUM(completed), 0) AS completed FROM todos")
+        .fetch_one(pool)
+        .await?;
+    let total: i64 = row.get("total");
+    let completed: i64 = row.get("completed");
+
+    metrics::gauge!("users_total").set(users as f64);
+    metrics::gauge!("todos_total", "completed" => "true").set(completed as f64);
+    metrics::gauge!("todos_total", "completed" => "false").set((total - completed) as f64);
+    Ok(())
+}
+
+/// Counts requests and records their latency, labelled by route template
+/// (`/todos/:id`, not the concrete path) so ids don't explode cardinality.
+#[cfg(feature = "metrics")]
+pub async fn track_requests(request: Request, next: Next) -> Response {
+    let route = request
+        .extensions()
+        .get::<MatchedPath>()
+        .map(|path| path.as_str().to_string())
+        .unwrap_or_else(|| "unmatched".to_string());
+    let method = request.method().to_string();
+    let start = Instant::now();
+
+    let response = next.run(request).await;
+
+    let labels = [("method", method), ("route", route), ("status", response.status().as_u16().to_string())];
+    metrics::counter!("http_requests_total", &labels).increment(1);
+    metrics::histogram!("http_request_duration_seconds", &labels).record(start.elapsed().as_secs_f64());
+    response
+}
+
+/// Records how long a database operation took when dropped:
+/// `let _timer = QueryTimer::start("get_todos");`
+#[cfg(feature = "metrics")]
+pub struct QueryTimer {
+    query: &'static str,
+    start: Instant,
+}
+
+#[cfg(feature = "metrics")]
+impl QueryTimer {
+    pub fn start(query: &'static str) -> Self {
+        QueryTimer { query, start: Instant::now() }
+    }
+}
+
+/// Without the `metrics` feature there is nowhere to record to.
+#[cfg(not(feature = "metrics"))]
+pub struct QueryTimer;
+
+#[cfg(not(feature = "metrics"))]
+impl QueryTimer {
+    pub fn start(_query: &'static str) -> Self {
+        QueryTimer
+    }
+}
+
+#[cfg(feature = "metrics")]
+impl Drop for QueryTimer {
+    fn drop(&mut self) {
+        metrics::histogram!("db_query_duration_seconds", "query" => self.query).record(self.start.elapsed().as_secs_f64());
+    }
+}
@@ -0,0 +1,161 @@
+use axum::async_trait;
+use chrono::{DateTime, Duration, NaiveDate, Utc};
+use serde::Serialize;
+use sqlx::{Connection, Row, SqlitePool};
+
+use crate::jobs::{Job, JobError, Trigger};
+use crate::settings;
+use crate::simple_db::{retry, Database, Todo, TODO_COLUMNS};
+
+/// A todo on a day's plan.
+#[derive(Debug, Serialize)]
+pub struct PlannedTodo {
+    #[serde(flatten)]
+    pub todo: Todo,
+    /// The day it was first planned for; earlier than the plan's date if it
+    /// was rolled over
+    pub planned_on: NaiveDate,
+    pub added_at: DateTime<Utc>,
+}
+
+/// One row of a user's plans, for the data export.
+#[derive(Debug, Serialize)]
+pub struct PlanEntry {
+    pub todo_id: String,
+    pub date: NaiveDate,
+    pub planned_on: NaiveDate,
+    pub added_at: DateTime<Utc>,
+}
+
+/// The user's plan for `date`, open todos first, each group in the order
+/// they were added. Todos the user can no longer see (e.g. in a workspace
+/// they left) are left out.
+pub async fn get_plan(pool: &SqlitePool, user_id: &str, date: NaiveDate) -> Result<Vec<PlannedTodo>, sqlx::Error> {
+    let rows = sqlx::query(&format!("SELECT {}, m.planned_on, m.added_at FROM todos JOIN (SELECT todo_id, planned_on, added_at FROM myday WHERE user_id = ?1 AND local_date = ?2) m ON m.todo_id = todos.id WHERE (todos.workspace_id IS NULL AND todos.user_id = ?1) OR todos.workspace_id IN (SELECT workspace_id FROM workspace_members WHERE user_id = ?1) ORDER BY todos.completed, m.added_at", TODO_COLUMNS))
+        .bind(user_id)
+        .bind(date)
+        .fetch_all(pool)
+        .await?;
+
+    Ok(rows
+        .iter()
+        .map(|row| PlannedTodo {
+            todo: Todo::from_row(row),
+            planned_on: row.get("planned_on"),
+            added_at: row.get("added_at"),
+        })
+        .collect())
+}
+
+/// Puts a todo on the user's plan for `date`; `false` if it already was.
+/// The caller checks the user may see the todo.
+pub async fn add(db: &Database, user_id: &str, date: NaiveDate, todo_id: &str) -> Result<bool, sqlx::Error> {
+    let result = retry(|| async {
+        sqlx::query("INSERT OR IGNORE INTO myday (user_id, todo_id, local_date, planned_on, added_at) VALUES (?1, ?2, ?3, ?3, ?4)")
+            .bind(user_id)
+            .bind(todo_id)
+            .bind(date)
+            .bind(Utc::now())
+            .execute(&mut *db.write().await?)
+            .await
+    })
+    .await?;
+    Ok(result.rows_affected() > 0)
+}
+
+/// Takes a todo off the user's plan for `date`; `false` if it wasn't on it.
+pub async fn remove(db: &Database, user_id: &str, date: NaiveDate, todo_id: &str) -> Result<bool, sqlx::Error> {
+    let result = retry(|| async {
+        sqlx::query("DELETE FROM myday WHERE user_id = ? AND local_date = ? AND todo_id = ?")
+            .bind(user_id)
+            .bind(date)
+            .bind(todo_id)
+            .execute(&mut *db.write().await?)
+            .await
+    })
+    .await?;
+    Ok(result.rows_affected() > 0)
+}
+
+/// Every plan entry of the user's, oldest day first, for the data export.
+pub async fn get_entries(pool: &SqlitePool, user_id: &str) -> Result<Vec<PlanEntry>, sqlx::Error> {
+    let rows = sqlx::query("SELECT todo_id, local_date, planned_on, added_at FROM myday WHERE user_id = ? ORDER BY local_date, added_at")
+        .bind(user_id)
+        .fetch_all(pool)
+        .await?;
+
+    Ok(rows
+        .iter()
+        .map(|row| PlanEntry {
+            todo_id: row.get("todo_id"),
+            date: row.get("local_date"),
+            planned_on: row.get("planned_on"),
+            added_at: row.get("added_at"),
+        })
+        .collect())
+}
+
+/// Moves the user's unfinished todos from earlier days' plans onto `today`'s,
+/// keeping the day each was first planned for. Completed todos stay where
+/// they were done. Returns how many entries were moved.
+pub async fn roll_over(db: &Database, user_id: &str, today: NaiveDate) -> Result<u64, sqlx::Error> {
+    retry(|| async {
+        let mut conn = db.write().await?;
+        let mut tx = conn.begin().await?;
+        // A todo may be on several past days, and on today's plan already
+        sqlx::query("INSERT OR IGNORE INTO myday (user_id, todo_id, local_date, planned_on, added_at) SELECT m.user_id, m.todo_id, ?2, MIN(m.planned_on), MIN(m.added_at) FROM myday m JOIN todos t ON t.id = m.todo_id WHERE m.user_id = ?1 AND m.local_date < ?2 AND t.completed = FALSE GROUP BY m.todo_id")
+            .bind(user_id)
+            .bind(today)
+            .execute(&mut *tx)
+            .await?;
+        let moved = sqlx::query("DELETE FROM myday WHERE user_id = ? AND local_date < ? AND todo_id IN (SELECT id FROM todos WHERE completed = FALSE)")
+            .bind(user_id)
+            .bind(today)
+            .execute(&mut *tx)
+            .await?
+            .rows_affected();
+        tx.commit().await?;
+        Ok(moved)
+    })
+    .await
+}
+
+/// Rolls unfinished todos over to today's plan once a user's day has
+/// ended. Runs every 15 minutes since midnight comes at a different time in
+/// each timezone.
+pub struct RolloverJob;
+
+#[async_trait]
+impl Job for RolloverJob {
+    fn name(&self) -> &'static str {
+        "myday_rollover"
+    }
+
+    fn default_schedule(&self) -> Option<&'static str> {
+        Some("*/15 * * * *")
+    }
+
+    async fn run(&self, db: &Database, _trigger: Trigger) -> Result<String, JobError> {
+        let now = Utc::now();
+        // No timezone is more than a day ahead of UTC, so this finds everyone
+        // who may have unfinished todos on a past day
+        let user_ids: Vec<String> = sqlx::query("SELECT DISTINCT m.user_id FROM myday m JOIN todos t ON t.id = m.todo_id WHERE t.completed = FALSE AND m.local_date < ?")
+            .bind(now.date_naive() + Duration::days(1))
+            .fetch_all(db.get_pool())
+            .await?
+            .iter()
+            .map(|row| row.get("user_id"))
+            .collect();
+
+        let (mut moved, mut users) = (0, 0);
+        for user_id in &user_ids {
+            let today = now.with_timezone(&settings::get_settings(db.get_pool(), user_id).await?.tz()).date_naive();
+            let count = roll_over(db, user_id, today).await?;
+            if count > 0 {
+                moved += count;
+                users += 1;
+            }
+        }
+        Ok(format!("Rolled over {} todos for {} users", moved, users))
+    }
+}
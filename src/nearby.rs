@@ -0,0 +1,99 @@
+use serde::{Deserialize, Serialize};
+use sqlx::SqlitePool;
+
+use crate::simple_db::{Scope, Todo, TODO_COLUMNS};
+
+const EARTH_RADIUS_METERS: f64 = 6_371_000.0;
+
+/// Length of one degree of latitude, and of longitude at the equator.
+const METERS_PER_DEGREE: f64 = 111_320.0;
+
+const DEFAULT_RADIUS_METERS: f64 = 500.0;
+const MAX_RADIUS_METERS: f64 = 50_000.0;
+
+/// `?lat=&lng=&radius=`, the radius in meters (default 500, at most 50 km).
+#[derive(Debug, Deserialize)]
+pub struct NearbyQuery {
+    pub lat: f64,
+    pub lng: f64,
+    pub radius: Option<f64>,
+}
+
+impl NearbyQuery {
+    fn radius(&self) -> f64 {
+        self.radius.unwrap_or(DEFAULT_RADIUS_METERS).clamp(1.0, MAX_RADIUS_METERS)
+    }
+}
+
+/// An open todo within the radius, with how far away it is.
+#[derive(Debug, Serialize)]
+pub struct NearbyTodo {
+    #[serde(flatten)]
+    pub todo: Todo,
+    pub distance_meters: f64,
+}
+
+/// Coordinates are given both or neither, and on the globe.
+pub fn valid(latitude: Option<f64>, longitude: Option<f64>) -> bool {
+    match (latitude, longitude) {
+        (Some(latitude), Some(longitude)) => (-90.0..=90.0).contains(&latitude) && (-180.0..=180.0).contains(&longitude),
+        (None, None) => true,
+        _ => false,
+    }
+}
+
+/// Great-circle distance by the haversine formula.
+fn distance_meters((lat1, lng1): (f64, f64), (lat2, lng2): (f64, f64)) -> f64 {
+    let (phi1, phi2) = (lat1.to_radians(), lat2.to_radians());
+    let a = ((phi2 - phi1) / 2.0).sin().powi(2) + phi1.cos() * phi2.cos() * ((lng2 - lng1).to_radians() / 2.0).sin().powi(2);
+    2.0 * EARTH_RADIUS_METERS * a.sqrt().asin()
+}
+
+/// Open todos in `scope` within the query's radius, nearest first. The
+/// database narrows them down to a bounding box around the point; the exact
+/// distance then drops the box's corners.
+pub async fn find(pool: &SqlitePool, scope: &Scope, query: &NearbyQuery) -> Result<Vec<NearbyTodo>, sqlx::Error> {
+    let radius = query.radius();
+    let lat_delta = radius / METERS_PER_DEGREE;
+    let lng_delta = radius / (METERS_PER_DEGREE * query.lat.to_radians().cos().max(f64::EPSILON));
+    let (condition, value) = scope.condition();
+
+    let mut sql = format!(
+        "SELECT {} FROM todos WHERE {}? AND completed = FALSE AND latitude BETWEEN ? AND ?",
+        TODO_COLUMNS, condition
+    );
+    let (west, east) = (query.lng - lng_delta, query.lng + lng_delta);
+    // Near the poles the box spans every longitude; across the antimeridian it wraps
+    let lng_bounds = if lng_delta >= 180.0 {
+        None
+    } else if west < -180.0 {
+        sql.push_str(" AND (longitude >= ? OR longitude <= ?)");
+        Some((west + 360.0, east))
+    } else if east > 180.0 {
+        sql.push_str(" AND (longitude >= ? OR longitude <= ?)");
+        Some((west, east - 360.0))
+    } else {
+        sql.push_str(" AND longitude BETWEEN ? AND ?");
+        Some((west, east))
+    };
+
+    let mut rows = sqlx::query(&sql)
+        .bind(value)
+        .bind(query.lat - lat_delta)
+        .bind(query.lat + lat_delta);
+    if let Some((first, second)) = lng_bounds {
+        rows = rows.bind(first).bind(second);
+    }
+    let rows = rows.fetch_all(pool).await?;
+
+    let mut nearby: Vec<NearbyTodo> = rows
+        .iter()
+        .map(Todo::from_row)
+        .filter_map(|todo| {
+            let distance = distance_meters((query.lat, query.lng), (todo.latitude?, todo.longitude?));
+            (distance <= radius).then(|| NearbyTodo { todo, distance_meters: distance.round() })
+        })
+        .collect();
+    nearby.sort_by(|a, b| a.distance_meters.total_cmp(&b.distance_meters));
+    Ok(nearby)
+}
@@ -0,0 +1,112 @@
+use axum::async_trait;
+use chrono::{DateTime, Utc};
+use serde::Serialize;
+use sqlx::{sqlite::SqliteRow, Row, SqlitePool};
+use uuid::Uuid;
+
+use crate::encryption;
+use crate::hooks::{Event, Hook, HookError};
+use crate::i18n::Message;
+use crate::notify::{Dispatcher, Note};
+use crate::pagination::Cursor;
+use crate::settings;
+use crate::simple_db::Database;
+
+/// Something a user should know about, e.g. a todo assigned to them. Kept in
+/// the database for `GET /notifications` and also sent through the user's
+/// notification channels.
+#[derive(Debug, Serialize)]
+pub struct Notification {
+    pub id: String,
+    /// What happened, e.g. `assigned`
+    pub kind: String,
+    pub message: String,
+    pub todo_id: Option<String>,
+    pub created_at: DateTime<Utc>,
+    pub read_at: Option<DateTime<Utc>>,
+}
+
+impl Notification {
+    fn from_row(row: &SqliteRow) -> Self {
+        let id: String = row.get("id");
+        Notification {
+            message: encryption::decrypt(&id, "notifications", row.get("message")),
+            id,
+            kind: row.get("kind"),
+            todo_id: row.get("todo_id"),
+            created_at: row.get("created_at"),
+            read_at: row.get("read_at"),
+        }
+    }
+}
+
+/// Stores a notification for `user_id`, worded in their language, and sends
+/// it through the channels they've enabled (see `notify::Dispatcher`). The
+/// message quotes the todo's text, so it's encrypted like it.
+pub async fn notify(db: &Database, dispatcher: &Dispatcher, user_id: &str, kind: &str, message: Message<'_>, todo_id: Option<&str>) -> Result<(), sqlx::Error> {
+    let settings = settings::get_settings(db.get_pool(), user_id).await?;
+    let note = Note {
+        id: Uuid::new_v4().to_string(),
+        kind: kind.to_string(),
+        text: message.text(settings.language),
+    };
+    sqlx::query("INSERT INTO notifications (id, user_id, kind, message, todo_id, created_at) VALUES (?, ?, ?, ?, ?, ?)")
+        .bind(&note.id)
+        .bind(user_id)
+        .bind(kind)
+        .bind(encryption::encrypt(&note.id, "notifications", &note.text))
+        .bind(todo_id)
+        .bind(Utc::now())
+        .execute(&mut *db.write().await?)
+        .await?;
+
+    dispatcher.dispatch(db, user_id, settings, note).await
+}
+
+/// Notifies the new assignee of a todo, unless they assigned it to themselves.
+pub struct AssignmentHook {
+    pub dispatcher: Dispatcher,
+}
+
+#[async_trait]
+impl Hook for AssignmentHook {
+    fn name(&self) -> &'static str {
+        "assignment_notifications"
+    }
+
+    async fn handle(&self, db: &Database, event: &Event<'_>) -> Result<(), HookError> {
+        let Event::TodoAssigned { todo, actor_id } = *event else {
+            return Ok(());
+        };
+        let Some(assignee_id) = todo.assignee_id.as_deref().filter(|assignee_id| *assignee_id != actor_id) else {
+            return Ok(());
+        };
+        let message = Message::Assigned { todo: &todo.text };
+        Ok(notify(db, &self.dispatcher, assignee_id, "assigned", message, Some(&todo.id)).await?)
+    }
+}
+
+/// The user's notifications after `after`, newest first; at most `limit`.
+pub async fn get_notifications(pool: &SqlitePool, user_id: &str, unread_only: bool, after: Option<&Cursor>, limit: u32) -> Result<Vec<Notification>, sqlx::Error> {
+    let rows = sqlx::query("SELECT id, kind, message, todo_id, created_at, read_at FROM notifications WHERE user_id = ?1 AND (read_at IS NULL OR NOT ?2) AND (?3 IS NULL OR created_at < ?3 OR (created_at = ?3 AND id < ?4)) ORDER BY created_at DESC, id DESC LIMIT ?5")
+        .bind(user_id)
+        .bind(unread_only)
+        .bind(after.map(|after| after.created_at))
+        .bind(after.map(|after| &after.id))
+        .bind(limit)
+        .fetch_all(pool)
+        .await?;
+
+    Ok(rows.iter().map(Notification::from_row).collect())
+}
+
+pub async fn mark_read(db: &Database, user_id: &str, id: &str) -> Result<bool, sqlx::Error> {
+    let result = sqlx::query("UPDATE notifications SET read_at = COALESCE(read_at, ?) WHERE id = ? AND user_id = ?")
+        .bind(Utc::now())
+        .bind(id)
+        .bind(user_id)
+        .execute(&mut *db.write().await?)
+        .await?;
+
+    Ok(result.rows_affected() > 0)
+}
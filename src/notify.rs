@@ -0,0 +1,462 @@
+use axum::async_trait;
+use chrono::{DateTime, Utc};
+use serde::{Deserialize, Serialize};
+use serde_json::json;
+use sqlx::{sqlite::SqliteRow, Row, SqlitePool};
+use std::sync::Arc;
+use std::time::Duration;
+
+use crate::mailer::{self, Mailer};
+use crate::settings::{self, UserSettings};
+use crate::simple_db::Database;
+use crate::slack;
+use crate::writer::Writer;
+
+/// A notification on its way out of the app.
+#[derive(Clone, Debug)]
+pub struct Note {
+    /// The `notifications` row it was stored as
+    pub id: String,
+    /// What happened, e.g. `assigned`
+    pub kind: String,
+    /// Already in the recipient's language
+    pub text: String,
+}
+
+/// Who a notification goes to and how to reach them.
+#[derive(Clone, Debug)]
+pub struct Recipient {
+    pub user_id: String,
+    pub email: Option<String>,
+    pub settings: UserSettings,
+    /// Enabled channels from `notification_channels`, with their addresses
+    pub addresses: Vec<(String, String)>,
+}
+
+impl Recipient {
+    pub fn address(&self, channel: &str) -> Option<&str> {
+        self.addresses.iter().find(|(name, _)| name == channel).map(|(_, address)| address.as_str())
+    }
+}
+
+#[derive(Debug)]
+pub struct NotifyError(String);
+
+impl std::fmt::Display for NotifyError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.write_str(&self.0)
+    }
+}
+
+impl std::error::Error for NotifyError {}
+
+/// A way of reaching users outside the app: email, a chat service, push.
+/// Each is registered with the `Dispatcher` on its own, so adding one
+/// doesn't touch the code that raises notifications.
+#[async_trait]
+pub trait Notifier: Send + Sync {
+    /// Stable name, used in channel preferences and delivery records
+    fn channel(&self) -> &'static str;
+
+    /// Whether `address` looks deliverable on this channel, checked when a
+    /// user sets it. Channels without per-user addresses accept none.
+    fn accepts_address(&self, address: &str) -> bool;
+
+    /// Whether `recipient` wants (and can get) notifications this way.
+    fn wants(&self, recipient: &Recipient) -> bool {
+        recipient.address(self.channel()).is_some()
+    }
+
+    async fn send(&self, recipient: &Recipient, note: &Note) -> Result<(), NotifyError>;
+}
+
+/// Sends each notification through every registered channel the recipient
+/// has enabled, in the background, and records how each delivery went in
+/// `notification_deliveries`.
+#[derive(Clone, Default)]
+pub struct Dispatcher {
+    notifiers: Vec<Arc<dyn Notifier>>,
+}
+
+impl Dispatcher {
+    pub fn register(mut self, notifier: impl Notifier + 'static) -> Self {
+        self.notifiers.push(Arc::new(notifier));
+        self
+    }
+
+    /// Email always; Telegram when `TELEGRAM_BOT_TOKEN` is set (and the
+    /// `telegram` feature is enabled); Slack through each user's own
+    /// incoming webhook.
+    pub fn from_env(mailer: Mailer) -> Self {
+        let dispatcher = Dispatcher::default().register(EmailNotifier { mailer }).register(SlackWebhookNotifier::new());
+        #[cfg(feature = "telegram")]
+        if let Some(telegram) = TelegramNotifier::from_env() {
+            return dispatcher.register(telegram);
+        }
+        dispatcher
+    }
+
+    pub fn channels(&self) -> impl Iterator<Item = &'static str> + '_ {
+        self.notifiers.iter().map(|notifier| notifier.channel())
+    }
+
+    pub fn notifier(&self, channel: &str) -> Option<&dyn Notifier> {
+        self.notifiers.iter().find(|notifier| notifier.channel() == channel).map(|notifier| notifier.as_ref())
+    }
+
+    /// Fans `note` out to `user_id`'s channels. Returns once the deliveries
+    /// are recorded as pending; sending happens in the background so a slow
+    /// provider doesn't hold up the request that caused the notification.
+    pub async fn dispatch(&self, db: &Database, user_id: &str, settings: UserSettings, note: Note) -> Result<(), sqlx::Error> {
+        // Deactivated accounts keep their notifications but aren't contacted
+        let Some(row) = sqlx::query("SELECT email FROM users WHERE id = ? AND disabled_at IS NULL")
+            .bind(user_id)
+            .fetch_optional(db.get_pool())
+            .await?
+        else {
+            return Ok(());
+        };
+        let email = row.get("email");
+        let addresses = sqlx::query("SELECT channel, address FROM notification_channels WHERE user_id = ? AND enabled")
+            .bind(user_id)
+            .fetch_all(db.get_pool())
+            .await?
+            .iter()
+            .map(|row| (row.get("channel"), row.get("address")))
+            .collect();
+        let recipient = Arc::new(Recipient { user_id: user_id.to_string(), email, settings, addresses });
+        let note = Arc::new(note);
+
+        for notifier in self.notifiers.iter().filter(|notifier| notifier.wants(&recipient)) {
+            let writer = db.writer();
+            record_delivery(&writer, &note.id, notifier.channel(), "pending", None).await?;
+
+            let (notifier, recipient, note) = (notifier.clone(), recipient.clone(), note.clone());
+            tokio::spawn(async move {
+                let channel = notifier.channel();
+                let result = notifier.send(&recipient, &note).await;
+                if let Err(e) = &result {
+                    tracing::warn!(channel, kind = %note.kind, user_id = %recipient.user_id, error = %e, "Failed to deliver notification");
+                }
+                let (status, error) = match result {
+                    Ok(()) => ("sent", None),
+                    Err(e) => ("failed", Some(e.to_string())),
+                };
+                if let Err(e) = record_delivery(&writer, &note.id, channel, status, error.as_deref()).await {
+                    tracing::warn!(channel, error = %e, "Failed to record notification delivery");
+                }
+            });
+        }
+        Ok(())
+    }
+}
+
+async fn record_delivery(writer: &Writer, notification_id: &str, channel: &str, status: &str, error: Option<&str>) -> Result<(), sqlx::Error> {
+    sqlx::query("INSERT INTO notification_deliveries (notification_id, channel, status, error, updated_at) VALUES (?, ?, ?, ?, ?) ON CONFLICT (notification_id, channel) DO UPDATE SET status = excluded.status, error = excluded.error, updated_at = excluded.updated_at")
+        .bind(notification_id)
+        .bind(channel)
+        .bind(status)
+        .bind(error)
+        .bind(Utc::now())
+        .execute(&mut *writer.acquire().await?)
+        .await?;
+    Ok(())
+}
+
+/// How sending a notification through one channel went.
+#[derive(Debug, Serialize)]
+pub struct Delivery {
+    pub channel: String,
+    /// `pending`, `sent` or `failed`
+    pub status: String,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub error: Option<String>,
+    pub updated_at: DateTime<Utc>,
+}
+
+/// Deliveries of one of the user's notifications; `None` if it isn't theirs.
+pub async fn get_deliveries(pool: &SqlitePool, user_id: &str, notification_id: &str) -> Result<Option<Vec<Delivery>>, sqlx::Error> {
+    let owned = sqlx::query("SELECT 1 FROM notifications WHERE id = ? AND user_id = ?")
+        .bind(notification_id)
+        .bind(user_id)
+        .fetch_optional(pool)
+        .await?;
+    if owned.is_none() {
+        return Ok(None);
+    }
+
+    let rows = sqlx::query("SELECT channel, status, error, updated_at FROM notification_deliveries WHERE notification_id = ? ORDER BY channel")
+        .bind(notification_id)
+        .fetch_all(pool)
+        .await?;
+    Ok(Some(
+        rows.iter()
+            .map(|row| Delivery {
+                channel: row.get("channel"),
+                status: row.get("status"),
+                error: row.get("error"),
+                updated_at: row.get("updated_at"),
+            })
+            .collect(),
+    ))
+}
+
+/// A user's preference for one channel, as listed by `GET /notifications/channels`.
+#[derive(Debug, Serialize)]
+pub struct Channel {
+    pub channel: String,
+    pub enabled: bool,
+    pub address: Option<String>,
+}
+
+impl Channel {
+    fn from_row(row: &SqliteRow) -> Self {
+        Channel {
+            channel: row.get("channel"),
+            enabled: row.get("enabled"),
+            address: row.get("address"),
+        }
+    }
+}
+
+/// Body of `PUT /notifications/channels/:channel`.
+#[derive(Debug, Deserialize)]
+pub struct UpdateChannel {
+    pub enabled: bool,
+    /// Where to send: a Telegram chat id, a Slack webhook URL. Required the
+    /// first time; not accepted for email, which goes to the account address.
+    pub address: Option<String>,
+}
+
+#[derive(Debug)]
+pub enum ChannelError {
+    UnknownChannel,
+    InvalidAddress,
+    DatabaseError,
+}
+
+impl From<ChannelError> for axum::http::StatusCode {
+    fn from(error: ChannelError) -> Self {
+        match error {
+            ChannelError::UnknownChannel => axum::http::StatusCode::NOT_FOUND,
+            ChannelError::InvalidAddress => axum::http::StatusCode::UNPROCESSABLE_ENTITY,
+            ChannelError::DatabaseError => axum::http::StatusCode::INTERNAL_SERVER_ERROR,
+        }
+    }
+}
+
+impl From<sqlx::Error> for ChannelError {
+    fn from(_: sqlx::Error) -> Self {
+        ChannelError::DatabaseError
+    }
+}
+
+/// Every registered channel with the user's preference. Email is on unless
+/// turned off with the `email_notifications` setting.
+pub async fn get_channels(pool: &SqlitePool, dispatcher: &Dispatcher, user_id: &str) -> Result<Vec<Channel>, sqlx::Error> {
+    let stored: Vec<Channel> = sqlx::query("SELECT channel, enabled, address FROM notification_channels WHERE user_id = ?")
+        .bind(user_id)
+        .fetch_all(pool)
+        .await?
+        .iter()
+        .map(Channel::from_row)
+        .collect();
+    let email_notifications = settings::get_settings(pool, user_id).await?.email_notifications;
+    let email: Option<String> = sqlx::query("SELECT email FROM users WHERE id = ?")
+        .bind(user_id)
+        .fetch_optional(pool)
+        .await?
+        .and_then(|row| row.get("email"));
+
+    Ok(dispatcher
+        .channels()
+        .map(|channel| match channel {
+            EMAIL => Channel { channel: channel.to_string(), enabled: email_notifications, address: email.clone() },
+            _ => stored
+                .iter()
+                .find(|stored| stored.channel == channel)
+                .map(|stored| Channel { channel: channel.to_string(), enabled: stored.enabled, address: stored.address.clone() })
+                .unwrap_or(Channel { channel: channel.to_string(), enabled: false, address: None }),
+        })
+        .collect())
+}
+
+pub async fn update_channel(
+    db: &Database,
+    dispatcher: &Dispatcher,
+    user_id: &str,
+    channel: &str,
+    update: UpdateChannel,
+) -> Result<(), ChannelError> {
+    let notifier = dispatcher.notifier(channel).ok_or(ChannelError::UnknownChannel)?;
+    if let Some(address) = &update.address
+        && !notifier.accepts_address(address)
+    {
+        return Err(ChannelError::InvalidAddress);
+    }
+
+    if channel == EMAIL {
+        let update = settings::UpdateSettings {
+            email_notifications: Some(update.enabled),
+            timezone: None,
+            date_format: None,
+            default_priority: None,
+            default_view: None,
+            theme: None,
+            daily_digest: None,
+            weekly_report: None,
+            language: None,
+            leaderboard: None,
+        };
+        settings::update_settings(db, user_id, update).await.map_err(|_| ChannelError::DatabaseError)?;
+        return Ok(());
+    }
+
+    let result = match &update.address {
+        Some(address) => sqlx::query("INSERT INTO notification_channels (user_id, channel, address, enabled, updated_at) VALUES (?, ?, ?, ?, ?) ON CONFLICT (user_id, channel) DO UPDATE SET address = excluded.address, enabled = excluded.enabled, updated_at = excluded.updated_at")
+            .bind(user_id)
+            .bind(channel)
+            .bind(address)
+            .bind(update.enabled)
+            .bind(Utc::now())
+            .execute(&mut *db.write().await?)
+            .await?,
+        None => sqlx::query("UPDATE notification_channels SET enabled = ?, updated_at = ? WHERE user_id = ? AND channel = ?")
+            .bind(update.enabled)
+            .bind(Utc::now())
+            .bind(user_id)
+            .bind(channel)
+            .execute(&mut *db.write().await?)
+            .await?,
+    };
+    // Enabling a channel we have no address for can't work
+    if result.rows_affected() == 0 && update.enabled {
+        return Err(ChannelError::InvalidAddress);
+    }
+    Ok(())
+}
+
+pub async fn delete_channel(db: &Database, user_id: &str, channel: &str) -> Result<bool, sqlx::Error> {
+    let result = sqlx::query("DELETE FROM notification_channels WHERE user_id = ? AND channel = ?")
+        .bind(user_id)
+        .bind(channel)
+        .execute(&mut *db.write().await?)
+        .await?;
+    Ok(result.rows_affected() > 0)
+}
+
+const EMAIL: &str = "email";
+
+/// To the account's email address, unless `email_notifications` is off.
+pub struct EmailNotifier {
+    mailer: Mailer,
+}
+
+#[async_trait]
+impl Notifier for EmailNotifier {
+    fn channel(&self) -> &'static str {
+        EMAIL
+    }
+
+    fn accepts_address(&self, _address: &str) -> bool {
+        false
+    }
+
+    fn wants(&self, recipient: &Recipient) -> bool {
+        recipient.settings.email_notifications && recipient.email.is_some()
+    }
+
+    async fn send(&self, recipient: &Recipient, note: &Note) -> Result<(), NotifyError> {
+        let email = recipient.email.as_deref().ok_or_else(|| NotifyError("no email address".to_string()))?;
+        let body = format!("{}\n\n{}\n", note.text, mailer::public_url());
+        self.mailer
+            .send(email, &note.text, body)
+            .await
+            .map_err(|e| NotifyError(e.to_string()))
+    }
+}
+
+fn http_client() -> reqwest::Client {
+    reqwest::Client::builder()
+        .timeout(Duration::from_secs(10))
+        .build()
+        .expect("Failed to build HTTP client")
+}
+
+/// Messages from the bot in `TELEGRAM_BOT_TOKEN` to a chat id the user got
+/// by messaging the bot (or a `@channel` the bot posts in).
+#[cfg(feature = "telegram")]
+pub struct TelegramNotifier {
+    http: reqwest::Client,
+    token: String,
+}
+
+#[cfg(feature = "telegram")]
+impl TelegramNotifier {
+    pub fn from_env() -> Option<Self> {
+        let token = std::env::var("TELEGRAM_BOT_TOKEN").ok().filter(|token| !token.is_empty())?;
+        Some(TelegramNotifier { http: http_client(), token })
+    }
+}
+
+#[cfg(feature = "telegram")]
+#[async_trait]
+impl Notifier for TelegramNotifier {
+    fn channel(&self) -> &'static str {
+        "telegram"
+    }
+
+    fn accepts_address(&self, address: &str) -> bool {
+        address.parse::<i64>().is_ok() || (address.starts_with('@') && address.len() > 1)
+    }
+
+    async fn send(&self, recipient: &Recipient, note: &Note) -> Result<(), NotifyError> {
+        let chat_id = recipient.address(self.channel()).ok_or_else(|| NotifyError("no chat id".to_string()))?;
+        self.http
+            .post(format!("https://api.telegram.org/bot{}/sendMessage", self.token))
+            .header("Content-Type", "application/json")
+            .body(json!({ "chat_id": chat_id, "text": note.text }).to_string())
+            .send()
+            .await
+            .and_then(|response| response.error_for_status())
+            // reqwest errors include the URL, which holds the bot token
+            .map_err(|e| NotifyError(e.without_url().to_string()))?;
+        Ok(())
+    }
+}
+
+/// To a Slack incoming webhook of the user's own, e.g. for a DM channel.
+/// Workspace-wide announcements go through `slack::SlackNotifier` instead.
+pub struct SlackWebhookNotifier {
+    http: reqwest::Client,
+}
+
+impl SlackWebhookNotifier {
+    pub fn new() -> Self {
+        SlackWebhookNotifier { http: http_client() }
+    }
+}
+
+#[async_trait]
+impl Notifier for SlackWebhookNotifier {
+    fn channel(&self) -> &'static str {
+        "slack"
+    }
+
+    fn accepts_address(&self, address: &str) -> bool {
+        slack::is_webhook_url(address)
+    }
+
+    async fn send(&self, recipient: &Recipient, note: &Note) -> Result<(), NotifyError> {
+        let webhook_url = recipient.address(self.channel()).ok_or_else(|| NotifyError("no webhook".to_string()))?;
+        self.http
+            .post(webhook_url)
+            .header("Content-Type", "application/json")
+            .body(json!({ "text": slack::escape(&note.text) }).to_string())
+            .send()
+            .await
+            .and_then(|response| response.error_for_status())
+            .map_err(|e| NotifyError(e.without_url().to_string()))?;
+        Ok(())
+    }
+}
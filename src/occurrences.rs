@@ -0,0 +1,70 @@
+use axum::async_trait;
+use chrono::{DateTime, Utc};
+use serde::Serialize;
+use sqlx::{Row, SqlitePool};
+use uuid::Uuid;
+
+use crate::hooks::{Event, Hook, HookError};
+use crate::simple_db::Database;
+
+/// One completion of a todo, from `GET /todos/:id/occurrences`. A todo
+/// completed, reopened and completed again has one occurrence per
+/// completion that stuck, each with the due date it had then.
+#[derive(Debug, Serialize)]
+pub struct Occurrence {
+    pub due_date: Option<DateTime<Utc>>,
+    pub completed_at: DateTime<Utc>,
+    /// `null` once that user's account is deleted
+    pub completed_by: Option<String>,
+}
+
+/// Records an occurrence as a todo is completed and takes the latest one
+/// back when it's reopened.
+pub struct OccurrenceHook;
+
+#[async_trait]
+impl Hook for OccurrenceHook {
+    fn name(&self) -> &'static str {
+        "occurrences"
+    }
+
+    async fn handle(&self, db: &Database, event: &Event<'_>) -> Result<(), HookError> {
+        match *event {
+            Event::TodoCompleted { todo, actor_id } => {
+                sqlx::query("INSERT INTO todo_occurrences (id, todo_id, due_date, completed_at, completed_by) VALUES (?, ?, ?, ?, ?)")
+                    .bind(Uuid::new_v4().to_string())
+                    .bind(&todo.id)
+                    .bind(todo.due_date)
+                    .bind(todo.completed_at.unwrap_or_else(Utc::now))
+                    .bind(actor_id)
+                    .execute(&mut *db.write().await?)
+                    .await?;
+            }
+            Event::TodoReopened { todo, .. } => {
+                sqlx::query("DELETE FROM todo_occurrences WHERE id = (SELECT id FROM todo_occurrences WHERE todo_id = ? ORDER BY completed_at DESC LIMIT 1)")
+                    .bind(&todo.id)
+                    .execute(&mut *db.write().await?)
+                    .await?;
+            }
+            _ => {}
+        }
+        Ok(())
+    }
+}
+
+/// A todo's occurrences, latest first.
+pub async fn get_occurrences(pool: &SqlitePool, todo_id: &str) -> Result<Vec<Occurrence>, sqlx::Error> {
+    let rows = sqlx::query("SELECT due_date, completed_at, completed_by FROM todo_occurrences WHERE todo_id = ? ORDER BY completed_at DESC")
+        .bind(todo_id)
+        .fetch_all(pool)
+        .await?;
+
+    Ok(rows
+        .iter()
+        .map(|row| Occurrence {
+            due_date: row.get("due_date"),
+            completed_at: row.get("completed_at"),
+            completed_by: row.get("completed_by"),
+        })
+        .collect())
+}
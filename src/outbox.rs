@@ -0,0 +1,155 @@
+use chrono::{Duration, Utc};
+use serde::{Deserialize, Serialize};
+use sqlx::{Row, SqliteConnection};
+use std::sync::Arc;
+use tokio::sync::Notify;
+
+use crate::encryption;
+use crate::events;
+use crate::hooks::{Event, Hooks};
+use crate::simple_db::{self, Database, Todo};
+
+/// How many events one pass of the relay hands out.
+const BATCH: i64 = 50;
+/// How often the relay looks for events when nothing woke it, e.g. those
+/// written by another instance or left behind by a crash.
+const POLL_INTERVAL: std::time::Duration = std::time::Duration::from_secs(5);
+/// How long a claimed event is left to the relay that claimed it; after
+/// that it's assumed to have died mid-delivery and the event is handed out
+/// again.
+const CLAIM_SECONDS: i64 = 300;
+
+/// `hooks::Event` with its data owned, as stored in the outbox.
+#[derive(Serialize, Deserialize)]
+#[serde(tag = "event", rename_all = "snake_case")]
+enum Stored {
+    TodoCreated { todo: Todo, actor_id: String },
+    TodoUpdated { todo: Todo, actor_id: String, text_changed: bool },
+    TodoCompleted { todo: Todo, actor_id: String },
+    TodoReopened { todo: Todo, actor_id: String },
+    TodoAssigned { todo: Todo, actor_id: String },
+    UserRegistered { user_id: String, username: String },
+}
+
+impl Stored {
+    fn from_event(event: &Event<'_>) -> Self {
+        match *event {
+            Event::TodoCreated { todo, actor_id } => Stored::TodoCreated { todo: todo.clone(), actor_id: actor_id.to_string() },
+            Event::TodoUpdated { todo, actor_id, text_changed } => Stored::TodoUpdated { todo: todo.clone(), actor_id: actor_id.to_string(), text_changed },
+            Event::TodoCompleted { todo, actor_id } => Stored::TodoCompleted { todo: todo.clone(), actor_id: actor_id.to_string() },
+            Event::TodoReopened { todo, actor_id } => Stored::TodoReopened { todo: todo.clone(), actor_id: actor_id.to_string() },
+            Event::TodoAssigned { todo, actor_id } => Stored::TodoAssigned { todo: todo.clone(), actor_id: actor_id.to_string() },
+            Event::UserRegistered { user_id, username } => Stored::UserRegistered { user_id: user_id.to_string(), username: username.to_string() },
+        }
+    }
+
+    fn event(&self) -> Event<'_> {
+        match self {
+            Stored::TodoCreated { todo, actor_id } => Event::TodoCreated { todo, actor_id },
+            Stored::TodoUpdated { todo, actor_id, text_changed } => Event::TodoUpdated { todo, actor_id, text_changed: *text_changed },
+            Stored::TodoCompleted { todo, actor_id } => Event::TodoCompleted { todo, actor_id },
+            Stored::TodoReopened { todo, actor_id } => Event::TodoReopened { todo, actor_id },
+            Stored::TodoAssigned { todo, actor_id } => Event::TodoAssigned { todo, actor_id },
+            Stored::UserRegistered { user_id, username } => Event::UserRegistered { user_id, username },
+        }
+    }
+}
+
+/// The todo or user an event is about.
+fn subject_id<'a>(event: &Event<'a>) -> &'a str {
+    match *event {
+        Event::TodoCreated { todo, .. }
+        | Event::TodoUpdated { todo, .. }
+        | Event::TodoCompleted { todo, .. }
+        | Event::TodoReopened { todo, .. }
+        | Event::TodoAssigned { todo, .. } => &todo.id,
+        Event::UserRegistered { user_id, .. } => user_id,
+    }
+}
+
+/// Adds `event` to the outbox and to the event log (`events::append`). Call
+/// it on the transaction that makes the change, so the event is stored if
+/// and only if the change is.
+///
+/// The event carries the todo as it was, text and notes included, so it's
+/// encrypted like them when [encryption at rest](crate::encryption) is on.
+pub async fn record(conn: &mut SqliteConnection, event: &Event<'_>) -> Result<(), sqlx::Error> {
+    let subject_id = subject_id(event);
+    let payload = serde_json::to_string(&Stored::from_event(event)).map_err(|e| sqlx::Error::Protocol(e.to_string()))?;
+    sqlx::query("INSERT INTO outbox (event, subject_id, payload, created_at) VALUES (?, ?, ?, ?)")
+        .bind(event.kind())
+        .bind(subject_id)
+        .bind(encryption::encrypt(subject_id, "outbox", &payload))
+        .bind(Utc::now())
+        .execute(&mut *conn)
+        .await?;
+    events::append(conn, event).await
+}
+
+/// Hands the events in the outbox to the hooks that reach outside the
+/// process (webhooks and notifiers), in the background and in the order
+/// they were recorded.
+///
+/// Delivery is at least once: an event is marked as dispatched only after
+/// every hook has had it, so one in flight when the process stopped is
+/// handed out again. A hook that fails is logged, as with `Hooks::emit`,
+/// and not retried; webhooks and notifications keep their own delivery
+/// logs for that.
+pub struct Relay {
+    hooks: Hooks,
+}
+
+impl Relay {
+    pub fn new(hooks: Hooks) -> Self {
+        Relay { hooks }
+    }
+
+    /// Runs until the process exits, woken by `Database` whenever a write
+    /// recorded events.
+    pub fn start(self, db: Arc<Database>) {
+        let wake: Arc<Notify> = db.outbox_signal();
+        tokio::spawn(async move {
+            loop {
+                match self.dispatch(&db).await {
+                    // A full batch may have left more behind
+                    Ok(dispatched) if dispatched as i64 == BATCH => continue,
+                    Ok(_) => {}
+                    Err(e) => tracing::warn!(error = %e, "Failed to dispatch outbox events"),
+                }
+                let _ = tokio::time::timeout(POLL_INTERVAL, wake.notified()).await;
+            }
+        });
+    }
+
+    /// Claims a batch of events, hands each to the hooks and marks it as
+    /// dispatched; returns how many there were.
+    async fn dispatch(&self, db: &Database) -> Result<usize, sqlx::Error> {
+        let now = Utc::now();
+        // Claimed in one statement, so relays on other instances can't take the same events
+        let mut rows = simple_db::retry(|| async {
+            sqlx::query("UPDATE outbox SET claimed_at = ?1 WHERE id IN (SELECT id FROM outbox WHERE dispatched_at IS NULL AND (claimed_at IS NULL OR claimed_at < ?2) ORDER BY id LIMIT ?3) RETURNING id, subject_id, payload")
+                .bind(now)
+                .bind(now - Duration::seconds(CLAIM_SECONDS))
+                .bind(BATCH)
+                .fetch_all(&mut *db.write().await?)
+                .await
+        })
+        .await?;
+        rows.sort_by_key(|row| row.get::<i64, _>("id"));
+
+        for row in &rows {
+            let id: i64 = row.get("id");
+            let payload = encryption::decrypt(row.get("subject_id"), "outbox", row.get("payload"));
+            match serde_json::from_str::<Stored>(&payload) {
+                Ok(stored) => self.hooks.emit_recorded(db, &id.to_string(), stored.event()).await,
+                // Nothing to hand out, and trying again won't change that
+                Err(e) => tracing::error!(outbox_id = id, error = %e, "Unreadable outbox event; skipping it"),
+            }
+            simple_db::retry(|| async {
+                sqlx::query("UPDATE outbox SET dispatched_at = ? WHERE id = ?").bind(Utc::now()).bind(id).execute(&mut *db.write().await?).await
+            })
+            .await?;
+        }
+        Ok(rows.len())
+    }
+}
@@ -0,0 +1,126 @@
+use base64::{engine::general_purpose::URL_SAFE_NO_PAD, Engine};
+use chrono::{DateTime, SecondsFormat, Utc};
+use axum::http::Uri;
+use serde::{Deserialize, Serialize};
+
+use crate::hypermedia::{self, Link, Links};
+
+const DEFAULT_LIMIT: u32 = 50;
+const MAX_LIMIT: u32 = 200;
+
+/// Position after the last row of a page. Lists are ordered newest first by
+/// `(created_at, id)`, so the next page is a range scan starting here rather
+/// than an `OFFSET` that reads and discards every earlier row.
+#[derive(Clone, Debug)]
+pub struct Cursor {
+    pub created_at: DateTime<Utc>,
+    pub id: String,
+}
+
+impl Cursor {
+    pub fn new(created_at: DateTime<Utc>, id: &str) -> Self {
+        Cursor { created_at, id: id.to_string() }
+    }
+
+    /// Opaque to clients, so the format can change without breaking them.
+    fn encode(&self) -> String {
+        URL_SAFE_NO_PAD.encode(format!("{}|{}", self.created_at.to_rfc3339_opts(SecondsFormat::Nanos, true), self.id))
+    }
+
+    fn decode(cursor: &str) -> Option<Self> {
+        let decoded = String::from_utf8(URL_SAFE_NO_PAD.decode(cursor).ok()?).ok()?;
+        let (created_at, id) = decoded.split_once('|')?;
+        Some(Cursor {
+            created_at: DateTime::parse_from_rfc3339(created_at).ok()?.with_timezone(&Utc),
+            id: id.to_string(),
+        })
+    }
+}
+
+/// `?limit=50&after=<next_cursor>`. Without either, list endpoints return
+/// everything as a plain array, as they did before pagination.
+#[derive(Debug, Default, Deserialize)]
+pub struct PageQuery {
+    pub after: Option<String>,
+    pub limit: Option<u32>,
+}
+
+impl PageQuery {
+    pub fn is_paginated(&self) -> bool {
+        self.after.is_some() || self.limit.is_some()
+    }
+
+    /// 1 to 200, default 50.
+    pub fn limit(&self) -> u32 {
+        self.limit.unwrap_or(DEFAULT_LIMIT).clamp(1, MAX_LIMIT)
+    }
+
+    /// `Err` for a cursor this server didn't hand out.
+    pub fn cursor(&self) -> Result<Option<Cursor>, axum::http::StatusCode> {
+        match &self.after {
+            Some(after) => Cursor::decode(after).map(Some).ok_or(axum::http::StatusCode::BAD_REQUEST),
+            None => Ok(None),
+        }
+    }
+}
+
+#[derive(Debug, Serialize)]
+pub struct Page<T> {
+    pub items: Vec<T>,
+    /// Pass as `after` for the next page; `null` on the last page
+    pub next_cursor: Option<String>,
+    /// `self`, `first` and (unless this is the last page) `next`, once
+    /// `with_links` has been called
+    #[serde(rename = "_links", skip_serializing_if = "Option::is_none")]
+    pub links: Option<Links>,
+}
+
+impl<T> Page<T> {
+    /// `items` as fetched with `LIMIT limit + 1`: the extra row only tells
+    /// whether there is a next page and is dropped.
+    pub fn new(mut items: Vec<T>, limit: u32, cursor: impl Fn(&T) -> Cursor) -> Self {
+        let next_cursor = if items.len() > limit as usize {
+            items.truncate(limit as usize);
+            items.last().map(|last| cursor(last).encode())
+        } else {
+            None
+        };
+        Page { items, next_cursor, links: None }
+    }
+
+    /// Adds links to this page, the first and the next, for the request `uri`.
+    pub fn with_links(mut self, uri: &Uri) -> Self {
+        let mut links = Links::new();
+        links.insert("self".to_string(), Link::get(uri.to_string()));
+        links.insert("first".to_string(), Link::get(hypermedia::page_link(uri, None)));
+        if let Some(cursor) = &self.next_cursor {
+            links.insert("next".to_string(), Link::get(hypermedia::page_link(uri, Some(cursor))));
+        }
+        self.links = Some(links);
+        self
+    }
+}
+
+/// A list endpoint's response: a page when the client asked for one.
+#[derive(Debug, Serialize)]
+#[serde(untagged)]
+pub enum Listing<T> {
+    All(Vec<T>),
+    Page(Page<T>),
+}
+
+impl<T> Listing<T> {
+    pub fn items_mut(&mut self) -> &mut Vec<T> {
+        match self {
+            Listing::All(items) | Listing::Page(Page { items, .. }) => items,
+        }
+    }
+
+    /// The same listing, each item converted with `f`.
+    pub fn map<U>(self, f: impl FnMut(T) -> U) -> Listing<U> {
+        match self {
+            Listing::All(items) => Listing::All(items.into_iter().map(f).collect()),
+            Listing::Page(Page { items, next_cursor, links }) => Listing::Page(Page { items: items.into_iter().map(f).collect(), next_cursor, links }),
+        }
+    }
+}
@@ -0,0 +1,284 @@
+use serde::Serialize;
+
+/// The most common passwords and password words, most common first. Matches
+/// cost an attacker about their rank in guesses.
+const COMMON: &[&str] = &[
+    "password", "123456", "123456789", "qwerty", "12345678", "111111", "12345", "1234567", "abc123", "password1",
+    "iloveyou", "admin", "welcome", "monkey", "dragon", "letmein", "football", "baseball", "sunshine", "princess",
+    "master", "shadow", "superman", "batman", "trustno1", "starwars", "whatever", "freedom", "secret", "login",
+    "hello", "charlie", "michael", "jordan", "jennifer", "hunter", "ranger", "buster", "soccer", "hockey",
+    "killer", "george", "andrew", "thomas", "summer", "winter", "spring", "autumn", "flower", "cookie",
+    "cheese", "orange", "banana", "purple", "pepper", "ginger", "silver", "golden", "family", "friend",
+    "computer", "internet", "qazwsx", "zxcvbn", "asdfgh", "passw0rd", "p@ssw0rd", "changeme", "default", "guest",
+    "access", "mustang", "maggie", "jessica", "matrix", "pokemon", "blink182", "naruto", "liverpool", "chelsea",
+    "arsenal", "love", "lovely", "angel", "baby", "honey", "money", "todo", "todos", "todoapp",
+];
+
+/// Keyboard rows; runs along them are as guessable as "abcd".
+const KEYBOARD_ROWS: &[&str] = &["qwertyuiop", "asdfghjkl", "zxcvbnm", "1234567890"];
+
+/// How strong a password is, on zxcvbn's 0-4 scale: 0 is too guessable, 1
+/// very guessable, 2 somewhat guessable, 3 safely unguessable (the default
+/// minimum), 4 very unguessable.
+#[derive(Debug, Serialize)]
+pub struct Strength {
+    pub score: u8,
+    /// What makes the password weak, if anything in particular
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub warning: Option<&'static str>,
+    pub suggestions: Vec<&'static str>,
+}
+
+/// The minimum score for new passwords, from `PASSWORD_MIN_SCORE` (0-4, default 3).
+pub fn min_score() -> u8 {
+    std::env::var("PASSWORD_MIN_SCORE")
+        .ok()
+        .and_then(|score| score.parse().ok())
+        .unwrap_or(3)
+        .min(4)
+}
+
+/// Undoes common letter substitutions ("p@ssw0rd" -> "password").
+fn unleet(c: char) -> char {
+    match c {
+        '@' | '4' => 'a',
+        '3' => 'e',
+        '1' | '!' => 'i',
+        '0' => 'o',
+        '$' | '5' => 's',
+        '7' => 't',
+        other => other,
+    }
+}
+
+/// Whether `chars` is a run like "abc", "4321" or "asdf".
+fn is_sequence(chars: &[char]) -> bool {
+    let steps: Vec<i64> = chars.windows(2).map(|pair| pair[1] as i64 - pair[0] as i64).collect();
+    if steps.iter().all(|step| *step == 1) || steps.iter().all(|step| *step == -1) {
+        return true;
+    }
+    let run: String = chars.iter().collect();
+    let reversed: String = chars.iter().rev().collect();
+    KEYBOARD_ROWS.iter().any(|row| row.contains(&run) || row.contains(&reversed))
+}
+
+/// Kinds of guessable pieces, for feedback.
+#[derive(Clone, Copy, PartialEq)]
+enum Piece {
+    Common,
+    /// A common word with letters swapped for look-alikes ("p@ssw0rd")
+    Substituted,
+    UserInput,
+    Repeat,
+    Sequence,
+    Year,
+}
+
+/// Estimates how many guesses an attacker needs, in the spirit of zxcvbn:
+/// the password is split into the cheapest known pieces (common words, the
+/// user's own name or email, repeats, sequences, years), each costing what it
+/// takes to guess it, and anything else is brute-forced character by
+/// character. `user_inputs` are things an attacker would know, like the
+/// username.
+pub fn estimate(password: &str, user_inputs: &[&str]) -> Strength {
+    let chars: Vec<char> = password.chars().collect();
+    let lower: Vec<char> = chars.iter().map(|c| c.to_lowercase().next().unwrap_or(*c)).collect();
+    let unleeted: Vec<char> = lower.iter().copied().map(unleet).collect();
+    let user_inputs: Vec<Vec<char>> = user_inputs
+        .iter()
+        // "sam.jones85@example.com" -> "sam", "jones", "example"
+        .flat_map(|input| input.split(|c: char| !c.is_alphabetic()))
+        .filter(|part| part.chars().count() >= 3)
+        .map(|part| part.to_lowercase().chars().collect())
+        .collect();
+
+    let mut pool = 0.0;
+    if chars.iter().any(|c| c.is_ascii_lowercase()) {
+        pool += 26.0;
+    }
+    if chars.iter().any(|c| c.is_ascii_uppercase()) {
+        pool += 26.0;
+    }
+    if chars.iter().any(|c| c.is_ascii_digit()) {
+        pool += 10.0;
+    }
+    if chars.iter().any(|c| !c.is_ascii_alphanumeric()) {
+        pool += 33.0;
+    }
+    let brute_force = f64::log10(f64::max(pool, 10.0));
+
+    let mut found = Vec::new();
+    let mut log_guesses = 0.0;
+    let mut i = 0;
+    while i < chars.len() {
+        let rest = chars.len() - i;
+        // (length, log10 guesses, kind); the longest piece starting here
+        // wins, and the most guessable of equally long ones
+        let mut candidates: Vec<(usize, f64, Piece)> = Vec::new();
+
+        for (rank, word) in COMMON.iter().enumerate() {
+            let word: Vec<char> = word.chars().collect();
+            let len = word.len();
+            if len > rest {
+                continue;
+            }
+            if lower[i..i + len] == word[..] {
+                candidates.push((len, f64::log10((rank + 1) as f64), Piece::Common));
+            } else if unleeted[i..i + len] == word[..] {
+                candidates.push((len, f64::log10((rank + 1) as f64 * 2.0), Piece::Substituted));
+            }
+        }
+        for input in &user_inputs {
+            if input.len() <= rest && lower[i..i + input.len()] == input[..] {
+                candidates.push((input.len(), 1.0, Piece::UserInput));
+            }
+        }
+        let repeat = chars[i..].iter().take_while(|c| **c == chars[i]).count();
+        if repeat >= 3 {
+            candidates.push((repeat, brute_force + f64::log10(repeat as f64), Piece::Repeat));
+        }
+        let sequence = (3..=rest).take_while(|len| is_sequence(&lower[i..i + len])).last();
+        if let Some(len) = sequence {
+            candidates.push((len, f64::log10(26.0 * 2.0 * len as f64), Piece::Sequence));
+        }
+        if rest >= 4 {
+            let piece: String = chars[i..i + 4].iter().collect();
+            if piece.parse::<u32>().is_ok_and(|year| (1900..2100).contains(&year)) {
+                candidates.push((4, f64::log10(200.0), Piece::Year));
+            }
+        }
+
+        match candidates.into_iter().max_by(|a, b| a.0.cmp(&b.0).then(b.1.total_cmp(&a.1))) {
+            Some((len, cost, piece)) => {
+                log_guesses += cost;
+                found.push(piece);
+                i += len;
+            }
+            None => {
+                log_guesses += brute_force;
+                i += 1;
+            }
+        }
+    }
+    let found = |piece: Piece| found.contains(&piece);
+
+    let score = match log_guesses {
+        g if g < 3.0 => 0,
+        g if g < 6.0 => 1,
+        g if g < 8.0 => 2,
+        g if g < 10.0 => 3,
+        _ => 4,
+    };
+
+    let warning = if score >= 3 {
+        None
+    } else if found(Piece::Common) || found(Piece::Substituted) {
+        Some("This is similar to a commonly used password.")
+    } else if found(Piece::UserInput) {
+        Some("Passwords containing your username or email are easy to guess.")
+    } else if found(Piece::Repeat) {
+        Some("Repeated characters like \"aaa\" are easy to guess.")
+    } else if found(Piece::Sequence) {
+        Some("Sequences like \"abc\" or \"6543\" are easy to guess.")
+    } else if found(Piece::Year) {
+        Some("Years are easy to guess.")
+    } else if chars.len() < 10 {
+        Some("Short passwords are easy to guess.")
+    } else {
+        None
+    };
+
+    let mut suggestions = Vec::new();
+    if score < 3 {
+        suggestions.push("Add another word or two. Uncommon words are better.");
+        if found(Piece::Substituted) {
+            suggestions.push("Predictable substitutions like \"@\" instead of \"a\" don't help very much.");
+        }
+        if found(Piece::UserInput) {
+            suggestions.push("Leave out your username and email address.");
+        }
+        if found(Piece::Repeat) || found(Piece::Sequence) {
+            suggestions.push("Avoid repeated characters and sequences.");
+        }
+        if found(Piece::Year) {
+            suggestions.push("Avoid years and dates that are associated with you.");
+        }
+    }
+
+    Strength { score, warning, suggestions }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn score(password: &str) -> u8 {
+        estimate(password, &[]).score
+    }
+
+    #[test]
+    fn scores_by_estimated_guesses() {
+        // Common passwords cost about their rank
+        assert_eq!(score("password"), 0);
+        assert_eq!(score("123456"), 0);
+        assert_eq!(score("p@ssw0rd"), 0);
+        // Brute force costs log10(pool) per character: 26 lowercase letters...
+        assert_eq!(score("xkqz"), 1);
+        assert_eq!(score("xkqzv"), 2);
+        assert_eq!(score("xkqzvjw"), 3);
+        assert_eq!(score("xkqzvjwf"), 4);
+        // ...or 95 once there are capitals, digits and symbols
+        assert_eq!(score("Xk7#"), 2);
+        assert_eq!(score("Xk7#vj"), 4);
+        assert_eq!(score("correct horse battery staple"), 4);
+        assert_eq!(score(""), 0);
+    }
+
+    #[test]
+    fn pieces_cost_less_than_their_characters() {
+        assert!(score("aaaaaaaaaaaa") < 3);
+        assert!(score("abcdefghijkl") < 3);
+        assert!(score("qwertyuiop") < 3);
+        assert!(score("sunshine2019") < 3);
+        assert!(score("monkeydragon") < 3);
+        assert!(estimate("samjones", &["sam.jones85@example.com"]).score < score("samjones"));
+    }
+
+    #[test]
+    fn explains_weak_passwords() {
+        let strength = estimate("password", &[]);
+        assert_eq!(strength.warning, Some("This is similar to a commonly used password."));
+        assert_eq!(strength.suggestions, ["Add another word or two. Uncommon words are better."]);
+
+        let strength = estimate("p@ssw0rd", &[]);
+        assert_eq!(strength.warning, Some("This is similar to a commonly used password."));
+        assert!(strength.suggestions.contains(&"Predictable substitutions like \"@\" instead of \"a\" don't help very much."));
+
+        let strength = estimate("samjones", &["sam.jones85@example.com"]);
+        assert_eq!(strength.warning, Some("Passwords containing your username or email are easy to guess."));
+        assert!(strength.suggestions.contains(&"Leave out your username and email address."));
+
+        let strength = estimate("zzzzzzzz", &[]);
+        assert_eq!(strength.warning, Some("Repeated characters like \"aaa\" are easy to guess."));
+        assert!(strength.suggestions.contains(&"Avoid repeated characters and sequences."));
+
+        let strength = estimate("abcdefgh", &[]);
+        assert_eq!(strength.warning, Some("Sequences like \"abc\" or \"6543\" are easy to guess."));
+        assert!(strength.suggestions.contains(&"Avoid repeated characters and sequences."));
+
+        let strength = estimate("19871987", &[]);
+        assert_eq!(strength.warning, Some("Years are easy to guess."));
+        assert!(strength.suggestions.contains(&"Avoid years and dates that are associated with you."));
+
+        let strength = estimate("xkqz", &[]);
+        assert_eq!(strength.warning, Some("Short passwords are easy to guess."));
+        assert_eq!(strength.suggestions, ["Add another word or two. Uncommon words are better."]);
+    }
+
+    #[test]
+    fn strong_passwords_get_no_feedback() {
+        let strength = estimate("correct horse battery staple", &[]);
+        assert_eq!(strength.warning, None);
+        assert!(strength.suggestions.is_empty());
+    }
+}
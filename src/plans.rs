@@ -0,0 +1,256 @@
+use axum::{
+    http::{HeaderMap, StatusCode},
+    response::{IntoResponse, Response},
+    Json,
+};
+use chrono::Utc;
+use ring::hmac;
+use serde::{Deserialize, Serialize};
+use sqlx::{Row, SqlitePool};
+
+use crate::simple_db::{self, Database};
+
+/// How old a Stripe signature may be before the event is refused as a replay
+const SIGNATURE_TOLERANCE_SECONDS: i64 = 300;
+
+/// What a user pays for. Everyone starts on `Free`.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum Plan {
+    Free,
+    Pro,
+}
+
+impl Plan {
+    pub fn as_str(self) -> &'static str {
+        match self {
+            Plan::Free => "free",
+            Plan::Pro => "pro",
+        }
+    }
+
+    pub fn parse(plan: &str) -> Option<Plan> {
+        match plan {
+            "free" => Some(Plan::Free),
+            "pro" => Some(Plan::Pro),
+            _ => None,
+        }
+    }
+}
+
+/// Features only `Pro` users get, once billing is set up.
+#[derive(Clone, Copy, Debug)]
+pub enum Feature {
+    /// Notifications to a chat webhook, and a workspace's Slack webhook
+    Webhooks,
+    /// Google Calendar
+    Integrations,
+}
+
+impl Feature {
+    fn as_str(self) -> &'static str {
+        match self {
+            Feature::Webhooks => "webhooks",
+            Feature::Integrations => "integrations",
+        }
+    }
+}
+
+pub async fn get_plan(pool: &SqlitePool, user_id: &str) -> Result<Plan, sqlx::Error> {
+    let plan: Option<String> = sqlx::query_scalar("SELECT plan FROM users WHERE id = ?").bind(user_id).fetch_optional(pool).await?;
+    Ok(plan.as_deref().and_then(Plan::parse).unwrap_or(Plan::Free))
+}
+
+pub async fn set_plan(db: &Database, user_id: &str, plan: Plan) -> Result<(), sqlx::Error> {
+    sqlx::query("UPDATE users SET plan = ?, updated_at = ? WHERE id = ?")
+        .bind(plan.as_str())
+        .bind(Utc::now())
+        .bind(user_id)
+        .execute(&mut *db.write().await?)
+        .await?;
+    Ok(())
+}
+
+/// Stripe billing, set up with `STRIPE_WEBHOOK_SECRET` (the endpoint's
+/// `whsec_...` signing secret). Until it is, there's no way to upgrade, so
+/// premium features stay open to everyone.
+#[derive(Clone)]
+pub struct Billing {
+    webhook_key: hmac::Key,
+}
+
+impl Billing {
+    pub fn from_env() -> Option<Self> {
+        let secret = std::env::var("STRIPE_WEBHOOK_SECRET").ok().filter(|secret| !secret.is_empty())?;
+        Some(Billing { webhook_key: hmac::Key::new(hmac::HMAC_SHA256, secret.as_bytes()) })
+    }
+
+    /// Whether `payload` came from Stripe: the `Stripe-Signature` header
+    /// holds `t=<unix time>,v1=<hex HMAC-SHA256 of "t.payload">`, possibly
+    /// with several `v1` while the secret is being rolled.
+    fn verify(&self, headers: &HeaderMap, payload: &[u8]) -> bool {
+        let Some(signature) = headers.get("stripe-signature").and_then(|value| value.to_str().ok()) else {
+            return false;
+        };
+        let mut timestamp = None;
+        let mut signatures = Vec::new();
+        for part in signature.split(',') {
+            match part.split_once('=') {
+                Some(("t", t)) => timestamp = t.parse::<i64>().ok(),
+                Some(("v1", v1)) => signatures.extend(decode_hex(v1)),
+                _ => {}
+            }
+        }
+        let Some(timestamp) = timestamp else {
+            return false;
+        };
+        if (Utc::now().timestamp() - timestamp).abs() > SIGNATURE_TOLERANCE_SECONDS {
+            return false;
+        }
+        let mut signed = format!("{}.", timestamp).into_bytes();
+        signed.extend_from_slice(payload);
+        signatures.iter().any(|tag| hmac::verify(&self.webhook_key, &signed, tag).is_ok())
+    }
+}
+
+fn decode_hex(hex: &str) -> Option<Vec<u8>> {
+    if !hex.len().is_multiple_of(2) {
+        return None;
+    }
+    (0..hex.len()).step_by(2).map(|i| u8::from_str_radix(hex.get(i..i + 2)?, 16).ok()).collect()
+}
+
+/// `Ok` if `user_id` may use `feature`: always while billing isn't set up,
+/// otherwise only on `Pro`.
+pub async fn require(pool: &SqlitePool, billing: Option<&Billing>, user_id: &str, feature: Feature) -> Result<(), PlanError> {
+    if billing.is_none() {
+        return Ok(());
+    }
+    match get_plan(pool, user_id).await? {
+        Plan::Pro => Ok(()),
+        plan => Err(PlanError::UpgradeRequired { plan, feature }),
+    }
+}
+
+pub enum PlanError {
+    UpgradeRequired { plan: Plan, feature: Feature },
+    Database(sqlx::Error),
+}
+
+impl From<sqlx::Error> for PlanError {
+    fn from(error: sqlx::Error) -> Self {
+        PlanError::Database(error)
+    }
+}
+
+#[derive(Serialize)]
+struct UpgradeRequired {
+    error: &'static str,
+    plan: Plan,
+    feature: &'static str,
+}
+
+/// `402` naming the feature and the user's plan, e.g.
+/// `{"error": "Upgrade required", "plan": "free", "feature": "integrations"}`.
+impl IntoResponse for PlanError {
+    fn into_response(self) -> Response {
+        match self {
+            PlanError::UpgradeRequired { plan, feature } => {
+                let body = UpgradeRequired { error: "Upgrade required", plan, feature: feature.as_str() };
+                (StatusCode::PAYMENT_REQUIRED, Json(body)).into_response()
+            }
+            PlanError::Database(e) => simple_db::error_status(&e).into_response(),
+        }
+    }
+}
+
+#[derive(Deserialize)]
+struct Event {
+    #[serde(rename = "type")]
+    kind: String,
+    data: EventData,
+}
+
+#[derive(Deserialize)]
+struct EventData {
+    object: serde_json::Value,
+}
+
+/// Applies a Stripe webhook event to users' plans:
+///
+/// - `checkout.session.completed`: the user in `client_reference_id` (set
+///   it to their id when creating the Checkout Session) moves to `Pro`,
+///   and the Stripe customer is remembered
+/// - `customer.subscription.updated`: the customer's user is `Pro` while
+///   the subscription is `active` or `trialing`, `Free` otherwise
+/// - `customer.subscription.deleted`: the customer's user moves to `Free`
+///
+/// Other events are acknowledged and ignored. `400` for a payload that
+/// isn't signed with the webhook secret.
+pub async fn handle_webhook(db: &Database, billing: &Billing, headers: &HeaderMap, payload: &[u8]) -> StatusCode {
+    if !billing.verify(headers, payload) {
+        return StatusCode::BAD_REQUEST;
+    }
+    let Ok(event) = serde_json::from_slice::<Event>(payload) else {
+        return StatusCode::BAD_REQUEST;
+    };
+    let object = &event.data.object;
+    let customer = object["customer"].as_str();
+    let result = match event.kind.as_str() {
+        "checkout.session.completed" => match object["client_reference_id"].as_str() {
+            Some(user_id) => start_subscription(db, user_id, customer).await,
+            None => {
+                tracing::warn!("Stripe checkout completed without a client_reference_id");
+                Ok(())
+            }
+        },
+        "customer.subscription.updated" => {
+            let plan = match object["status"].as_str() {
+                Some("active" | "trialing") => Plan::Pro,
+                _ => Plan::Free,
+            };
+            set_customer_plan(db, customer, plan).await
+        }
+        "customer.subscription.deleted" => set_customer_plan(db, customer, Plan::Free).await,
+        _ => Ok(()),
+    };
+    match result {
+        Ok(()) => StatusCode::OK,
+        Err(e) => {
+            tracing::error!(error = %e, event = %event.kind, "Failed to apply Stripe event");
+            simple_db::error_status(&e)
+        }
+    }
+}
+
+async fn start_subscription(db: &Database, user_id: &str, customer: Option<&str>) -> Result<(), sqlx::Error> {
+    let result = sqlx::query("UPDATE users SET plan = ?, stripe_customer_id = COALESCE(?, stripe_customer_id), updated_at = ? WHERE id = ?")
+        .bind(Plan::Pro.as_str())
+        .bind(customer)
+        .bind(Utc::now())
+        .bind(user_id)
+        .execute(&mut *db.write().await?)
+        .await?;
+    if result.rows_affected() == 0 {
+        tracing::warn!(user_id, "Stripe checkout completed for an unknown user");
+    } else {
+        tracing::info!(user_id, "Upgraded to pro");
+    }
+    Ok(())
+}
+
+async fn set_customer_plan(db: &Database, customer: Option<&str>, plan: Plan) -> Result<(), sqlx::Error> {
+    let Some(customer) = customer else {
+        return Ok(());
+    };
+    let rows = sqlx::query("UPDATE users SET plan = ?, updated_at = ? WHERE stripe_customer_id = ? RETURNING id")
+        .bind(plan.as_str())
+        .bind(Utc::now())
+        .bind(customer)
+        .fetch_all(&mut *db.write().await?)
+        .await?;
+    for row in rows {
+        tracing::info!(user_id = %row.get::<String, _>("id"), plan = plan.as_str(), "Changed plan");
+    }
+    Ok(())
+}
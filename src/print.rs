@@ -0,0 +1,55 @@
+use askama::Template;
+use chrono::Utc;
+
+use crate::markdown;
+use crate::settings::UserSettings;
+use crate::simple_db::Todo;
+
+struct PrintItem {
+    text: String,
+    completed: bool,
+    /// Priority and category
+    details: Vec<String>,
+    due: Option<String>,
+    overdue: bool,
+    notes_html: Option<String>,
+}
+
+#[derive(Template)]
+#[template(path = "print.html")]
+struct PrintTemplate<'a> {
+    title: &'a str,
+    generated: String,
+    open: usize,
+    items: Vec<PrintItem>,
+}
+
+/// A standalone, print-friendly HTML page listing `todos` as a checklist,
+/// with dates in the user's timezone and format. Printing it (or the
+/// browser's "Save as PDF") gives a paper checklist or handout.
+pub fn render(title: &str, todos: &[Todo], settings: &UserSettings) -> Result<String, askama::Error> {
+    let items = todos
+        .iter()
+        .map(|todo| PrintItem {
+            text: todo.text.clone(),
+            completed: todo.completed,
+            details: todo
+                .priority
+                .map(|priority| priority.as_str().to_string())
+                .into_iter()
+                .chain(todo.category.clone())
+                .collect(),
+            due: todo.due_date.map(|due| settings.format_datetime(due)),
+            overdue: todo.is_overdue,
+            notes_html: todo.notes.as_deref().map(markdown::render),
+        })
+        .collect();
+
+    PrintTemplate {
+        title,
+        generated: settings.format_datetime(Utc::now()),
+        open: todos.iter().filter(|todo| !todo.completed).count(),
+        items,
+    }
+    .render()
+}
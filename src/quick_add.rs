@@ -0,0 +1,115 @@
+use crate::simple_db::{NewTodo, Priority};
+
+/// Metadata pulled out of a quick-add string such as
+/// `"Buy milk #errands @shopping !high due:friday"`.
+#[derive(Debug, Default, PartialEq)]
+pub struct QuickAdd {
+    pub text: String,
+    pub category: Option<String>,
+    pub tags: Vec<String>,
+    pub priority: Option<Priority>,
+    pub due: Option<String>,
+}
+
+/// `#word` sets the category, `@word` adds a tag, `!high|medium|low` sets the
+/// priority and `due:phrase` the due date (underscores stand in for spaces,
+/// e.g. `due:next_friday`). Everything else stays in the todo text, and a
+/// leading backslash makes a word plain text (`\#1` is the text `#1`).
+pub fn parse(input: &str) -> QuickAdd {
+    let mut parsed = QuickAdd::default();
+    let mut words = Vec::new();
+
+    for word in input.split_whitespace() {
+        if let Some(literal) = word.strip_prefix('\\').filter(|l| !l.is_empty()) {
+            words.push(literal);
+        } else if let Some(category) = word.strip_prefix('#').filter(|c| !c.is_empty()) {
+            parsed.category = Some(category.to_string());
+        } else if let Some(tag) = word.strip_prefix('@').filter(|t| !t.is_empty()) {
+            if !parsed.tags.iter().any(|t| t == tag) {
+                parsed.tags.push(tag.to_string());
+            }
+        } else if let Some(priority) = word.strip_prefix('!').and_then(|p| p.to_lowercase().parse().ok()) {
+            parsed.priority = Some(priority);
+        } else if let Some(due) = word.strip_prefix("due:").filter(|d| !d.is_empty()) {
+            parsed.due = Some(due.replace('_', " "));
+        } else {
+            words.push(word);
+        }
+    }
+
+    parsed.text = words.join(" ");
+    parsed
+}
+
+/// Parses `new_todo.text` as quick-add syntax. Fields set explicitly on the
+/// request take precedence over the ones found in the text.
+pub fn apply(new_todo: &mut NewTodo) {
+    let parsed = parse(&new_todo.text);
+
+    new_todo.text = parsed.text;
+    if new_todo.category.is_none() {
+        new_todo.category = parsed.category;
+    }
+    if new_todo.tags.is_none() && !parsed.tags.is_empty() {
+        new_todo.tags = Some(parsed.tags);
+    }
+    if new_todo.priority.is_none() {
+        new_todo.priority = parsed.priority;
+    }
+    if new_todo.due.is_none() && new_todo.due_date.is_none() {
+        new_todo.due = parsed.due;
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn pulls_out_tokens() {
+        assert_eq!(
+            parse("Buy milk #errands @shopping !high due:friday"),
+            QuickAdd {
+                text: "Buy milk".to_string(),
+                category: Some("errands".to_string()),
+                tags: vec!["shopping".to_string()],
+                priority: Some(Priority::High),
+                due: Some("friday".to_string()),
+            }
+        );
+        assert_eq!(parse("Plan trip due:next_friday_5pm").due.as_deref(), Some("next friday 5pm"));
+        assert_eq!(parse("Call mum !LOW").priority, Some(Priority::Low));
+        assert_eq!(parse("Just text"), QuickAdd { text: "Just text".to_string(), ..QuickAdd::default() });
+    }
+
+    #[test]
+    fn finds_tokens_anywhere_in_the_text() {
+        let parsed = parse("@home Water the #garden plants @home @weekly before !medium noon");
+        assert_eq!(parsed.text, "Water the plants before noon");
+        assert_eq!(parsed.category.as_deref(), Some("garden"));
+        assert_eq!(parsed.tags, ["home", "weekly"]);
+        assert_eq!(parsed.priority, Some(Priority::Medium));
+
+        // The last category wins
+        assert_eq!(parse("#work Email Sam #personal").category.as_deref(), Some("personal"));
+    }
+
+    #[test]
+    fn keeps_words_that_are_not_tokens() {
+        let parsed = parse("Reply! # @ due: !urgent email@example.com 50%");
+        assert_eq!(parsed.text, "Reply! # @ due: !urgent email@example.com 50%");
+        assert_eq!(parsed, QuickAdd { text: parsed.text.clone(), ..QuickAdd::default() });
+    }
+
+    #[test]
+    fn backslash_escapes_a_token() {
+        let parsed = parse(r"Fix \#1 for \@sam \!high \due:today #bugs");
+        assert_eq!(parsed.text, "Fix #1 for @sam !high due:today");
+        assert_eq!(parsed.category.as_deref(), Some("bugs"));
+        assert!(parsed.tags.is_empty());
+        assert_eq!((parsed.priority, parsed.due), (None, None));
+
+        // Only the first backslash goes, and a lone one stays
+        assert_eq!(parse(r"a \\#b \ c").text, r"a \#b \ c");
+    }
+}
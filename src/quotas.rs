@@ -0,0 +1,119 @@
+use axum::{
+    http::StatusCode,
+    response::{IntoResponse, Response},
+    Json,
+};
+use serde::Serialize;
+use sqlx::SqlitePool;
+
+use crate::plans::{self, Plan};
+use crate::simple_db;
+
+/// Per-user limits on the todos a user has created (in any workspace):
+/// `QUOTA_MAX_TODOS` on the free plan and `QUOTA_PRO_MAX_TODOS` on pro.
+/// Unset or `0` means no limit.
+#[derive(Clone, Copy, Default)]
+pub struct Quotas {
+    pub max_todos: Option<i64>,
+    pub pro_max_todos: Option<i64>,
+}
+
+impl Quotas {
+    pub fn from_env() -> Self {
+        Quotas { max_todos: limit_from_env("QUOTA_MAX_TODOS"), pro_max_todos: limit_from_env("QUOTA_PRO_MAX_TODOS") }
+    }
+
+    fn max_todos(&self, plan: Plan) -> Option<i64> {
+        match plan {
+            Plan::Free => self.max_todos,
+            Plan::Pro => self.pro_max_todos,
+        }
+    }
+
+    /// Whether `user_id` may create `adding` more todos. Counted before the
+    /// insert rather than with it, so two creates racing at the limit can
+    /// both get in; the quota is a cap on growth, not an exact invariant.
+    pub async fn check_todos(&self, pool: &SqlitePool, user_id: &str, adding: i64) -> Result<(), QuotaError> {
+        if self.max_todos.is_none() && self.pro_max_todos.is_none() {
+            return Ok(());
+        }
+        let Some(limit) = self.max_todos(plans::get_plan(pool, user_id).await?) else {
+            return Ok(());
+        };
+        let used = count_todos(pool, user_id).await?;
+        if used + adding > limit {
+            return Err(QuotaError::Exceeded { quota: "todos", limit, used });
+        }
+        Ok(())
+    }
+
+    /// What `user_id` is using against each quota.
+    pub async fn usage(&self, pool: &SqlitePool, user_id: &str) -> Result<Usage, sqlx::Error> {
+        let plan = plans::get_plan(pool, user_id).await?;
+        Ok(Usage { plan, todos: Consumption { used: count_todos(pool, user_id).await?, limit: self.max_todos(plan) } })
+    }
+}
+
+fn limit_from_env(var: &str) -> Option<i64> {
+    std::env::var(var).ok().and_then(|max| max.parse().ok()).filter(|&max| max > 0)
+}
+
+async fn count_todos(pool: &SqlitePool, user_id: &str) -> Result<i64, sqlx::Error> {
+    sqlx::query_scalar("SELECT COUNT(*) FROM todos WHERE user_id = ?").bind(user_id).fetch_one(pool).await
+}
+
+#[derive(Serialize)]
+pub struct Usage {
+    pub plan: Plan,
+    pub todos: Consumption,
+}
+
+#[derive(Serialize)]
+pub struct Consumption {
+    pub used: i64,
+    /// `null` when there is no limit
+    pub limit: Option<i64>,
+}
+
+pub enum QuotaError {
+    /// Going ahead would take the user past `limit`
+    Exceeded { quota: &'static str, limit: i64, used: i64 },
+    Database(sqlx::Error),
+}
+
+impl From<sqlx::Error> for QuotaError {
+    fn from(error: sqlx::Error) -> Self {
+        QuotaError::Database(error)
+    }
+}
+
+impl From<QuotaError> for StatusCode {
+    fn from(error: QuotaError) -> Self {
+        match error {
+            QuotaError::Exceeded { .. } => StatusCode::FORBIDDEN,
+            QuotaError::Database(e) => simple_db::error_status(&e),
+        }
+    }
+}
+
+#[derive(Serialize)]
+struct QuotaExceeded {
+    error: &'static str,
+    quota: &'static str,
+    limit: i64,
+    used: i64,
+}
+
+/// `403` with which quota was hit, e.g.
+/// `{"error": "Quota exceeded", "quota": "todos", "limit": 500, "used": 500}`.
+impl IntoResponse for QuotaError {
+    fn into_response(self) -> Response {
+        match self {
+            QuotaError::Exceeded { quota, limit, used } => {
+                let body = QuotaExceeded { error: "Quota exceeded", quota, limit, used };
+                (StatusCode::FORBIDDEN, Json(body)).into_response()
+            }
+            QuotaError::Database(e) => simple_db::error_status(&e).into_response(),
+        }
+    }
+}
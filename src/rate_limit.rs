@@ -0,0 +1,107 @@
+use axum::{
+    extract::{ConnectInfo, Request, State},
+    http::{header, HeaderMap, HeaderName, HeaderValue, StatusCode},
+    middleware::Next,
+    response::{IntoResponse, Response},
+};
+use std::net::SocketAddr;
+use std::time::Duration;
+
+use crate::shared_state::SharedState;
+
+/// Fixed-window request limit per client IP. Counters live in `SharedState`,
+/// so instances behind a load balancer enforce one combined limit when Redis
+/// is configured.
+#[derive(Clone)]
+pub struct RateLimit {
+    shared: SharedState,
+    scope: &'static str,
+    limit: u64,
+    window: Duration,
+    /// Take the client IP from `X-Forwarded-For` (only safe behind a proxy that sets it)
+    trust_forwarded_for: bool,
+}
+
+impl RateLimit {
+    /// Limits the auth endpoints to `RATE_LIMIT_AUTH_PER_MINUTE` requests per
+    /// minute per IP (default 20); `0` disables the limit.
+    pub fn auth_from_env(shared: SharedState) -> Option<Self> {
+        let limit = std::env::var("RATE_LIMIT_AUTH_PER_MINUTE")
+            .ok()
+            .and_then(|limit| limit.parse().ok())
+            .unwrap_or(20);
+        if limit == 0 {
+            return None;
+        }
+
+        Some(RateLimit {
+            shared,
+            scope: "auth",
+            limit,
+            window: Duration::from_secs(60),
+            trust_forwarded_for: trust_forwarded_for(),
+        })
+    }
+
+    fn client_ip(&self, request: &Request) -> Option<String> {
+        client_ip(request.headers(), request.extensions().get(), self.trust_forwarded_for)
+    }
+}
+
+/// The client's IP: the first `X-Forwarded-For` entry when trusted, else the peer address.
+pub fn client_ip(headers: &HeaderMap, connect_info: Option<&ConnectInfo<SocketAddr>>, trust_forwarded_for: bool) -> Option<String> {
+    if trust_forwarded_for
+        && let Some(forwarded) = headers.get("x-forwarded-for").and_then(|v| v.to_str().ok())
+        && let Some(client) = forwarded.split(',').next()
+    {
+        return Some(client.trim().to_string());
+    }
+
+    connect_info.map(|ConnectInfo(addr)| addr.ip().to_string())
+}
+
+/// `TRUST_FORWARDED_FOR=true`: take client IPs from `X-Forwarded-For` (only safe behind a proxy that sets it).
+pub fn trust_forwarded_for() -> bool {
+    std::env::var("TRUST_FORWARDED_FOR").is_ok_and(|v| v == "true")
+}
+
+pub const X_RATELIMIT_LIMIT: HeaderName = HeaderName::from_static("x-ratelimit-limit");
+pub const X_RATELIMIT_REMAINING: HeaderName = HeaderName::from_static("x-ratelimit-remaining");
+/// Unix time the current window ends and the count starts over
+pub const X_RATELIMIT_RESET: HeaderName = HeaderName::from_static("x-ratelimit-reset");
+
+/// Counts the request against its client's limit and tells the client where
+/// it stands, so well-behaved API clients can slow down before they hit 429.
+pub async fn limit(State(rate_limit): State<RateLimit>, request: Request, next: Next) -> Response {
+    let Some(client) = rate_limit.client_ip(&request) else {
+        return next.run(request).await;
+    };
+
+    let key = format!("ratelimit:{}:{}", rate_limit.scope, client);
+    let count = match rate_limit.shared.increment(&key, rate_limit.window).await {
+        Ok(count) => count,
+        // Fail open: an unavailable counter store shouldn't lock everyone out
+        Err(e) => {
+            tracing::warn!(error = %e, "Rate limit check failed");
+            return next.run(request).await;
+        }
+    };
+
+    // Windows start at multiples of their length (see `SharedState::increment`)
+    let window = rate_limit.window.as_secs().max(1);
+    let now = chrono::Utc::now().timestamp() as u64;
+    let reset = (now / window + 1) * window;
+    let mut response = if count > rate_limit.limit {
+        let mut response = StatusCode::TOO_MANY_REQUESTS.into_response();
+        response.headers_mut().insert(header::RETRY_AFTER, HeaderValue::from(reset - now));
+        response
+    } else {
+        next.run(request).await
+    };
+
+    let headers = response.headers_mut();
+    headers.insert(X_RATELIMIT_LIMIT, HeaderValue::from(rate_limit.limit));
+    headers.insert(X_RATELIMIT_REMAINING, HeaderValue::from(rate_limit.limit.saturating_sub(count)));
+    headers.insert(X_RATELIMIT_RESET, HeaderValue::from(reset));
+    response
+}
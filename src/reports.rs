@@ -0,0 +1,223 @@
+use axum::async_trait;
+use chrono::{DateTime, Datelike, Duration, NaiveTime, Utc};
+use serde::Serialize;
+use sqlx::{sqlite::SqliteRow, Row, SqlitePool};
+use uuid::Uuid;
+
+use crate::i18n::Message;
+use crate::jobs::{Job, JobError, Trigger};
+use crate::mailer::{self, Mailer};
+use crate::settings::{UserSettings, SETTINGS_COLUMNS};
+use crate::simple_db::Database;
+
+#[derive(Clone, Debug, Serialize)]
+pub struct WeeklyReport {
+    pub id: String,
+    pub user_id: String,
+    pub week_start: DateTime<Utc>,
+    pub week_end: DateTime<Utc>,
+    pub completed: i64,
+    pub created: i64,
+    pub overdue: i64,
+    pub busiest_category: Option<String>,
+    pub generated_at: DateTime<Utc>,
+}
+
+impl WeeklyReport {
+    fn from_row(row: &SqliteRow) -> Self {
+        WeeklyReport {
+            id: row.get("id"),
+            user_id: row.get("user_id"),
+            week_start: row.get("week_start"),
+            week_end: row.get("week_end"),
+            completed: row.get("completed"),
+            created: row.get("created"),
+            overdue: row.get("overdue"),
+            busiest_category: row.get("busiest_category"),
+            generated_at: row.get("generated_at"),
+        }
+    }
+}
+
+/// Monday 00:00 UTC of the week containing `at`.
+pub fn week_start(at: DateTime<Utc>) -> DateTime<Utc> {
+    let days_from_monday = at.weekday().num_days_from_monday() as i64;
+    (at.date_naive() - Duration::days(days_from_monday))
+        .and_time(NaiveTime::MIN)
+        .and_utc()
+}
+
+pub async fn compute_report(pool: &SqlitePool, user_id: &str, week_start: DateTime<Utc>) -> Result<WeeklyReport, sqlx::Error> {
+    let week_end = week_start + Duration::days(7);
+
+    let completed: i64 = sqlx::query("SELECT COUNT(*) AS n FROM todos WHERE user_id = ? AND completed_at >= ? AND completed_at < ?")
+        .bind(user_id)
+        .bind(week_start)
+        .bind(week_end)
+        .fetch_one(pool)
+        .await?
+        .get("n");
+
+    let created: i64 = sqlx::query("SELECT COUNT(*) AS n FROM todos WHERE user_id = ? AND created_at >= ? AND created_at < ?")
+        .bind(user_id)
+        .bind(week_start)
+        .bind(week_end)
+        .fetch_one(pool)
+        .await?
+        .get("n");
+
+    // Todos that fell due this week and weren't finished by their due date
+    let overdue: i64 = sqlx::query("SELECT COUNT(*) AS n FROM todos WHERE user_id = ? AND due_date >= ? AND due_date < ? AND (completed_at IS NULL OR completed_at > due_date)")
+        .bind(user_id)
+        .bind(week_start)
+        .bind(week_end)
+        .fetch_one(pool)
+        .await?
+        .get("n");
+
+    // Busiest category counts both todos created and todos completed during the week
+    let busiest_category: Option<String> = sqlx::query(
+        "SELECT category, COUNT(*) AS n FROM (
+            SELECT category FROM todos WHERE user_id = ? AND created_at >= ? AND created_at < ?
+            UNION ALL
+            SELECT category FROM todos WHERE user_id = ? AND completed_at >= ? AND completed_at < ?
+        ) WHERE category IS NOT NULL GROUP BY category ORDER BY n DESC, category LIMIT 1",
+    )
+    .bind(user_id)
+    .bind(week_start)
+    .bind(week_end)
+    .bind(user_id)
+    .bind(week_start)
+    .bind(week_end)
+    .fetch_optional(pool)
+    .await?
+    .map(|row| row.get("category"));
+
+    Ok(WeeklyReport {
+        id: Uuid::new_v4().to_string(),
+        user_id: user_id.to_string(),
+        week_start,
+        week_end,
+        completed,
+        created,
+        overdue,
+        busiest_category,
+        generated_at: Utc::now(),
+    })
+}
+
+/// Generates and stores the report for `week_start` for every user that
+/// doesn't have one yet. Returns the reports stored by this call, leaving
+/// out any another instance stored first.
+pub async fn generate_weekly_reports(db: &Database, week_start: DateTime<Utc>) -> Result<Vec<WeeklyReport>, sqlx::Error> {
+    let rows = sqlx::query("SELECT id FROM users WHERE id NOT IN (SELECT user_id FROM reports WHERE week_start = ?)")
+        .bind(week_start)
+        .fetch_all(db.get_pool())
+        .await?;
+
+    let mut reports = Vec::new();
+    for row in rows {
+        let user_id: String = row.get("id");
+        let report = compute_report(db.get_pool(), &user_id, week_start).await?;
+
+        let stored = sqlx::query("INSERT OR IGNORE INTO reports (id, user_id, week_start, week_end, completed, created, overdue, busiest_category, generated_at) VALUES (?, ?, ?, ?, ?, ?, ?, ?, ?)")
+            .bind(&report.id)
+            .bind(&report.user_id)
+            .bind(report.week_start)
+            .bind(report.week_end)
+            .bind(report.completed)
+            .bind(report.created)
+            .bind(report.overdue)
+            .bind(&report.busiest_category)
+            .bind(report.generated_at)
+            .execute(&mut *db.write().await?)
+            .await?;
+
+        if stored.rows_affected() > 0 {
+            reports.push(report);
+        }
+    }
+    Ok(reports)
+}
+
+/// Emails `report` to its user if they turned on `weekly_report`. Returns
+/// whether it was sent.
+pub async fn email_report(pool: &SqlitePool, mailer: &Mailer, report: &WeeklyReport) -> Result<bool, sqlx::Error> {
+    let row = sqlx::query(&format!("SELECT u.email, {} FROM users u JOIN user_settings s ON s.user_id = u.id WHERE u.id = ? AND s.weekly_report = TRUE AND u.disabled_at IS NULL", SETTINGS_COLUMNS))
+        .bind(&report.user_id)
+        .fetch_optional(pool)
+        .await?;
+    let Some(row) = row else {
+        return Ok(false);
+    };
+    let Some(email): Option<String> = row.get("email") else {
+        return Ok(false);
+    };
+    let settings = UserSettings::from_row(&row);
+    let language = settings.language;
+
+    let week_start = report.week_start.date_naive();
+    let body = Message::ReportBody {
+        week_start,
+        week_end: (report.week_end - Duration::days(1)).date_naive(),
+        completed: report.completed,
+        created: report.created,
+        overdue: report.overdue,
+        busiest_category: report.busiest_category.as_deref(),
+    }
+    .text(language);
+    let body = format!("{}\n{}\n\n{}\n", body, mailer::public_url(), Message::ReportFooter.text(language));
+    let subject = Message::ReportSubject { week_start }.text(language);
+    match mailer.send(&email, &subject, body).await {
+        Ok(()) => Ok(true),
+        Err(e) => {
+            tracing::warn!(error = %e, user_id = report.user_id, "Failed to send weekly report");
+            Ok(false)
+        }
+    }
+}
+
+pub async fn get_reports(pool: &SqlitePool, user_id: &str) -> Result<Vec<WeeklyReport>, sqlx::Error> {
+    let rows = sqlx::query("SELECT id, user_id, week_start, week_end, completed, created, overdue, busiest_category, generated_at FROM reports WHERE user_id = ? ORDER BY week_start DESC")
+        .bind(user_id)
+        .fetch_all(pool)
+        .await?;
+
+    Ok(rows.iter().map(WeeklyReport::from_row).collect())
+}
+
+/// Produces last week's reports and emails them to users who asked for
+/// them. Runs hourly so a restart never skips a week; already generated
+/// reports are left untouched, and only newly generated ones are emailed.
+pub struct WeeklyReportsJob {
+    mailer: Mailer,
+}
+
+impl WeeklyReportsJob {
+    pub fn new(mailer: Mailer) -> Self {
+        WeeklyReportsJob { mailer }
+    }
+}
+
+#[async_trait]
+impl Job for WeeklyReportsJob {
+    fn name(&self) -> &'static str {
+        "weekly_reports"
+    }
+
+    fn default_schedule(&self) -> Option<&'static str> {
+        Some("0 * * * *")
+    }
+
+    async fn run(&self, db: &Database, _trigger: Trigger) -> Result<String, JobError> {
+        let last_week = week_start(Utc::now()) - Duration::days(7);
+        let reports = generate_weekly_reports(db, last_week).await?;
+        let mut emailed = 0;
+        for report in &reports {
+            if email_report(db.get_pool(), &self.mailer, report).await? {
+                emailed += 1;
+            }
+        }
+        Ok(format!("Generated {} reports for the week of {}, emailed {}", reports.len(), last_week.date_naive(), emailed))
+    }
+}
@@ -0,0 +1,118 @@
+use axum::{
+    body::{Body, HttpBody},
+    extract::Request,
+    http::{header, HeaderName, HeaderValue, StatusCode},
+    middleware::Next,
+    response::Response,
+};
+use serde_json::{json, Map, Value};
+
+use crate::formats::Format;
+use crate::i18n::{self, Language};
+use crate::jsonapi;
+
+pub const X_REQUEST_ID: HeaderName = HeaderName::from_static("x-request-id");
+
+/// Error bodies are small; anything larger is passed through untouched.
+const MAX_ERROR_BODY: usize = 64 * 1024;
+
+/// The `X-Request-Id` set (or passed through) by `SetRequestIdLayer`.
+pub fn request_id(request: &Request) -> &str {
+    request
+        .headers()
+        .get(X_REQUEST_ID)
+        .and_then(|id| id.to_str().ok())
+        .unwrap_or_default()
+}
+
+/// Gives every 4xx/5xx response a JSON body of the form
+/// `{"error": "...", "request_id": "..."}` so users can quote the id when
+/// reporting a failure. Handlers mostly return a bare `StatusCode`; the
+/// message is the status reason, or the plain-text body of extractor
+/// rejections.
+///
+/// Clients preferring another supported language (`Accept-Language`) get the
+/// status reason in it, with any rejection text, which is English, as `detail`.
+///
+/// Handlers with their own JSON error object (quotas, plans, weak passwords,
+/// device sign-in) keep their fields, `error` included since clients match
+/// on it; `request_id` and `message`, the status reason in the client's
+/// language, are added.
+///
+/// Clients asking for JSON:API get an error document instead:
+/// `{"errors": [{"status": "404", "title": "...", "meta": {"request_id": "..."}}]}`.
+/// A handler's own `error` becomes the `detail` and its other fields go in `meta`.
+///
+/// 503s, which mean the database is overloaded, get a `Retry-After` if the
+/// handler didn't set one.
+pub async fn error_body(request: Request, next: Next) -> Response {
+    let id = request_id(&request).to_string();
+    let language = request
+        .headers()
+        .get(header::ACCEPT_LANGUAGE)
+        .and_then(|value| value.to_str().ok())
+        .and_then(Language::from_accept_language)
+        .unwrap_or_default();
+    let jsonapi = Format::from_accept(request.headers()) == Ok(Format::JsonApi);
+    let mut response = next.run(request).await;
+
+    let status = response.status();
+    if status == StatusCode::SERVICE_UNAVAILABLE && !response.headers().contains_key(header::RETRY_AFTER) {
+        response.headers_mut().insert(header::RETRY_AFTER, HeaderValue::from_static("2"));
+    }
+    let is_json = response
+        .headers()
+        .get(header::CONTENT_TYPE)
+        .is_some_and(|content_type| content_type.as_bytes().starts_with(b"application/json"));
+    let too_large = response.body().size_hint().lower() > MAX_ERROR_BODY as u64;
+    if !(status.is_client_error() || status.is_server_error()) || too_large {
+        return response;
+    }
+
+    let (mut parts, body) = response.into_parts();
+    let bytes = axum::body::to_bytes(body, MAX_ERROR_BODY).await.unwrap_or_default();
+    let body = if is_json {
+        match serde_json::from_slice(&bytes) {
+            Ok(Value::Object(fields)) => with_request_id(fields, status, language, &id, jsonapi),
+            // Not an object, so there's nowhere to put the id
+            _ => return Response::from_parts(parts, Body::from(bytes)),
+        }
+    } else {
+        let text = String::from_utf8_lossy(&bytes);
+        match (text.trim(), language) {
+            (text, _) if jsonapi => {
+                let mut error = json!({ "status": status.as_str(), "title": i18n::status_message(status, language), "meta": { "request_id": id } });
+                if !text.is_empty() {
+                    error["detail"] = json!(text);
+                }
+                json!({ "errors": [error] })
+            }
+            ("", _) => json!({ "error": i18n::status_message(status, language), "request_id": id }),
+            (text, Language::En) => json!({ "error": text, "request_id": id }),
+            (text, _) => json!({ "error": i18n::status_message(status, language), "detail": text, "request_id": id }),
+        }
+    }
+    .to_string();
+    let content_type = if jsonapi { jsonapi::MEDIA_TYPE } else { "application/json" };
+    parts.headers.remove(header::CONTENT_LENGTH);
+    parts.headers.insert(header::CONTENT_TYPE, HeaderValue::from_static(content_type));
+    parts.headers.insert(header::CONTENT_LANGUAGE, HeaderValue::from_static(language.as_str()));
+    Response::from_parts(parts, Body::from(body))
+}
+
+/// A handler's own JSON error with the request id and the localized status
+/// reason added, or as a JSON:API error document.
+fn with_request_id(mut fields: Map<String, Value>, status: StatusCode, language: Language, id: &str, jsonapi: bool) -> Value {
+    if jsonapi {
+        let mut error = json!({ "status": status.as_str(), "title": i18n::status_message(status, language) });
+        if let Some(detail) = fields.remove("error") {
+            error["detail"] = detail;
+        }
+        fields.insert("request_id".to_string(), json!(id));
+        error["meta"] = Value::Object(fields);
+        return json!({ "errors": [error] });
+    }
+    fields.entry("message").or_insert_with(|| json!(i18n::status_message(status, language)));
+    fields.entry("request_id").or_insert_with(|| json!(id));
+    Value::Object(fields)
+}
@@ -0,0 +1,233 @@
+use axum::async_trait;
+use chrono::{Duration, Utc};
+use sqlx::{Connection, Row};
+use std::sync::Arc;
+
+use crate::jobs::{Job, JobError, Trigger};
+use crate::simple_db::Database;
+
+/// A kind of record that expires.
+struct Rule {
+    /// Used in reports and in `RETENTION_<NAME>_DAYS`
+    name: &'static str,
+    /// `0` keeps the records forever
+    default_days: i64,
+    /// Counts the expired records; `?1` is the cutoff
+    count: &'static str,
+    /// Deletes the expired records and anything that depends on them,
+    /// dependents first; `?1` is the cutoff
+    delete: &'static [&'static str],
+}
+
+const RULES: &[Rule] = &[
+    Rule {
+        name: "auth_events",
+        default_days: 365,
+        count: "SELECT COUNT(*) FROM auth_events WHERE created_at < ?1",
+        delete: &["DELETE FROM auth_events WHERE created_at < ?1"],
+    },
+    Rule {
+        name: "read_notifications",
+        default_days: 90,
+        count: "SELECT COUNT(*) FROM notifications WHERE read_at IS NOT NULL AND created_at < ?1",
+        delete: &[
+            "DELETE FROM notification_deliveries WHERE notification_id IN (SELECT id FROM notifications WHERE read_at IS NOT NULL AND created_at < ?1)",
+            "DELETE FROM notifications WHERE read_at IS NOT NULL AND created_at < ?1",
+        ],
+    },
+    Rule {
+        name: "job_runs",
+        default_days: 90,
+        count: "SELECT COUNT(*) FROM job_runs WHERE finished_at < ?1",
+        delete: &["DELETE FROM job_runs WHERE finished_at < ?1"],
+    },
+    Rule {
+        name: "digests",
+        default_days: 90,
+        count: "SELECT COUNT(*) FROM digests WHERE sent_at < ?1",
+        delete: &["DELETE FROM digests WHERE sent_at < ?1"],
+    },
+    Rule {
+        name: "link_previews",
+        default_days: 30,
+        count: "SELECT COUNT(*) FROM link_previews WHERE fetched_at < ?1",
+        delete: &["DELETE FROM link_previews WHERE fetched_at < ?1"],
+    },
+    Rule {
+        name: "device_codes",
+        default_days: 1,
+        count: "SELECT COUNT(*) FROM device_codes WHERE expires_at < ?1",
+        delete: &["DELETE FROM device_codes WHERE expires_at < ?1"],
+    },
+    Rule {
+        name: "outbox",
+        default_days: 7,
+        count: "SELECT COUNT(*) FROM outbox WHERE dispatched_at < ?1",
+        delete: &["DELETE FROM outbox WHERE dispatched_at < ?1"],
+    },
+    Rule {
+        name: "webhook_deliveries",
+        default_days: 30,
+        count: "SELECT COUNT(*) FROM webhook_deliveries WHERE created_at < ?1",
+        delete: &["DELETE FROM webhook_deliveries WHERE created_at < ?1"],
+    },
+    Rule {
+        name: "idempotency_keys",
+        default_days: 1,
+        count: "SELECT COUNT(*) FROM idempotency_keys WHERE created_at < ?1",
+        delete: &["DELETE FROM idempotency_keys WHERE created_at < ?1"],
+    },
+    Rule {
+        name: "completed_todos",
+        default_days: 0,
+        count: "SELECT COUNT(*) FROM todos WHERE completed = TRUE AND COALESCE(completed_at, updated_at) < ?1",
+        delete: &[
+            "DELETE FROM todo_dependencies WHERE todo_id IN (SELECT id FROM todos WHERE completed = TRUE AND COALESCE(completed_at, updated_at) < ?1) OR blocker_id IN (SELECT id FROM todos WHERE completed = TRUE AND COALESCE(completed_at, updated_at) < ?1)",
+            "DELETE FROM mentions WHERE todo_id IN (SELECT id FROM todos WHERE completed = TRUE AND COALESCE(completed_at, updated_at) < ?1)",
+            "DELETE FROM todo_escalations WHERE todo_id IN (SELECT id FROM todos WHERE completed = TRUE AND COALESCE(completed_at, updated_at) < ?1)",
+            "DELETE FROM todo_occurrences WHERE todo_id IN (SELECT id FROM todos WHERE completed = TRUE AND COALESCE(completed_at, updated_at) < ?1)",
+            "DELETE FROM myday WHERE todo_id IN (SELECT id FROM todos WHERE completed = TRUE AND COALESCE(completed_at, updated_at) < ?1)",
+            "DELETE FROM events WHERE subject_id IN (SELECT id FROM todos WHERE completed = TRUE AND COALESCE(completed_at, updated_at) < ?1)",
+            "DELETE FROM todos WHERE completed = TRUE AND COALESCE(completed_at, updated_at) < ?1",
+        ],
+    },
+    Rule {
+        name: "events",
+        default_days: 0,
+        count: "SELECT COUNT(*) FROM events WHERE created_at < ?1",
+        delete: &["DELETE FROM events WHERE created_at < ?1"],
+    },
+    Rule {
+        name: "tombstones",
+        default_days: 90,
+        count: "SELECT COUNT(*) FROM deleted_items WHERE deleted_at < ?1",
+        delete: &[
+            "DELETE FROM todo_changes WHERE deleted = TRUE AND todo_id IN (SELECT entity_id FROM deleted_items WHERE entity_type = 'todo' AND deleted_at < ?1)",
+            "DELETE FROM deleted_items WHERE deleted_at < ?1",
+        ],
+    },
+];
+
+/// How long each kind of record is kept, from `RETENTION_<NAME>_DAYS`:
+///
+/// - `AUTH_EVENTS` (default 365)
+/// - `READ_NOTIFICATIONS` (default 90; unread ones are kept)
+/// - `JOB_RUNS` (default 90)
+/// - `DIGESTS`, the record of which daily digests were sent (default 90)
+/// - `LINK_PREVIEWS`, counted from when the page was fetched (default 30)
+/// - `DEVICE_CODES`, counted from expiry (default 1)
+/// - `OUTBOX`, events already handed out (default 7)
+/// - `WEBHOOK_DELIVERIES` (default 30)
+/// - `IDEMPOTENCY_KEYS`, remembered from `POST /todos` (default 1)
+/// - `COMPLETED_TODOS`, counted from completion (default 0)
+/// - `EVENTS`, the event log (default 0)
+/// - `TOMBSTONES`, the record of deleted todos, categories, filters and rules
+///   (default 90; sync cursors older than this expire)
+///
+/// `0` keeps records forever. `RETENTION_DRY_RUN=true` makes the scheduled
+/// purge only report what it would remove.
+#[derive(Clone, Debug)]
+pub struct RetentionPolicy {
+    /// Days to keep, by rule name; rules with `0` are left out
+    days: Vec<(&'static str, i64)>,
+    pub dry_run: bool,
+}
+
+impl RetentionPolicy {
+    pub fn from_env() -> Self {
+        let days = RULES
+            .iter()
+            .map(|rule| {
+                let days = std::env::var(format!("RETENTION_{}_DAYS", rule.name.to_uppercase()))
+                    .ok()
+                    .and_then(|days| days.parse().ok())
+                    .unwrap_or(rule.default_days);
+                (rule.name, days)
+            })
+            .filter(|(_, days)| *days > 0)
+            .collect();
+
+        RetentionPolicy {
+            days,
+            dry_run: std::env::var("RETENTION_DRY_RUN").is_ok_and(|value| value == "true"),
+        }
+    }
+
+    /// How long the `name` rule keeps records; `None` if forever.
+    pub fn days(&self, name: &str) -> Option<i64> {
+        self.days.iter().find(|(rule, _)| *rule == name).map(|(_, days)| *days)
+    }
+}
+
+/// What a purge removed, or would have in a dry run.
+#[derive(Debug)]
+pub struct Purged {
+    pub name: &'static str,
+    pub older_than_days: i64,
+    pub count: i64,
+}
+
+/// Removes everything older than `policy` allows, one kind of record per
+/// transaction. With `dry_run` nothing is deleted; the counts are what would be.
+pub async fn purge(db: &Database, policy: &RetentionPolicy, dry_run: bool) -> Result<Vec<Purged>, sqlx::Error> {
+    let mut report = Vec::new();
+
+    for rule in RULES {
+        let Some(days) = policy.days(rule.name) else {
+            continue;
+        };
+        let cutoff = Utc::now() - Duration::days(days);
+
+        let mut conn = db.write().await?;
+        let mut tx = conn.begin().await?;
+        let count: i64 = sqlx::query(rule.count).bind(cutoff).fetch_one(&mut *tx).await?.get(0);
+        if count > 0 && !dry_run {
+            for statement in rule.delete {
+                sqlx::query(statement).bind(cutoff).execute(&mut *tx).await?;
+            }
+            tx.commit().await?;
+            if rule.name == "completed_todos" {
+                db.todos_changed().await;
+            }
+        }
+        report.push(Purged { name: rule.name, older_than_days: days, count });
+    }
+    Ok(report)
+}
+
+/// "auth_events: 12, read_notifications: 0"
+pub fn summarize(report: &[Purged]) -> String {
+    if report.is_empty() {
+        return "No retention rules are enabled".to_string();
+    }
+    report.iter().map(|purged| format!("{}: {}", purged.name, purged.count)).collect::<Vec<_>>().join(", ")
+}
+
+/// Enforces the `RetentionPolicy` daily.
+pub struct RetentionJob {
+    db: Arc<Database>,
+    policy: RetentionPolicy,
+}
+
+impl RetentionJob {
+    pub fn new(db: Arc<Database>, policy: RetentionPolicy) -> Self {
+        RetentionJob { db, policy }
+    }
+}
+
+#[async_trait]
+impl Job for RetentionJob {
+    fn name(&self) -> &'static str {
+        "retention"
+    }
+
+    fn default_schedule(&self) -> Option<&'static str> {
+        Some("30 3 * * *")
+    }
+
+    async fn run(&self, _db: &Database, _trigger: Trigger) -> Result<String, JobError> {
+        let report = purge(&self.db, &self.policy, self.policy.dry_run).await?;
+        let summary = summarize(&report);
+        Ok(if self.policy.dry_run { format!("Dry run, would remove {}", summary) } else { format!("Removed {}", summary) })
+    }
+}
@@ -0,0 +1,254 @@
+use chrono::{DateTime, Utc};
+use serde::{Deserialize, Serialize};
+use sqlx::{sqlite::SqliteRow, Row, SqlitePool};
+use uuid::Uuid;
+
+use crate::colors;
+use crate::search;
+use crate::simple_db::{Database, NewTodo, Priority, Todo};
+
+/// What a rule looks for in a new todo's text, ignoring case and accents.
+#[derive(Clone, Debug, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum Condition {
+    TextContains(String),
+    TextStartsWith(String),
+}
+
+impl Condition {
+    fn matches(&self, text: &str) -> bool {
+        let text = search::normalize(text);
+        match self {
+            Condition::TextContains(needle) => text.contains(&search::normalize(needle)),
+            Condition::TextStartsWith(prefix) => text.starts_with(&search::normalize(prefix)),
+        }
+    }
+
+    fn is_empty(&self) -> bool {
+        match self {
+            Condition::TextContains(text) | Condition::TextStartsWith(text) => text.trim().is_empty(),
+        }
+    }
+}
+
+/// What a matching rule fills in. Category, priority and color only fill
+/// gaps, so values the todo was created with, or that an earlier rule set,
+/// win; tags are added.
+#[derive(Clone, Debug, Default, Serialize, Deserialize)]
+pub struct Actions {
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub category: Option<String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub priority: Option<Priority>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub tags: Option<Vec<String>>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub color: Option<String>,
+}
+
+impl Actions {
+    fn is_empty(&self) -> bool {
+        self.category.is_none() && self.priority.is_none() && self.tags.as_ref().is_none_or(Vec::is_empty) && self.color.is_none()
+    }
+
+    fn apply(&self, new_todo: &mut NewTodo) {
+        if new_todo.category.is_none() {
+            new_todo.category = self.category.clone();
+        }
+        if new_todo.priority.is_none() {
+            new_todo.priority = self.priority;
+        }
+        if new_todo.color.is_none() {
+            new_todo.color = self.color.clone();
+        }
+        for tag in self.tags.iter().flatten() {
+            let tags = new_todo.tags.get_or_insert_with(Vec::new);
+            if !tags.contains(tag) {
+                tags.push(tag.clone());
+            }
+        }
+    }
+}
+
+/// "If the text contains 'invoice', file it under Finance with high
+/// priority." A user's rules run in `position` order on every todo they add.
+#[derive(Clone, Debug, Serialize)]
+pub struct Rule {
+    pub id: String,
+    pub name: String,
+    #[serde(rename = "if")]
+    pub condition: Condition,
+    #[serde(rename = "then")]
+    pub actions: Actions,
+    pub position: i64,
+    pub created_at: DateTime<Utc>,
+    pub updated_at: DateTime<Utc>,
+}
+
+impl Rule {
+    /// `None` for rows whose JSON no longer parses, which are skipped.
+    fn from_row(row: &SqliteRow) -> Option<Self> {
+        Some(Rule {
+            id: row.get("id"),
+            name: row.get("name"),
+            condition: serde_json::from_str(&row.get::<String, _>("condition")).ok()?,
+            actions: serde_json::from_str(&row.get::<String, _>("actions")).ok()?,
+            position: row.get("position"),
+            created_at: row.get("created_at"),
+            updated_at: row.get("updated_at"),
+        })
+    }
+}
+
+#[derive(Debug, Deserialize)]
+pub struct NewRule {
+    pub name: String,
+    #[serde(rename = "if")]
+    pub condition: Condition,
+    #[serde(rename = "then")]
+    pub actions: Actions,
+    #[serde(default)]
+    pub position: i64,
+}
+
+impl NewRule {
+    /// The rule with its color normalized; `None` if it has no name, an empty
+    /// condition, nothing to do or a color that isn't one.
+    pub fn validated(mut self) -> Option<Self> {
+        if self.name.trim().is_empty() || self.condition.is_empty() || self.actions.is_empty() {
+            return None;
+        }
+        if let Some(color) = &self.actions.color {
+            self.actions.color = Some(colors::normalize(color)?);
+        }
+        Some(self)
+    }
+}
+
+/// Fills in `new_todo` from every rule that matches its text.
+pub fn apply(rules: &[Rule], new_todo: &mut NewTodo) {
+    for rule in rules {
+        if rule.condition.matches(&new_todo.text) {
+            rule.actions.apply(new_todo);
+        }
+    }
+}
+
+/// An existing todo a rule would have matched, with what it would have got.
+#[derive(Debug, Serialize)]
+pub struct PreviewMatch {
+    pub id: String,
+    pub text: String,
+    pub category: Option<String>,
+    pub priority: Option<Priority>,
+    pub tags: Vec<String>,
+    pub color: Option<String>,
+}
+
+/// How `rule` would have treated `todos` had they been added with it in
+/// place, without saving anything.
+pub fn preview(rule: &NewRule, todos: &[Todo]) -> Vec<PreviewMatch> {
+    todos
+        .iter()
+        .filter(|todo| rule.condition.matches(&todo.text))
+        .map(|todo| {
+            let mut new_todo = NewTodo {
+                text: todo.text.clone(),
+                notes: None,
+                category: todo.category.clone(),
+                tags: todo.tags.is_some().then(|| todo.tag_list()),
+                color: todo.color.clone(),
+                latitude: None,
+                longitude: None,
+                location_name: None,
+                priority: todo.priority,
+                due_date: None,
+                due: None,
+                estimate_minutes: None,
+                spent_minutes: None,
+                quick_add: false,
+            };
+            rule.actions.apply(&mut new_todo);
+            PreviewMatch {
+                id: todo.id.clone(),
+                text: new_todo.text,
+                category: new_todo.category,
+                priority: new_todo.priority,
+                tags: new_todo.tags.unwrap_or_default(),
+                color: new_todo.color,
+            }
+        })
+        .collect()
+}
+
+const RULE_COLUMNS: &str = "id, name, condition, actions, position, created_at, updated_at";
+
+pub async fn create_rule(db: &Database, user_id: &str, new_rule: NewRule) -> Result<Rule, sqlx::Error> {
+    let id = Uuid::new_v4().to_string();
+    let now = Utc::now();
+
+    sqlx::query("INSERT INTO rules (id, user_id, name, condition, actions, position, created_at, updated_at) VALUES (?, ?, ?, ?, ?, ?, ?, ?)")
+        .bind(&id)
+        .bind(user_id)
+        .bind(&new_rule.name)
+        .bind(serde_json::to_string(&new_rule.condition).unwrap_or_default())
+        .bind(serde_json::to_string(&new_rule.actions).unwrap_or_default())
+        .bind(new_rule.position)
+        .bind(now)
+        .bind(now)
+        .execute(&mut *db.write().await?)
+        .await?;
+
+    Ok(Rule {
+        id,
+        name: new_rule.name,
+        condition: new_rule.condition,
+        actions: new_rule.actions,
+        position: new_rule.position,
+        created_at: now,
+        updated_at: now,
+    })
+}
+
+/// A user's rules in the order they run.
+pub async fn get_rules(pool: &SqlitePool, user_id: &str) -> Result<Vec<Rule>, sqlx::Error> {
+    let rows = sqlx::query(&format!("SELECT {} FROM rules WHERE user_id = ? ORDER BY position, created_at", RULE_COLUMNS))
+        .bind(user_id)
+        .fetch_all(pool)
+        .await?;
+
+    Ok(rows.iter().filter_map(Rule::from_row).collect())
+}
+
+/// Replaces a rule; `None` if the user has no such rule.
+pub async fn update_rule(db: &Database, user_id: &str, id: &str, rule: NewRule) -> Result<Option<Rule>, sqlx::Error> {
+    let result = sqlx::query("UPDATE rules SET name = ?, condition = ?, actions = ?, position = ?, updated_at = ? WHERE id = ? AND user_id = ?")
+        .bind(&rule.name)
+        .bind(serde_json::to_string(&rule.condition).unwrap_or_default())
+        .bind(serde_json::to_string(&rule.actions).unwrap_or_default())
+        .bind(rule.position)
+        .bind(Utc::now())
+        .bind(id)
+        .bind(user_id)
+        .execute(&mut *db.write().await?)
+        .await?;
+    if result.rows_affected() == 0 {
+        return Ok(None);
+    }
+
+    let row = sqlx::query(&format!("SELECT {} FROM rules WHERE id = ?", RULE_COLUMNS))
+        .bind(id)
+        .fetch_one(db.get_pool())
+        .await?;
+    Ok(Rule::from_row(&row))
+}
+
+pub async fn delete_rule(db: &Database, user_id: &str, id: &str) -> Result<bool, sqlx::Error> {
+    let result = sqlx::query("DELETE FROM rules WHERE id = ? AND user_id = ?")
+        .bind(id)
+        .bind(user_id)
+        .execute(&mut *db.write().await?)
+        .await?;
+
+    Ok(result.rows_affected() > 0)
+}
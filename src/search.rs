@@ -0,0 +1,17 @@
+use unicode_normalization::{char::is_combining_mark, UnicodeNormalization};
+
+use crate::encryption;
+
+/// `text` as it's compared when searching: accents stripped, case folded
+/// and in NFC, so "cafe" finds "Café" and "strasse" finds "Straße".
+pub fn normalize(text: &str) -> String {
+    let lowered: String = text.nfd().filter(|c| !is_combining_mark(*c)).flat_map(char::to_lowercase).collect();
+    // The case folds lowercasing leaves out
+    lowered.replace('ß', "ss").replace('ς', "σ").nfc().collect()
+}
+
+/// What goes in a todo's `search_text` for `text`. Nothing while
+/// encryption is on: the column would give away the text it protects.
+pub fn search_text(text: &str) -> Option<String> {
+    (!encryption::is_enabled()).then(|| normalize(text))
+}
@@ -0,0 +1,180 @@
+use chrono::{Duration, Utc};
+
+use crate::simple_auth::{AuthError, AuthService, RegisterRequest};
+use crate::simple_db::{Database, NewTodo, Priority, Scope, TodoError};
+
+const NAMES: &[&str] = &[
+    "alice", "bob", "carol", "dave", "erin", "frank", "grace", "heidi", "ivan", "judy", "mallory", "niaj", "olivia",
+    "peggy", "rupert", "sybil", "trent", "victor", "walter", "yara",
+];
+
+/// Todo texts by category, with tags that suit them.
+const CATEGORIES: &[(&str, &[&str], &[&str])] = &[
+    (
+        "work",
+        &["meeting", "review", "email"],
+        &[
+            "Prepare slides for the quarterly review",
+            "Reply to the vendor about the contract renewal",
+            "Review the pull request for the billing fix",
+            "Write up notes from the planning meeting",
+            "Update the on-call runbook",
+            "Schedule 1:1s for next week",
+            "Draft the hiring plan for Q3",
+            "Fix the flaky integration test",
+        ],
+    ),
+    (
+        "home",
+        &["chores", "repairs"],
+        &[
+            "Clean out the garage",
+            "Fix the leaking kitchen tap",
+            "Replace the smoke alarm batteries",
+            "Mow the lawn",
+            "Book a chimney sweep",
+            "Sort the recycling",
+        ],
+    ),
+    (
+        "errands",
+        &["shopping", "outside"],
+        &[
+            "Buy milk and eggs",
+            "Pick up the dry cleaning",
+            "Return the library books",
+            "Get a birthday card for Sam",
+            "Drop off the parcel at the post office",
+            "Renew the car registration",
+        ],
+    ),
+    (
+        "health",
+        &["appointments", "fitness"],
+        &[
+            "Book a dentist appointment",
+            "Go for a 5k run",
+            "Refill the prescription",
+            "Sign up for the yoga class",
+            "Schedule the annual check-up",
+        ],
+    ),
+    (
+        "finance",
+        &["bills", "taxes"],
+        &[
+            "Pay the electricity bill",
+            "File the expense report",
+            "Gather documents for the tax return",
+            "Cancel the unused streaming subscription",
+            "Set up the monthly savings transfer",
+        ],
+    ),
+    (
+        "learning",
+        &["reading", "courses"],
+        &[
+            "Finish chapter 4 of the Rust book",
+            "Watch the talk on database indexing",
+            "Practise Spanish for 20 minutes",
+            "Read the article on async cancellation",
+        ],
+    ),
+];
+
+const NOTES: &[&str] = &[
+    "Check with the team first.",
+    "See the **shared doc** for details.",
+    "Needs to happen before the end of the month.",
+    "- [ ] first step\n- [ ] second step",
+];
+
+/// What `seed` created.
+pub struct Seeded {
+    pub usernames: Vec<String>,
+    pub todos: usize,
+    pub completed: usize,
+}
+
+/// Creates `users` users with `password` and spreads `todos` todos across
+/// them, through the same `AuthService` and `Database` calls the API uses.
+/// Todos get a mix of categories, tags, priorities and due dates (overdue,
+/// upcoming and none), and about a third are completed. In an empty
+/// database the same `seed` gives the same data.
+pub async fn seed(
+    db: &Database,
+    auth_service: &AuthService,
+    users: usize,
+    todos: usize,
+    password: &str,
+    seed: u64,
+) -> Result<Seeded, Box<dyn std::error::Error>> {
+    let mut rng = fastrand::Rng::with_seed(seed);
+    let mut seeded = Seeded { usernames: Vec::new(), todos: 0, completed: 0 };
+    let mut user_ids = Vec::new();
+
+    while user_ids.len() < users {
+        let username = format!("{}{}", rng.choice(NAMES).unwrap(), rng.u32(100..1000));
+        let request = RegisterRequest {
+            username: username.clone(),
+            email: format!("{}@example.com", username),
+            password: password.to_string(),
+        };
+        match auth_service.register(request).await {
+            Ok(response) => {
+                user_ids.push(response.user_id);
+                seeded.usernames.push(username);
+            }
+            // Seeded before; pick another name
+            Err(AuthError::UserExists) => continue,
+            Err(e) => return Err(e.into()),
+        }
+    }
+
+    for i in 0..todos {
+        let Some(user_id) = user_ids.get(i % user_ids.len().max(1)) else {
+            break;
+        };
+        let (category, tags, texts) = CATEGORIES[rng.usize(..CATEGORIES.len())];
+        let new_todo = NewTodo {
+            text: rng.choice(texts).unwrap().to_string(),
+            notes: (rng.u8(..8) == 0).then(|| rng.choice(NOTES).unwrap().to_string()),
+            // A few uncategorised todos, as real lists have
+            category: (rng.u8(..10) != 0).then(|| category.to_string()),
+            tags: rng.bool().then(|| vec![rng.choice(tags).unwrap().to_string()]),
+            color: None,
+            latitude: None,
+            longitude: None,
+            location_name: None,
+            priority: match rng.u8(..4) {
+                0 => Some(Priority::High),
+                1 => Some(Priority::Medium),
+                2 => Some(Priority::Low),
+                _ => None,
+            },
+            due_date: match rng.u8(..10) {
+                // Overdue
+                0..=1 => Some(Utc::now() - Duration::hours(rng.i64(1..24 * 14))),
+                // Coming up in the next few weeks
+                2..=6 => Some(Utc::now() + Duration::hours(rng.i64(1..24 * 30))),
+                _ => None,
+            },
+            due: None,
+            estimate_minutes: (rng.u8(..3) == 0).then(|| rng.i64(1..16) * 15),
+            spent_minutes: None,
+            quick_add: false,
+        };
+
+        let scope = Scope { user_id: user_id.clone(), workspace_id: None };
+        let todo = db.create_todo(new_todo, &scope).await?;
+        seeded.todos += 1;
+        if rng.u8(..3) == 0 {
+            match db.toggle_todo(&todo.id, &scope.user_id, None).await {
+                Err(TodoError::DatabaseError(e)) => return Err(e.into()),
+                // The todo was just created, so nothing else can go wrong
+                _ => seeded.completed += 1,
+            }
+        }
+    }
+    Ok(seeded)
+}
@@ -0,0 +1,114 @@
+use axum::{
+    extract::Request,
+    http::{header, HeaderMap, StatusCode},
+    middleware::Next,
+    response::{AppendHeaders, IntoResponse, Response},
+    Json,
+};
+use serde::Deserialize;
+
+use crate::simple_auth::{AuthResponse, AuthService};
+
+/// HttpOnly cookie holding the session token, so scripts (and XSS) can't read it.
+pub const SESSION_COOKIE: &str = "session";
+/// Readable by the page's own scripts, which echo it in `X-CSRF-Token`.
+pub const CSRF_COOKIE: &str = "csrf_token";
+pub const CSRF_HEADER: &str = "x-csrf-token";
+
+/// `?cookie=true` on login and register: set the token as a cookie instead of
+/// returning it, for the built-in web UI.
+#[derive(Debug, Default, Deserialize)]
+pub struct SessionQuery {
+    #[serde(default)]
+    pub cookie: bool,
+}
+
+/// Marks a request authenticated by the session cookie rather than a bearer
+/// token; holds the token id its CSRF token is derived from.
+#[derive(Clone, Debug)]
+pub struct CookieSession {
+    pub jti: String,
+}
+
+/// The value of cookie `name` in the request's `Cookie` headers.
+pub fn cookie<'a>(headers: &'a HeaderMap, name: &str) -> Option<&'a str> {
+    headers
+        .get_all(header::COOKIE)
+        .iter()
+        .filter_map(|value| value.to_str().ok())
+        .flat_map(|value| value.split(';'))
+        .filter_map(|pair| pair.trim().split_once('='))
+        .find(|(key, _)| *key == name)
+        .map(|(_, value)| value)
+}
+
+/// Whether cookies get the `Secure` attribute: `COOKIE_SECURE`, defaulting to
+/// on when the app serves HTTPS itself. Set it behind a TLS-terminating proxy.
+fn secure() -> bool {
+    std::env::var("COOKIE_SECURE")
+        .ok()
+        .and_then(|value| value.parse().ok())
+        .unwrap_or_else(|| std::env::var("USE_HTTPS").is_ok_and(|value| value == "true"))
+}
+
+fn set_cookie(name: &str, value: &str, max_age: i64, http_only: bool) -> (header::HeaderName, String) {
+    let mut cookie = format!("{}={}; Path=/; Max-Age={}; SameSite=Strict", name, value, max_age);
+    if http_only {
+        cookie.push_str("; HttpOnly");
+    }
+    if secure() {
+        cookie.push_str("; Secure");
+    }
+    (header::SET_COOKIE, cookie)
+}
+
+/// Expires both cookies, e.g. on logout.
+pub fn clear_cookies() -> AppendHeaders<[(header::HeaderName, String); 2]> {
+    AppendHeaders([set_cookie(SESSION_COOKIE, "", 0, true), set_cookie(CSRF_COOKIE, "", 0, false)])
+}
+
+/// The response to a login, registration or workspace switch: the token in
+/// the body, or moved into the session cookie (next to a fresh CSRF token)
+/// when `cookie` is set.
+pub fn respond(auth_service: &AuthService, mut response: AuthResponse, cookie: bool) -> Response {
+    if !cookie {
+        return Json(response).into_response();
+    }
+    let csrf_token = match auth_service.csrf_token_for(&response.token) {
+        Ok(csrf_token) => csrf_token,
+        Err(err) => return StatusCode::from(err).into_response(),
+    };
+    let max_age = auth_service.token_expiry().num_seconds();
+    let token = std::mem::take(&mut response.token);
+    let cookies = AppendHeaders([
+        set_cookie(SESSION_COOKIE, &token, max_age, true),
+        set_cookie(CSRF_COOKIE, &csrf_token, max_age, false),
+    ]);
+    (cookies, Json(response)).into_response()
+}
+
+/// Double-submit CSRF check for cookie sessions: requests other than GET,
+/// HEAD and OPTIONS must send the `csrf_token` cookie back in `X-CSRF-Token`.
+/// Another site can make the browser send the cookies but can't read them to
+/// set the header. The token is also an HMAC of the session's token id, so a
+/// cookie planted by a sibling subdomain doesn't pass either. Bearer-token
+/// requests aren't affected; browsers never attach those on their own.
+pub async fn require_csrf(
+    axum::extract::State(auth_service): axum::extract::State<std::sync::Arc<AuthService>>,
+    request: Request,
+    next: Next,
+) -> Result<Response, StatusCode> {
+    if let Some(session) = request.extensions().get::<CookieSession>()
+        && !request.method().is_safe()
+    {
+        let sent = request.headers().get(CSRF_HEADER).and_then(|value| value.to_str().ok());
+        let valid = sent.is_some_and(|sent| {
+            Some(sent) == cookie(request.headers(), CSRF_COOKIE) && auth_service.verify_csrf(&session.jti, sent)
+        });
+        if !valid {
+            tracing::warn!(method = %request.method(), uri = %request.uri(), "Rejected request without a valid CSRF token");
+            return Err(StatusCode::FORBIDDEN);
+        }
+    }
+    Ok(next.run(request).await)
+}
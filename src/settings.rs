@@ -0,0 +1,320 @@
+use chrono::{DateTime, Datelike, Utc};
+use chrono_tz::Tz;
+use serde::{Deserialize, Serialize};
+use sqlx::{sqlite::SqliteRow, Row, SqlitePool};
+
+use crate::i18n::{self, Language};
+use crate::simple_db::{Database, Priority};
+
+/// How dates are shown in emails and server-rendered pages.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum DateFormat {
+    /// May 1, 2024 17:00
+    #[default]
+    Text,
+    /// 2024-05-01 17:00
+    Iso,
+    /// 05/01/2024 5:00 PM
+    Us,
+    /// 01.05.2024 17:00
+    Eu,
+}
+
+impl DateFormat {
+    pub fn as_str(self) -> &'static str {
+        match self {
+            DateFormat::Text => "text",
+            DateFormat::Iso => "iso",
+            DateFormat::Us => "us",
+            DateFormat::Eu => "eu",
+        }
+    }
+
+    /// `month` names the month in the `text` format, which spells it out.
+    fn date_pattern(self, language: Language, month: u32) -> String {
+        match (self, language) {
+            (DateFormat::Text, Language::En) => "%b %-d, %Y".to_string(),
+            (DateFormat::Text, Language::Es) => format!("%-d {} %Y", i18n::short_month(month, language)),
+            (DateFormat::Iso, _) => "%Y-%m-%d".to_string(),
+            (DateFormat::Us, _) => "%m/%d/%Y".to_string(),
+            (DateFormat::Eu, _) => "%d.%m.%Y".to_string(),
+        }
+    }
+
+    fn time_pattern(self) -> &'static str {
+        match self {
+            DateFormat::Us => "%-I:%M %p",
+            _ => "%H:%M",
+        }
+    }
+}
+
+impl std::str::FromStr for DateFormat {
+    type Err = ();
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "text" => Ok(DateFormat::Text),
+            "iso" => Ok(DateFormat::Iso),
+            "us" => Ok(DateFormat::Us),
+            "eu" => Ok(DateFormat::Eu),
+            _ => Err(()),
+        }
+    }
+}
+
+/// Which todos the web UI shows first.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum DefaultView {
+    #[default]
+    All,
+    Open,
+    Overdue,
+    Upcoming,
+}
+
+impl DefaultView {
+    pub fn as_str(self) -> &'static str {
+        match self {
+            DefaultView::All => "all",
+            DefaultView::Open => "open",
+            DefaultView::Overdue => "overdue",
+            DefaultView::Upcoming => "upcoming",
+        }
+    }
+}
+
+impl std::str::FromStr for DefaultView {
+    type Err = ();
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "all" => Ok(DefaultView::All),
+            "open" => Ok(DefaultView::Open),
+            "overdue" => Ok(DefaultView::Overdue),
+            "upcoming" => Ok(DefaultView::Upcoming),
+            _ => Err(()),
+        }
+    }
+}
+
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum Theme {
+    /// Follow the browser's light/dark preference
+    #[default]
+    System,
+    Light,
+    Dark,
+}
+
+impl Theme {
+    pub fn as_str(self) -> &'static str {
+        match self {
+            Theme::System => "system",
+            Theme::Light => "light",
+            Theme::Dark => "dark",
+        }
+    }
+}
+
+impl std::str::FromStr for Theme {
+    type Err = ();
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "system" => Ok(Theme::System),
+            "light" => Ok(Theme::Light),
+            "dark" => Ok(Theme::Dark),
+            _ => Err(()),
+        }
+    }
+}
+
+/// Per-user preferences. Users without a `user_settings` row get the defaults.
+#[derive(Clone, Debug, Serialize)]
+pub struct UserSettings {
+    /// IANA timezone name, e.g. `Europe/Berlin`
+    pub timezone: String,
+    pub date_format: DateFormat,
+    /// Priority for new todos created without one
+    pub default_priority: Option<Priority>,
+    pub default_view: DefaultView,
+    pub theme: Theme,
+    /// Email a summary of the day's todos every morning
+    pub daily_digest: bool,
+    /// Email last week's report when it's generated, early on Monday (UTC)
+    pub weekly_report: bool,
+    /// Also email notifications (assignments, mentions); they are always listed under /notifications
+    pub email_notifications: bool,
+    /// Language of emails and notifications
+    pub language: Language,
+    /// Appear on the leaderboards of the user's workspaces
+    pub leaderboard: bool,
+}
+
+impl Default for UserSettings {
+    fn default() -> Self {
+        UserSettings {
+            timezone: "UTC".to_string(),
+            date_format: DateFormat::default(),
+            default_priority: None,
+            default_view: DefaultView::default(),
+            theme: Theme::default(),
+            daily_digest: false,
+            weekly_report: false,
+            email_notifications: true,
+            language: Language::default(),
+            leaderboard: true,
+        }
+    }
+}
+
+impl UserSettings {
+    pub(crate) fn from_row(row: &SqliteRow) -> Self {
+        // Unknown values (e.g. written by a newer version) fall back to the defaults
+        UserSettings {
+            timezone: row.get("timezone"),
+            date_format: row.get::<String, _>("date_format").parse().unwrap_or_default(),
+            default_priority: row
+                .get::<Option<String>, _>("default_priority")
+                .and_then(|p| p.parse().ok()),
+            default_view: row.get::<String, _>("default_view").parse().unwrap_or_default(),
+            theme: row.get::<String, _>("theme").parse().unwrap_or_default(),
+            daily_digest: row.get("daily_digest"),
+            weekly_report: row.get("weekly_report"),
+            email_notifications: row.get("email_notifications"),
+            language: row.get::<String, _>("language").parse().unwrap_or_default(),
+            leaderboard: row.get("leaderboard"),
+        }
+    }
+
+    pub fn tz(&self) -> Tz {
+        self.timezone.parse().unwrap_or(Tz::UTC)
+    }
+
+    /// `time` as a date in the user's timezone, date format and language.
+    pub fn format_date(&self, time: DateTime<Utc>) -> String {
+        let local = time.with_timezone(&self.tz());
+        local.format(&self.date_format.date_pattern(self.language, local.month())).to_string()
+    }
+
+    /// `time` as a date and time in the user's timezone, date format and language.
+    pub fn format_datetime(&self, time: DateTime<Utc>) -> String {
+        let local = time.with_timezone(&self.tz());
+        let pattern = format!("{} {}", self.date_format.date_pattern(self.language, local.month()), self.date_format.time_pattern());
+        local.format(&pattern).to_string()
+    }
+}
+
+/// Partial update; fields left out keep their current value.
+#[derive(Debug, Deserialize)]
+pub struct UpdateSettings {
+    pub timezone: Option<String>,
+    pub date_format: Option<DateFormat>,
+    /// `null` leaves new todos without a priority
+    #[serde(default, deserialize_with = "nullable")]
+    pub default_priority: Option<Option<Priority>>,
+    pub default_view: Option<DefaultView>,
+    pub theme: Option<Theme>,
+    pub daily_digest: Option<bool>,
+    pub weekly_report: Option<bool>,
+    pub email_notifications: Option<bool>,
+    pub language: Option<Language>,
+    pub leaderboard: Option<bool>,
+}
+
+/// Tells a field set to `null` (`Some(None)`) apart from one left out (`None`).
+fn nullable<'de, D, T>(deserializer: D) -> Result<Option<Option<T>>, D::Error>
+where
+    D: serde::Deserializer<'de>,
+    T: Deserialize<'de>,
+{
+    Option::<T>::deserialize(deserializer).map(Some)
+}
+
+#[derive(Debug)]
+pub enum SettingsError {
+    InvalidTimezone,
+    DatabaseError,
+}
+
+impl From<SettingsError> for axum::http::StatusCode {
+    fn from(error: SettingsError) -> Self {
+        match error {
+            SettingsError::InvalidTimezone => axum::http::StatusCode::UNPROCESSABLE_ENTITY,
+            SettingsError::DatabaseError => axum::http::StatusCode::INTERNAL_SERVER_ERROR,
+        }
+    }
+}
+
+impl From<sqlx::Error> for SettingsError {
+    fn from(_: sqlx::Error) -> Self {
+        SettingsError::DatabaseError
+    }
+}
+
+pub(crate) const SETTINGS_COLUMNS: &str = "timezone, date_format, default_priority, default_view, theme, daily_digest, weekly_report, email_notifications, language, leaderboard";
+
+pub async fn get_settings(pool: &SqlitePool, user_id: &str) -> Result<UserSettings, sqlx::Error> {
+    let row = sqlx::query(&format!("SELECT {} FROM user_settings WHERE user_id = ?", SETTINGS_COLUMNS))
+        .bind(user_id)
+        .fetch_optional(pool)
+        .await?;
+
+    Ok(row.as_ref().map(UserSettings::from_row).unwrap_or_default())
+}
+
+pub async fn update_settings(db: &Database, user_id: &str, update: UpdateSettings) -> Result<UserSettings, SettingsError> {
+    let mut settings = get_settings(db.get_pool(), user_id).await?;
+    if let Some(timezone) = update.timezone {
+        timezone.parse::<Tz>().map_err(|_| SettingsError::InvalidTimezone)?;
+        settings.timezone = timezone;
+    }
+    if let Some(date_format) = update.date_format {
+        settings.date_format = date_format;
+    }
+    if let Some(default_priority) = update.default_priority {
+        settings.default_priority = default_priority;
+    }
+    if let Some(default_view) = update.default_view {
+        settings.default_view = default_view;
+    }
+    if let Some(theme) = update.theme {
+        settings.theme = theme;
+    }
+    if let Some(daily_digest) = update.daily_digest {
+        settings.daily_digest = daily_digest;
+    }
+    if let Some(weekly_report) = update.weekly_report {
+        settings.weekly_report = weekly_report;
+    }
+    if let Some(email_notifications) = update.email_notifications {
+        settings.email_notifications = email_notifications;
+    }
+    if let Some(language) = update.language {
+        settings.language = language;
+    }
+    if let Some(leaderboard) = update.leaderboard {
+        settings.leaderboard = leaderboard;
+    }
+
+    sqlx::query(&format!("INSERT INTO user_settings (user_id, {}, updated_at) VALUES (?, ?, ?, ?, ?, ?, ?, ?, ?, ?, ?, ?) ON CONFLICT (user_id) DO UPDATE SET timezone = excluded.timezone, date_format = excluded.date_format, default_priority = excluded.default_priority, default_view = excluded.default_view, theme = excluded.theme, daily_digest = excluded.daily_digest, weekly_report = excluded.weekly_report, email_notifications = excluded.email_notifications, language = excluded.language, leaderboard = excluded.leaderboard, updated_at = excluded.updated_at", SETTINGS_COLUMNS))
+        .bind(user_id)
+        .bind(&settings.timezone)
+        .bind(settings.date_format.as_str())
+        .bind(settings.default_priority.map(Priority::as_str))
+        .bind(settings.default_view.as_str())
+        .bind(settings.theme.as_str())
+        .bind(settings.daily_digest)
+        .bind(settings.weekly_report)
+        .bind(settings.email_notifications)
+        .bind(settings.language.as_str())
+        .bind(settings.leaderboard)
+        .bind(Utc::now())
+        .execute(&mut *db.write().await?)
+        .await?;
+    Ok(settings)
+}
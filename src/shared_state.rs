@@ -0,0 +1,235 @@
+use moka::{future::Cache, Expiry};
+use std::collections::HashMap;
+use std::sync::{
+    atomic::{AtomicU64, Ordering},
+    Arc, Mutex,
+};
+use std::time::{Duration, Instant};
+
+/// Short-lived state that has to agree across app instances: cached query
+/// results, rate limit counters and revoked tokens. It lives in process memory
+/// by default; with the `redis` feature and `REDIS_URL` set it is kept in Redis
+/// so several instances behind a load balancer see the same values.
+#[derive(Clone)]
+pub enum SharedState {
+    Local(LocalState),
+    #[cfg(feature = "redis")]
+    Redis(redis::aio::ConnectionManager),
+}
+
+#[derive(Clone)]
+pub struct LocalState {
+    values: Cache<String, Expiring<String>>,
+    counters: Cache<String, Expiring<Arc<AtomicU64>>>,
+    /// Revoked token IDs and when they can be forgotten. Kept apart from
+    /// `values`, which evicts entries when full: an evicted revocation would
+    /// bring a logged-out token back to life.
+    revoked: Arc<Mutex<HashMap<String, Instant>>>,
+}
+
+#[derive(Clone)]
+struct Expiring<T> {
+    value: T,
+    ttl: Duration,
+}
+
+struct PerEntryTtl;
+
+impl<T> Expiry<String, Expiring<T>> for PerEntryTtl {
+    fn expire_after_create(&self, _key: &String, entry: &Expiring<T>, _created_at: Instant) -> Option<Duration> {
+        Some(entry.ttl)
+    }
+
+    /// Writing a key again starts its TTL over, as `SET EX` does in Redis.
+    fn expire_after_update(&self, _key: &String, entry: &Expiring<T>, _updated_at: Instant, _duration_until_expiry: Option<Duration>) -> Option<Duration> {
+        Some(entry.ttl)
+    }
+}
+
+#[derive(Debug)]
+pub struct StateError(String);
+
+impl std::fmt::Display for StateError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "shared state unavailable: {}", self.0)
+    }
+}
+
+impl std::error::Error for StateError {}
+
+#[cfg(feature = "redis")]
+impl From<redis::RedisError> for StateError {
+    fn from(err: redis::RedisError) -> Self {
+        StateError(err.to_string())
+    }
+}
+
+/// Keys are namespaced so the Redis database can be shared with other services.
+#[cfg(feature = "redis")]
+const REDIS_PREFIX: &str = "todo-app:";
+
+impl SharedState {
+    pub fn local() -> Self {
+        SharedState::Local(LocalState {
+            values: Cache::builder()
+                .max_capacity(100_000)
+                .expire_after(PerEntryTtl)
+                .support_invalidation_closures()
+                .build(),
+            counters: Cache::builder().max_capacity(100_000).expire_after(PerEntryTtl).build(),
+            revoked: Arc::default(),
+        })
+    }
+
+    /// Redis when `REDIS_URL` is set (and the `redis` feature is enabled), process memory otherwise.
+    pub async fn from_env() -> Result<Self, StateError> {
+        let Ok(url) = std::env::var("REDIS_URL") else {
+            return Ok(Self::local());
+        };
+
+        #[cfg(feature = "redis")]
+        {
+            let client = redis::Client::open(url)?;
+            Ok(SharedState::Redis(redis::aio::ConnectionManager::new(client).await?))
+        }
+        #[cfg(not(feature = "redis"))]
+        {
+            let _ = url;
+            tracing::warn!("REDIS_URL is set but this build lacks the `redis` feature; using in-process state");
+            Ok(Self::local())
+        }
+    }
+
+    pub async fn get(&self, key: &str) -> Result<Option<String>, StateError> {
+        match self {
+            SharedState::Local(local) => Ok(local.values.get(key).await.map(|entry| entry.value)),
+            #[cfg(feature = "redis")]
+            SharedState::Redis(conn) => {
+                use redis::AsyncCommands;
+                Ok(conn.clone().get(format!("{}{}", REDIS_PREFIX, key)).await?)
+            }
+        }
+    }
+
+    pub async fn set(&self, key: &str, value: String, ttl: Duration) -> Result<(), StateError> {
+        match self {
+            SharedState::Local(local) => {
+                local.values.insert(key.to_string(), Expiring { value, ttl }).await;
+                Ok(())
+            }
+            #[cfg(feature = "redis")]
+            SharedState::Redis(conn) => {
+                use redis::AsyncCommands;
+                Ok(conn.clone().set_ex(format!("{}{}", REDIS_PREFIX, key), value, ttl.as_secs().max(1)).await?)
+            }
+        }
+    }
+
+    /// Deletes a value or a counter.
+    pub async fn delete(&self, key: &str) -> Result<(), StateError> {
+        match self {
+            SharedState::Local(local) => {
+                local.values.invalidate(key).await;
+                local.counters.invalidate(key).await;
+                Ok(())
+            }
+            #[cfg(feature = "redis")]
+            SharedState::Redis(conn) => {
+                use redis::AsyncCommands;
+                Ok(conn.clone().del(format!("{}{}", REDIS_PREFIX, key)).await?)
+            }
+        }
+    }
+
+    /// Deletes every key starting with `prefix`. Meant for rare bulk
+    /// invalidation; on Redis this is a SCAN over the keyspace.
+    pub async fn delete_prefix(&self, prefix: &str) -> Result<(), StateError> {
+        match self {
+            SharedState::Local(local) => {
+                let prefix = prefix.to_string();
+                local
+                    .values
+                    .invalidate_entries_if(move |key, _| key.starts_with(&prefix))
+                    .map_err(|e| StateError(e.to_string()))?;
+                Ok(())
+            }
+            #[cfg(feature = "redis")]
+            SharedState::Redis(conn) => {
+                use redis::AsyncCommands;
+                let mut scan_conn = conn.clone();
+                let mut keys: Vec<String> = Vec::new();
+                let mut iter = scan_conn.scan_match::<_, String>(format!("{}{}*", REDIS_PREFIX, prefix)).await?;
+                while let Some(key) = iter.next_item().await {
+                    keys.push(key);
+                }
+                drop(iter);
+                if !keys.is_empty() {
+                    conn.clone().del::<_, ()>(keys).await?;
+                }
+                Ok(())
+            }
+        }
+    }
+
+    /// Adds one to the counter for `key` in the current fixed window of length
+    /// `window` and returns the new count.
+    pub async fn increment(&self, key: &str, window: Duration) -> Result<u64, StateError> {
+        let window_secs = window.as_secs().max(1);
+        let current_window = chrono::Utc::now().timestamp() as u64 / window_secs;
+        self.count(&format!("{}:{}", key, current_window), window).await
+    }
+
+    /// Adds one to the counter `key` and returns the new count, atomically,
+    /// so concurrent callers each see a different count. The counter is
+    /// forgotten `ttl` after the last call.
+    pub async fn count(&self, key: &str, ttl: Duration) -> Result<u64, StateError> {
+        match self {
+            SharedState::Local(local) => {
+                let entry = local
+                    .counters
+                    .get_with(key.to_string(), async move { Expiring { value: Arc::new(AtomicU64::new(0)), ttl } })
+                    .await;
+                let count = entry.value.fetch_add(1, Ordering::Relaxed) + 1;
+                // Written back only to start the TTL over
+                local.counters.insert(key.to_string(), entry).await;
+                Ok(count)
+            }
+            #[cfg(feature = "redis")]
+            SharedState::Redis(conn) => {
+                let key = format!("{}{}", REDIS_PREFIX, key);
+                let (count,): (u64,) = redis::pipe()
+                    .incr(&key, 1)
+                    .expire(&key, ttl.as_secs().max(1) as i64)
+                    .ignore()
+                    .query_async(&mut conn.clone())
+                    .await?;
+                Ok(count)
+            }
+        }
+    }
+
+    /// Remembers `id` (a token's `jti`) as revoked for `ttl`. Unlike `set`,
+    /// nothing else stored here can push it out early.
+    pub async fn revoke(&self, id: &str, ttl: Duration) -> Result<(), StateError> {
+        match self {
+            SharedState::Local(local) => {
+                let now = Instant::now();
+                let mut revoked = local.revoked.lock().unwrap();
+                // Forget the ones whose tokens have expired anyway
+                revoked.retain(|_, until| *until > now);
+                revoked.insert(id.to_string(), now + ttl);
+                Ok(())
+            }
+            #[cfg(feature = "redis")]
+            SharedState::Redis(_) => self.set(&format!("revoked:{}", id), String::new(), ttl).await,
+        }
+    }
+
+    pub async fn is_revoked(&self, id: &str) -> Result<bool, StateError> {
+        match self {
+            SharedState::Local(local) => Ok(local.revoked.lock().unwrap().get(id).is_some_and(|until| *until > Instant::now())),
+            #[cfg(feature = "redis")]
+            SharedState::Redis(_) => Ok(self.get(&format!("revoked:{}", id)).await?.is_some()),
+        }
+    }
+}
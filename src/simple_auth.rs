@@ -1,20 +1,42 @@
 use axum::{
     extract::{Request, State},
-    http::{header::AUTHORIZATION, StatusCode},
+    http::{header::AUTHORIZATION, HeaderMap, StatusCode},
     middleware::Next,
-    response::Response,
+    response::{IntoResponse, Response},
+    Json,
 };
-use chrono::Utc;
+use chrono::{DateTime, Utc};
+use base64::{engine::general_purpose::URL_SAFE_NO_PAD, Engine};
 use jsonwebtoken::{decode, encode, DecodingKey, EncodingKey, Header, Validation};
+use ring::hmac;
 use serde::{Deserialize, Serialize};
-use sqlx::{Row, SqlitePool};
+use sqlx::{Connection, Row, SqlitePool};
 use std::sync::Arc;
 use uuid::Uuid;
 
+use crate::hooks::Event;
+use crate::outbox;
+use crate::password_strength::{self, Strength};
+use crate::session;
+use crate::shared_state::SharedState;
+use crate::simple_db::{Database, Scope};
+use crate::workspaces;
+use crate::writer::{WriteConnection, Writer};
+
 #[derive(Debug, Serialize, Deserialize)]
 pub struct Claims {
     pub sub: String,
+    /// Who issued the token (`JWT_ISSUER`)
+    pub iss: String,
+    /// Who the token is for (`JWT_AUDIENCE`)
+    pub aud: String,
+    pub iat: usize,
     pub exp: usize,
+    /// Token id, used to revoke a single token on logout
+    pub jti: String,
+    /// The workspace the token works in; personal todos when absent
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub ws: Option<String>,
 }
 
 #[derive(Debug, Deserialize)]
@@ -30,20 +52,111 @@ pub struct LoginRequest {
     pub password: String,
 }
 
+/// Body of `POST /auth/password`.
+#[derive(Debug, Deserialize)]
+pub struct ChangePasswordRequest {
+    pub current_password: String,
+    pub new_password: String,
+}
+
 #[derive(Debug, Serialize)]
 pub struct AuthResponse {
+    /// Omitted when the token was set as a session cookie instead
+    #[serde(skip_serializing_if = "String::is_empty")]
     pub token: String,
     pub user_id: String,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub workspace_id: Option<String>,
+}
+
+/// Body of `POST /workspaces/switch`; `null` switches back to personal todos.
+#[derive(Debug, Deserialize)]
+pub struct SwitchWorkspaceRequest {
+    pub workspace_id: Option<String>,
+}
+
+/// The signed-in user as returned by `GET /auth/me`.
+#[derive(Debug, Serialize)]
+pub struct Profile {
+    pub id: String,
+    pub username: String,
+    pub email: String,
+    pub created_at: DateTime<Utc>,
+    /// May use the `/admin` endpoints
+    pub is_admin: bool,
+}
+
+/// A full users row, including the password hash; used for administration and export.
+#[derive(Debug, Serialize, Deserialize)]
+pub struct UserRecord {
+    pub id: String,
+    pub username: String,
+    pub email: String,
+    pub password_hash: String,
+    pub created_at: DateTime<Utc>,
+    pub updated_at: DateTime<Utc>,
+    /// When the account was deactivated; it can't log in until re-enabled
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub disabled_at: Option<DateTime<Utc>>,
+    #[serde(default)]
+    pub is_admin: bool,
+}
+
+/// Body of `POST /auth/me/deactivate`.
+#[derive(Debug, Deserialize)]
+pub struct DeactivateRequest {
+    pub password: String,
+}
+
+/// How tokens are issued and checked. Read from `JWT_ISSUER` and
+/// `JWT_AUDIENCE` (both default `todo-app`), `JWT_EXPIRY_MINUTES` (default
+/// 1440) and `JWT_LEEWAY_SECS`, the clock skew allowed between instances
+/// when checking `exp` and `iat` (default 60).
+#[derive(Clone, Debug)]
+pub struct TokenConfig {
+    pub issuer: String,
+    pub audience: String,
+    pub expiry: chrono::Duration,
+    pub leeway: u64,
+}
+
+impl TokenConfig {
+    pub fn from_env() -> Self {
+        fn var<T: std::str::FromStr>(name: &str, default: T) -> T {
+            std::env::var(name).ok().and_then(|value| value.parse().ok()).unwrap_or(default)
+        }
+
+        TokenConfig {
+            issuer: var("JWT_ISSUER", "todo-app".to_string()),
+            audience: var("JWT_AUDIENCE", "todo-app".to_string()),
+            expiry: chrono::Duration::minutes(var("JWT_EXPIRY_MINUTES", 24 * 60).max(1)),
+            leeway: var("JWT_LEEWAY_SECS", 60),
+        }
+    }
 }
 
 pub struct AuthService {
     pool: SqlitePool,
+    writer: Writer,
     jwt_secret: String,
+    tokens: TokenConfig,
+    /// bcrypt cost for new hashes; older, cheaper hashes are upgraded on login
+    bcrypt_cost: u32,
+    /// Revoked token ids, shared so a logout is honored by every instance
+    shared: SharedState,
 }
 
 impl AuthService {
-    pub fn new(pool: SqlitePool, jwt_secret: String) -> Self {
-        Self { pool, jwt_secret }
+    pub fn new(db: &Database, jwt_secret: String, tokens: TokenConfig, shared: SharedState) -> Self {
+        Self { pool: db.get_pool().clone(), writer: db.writer(), jwt_secret, tokens, bcrypt_cost: bcrypt_cost(), shared }
+    }
+
+    async fn write(&self) -> Result<WriteConnection, AuthError> {
+        self.writer.acquire().await.map_err(|_| AuthError::DatabaseError)
+    }
+
+    fn hash_password(&self, password: &str) -> Result<String, AuthError> {
+        bcrypt::hash(password, self.bcrypt_cost).map_err(|_| AuthError::HashError)
     }
 
     pub async fn register(&self, req: RegisterRequest) -> Result<AuthResponse, AuthError> {
@@ -58,32 +171,38 @@ impl AuthService {
         if existing.is_some() {
             return Err(AuthError::UserExists);
         }
+        check_strength(&req.password, &[&req.username, &req.email])?;
 
         // Hash password
-        let password_hash = bcrypt::hash(&req.password, bcrypt::DEFAULT_COST)
-            .map_err(|_| AuthError::HashError)?;
+        let password_hash = self.hash_password(&req.password)?;
 
         // Create user
         let id = Uuid::new_v4().to_string();
         let now = Utc::now();
 
+        let mut conn = self.write().await?;
+        let mut tx = conn.begin().await.map_err(|_| AuthError::DatabaseError)?;
         sqlx::query("INSERT INTO users (id, username, email, password_hash, created_at, updated_at) VALUES (?, ?, ?, ?, ?, ?)")
             .bind(&id)
             .bind(&req.username)
             .bind(&req.email)
             .bind(&password_hash)
-            .bind(&now)
-            .bind(&now)
-            .execute(&self.pool)
+            .bind(now)
+            .bind(now)
+            .execute(&mut *tx)
             .await
             .map_err(|_| AuthError::DatabaseError)?;
+        outbox::record(&mut tx, &Event::UserRegistered { user_id: &id, username: &req.username })
+            .await
+            .map_err(|_| AuthError::DatabaseError)?;
+        tx.commit().await.map_err(|_| AuthError::DatabaseError)?;
 
-        let token = self.create_token(&id)?;
-        Ok(AuthResponse { token, user_id: id })
+        let token = self.create_token(&id, None)?;
+        Ok(AuthResponse { token, user_id: id, workspace_id: None })
     }
 
     pub async fn login(&self, req: LoginRequest) -> Result<AuthResponse, AuthError> {
-        let row = sqlx::query("SELECT id, password_hash FROM users WHERE username = ?")
+        let row = sqlx::query("SELECT id, password_hash, disabled_at FROM users WHERE username = ?")
             .bind(&req.username)
             .fetch_optional(&self.pool)
             .await
@@ -99,44 +218,393 @@ impl AuthService {
         if !valid {
             return Err(AuthError::InvalidCredentials);
         }
+        // Only after the password: don't tell guessers which accounts exist
+        if row.get::<Option<DateTime<Utc>>, _>("disabled_at").is_some() {
+            return Err(AuthError::AccountDisabled);
+        }
+        self.upgrade_hash(&user_id, &req.password, &stored_hash).await;
+
+        let token = self.create_token(&user_id, None)?;
+        Ok(AuthResponse { token, user_id, workspace_id: None })
+    }
+
+    /// Rehashes the password with the current cost if `stored_hash` was made
+    /// with a lower one. Only logged on failure: the login itself succeeded.
+    async fn upgrade_hash(&self, user_id: &str, password: &str, stored_hash: &str) {
+        let Ok(parts) = stored_hash.parse::<bcrypt::HashParts>() else {
+            return;
+        };
+        if parts.get_cost() >= self.bcrypt_cost {
+            return;
+        }
+        let result = match self.hash_password(password) {
+            Ok(password_hash) => async {
+                sqlx::query("UPDATE users SET password_hash = ? WHERE id = ? AND password_hash = ?")
+                    .bind(password_hash)
+                    .bind(user_id)
+                    .bind(stored_hash)
+                    .execute(&mut *self.write().await?)
+                    .await
+                    .map(|_| ())
+                    .map_err(|_| AuthError::DatabaseError)
+            }
+            .await,
+            Err(e) => Err(e),
+        };
+        match result {
+            Ok(()) => tracing::info!(user_id, from = parts.get_cost(), to = self.bcrypt_cost, "Upgraded password hash"),
+            Err(e) => tracing::warn!(user_id, error = %e, "Failed to upgrade password hash"),
+        }
+    }
+
+    /// Issues a token scoped to `workspace_id`, which the user must belong to.
+    pub async fn switch_workspace(&self, user_id: &str, workspace_id: Option<String>) -> Result<AuthResponse, AuthError> {
+        if let Some(workspace_id) = &workspace_id
+            && !self.is_member(workspace_id, user_id).await?
+        {
+            return Err(AuthError::NotAMember);
+        }
+
+        let token = self.create_token(user_id, workspace_id.as_deref())?;
+        Ok(AuthResponse { token, user_id: user_id.to_string(), workspace_id })
+    }
+
+    /// Issues a personal token for `user_id` without their password, for a
+    /// device authorization they approved.
+    pub async fn issue_token(&self, user_id: &str) -> Result<AuthResponse, AuthError> {
+        if !self.is_active(user_id).await? {
+            return Err(AuthError::AccountDisabled);
+        }
+        let token = self.create_token(user_id, None)?;
+        Ok(AuthResponse { token, user_id: user_id.to_string(), workspace_id: None })
+    }
+
+    async fn is_member(&self, workspace_id: &str, user_id: &str) -> Result<bool, AuthError> {
+        let role = workspaces::role(&self.pool, workspace_id, user_id)
+            .await
+            .map_err(|_| AuthError::DatabaseError)?;
+        Ok(role.is_some())
+    }
+
+    pub async fn list_users(&self) -> Result<Vec<UserRecord>, AuthError> {
+        let rows = sqlx::query("SELECT id, username, email, password_hash, created_at, updated_at, disabled_at, is_admin FROM users ORDER BY username")
+            .fetch_all(&self.pool)
+            .await
+            .map_err(|_| AuthError::DatabaseError)?;
+
+        Ok(rows
+            .into_iter()
+            .map(|row| UserRecord {
+                id: row.get("id"),
+                username: row.get("username"),
+                email: row.get("email"),
+                password_hash: row.get("password_hash"),
+                created_at: row.get("created_at"),
+                updated_at: row.get("updated_at"),
+                disabled_at: row.get("disabled_at"),
+                is_admin: row.get("is_admin"),
+            })
+            .collect())
+    }
+
+    pub async fn get_profile(&self, user_id: &str) -> Result<Profile, AuthError> {
+        sqlx::query("SELECT id, username, email, created_at, is_admin FROM users WHERE id = ?")
+            .bind(user_id)
+            .fetch_optional(&self.pool)
+            .await
+            .map_err(|_| AuthError::DatabaseError)?
+            .map(|row| Profile {
+                id: row.get("id"),
+                username: row.get("username"),
+                email: row.get("email"),
+                created_at: row.get("created_at"),
+                is_admin: row.get("is_admin"),
+            })
+            .ok_or(AuthError::UserNotFound)
+    }
+
+    pub async fn find_user_id(&self, username: &str) -> Result<String, AuthError> {
+        sqlx::query("SELECT id FROM users WHERE username = ?")
+            .bind(username)
+            .fetch_optional(&self.pool)
+            .await
+            .map_err(|_| AuthError::DatabaseError)?
+            .map(|row| row.get("id"))
+            .ok_or(AuthError::UserNotFound)
+    }
+
+    /// Replaces the signed-in user's password after checking the current one.
+    pub async fn change_password(&self, user_id: &str, req: ChangePasswordRequest) -> Result<(), AuthError> {
+        let row = sqlx::query("SELECT username, email, password_hash FROM users WHERE id = ?")
+            .bind(user_id)
+            .fetch_optional(&self.pool)
+            .await
+            .map_err(|_| AuthError::DatabaseError)?
+            .ok_or(AuthError::UserNotFound)?;
+        let username: String = row.get("username");
+        let email: String = row.get("email");
+        let stored_hash: String = row.get("password_hash");
+
+        if !bcrypt::verify(&req.current_password, &stored_hash).map_err(|_| AuthError::HashError)? {
+            return Err(AuthError::WrongPassword);
+        }
+        check_strength(&req.new_password, &[&username, &email])?;
+
+        let password_hash = self.hash_password(&req.new_password)?;
+        sqlx::query("UPDATE users SET password_hash = ?, updated_at = ? WHERE id = ?")
+            .bind(&password_hash)
+            .bind(Utc::now())
+            .bind(user_id)
+            .execute(&mut *self.write().await?)
+            .await
+            .map_err(|_| AuthError::DatabaseError)?;
+        Ok(())
+    }
+
+    pub async fn reset_password(&self, username: &str, password: &str) -> Result<(), AuthError> {
+        check_strength(password, &[username])?;
+        let password_hash = self.hash_password(password)?;
+
+        let result = sqlx::query("UPDATE users SET password_hash = ?, updated_at = ? WHERE username = ?")
+            .bind(&password_hash)
+            .bind(Utc::now())
+            .bind(username)
+            .execute(&mut *self.write().await?)
+            .await
+            .map_err(|_| AuthError::DatabaseError)?;
+
+        if result.rows_affected() == 0 {
+            return Err(AuthError::UserNotFound);
+        }
+        Ok(())
+    }
+
+    /// Deactivates (or re-enables) an account. A disabled account can't log
+    /// in and its tokens stop working, but all of its data is kept.
+    pub async fn set_disabled(&self, user_id: &str, disabled: bool) -> Result<(), AuthError> {
+        let now = Utc::now();
+        let result = sqlx::query("UPDATE users SET disabled_at = CASE WHEN ? THEN COALESCE(disabled_at, ?) END, updated_at = ? WHERE id = ?")
+            .bind(disabled)
+            .bind(now)
+            .bind(now)
+            .bind(user_id)
+            .execute(&mut *self.write().await?)
+            .await
+            .map_err(|_| AuthError::DatabaseError)?;
+
+        if result.rows_affected() == 0 {
+            return Err(AuthError::UserNotFound);
+        }
+        Ok(())
+    }
+
+    /// Grants or revokes access to the `/admin` endpoints. Only the CLI does this.
+    pub async fn set_admin(&self, user_id: &str, is_admin: bool) -> Result<(), AuthError> {
+        let result = sqlx::query("UPDATE users SET is_admin = ?, updated_at = ? WHERE id = ?")
+            .bind(is_admin)
+            .bind(Utc::now())
+            .bind(user_id)
+            .execute(&mut *self.write().await?)
+            .await
+            .map_err(|_| AuthError::DatabaseError)?;
+
+        if result.rows_affected() == 0 {
+            return Err(AuthError::UserNotFound);
+        }
+        Ok(())
+    }
+
+    async fn is_admin(&self, user_id: &str) -> Result<bool, AuthError> {
+        let row = sqlx::query("SELECT 1 FROM users WHERE id = ? AND is_admin")
+            .bind(user_id)
+            .fetch_optional(&self.pool)
+            .await
+            .map_err(|_| AuthError::DatabaseError)?;
+        Ok(row.is_some())
+    }
+
+    /// Lets users deactivate their own account, confirming with their password.
+    pub async fn deactivate(&self, user_id: &str, req: DeactivateRequest) -> Result<(), AuthError> {
+        let stored_hash: String = sqlx::query("SELECT password_hash FROM users WHERE id = ?")
+            .bind(user_id)
+            .fetch_optional(&self.pool)
+            .await
+            .map_err(|_| AuthError::DatabaseError)?
+            .ok_or(AuthError::UserNotFound)?
+            .get("password_hash");
+
+        if !bcrypt::verify(&req.password, &stored_hash).map_err(|_| AuthError::HashError)? {
+            return Err(AuthError::WrongPassword);
+        }
+        self.set_disabled(user_id, true).await
+    }
+
+    /// False for disabled and deleted accounts.
+    async fn is_active(&self, user_id: &str) -> Result<bool, AuthError> {
+        let row = sqlx::query("SELECT 1 FROM users WHERE id = ? AND disabled_at IS NULL")
+            .bind(user_id)
+            .fetch_optional(&self.pool)
+            .await
+            .map_err(|_| AuthError::DatabaseError)?;
+        Ok(row.is_some())
+    }
 
-        let token = self.create_token(&user_id)?;
-        Ok(AuthResponse { token, user_id })
+    /// Removes the users row only; the caller is responsible for the user's data.
+    pub async fn delete_user(&self, user_id: &str) -> Result<(), AuthError> {
+        sqlx::query("DELETE FROM users WHERE id = ?")
+            .bind(user_id)
+            .execute(&mut *self.write().await?)
+            .await
+            .map_err(|_| AuthError::DatabaseError)?;
+        Ok(())
     }
 
-    fn create_token(&self, user_id: &str) -> Result<String, AuthError> {
+    /// Inserts a previously exported user; existing ids, usernames and emails are left alone.
+    pub async fn import_user(&self, user: &UserRecord) -> Result<bool, AuthError> {
+        let result = sqlx::query("INSERT OR IGNORE INTO users (id, username, email, password_hash, created_at, updated_at, disabled_at, is_admin) VALUES (?, ?, ?, ?, ?, ?, ?, ?)")
+            .bind(&user.id)
+            .bind(&user.username)
+            .bind(&user.email)
+            .bind(&user.password_hash)
+            .bind(user.created_at)
+            .bind(user.updated_at)
+            .bind(user.disabled_at)
+            .bind(user.is_admin)
+            .execute(&mut *self.write().await?)
+            .await
+            .map_err(|_| AuthError::DatabaseError)?;
+        Ok(result.rows_affected() > 0)
+    }
+
+    /// Revokes `token` until it would have expired anyway.
+    pub async fn logout(&self, token: &str) -> Result<(), AuthError> {
+        let claims = self.decode_token(token)?;
+        let remaining = (claims.exp as i64 - Utc::now().timestamp()).max(1) as u64;
+        self.shared
+            .revoke(&claims.jti, std::time::Duration::from_secs(remaining))
+            .await
+            .map_err(|_| AuthError::StateUnavailable)
+    }
+
+    async fn is_revoked(&self, claims: &Claims) -> Result<bool, AuthError> {
+        self.shared.is_revoked(&claims.jti).await.map_err(|_| AuthError::StateUnavailable)
+    }
+
+    fn create_token(&self, user_id: &str, workspace_id: Option<&str>) -> Result<String, AuthError> {
+        let now = Utc::now();
         let claims = Claims {
             sub: user_id.to_string(),
-            exp: (chrono::Utc::now() + chrono::Duration::hours(24)).timestamp() as usize,
+            iss: self.tokens.issuer.clone(),
+            aud: self.tokens.audience.clone(),
+            iat: now.timestamp() as usize,
+            exp: (now + self.tokens.expiry).timestamp() as usize,
+            jti: Uuid::new_v4().to_string(),
+            ws: workspace_id.map(String::from),
         };
 
         encode(&Header::default(), &claims, &EncodingKey::from_secret(self.jwt_secret.as_ref()))
             .map_err(|_| AuthError::TokenError)
     }
 
+    pub fn token_expiry(&self) -> chrono::Duration {
+        self.tokens.expiry
+    }
+
+    fn csrf_key(&self) -> hmac::Key {
+        hmac::Key::new(hmac::HMAC_SHA256, format!("csrf:{}", self.jwt_secret).as_bytes())
+    }
+
+    /// The CSRF token that goes with a cookie session: an HMAC of the
+    /// token's id, so it can be checked without storing it.
+    pub fn csrf_token_for(&self, token: &str) -> Result<String, AuthError> {
+        let claims = self.decode_token(token)?;
+        Ok(URL_SAFE_NO_PAD.encode(hmac::sign(&self.csrf_key(), claims.jti.as_bytes())))
+    }
+
+    pub fn verify_csrf(&self, jti: &str, csrf_token: &str) -> bool {
+        URL_SAFE_NO_PAD
+            .decode(csrf_token)
+            .is_ok_and(|tag| hmac::verify(&self.csrf_key(), jti.as_bytes(), &tag).is_ok())
+    }
+
     fn decode_token(&self, token: &str) -> Result<Claims, AuthError> {
-        decode::<Claims>(token, &DecodingKey::from_secret(self.jwt_secret.as_ref()), &Validation::default())
+        let mut validation = Validation::default();
+        validation.set_required_spec_claims(&["sub", "iss", "aud", "exp"]);
+        validation.set_issuer(&[&self.tokens.issuer]);
+        validation.set_audience(&[&self.tokens.audience]);
+        validation.leeway = self.tokens.leeway;
+
+        let claims = decode::<Claims>(token, &DecodingKey::from_secret(self.jwt_secret.as_ref()), &validation)
             .map(|data| data.claims)
-            .map_err(|_| AuthError::InvalidToken)
+            .map_err(|_| AuthError::InvalidToken)?;
+        // Not checked by jsonwebtoken: a token from the future was not issued by a sane clock
+        if claims.iat as i64 > Utc::now().timestamp() + self.tokens.leeway as i64 {
+            return Err(AuthError::InvalidToken);
+        }
+        Ok(claims)
     }
 }
 
+/// The bcrypt cost for new hashes, from `BCRYPT_COST` (4-31, default 12).
+/// Each step doubles the time a login takes and an attacker needs per guess.
+fn bcrypt_cost() -> u32 {
+    std::env::var("BCRYPT_COST")
+        .ok()
+        .and_then(|cost| cost.parse().ok())
+        .unwrap_or(bcrypt::DEFAULT_COST)
+        .clamp(4, 31)
+}
+
+/// Rejects passwords scoring below `PASSWORD_MIN_SCORE`.
+fn check_strength(password: &str, user_inputs: &[&str]) -> Result<(), AuthError> {
+    let strength = password_strength::estimate(password, user_inputs);
+    if strength.score < password_strength::min_score() {
+        return Err(AuthError::WeakPassword(strength));
+    }
+    Ok(())
+}
+
+pub fn bearer_token(headers: &HeaderMap) -> Option<&str> {
+    headers
+        .get(AUTHORIZATION)
+        .and_then(|header| header.to_str().ok())
+        .and_then(|header| header.strip_prefix("Bearer "))
+}
+
+/// The request's token: a bearer token, or else the web UI's session cookie.
+pub fn request_token(headers: &HeaderMap) -> Option<&str> {
+    bearer_token(headers).or_else(|| session::cookie(headers, session::SESSION_COOKIE))
+}
+
 pub async fn auth_middleware(
     State(auth_service): State<Arc<AuthService>>,
     mut request: Request,
     next: Next,
 ) -> Result<Response, StatusCode> {
-    let auth_header = request
-        .headers()
-        .get(AUTHORIZATION)
-        .and_then(|header| header.to_str().ok())
-        .and_then(|header| header.strip_prefix("Bearer "));
-
-    let Some(token) = auth_header else {
+    let from_cookie = bearer_token(request.headers()).is_none();
+    let Some(token) = request_token(request.headers()) else {
         return Err(StatusCode::UNAUTHORIZED);
     };
 
     let claims = auth_service.decode_token(token).map_err(|_| StatusCode::UNAUTHORIZED)?;
+    // Fail closed: if we can't tell whether the token was revoked, don't accept it
+    if auth_service.is_revoked(&claims).await? {
+        return Err(StatusCode::UNAUTHORIZED);
+    }
+    // Checked on every request, like membership, so deactivation takes effect immediately
+    if !auth_service.is_active(&claims.sub).await? {
+        return Err(StatusCode::UNAUTHORIZED);
+    }
+    // Membership is checked on every request so removal takes effect immediately
+    if let Some(workspace_id) = &claims.ws
+        && !auth_service.is_member(workspace_id, &claims.sub).await?
+    {
+        return Err(StatusCode::FORBIDDEN);
+    }
+    if from_cookie {
+        request.extensions_mut().insert(session::CookieSession { jti: claims.jti.clone() });
+    }
+    request.extensions_mut().insert(Scope { user_id: claims.sub.clone(), workspace_id: claims.ws });
     request.extensions_mut().insert(claims.sub);
 
     Ok(next.run(request).await)
@@ -146,21 +614,94 @@ pub async fn auth_middleware(
 pub enum AuthError {
     DatabaseError,
     UserExists,
+    UserNotFound,
     InvalidCredentials,
     HashError,
     TokenError,
     InvalidToken,
+    StateUnavailable,
+    NotAMember,
+    /// The current password given to change it was wrong
+    WrongPassword,
+    WeakPassword(Strength),
+    AccountDisabled,
 }
 
+impl std::fmt::Display for AuthError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        let message = match self {
+            AuthError::DatabaseError => "database error",
+            AuthError::UserExists => "a user with that username or email already exists",
+            AuthError::UserNotFound => "user not found",
+            AuthError::InvalidCredentials => "invalid credentials",
+            AuthError::HashError => "password hashing failed",
+            AuthError::TokenError => "token creation failed",
+            AuthError::InvalidToken => "invalid token",
+            AuthError::StateUnavailable => "revoked token store unavailable",
+            AuthError::NotAMember => "not a member of that workspace",
+            AuthError::WrongPassword => "current password is incorrect",
+            AuthError::AccountDisabled => "account is disabled",
+            AuthError::WeakPassword(strength) => {
+                return write!(f, "password is too weak: {}", strength.warning.unwrap_or("add another word or two"));
+            }
+        };
+        f.write_str(message)
+    }
+}
+
+impl std::error::Error for AuthError {}
+
 impl From<AuthError> for StatusCode {
     fn from(error: AuthError) -> Self {
         match error {
             AuthError::DatabaseError => StatusCode::INTERNAL_SERVER_ERROR,
             AuthError::UserExists => StatusCode::CONFLICT,
+            AuthError::UserNotFound => StatusCode::NOT_FOUND,
             AuthError::InvalidCredentials => StatusCode::UNAUTHORIZED,
             AuthError::HashError => StatusCode::INTERNAL_SERVER_ERROR,
             AuthError::TokenError => StatusCode::INTERNAL_SERVER_ERROR,
             AuthError::InvalidToken => StatusCode::UNAUTHORIZED,
+            AuthError::StateUnavailable => StatusCode::SERVICE_UNAVAILABLE,
+            AuthError::NotAMember => StatusCode::NOT_FOUND,
+            AuthError::WrongPassword => StatusCode::FORBIDDEN,
+            AuthError::WeakPassword(_) => StatusCode::UNPROCESSABLE_ENTITY,
+            AuthError::AccountDisabled => StatusCode::FORBIDDEN,
+        }
+    }
+}
+
+/// Like the `StatusCode` conversion, but a weak password also gets a body
+/// saying what is wrong with it and how to fix it.
+impl IntoResponse for AuthError {
+    fn into_response(self) -> Response {
+        match self {
+            AuthError::WeakPassword(strength) => {
+                let body = serde_json::json!({
+                    "error": "Password is too weak",
+                    "score": strength.score,
+                    "min_score": password_strength::min_score(),
+                    "warning": strength.warning,
+                    "suggestions": strength.suggestions,
+                });
+                (StatusCode::UNPROCESSABLE_ENTITY, Json(body)).into_response()
+            }
+            error => StatusCode::from(error).into_response(),
         }
     }
-}
\ No newline at end of file
+}
+
+/// Limits a route to admins. Goes after `auth_middleware`, which supplies the
+/// user id. Checked against the database on every request, like deactivation.
+pub async fn require_admin(
+    State(auth_service): State<Arc<AuthService>>,
+    request: Request,
+    next: Next,
+) -> Result<Response, StatusCode> {
+    let Some(user_id) = request.extensions().get::<String>() else {
+        return Err(StatusCode::UNAUTHORIZED);
+    };
+    if !auth_service.is_admin(user_id).await? {
+        return Err(StatusCode::FORBIDDEN);
+    }
+    Ok(next.run(request).await)
+}
@@ -1,164 +1,1744 @@
 use chrono::{DateTime, Utc};
-use serde::{Deserialize, Serialize};
-use sqlx::{Row, SqlitePool};
+use chrono_tz::Tz;
+use serde::{de::DeserializeOwned, Deserialize, Serialize};
+use sqlx::sqlite::{SqliteConnectOptions, SqliteJournalMode, SqlitePoolOptions, SqliteRow};
+use sqlx::{Connection, QueryBuilder, Row, Sqlite, SqlitePool};
+use std::future::Future;
+use std::str::FromStr;
+use std::sync::Arc;
+use std::time::Duration;
+use tokio::sync::{mpsc, Notify};
+use tokio_stream::StreamExt;
+use ring::rand::{SecureRandom, SystemRandom};
 use uuid::Uuid;
 
+use crate::dates;
+use crate::encryption;
+use crate::hooks::Event;
+use crate::hypermedia::Links;
+use crate::idempotency;
+use crate::link_previews::LinkPreview;
+use crate::monitoring::QueryTimer;
+use crate::outbox;
+use crate::pagination::Cursor;
+use crate::search;
+use crate::shared_state::SharedState;
+use crate::writer::{WriteConnection, Writer};
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum Priority {
+    High,
+    Medium,
+    Low,
+}
+
+impl Priority {
+    pub fn as_str(self) -> &'static str {
+        match self {
+            Priority::High => "high",
+            Priority::Medium => "medium",
+            Priority::Low => "low",
+        }
+    }
+}
+
+impl std::str::FromStr for Priority {
+    type Err = ();
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "high" => Ok(Priority::High),
+            "medium" => Ok(Priority::Medium),
+            "low" => Ok(Priority::Low),
+            _ => Err(()),
+        }
+    }
+}
+
 #[derive(Clone, Debug, Serialize, Deserialize)]
 pub struct Todo {
     pub id: String,
+    /// The id to put in URLs and share links, e.g. `td_8Kj3pQ2xLmV9sT4w`;
+    /// routes taking a todo id accept either
+    #[serde(default)]
+    pub public_id: String,
     pub text: String,
+    /// Longer free-form details, in Markdown
+    #[serde(default)]
+    pub notes: Option<String>,
+    /// `notes` rendered to sanitized HTML; only filled in when asked for with `?render=html`
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub notes_html: Option<String>,
+    /// Titles and icons of the pages linked from `text`, once fetched; only
+    /// filled in by `GET /todos` and `GET /todos/:id`
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub links: Option<Vec<LinkPreview>>,
+    /// Where to read, update, toggle and so on this todo; only filled in by
+    /// the endpoints that read todos
+    #[serde(default, rename = "_links", skip_serializing_if = "Option::is_none")]
+    pub hypermedia: Option<Links>,
     pub completed: bool,
     pub category: Option<String>,
+    /// Stored as a JSON array in a TEXT column; an array in the API
+    #[serde(default, with = "tags_array")]
     pub tags: Option<String>,
-    pub priority: Option<String>,
+    /// A palette name or hex code, see `colors`
+    #[serde(default)]
+    pub color: Option<String>,
+    /// Where the todo is to be done, for `GET /todos/nearby`; set together
+    #[serde(default)]
+    pub latitude: Option<f64>,
+    #[serde(default)]
+    pub longitude: Option<f64>,
+    /// "Corner shop", shown instead of the coordinates
+    #[serde(default)]
+    pub location_name: Option<String>,
+    pub priority: Option<Priority>,
     pub due_date: Option<DateTime<Utc>>,
     pub user_id: Option<String>,
+    /// Set for todos shared with a workspace instead of kept personal
+    #[serde(default)]
+    pub workspace_id: Option<String>,
+    /// The workspace member responsible for the todo
+    #[serde(default)]
+    pub assignee_id: Option<String>,
+    pub estimate_minutes: Option<i64>,
+    pub spent_minutes: Option<i64>,
+    pub completed_at: Option<DateTime<Utc>>,
     pub created_at: DateTime<Utc>,
     pub updated_at: DateTime<Utc>,
+    /// Incremented on every change; updates must name the version they were based on
+    #[serde(default = "initial_version")]
+    pub version: i64,
+    /// Whether the escalation job may raise the priority as the due date nears
+    #[serde(default = "escalate_by_default")]
+    pub auto_escalate: bool,
+    /// Computed when the todo is loaded: open and past its due date
+    #[serde(default)]
+    pub is_overdue: bool,
+}
+
+/// The most todos `create_todos` takes at once, keeping its INSERT well
+/// under SQLite's limit on bound parameters.
+pub const MAX_BATCH: usize = 500;
+
+/// The furthest ahead a day count looks, like `GET /todos/upcoming`'s `days`;
+/// far larger counts overflow chrono's dates.
+pub const MAX_DAYS_AHEAD: u32 = 366;
+
+pub(crate) const TODO_COLUMNS: &str = "id, text, notes, completed, category, tags, color, latitude, longitude, location_name, priority, due_date, user_id, workspace_id, assignee_id, estimate_minutes, spent_minutes, completed_at, created_at, updated_at, version, auto_escalate, public_id";
+
+/// Starts every todo's public id
+const PUBLIC_ID_PREFIX: &str = "td_";
+
+/// A new public id: the prefix and 16 random base62 characters, so one
+/// todo's id says nothing about any other's.
+fn new_public_id() -> String {
+    const ALPHABET: &[u8] = b"0123456789ABCDEFGHIJKLMNOPQRSTUVWXYZabcdefghijklmnopqrstuvwxyz";
+    let mut random = [0u8; 16];
+    SystemRandom::new().fill(&mut random).expect("system randomness is available");
+    let mut id = String::from(PUBLIC_ID_PREFIX);
+    id.extend(random.iter().map(|byte| ALPHABET[*byte as usize % ALPHABET.len()] as char));
+    id
+}
+
+fn initial_version() -> i64 {
+    1
+}
+
+fn escalate_by_default() -> bool {
+    true
+}
+
+impl Todo {
+    pub(crate) fn from_row(row: &SqliteRow) -> Self {
+        let id: String = row.get("id");
+        let completed: bool = row.get("completed");
+        let due_date: Option<DateTime<Utc>> = row.get("due_date");
+
+        Todo {
+            public_id: row.get::<Option<String>, _>("public_id").unwrap_or_default(),
+            text: encryption::decrypt(&id, "text", row.get("text")),
+            notes: encryption::decrypt_optional(&id, "notes", row.get("notes")),
+            id,
+            notes_html: None,
+            links: None,
+            hypermedia: None,
+            completed,
+            category: row.get("category"),
+            tags: row.get("tags"),
+            color: row.get("color"),
+            latitude: row.get("latitude"),
+            longitude: row.get("longitude"),
+            location_name: row.get("location_name"),
+            // Rows written before priorities were validated may hold other values
+            priority: row
+                .get::<Option<String>, _>("priority")
+                .and_then(|p| p.parse().ok()),
+            due_date,
+            user_id: row.get("user_id"),
+            workspace_id: row.get("workspace_id"),
+            assignee_id: row.get("assignee_id"),
+            estimate_minutes: row.get("estimate_minutes"),
+            spent_minutes: row.get("spent_minutes"),
+            completed_at: row.get("completed_at"),
+            created_at: row.get("created_at"),
+            updated_at: row.get("updated_at"),
+            version: row.get("version"),
+            auto_escalate: row.get("auto_escalate"),
+            is_overdue: is_overdue(completed, due_date),
+        }
+    }
+
+    /// The stored tags, e.g. `["home", "urgent"]`.
+    pub fn tag_list(&self) -> Vec<String> {
+        self.tags
+            .as_deref()
+            .and_then(|tags| serde_json::from_str(tags).ok())
+            .unwrap_or_default()
+    }
+
+    /// A todo as `create_todo` stores it.
+    fn created(new_todo: NewTodo, scope: &Scope, now: DateTime<Utc>) -> Self {
+        Todo {
+            id: Uuid::new_v4().to_string(),
+            public_id: new_public_id(),
+            text: new_todo.text,
+            notes: new_todo.notes,
+            notes_html: None,
+            links: None,
+            hypermedia: None,
+            completed: false,
+            category: new_todo.category,
+            tags: new_todo.tags.map(|tags| serde_json::to_string(&tags).unwrap_or_default()),
+            color: new_todo.color,
+            latitude: new_todo.latitude,
+            longitude: new_todo.longitude,
+            location_name: new_todo.location_name,
+            priority: new_todo.priority,
+            due_date: new_todo.due_date,
+            user_id: Some(scope.user_id.clone()),
+            workspace_id: scope.workspace_id.clone(),
+            assignee_id: None,
+            estimate_minutes: new_todo.estimate_minutes,
+            spent_minutes: new_todo.spent_minutes,
+            completed_at: None,
+            created_at: now,
+            updated_at: now,
+            version: 1,
+            auto_escalate: true,
+            is_overdue: is_overdue(false, new_todo.due_date),
+        }
+    }
+}
+
+/// Serializes the stored `tags` text as the array it holds. Reads either
+/// form, since cache entries and exports made before the change hold the text.
+mod tags_array {
+    use serde::{de::Error, Deserialize, Deserializer, Serialize, Serializer};
+
+    #[derive(Deserialize)]
+    #[serde(untagged)]
+    enum Tags {
+        Array(Vec<String>),
+        Encoded(String),
+    }
+
+    pub fn serialize<S: Serializer>(tags: &Option<String>, serializer: S) -> Result<S::Ok, S::Error> {
+        let tags: Option<Vec<String>> = tags.as_deref().map(|tags| serde_json::from_str(tags).unwrap_or_default());
+        tags.serialize(serializer)
+    }
+
+    pub fn deserialize<'de, D: Deserializer<'de>>(deserializer: D) -> Result<Option<String>, D::Error> {
+        match Option::<Tags>::deserialize(deserializer)? {
+            Some(Tags::Array(tags)) => serde_json::to_string(&tags).map(Some).map_err(D::Error::custom),
+            Some(Tags::Encoded(tags)) => Ok(Some(tags)),
+            None => Ok(None),
+        }
+    }
+}
+
+fn is_overdue(completed: bool, due_date: Option<DateTime<Utc>>) -> bool {
+    !completed && due_date.is_some_and(|due| due < Utc::now())
+}
+
+/// Whose todos a request works on: the user's personal todos, or those of the
+/// workspace selected in their token.
+#[derive(Clone, Debug)]
+pub struct Scope {
+    pub user_id: String,
+    pub workspace_id: Option<String>,
+}
+
+impl Scope {
+    /// A condition on `todos` ending in a comparison, to be followed by one
+    /// placeholder bound to the returned value.
+    pub(crate) fn condition(&self) -> (&'static str, &str) {
+        match &self.workspace_id {
+            Some(workspace_id) => ("todos.workspace_id = ", workspace_id),
+            None => ("todos.workspace_id IS NULL AND todos.user_id = ", &self.user_id),
+        }
+    }
+
+    fn cache_key(&self) -> String {
+        match &self.workspace_id {
+            Some(workspace_id) => format!("ws:{}", workspace_id),
+            None => format!("user:{}", self.user_id),
+        }
+    }
+}
+
+/// The cache key of the scope `todo` belongs to; `None` for unowned todos.
+fn todo_cache_key(todo: &Todo) -> Option<String> {
+    match (&todo.workspace_id, &todo.user_id) {
+        (Some(workspace_id), _) => Some(format!("ws:{}", workspace_id)),
+        (None, Some(user_id)) => Some(format!("user:{}", user_id)),
+        (None, None) => None,
+    }
 }
 
 #[derive(Debug, Deserialize)]
 pub struct NewTodo {
     pub text: String,
+    pub notes: Option<String>,
+    pub category: Option<String>,
+    pub tags: Option<Vec<String>>,
+    /// A palette name or hex code, see `colors`
+    pub color: Option<String>,
+    /// Both or neither
+    pub latitude: Option<f64>,
+    pub longitude: Option<f64>,
+    pub location_name: Option<String>,
+    pub priority: Option<Priority>,
+    pub due_date: Option<DateTime<Utc>>,
+    /// Natural-language due date ("tomorrow 5pm"), resolved into `due_date`
+    pub due: Option<String>,
+    pub estimate_minutes: Option<i64>,
+    pub spent_minutes: Option<i64>,
+    /// Parse `text` as quick-add syntax ("Buy milk #errands @shopping !high due:friday")
+    #[serde(default)]
+    pub quick_add: bool,
+}
+
+/// Partial update; fields left out keep their current value.
+#[derive(Debug, Deserialize)]
+pub struct UpdateTodo {
+    pub text: Option<String>,
+    /// `""` clears the notes
+    pub notes: Option<String>,
     pub category: Option<String>,
     pub tags: Option<Vec<String>>,
-    pub priority: Option<String>,
+    /// `""` clears the color
+    pub color: Option<String>,
+    /// Both or neither
+    pub latitude: Option<f64>,
+    pub longitude: Option<f64>,
+    /// `""` clears the whole location, coordinates included
+    pub location_name: Option<String>,
+    pub priority: Option<Priority>,
     pub due_date: Option<DateTime<Utc>>,
+    /// Natural-language due date ("friday 5pm"), resolved into `due_date`;
+    /// `""` clears the due date
+    pub due: Option<String>,
+    pub estimate_minutes: Option<i64>,
+    pub spent_minutes: Option<i64>,
+    /// `false` stops the escalation job from raising the priority
+    pub auto_escalate: Option<bool>,
+    /// Alternative to the `If-Match` header
+    pub expected_version: Option<i64>,
+}
+
+#[derive(Debug)]
+pub enum TodoError {
+    NotFound,
+    /// The todo changed since the version the client based its update on
+    VersionMismatch,
+    DatabaseError(sqlx::Error),
+}
+
+impl From<sqlx::Error> for TodoError {
+    fn from(err: sqlx::Error) -> Self {
+        TodoError::DatabaseError(err)
+    }
+}
+
+impl From<TodoError> for axum::http::StatusCode {
+    fn from(err: TodoError) -> Self {
+        match err {
+            TodoError::NotFound => axum::http::StatusCode::NOT_FOUND,
+            TodoError::VersionMismatch => axum::http::StatusCode::CONFLICT,
+            TodoError::DatabaseError(e) => error_status(&e),
+        }
+    }
+}
+
+/// Criteria for selecting a user's todos; every field that is set must match.
+#[derive(Clone, Debug, Default, Serialize, Deserialize)]
+pub struct TodoFilter {
+    pub completed: Option<bool>,
+    pub category: Option<String>,
+    pub tag: Option<String>,
+    pub priority: Option<Priority>,
+    /// Substring of the todo text, ignoring case and accents
+    pub text: Option<String>,
+    /// Only todos due by the end of the day this many days from today, in the
+    /// user's timezone (overdue ones included); at most `MAX_DAYS_AHEAD`
+    pub due_within_days: Option<u32>,
+    pub overdue: Option<bool>,
+    pub assignee_id: Option<String>,
+    /// Only todos due before this instant
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub due_before: Option<DateTime<Utc>>,
+}
+
+impl TodoFilter {
+    /// Appends ` AND ...` conditions for each criterion to a query already
+    /// filtering on `todos`. Day counts are in `tz`.
+    fn push_conditions(&self, query: &mut QueryBuilder<'_, Sqlite>, tz: Tz) {
+        let now = Utc::now();
+
+        if let Some(completed) = self.completed {
+            query.push(" AND completed = ").push_bind(completed);
+        }
+        if let Some(category) = &self.category {
+            query.push(" AND category = ").push_bind(category.clone());
+        }
+        if let Some(tag) = &self.tag {
+            query
+                .push(" AND EXISTS (SELECT 1 FROM json_each(todos.tags) WHERE value = ")
+                .push_bind(tag.clone())
+                .push(")");
+        }
+        if let Some(priority) = self.priority {
+            query.push(" AND priority = ").push_bind(priority.as_str());
+        }
+        if let Some(text) = &self.text {
+            query
+                .push(" AND instr(search_text, ")
+                .push_bind(search::normalize(text))
+                .push(") > 0");
+        }
+        if let Some(days) = self.due_within_days {
+            query
+                .push(" AND due_date < ")
+                .push_bind(dates::end_of_days_ahead(now.with_timezone(&tz), days.min(MAX_DAYS_AHEAD)));
+        }
+        match self.overdue {
+            Some(true) => {
+                query.push(" AND completed = FALSE AND due_date < ").push_bind(now);
+            }
+            Some(false) => {
+                query.push(" AND (completed = TRUE OR due_date IS NULL OR due_date >= ").push_bind(now).push(")");
+            }
+            None => {}
+        }
+        if let Some(assignee_id) = &self.assignee_id {
+            query.push(" AND assignee_id = ").push_bind(assignee_id.clone());
+        }
+        if let Some(due_before) = self.due_before {
+            query.push(" AND due_date < ").push_bind(due_before);
+        }
+    }
+}
+
+#[derive(Debug, Serialize)]
+pub struct CategoryEffort {
+    pub category: Option<String>,
+    pub open: i64,
+    pub estimate_minutes: i64,
+    pub spent_minutes: i64,
+    pub remaining_minutes: i64,
+}
+
+#[derive(Debug, Serialize)]
+pub struct TodoStats {
+    pub total: i64,
+    pub completed: i64,
+    pub open: i64,
+    pub remaining_minutes: i64,
+    pub by_category: Vec<CategoryEffort>,
+}
+
+/// How many todos in a scope carry a tag or category.
+#[derive(Clone, Debug, Serialize, Deserialize)]
+pub struct TermCount {
+    pub term: String,
+    pub uses: i64,
+}
+
+/// The tags and categories used in a scope, most used first.
+#[derive(Debug, Serialize, Deserialize)]
+pub struct Terms {
+    pub tags: Vec<TermCount>,
+    pub categories: Vec<TermCount>,
+}
+
+/// Connection pool and pragma settings, read from the environment:
+///
+/// - `DATABASE_URL` (default `sqlite:todos.db`)
+/// - `DATABASE_MAX_CONNECTIONS` (default 5)
+/// - `DATABASE_ACQUIRE_TIMEOUT_SECS` (default 30)
+/// - `SQLITE_JOURNAL_MODE` (default `wal`, so readers don't block the writer)
+/// - `SQLITE_BUSY_TIMEOUT_MS` (default 5000; how long a writer waits for the lock
+///   before failing with "database is locked")
+/// - `SQLITE_FOREIGN_KEYS` (default `true`)
+#[derive(Clone, Debug)]
+pub struct DatabaseConfig {
+    pub url: String,
+    pub max_connections: u32,
+    pub acquire_timeout: Duration,
+    pub journal_mode: SqliteJournalMode,
+    pub busy_timeout: Duration,
+    pub foreign_keys: bool,
+    /// Encrypt todo text and notes at rest; see `encryption`
+    pub encryption_key: Option<String>,
+}
+
+impl DatabaseConfig {
+    pub fn from_env() -> Self {
+        fn var<T: FromStr>(name: &str, default: T) -> T {
+            std::env::var(name).ok().and_then(|value| value.parse().ok()).unwrap_or(default)
+        }
+
+        DatabaseConfig {
+            url: var("DATABASE_URL", "sqlite:todos.db".to_string()),
+            max_connections: var("DATABASE_MAX_CONNECTIONS", 5),
+            acquire_timeout: Duration::from_secs(var("DATABASE_ACQUIRE_TIMEOUT_SECS", 30)),
+            journal_mode: var("SQLITE_JOURNAL_MODE", SqliteJournalMode::Wal),
+            busy_timeout: Duration::from_millis(var("SQLITE_BUSY_TIMEOUT_MS", 5000)),
+            foreign_keys: var("SQLITE_FOREIGN_KEYS", true),
+            encryption_key: std::env::var("TODO_ENCRYPTION_KEY").ok().filter(|key| !key.is_empty()),
+        }
+    }
 }
 
+/// Upper bound on staleness for writes that bypass invalidation (e.g. the CLI
+/// running against a server that keeps its cache in process memory).
+const CACHE_TTL: Duration = Duration::from_secs(30);
+
 pub struct Database {
     pool: SqlitePool,
+    /// Every write in the app goes through here; see `Writer`
+    writer: Writer,
+    /// Holds cached `get_todos` results ("todos:<scope>"), which the web UI
+    /// refetches after every action, and tag and category counts for
+    /// autocomplete ("terms:<scope>"); these are the hottest queries
+    cache: SharedState,
+    /// Woken after each write that recorded events, so the outbox relay
+    /// delivers them right away
+    outbox: Arc<Notify>,
 }
 
 impl Database {
-    pub async fn new(database_url: &str) -> Result<Self, sqlx::Error> {
-        let pool = SqlitePool::connect(database_url).await?;
+    pub async fn new(config: &DatabaseConfig, cache: SharedState) -> Result<Self, sqlx::Error> {
+        if let Some(key) = &config.encryption_key {
+            encryption::enable(key).map_err(|e| sqlx::Error::Configuration(e.into()))?;
+        }
+        let options = SqliteConnectOptions::from_str(&config.url)?
+            .create_if_missing(true)
+            .journal_mode(config.journal_mode)
+            .busy_timeout(config.busy_timeout)
+            .foreign_keys(config.foreign_keys);
+        let pool = SqlitePoolOptions::new()
+            .max_connections(config.max_connections)
+            .acquire_timeout(config.acquire_timeout)
+            .connect_with(options.clone())
+            .await?;
 
         // Create tables if they don't exist
         sqlx::query("CREATE TABLE IF NOT EXISTS users (id TEXT PRIMARY KEY, username TEXT UNIQUE, email TEXT UNIQUE, password_hash TEXT, created_at DATETIME DEFAULT CURRENT_TIMESTAMP, updated_at DATETIME DEFAULT CURRENT_TIMESTAMP)")
             .execute(&pool)
             .await?;
 
-        sqlx::query("CREATE TABLE IF NOT EXISTS todos (id TEXT PRIMARY KEY, text TEXT, completed BOOLEAN DEFAULT FALSE, category TEXT, tags TEXT, priority TEXT, due_date DATETIME, user_id TEXT, created_at DATETIME DEFAULT CURRENT_TIMESTAMP, updated_at DATETIME DEFAULT CURRENT_TIMESTAMP)")
+        add_column_if_missing(&pool, "users", "disabled_at", "DATETIME").await?;
+        add_column_if_missing(&pool, "users", "is_admin", "BOOLEAN NOT NULL DEFAULT FALSE").await?;
+        add_column_if_missing(&pool, "users", "plan", "TEXT NOT NULL DEFAULT 'free'").await?;
+        add_column_if_missing(&pool, "users", "stripe_customer_id", "TEXT").await?;
+        sqlx::query("CREATE INDEX IF NOT EXISTS idx_users_stripe_customer ON users(stripe_customer_id)")
+            .execute(&pool)
+            .await?;
+
+        sqlx::query("CREATE TABLE IF NOT EXISTS todos (id TEXT PRIMARY KEY, text TEXT, completed BOOLEAN DEFAULT FALSE, category TEXT, tags TEXT, priority TEXT CHECK (priority IN ('high', 'medium', 'low')), due_date DATETIME, user_id TEXT, created_at DATETIME DEFAULT CURRENT_TIMESTAMP, updated_at DATETIME DEFAULT CURRENT_TIMESTAMP)")
+            .execute(&pool)
+            .await?;
+
+        // Older databases were created without the CHECK constraint; normalize what we can
+        sqlx::query("UPDATE todos SET priority = lower(trim(priority)) WHERE priority <> lower(trim(priority))")
             .execute(&pool)
             .await?;
 
-        Ok(Database { pool })
+        add_column_if_missing(&pool, "todos", "completed_at", "DATETIME").await?;
+        add_column_if_missing(&pool, "todos", "estimate_minutes", "INTEGER").await?;
+        add_column_if_missing(&pool, "todos", "spent_minutes", "INTEGER").await?;
+        add_column_if_missing(&pool, "todos", "version", "INTEGER NOT NULL DEFAULT 1").await?;
+        add_column_if_missing(&pool, "todos", "workspace_id", "TEXT").await?;
+        add_column_if_missing(&pool, "todos", "assignee_id", "TEXT").await?;
+        add_column_if_missing(&pool, "todos", "notes", "TEXT").await?;
+        add_column_if_missing(&pool, "todos", "auto_escalate", "BOOLEAN NOT NULL DEFAULT TRUE").await?;
+        add_column_if_missing(&pool, "todos", "color", "TEXT").await?;
+        add_column_if_missing(&pool, "todos", "latitude", "REAL").await?;
+        add_column_if_missing(&pool, "todos", "longitude", "REAL").await?;
+        add_column_if_missing(&pool, "todos", "location_name", "TEXT").await?;
+        add_column_if_missing(&pool, "todos", "public_id", "TEXT").await?;
+        add_column_if_missing(&pool, "todos", "search_text", "TEXT").await?;
+
+        // Public ids for todos from before they had them
+        let missing = sqlx::query("SELECT id FROM todos WHERE public_id IS NULL").fetch_all(&pool).await?;
+        if !missing.is_empty() {
+            let mut tx = pool.begin().await?;
+            for row in &missing {
+                sqlx::query("UPDATE todos SET public_id = ? WHERE id = ?")
+                    .bind(new_public_id())
+                    .bind(row.get::<String, _>("id"))
+                    .execute(&mut *tx)
+                    .await?;
+            }
+            tx.commit().await?;
+        }
+
+        sqlx::query("CREATE UNIQUE INDEX IF NOT EXISTS idx_todos_public_id ON todos(public_id)")
+            .execute(&pool)
+            .await?;
+
+        // Search text for todos from before it was kept; encrypted ones stay without
+        if !encryption::is_enabled() {
+            let missing = sqlx::query("SELECT id, text FROM todos WHERE search_text IS NULL AND text IS NOT NULL AND text NOT LIKE ?")
+                .bind(format!("{}%", encryption::PREFIX))
+                .fetch_all(&pool)
+                .await?;
+            if !missing.is_empty() {
+                let mut tx = pool.begin().await?;
+                for row in &missing {
+                    sqlx::query("UPDATE todos SET search_text = ? WHERE id = ?")
+                        .bind(search::normalize(row.get("text")))
+                        .bind(row.get::<String, _>("id"))
+                        .execute(&mut *tx)
+                        .await?;
+                }
+                tx.commit().await?;
+            }
+        }
+
+        // Also serves lookups by user_id alone, so there is no separate index for that
+        sqlx::query("CREATE INDEX IF NOT EXISTS idx_todos_user_due_date ON todos(user_id, due_date)")
+            .execute(&pool)
+            .await?;
+
+        sqlx::query("CREATE INDEX IF NOT EXISTS idx_todos_due_date ON todos(due_date)")
+            .execute(&pool)
+            .await?;
+
+        sqlx::query("CREATE INDEX IF NOT EXISTS idx_todos_category ON todos(category)")
+            .execute(&pool)
+            .await?;
+
+        // Per-scope category counts, for autocomplete
+        sqlx::query("CREATE INDEX IF NOT EXISTS idx_todos_user_category ON todos(user_id, category)")
+            .execute(&pool)
+            .await?;
+
+        sqlx::query("CREATE INDEX IF NOT EXISTS idx_todos_workspace_category ON todos(workspace_id, category)")
+            .execute(&pool)
+            .await?;
+
+        sqlx::query("CREATE INDEX IF NOT EXISTS idx_todos_workspace_due_date ON todos(workspace_id, due_date)")
+            .execute(&pool)
+            .await?;
+
+        sqlx::query("CREATE INDEX IF NOT EXISTS idx_todos_assignee ON todos(assignee_id)")
+            .execute(&pool)
+            .await?;
+
+        // Keyset pagination of todo lists, newest first
+        sqlx::query("CREATE INDEX IF NOT EXISTS idx_todos_user_created ON todos(user_id, created_at, id)")
+            .execute(&pool)
+            .await?;
+
+        sqlx::query("CREATE INDEX IF NOT EXISTS idx_todos_workspace_created ON todos(workspace_id, created_at, id)")
+            .execute(&pool)
+            .await?;
+
+        // Bounding-box scans for GET /todos/nearby; most todos have no location
+        sqlx::query("CREATE INDEX IF NOT EXISTS idx_todos_location ON todos(latitude, longitude) WHERE latitude IS NOT NULL")
+            .execute(&pool)
+            .await?;
+
+        // The change log behind GET /sync: one row per todo, moved to the end
+        // each time it changes. `user_id` is only set for personal todos.
+        sqlx::query("CREATE TABLE IF NOT EXISTS todo_changes (seq INTEGER PRIMARY KEY AUTOINCREMENT, todo_id TEXT NOT NULL UNIQUE, user_id TEXT, workspace_id TEXT, deleted BOOLEAN NOT NULL DEFAULT FALSE)")
+            .execute(&pool)
+            .await?;
+
+        sqlx::query("CREATE INDEX IF NOT EXISTS idx_todo_changes_scope ON todo_changes(workspace_id, user_id, seq)")
+            .execute(&pool)
+            .await?;
+
+        // Triggers rather than bookkeeping in each write, so nothing that
+        // writes todos (jobs, rules, imports, the CLI) can be missed. A DELETE
+        // then INSERT instead of INSERT OR REPLACE, which an outer INSERT OR
+        // IGNORE would override.
+        for (event, row, deleted) in [("INSERT", "NEW", "FALSE"), ("UPDATE", "NEW", "FALSE"), ("DELETE", "OLD", "TRUE")] {
+            sqlx::query(&format!(
+                "CREATE TRIGGER IF NOT EXISTS todos_log_{} AFTER {} ON todos BEGIN DELETE FROM todo_changes WHERE todo_id = {row}.id; INSERT INTO todo_changes (todo_id, user_id, workspace_id, deleted) VALUES ({row}.id, CASE WHEN {row}.workspace_id IS NULL THEN {row}.user_id END, {row}.workspace_id, {}); END",
+                event.to_lowercase(),
+                event,
+                deleted,
+            ))
+            .execute(&pool)
+            .await?;
+        }
+
+        // Todos written before the log existed
+        sqlx::query("INSERT INTO todo_changes (todo_id, user_id, workspace_id) SELECT id, CASE WHEN workspace_id IS NULL THEN user_id END, workspace_id FROM todos WHERE id NOT IN (SELECT todo_id FROM todo_changes) ORDER BY updated_at")
+            .execute(&pool)
+            .await?;
+
+        sqlx::query("CREATE TABLE IF NOT EXISTS notifications (id TEXT PRIMARY KEY, user_id TEXT NOT NULL REFERENCES users(id), kind TEXT NOT NULL, message TEXT NOT NULL, todo_id TEXT, created_at DATETIME NOT NULL, read_at DATETIME)")
+            .execute(&pool)
+            .await?;
+
+        sqlx::query("CREATE INDEX IF NOT EXISTS idx_notifications_user ON notifications(user_id, created_at)")
+            .execute(&pool)
+            .await?;
+
+        sqlx::query("CREATE TABLE IF NOT EXISTS notification_channels (user_id TEXT NOT NULL REFERENCES users(id), channel TEXT NOT NULL, address TEXT NOT NULL, enabled BOOLEAN NOT NULL, updated_at DATETIME NOT NULL, PRIMARY KEY (user_id, channel))")
+            .execute(&pool)
+            .await?;
+
+        sqlx::query("CREATE TABLE IF NOT EXISTS notification_deliveries (notification_id TEXT NOT NULL REFERENCES notifications(id), channel TEXT NOT NULL, status TEXT NOT NULL CHECK (status IN ('pending', 'sent', 'failed')), error TEXT, updated_at DATETIME NOT NULL, PRIMARY KEY (notification_id, channel))")
+            .execute(&pool)
+            .await?;
+
+        sqlx::query("CREATE TABLE IF NOT EXISTS mentions (todo_id TEXT NOT NULL REFERENCES todos(id), user_id TEXT NOT NULL REFERENCES users(id), mentioned_by TEXT NOT NULL, created_at DATETIME NOT NULL, PRIMARY KEY (todo_id, user_id))")
+            .execute(&pool)
+            .await?;
+
+        sqlx::query("CREATE INDEX IF NOT EXISTS idx_mentions_user ON mentions(user_id, created_at)")
+            .execute(&pool)
+            .await?;
+
+        sqlx::query("CREATE TABLE IF NOT EXISTS workspaces (id TEXT PRIMARY KEY, name TEXT NOT NULL, created_by TEXT NOT NULL, created_at DATETIME NOT NULL, updated_at DATETIME NOT NULL)")
+            .execute(&pool)
+            .await?;
+
+        sqlx::query("CREATE TABLE IF NOT EXISTS workspace_members (workspace_id TEXT NOT NULL REFERENCES workspaces(id), user_id TEXT NOT NULL REFERENCES users(id), role TEXT NOT NULL CHECK (role IN ('owner', 'member')), created_at DATETIME NOT NULL, PRIMARY KEY (workspace_id, user_id))")
+            .execute(&pool)
+            .await?;
+
+        add_column_if_missing(&pool, "workspaces", "slack_webhook_url", "TEXT").await?;
+
+        sqlx::query("CREATE INDEX IF NOT EXISTS idx_workspace_members_user ON workspace_members(user_id)")
+            .execute(&pool)
+            .await?;
+
+        sqlx::query("CREATE TABLE IF NOT EXISTS workspace_invites (id TEXT PRIMARY KEY, workspace_id TEXT NOT NULL REFERENCES workspaces(id), email TEXT NOT NULL, role TEXT NOT NULL CHECK (role IN ('owner', 'member')), token_hash TEXT NOT NULL UNIQUE, invited_by TEXT NOT NULL, created_at DATETIME NOT NULL, expires_at DATETIME NOT NULL, accepted_at DATETIME, accepted_by TEXT)")
+            .execute(&pool)
+            .await?;
+
+        sqlx::query("CREATE TABLE IF NOT EXISTS saved_filters (id TEXT PRIMARY KEY, user_id TEXT NOT NULL REFERENCES users(id), name TEXT NOT NULL, filter TEXT NOT NULL, created_at DATETIME NOT NULL, updated_at DATETIME NOT NULL)")
+            .execute(&pool)
+            .await?;
+
+        sqlx::query("CREATE TABLE IF NOT EXISTS rules (id TEXT PRIMARY KEY, user_id TEXT NOT NULL REFERENCES users(id), name TEXT NOT NULL, condition TEXT NOT NULL, actions TEXT NOT NULL, position INTEGER NOT NULL DEFAULT 0, created_at DATETIME NOT NULL, updated_at DATETIME NOT NULL)")
+            .execute(&pool)
+            .await?;
+
+        sqlx::query("CREATE TABLE IF NOT EXISTS categories (id TEXT PRIMARY KEY, user_id TEXT NOT NULL REFERENCES users(id), name TEXT NOT NULL, color TEXT, icon TEXT, position INTEGER NOT NULL DEFAULT 0, created_at DATETIME NOT NULL, updated_at DATETIME NOT NULL, UNIQUE (user_id, name))")
+            .execute(&pool)
+            .await?;
+
+        // Categories used to exist only as strings on todos; give each user
+        // the ones their todos use (and any added by the CLI since)
+        sqlx::query("INSERT OR IGNORE INTO categories (id, user_id, name, created_at, updated_at) SELECT lower(hex(randomblob(16))), user_id, category, MIN(created_at), MIN(created_at) FROM todos WHERE user_id IN (SELECT id FROM users) AND category IS NOT NULL GROUP BY user_id, category")
+            .execute(&pool)
+            .await?;
+
+        sqlx::query("CREATE TABLE IF NOT EXISTS points_awards (user_id TEXT NOT NULL REFERENCES users(id), todo_id TEXT NOT NULL, points INTEGER NOT NULL, local_date TEXT NOT NULL, awarded_at DATETIME NOT NULL, PRIMARY KEY (user_id, todo_id))")
+            .execute(&pool)
+            .await?;
+
+        sqlx::query("CREATE INDEX IF NOT EXISTS idx_points_awards_user_date ON points_awards(user_id, local_date)")
+            .execute(&pool)
+            .await?;
+
+        sqlx::query("CREATE TABLE IF NOT EXISTS myday (user_id TEXT NOT NULL REFERENCES users(id), todo_id TEXT NOT NULL REFERENCES todos(id), local_date TEXT NOT NULL, planned_on TEXT NOT NULL, added_at DATETIME NOT NULL, PRIMARY KEY (user_id, local_date, todo_id))")
+            .execute(&pool)
+            .await?;
+
+        // A row is created when the user starts connecting; `refresh_token` is set once they have
+        sqlx::query("CREATE TABLE IF NOT EXISTS google_calendars (user_id TEXT PRIMARY KEY REFERENCES users(id), refresh_token TEXT, access_token TEXT, access_expires_at DATETIME, calendar_id TEXT, sync_token TEXT, state_hash TEXT UNIQUE, state_expires_at DATETIME, connected_at DATETIME, last_synced_at DATETIME, last_error TEXT)")
+            .execute(&pool)
+            .await?;
+
+        // No foreign key on todo_id: the sync deletes the events of todos that are gone
+        sqlx::query("CREATE TABLE IF NOT EXISTS google_calendar_events (user_id TEXT NOT NULL REFERENCES users(id), todo_id TEXT NOT NULL, event_id TEXT NOT NULL, etag TEXT, synced_version INTEGER NOT NULL, PRIMARY KEY (user_id, todo_id), UNIQUE (user_id, event_id))")
+            .execute(&pool)
+            .await?;
+
+        sqlx::query("CREATE TABLE IF NOT EXISTS achievements (user_id TEXT NOT NULL REFERENCES users(id), achievement TEXT NOT NULL, unlocked_at DATETIME NOT NULL, PRIMARY KEY (user_id, achievement))")
+            .execute(&pool)
+            .await?;
+
+        sqlx::query("CREATE TABLE IF NOT EXISTS todo_escalations (id TEXT PRIMARY KEY, todo_id TEXT NOT NULL REFERENCES todos(id), from_priority TEXT, to_priority TEXT NOT NULL, due_date DATETIME NOT NULL, escalated_at DATETIME NOT NULL)")
+            .execute(&pool)
+            .await?;
+
+        sqlx::query("CREATE INDEX IF NOT EXISTS idx_todo_escalations_todo ON todo_escalations(todo_id, escalated_at)")
+            .execute(&pool)
+            .await?;
+
+        sqlx::query("CREATE TABLE IF NOT EXISTS todo_occurrences (id TEXT PRIMARY KEY, todo_id TEXT NOT NULL REFERENCES todos(id), due_date DATETIME, completed_at DATETIME NOT NULL, completed_by TEXT)")
+            .execute(&pool)
+            .await?;
+
+        sqlx::query("CREATE INDEX IF NOT EXISTS idx_todo_occurrences_todo ON todo_occurrences(todo_id, completed_at)")
+            .execute(&pool)
+            .await?;
+
+        // Todos completed before occurrences were recorded, or by something
+        // that doesn't run hooks
+        sqlx::query("INSERT INTO todo_occurrences (id, todo_id, due_date, completed_at, completed_by) SELECT lower(hex(randomblob(16))), id, due_date, COALESCE(completed_at, updated_at), NULL FROM todos WHERE completed = TRUE AND id NOT IN (SELECT todo_id FROM todo_occurrences)")
+            .execute(&pool)
+            .await?;
+
+        sqlx::query("CREATE TABLE IF NOT EXISTS todo_dependencies (todo_id TEXT NOT NULL REFERENCES todos(id), blocker_id TEXT NOT NULL REFERENCES todos(id), created_at DATETIME NOT NULL, PRIMARY KEY (todo_id, blocker_id))")
+            .execute(&pool)
+            .await?;
+
+        sqlx::query("CREATE INDEX IF NOT EXISTS idx_todo_dependencies_blocker ON todo_dependencies(blocker_id)")
+            .execute(&pool)
+            .await?;
+
+        sqlx::query("CREATE TABLE IF NOT EXISTS reports (id TEXT PRIMARY KEY, user_id TEXT NOT NULL REFERENCES users(id), week_start DATETIME NOT NULL, week_end DATETIME NOT NULL, completed INTEGER NOT NULL, created INTEGER NOT NULL, overdue INTEGER NOT NULL, busiest_category TEXT, generated_at DATETIME NOT NULL, UNIQUE (user_id, week_start))")
+            .execute(&pool)
+            .await?;
+
+        sqlx::query("CREATE TABLE IF NOT EXISTS user_settings (user_id TEXT PRIMARY KEY REFERENCES users(id), timezone TEXT NOT NULL DEFAULT 'UTC', daily_digest BOOLEAN NOT NULL DEFAULT FALSE, updated_at DATETIME NOT NULL)")
+            .execute(&pool)
+            .await?;
+
+        add_column_if_missing(&pool, "user_settings", "date_format", "TEXT NOT NULL DEFAULT 'text'").await?;
+        add_column_if_missing(&pool, "user_settings", "default_priority", "TEXT").await?;
+        add_column_if_missing(&pool, "user_settings", "default_view", "TEXT NOT NULL DEFAULT 'all'").await?;
+        add_column_if_missing(&pool, "user_settings", "theme", "TEXT NOT NULL DEFAULT 'system'").await?;
+        add_column_if_missing(&pool, "user_settings", "email_notifications", "BOOLEAN NOT NULL DEFAULT TRUE").await?;
+        add_column_if_missing(&pool, "user_settings", "language", "TEXT NOT NULL DEFAULT 'en'").await?;
+        add_column_if_missing(&pool, "user_settings", "leaderboard", "BOOLEAN NOT NULL DEFAULT TRUE").await?;
+        add_column_if_missing(&pool, "user_settings", "weekly_report", "BOOLEAN NOT NULL DEFAULT FALSE").await?;
+
+        sqlx::query("CREATE TABLE IF NOT EXISTS job_runs (id TEXT PRIMARY KEY, job TEXT NOT NULL, trigger TEXT NOT NULL CHECK (trigger IN ('schedule', 'manual')), status TEXT NOT NULL CHECK (status IN ('running', 'succeeded', 'failed')), output TEXT, started_at DATETIME NOT NULL, finished_at DATETIME)")
+            .execute(&pool)
+            .await?;
+
+        sqlx::query("CREATE INDEX IF NOT EXISTS idx_job_runs_job ON job_runs(job, started_at)")
+            .execute(&pool)
+            .await?;
+
+        sqlx::query("CREATE TABLE IF NOT EXISTS auth_events (id TEXT PRIMARY KEY, user_id TEXT, kind TEXT NOT NULL, ip TEXT, created_at DATETIME NOT NULL)")
+            .execute(&pool)
+            .await?;
+
+        add_column_if_missing(&pool, "auth_events", "user_agent", "TEXT").await?;
+
+        sqlx::query("CREATE INDEX IF NOT EXISTS idx_auth_events_user ON auth_events(user_id, created_at)")
+            .execute(&pool)
+            .await?;
+
+        sqlx::query("CREATE INDEX IF NOT EXISTS idx_auth_events_created ON auth_events(created_at)")
+            .execute(&pool)
+            .await?;
+
+        sqlx::query("CREATE TABLE IF NOT EXISTS inboxes (user_id TEXT PRIMARY KEY REFERENCES users(id), token TEXT NOT NULL UNIQUE, created_at DATETIME NOT NULL)")
+            .execute(&pool)
+            .await?;
+
+        sqlx::query("CREATE TABLE IF NOT EXISTS digests (user_id TEXT NOT NULL REFERENCES users(id), local_date TEXT NOT NULL, sent_at DATETIME NOT NULL, PRIMARY KEY (user_id, local_date))")
+            .execute(&pool)
+            .await?;
+
+        // Shared by every todo linking to the same URL; `error` says why a fetch came up empty
+        sqlx::query("CREATE TABLE IF NOT EXISTS link_previews (url TEXT PRIMARY KEY, title TEXT, favicon_url TEXT, error TEXT, fetched_at DATETIME NOT NULL)")
+            .execute(&pool)
+            .await?;
+
+        // Device authorizations in progress; `user_id` is who approved or denied the code
+        sqlx::query("CREATE TABLE IF NOT EXISTS device_codes (device_code_hash TEXT PRIMARY KEY, user_code TEXT NOT NULL UNIQUE, status TEXT NOT NULL CHECK (status IN ('pending', 'approved', 'denied')), user_id TEXT REFERENCES users(id), interval INTEGER NOT NULL, created_at DATETIME NOT NULL, expires_at DATETIME NOT NULL, last_polled_at DATETIME)")
+            .execute(&pool)
+            .await?;
+
+        // Events recorded with the change that raised them, for `outbox::Relay` to hand out
+        sqlx::query("CREATE TABLE IF NOT EXISTS outbox (id INTEGER PRIMARY KEY AUTOINCREMENT, event TEXT NOT NULL, subject_id TEXT NOT NULL, payload TEXT NOT NULL, created_at DATETIME NOT NULL, claimed_at DATETIME, dispatched_at DATETIME)")
+            .execute(&pool)
+            .await?;
+        sqlx::query("CREATE INDEX IF NOT EXISTS idx_outbox_pending ON outbox(id) WHERE dispatched_at IS NULL")
+            .execute(&pool)
+            .await?;
+
+        // Every event, appended with the change that raised it and never changed; `user_id` is set for personal scopes only, like `todo_changes`
+        sqlx::query("CREATE TABLE IF NOT EXISTS events (seq INTEGER PRIMARY KEY AUTOINCREMENT, event TEXT NOT NULL, subject_id TEXT NOT NULL, actor_id TEXT NOT NULL, user_id TEXT, workspace_id TEXT, data TEXT NOT NULL, created_at DATETIME NOT NULL)")
+            .execute(&pool)
+            .await?;
+        sqlx::query("CREATE INDEX IF NOT EXISTS idx_events_scope ON events(workspace_id, user_id, seq)")
+            .execute(&pool)
+            .await?;
+
+        // `events` is a JSON array of event names, empty for all of them
+        sqlx::query("CREATE TABLE IF NOT EXISTS webhooks (id TEXT PRIMARY KEY, user_id TEXT NOT NULL REFERENCES users(id), url TEXT NOT NULL, secret TEXT NOT NULL, events TEXT NOT NULL DEFAULT '[]', created_at DATETIME NOT NULL)")
+            .execute(&pool)
+            .await?;
+
+        // One row per attempt; a redelivery shares `event_id` and `payload` with the delivery it replays
+        sqlx::query("CREATE TABLE IF NOT EXISTS webhook_deliveries (id TEXT PRIMARY KEY, webhook_id TEXT NOT NULL REFERENCES webhooks(id), event_id TEXT NOT NULL, event TEXT NOT NULL, payload TEXT NOT NULL, status TEXT NOT NULL CHECK (status IN ('pending', 'succeeded', 'failed')), response_status INTEGER, response_body TEXT, duration_ms INTEGER, error TEXT, redelivery_of TEXT, created_at DATETIME NOT NULL)")
+            .execute(&pool)
+            .await?;
+        sqlx::query("CREATE INDEX IF NOT EXISTS idx_webhook_deliveries_webhook ON webhook_deliveries(webhook_id, created_at)")
+            .execute(&pool)
+            .await?;
+
+        // Per-user overrides of feature flag defaults
+        sqlx::query("CREATE TABLE IF NOT EXISTS feature_flags (name TEXT NOT NULL, user_id TEXT NOT NULL REFERENCES users(id), enabled BOOLEAN NOT NULL, PRIMARY KEY (name, user_id))")
+            .execute(&pool)
+            .await?;
+
+        // Saved with the todo the key created; `request_hash` is of the body that came with it
+        sqlx::query("CREATE TABLE IF NOT EXISTS idempotency_keys (user_id TEXT NOT NULL REFERENCES users(id), key TEXT NOT NULL, todo_id TEXT, created_at DATETIME NOT NULL, PRIMARY KEY (user_id, key))")
+            .execute(&pool)
+            .await?;
+        add_column_if_missing(&pool, "idempotency_keys", "request_hash", "TEXT").await?;
+
+        // Tombstones: what was deleted and when, for sync, exports and imports.
+        // `user_id` is the owner of personal records; the retention job prunes them.
+        sqlx::query("CREATE TABLE IF NOT EXISTS deleted_items (entity_type TEXT NOT NULL, entity_id TEXT NOT NULL, user_id TEXT, workspace_id TEXT, deleted_at DATETIME NOT NULL, PRIMARY KEY (entity_type, entity_id))")
+            .execute(&pool)
+            .await?;
+
+        sqlx::query("CREATE INDEX IF NOT EXISTS idx_deleted_items_user ON deleted_items(user_id, deleted_at)")
+            .execute(&pool)
+            .await?;
+
+        sqlx::query("CREATE INDEX IF NOT EXISTS idx_deleted_items_deleted_at ON deleted_items(deleted_at)")
+            .execute(&pool)
+            .await?;
+
+        for (entity_type, table, user_id, workspace_id) in [
+            ("todo", "todos", "CASE WHEN OLD.workspace_id IS NULL THEN OLD.user_id END", "OLD.workspace_id"),
+            ("category", "categories", "OLD.user_id", "NULL"),
+            ("saved_filter", "saved_filters", "OLD.user_id", "NULL"),
+            ("rule", "rules", "OLD.user_id", "NULL"),
+        ] {
+            sqlx::query(&format!(
+                "CREATE TRIGGER IF NOT EXISTS {}_tombstone AFTER DELETE ON {} BEGIN DELETE FROM deleted_items WHERE entity_type = '{}' AND entity_id = OLD.id; INSERT INTO deleted_items (entity_type, entity_id, user_id, workspace_id, deleted_at) VALUES ('{}', OLD.id, {}, {}, strftime('%Y-%m-%dT%H:%M:%fZ', 'now')); END",
+                table, table, entity_type, entity_type, user_id, workspace_id,
+            ))
+            .execute(&pool)
+            .await?;
+        }
+
+        let writer = Writer::connect(&options, config.acquire_timeout).await?;
+        Ok(Database { pool, writer, cache, outbox: Arc::new(Notify::new()) })
     }
 
+    /// For reads. Writes go through `write`.
     pub fn get_pool(&self) -> &SqlitePool {
         &self.pool
     }
 
-    pub async fn create_todo(&self, new_todo: NewTodo, user_id: Option<&str>) -> Result<Todo, sqlx::Error> {
-        let id = Uuid::new_v4().to_string();
+    /// The writer connection, once the writes queued before this one are
+    /// done with it. Every INSERT, UPDATE and DELETE runs on it, for one
+    /// statement (`.execute(&mut *db.write().await?)`) or one transaction.
+    /// Don't wait for it again while holding it: the second wait never
+    /// ends before the acquire timeout.
+    pub async fn write(&self) -> Result<WriteConnection, sqlx::Error> {
+        self.writer.acquire().await
+    }
+
+    /// The writer itself, for a task that outlives the borrow of `self`.
+    pub fn writer(&self) -> Writer {
+        self.writer.clone()
+    }
+
+    /// What `outbox::Relay` waits on between batches.
+    pub fn outbox_signal(&self) -> Arc<Notify> {
+        self.outbox.clone()
+    }
+
+    /// Wakes the outbox relay, after a write made outside this type
+    /// recorded events.
+    pub fn events_recorded(&self) {
+        self.outbox.notify_one();
+    }
+
+    /// The internal id of the todo `id` names, which may be its public id.
+    /// Anything else comes back as given, for the lookup that follows to
+    /// find or not.
+    pub async fn todo_id(&self, id: &str) -> Result<String, sqlx::Error> {
+        if !id.starts_with(PUBLIC_ID_PREFIX) {
+            return Ok(id.to_string());
+        }
+        let row = sqlx::query("SELECT id FROM todos WHERE public_id = ?")
+            .bind(id)
+            .fetch_optional(&self.pool)
+            .await?;
+        Ok(row.map_or_else(|| id.to_string(), |row| row.get("id")))
+    }
+
+    pub async fn create_todo(&self, new_todo: NewTodo, scope: &Scope) -> Result<Todo, sqlx::Error> {
+        Ok(self.insert_todo(new_todo, scope, None).await?.expect("only an idempotency key can stop a create"))
+    }
+
+    /// Creates the todo and saves `key` with it, in one transaction. `None`
+    /// if a request with the key created a todo first; nothing is created then.
+    pub(crate) async fn create_todo_once(&self, new_todo: NewTodo, scope: &Scope, key: &idempotency::Key) -> Result<Option<Todo>, sqlx::Error> {
+        self.insert_todo(new_todo, scope, Some(key)).await
+    }
+
+    async fn insert_todo(&self, new_todo: NewTodo, scope: &Scope, key: Option<&idempotency::Key>) -> Result<Option<Todo>, sqlx::Error> {
+        let _timer = QueryTimer::start("create_todo");
+        let todo = Todo::created(new_todo, scope, Utc::now());
+
+        let created = retry(|| async {
+            let mut conn = self.writer.acquire().await?;
+            let mut tx = conn.begin().await?;
+            if let Some(key) = key
+                && !idempotency::claim(&mut tx, &scope.user_id, key, &todo.id, todo.created_at).await?
+            {
+                return Ok(false);
+            }
+            sqlx::query("INSERT INTO todos (id, public_id, text, search_text, notes, completed, category, tags, color, latitude, longitude, location_name, priority, due_date, user_id, workspace_id, estimate_minutes, spent_minutes, created_at, updated_at) VALUES (?, ?, ?, ?, ?, ?, ?, ?, ?, ?, ?, ?, ?, ?, ?, ?, ?, ?, ?, ?)")
+                .bind(&todo.id)
+                .bind(&todo.public_id)
+                .bind(encryption::encrypt(&todo.id, "text", &todo.text))
+                .bind(search::search_text(&todo.text))
+                .bind(encryption::encrypt_optional(&todo.id, "notes", todo.notes.as_deref()))
+                .bind(false)
+                .bind(&todo.category)
+                .bind(&todo.tags)
+                .bind(&todo.color)
+                .bind(todo.latitude)
+                .bind(todo.longitude)
+                .bind(&todo.location_name)
+                .bind(todo.priority.map(Priority::as_str))
+                .bind(todo.due_date)
+                .bind(&scope.user_id)
+                .bind(&scope.workspace_id)
+                .bind(todo.estimate_minutes)
+                .bind(todo.spent_minutes)
+                .bind(todo.created_at)
+                .bind(todo.updated_at)
+                .execute(&mut *tx)
+                .await?;
+            outbox::record(&mut tx, &Event::TodoCreated { todo: &todo, actor_id: &scope.user_id }).await?;
+            tx.commit().await?;
+            Ok(true)
+        })
+        .await?;
+        if !created {
+            return Ok(None);
+        }
+        self.invalidate(Some(&scope.cache_key())).await;
+        self.events_recorded();
+
+        Ok(Some(todo))
+    }
+
+    /// Creates up to `MAX_BATCH` todos with one multi-row INSERT; either all
+    /// of them are created or none are. The todos come back in the given order.
+    pub async fn create_todos(&self, new_todos: Vec<NewTodo>, scope: &Scope) -> Result<Vec<Todo>, sqlx::Error> {
+        if new_todos.is_empty() {
+            return Ok(Vec::new());
+        }
+        let _timer = QueryTimer::start("create_todos");
         let now = Utc::now();
-        let tags_json = new_todo
-            .tags
-            .map(|tags| serde_json::to_string(&tags).unwrap_or_default());
+        let todos: Vec<Todo> = new_todos.into_iter().map(|new_todo| Todo::created(new_todo, scope, now)).collect();
 
-        sqlx::query("INSERT INTO todos (id, text, completed, category, tags, priority, due_date, user_id, created_at, updated_at) VALUES (?, ?, ?, ?, ?, ?, ?, ?, ?, ?)")
-            .bind(&id)
-            .bind(&new_todo.text)
-            .bind(false)
-            .bind(&new_todo.category)
-            .bind(&tags_json)
-            .bind(&new_todo.priority)
-            .bind(&new_todo.due_date)
-            .bind(user_id)
-            .bind(&now)
-            .bind(&now)
-            .execute(&self.pool)
-            .await?;
-
-        Ok(Todo {
-            id,
-            text: new_todo.text,
-            completed: false,
-            category: new_todo.category,
-            tags: tags_json,
-            priority: new_todo.priority,
-            due_date: new_todo.due_date,
-            user_id: user_id.map(String::from),
-            created_at: now,
-            updated_at: now,
+        retry(|| async {
+            let mut query = QueryBuilder::<Sqlite>::new("INSERT INTO todos (id, public_id, text, search_text, notes, completed, category, tags, color, latitude, longitude, location_name, priority, due_date, user_id, workspace_id, estimate_minutes, spent_minutes, created_at, updated_at) ");
+            query.push_values(&todos, |mut row, todo| {
+                row.push_bind(&todo.id)
+                    .push_bind(&todo.public_id)
+                    .push_bind(encryption::encrypt(&todo.id, "text", &todo.text))
+                    .push_bind(search::search_text(&todo.text))
+                    .push_bind(encryption::encrypt_optional(&todo.id, "notes", todo.notes.as_deref()))
+                    .push_bind(false)
+                    .push_bind(&todo.category)
+                    .push_bind(&todo.tags)
+                    .push_bind(&todo.color)
+                    .push_bind(todo.latitude)
+                    .push_bind(todo.longitude)
+                    .push_bind(&todo.location_name)
+                    .push_bind(todo.priority.map(Priority::as_str))
+                    .push_bind(todo.due_date)
+                    .push_bind(&scope.user_id)
+                    .push_bind(&scope.workspace_id)
+                    .push_bind(todo.estimate_minutes)
+                    .push_bind(todo.spent_minutes)
+                    .push_bind(now)
+                    .push_bind(now);
+            });
+            let mut conn = self.writer.acquire().await?;
+            let mut tx = conn.begin().await?;
+            query.build().execute(&mut *tx).await?;
+            for todo in &todos {
+                outbox::record(&mut tx, &Event::TodoCreated { todo, actor_id: &scope.user_id }).await?;
+            }
+            tx.commit().await
         })
+        .await?;
+        self.invalidate(Some(&scope.cache_key())).await;
+        self.events_recorded();
+
+        Ok(todos)
+    }
+
+    /// Every todo in `scope`, oldest first, read from one query cursor as the
+    /// receiver is drained, so memory use doesn't grow with the list. The
+    /// query holds a pooled connection until it finishes or the receiver is
+    /// dropped.
+    pub fn stream_todos(&self, scope: &Scope) -> mpsc::Receiver<Result<Todo, sqlx::Error>> {
+        let (sender, receiver) = mpsc::channel(64);
+        let pool = self.pool.clone();
+        let (condition, value) = scope.condition();
+        let query = format!("SELECT {} FROM todos WHERE {}? ORDER BY created_at, id", TODO_COLUMNS, condition);
+        let value = value.to_string();
+
+        tokio::spawn(async move {
+            let mut rows = sqlx::query(&query).bind(&value).fetch(&pool);
+            while let Some(row) = rows.next().await {
+                if sender.send(row.map(|row| Todo::from_row(&row))).await.is_err() {
+                    break;
+                }
+            }
+        });
+        receiver
+    }
+
+    pub async fn get_todos(&self, scope: &Scope) -> Result<Vec<Todo>, sqlx::Error> {
+        let key = format!("todos:{}", scope.cache_key());
+        if let Some(todos) = self.cached(&key).await {
+            return Ok(refreshed(todos));
+        }
+
+        let _timer = QueryTimer::start("get_todos");
+        let (condition, value) = scope.condition();
+        // Unowned todos from before accounts existed stay visible in personal lists
+        let unowned = if scope.workspace_id.is_none() { " OR (user_id IS NULL AND workspace_id IS NULL)" } else { "" };
+        let rows = sqlx::query(&format!("SELECT {} FROM todos WHERE ({}?){} ORDER BY created_at DESC", TODO_COLUMNS, condition, unowned))
+            .bind(value)
+            .fetch_all(&self.pool)
+            .await?;
+
+        let todos: Vec<Todo> = rows.iter().map(Todo::from_row).collect();
+        self.store(&key, &todos).await;
+        Ok(todos)
+    }
+
+    /// Up to `limit + 1` todos after `after` in `get_todos` order, matching
+    /// `filter`; see `pagination::Page`. Not cached, unlike full lists.
+    pub async fn get_todos_page(&self, scope: &Scope, filter: &TodoFilter, tz: Tz, after: Option<&Cursor>, limit: u32) -> Result<Vec<Todo>, sqlx::Error> {
+        let _timer = QueryTimer::start("get_todos_page");
+        let (condition, value) = scope.condition();
+        let unowned = if scope.workspace_id.is_none() { " OR (user_id IS NULL AND workspace_id IS NULL)" } else { "" };
+        let mut query = QueryBuilder::new(format!("SELECT {} FROM todos WHERE ({}", TODO_COLUMNS, condition));
+        query.push_bind(value).push(format!("{})", unowned));
+        filter.push_conditions(&mut query, tz);
+        if let Some(after) = after {
+            query
+                .push(" AND (created_at < ")
+                .push_bind(after.created_at)
+                .push(" OR (created_at = ")
+                .push_bind(after.created_at)
+                .push(" AND id < ")
+                .push_bind(after.id.clone())
+                .push("))");
+        }
+        query.push(" ORDER BY created_at DESC, id DESC LIMIT ").push_bind(limit as i64 + 1);
+
+        let rows = query.build().fetch_all(&self.pool).await?;
+        Ok(rows.iter().map(Todo::from_row).collect())
+    }
+
+    pub async fn get_todo(&self, id: &str, scope: &Scope) -> Result<Option<Todo>, sqlx::Error> {
+        let _timer = QueryTimer::start("get_todo");
+        let (condition, value) = scope.condition();
+        let row = sqlx::query(&format!("SELECT {} FROM todos WHERE {}? AND id = ?", TODO_COLUMNS, condition))
+            .bind(value)
+            .bind(id)
+            .fetch_optional(&self.pool)
+            .await?;
+
+        Ok(row.as_ref().map(Todo::from_row))
+    }
+
+    pub async fn find_todos(&self, scope: &Scope, filter: &TodoFilter, tz: Tz) -> Result<Vec<Todo>, sqlx::Error> {
+        let _timer = QueryTimer::start("find_todos");
+        let (condition, value) = scope.condition();
+        let mut query = QueryBuilder::new(format!("SELECT {} FROM todos WHERE {}", TODO_COLUMNS, condition));
+        query.push_bind(value);
+        filter.push_conditions(&mut query, tz);
+        query.push(" ORDER BY created_at DESC");
+
+        let rows = query.build().fetch_all(&self.pool).await?;
+        Ok(rows.iter().map(Todo::from_row).collect())
     }
 
-    pub async fn get_todos(&self, user_id: Option<&str>) -> Result<Vec<Todo>, sqlx::Error> {
+    /// Every todo, or only those owned by `user_id`; used by export.
+    pub async fn all_todos(&self, user_id: Option<&str>) -> Result<Vec<Todo>, sqlx::Error> {
         let rows = match user_id {
             Some(uid) => {
-                sqlx::query("SELECT id, text, completed, category, tags, priority, due_date, user_id, created_at, updated_at FROM todos WHERE user_id = ? OR user_id IS NULL ORDER BY created_at DESC")
+                sqlx::query(&format!("SELECT {} FROM todos WHERE user_id = ? ORDER BY created_at", TODO_COLUMNS))
                     .bind(uid)
                     .fetch_all(&self.pool)
                     .await?
             }
             None => {
-                sqlx::query("SELECT id, text, completed, category, tags, priority, due_date, user_id, created_at, updated_at FROM todos WHERE user_id IS NULL ORDER BY created_at DESC")
+                sqlx::query(&format!("SELECT {} FROM todos ORDER BY created_at", TODO_COLUMNS))
                     .fetch_all(&self.pool)
                     .await?
             }
         };
 
-        let mut todos = Vec::new();
-        for row in rows {
-            todos.push(Todo {
-                id: row.get("id"),
-                text: row.get("text"),
-                completed: row.get("completed"),
-                category: row.get("category"),
-                tags: row.get("tags"),
-                priority: row.get("priority"),
-                due_date: row.get("due_date"),
-                user_id: row.get("user_id"),
-                created_at: row.get("created_at"),
-                updated_at: row.get("updated_at"),
-            });
+        Ok(rows.iter().map(Todo::from_row).collect())
+    }
+
+    /// Inserts a previously exported todo as-is; returns false if its id
+    /// already exists or a todo with it was deleted here.
+    pub async fn import_todo(&self, todo: &Todo) -> Result<bool, sqlx::Error> {
+        // Exports from before public ids lack them
+        let public_id = if todo.public_id.is_empty() { new_public_id() } else { todo.public_id.clone() };
+        let query = format!("INSERT OR IGNORE INTO todos ({}, search_text) SELECT ?1, ?2, ?3, ?4, ?5, ?6, ?7, ?8, ?9, ?10, ?11, ?12, ?13, ?14, ?15, ?16, ?17, ?18, ?19, ?20, ?21, ?22, ?23, ?24 WHERE NOT EXISTS (SELECT 1 FROM deleted_items WHERE entity_type = 'todo' AND entity_id = ?1)", TODO_COLUMNS);
+        let result = retry(|| async {
+            let mut conn = self.writer.acquire().await?;
+            sqlx::query(&query)
+                .bind(&todo.id)
+                .bind(encryption::encrypt(&todo.id, "text", &todo.text))
+                .bind(encryption::encrypt_optional(&todo.id, "notes", todo.notes.as_deref()))
+                .bind(todo.completed)
+                .bind(&todo.category)
+                .bind(&todo.tags)
+                .bind(&todo.color)
+                .bind(todo.latitude)
+                .bind(todo.longitude)
+                .bind(&todo.location_name)
+                .bind(todo.priority.map(Priority::as_str))
+                .bind(todo.due_date)
+                .bind(&todo.user_id)
+                .bind(&todo.workspace_id)
+                .bind(&todo.assignee_id)
+                .bind(todo.estimate_minutes)
+                .bind(todo.spent_minutes)
+                .bind(todo.completed_at)
+                .bind(todo.created_at)
+                .bind(todo.updated_at)
+                .bind(todo.version)
+                .bind(todo.auto_escalate)
+                .bind(&public_id)
+                .bind(search::search_text(&todo.text))
+                .execute(&mut *conn)
+                .await
+        })
+        .await?;
+        self.invalidate(todo_cache_key(todo).as_deref()).await;
+
+        Ok(result.rows_affected() > 0)
+    }
+
+    /// Deletes everything stored for a user except the users row itself.
+    pub async fn delete_user_data(&self, user_id: &str) -> Result<(), sqlx::Error> {
+        retry(|| async {
+            let mut conn = self.writer.acquire().await?;
+            let mut tx = conn.begin().await?;
+
+            sqlx::query("DELETE FROM todo_dependencies WHERE todo_id IN (SELECT id FROM todos WHERE user_id = ?1) OR blocker_id IN (SELECT id FROM todos WHERE user_id = ?1)")
+                .bind(user_id)
+                .execute(&mut *tx)
+                .await?;
+            sqlx::query("DELETE FROM mentions WHERE user_id = ?1 OR mentioned_by = ?1 OR todo_id IN (SELECT id FROM todos WHERE user_id = ?1)")
+                .bind(user_id)
+                .execute(&mut *tx)
+                .await?;
+            sqlx::query("DELETE FROM todo_escalations WHERE todo_id IN (SELECT id FROM todos WHERE user_id = ?)")
+                .bind(user_id)
+                .execute(&mut *tx)
+                .await?;
+            sqlx::query("DELETE FROM todo_occurrences WHERE todo_id IN (SELECT id FROM todos WHERE user_id = ?)")
+                .bind(user_id)
+                .execute(&mut *tx)
+                .await?;
+            sqlx::query("UPDATE todo_occurrences SET completed_by = NULL WHERE completed_by = ?")
+                .bind(user_id)
+                .execute(&mut *tx)
+                .await?;
+            // Other members may have planned the user's workspace todos
+            sqlx::query("DELETE FROM myday WHERE user_id = ?1 OR todo_id IN (SELECT id FROM todos WHERE user_id = ?1)")
+                .bind(user_id)
+                .execute(&mut *tx)
+                .await?;
+            sqlx::query("UPDATE todos SET assignee_id = NULL WHERE assignee_id = ?")
+                .bind(user_id)
+                .execute(&mut *tx)
+                .await?;
+            sqlx::query("DELETE FROM notification_deliveries WHERE notification_id IN (SELECT id FROM notifications WHERE user_id = ?)")
+                .bind(user_id)
+                .execute(&mut *tx)
+                .await?;
+            // Events carry the todo as it was, so they go with it
+            for table in ["outbox", "events"] {
+                sqlx::query(&format!("DELETE FROM {} WHERE subject_id = ?1 OR subject_id IN (SELECT id FROM todos WHERE user_id = ?1)", table))
+                    .bind(user_id)
+                    .execute(&mut *tx)
+                    .await?;
+            }
+            sqlx::query("DELETE FROM webhook_deliveries WHERE webhook_id IN (SELECT id FROM webhooks WHERE user_id = ?)")
+                .bind(user_id)
+                .execute(&mut *tx)
+                .await?;
+            for table in ["todos", "todo_changes", "saved_filters", "rules", "categories", "points_awards", "achievements", "google_calendar_events", "google_calendars", "reports", "workspace_members", "notifications", "notification_channels", "user_settings", "digests", "inboxes", "idempotency_keys", "feature_flags", "device_codes", "webhooks", "auth_events", "deleted_items"] {
+                sqlx::query(&format!("DELETE FROM {} WHERE user_id = ?", table))
+                    .bind(user_id)
+                    .execute(&mut *tx)
+                    .await?;
+            }
+
+            tx.commit().await
+        })
+        .await?;
+        // Their workspace todos went too, so every list may have changed
+        self.invalidate(None).await;
+        Ok(())
+    }
+
+    pub async fn get_overdue_todos(&self, scope: &Scope) -> Result<Vec<Todo>, sqlx::Error> {
+        let _timer = QueryTimer::start("get_overdue_todos");
+        let (condition, value) = scope.condition();
+        let rows = sqlx::query(&format!("SELECT {} FROM todos WHERE {}? AND completed = FALSE AND due_date < ? ORDER BY due_date", TODO_COLUMNS, condition))
+            .bind(value)
+            .bind(Utc::now())
+            .fetch_all(&self.pool)
+            .await?;
+
+        Ok(rows.iter().map(Todo::from_row).collect())
+    }
+
+    /// Open todos due from now through the end of the day `days` days from today in `tz`.
+    pub async fn get_upcoming_todos(&self, scope: &Scope, days: u32, tz: Tz) -> Result<Vec<Todo>, sqlx::Error> {
+        let _timer = QueryTimer::start("get_upcoming_todos");
+        let now = Utc::now();
+        let (condition, value) = scope.condition();
+
+        let rows = sqlx::query(&format!("SELECT {} FROM todos WHERE {}? AND completed = FALSE AND due_date >= ? AND due_date < ? ORDER BY due_date", TODO_COLUMNS, condition))
+            .bind(value)
+            .bind(now)
+            .bind(dates::end_of_days_ahead(now.with_timezone(&tz), days))
+            .fetch_all(&self.pool)
+            .await?;
+
+        Ok(rows.iter().map(Todo::from_row).collect())
+    }
+
+    /// Flips `completed` on behalf of `actor_id`. With `expected_version`,
+    /// only toggles if the todo is still at that version.
+    pub async fn toggle_todo(&self, id: &str, actor_id: &str, expected_version: Option<i64>) -> Result<Todo, TodoError> {
+        let _timer = QueryTimer::start("toggle_todo");
+        let now = Utc::now();
+
+        let toggled = retry(|| async {
+            let mut conn = self.writer.acquire().await?;
+            let mut tx = conn.begin().await?;
+            let row = sqlx::query(&format!("UPDATE todos SET completed = NOT completed, completed_at = CASE WHEN completed THEN NULL ELSE ? END, updated_at = ?, version = version + 1 WHERE id = ? AND version = COALESCE(?, version) RETURNING {}", TODO_COLUMNS))
+                .bind(now)
+                .bind(now)
+                .bind(id)
+                .bind(expected_version)
+                .fetch_optional(&mut *tx)
+                .await?;
+            let todo = row.as_ref().map(Todo::from_row);
+            if let Some(todo) = &todo {
+                let event = if todo.completed {
+                    Event::TodoCompleted { todo, actor_id }
+                } else {
+                    Event::TodoReopened { todo, actor_id }
+                };
+                outbox::record(&mut tx, &event).await?;
+            }
+            tx.commit().await?;
+            Ok(todo)
+        })
+        .await?;
+
+        match toggled {
+            Some(todo) => {
+                self.invalidate(todo_cache_key(&todo).as_deref()).await;
+                self.events_recorded();
+                Ok(todo)
+            }
+            None => {
+                let exists = sqlx::query("SELECT 1 FROM todos WHERE id = ?").bind(id).fetch_optional(&self.pool).await?.is_some();
+                Err(if exists { TodoError::VersionMismatch } else { TodoError::NotFound })
+            }
+        }
+    }
+
+    /// Completes every open todo in `scope` matching `filter` in one UPDATE,
+    /// leaving out those still blocked by an open todo. Returns the todos it
+    /// completed.
+    pub async fn complete_todos(&self, scope: &Scope, filter: &TodoFilter, tz: Tz) -> Result<Vec<Todo>, sqlx::Error> {
+        let _timer = QueryTimer::start("complete_todos");
+        let now = Utc::now();
+        let (condition, value) = scope.condition();
+
+        let todos = retry(|| async {
+            let mut query = QueryBuilder::<Sqlite>::new("UPDATE todos SET completed = TRUE, completed_at = ");
+            query
+                .push_bind(now)
+                .push(", updated_at = ")
+                .push_bind(now)
+                .push(", version = version + 1 WHERE ")
+                .push(condition)
+                .push_bind(value)
+                .push(" AND completed = FALSE AND NOT EXISTS (SELECT 1 FROM todo_dependencies d JOIN todos b ON b.id = d.blocker_id WHERE d.todo_id = todos.id AND b.completed = FALSE)");
+            filter.push_conditions(&mut query, tz);
+            query.push(format!(" RETURNING {}", TODO_COLUMNS));
+            let mut conn = self.writer.acquire().await?;
+            let mut tx = conn.begin().await?;
+            let todos: Vec<Todo> = query.build().fetch_all(&mut *tx).await?.iter().map(Todo::from_row).collect();
+            for todo in &todos {
+                outbox::record(&mut tx, &Event::TodoCompleted { todo, actor_id: &scope.user_id }).await?;
+            }
+            tx.commit().await?;
+            Ok(todos)
+        })
+        .await?;
+        if !todos.is_empty() {
+            self.invalidate(Some(&scope.cache_key())).await;
+            self.events_recorded();
         }
+
         Ok(todos)
     }
 
-    pub async fn toggle_todo(&self, id: &str) -> Result<Option<Todo>, sqlx::Error> {
+    /// Applies `update` if the todo is still at `expected_version`.
+    pub async fn update_todo(&self, id: &str, scope: &Scope, update: UpdateTodo, expected_version: i64) -> Result<Todo, TodoError> {
+        let _timer = QueryTimer::start("update_todo");
         let now = Utc::now();
+        let tags_json = update
+            .tags
+            .map(|tags| serde_json::to_string(&tags).unwrap_or_default());
+        let text = encryption::encrypt_optional(id, "text", update.text.as_deref());
+        let search_text = update.text.as_deref().and_then(search::search_text);
+        // An empty string clears the notes, so it stays as it is
+        let notes = update.notes.as_deref().map(|notes| if notes.is_empty() { String::new() } else { encryption::encrypt(id, "notes", notes) });
+        let clear_location = update.location_name.as_deref() == Some("");
+        let clear_due_date = update.due.as_deref() == Some("");
 
-        sqlx::query("UPDATE todos SET completed = NOT completed, updated_at = ? WHERE id = ?")
-            .bind(&now)
-            .bind(id)
-            .execute(&self.pool)
+        let (condition, value) = scope.condition();
+
+        let text_changed = update.text.is_some();
+        let query = format!("UPDATE todos SET text = COALESCE(?, text), search_text = IIF(? IS NULL, search_text, ?), notes = IIF(? IS NULL, notes, NULLIF(?, '')), category = COALESCE(?, category), tags = COALESCE(?, tags), color = IIF(? IS NULL, color, NULLIF(?, '')), latitude = IIF(?, NULL, COALESCE(?, latitude)), longitude = IIF(?, NULL, COALESCE(?, longitude)), location_name = IIF(?, NULL, COALESCE(?, location_name)), priority = COALESCE(?, priority), due_date = IIF(?, NULL, COALESCE(?, due_date)), estimate_minutes = COALESCE(?, estimate_minutes), spent_minutes = COALESCE(?, spent_minutes), auto_escalate = COALESCE(?, auto_escalate), updated_at = ?, version = version + 1 WHERE {}? AND id = ? AND version = ? RETURNING {}", condition, TODO_COLUMNS);
+        let updated = retry(|| async {
+            let mut conn = self.writer.acquire().await?;
+            let mut tx = conn.begin().await?;
+            let row = sqlx::query(&query)
+                .bind(&text)
+                .bind(&text)
+                .bind(&search_text)
+                .bind(&notes)
+                .bind(&notes)
+                .bind(&update.category)
+                .bind(&tags_json)
+                .bind(&update.color)
+                .bind(&update.color)
+                .bind(clear_location)
+                .bind(update.latitude)
+                .bind(clear_location)
+                .bind(update.longitude)
+                .bind(clear_location)
+                .bind(&update.location_name)
+                .bind(update.priority.map(Priority::as_str))
+                .bind(clear_due_date)
+                .bind(update.due_date)
+                .bind(update.estimate_minutes)
+                .bind(update.spent_minutes)
+                .bind(update.auto_escalate)
+                .bind(now)
+                .bind(value)
+                .bind(id)
+                .bind(expected_version)
+                .fetch_optional(&mut *tx)
+                .await?;
+            let todo = row.as_ref().map(Todo::from_row);
+            if let Some(todo) = &todo {
+                outbox::record(&mut tx, &Event::TodoUpdated { todo, actor_id: &scope.user_id, text_changed }).await?;
+            }
+            tx.commit().await?;
+            Ok(todo)
+        })
+        .await?;
+
+        let Some(todo) = updated else {
+            self.get_todo(id, scope).await?.ok_or(TodoError::NotFound)?;
+            return Err(TodoError::VersionMismatch);
+        };
+        self.invalidate(Some(&scope.cache_key())).await;
+        self.events_recorded();
+        Ok(todo)
+    }
+
+    /// Sets or clears the assignee; the caller checks the assignee may see the todo.
+    pub async fn assign_todo(&self, id: &str, scope: &Scope, assignee_id: Option<&str>) -> Result<Todo, TodoError> {
+        let _timer = QueryTimer::start("assign_todo");
+        let (condition, value) = scope.condition();
+
+        let query = format!("UPDATE todos SET assignee_id = ?, updated_at = ?, version = version + 1 WHERE {}? AND id = ? RETURNING {}", condition, TODO_COLUMNS);
+        let assigned = retry(|| async {
+            let mut conn = self.writer.acquire().await?;
+            let mut tx = conn.begin().await?;
+            let row = sqlx::query(&query)
+                .bind(assignee_id)
+                .bind(Utc::now())
+                .bind(value)
+                .bind(id)
+                .fetch_optional(&mut *tx)
+                .await?;
+            let todo = row.as_ref().map(Todo::from_row);
+            if let Some(todo) = &todo {
+                outbox::record(&mut tx, &Event::TodoAssigned { todo, actor_id: &scope.user_id }).await?;
+            }
+            tx.commit().await?;
+            Ok(todo)
+        })
+        .await?;
+        let todo = assigned.ok_or(TodoError::NotFound)?;
+
+        self.invalidate(Some(&scope.cache_key())).await;
+        self.events_recorded();
+        Ok(todo)
+    }
+
+    /// Takes `member_id` out of the workspace and unassigns the workspace
+    /// todos they had: former members can't see them, so they can't work on
+    /// them either. Each unassignment is a change like `assign_todo` makes.
+    pub async fn remove_workspace_member(&self, workspace_id: &str, member_id: &str, actor_id: &str) -> Result<(), sqlx::Error> {
+        let _timer = QueryTimer::start("remove_workspace_member");
+
+        let query = format!("UPDATE todos SET assignee_id = NULL, updated_at = ?, version = version + 1 WHERE workspace_id = ? AND assignee_id = ? RETURNING {}", TODO_COLUMNS);
+        retry(|| async {
+            let mut conn = self.writer.acquire().await?;
+            let mut tx = conn.begin().await?;
+            sqlx::query("DELETE FROM workspace_members WHERE workspace_id = ? AND user_id = ?")
+                .bind(workspace_id)
+                .bind(member_id)
+                .execute(&mut *tx)
+                .await?;
+            let rows = sqlx::query(&query)
+                .bind(Utc::now())
+                .bind(workspace_id)
+                .bind(member_id)
+                .fetch_all(&mut *tx)
+                .await?;
+            for row in &rows {
+                outbox::record(&mut tx, &Event::TodoAssigned { todo: &Todo::from_row(row), actor_id }).await?;
+            }
+            tx.commit().await
+        })
+        .await?;
+
+        self.invalidate(Some(&format!("ws:{}", workspace_id))).await;
+        self.events_recorded();
+        Ok(())
+    }
+
+    pub async fn get_stats(&self, scope: &Scope) -> Result<TodoStats, sqlx::Error> {
+        let _timer = QueryTimer::start("get_stats");
+        let (condition, value) = scope.condition();
+        let totals = sqlx::query(&format!("SELECT COUNT(*) AS total, COALESCE(SUM(completed), 0) AS completed FROM todos WHERE {}?", condition))
+            .bind(value)
+            .fetch_one(&self.pool)
             .await?;
+        let total: i64 = totals.get("total");
+        let completed: i64 = totals.get("completed");
 
-        let row = sqlx::query("SELECT id, text, completed, category, tags, priority, due_date, user_id, created_at, updated_at FROM todos WHERE id = ?")
-            .bind(id)
-            .fetch_optional(&self.pool)
+        // Remaining effort only counts open todos; overspent todos contribute zero
+        let rows = sqlx::query(&format!("SELECT category, COUNT(*) AS open, COALESCE(SUM(estimate_minutes), 0) AS estimate, COALESCE(SUM(spent_minutes), 0) AS spent, COALESCE(SUM(MAX(COALESCE(estimate_minutes, 0) - COALESCE(spent_minutes, 0), 0)), 0) AS remaining FROM todos WHERE {}? AND completed = FALSE GROUP BY category ORDER BY remaining DESC, category", condition))
+            .bind(value)
+            .fetch_all(&self.pool)
             .await?;
 
-        if let Some(row) = row {
-            Ok(Some(Todo {
-                id: row.get("id"),
-                text: row.get("text"),
-                completed: row.get("completed"),
+        let by_category: Vec<CategoryEffort> = rows
+            .into_iter()
+            .map(|row| CategoryEffort {
                 category: row.get("category"),
-                tags: row.get("tags"),
-                priority: row.get("priority"),
-                due_date: row.get("due_date"),
-                user_id: row.get("user_id"),
-                created_at: row.get("created_at"),
-                updated_at: row.get("updated_at"),
-            }))
-        } else {
-            Ok(None)
-        }
+                open: row.get("open"),
+                estimate_minutes: row.get("estimate"),
+                spent_minutes: row.get("spent"),
+                remaining_minutes: row.get("remaining"),
+            })
+            .collect();
+
+        Ok(TodoStats {
+            total,
+            completed,
+            open: total - completed,
+            remaining_minutes: by_category.iter().map(|c| c.remaining_minutes).sum(),
+            by_category,
+        })
     }
 
-    pub async fn get_categories(&self) -> Result<Vec<String>, sqlx::Error> {
-        let rows = sqlx::query("SELECT DISTINCT category FROM todos WHERE category IS NOT NULL")
+    /// The tags and categories of the todos in `scope`, counted. Cached like
+    /// `get_todos`, since autocomplete asks on every keystroke.
+    pub async fn terms(&self, scope: &Scope) -> Result<Terms, sqlx::Error> {
+        let key = format!("terms:{}", scope.cache_key());
+        if let Some(terms) = self.cached(&key).await {
+            return Ok(terms);
+        }
+
+        let _timer = QueryTimer::start("terms");
+        let (condition, value) = scope.condition();
+        let count = |rows: Vec<SqliteRow>| -> Vec<TermCount> {
+            rows.iter().map(|row| TermCount { term: row.get("term"), uses: row.get("uses") }).collect()
+        };
+        let categories = sqlx::query(&format!("SELECT category AS term, COUNT(*) AS uses FROM todos WHERE {}? AND category IS NOT NULL GROUP BY category ORDER BY uses DESC, term", condition))
+            .bind(value)
             .fetch_all(&self.pool)
             .await?;
+        let tags = sqlx::query(&format!("SELECT tag.value AS term, COUNT(*) AS uses FROM todos, json_each(todos.tags) AS tag WHERE {}? AND json_valid(todos.tags) GROUP BY tag.value ORDER BY uses DESC, term", condition))
+            .bind(value)
+            .fetch_all(&self.pool)
+            .await?;
+
+        let terms = Terms { tags: count(tags), categories: count(categories) };
+        self.store(&key, &terms).await;
+        Ok(terms)
+    }
 
-        Ok(
-            rows.into_iter()
-                .filter_map(|row| row.get::<Option<String>, _>("category"))
-                .collect(),
-        )
+    // Cache failures are logged and otherwise ignored; the database stays the source of truth
+
+    async fn cached<T: DeserializeOwned>(&self, key: &str) -> Option<T> {
+        match self.cache.get(key).await {
+            Ok(value) => value.and_then(|json| serde_json::from_str(&json).ok()),
+            Err(e) => {
+                tracing::warn!(error = %e, key, "Cache read failed");
+                None
+            }
+        }
     }
+
+    async fn store<T: Serialize>(&self, key: &str, value: &T) {
+        let Ok(json) = serde_json::to_string(value) else {
+            return;
+        };
+        if let Err(e) = self.cache.set(key, json, CACHE_TTL).await {
+            tracing::warn!(error = %e, key, "Cache write failed");
+        }
+    }
+
+    /// Drops cached lists that include `todo`, for changes made outside this type.
+    pub async fn todo_changed(&self, todo: &Todo) {
+        self.invalidate(todo_cache_key(todo).as_deref()).await;
+    }
+
+    /// Drops every cached list, for bulk changes made outside this type.
+    pub async fn todos_changed(&self) {
+        self.invalidate(None).await;
+    }
+
+    /// Drops cached lists a write to the scope with cache key `scope` may have
+    /// changed. Unowned todos show up in every personal list, so those (and
+    /// `None`) clear everything.
+    async fn invalidate(&self, scope: Option<&str>) {
+        let result = match scope {
+            Some(scope) => self
+                .cache
+                .delete(&format!("todos:{}", scope))
+                .await
+                .and(self.cache.delete(&format!("terms:{}", scope)).await),
+            None => self
+                .cache
+                .delete_prefix("todos:")
+                .await
+                .and(self.cache.delete_prefix("terms:").await),
+        };
+        if let Err(e) = result {
+            tracing::warn!(error = %e, "Cache invalidation failed");
+        }
+    }
+}
+
+/// Cached todos with `is_overdue` recomputed, since time has passed since they were loaded.
+fn refreshed(todos: Vec<Todo>) -> Vec<Todo> {
+    todos
+        .into_iter()
+        .map(|mut todo| {
+            todo.is_overdue = is_overdue(todo.completed, todo.due_date);
+            todo
+        })
+        .collect()
+}
+
+/// How many times `retry` runs a write before giving up.
+const WRITE_ATTEMPTS: u32 = 4;
+
+/// SQLite's `SQLITE_BUSY` or `SQLITE_LOCKED`, under any extended code.
+/// `busy_timeout` already waits these out in most cases, but not all: a
+/// transaction that read before another connection committed gets
+/// `SQLITE_BUSY_SNAPSHOT` right away when it tries to write.
+fn is_busy(e: &sqlx::Error) -> bool {
+    let sqlx::Error::Database(e) = e else {
+        return false;
+    };
+    e.code()
+        .and_then(|code| code.parse::<i32>().ok())
+        .is_some_and(|code| matches!(code & 0xff, 5 | 6))
+}
+
+/// Runs a write, running it again while the database is busy: up to
+/// `WRITE_ATTEMPTS` times in all, waiting 20, 40, 80ms between attempts,
+/// each plus up to as much again of random jitter so the writers that
+/// collided don't collide again. `op` must be safe to repeat, e.g. a single
+/// statement or a whole transaction.
+pub async fn retry<T, F, Fut>(mut op: F) -> Result<T, sqlx::Error>
+where
+    F: FnMut() -> Fut,
+    Fut: Future<Output = Result<T, sqlx::Error>>,
+{
+    let mut delay = Duration::from_millis(20);
+    for _ in 1..WRITE_ATTEMPTS {
+        match op().await {
+            Err(e) if is_busy(&e) => {
+                tracing::debug!(error = %e, ?delay, "Database busy; retrying");
+                tokio::time::sleep(delay.mul_f64(1.0 + fastrand::f64())).await;
+                delay *= 2;
+            }
+            result => return result,
+        }
+    }
+    op().await
+}
+
+/// The status for a failed query: 503 when the database is overloaded (no
+/// connection came free within `DATABASE_ACQUIRE_TIMEOUT_SECS`, or it stayed
+/// busy through the retries), so clients know to try again; 500 otherwise.
+pub fn error_status(e: &sqlx::Error) -> axum::http::StatusCode {
+    if matches!(e, sqlx::Error::PoolTimedOut) || is_busy(e) {
+        tracing::warn!(error = %e, "Database overloaded");
+        axum::http::StatusCode::SERVICE_UNAVAILABLE
+    } else {
+        axum::http::StatusCode::INTERNAL_SERVER_ERROR
+    }
+}
+
+async fn add_column_if_missing(pool: &SqlitePool, table: &str, column: &str, definition: &str) -> Result<(), sqlx::Error> {
+    let exists = sqlx::query(&format!("SELECT 1 FROM pragma_table_info('{}') WHERE name = ?", table))
+        .bind(column)
+        .fetch_optional(pool)
+        .await?
+        .is_some();
+
+    if !exists {
+        sqlx::query(&format!("ALTER TABLE {} ADD COLUMN {} {}", table, column, definition))
+            .execute(pool)
+            .await?;
+    }
+
+    Ok(())
 }
@@ -0,0 +1,129 @@
+use axum::async_trait;
+use serde_json::json;
+use sqlx::{Row, SqlitePool};
+use std::time::Duration;
+
+use crate::hooks::{Event, Hook, HookError};
+use crate::shared_state::SharedState;
+use crate::simple_db::{Database, Todo};
+
+/// Only Slack incoming webhooks are accepted, so workspace owners can't make
+/// the server POST to arbitrary URLs.
+const WEBHOOK_PREFIX: &str = "https://hooks.slack.com/";
+
+pub fn is_webhook_url(url: &str) -> bool {
+    url.starts_with(WEBHOOK_PREFIX) && url.len() > WEBHOOK_PREFIX.len()
+}
+
+/// Posts workspace events to the workspace's Slack incoming webhook, if one
+/// is configured. Each workspace may send `SLACK_RATE_LIMIT_PER_MINUTE`
+/// messages per minute (default 20); further messages are dropped so a bulk
+/// change can't get the webhook throttled by Slack.
+#[derive(Clone)]
+pub struct SlackNotifier {
+    http: reqwest::Client,
+    shared: SharedState,
+    per_minute: u64,
+}
+
+impl SlackNotifier {
+    pub fn from_env(shared: SharedState) -> Self {
+        SlackNotifier {
+            http: reqwest::Client::builder()
+                .timeout(Duration::from_secs(10))
+                .build()
+                .expect("Failed to build HTTP client"),
+            shared,
+            per_minute: std::env::var("SLACK_RATE_LIMIT_PER_MINUTE")
+                .ok()
+                .and_then(|limit| limit.parse().ok())
+                .unwrap_or(20),
+        }
+    }
+
+    /// "*alice* assigned *Fix the login page* to *bob*"
+    pub async fn todo_assigned(&self, pool: &SqlitePool, todo: &Todo, actor_id: &str, assignee_id: &str) {
+        let Some(workspace_id) = &todo.workspace_id else {
+            return;
+        };
+        let (Some(actor), Some(assignee)) = (username(pool, actor_id).await, username(pool, assignee_id).await) else {
+            return;
+        };
+
+        let text = format!("*{}* assigned *{}* to *{}*", escape(&actor), escape(&todo.text), escape(&assignee));
+        self.send(pool, workspace_id, text).await;
+    }
+
+    /// Sends in the background; failures are logged.
+    async fn send(&self, pool: &SqlitePool, workspace_id: &str, text: String) {
+        let webhook_url: Option<String> = match sqlx::query("SELECT slack_webhook_url FROM workspaces WHERE id = ?")
+            .bind(workspace_id)
+            .fetch_optional(pool)
+            .await
+        {
+            Ok(row) => row.and_then(|row| row.get("slack_webhook_url")),
+            Err(e) => {
+                tracing::warn!(error = %e, "Failed to look up Slack webhook");
+                return;
+            }
+        };
+        let Some(webhook_url) = webhook_url else {
+            return;
+        };
+
+        match self.shared.increment(&format!("slack:{}", workspace_id), Duration::from_secs(60)).await {
+            Ok(count) if count > self.per_minute => {
+                tracing::warn!(workspace_id, "Slack rate limit reached; dropping message");
+                return;
+            }
+            Ok(_) => {}
+            Err(e) => tracing::warn!(error = %e, "Slack rate limit check failed"),
+        }
+
+        let http = self.http.clone();
+        let workspace_id = workspace_id.to_string();
+        tokio::spawn(async move {
+            let result = http
+                .post(&webhook_url)
+                .header("Content-Type", "application/json")
+                .body(json!({ "text": text }).to_string())
+                .send()
+                .await
+                .and_then(|response| response.error_for_status());
+            if let Err(e) = result {
+                tracing::warn!(error = %e, workspace_id, "Failed to post to Slack");
+            }
+        });
+    }
+}
+
+#[async_trait]
+impl Hook for SlackNotifier {
+    fn name(&self) -> &'static str {
+        "slack"
+    }
+
+    async fn handle(&self, db: &Database, event: &Event<'_>) -> Result<(), HookError> {
+        if let Event::TodoAssigned { todo, actor_id } = *event
+            && let Some(assignee_id) = &todo.assignee_id
+        {
+            self.todo_assigned(db.get_pool(), todo, actor_id, assignee_id).await;
+        }
+        Ok(())
+    }
+}
+
+async fn username(pool: &SqlitePool, user_id: &str) -> Option<String> {
+    sqlx::query("SELECT username FROM users WHERE id = ?")
+        .bind(user_id)
+        .fetch_optional(pool)
+        .await
+        .ok()
+        .flatten()
+        .map(|row| row.get("username"))
+}
+
+/// Slack treats `&`, `<` and `>` as control characters in message text.
+pub fn escape(text: &str) -> String {
+    text.replace('&', "&amp;").replace('<', "&lt;").replace('>', "&gt;")
+}
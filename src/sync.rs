@@ -0,0 +1,166 @@
+use axum::http::StatusCode;
+use base64::{engine::general_purpose::URL_SAFE_NO_PAD, Engine};
+use chrono::{DateTime, Duration, Utc};
+use serde::{Deserialize, Serialize};
+use serde_json::{Map, Value};
+use sqlx::{Row, SqlitePool};
+
+use crate::simple_db::{Scope, Todo, TODO_COLUMNS};
+
+const DEFAULT_LIMIT: u32 = 500;
+const MAX_LIMIT: u32 = 1000;
+
+/// `?since=<cursor>&limit=500`. Without `since`, every todo in the scope.
+#[derive(Debug, Deserialize)]
+pub struct SyncQuery {
+    pub since: Option<String>,
+    pub limit: Option<u32>,
+}
+
+impl SyncQuery {
+    /// The position in the change log `since` points after; 0 without one.
+    /// 400 for a cursor this server didn't hand out, and 410 for one older
+    /// than tombstones are kept (`tombstone_days`), since deletions after
+    /// it may be forgotten; the client has to sync from scratch.
+    pub fn since(&self, tombstone_days: Option<i64>) -> Result<i64, StatusCode> {
+        let Some(since) = &self.since else {
+            return Ok(0);
+        };
+        let (seq, issued_at) = decode_cursor(since).ok_or(StatusCode::BAD_REQUEST)?;
+        if tombstone_days.is_some_and(|days| issued_at < Utc::now() - Duration::days(days)) {
+            return Err(StatusCode::GONE);
+        }
+        Ok(seq)
+    }
+
+    /// 1 to 1000, default 500.
+    pub fn limit(&self) -> u32 {
+        self.limit.unwrap_or(DEFAULT_LIMIT).clamp(1, MAX_LIMIT)
+    }
+}
+
+/// A position in the change log and when it was handed out. Opaque to
+/// clients, like pagination cursors.
+fn encode_cursor(seq: i64, issued_at: DateTime<Utc>) -> String {
+    URL_SAFE_NO_PAD.encode(format!("{}|{}", seq, issued_at.timestamp()))
+}
+
+fn decode_cursor(cursor: &str) -> Option<(i64, DateTime<Utc>)> {
+    let decoded = String::from_utf8(URL_SAFE_NO_PAD.decode(cursor).ok()?).ok()?;
+    let (seq, issued_at) = decoded.split_once('|')?;
+    Some((seq.parse().ok()?, DateTime::from_timestamp(issued_at.parse().ok()?, 0)?))
+}
+
+/// A todo deleted after the cursor.
+#[derive(Debug, Serialize)]
+pub struct Deleted {
+    pub id: String,
+    pub deleted_at: DateTime<Utc>,
+}
+
+/// What changed in a scope after a cursor.
+#[derive(Debug, Serialize)]
+pub struct Changes {
+    /// Where the next sync picks up
+    pub cursor: String,
+    /// More changes are waiting; sync again from `cursor` right away
+    pub has_more: bool,
+    /// Todos created or changed, each in its current state
+    pub todos: Vec<Todo>,
+    pub deleted: Vec<Deleted>,
+}
+
+/// The changes to the todos in `scope` after `since`, oldest first. Each
+/// todo shows up once however often it changed. A first sync (`since` 0)
+/// leaves out deletions, which the client has nothing to apply to.
+///
+/// SQLite commits writes one at a time, so a change can't appear behind a
+/// cursor a client has already been given.
+pub async fn get_changes(pool: &SqlitePool, scope: &Scope, since: i64, limit: u32) -> Result<Changes, sqlx::Error> {
+    let (condition, value) = match &scope.workspace_id {
+        Some(workspace_id) => ("workspace_id = ?", workspace_id),
+        None => ("workspace_id IS NULL AND user_id = ?", &scope.user_id),
+    };
+    let now = Utc::now();
+    let rows = sqlx::query(&format!("SELECT {}, c.seq, c.todo_id, c.deleted, d.deleted_at FROM (SELECT seq, todo_id, deleted FROM todo_changes WHERE {} AND seq > ? AND (? > 0 OR deleted = FALSE) ORDER BY seq LIMIT ?) c LEFT JOIN todos ON todos.id = c.todo_id LEFT JOIN (SELECT entity_id, deleted_at FROM deleted_items WHERE entity_type = 'todo') d ON d.entity_id = c.todo_id ORDER BY c.seq", TODO_COLUMNS, condition))
+        .bind(value)
+        .bind(since)
+        .bind(since)
+        .bind(limit + 1)
+        .fetch_all(pool)
+        .await?;
+
+    let has_more = rows.len() > limit as usize;
+    let (mut seq, mut todos, mut deleted) = (since, Vec::new(), Vec::new());
+    for row in rows.iter().take(limit as usize) {
+        seq = row.get("seq");
+        if row.get("deleted") {
+            deleted.push(Deleted {
+                id: row.get("todo_id"),
+                deleted_at: row.get::<Option<DateTime<Utc>>, _>("deleted_at").unwrap_or(now),
+            });
+        } else if row.get::<Option<String>, _>("id").is_some() {
+            todos.push(Todo::from_row(row));
+        }
+    }
+    Ok(Changes { cursor: encode_cursor(seq, now), has_more, todos, deleted })
+}
+
+/// A change a client made while offline.
+///
+/// Without `id` it creates a todo from the other fields, like `POST /todos`;
+/// `client_id` names it so the response can be matched up and a retried
+/// sync doesn't create it twice. With `id` it updates that todo, like
+/// `PATCH /todos/:id`, based on `version`.
+#[derive(Debug, Deserialize)]
+pub struct ClientChange {
+    pub id: Option<String>,
+    pub client_id: Option<String>,
+    /// The version the client's copy was at before the change
+    pub version: Option<i64>,
+    /// When the change was made on the client, for settling conflicts
+    pub updated_at: Option<DateTime<Utc>>,
+    /// Completes or reopens the todo
+    pub completed: Option<bool>,
+    /// The todo's fields, as `POST /todos` or `PATCH /todos/:id` take them
+    #[serde(flatten)]
+    pub fields: Map<String, Value>,
+}
+
+#[derive(Debug, Deserialize)]
+pub struct SyncRequest {
+    pub changes: Vec<ClientChange>,
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Serialize)]
+#[serde(rename_all = "lowercase")]
+pub enum Outcome {
+    Created,
+    Applied,
+    /// The server's copy changed later than the client's; `todo` is what
+    /// the client should keep
+    Conflict,
+    /// The change can't be made; `error` is the status the matching
+    /// single-todo request would have got
+    Rejected,
+}
+
+/// What became of one `ClientChange`, in the order they were sent.
+#[derive(Debug, Serialize)]
+pub struct ChangeResult {
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub client_id: Option<String>,
+    pub id: Option<String>,
+    pub outcome: Outcome,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub todo: Option<Todo>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub error: Option<u16>,
+}
+
+/// Whether a change based on an older version than `current` still wins:
+/// the later edit does, and the server's on a tie or when the client can't
+/// say when it made its own.
+pub fn client_wins(change: &ClientChange, current: &Todo) -> bool {
+    change.version == Some(current.version) || change.updated_at.is_some_and(|updated_at| updated_at > current.updated_at)
+}
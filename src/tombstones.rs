@@ -0,0 +1,55 @@
+use chrono::{DateTime, Utc};
+use serde::{Deserialize, Serialize};
+use sqlx::{Row, SqlitePool};
+
+use crate::simple_db::Database;
+
+/// A record that was deleted: a `todo`, `category`, `saved_filter` or
+/// `rule`. Written by triggers on those tables, kept as long as the
+/// `tombstones` retention rule allows.
+#[derive(Debug, Serialize, Deserialize)]
+pub struct Tombstone {
+    pub entity_type: String,
+    pub id: String,
+    /// The owner of a personal record
+    #[serde(default)]
+    pub user_id: Option<String>,
+    #[serde(default)]
+    pub workspace_id: Option<String>,
+    pub deleted_at: DateTime<Utc>,
+}
+
+fn from_row(row: &sqlx::sqlite::SqliteRow) -> Tombstone {
+    Tombstone {
+        entity_type: row.get("entity_type"),
+        id: row.get("entity_id"),
+        user_id: row.get("user_id"),
+        workspace_id: row.get("workspace_id"),
+        deleted_at: row.get("deleted_at"),
+    }
+}
+
+/// Tombstones of `user_id`'s personal records, or every one without a
+/// user, oldest first.
+pub async fn list(pool: &SqlitePool, user_id: Option<&str>) -> Result<Vec<Tombstone>, sqlx::Error> {
+    let rows = sqlx::query("SELECT entity_type, entity_id, user_id, workspace_id, deleted_at FROM deleted_items WHERE ?1 IS NULL OR user_id = ?1 ORDER BY deleted_at, entity_type, entity_id")
+        .bind(user_id)
+        .fetch_all(pool)
+        .await?;
+
+    Ok(rows.iter().map(from_row).collect())
+}
+
+/// Records a tombstone from an export, so importing stays in step with
+/// what was deleted at the source; `false` if one was already recorded.
+pub async fn import(db: &Database, tombstone: &Tombstone) -> Result<bool, sqlx::Error> {
+    let result = sqlx::query("INSERT OR IGNORE INTO deleted_items (entity_type, entity_id, user_id, workspace_id, deleted_at) VALUES (?, ?, ?, ?, ?)")
+        .bind(&tombstone.entity_type)
+        .bind(&tombstone.id)
+        .bind(&tombstone.user_id)
+        .bind(&tombstone.workspace_id)
+        .bind(tombstone.deleted_at)
+        .execute(&mut *db.write().await?)
+        .await?;
+    Ok(result.rows_affected() > 0)
+}
@@ -0,0 +1,207 @@
+use askama::Template;
+use axum::{
+    extract::{Path, Query},
+    http::{header, HeaderMap, StatusCode},
+    response::{Html, IntoResponse, Response},
+};
+use chrono::{DateTime, NaiveDateTime, TimeZone, Utc};
+use rust_embed::RustEmbed;
+use serde::Deserialize;
+
+use crate::dates;
+use crate::markdown;
+use crate::settings::UserSettings;
+use crate::simple_db::{NewTodo, Priority, Todo};
+
+/// CSS and JavaScript for the web UI, compiled into the binary so a release
+/// build runs without the source tree.
+#[derive(RustEmbed)]
+#[folder = "static/"]
+struct Assets;
+
+/// A message shown above the page content.
+pub struct Flash {
+    /// `info` or `error`; picks the CSS class
+    pub kind: &'static str,
+    pub message: String,
+}
+
+#[derive(Template)]
+#[template(path = "index.html")]
+struct IndexTemplate {
+    flash: Option<Flash>,
+}
+
+#[derive(Deserialize)]
+pub struct HomeQuery {
+    /// Set by the link in invitation emails
+    invite: Option<String>,
+}
+
+pub async fn home(Query(query): Query<HomeQuery>) -> Result<Html<String>, StatusCode> {
+    let flash = query.invite.map(|_| Flash {
+        kind: "info",
+        message: "Sign in or register to join the workspace you were invited to.".to_string(),
+    });
+
+    IndexTemplate { flash }
+        .render()
+        .map(Html)
+        .map_err(|_| StatusCode::INTERNAL_SERVER_ERROR)
+}
+
+#[derive(Template)]
+#[template(path = "device.html")]
+struct DeviceTemplate {
+    user_code: String,
+}
+
+#[derive(Deserialize)]
+pub struct DeviceQuery {
+    /// Filled in from a device's `verification_uri_complete`
+    #[serde(default)]
+    user_code: String,
+}
+
+/// Where users approve a CLI or browser extension's device code. The page
+/// signs in with the session cookie first if needed.
+pub async fn device(Query(query): Query<DeviceQuery>) -> Result<Html<String>, StatusCode> {
+    DeviceTemplate { user_code: query.user_code }
+        .render()
+        .map(Html)
+        .map_err(|_| StatusCode::INTERNAL_SERVER_ERROR)
+}
+
+/// Serves a file from `static/`. Browsers revalidate on every load and get a
+/// 304 while the file is unchanged, so a deploy takes effect immediately.
+pub async fn static_asset(Path(path): Path<String>, headers: HeaderMap) -> Response {
+    let Some(file) = Assets::get(&path) else {
+        return StatusCode::NOT_FOUND.into_response();
+    };
+    let etag = format!("\"{}\"", file.metadata.sha256_hash().iter().take(8).map(|b| format!("{:02x}", b)).collect::<String>());
+
+    let cache_headers = [(header::ETAG, etag.clone()), (header::CACHE_CONTROL, "no-cache".to_string())];
+    if headers.get(header::IF_NONE_MATCH).is_some_and(|value| value.as_bytes() == etag.as_bytes()) {
+        return (StatusCode::NOT_MODIFIED, cache_headers).into_response();
+    }
+    (
+        [(header::CONTENT_TYPE, file.metadata.mimetype().to_string())],
+        cache_headers,
+        file.data,
+    )
+        .into_response()
+}
+
+/// A todo as shown in the list, with dates in the user's timezone and format.
+struct TodoRow {
+    /// The public id, for URLs
+    id: String,
+    text: String,
+    completed: bool,
+    priority: Option<&'static str>,
+    category: Option<String>,
+    due: Option<String>,
+    overdue: bool,
+    created: String,
+    tags: Vec<String>,
+    notes_html: Option<String>,
+}
+
+impl TodoRow {
+    fn new(todo: &Todo, settings: &UserSettings) -> Self {
+        TodoRow {
+            id: todo.public_id.clone(),
+            text: todo.text.clone(),
+            completed: todo.completed,
+            priority: todo.priority.map(Priority::as_str),
+            category: todo.category.clone(),
+            due: todo.due_date.map(|due| settings.format_datetime(due)),
+            overdue: todo.is_overdue,
+            created: settings.format_date(todo.created_at),
+            tags: todo.tag_list(),
+            notes_html: todo.notes.as_deref().map(markdown::render),
+        }
+    }
+}
+
+#[derive(Template)]
+#[template(path = "fragments/todos.html")]
+struct TodoRowsTemplate {
+    rows: Vec<TodoRow>,
+}
+
+/// HTML for a list of todo rows (`.todo-item` elements), for HTMX to swap in.
+pub fn todo_rows(todos: &[Todo], settings: &UserSettings) -> Result<String, askama::Error> {
+    TodoRowsTemplate {
+        rows: todos.iter().map(|todo| TodoRow::new(todo, settings)).collect(),
+    }
+    .render()
+}
+
+/// The add-todo form as HTMX posts it. Empty inputs count as not given.
+#[derive(Deserialize)]
+pub struct TodoForm {
+    text: String,
+    #[serde(default)]
+    notes: String,
+    #[serde(default)]
+    category: String,
+    /// Comma-separated
+    #[serde(default)]
+    tags: String,
+    #[serde(default)]
+    priority: String,
+    /// From a `datetime-local` or `date` input (`2024-05-01T17:00`,
+    /// `2024-05-01`) or typed ("friday 5pm"), in the user's timezone; a date
+    /// alone means the end of that day
+    #[serde(default)]
+    due_date: String,
+}
+
+impl TodoForm {
+    /// `None` if the text is empty or a field doesn't parse.
+    pub fn into_new_todo(self, settings: &UserSettings) -> Option<NewTodo> {
+        fn non_empty(value: String) -> Option<String> {
+            let value = value.trim();
+            (!value.is_empty()).then(|| value.to_string())
+        }
+
+        let text = non_empty(self.text)?;
+        let priority = match non_empty(self.priority) {
+            Some(priority) => Some(priority.parse().ok()?),
+            None => None,
+        };
+        let due_date: Option<DateTime<Utc>> = match non_empty(self.due_date) {
+            Some(due) => match NaiveDateTime::parse_from_str(&due, "%Y-%m-%dT%H:%M") {
+                Ok(local) => Some(settings.tz().from_local_datetime(&local).earliest()?.with_timezone(&Utc)),
+                // A date alone or a phrase such as "friday"
+                Err(_) => Some(dates::parse_due(&due, Utc::now().with_timezone(&settings.tz()))?),
+            },
+            None => None,
+        };
+        let tags: Vec<String> = self
+            .tags
+            .split(',')
+            .map(str::trim)
+            .filter(|tag| !tag.is_empty())
+            .map(str::to_string)
+            .collect();
+
+        Some(NewTodo {
+            text,
+            notes: non_empty(self.notes),
+            category: non_empty(self.category),
+            tags: (!tags.is_empty()).then_some(tags),
+            color: None,
+            latitude: None,
+            longitude: None,
+            location_name: None,
+            priority,
+            due_date,
+            due: None,
+            estimate_minutes: None,
+            spent_minutes: None,
+            quick_add: false,
+        })
+    }
+}
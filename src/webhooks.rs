@@ -0,0 +1,414 @@
+use axum::async_trait;
+use chrono::{DateTime, Utc};
+use reqwest::{header, redirect, Url};
+use ring::{
+    hmac,
+    rand::{SecureRandom, SystemRandom},
+};
+use serde::{Deserialize, Serialize};
+use serde_json::json;
+use sqlx::{sqlite::SqliteRow, Connection, Row, SqlitePool};
+use std::time::{Duration, Instant};
+use uuid::Uuid;
+
+use crate::encryption;
+use crate::hooks::{Event, Hook, HookError};
+use crate::pagination::Cursor;
+use crate::simple_db::{self, Database};
+use crate::ssrf;
+use crate::writer::Writer;
+
+/// The events a webhook can subscribe to.
+pub const EVENTS: &[&str] = &["todo_created", "todo_updated", "todo_completed", "todo_reopened", "todo_assigned"];
+
+/// Time for one delivery, connecting included.
+const DELIVERY_TIMEOUT: Duration = Duration::from_secs(10);
+/// How much of a receiver's response body is kept in the delivery log
+const MAX_RESPONSE_SNIPPET: usize = 1024;
+
+/// A URL the user's todo events are posted to.
+#[derive(Debug, Serialize)]
+pub struct Webhook {
+    pub id: String,
+    pub url: String,
+    /// Empty for every event
+    pub events: Vec<String>,
+    pub created_at: DateTime<Utc>,
+    /// For checking `X-Webhook-Signature`; only returned when the webhook is created
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub secret: Option<String>,
+}
+
+impl Webhook {
+    fn from_row(row: &SqliteRow) -> Self {
+        Webhook {
+            id: row.get("id"),
+            url: row.get("url"),
+            events: serde_json::from_str(row.get("events")).unwrap_or_default(),
+            created_at: row.get("created_at"),
+            secret: None,
+        }
+    }
+}
+
+/// Body of `POST /webhooks`.
+#[derive(Debug, Deserialize)]
+pub struct NewWebhook {
+    pub url: String,
+    /// Names from `EVENTS`; every event if left out
+    #[serde(default)]
+    pub events: Vec<String>,
+}
+
+/// One attempt at posting an event to a webhook.
+#[derive(Debug, Serialize)]
+pub struct Delivery {
+    pub id: String,
+    /// The same for every attempt at the same event, redeliveries included
+    pub event_id: String,
+    pub event: String,
+    /// `pending`, `succeeded` (a 2xx answer) or `failed`
+    pub status: String,
+    pub response_status: Option<i64>,
+    /// The start of the receiver's answer
+    pub response_body: Option<String>,
+    /// How long the receiver took to answer
+    pub duration_ms: Option<i64>,
+    /// Why there was no answer, e.g. a timeout or a refused address
+    pub error: Option<String>,
+    /// The delivery this one replayed
+    pub redelivery_of: Option<String>,
+    pub payload: serde_json::Value,
+    pub created_at: DateTime<Utc>,
+}
+
+impl Delivery {
+    fn from_row(row: &SqliteRow) -> Self {
+        let id: String = row.get("id");
+        Delivery {
+            payload: serde_json::from_str(&encryption::decrypt(&id, "webhook_deliveries", row.get("payload"))).unwrap_or_default(),
+            id,
+            event_id: row.get("event_id"),
+            event: row.get("event"),
+            status: row.get("status"),
+            response_status: row.get("response_status"),
+            response_body: row.get("response_body"),
+            duration_ms: row.get("duration_ms"),
+            error: row.get("error"),
+            redelivery_of: row.get("redelivery_of"),
+            created_at: row.get("created_at"),
+        }
+    }
+}
+
+const DELIVERY_COLUMNS: &str = "id, event_id, event, status, response_status, response_body, duration_ms, error, redelivery_of, payload, created_at";
+
+#[derive(Debug)]
+pub enum WebhookError {
+    NotFound,
+    /// Not an `http` or `https` URL, or one the server may not post to
+    InvalidUrl,
+    UnknownEvent,
+    Database(sqlx::Error),
+}
+
+impl From<sqlx::Error> for WebhookError {
+    fn from(error: sqlx::Error) -> Self {
+        WebhookError::Database(error)
+    }
+}
+
+impl From<WebhookError> for axum::http::StatusCode {
+    fn from(error: WebhookError) -> Self {
+        match error {
+            WebhookError::NotFound => axum::http::StatusCode::NOT_FOUND,
+            WebhookError::InvalidUrl | WebhookError::UnknownEvent => axum::http::StatusCode::UNPROCESSABLE_ENTITY,
+            WebhookError::Database(e) => simple_db::error_status(&e),
+        }
+    }
+}
+
+/// Where a delivery goes.
+struct Target {
+    id: String,
+    url: String,
+    secret: String,
+}
+
+/// Posts todo events to the webhooks of everyone who can see the todo (the
+/// members of its workspace, or its owner), in the background, and logs each
+/// attempt in `webhook_deliveries`.
+///
+/// Only public `http` and `https` addresses on the default ports are posted
+/// to, checked like link previews, and redirects aren't followed. Set
+/// `WEBHOOKS_ALLOW_PRIVATE_URLS=true` to post to services on your own
+/// network, e.g. on a self-hosted install.
+#[derive(Clone)]
+pub struct Webhooks {
+    allow_private: bool,
+}
+
+impl Webhooks {
+    pub fn from_env() -> Self {
+        Webhooks { allow_private: std::env::var("WEBHOOKS_ALLOW_PRIVATE_URLS").is_ok_and(|value| value == "true") }
+    }
+
+    pub async fn create(&self, db: &Database, user_id: &str, new_webhook: NewWebhook) -> Result<Webhook, WebhookError> {
+        if new_webhook.events.iter().any(|event| !EVENTS.contains(&event.as_str())) {
+            return Err(WebhookError::UnknownEvent);
+        }
+        let url = Url::parse(&new_webhook.url).map_err(|_| WebhookError::InvalidUrl)?;
+        if !matches!(url.scheme(), "http" | "https") || url.host().is_none() {
+            return Err(WebhookError::InvalidUrl);
+        }
+        // Checked again on every delivery, since DNS can change
+        if !self.allow_private && ssrf::public_address(&url).await.is_err() {
+            return Err(WebhookError::InvalidUrl);
+        }
+
+        let webhook = Webhook {
+            id: Uuid::new_v4().to_string(),
+            url: url.to_string(),
+            events: new_webhook.events,
+            created_at: Utc::now(),
+            secret: Some(new_secret()?),
+        };
+        sqlx::query("INSERT INTO webhooks (id, user_id, url, secret, events, created_at) VALUES (?, ?, ?, ?, ?, ?)")
+            .bind(&webhook.id)
+            .bind(user_id)
+            .bind(&webhook.url)
+            .bind(&webhook.secret)
+            .bind(serde_json::to_string(&webhook.events).unwrap_or_default())
+            .bind(webhook.created_at)
+            .execute(&mut *db.write().await?)
+            .await?;
+        Ok(webhook)
+    }
+
+    /// Posts the delivery `delivery_id` of one of the user's webhooks again,
+    /// as a new delivery with the same event id and payload, and waits for
+    /// the answer.
+    pub async fn redeliver(&self, db: &Database, user_id: &str, webhook_id: &str, delivery_id: &str) -> Result<Delivery, WebhookError> {
+        let target = target(db.get_pool(), user_id, webhook_id).await?.ok_or(WebhookError::NotFound)?;
+        let row = sqlx::query("SELECT event_id, event, payload FROM webhook_deliveries WHERE id = ? AND webhook_id = ?")
+            .bind(delivery_id)
+            .bind(webhook_id)
+            .fetch_optional(db.get_pool())
+            .await?
+            .ok_or(WebhookError::NotFound)?;
+        let (event, payload): (String, String) = (row.get("event"), encryption::decrypt(delivery_id, "webhook_deliveries", row.get("payload")));
+        let writer = db.writer();
+        let id = Self::log_pending(&writer, &target, row.get::<&str, _>("event_id"), &event, &payload, Some(delivery_id)).await?;
+        self.send(&writer, &target, &id, &event, &payload).await?;
+        let row = sqlx::query(&format!("SELECT {} FROM webhook_deliveries WHERE id = ?", DELIVERY_COLUMNS)).bind(&id).fetch_one(db.get_pool()).await?;
+        Ok(Delivery::from_row(&row))
+    }
+
+    /// Logs a delivery as pending, to be sent by `send`; returns its id. The
+    /// payload carries the todo, so it's encrypted like its text.
+    async fn log_pending(writer: &Writer, target: &Target, event_id: &str, event: &str, payload: &str, redelivery_of: Option<&str>) -> Result<String, sqlx::Error> {
+        let id = Uuid::new_v4().to_string();
+        sqlx::query("INSERT INTO webhook_deliveries (id, webhook_id, event_id, event, payload, status, redelivery_of, created_at) VALUES (?, ?, ?, ?, ?, 'pending', ?, ?)")
+            .bind(&id)
+            .bind(&target.id)
+            .bind(event_id)
+            .bind(event)
+            .bind(encryption::encrypt(&id, "webhook_deliveries", payload))
+            .bind(redelivery_of)
+            .bind(Utc::now())
+            .execute(&mut *writer.acquire().await?)
+            .await?;
+        Ok(id)
+    }
+
+    /// Posts the pending delivery `id` and logs the outcome.
+    async fn send(&self, writer: &Writer, target: &Target, id: &str, event: &str, payload: &str) -> Result<(), sqlx::Error> {
+        let started = Instant::now();
+        let outcome = match tokio::time::timeout(DELIVERY_TIMEOUT, self.post(target, id, event, payload)).await {
+            Ok(outcome) => outcome,
+            Err(_) => Err("timed out".to_string()),
+        };
+        let duration_ms = started.elapsed().as_millis() as i64;
+        let (status, response_status, response_body, error) = match outcome {
+            Ok((code, body)) => {
+                let status = if (200..300).contains(&code) { "succeeded" } else { "failed" };
+                (status, Some(code as i64), Some(body), None)
+            }
+            Err(e) => ("failed", None, None, Some(e)),
+        };
+        if status == "failed" {
+            tracing::info!(webhook_id = %target.id, delivery_id = %id, response_status, error, "Webhook delivery failed");
+        }
+        sqlx::query("UPDATE webhook_deliveries SET status = ?, response_status = ?, response_body = ?, duration_ms = ?, error = ? WHERE id = ?")
+            .bind(status)
+            .bind(response_status)
+            .bind(response_body)
+            .bind(duration_ms)
+            .bind(error)
+            .bind(id)
+            .execute(&mut *writer.acquire().await?)
+            .await?;
+        Ok(())
+    }
+
+    /// The receiver's status code and the start of its body.
+    async fn post(&self, target: &Target, delivery_id: &str, event: &str, payload: &str) -> Result<(u16, String), String> {
+        let url = Url::parse(&target.url).map_err(|e| e.to_string())?;
+        let mut http = reqwest::Client::builder().redirect(redirect::Policy::none()).timeout(DELIVERY_TIMEOUT);
+        if !self.allow_private {
+            // Connect to the address just checked, so a second DNS answer can't point elsewhere
+            let address = ssrf::public_address(&url).await?;
+            http = http.no_proxy().resolve(url.host_str().unwrap_or_default(), address);
+        }
+        let http = http.build().map_err(|e| e.to_string())?;
+
+        let timestamp = Utc::now().timestamp();
+        let key = hmac::Key::new(hmac::HMAC_SHA256, target.secret.as_bytes());
+        let signature = hex(hmac::sign(&key, format!("{}.{}", timestamp, payload).as_bytes()).as_ref());
+        let mut response = http
+            .post(url)
+            .header(header::CONTENT_TYPE, "application/json")
+            .header(header::USER_AGENT, concat!("todo-app/", env!("CARGO_PKG_VERSION"), " (webhook)"))
+            .header("X-Webhook-Event", event)
+            .header("X-Webhook-Delivery", delivery_id)
+            .header("X-Webhook-Signature", format!("t={},v1={}", timestamp, signature))
+            .body(payload.to_string())
+            .send()
+            .await
+            .map_err(|e| e.without_url().to_string())?;
+
+        let mut body = Vec::new();
+        while body.len() < MAX_RESPONSE_SNIPPET
+            && let Ok(Some(chunk)) = response.chunk().await
+        {
+            body.extend_from_slice(&chunk);
+        }
+        body.truncate(MAX_RESPONSE_SNIPPET);
+        Ok((response.status().as_u16(), String::from_utf8_lossy(&body).into_owned()))
+    }
+}
+
+#[async_trait]
+impl Hook for Webhooks {
+    fn name(&self) -> &'static str {
+        "webhooks"
+    }
+
+    async fn handle(&self, db: &Database, event: &Event<'_>) -> Result<(), HookError> {
+        self.handle_recorded(db, &Uuid::new_v4().to_string(), event).await
+    }
+
+    /// `event_id` is the payload's `id`, so an event the relay hands out
+    /// again reaches receivers as the same event.
+    async fn handle_recorded(&self, db: &Database, event_id: &str, event: &Event<'_>) -> Result<(), HookError> {
+        let todo = match *event {
+            Event::TodoCreated { todo, .. }
+            | Event::TodoUpdated { todo, .. }
+            | Event::TodoCompleted { todo, .. }
+            | Event::TodoReopened { todo, .. }
+            | Event::TodoAssigned { todo, .. } => todo,
+            Event::UserRegistered { .. } => return Ok(()),
+        };
+        // Members as of now, so someone removed from the workspace stops getting its events
+        let targets: Vec<Target> = sqlx::query("SELECT id, url, secret, events FROM webhooks WHERE user_id IN (SELECT user_id FROM workspace_members WHERE workspace_id = ?1 UNION SELECT ?2 WHERE ?1 IS NULL)")
+            .bind(&todo.workspace_id)
+            .bind(&todo.user_id)
+            .fetch_all(db.get_pool())
+            .await?
+            .iter()
+            .filter(|row| {
+                let events: Vec<String> = serde_json::from_str(row.get("events")).unwrap_or_default();
+                events.is_empty() || events.iter().any(|name| name == event.kind())
+            })
+            .map(|row| Target { id: row.get("id"), url: row.get("url"), secret: row.get("secret") })
+            .collect();
+        if targets.is_empty() {
+            return Ok(());
+        }
+
+        let payload = json!({
+            "id": event_id,
+            "event": event.kind(),
+            "created_at": Utc::now(),
+            "data": { "todo": todo },
+        })
+        .to_string();
+        let writer = db.writer();
+        for target in targets {
+            // Logged before the request returns, so it's in the deliveries right away
+            let id = Self::log_pending(&writer, &target, event_id, event.kind(), &payload, None).await?;
+            let (webhooks, writer, event, payload) = (self.clone(), writer.clone(), event.kind(), payload.clone());
+            tokio::spawn(async move {
+                if let Err(e) = webhooks.send(&writer, &target, &id, event, &payload).await {
+                    tracing::warn!(webhook_id = %target.id, delivery_id = %id, error = %e, "Failed to log webhook delivery");
+                }
+            });
+        }
+        Ok(())
+    }
+}
+
+/// 32 random bytes in hex, after a `whsec_` prefix.
+fn new_secret() -> Result<String, sqlx::Error> {
+    let mut bytes = [0u8; 32];
+    SystemRandom::new()
+        .fill(&mut bytes)
+        .map_err(|_| sqlx::Error::Protocol("Failed to generate a webhook secret".to_string()))?;
+    Ok(format!("whsec_{}", hex(&bytes)))
+}
+
+fn hex(bytes: &[u8]) -> String {
+    bytes.iter().map(|b| format!("{:02x}", b)).collect()
+}
+
+async fn target(pool: &SqlitePool, user_id: &str, webhook_id: &str) -> Result<Option<Target>, sqlx::Error> {
+    let row = sqlx::query("SELECT id, url, secret FROM webhooks WHERE id = ? AND user_id = ?")
+        .bind(webhook_id)
+        .bind(user_id)
+        .fetch_optional(pool)
+        .await?;
+    Ok(row.map(|row| Target { id: row.get("id"), url: row.get("url"), secret: row.get("secret") }))
+}
+
+pub async fn list(pool: &SqlitePool, user_id: &str) -> Result<Vec<Webhook>, sqlx::Error> {
+    let rows = sqlx::query("SELECT id, url, events, created_at FROM webhooks WHERE user_id = ? ORDER BY created_at")
+        .bind(user_id)
+        .fetch_all(pool)
+        .await?;
+    Ok(rows.iter().map(Webhook::from_row).collect())
+}
+
+/// Removes one of the user's webhooks with its delivery log.
+pub async fn delete(db: &Database, user_id: &str, webhook_id: &str) -> Result<(), WebhookError> {
+    let mut conn = db.write().await?;
+    let mut tx = conn.begin().await?;
+    sqlx::query("DELETE FROM webhook_deliveries WHERE webhook_id IN (SELECT id FROM webhooks WHERE id = ? AND user_id = ?)")
+        .bind(webhook_id)
+        .bind(user_id)
+        .execute(&mut *tx)
+        .await?;
+    let result = sqlx::query("DELETE FROM webhooks WHERE id = ? AND user_id = ?").bind(webhook_id).bind(user_id).execute(&mut *tx).await?;
+    if result.rows_affected() == 0 {
+        return Err(WebhookError::NotFound);
+    }
+    tx.commit().await?;
+    Ok(())
+}
+
+/// Deliveries of one of the user's webhooks, newest first.
+pub async fn deliveries(pool: &SqlitePool, user_id: &str, webhook_id: &str, after: Option<&Cursor>, limit: u32) -> Result<Vec<Delivery>, WebhookError> {
+    if target(pool, user_id, webhook_id).await?.is_none() {
+        return Err(WebhookError::NotFound);
+    }
+    let rows = sqlx::query(&format!(
+        "SELECT {} FROM webhook_deliveries WHERE webhook_id = ?1 AND (?2 IS NULL OR created_at < ?2 OR (created_at = ?2 AND id < ?3)) ORDER BY created_at DESC, id DESC LIMIT ?4",
+        DELIVERY_COLUMNS
+    ))
+    .bind(webhook_id)
+    .bind(after.map(|after| after.created_at))
+    .bind(after.map(|after| &after.id))
+    .bind(limit)
+    .fetch_all(pool)
+    .await?;
+    Ok(rows.iter().map(Delivery::from_row).collect())
+}
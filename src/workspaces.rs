@@ -0,0 +1,401 @@
+use axum::http::StatusCode;
+use base64::{engine::general_purpose::URL_SAFE_NO_PAD, Engine};
+use chrono::{DateTime, Duration, Utc};
+use ring::{
+    digest::{digest, SHA256},
+    rand::{SecureRandom, SystemRandom},
+};
+use serde::{Deserialize, Serialize};
+use sqlx::{sqlite::SqliteRow, Connection, Row, SqlitePool};
+use uuid::Uuid;
+
+use crate::simple_db::Database;
+
+/// How long an invite link stays valid.
+const INVITE_TTL_DAYS: i64 = 7;
+
+/// A team sharing one set of todos. Members switch into a workspace by
+/// requesting a token for it; personal todos stay outside every workspace.
+#[derive(Clone, Debug, Serialize)]
+pub struct Workspace {
+    pub id: String,
+    pub name: String,
+    /// The caller's role: `owner` or `member`
+    pub role: String,
+    pub created_by: String,
+    pub created_at: DateTime<Utc>,
+    pub updated_at: DateTime<Utc>,
+}
+
+impl Workspace {
+    fn from_row(row: &SqliteRow) -> Self {
+        Workspace {
+            id: row.get("id"),
+            name: row.get("name"),
+            role: row.get("role"),
+            created_by: row.get("created_by"),
+            created_at: row.get("created_at"),
+            updated_at: row.get("updated_at"),
+        }
+    }
+}
+
+#[derive(Debug, Serialize)]
+pub struct Member {
+    pub user_id: String,
+    pub username: String,
+    pub role: String,
+    pub created_at: DateTime<Utc>,
+}
+
+#[derive(Debug, Deserialize)]
+pub struct NewWorkspace {
+    pub name: String,
+}
+
+#[derive(Debug, Deserialize)]
+pub struct NewMember {
+    pub username: String,
+}
+
+/// A pending or accepted invitation; the token itself is only ever emailed.
+#[derive(Debug, Serialize)]
+pub struct Invite {
+    pub id: String,
+    pub workspace_id: String,
+    pub email: String,
+    pub role: String,
+    pub invited_by: String,
+    pub created_at: DateTime<Utc>,
+    pub expires_at: DateTime<Utc>,
+    pub accepted_at: Option<DateTime<Utc>>,
+}
+
+impl Invite {
+    fn from_row(row: &SqliteRow) -> Self {
+        Invite {
+            id: row.get("id"),
+            workspace_id: row.get("workspace_id"),
+            email: row.get("email"),
+            role: row.get("role"),
+            invited_by: row.get("invited_by"),
+            created_at: row.get("created_at"),
+            expires_at: row.get("expires_at"),
+            accepted_at: row.get("accepted_at"),
+        }
+    }
+}
+
+#[derive(Debug, Deserialize)]
+pub struct NewInvite {
+    pub email: String,
+    /// `member` (default) or `owner`
+    #[serde(default = "default_role")]
+    pub role: String,
+}
+
+fn default_role() -> String {
+    "member".to_string()
+}
+
+#[derive(Debug, Deserialize)]
+pub struct AcceptInvite {
+    pub token: String,
+}
+
+#[derive(Debug)]
+pub enum WorkspaceError {
+    NotFound,
+    /// Only owners may manage members
+    Forbidden,
+    AlreadyMember,
+    /// Removing the member would leave the workspace without an owner
+    LastOwner,
+    InvalidRole,
+    DatabaseError,
+}
+
+impl From<WorkspaceError> for StatusCode {
+    fn from(error: WorkspaceError) -> Self {
+        match error {
+            WorkspaceError::NotFound => StatusCode::NOT_FOUND,
+            WorkspaceError::Forbidden => StatusCode::FORBIDDEN,
+            WorkspaceError::AlreadyMember => StatusCode::CONFLICT,
+            WorkspaceError::LastOwner => StatusCode::CONFLICT,
+            WorkspaceError::InvalidRole => StatusCode::UNPROCESSABLE_ENTITY,
+            WorkspaceError::DatabaseError => StatusCode::INTERNAL_SERVER_ERROR,
+        }
+    }
+}
+
+impl From<sqlx::Error> for WorkspaceError {
+    fn from(_: sqlx::Error) -> Self {
+        WorkspaceError::DatabaseError
+    }
+}
+
+/// Creates a workspace with `user_id` as its owner.
+pub async fn create_workspace(db: &Database, user_id: &str, new_workspace: NewWorkspace) -> Result<Workspace, sqlx::Error> {
+    let id = Uuid::new_v4().to_string();
+    let now = Utc::now();
+    let mut conn = db.write().await?;
+    let mut tx = conn.begin().await?;
+
+    sqlx::query("INSERT INTO workspaces (id, name, created_by, created_at, updated_at) VALUES (?, ?, ?, ?, ?)")
+        .bind(&id)
+        .bind(&new_workspace.name)
+        .bind(user_id)
+        .bind(now)
+        .bind(now)
+        .execute(&mut *tx)
+        .await?;
+    sqlx::query("INSERT INTO workspace_members (workspace_id, user_id, role, created_at) VALUES (?, ?, 'owner', ?)")
+        .bind(&id)
+        .bind(user_id)
+        .bind(now)
+        .execute(&mut *tx)
+        .await?;
+
+    tx.commit().await?;
+    Ok(Workspace {
+        id,
+        name: new_workspace.name,
+        role: "owner".to_string(),
+        created_by: user_id.to_string(),
+        created_at: now,
+        updated_at: now,
+    })
+}
+
+/// Workspaces `user_id` belongs to.
+pub async fn get_workspaces(pool: &SqlitePool, user_id: &str) -> Result<Vec<Workspace>, sqlx::Error> {
+    let rows = sqlx::query("SELECT w.id, w.name, m.role, w.created_by, w.created_at, w.updated_at FROM workspaces w JOIN workspace_members m ON m.workspace_id = w.id WHERE m.user_id = ? ORDER BY w.name")
+        .bind(user_id)
+        .fetch_all(pool)
+        .await?;
+
+    Ok(rows.iter().map(Workspace::from_row).collect())
+}
+
+/// A workspace `user_id` belongs to.
+pub async fn get_workspace(pool: &SqlitePool, user_id: &str, workspace_id: &str) -> Result<Option<Workspace>, sqlx::Error> {
+    let row = sqlx::query("SELECT w.id, w.name, m.role, w.created_by, w.created_at, w.updated_at FROM workspaces w JOIN workspace_members m ON m.workspace_id = w.id WHERE w.id = ? AND m.user_id = ?")
+        .bind(workspace_id)
+        .bind(user_id)
+        .fetch_optional(pool)
+        .await?;
+
+    Ok(row.as_ref().map(Workspace::from_row))
+}
+
+/// `user_id`'s role in the workspace, or `None` if they aren't a member.
+pub async fn role(pool: &SqlitePool, workspace_id: &str, user_id: &str) -> Result<Option<String>, sqlx::Error> {
+    let row = sqlx::query("SELECT role FROM workspace_members WHERE workspace_id = ? AND user_id = ?")
+        .bind(workspace_id)
+        .bind(user_id)
+        .fetch_optional(pool)
+        .await?;
+
+    Ok(row.map(|row| row.get("role")))
+}
+
+/// Members of a workspace `user_id` belongs to.
+pub async fn get_members(pool: &SqlitePool, user_id: &str, workspace_id: &str) -> Result<Vec<Member>, WorkspaceError> {
+    if role(pool, workspace_id, user_id).await?.is_none() {
+        return Err(WorkspaceError::NotFound);
+    }
+
+    let rows = sqlx::query("SELECT m.user_id, u.username, m.role, m.created_at FROM workspace_members m JOIN users u ON u.id = m.user_id WHERE m.workspace_id = ? ORDER BY u.username")
+        .bind(workspace_id)
+        .fetch_all(pool)
+        .await?;
+
+    Ok(rows
+        .into_iter()
+        .map(|row| Member {
+            user_id: row.get("user_id"),
+            username: row.get("username"),
+            role: row.get("role"),
+            created_at: row.get("created_at"),
+        })
+        .collect())
+}
+
+async fn require_owner(pool: &SqlitePool, workspace_id: &str, user_id: &str) -> Result<(), WorkspaceError> {
+    match role(pool, workspace_id, user_id).await?.as_deref() {
+        Some("owner") => Ok(()),
+        Some(_) => Err(WorkspaceError::Forbidden),
+        // Don't reveal workspaces to non-members
+        None => Err(WorkspaceError::NotFound),
+    }
+}
+
+#[derive(Debug, Deserialize)]
+pub struct SlackSettings {
+    /// A Slack incoming webhook URL; `null` stops posting to Slack
+    pub webhook_url: Option<String>,
+}
+
+/// Sets or clears the workspace's Slack webhook; owners only.
+pub async fn set_slack_webhook(db: &Database, owner_id: &str, workspace_id: &str, webhook_url: Option<&str>) -> Result<(), WorkspaceError> {
+    require_owner(db.get_pool(), workspace_id, owner_id).await?;
+
+    sqlx::query("UPDATE workspaces SET slack_webhook_url = ?, updated_at = ? WHERE id = ?")
+        .bind(webhook_url)
+        .bind(Utc::now())
+        .bind(workspace_id)
+        .execute(&mut *db.write().await?)
+        .await?;
+    Ok(())
+}
+
+/// Adds `member_id` to the workspace; `owner_id` must own it.
+pub async fn add_member(db: &Database, owner_id: &str, workspace_id: &str, member_id: &str) -> Result<(), WorkspaceError> {
+    require_owner(db.get_pool(), workspace_id, owner_id).await?;
+
+    let result = sqlx::query("INSERT OR IGNORE INTO workspace_members (workspace_id, user_id, role, created_at) VALUES (?, ?, 'member', ?)")
+        .bind(workspace_id)
+        .bind(member_id)
+        .bind(Utc::now())
+        .execute(&mut *db.write().await?)
+        .await?;
+
+    if result.rows_affected() == 0 {
+        return Err(WorkspaceError::AlreadyMember);
+    }
+    Ok(())
+}
+
+/// Removes `member_id` from the workspace. Owners may remove anyone and
+/// members may remove themselves, as long as an owner remains.
+pub async fn remove_member(db: &Database, user_id: &str, workspace_id: &str, member_id: &str) -> Result<(), WorkspaceError> {
+    let pool = db.get_pool();
+    if user_id != member_id {
+        require_owner(pool, workspace_id, user_id).await?;
+    }
+
+    let Some(member_role) = role(pool, workspace_id, member_id).await? else {
+        return Err(WorkspaceError::NotFound);
+    };
+    if member_role == "owner" {
+        let owners: i64 = sqlx::query("SELECT COUNT(*) AS n FROM workspace_members WHERE workspace_id = ? AND role = 'owner'")
+            .bind(workspace_id)
+            .fetch_one(pool)
+            .await?
+            .get("n");
+        if owners <= 1 {
+            return Err(WorkspaceError::LastOwner);
+        }
+    }
+
+    Ok(db.remove_workspace_member(workspace_id, member_id, user_id).await?)
+}
+
+fn hash_token(token: &str) -> String {
+    URL_SAFE_NO_PAD.encode(digest(&SHA256, token.as_bytes()))
+}
+
+/// Creates an invite to the workspace and returns it with its single-use
+/// token. Only a hash of the token is stored.
+pub async fn create_invite(db: &Database, owner_id: &str, workspace_id: &str, new_invite: NewInvite) -> Result<(Invite, String), WorkspaceError> {
+    require_owner(db.get_pool(), workspace_id, owner_id).await?;
+    if !matches!(new_invite.role.as_str(), "owner" | "member") {
+        return Err(WorkspaceError::InvalidRole);
+    }
+
+    let mut bytes = [0u8; 32];
+    SystemRandom::new().fill(&mut bytes).map_err(|_| WorkspaceError::DatabaseError)?;
+    let token = URL_SAFE_NO_PAD.encode(bytes);
+
+    let invite = Invite {
+        id: Uuid::new_v4().to_string(),
+        workspace_id: workspace_id.to_string(),
+        email: new_invite.email,
+        role: new_invite.role,
+        invited_by: owner_id.to_string(),
+        created_at: Utc::now(),
+        expires_at: Utc::now() + Duration::days(INVITE_TTL_DAYS),
+        accepted_at: None,
+    };
+    sqlx::query("INSERT INTO workspace_invites (id, workspace_id, email, role, token_hash, invited_by, created_at, expires_at) VALUES (?, ?, ?, ?, ?, ?, ?, ?)")
+        .bind(&invite.id)
+        .bind(&invite.workspace_id)
+        .bind(&invite.email)
+        .bind(&invite.role)
+        .bind(hash_token(&token))
+        .bind(&invite.invited_by)
+        .bind(invite.created_at)
+        .bind(invite.expires_at)
+        .execute(&mut *db.write().await?)
+        .await?;
+
+    Ok((invite, token))
+}
+
+/// Invites to the workspace that haven't been accepted yet; owners only.
+pub async fn get_invites(pool: &SqlitePool, owner_id: &str, workspace_id: &str) -> Result<Vec<Invite>, WorkspaceError> {
+    require_owner(pool, workspace_id, owner_id).await?;
+
+    let rows = sqlx::query("SELECT id, workspace_id, email, role, invited_by, created_at, expires_at, accepted_at FROM workspace_invites WHERE workspace_id = ? AND accepted_at IS NULL ORDER BY created_at DESC")
+        .bind(workspace_id)
+        .fetch_all(pool)
+        .await?;
+
+    Ok(rows.iter().map(Invite::from_row).collect())
+}
+
+/// Withdraws an invite that hasn't been accepted yet; owners only.
+pub async fn revoke_invite(db: &Database, owner_id: &str, workspace_id: &str, invite_id: &str) -> Result<(), WorkspaceError> {
+    require_owner(db.get_pool(), workspace_id, owner_id).await?;
+
+    let result = sqlx::query("DELETE FROM workspace_invites WHERE id = ? AND workspace_id = ? AND accepted_at IS NULL")
+        .bind(invite_id)
+        .bind(workspace_id)
+        .execute(&mut *db.write().await?)
+        .await?;
+
+    if result.rows_affected() == 0 {
+        return Err(WorkspaceError::NotFound);
+    }
+    Ok(())
+}
+
+/// Adds `user_id` to the workspace the invite is for, with the invite's role,
+/// and uses the invite up. Unknown, expired and used tokens are `NotFound`.
+pub async fn accept_invite(db: &Database, user_id: &str, token: &str) -> Result<Workspace, WorkspaceError> {
+    let now = Utc::now();
+    let mut conn = db.write().await?;
+    let mut tx = conn.begin().await?;
+
+    let row = sqlx::query("SELECT id, workspace_id, role FROM workspace_invites WHERE token_hash = ? AND accepted_at IS NULL AND expires_at > ?")
+        .bind(hash_token(token))
+        .bind(now)
+        .fetch_optional(&mut *tx)
+        .await?
+        .ok_or(WorkspaceError::NotFound)?;
+    let invite_id: String = row.get("id");
+    let workspace_id: String = row.get("workspace_id");
+    let role: String = row.get("role");
+
+    let added = sqlx::query("INSERT OR IGNORE INTO workspace_members (workspace_id, user_id, role, created_at) VALUES (?, ?, ?, ?)")
+        .bind(&workspace_id)
+        .bind(user_id)
+        .bind(&role)
+        .bind(now)
+        .execute(&mut *tx)
+        .await?;
+    // Leave the invite usable for whoever it was really meant for
+    if added.rows_affected() == 0 {
+        return Err(WorkspaceError::AlreadyMember);
+    }
+
+    sqlx::query("UPDATE workspace_invites SET accepted_at = ?, accepted_by = ? WHERE id = ?")
+        .bind(now)
+        .bind(user_id)
+        .bind(&invite_id)
+        .execute(&mut *tx)
+        .await?;
+    tx.commit().await?;
+
+    get_workspace(db.get_pool(), user_id, &workspace_id).await?.ok_or(WorkspaceError::NotFound)
+}
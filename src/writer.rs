@@ -0,0 +1,90 @@
+use sqlx::sqlite::{SqliteConnectOptions, SqliteConnection};
+use sqlx::ConnectOptions;
+use std::ops::{Deref, DerefMut};
+use std::time::Duration;
+use tokio::sync::{mpsc, oneshot};
+
+/// How many writers can wait in line before asking blocks too
+const QUEUE_LENGTH: usize = 256;
+
+/// The one connection `Database` writes through.
+///
+/// SQLite lets one connection write at a time. Writers on separate pooled
+/// connections only find that out by colliding, then wait out
+/// `busy_timeout` and can still fail with `database is locked`. Instead a
+/// task owns a single connection and lends it to one writer after another
+/// in the order they asked, so writes queue here rather than in SQLite.
+/// Reads stay on the pool.
+#[derive(Clone)]
+pub struct Writer {
+    requests: mpsc::Sender<oneshot::Sender<WriteConnection>>,
+    timeout: Duration,
+}
+
+impl Writer {
+    /// Opens the writer connection; waiting for it gives up after `timeout`,
+    /// like acquiring a pooled connection.
+    pub async fn connect(options: &SqliteConnectOptions, timeout: Duration) -> Result<Self, sqlx::Error> {
+        let connection = options.connect().await?;
+        let (requests, receiver) = mpsc::channel(QUEUE_LENGTH);
+        tokio::spawn(lend(connection, receiver));
+        Ok(Writer { requests, timeout })
+    }
+
+    /// Waits for the connection, then holds it until the `WriteConnection`
+    /// is dropped. `PoolTimedOut` if the queue doesn't get to us in time,
+    /// which `error_status` turns into 503 like a busy pool.
+    pub async fn acquire(&self) -> Result<WriteConnection, sqlx::Error> {
+        let (sender, receiver) = oneshot::channel();
+        let lease = async {
+            self.requests.send(sender).await.map_err(|_| sqlx::Error::PoolClosed)?;
+            receiver.await.map_err(|_| sqlx::Error::PoolClosed)
+        };
+        tokio::time::timeout(self.timeout, lease).await.map_err(|_| sqlx::Error::PoolTimedOut)?
+    }
+}
+
+/// Lends `connection` to each request in turn, waiting for it to come back
+/// before the next.
+async fn lend(mut connection: SqliteConnection, mut requests: mpsc::Receiver<oneshot::Sender<WriteConnection>>) {
+    while let Some(request) = requests.recv().await {
+        let (returned, back) = oneshot::channel();
+        // A writer that stopped waiting drops the lease, which hands the connection straight back
+        let _ = request.send(WriteConnection { lease: Some((connection, returned)) });
+        connection = match back.await {
+            Ok(connection) => connection,
+            Err(_) => {
+                tracing::error!("Writer connection was never returned; writes will fail");
+                return;
+            }
+        };
+    }
+}
+
+/// The writer connection, on loan until dropped. A transaction left open
+/// is rolled back before the next writer gets it.
+pub struct WriteConnection {
+    lease: Option<(SqliteConnection, oneshot::Sender<SqliteConnection>)>,
+}
+
+impl Deref for WriteConnection {
+    type Target = SqliteConnection;
+
+    fn deref(&self) -> &SqliteConnection {
+        &self.lease.as_ref().expect("lease is held until dropped").0
+    }
+}
+
+impl DerefMut for WriteConnection {
+    fn deref_mut(&mut self) -> &mut SqliteConnection {
+        &mut self.lease.as_mut().expect("lease is held until dropped").0
+    }
+}
+
+impl Drop for WriteConnection {
+    fn drop(&mut self) {
+        if let Some((connection, returned)) = self.lease.take() {
+            let _ = returned.send(connection);
+        }
+    }
+}
@@ -0,0 +1,255 @@
+// The session token lives in an HttpOnly cookie that scripts can't read;
+// the server tells us whether we're signed in
+let signedIn = false;
+// Tokens were kept in localStorage before the cookie session
+localStorage.removeItem('authToken');
+// Invite emails link to /?invite=<token>; accepted once signed in
+let inviteToken = new URLSearchParams(location.search).get('invite');
+// From GET /settings: which todos to list, and the color theme
+let defaultView = 'all';
+
+// Sent with every request that changes something; the server checks it
+// against the csrf_token cookie so other sites can't act as the user
+function csrfHeaders(headers = {}) {
+    const match = document.cookie.match(/(?:^|; )csrf_token=([^;]*)/);
+    return match ? {...headers, 'X-CSRF-Token': match[1]} : headers;
+}
+
+// Authentication functions
+async function login() {
+    const username = document.getElementById('usernameInput').value.trim();
+    const password = document.getElementById('passwordInput').value.trim();
+    if (!username || !password) return;
+
+    try {
+        const response = await fetch('/auth/login?cookie=true', {
+            method: 'POST',
+            headers: {'Content-Type': 'application/json'},
+            body: JSON.stringify({username, password})
+        });
+
+        if (response.ok) {
+            signedIn = true;
+            showTodoSection();
+            await loadSettings();
+            loadTodos();
+            loadCategories();
+        } else {
+            alert('Login failed!');
+        }
+    } catch (error) {
+        alert('Login error: ' + error.message);
+    }
+}
+
+async function register() {
+    const username = document.getElementById('regUsernameInput').value.trim();
+    const email = document.getElementById('regEmailInput').value.trim();
+    const password = document.getElementById('regPasswordInput').value.trim();
+    if (!username || !email || !password) return;
+
+    try {
+        const response = await fetch('/auth/register?cookie=true', {
+            method: 'POST',
+            headers: {'Content-Type': 'application/json'},
+            body: JSON.stringify({username, email, password})
+        });
+
+        if (response.ok) {
+            signedIn = true;
+            showTodoSection();
+            await loadSettings();
+            loadTodos();
+            loadCategories();
+        } else if (response.status === 422) {
+            const data = await response.json();
+            alert([data.error, data.warning, ...(data.suggestions || [])].filter(Boolean).join('\n'));
+        } else {
+            alert('Registration failed!');
+        }
+    } catch (error) {
+        alert('Registration error: ' + error.message);
+    }
+}
+
+async function logout() {
+    await fetch('/auth/logout', {method: 'POST', headers: csrfHeaders()});
+    signedIn = false;
+    defaultView = 'all';
+    delete document.documentElement.dataset.theme;
+    showLoginSection();
+}
+
+function showRegister() {
+    document.getElementById('loginSection').style.display = 'none';
+    document.getElementById('registerSection').style.display = 'block';
+}
+
+function showLogin() {
+    document.getElementById('registerSection').style.display = 'none';
+    document.getElementById('loginSection').style.display = 'block';
+}
+
+function showTodoSection() {
+    document.getElementById('loginSection').style.display = 'none';
+    document.getElementById('registerSection').style.display = 'none';
+    document.getElementById('todoSection').style.display = 'block';
+    acceptInvite();
+}
+
+async function loadSettings() {
+    const response = await fetch('/settings');
+    signedIn = response.ok;
+    if (response.ok) {
+        const settings = await response.json();
+        defaultView = settings.default_view;
+        document.documentElement.dataset.theme = settings.theme;
+    }
+}
+
+async function acceptInvite() {
+    if (!inviteToken) return;
+    const token = inviteToken;
+    inviteToken = null;
+    history.replaceState(null, '', '/');
+    document.querySelector('.flash')?.remove();
+
+    const response = await fetch('/invites/accept', {
+        method: 'POST',
+        headers: csrfHeaders({'Content-Type': 'application/json'}),
+        body: JSON.stringify({token})
+    });
+    if (response.ok) {
+        const workspace = await response.json();
+        alert(`You joined the "${workspace.name}" workspace.`);
+    } else {
+        alert('This invitation is invalid, expired or already used.');
+    }
+}
+
+function showLoginSection() {
+    document.getElementById('todoSection').style.display = 'none';
+    document.getElementById('loginSection').style.display = 'block';
+    document.getElementById('registerSection').style.display = 'none';
+}
+
+// Todo functions
+async function loadTodos() {
+    if (!signedIn) return;
+
+    try {
+        const path = {overdue: '/todos/overdue', upcoming: '/todos/upcoming'}[defaultView] || '/todos';
+        const response = await fetch(path);
+
+        if (response.ok) {
+            let todos = await response.json();
+            if (defaultView === 'open') todos = todos.filter(todo => !todo.completed);
+            const todosDiv = document.getElementById('todos');
+            todosDiv.innerHTML = todos.map(todo => renderTodo(todo)).join('');
+        }
+    } catch (error) {
+        console.error('Failed to load todos:', error);
+    }
+}
+
+function renderTodo(todo) {
+    const tags = todo.tags || [];
+    const tagHtml = tags.map(tag => `<span class="tag">${tag}</span>`).join('');
+    const priorityClass = todo.priority ? `priority-${todo.priority}` : '';
+    const dueDate = todo.due_date ? new Date(todo.due_date).toLocaleDateString() : '';
+
+    return `
+        <div class="todo-item ${todo.completed ? 'completed' : ''} ${priorityClass}">
+            <div>
+                <strong>${todo.text}</strong>
+                <button class="toggle-btn" onclick="toggleTodo('${todo.public_id}')">
+                    ${todo.completed ? 'Undo' : 'Complete'}
+                </button>
+            </div>
+            <div class="todo-meta">
+                ${todo.category ? `Category: ${todo.category} | ` : ''}
+                ${todo.priority ? `Priority: ${todo.priority} | ` : ''}
+                ${dueDate ? `Due: ${dueDate} | ` : ''}
+                Created: ${new Date(todo.created_at).toLocaleDateString()}
+            </div>
+            <div>${tagHtml}</div>
+        </div>
+    `;
+}
+
+async function addTodo() {
+    if (!signedIn) return;
+
+    const text = document.getElementById('todoInput').value.trim();
+    if (!text) return;
+
+    const category = document.getElementById('categoryInput').value.trim() || null;
+    const tagsInput = document.getElementById('tagsInput').value.trim();
+    const tags = tagsInput ? tagsInput.split(',').map(t => t.trim()) : null;
+    const priority = document.getElementById('prioritySelect').value || null;
+    const dueDateInput = document.getElementById('dueDateInput').value;
+    // Sent as wall-clock time so the server reads it in the timezone from the user's settings
+    const due = dueDateInput ? dueDateInput.replace('T', ' ') : null;
+
+    try {
+        const response = await fetch('/todos', {
+            method: 'POST',
+            headers: csrfHeaders({'Content-Type': 'application/json'}),
+            body: JSON.stringify({text, category, tags, priority, due})
+        });
+
+        if (response.ok) {
+            document.getElementById('todoInput').value = '';
+            document.getElementById('categoryInput').value = '';
+            document.getElementById('tagsInput').value = '';
+            document.getElementById('prioritySelect').value = '';
+            document.getElementById('dueDateInput').value = '';
+            loadTodos();
+            loadCategories();
+        }
+    } catch (error) {
+        console.error('Failed to add todo:', error);
+    }
+}
+
+async function toggleTodo(id) {
+    if (!signedIn) return;
+
+    try {
+        await fetch(`/toggle/${id}`, {
+            method: 'POST',
+            headers: csrfHeaders()
+        });
+        loadTodos();
+    } catch (error) {
+        console.error('Failed to toggle todo:', error);
+    }
+}
+
+async function loadCategories() {
+    if (!signedIn) return;
+
+    try {
+        const response = await fetch('/categories');
+
+        if (response.ok) {
+            const categories = await response.json();
+            const select = document.getElementById('categoryFilter');
+            select.innerHTML = '<option value="">All Categories</option>';
+            categories.forEach(cat => select.add(new Option(cat.name, cat.name)));
+        }
+    } catch (error) {
+        console.error('Failed to load categories:', error);
+    }
+}
+
+// Initialize app: a valid session cookie answers /settings
+loadSettings().then(() => {
+    if (signedIn) {
+        showTodoSection();
+        loadTodos();
+        loadCategories();
+    } else {
+        showLoginSection();
+    }
+});
@@ -0,0 +1,62 @@
+// The /device page: approve or deny a CLI or extension's user code with the
+// cookie session, signing in first if there isn't one
+
+function csrfHeaders(headers = {}) {
+    const match = document.cookie.match(/(?:^|; )csrf_token=([^;]*)/);
+    return match ? {...headers, 'X-CSRF-Token': match[1]} : headers;
+}
+
+function showFlash(kind, message) {
+    const flash = document.getElementById('deviceFlash');
+    flash.className = `flash flash-${kind}`;
+    flash.textContent = message;
+    flash.style.display = 'block';
+}
+
+async function showSection() {
+    const response = await fetch('/auth/me');
+    document.getElementById('loginSection').style.display = response.ok ? 'none' : 'block';
+    document.getElementById('deviceSection').style.display = response.ok ? 'block' : 'none';
+}
+
+async function login() {
+    const username = document.getElementById('usernameInput').value.trim();
+    const password = document.getElementById('passwordInput').value;
+    if (!username || !password) return;
+
+    const response = await fetch('/auth/login?cookie=true', {
+        method: 'POST',
+        headers: {'Content-Type': 'application/json'},
+        body: JSON.stringify({username, password})
+    });
+    if (!response.ok) {
+        showFlash('error', 'Login failed');
+        return;
+    }
+    document.getElementById('deviceFlash').style.display = 'none';
+    await showSection();
+}
+
+async function decide(approve) {
+    const userCode = document.getElementById('userCodeInput').value.trim();
+    if (!userCode) return;
+
+    const response = await fetch('/auth/device/approve', {
+        method: 'POST',
+        headers: csrfHeaders({'Content-Type': 'application/json'}),
+        body: JSON.stringify({user_code: userCode, approve})
+    });
+    if (response.status === 404) {
+        showFlash('error', 'That code is unknown or has expired. Start again on the device.');
+    } else if (!response.ok) {
+        showFlash('error', 'Something went wrong; try again.');
+    } else {
+        document.getElementById('deviceSection').style.display = 'none';
+        showFlash('info', approve ? 'Device connected. You can go back to it now.' : 'Sign-in denied.');
+    }
+}
+
+document.getElementById('loginButton').addEventListener('click', login);
+document.getElementById('approveButton').addEventListener('click', () => decide(true));
+document.getElementById('denyButton').addEventListener('click', () => decide(false));
+showSection();
@@ -0,0 +1,321 @@
+use axum::extract::{Path, State};
+use axum::http::{header, HeaderMap, StatusCode};
+use axum::response::{IntoResponse, Response};
+use axum::routing::{get, post};
+use axum::{Json, Router};
+use base64::{engine::general_purpose::URL_SAFE_NO_PAD, Engine};
+use ring::digest::{digest, SHA256};
+use ring::signature::{UnparsedPublicKey, ECDSA_P256_SHA256_FIXED};
+use rustls::client::{ServerCertVerified, ServerCertVerifier};
+use rustls::{Certificate, ClientConfig, ClientConnection, ServerConfig, ServerConnection, ServerName};
+use serde_json::{json, Value};
+use std::collections::HashSet;
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, SystemTime};
+
+use todo_app::acme::{self, AcmeConfig, AcmeResolver};
+
+// A stand-in for an ACME CA such as Pebble: it checks the JWS on every
+// request like RFC 8555 asks, validates tls-alpn-01 by handshaking with the
+// app's TLS config the way a CA would over the network, and issues a
+// certificate once the order is finalized.
+
+const DOMAIN: &str = "todo.example.com";
+
+#[derive(Default)]
+struct Ca {
+    base: String,
+    issued_nonces: HashSet<String>,
+    next_nonce: u64,
+    account_jwk: Option<Value>,
+    token: String,
+    authorization_status: &'static str,
+    order_status: &'static str,
+    /// The certificate the app presented for `acme-tls/1`
+    challenge_certificate: Option<Vec<u8>>,
+    certificate_pem: String,
+}
+
+type SharedCa = Arc<Mutex<Ca>>;
+
+fn error(status: StatusCode, detail: &str) -> Response {
+    (status, Json(json!({ "type": "urn:ietf:params:acme:error:malformed", "detail": detail }))).into_response()
+}
+
+fn nonce(ca: &mut Ca) -> String {
+    ca.next_nonce += 1;
+    let nonce = format!("nonce-{}", ca.next_nonce);
+    ca.issued_nonces.insert(nonce.clone());
+    nonce
+}
+
+fn thumbprint(jwk: &Value) -> String {
+    URL_SAFE_NO_PAD.encode(digest(&SHA256, jwk.to_string().as_bytes()))
+}
+
+fn verify_signature(jwk: &Value, signed: &str, signature: &str) -> bool {
+    let coordinate = |name: &str| jwk[name].as_str().and_then(|value| URL_SAFE_NO_PAD.decode(value).ok()).unwrap_or_default();
+    let point = [vec![0x04], coordinate("x"), coordinate("y")].concat();
+    let signature = URL_SAFE_NO_PAD.decode(signature).unwrap_or_default();
+    UnparsedPublicKey::new(&ECDSA_P256_SHA256_FIXED, point).verify(signed.as_bytes(), &signature).is_ok()
+}
+
+/// Checks the JWS the way a CA does and returns its payload; `Value::Null`
+/// for a POST-as-GET.
+fn verify(ca: &mut Ca, path: &str, body: &Value) -> Result<Value, (StatusCode, &'static str)> {
+    let protected = body["protected"].as_str().unwrap_or_default();
+    let payload = body["payload"].as_str().unwrap_or_default();
+    let header: Value = serde_json::from_slice(&URL_SAFE_NO_PAD.decode(protected).unwrap_or_default())
+        .map_err(|_| (StatusCode::BAD_REQUEST, "bad protected header"))?;
+
+    if header["alg"] != "ES256" || header["url"] != format!("{}{}", ca.base, path) {
+        return Err((StatusCode::BAD_REQUEST, "wrong alg or url"));
+    }
+    let nonce = header["nonce"].as_str().unwrap_or_default();
+    if !ca.issued_nonces.remove(nonce) {
+        return Err((StatusCode::BAD_REQUEST, "bad nonce"));
+    }
+
+    // Only a new account introduces its key; everything after names the account
+    let jwk = if path == "/new-account" {
+        header["jwk"].clone()
+    } else if header["kid"] == format!("{}/account/1", ca.base) && header.get("jwk").is_none() {
+        ca.account_jwk.clone().unwrap_or_default()
+    } else {
+        return Err((StatusCode::UNAUTHORIZED, "requests must name the account by kid"));
+    };
+    if !verify_signature(&jwk, &format!("{}.{}", protected, payload), body["signature"].as_str().unwrap_or_default()) {
+        return Err((StatusCode::UNAUTHORIZED, "bad signature"));
+    }
+    if path == "/new-account" {
+        ca.account_jwk = Some(jwk);
+    }
+
+    if payload.is_empty() {
+        return Ok(Value::Null);
+    }
+    serde_json::from_slice(&URL_SAFE_NO_PAD.decode(payload).unwrap_or_default()).map_err(|_| (StatusCode::BAD_REQUEST, "bad payload"))
+}
+
+/// Accepts any certificate and keeps it, so the validation can look inside.
+struct KeepCertificate(Mutex<Option<Vec<u8>>>);
+
+impl ServerCertVerifier for KeepCertificate {
+    fn verify_server_cert(
+        &self,
+        end_entity: &Certificate,
+        _intermediates: &[Certificate],
+        _server_name: &ServerName,
+        _scts: &mut dyn Iterator<Item = &[u8]>,
+        _ocsp_response: &[u8],
+        _now: SystemTime,
+    ) -> Result<ServerCertVerified, rustls::Error> {
+        *self.0.lock().unwrap() = Some(end_entity.0.clone());
+        Ok(ServerCertVerified::assertion())
+    }
+}
+
+/// Handshakes with `server_config` in memory and returns the certificate the
+/// server presented, if it presented one.
+fn presented_certificate(server_config: Arc<ServerConfig>, alpn: Option<&[u8]>) -> Option<Vec<u8>> {
+    let verifier = Arc::new(KeepCertificate(Mutex::new(None)));
+    let mut config = ClientConfig::builder()
+        .with_safe_defaults()
+        .with_custom_certificate_verifier(verifier.clone())
+        .with_no_client_auth();
+    config.alpn_protocols = alpn.map(|protocol| vec![protocol.to_vec()]).unwrap_or_default();
+    let mut client = ClientConnection::new(Arc::new(config), DOMAIN.try_into().unwrap()).unwrap();
+    let mut server = ServerConnection::new(server_config).unwrap();
+
+    while client.is_handshaking() || server.is_handshaking() {
+        let mut bytes = Vec::new();
+        client.write_tls(&mut bytes).unwrap();
+        server.read_tls(&mut bytes.as_slice()).unwrap();
+        if server.process_new_packets().is_err() {
+            break;
+        }
+        let mut bytes = Vec::new();
+        server.write_tls(&mut bytes).unwrap();
+        client.read_tls(&mut bytes.as_slice()).unwrap();
+        if client.process_new_packets().is_err() || bytes.is_empty() {
+            break;
+        }
+    }
+    verifier.0.lock().unwrap().take()
+}
+
+fn order(ca: &Ca) -> Value {
+    json!({
+        "status": ca.order_status,
+        "authorizations": [format!("{}/authz/1", ca.base)],
+        "finalize": format!("{}/finalize/1", ca.base),
+        "certificate": (ca.order_status == "valid").then(|| format!("{}/cert/1", ca.base)),
+    })
+}
+
+fn with_nonce(ca: &mut Ca, status: StatusCode, location: Option<String>, body: Value) -> Response {
+    let mut headers = HeaderMap::new();
+    headers.insert("replay-nonce", nonce(ca).parse().unwrap());
+    if let Some(location) = location {
+        headers.insert(header::LOCATION, location.parse().unwrap());
+    }
+    (status, headers, Json(body)).into_response()
+}
+
+fn ca_router(ca: SharedCa, app_tls: Arc<ServerConfig>) -> Router {
+    Router::new()
+        .route(
+            "/directory",
+            get(|State(ca): State<SharedCa>| async move {
+                let base = ca.lock().unwrap().base.clone();
+                Json(json!({
+                    "newNonce": format!("{}/new-nonce", base),
+                    "newAccount": format!("{}/new-account", base),
+                    "newOrder": format!("{}/new-order", base),
+                }))
+            }),
+        )
+        .route("/new-nonce", get(|State(ca): State<SharedCa>| async move { [("replay-nonce", nonce(&mut ca.lock().unwrap()))] }))
+        .route(
+            "/*path",
+            post(move |State(ca): State<SharedCa>, Path(path): Path<String>, Json(body): Json<Value>| {
+                let app_tls = app_tls.clone();
+                async move {
+                    let path = format!("/{}", path);
+                    let mut ca = ca.lock().unwrap();
+                    let payload = match verify(&mut ca, &path, &body) {
+                        Ok(payload) => payload,
+                        Err((status, detail)) => return error(status, detail),
+                    };
+                    let base = ca.base.clone();
+                    match path.as_str() {
+                        "/new-account" => {
+                            assert_eq!(payload["termsOfServiceAgreed"], true);
+                            assert_eq!(payload["contact"], json!(["mailto:admin@example.com"]));
+                            with_nonce(&mut ca, StatusCode::CREATED, Some(format!("{}/account/1", base)), json!({ "status": "valid" }))
+                        }
+                        "/new-order" => {
+                            assert_eq!(payload["identifiers"], json!([{ "type": "dns", "value": DOMAIN }]));
+                            let order = order(&ca);
+                            with_nonce(&mut ca, StatusCode::CREATED, Some(format!("{}/order/1", base)), order)
+                        }
+                        "/authz/1" => {
+                            let authorization = json!({
+                                "status": ca.authorization_status,
+                                "identifier": { "type": "dns", "value": DOMAIN },
+                                "challenges": [
+                                    { "type": "http-01", "url": format!("{}/challenge/2", base), "token": "unused" },
+                                    { "type": "tls-alpn-01", "url": format!("{}/challenge/1", base), "token": ca.token },
+                                ],
+                            });
+                            with_nonce(&mut ca, StatusCode::OK, None, authorization)
+                        }
+                        "/challenge/1" => {
+                            // Connect with only acme-tls/1 and check the key authorization in the certificate
+                            let key_authorization = format!("{}.{}", ca.token, thumbprint(ca.account_jwk.as_ref().unwrap()));
+                            let certificate = presented_certificate(app_tls, Some(b"acme-tls/1"));
+                            let expected = digest(&SHA256, key_authorization.as_bytes());
+                            let valid = certificate
+                                .as_ref()
+                                .is_some_and(|der| der.windows(32).any(|window| window == expected.as_ref()));
+                            ca.authorization_status = if valid { "valid" } else { "invalid" };
+                            ca.challenge_certificate = certificate;
+                            with_nonce(&mut ca, StatusCode::OK, None, json!({ "type": "tls-alpn-01", "status": "processing" }))
+                        }
+                        "/finalize/1" => {
+                            if ca.authorization_status != "valid" {
+                                return error(StatusCode::FORBIDDEN, "order is not ready");
+                            }
+                            let csr = payload["csr"].as_str().and_then(|csr| URL_SAFE_NO_PAD.decode(csr).ok()).unwrap_or_default();
+                            assert!(csr.windows(DOMAIN.len()).any(|window| window == DOMAIN.as_bytes()));
+                            ca.order_status = "valid";
+                            let order = order(&ca);
+                            with_nonce(&mut ca, StatusCode::OK, Some(format!("{}/order/1", base)), order)
+                        }
+                        "/order/1" => {
+                            let order = order(&ca);
+                            with_nonce(&mut ca, StatusCode::OK, None, order)
+                        }
+                        "/cert/1" => {
+                            let mut headers = HeaderMap::new();
+                            headers.insert("replay-nonce", nonce(&mut ca).parse().unwrap());
+                            headers.insert(header::CONTENT_TYPE, "application/pem-certificate-chain".parse().unwrap());
+                            (headers, ca.certificate_pem.clone()).into_response()
+                        }
+                        _ => error(StatusCode::NOT_FOUND, "no such resource"),
+                    }
+                }
+            }),
+        )
+        .with_state(ca)
+}
+
+#[tokio::test]
+async fn obtains_a_certificate_through_tls_alpn_01() {
+    let cache_dir = std::env::temp_dir().join(format!("todo-app-acme-{}", std::process::id()));
+    let _ = std::fs::remove_dir_all(&cache_dir);
+
+    let mut params = rcgen::CertificateParams::new(vec![DOMAIN.to_string()]);
+    params.not_after = rcgen::date_time_ymd(2040, 1, 1);
+    let issued = rcgen::Certificate::from_params(params).unwrap();
+    // Serialized once, since each call signs afresh
+    let issued_pem = issued.serialize_pem().unwrap();
+    let issued_der = rustls_pemfile::certs(&mut issued_pem.as_bytes()).unwrap().remove(0);
+
+    let resolver = Arc::new(AcmeResolver::default());
+    let app_tls = acme::server_config(resolver.clone());
+    let listener = tokio::net::TcpListener::bind("127.0.0.1:0").await.unwrap();
+    let base = format!("http://{}", listener.local_addr().unwrap());
+    let ca = Arc::new(Mutex::new(Ca {
+        base: base.clone(),
+        token: "evaGxfADs6pSRb2LAv9IZf17Dt3juxGJ-PCt92wr-oA".to_string(),
+        authorization_status: "pending",
+        order_status: "pending",
+        certificate_pem: issued_pem.clone(),
+        ..Ca::default()
+    }));
+    let router = ca_router(ca.clone(), app_tls.clone());
+    tokio::spawn(async move { axum::serve(listener, router).await.unwrap() });
+
+    // Nothing to serve before the order completes
+    assert_eq!(presented_certificate(app_tls.clone(), None), None);
+
+    acme::spawn(
+        AcmeConfig {
+            domain: DOMAIN.to_string(),
+            contact: Some("admin@example.com".to_string()),
+            directory_url: format!("{}/directory", base),
+            cache_dir: cache_dir.clone(),
+        },
+        resolver,
+    );
+
+    let cert_path = cache_dir.join(format!("{}.crt.pem", DOMAIN));
+    for _ in 0..100 {
+        if cert_path.exists() {
+            break;
+        }
+        tokio::time::sleep(Duration::from_millis(100)).await;
+    }
+    assert_eq!(std::fs::read_to_string(&cert_path).unwrap(), issued_pem);
+    assert!(cache_dir.join(format!("{}.key.pem", DOMAIN)).exists());
+    assert!(cache_dir.join("account.key").exists());
+    // Both private keys are for the owner's eyes only
+    #[cfg(unix)]
+    for key in [format!("{}.key.pem", DOMAIN), "account.key".to_string()] {
+        use std::os::unix::fs::PermissionsExt;
+        let mode = std::fs::metadata(cache_dir.join(&key)).unwrap().permissions().mode();
+        assert_eq!(mode & 0o777, 0o600, "{}", key);
+    }
+
+    let ca = ca.lock().unwrap();
+    assert_eq!(ca.authorization_status, "valid");
+    let challenge_certificate = ca.challenge_certificate.clone().expect("the challenge certificate was presented");
+    assert_ne!(challenge_certificate, issued_der);
+
+    // Regular handshakes now get the issued certificate, and the challenge one is gone
+    assert_eq!(presented_certificate(app_tls.clone(), None), Some(issued_der));
+    assert_eq!(presented_certificate(app_tls, Some(b"acme-tls/1")), None);
+
+    let _ = std::fs::remove_dir_all(&cache_dir);
+}
@@ -0,0 +1,1564 @@
+use axum::body::Body;
+use axum::http::{header, Method, Request, StatusCode};
+use axum::Router;
+use serde_json::{json, Value};
+use std::path::PathBuf;
+use std::time::Duration;
+use tower::ServiceExt;
+
+mod common;
+
+const PASSWORD: &str = "violet-kettle-harbour-93";
+
+/// The app on a fresh database file; the file is removed on drop.
+struct TestApp {
+    router: Router,
+    path: PathBuf,
+}
+
+impl Drop for TestApp {
+    fn drop(&mut self) {
+        common::remove_database(&self.path);
+    }
+}
+
+async fn app(name: &str) -> TestApp {
+    let path = std::env::temp_dir().join(format!("todo-app-api-{}-{}.db", name, std::process::id()));
+    let _ = std::fs::remove_file(&path);
+
+    let router = todo_app::build_app(&common::config(&format!("sqlite:{}", path.display()))).await.expect("app should start");
+    TestApp { router, path }
+}
+
+impl TestApp {
+    async fn request(&self, method: Method, uri: &str, token: Option<&str>, body: Option<Value>) -> (StatusCode, Value) {
+        common::request(&self.router, method, uri, token, body).await
+    }
+
+    /// Registers `username` and returns their token.
+    async fn register(&self, username: &str) -> String {
+        let body = json!({ "username": username, "email": format!("{}@example.com", username), "password": PASSWORD });
+        let (status, body) = self.request(Method::POST, "/auth/register", None, Some(body)).await;
+        assert_eq!(status, StatusCode::OK, "register failed: {}", body);
+        body["token"].as_str().unwrap().to_string()
+    }
+
+    /// The signed-in user's todos, unpaginated.
+    async fn todos(&self, token: &str) -> Vec<Value> {
+        let (status, body) = self.request(Method::GET, "/todos", Some(token), None).await;
+        assert_eq!(status, StatusCode::OK);
+        body.as_array().unwrap().clone()
+    }
+}
+
+#[tokio::test]
+async fn serves_the_home_page_without_signing_in() {
+    let app = app("home").await;
+    let response = app.router.clone().oneshot(Request::get("/").body(Body::empty()).unwrap()).await.unwrap();
+
+    assert_eq!(response.status(), StatusCode::OK);
+    assert!(response.headers().contains_key("x-request-id"));
+}
+
+#[tokio::test]
+async fn adds_the_request_id_to_json_errors() {
+    let app = app("json-errors").await;
+    let weak = json!({ "username": "alice", "email": "alice@example.com", "password": "password1" }).to_string();
+    let register = |accept: &str| {
+        let request = Request::post("/auth/register")
+            .header(header::CONTENT_TYPE, "application/json")
+            .header(header::ACCEPT, accept)
+            .header(header::ACCEPT_LANGUAGE, "es")
+            .header("x-request-id", "weak-password-1")
+            .body(Body::from(weak.clone()))
+            .unwrap();
+        let router = app.router.clone();
+        async move {
+            let response = router.oneshot(request).await.unwrap();
+            let status = response.status();
+            let bytes = axum::body::to_bytes(response.into_body(), usize::MAX).await.unwrap();
+            (status, serde_json::from_slice::<Value>(&bytes).unwrap())
+        }
+    };
+
+    // The handler's own fields stay
+    let (status, body) = register("application/json").await;
+    assert_eq!(status, StatusCode::UNPROCESSABLE_ENTITY);
+    assert_eq!(body["error"], "Password is too weak");
+    assert!(body["score"].is_number());
+    assert_eq!(body["message"], "Entidad no procesable");
+    assert_eq!(body["request_id"], "weak-password-1");
+
+    let (status, document) = register("application/vnd.api+json").await;
+    assert_eq!(status, StatusCode::UNPROCESSABLE_ENTITY);
+    let error = &document["errors"][0];
+    assert_eq!(error["status"], "422");
+    assert_eq!(error["title"], "Entidad no procesable");
+    assert_eq!(error["detail"], "Password is too weak");
+    assert_eq!(error["meta"]["request_id"], "weak-password-1");
+    assert!(error["meta"]["score"].is_number());
+}
+
+#[tokio::test]
+async fn rejects_requests_without_a_valid_token() {
+    let app = app("unauthorized").await;
+
+    let (status, _) = app.request(Method::GET, "/todos", None, None).await;
+    assert_eq!(status, StatusCode::UNAUTHORIZED);
+    let (status, _) = app.request(Method::GET, "/todos", Some("not-a-token"), None).await;
+    assert_eq!(status, StatusCode::UNAUTHORIZED);
+}
+
+#[tokio::test]
+async fn registers_logs_in_and_out() {
+    let app = app("auth").await;
+    app.register("alice").await;
+
+    let wrong = json!({ "username": "alice", "password": "not-the-password" });
+    let (status, _) = app.request(Method::POST, "/auth/login", None, Some(wrong)).await;
+    assert_eq!(status, StatusCode::UNAUTHORIZED);
+
+    let login = json!({ "username": "alice", "password": PASSWORD });
+    let (status, body) = app.request(Method::POST, "/auth/login", None, Some(login)).await;
+    assert_eq!(status, StatusCode::OK);
+    let token = body["token"].as_str().unwrap();
+
+    let (status, me) = app.request(Method::GET, "/auth/me", Some(token), None).await;
+    assert_eq!(status, StatusCode::OK);
+    assert_eq!(me["username"], "alice");
+
+    let (status, _) = app.request(Method::POST, "/auth/logout", Some(token), None).await;
+    assert!(status.is_success());
+    let (status, _) = app.request(Method::GET, "/auth/me", Some(token), None).await;
+    assert_eq!(status, StatusCode::UNAUTHORIZED);
+
+    let duplicate = json!({ "username": "alice", "email": "other@example.com", "password": PASSWORD });
+    let (status, _) = app.request(Method::POST, "/auth/register", None, Some(duplicate)).await;
+    assert_eq!(status, StatusCode::CONFLICT);
+}
+
+#[tokio::test]
+async fn requires_a_csrf_token_with_the_session_cookie() {
+    let app = app("csrf").await;
+    app.register("alice").await;
+
+    let login = Request::builder()
+        .method(Method::POST)
+        .uri("/auth/login?cookie=true")
+        .header(header::CONTENT_TYPE, "application/json")
+        .body(Body::from(json!({ "username": "alice", "password": PASSWORD }).to_string()))
+        .unwrap();
+    let response = app.router.clone().oneshot(login).await.unwrap();
+    assert_eq!(response.status(), StatusCode::OK);
+    let cookies: Vec<String> = response.headers().get_all(header::SET_COOKIE).iter().map(|value| value.to_str().unwrap().to_string()).collect();
+    let cookie_value = |name: &str| {
+        let cookie = cookies.iter().find(|cookie| cookie.starts_with(&format!("{}=", name))).unwrap();
+        cookie.split(';').next().unwrap().split_once('=').unwrap().1.to_string()
+    };
+    let (session, csrf_token) = (cookie_value("session"), cookie_value("csrf_token"));
+    assert!(cookies.iter().any(|cookie| cookie.starts_with("session=") && cookie.contains("HttpOnly")));
+
+    let send = |method: Method, csrf_cookie: &str, csrf_header: Option<&str>| {
+        let mut request = Request::builder()
+            .method(method)
+            .uri("/todos")
+            .header(header::CONTENT_TYPE, "application/json")
+            .header(header::COOKIE, format!("session={}; csrf_token={}", session, csrf_cookie));
+        if let Some(csrf_header) = csrf_header {
+            request = request.header("x-csrf-token", csrf_header);
+        }
+        let request = request.body(Body::from(json!({ "text": "Water the plants" }).to_string())).unwrap();
+        let router = app.router.clone();
+        async move { router.oneshot(request).await.unwrap().status() }
+    };
+
+    // Reads don't need it; writes need the cookie echoed in the header, and
+    // it must be the one issued for this session
+    assert_eq!(send(Method::GET, &csrf_token, None).await, StatusCode::OK);
+    assert_eq!(send(Method::POST, &csrf_token, None).await, StatusCode::FORBIDDEN);
+    assert_eq!(send(Method::POST, &csrf_token, Some("something-else")).await, StatusCode::FORBIDDEN);
+    assert_eq!(send(Method::POST, "planted", Some("planted")).await, StatusCode::FORBIDDEN);
+    assert_eq!(send(Method::POST, &csrf_token, Some(&csrf_token)).await, StatusCode::CREATED);
+
+    // Bearer tokens aren't sent by browsers on their own, so they don't need one
+    let token = app.register("bob").await;
+    let (status, _) = app.request(Method::POST, "/todos", Some(&token), Some(json!({ "text": "Feed the cat" }))).await;
+    assert_eq!(status, StatusCode::CREATED);
+}
+
+#[tokio::test]
+async fn keeps_revoked_tokens_when_the_cache_fills_up() {
+    let shared = todo_app::shared_state::SharedState::local();
+    shared.revoke("logged-out", Duration::from_secs(3600)).await.unwrap();
+    shared.revoke("short-lived", Duration::from_millis(1)).await.unwrap();
+
+    // More cached values than the cache holds, each read more often than the revocation
+    for i in 0..150_000 {
+        let key = format!("todos:{}", i);
+        shared.set(&key, String::new(), Duration::from_secs(3600)).await.unwrap();
+        shared.get(&key).await.unwrap();
+        shared.get(&key).await.unwrap();
+    }
+    tokio::time::sleep(Duration::from_millis(5)).await;
+    assert!(shared.is_revoked("logged-out").await.unwrap());
+    assert!(!shared.is_revoked("short-lived").await.unwrap());
+    assert!(!shared.is_revoked("never-revoked").await.unwrap());
+}
+
+#[tokio::test]
+async fn creates_updates_and_completes_todos() {
+    let app = app("todos").await;
+    let token = app.register("alice").await;
+
+    let new_todo = json!({ "text": "Write the quarterly report", "category": "work", "priority": "high" });
+    let (status, _) = app.request(Method::POST, "/todos", Some(&token), Some(new_todo)).await;
+    assert_eq!(status, StatusCode::CREATED);
+
+    let todos = app.todos(&token).await;
+    assert_eq!(todos.len(), 1);
+    let id = todos[0]["id"].as_str().unwrap().to_string();
+    assert_eq!(todos[0]["category"], "work");
+    assert_eq!(todos[0]["completed"], false);
+
+    let (status, todo) = app.request(Method::GET, &format!("/todos/{}", id), Some(&token), None).await;
+    assert_eq!(status, StatusCode::OK);
+    assert_eq!(todo["text"], "Write the quarterly report");
+    let version = todo["version"].as_i64().unwrap();
+
+    // Updates must name the version they're based on
+    let update = json!({ "text": "Write the annual report" });
+    let (status, _) = app.request(Method::PATCH, &format!("/todos/{}", id), Some(&token), Some(update)).await;
+    assert_eq!(status, StatusCode::PRECONDITION_REQUIRED);
+    let update = json!({ "text": "Write the annual report", "expected_version": version });
+    let (status, todo) = app.request(Method::PATCH, &format!("/todos/{}", id), Some(&token), Some(update)).await;
+    assert_eq!(status, StatusCode::OK);
+    assert_eq!(todo["text"], "Write the annual report");
+    let stale = json!({ "text": "Lost update", "expected_version": version });
+    let (status, _) = app.request(Method::PATCH, &format!("/todos/{}", id), Some(&token), Some(stale)).await;
+    assert_eq!(status, StatusCode::CONFLICT);
+
+    let (status, _) = app.request(Method::POST, &format!("/toggle/{}", id), Some(&token), None).await;
+    assert_eq!(status, StatusCode::OK);
+    let (status, stats) = app.request(Method::GET, "/stats", Some(&token), None).await;
+    assert_eq!(status, StatusCode::OK);
+    assert_eq!(stats["total"], 1);
+    assert_eq!(stats["completed"], 1);
+}
+
+#[tokio::test]
+async fn keeps_todos_private_to_their_owner() {
+    let app = app("isolation").await;
+    let alice = app.register("alice").await;
+    let bob = app.register("bob").await;
+
+    let new_todo = json!({ "text": "Alice's secret plan" });
+    let (status, _) = app.request(Method::POST, "/todos", Some(&alice), Some(new_todo)).await;
+    assert_eq!(status, StatusCode::CREATED);
+    let id = app.todos(&alice).await[0]["id"].as_str().unwrap().to_string();
+
+    assert!(app.todos(&bob).await.is_empty());
+    let (status, _) = app.request(Method::GET, &format!("/todos/{}", id), Some(&bob), None).await;
+    assert_eq!(status, StatusCode::NOT_FOUND);
+    let (status, _) = app.request(Method::POST, &format!("/toggle/{}", id), Some(&bob), None).await;
+    assert_eq!(status, StatusCode::NOT_FOUND);
+}
+
+#[tokio::test]
+async fn paginates_todo_lists() {
+    let app = app("pagination").await;
+    let token = app.register("alice").await;
+    for i in 0..5 {
+        let new_todo = json!({ "text": format!("Todo {}", i) });
+        app.request(Method::POST, "/todos", Some(&token), Some(new_todo)).await;
+        // Distinct creation times keep the order stable
+        tokio::time::sleep(Duration::from_millis(5)).await;
+    }
+
+    let (status, page) = app.request(Method::GET, "/todos?limit=3", Some(&token), None).await;
+    assert_eq!(status, StatusCode::OK);
+    assert_eq!(page["items"].as_array().unwrap().len(), 3);
+    let cursor = page["next_cursor"].as_str().unwrap();
+
+    let (status, page) = app.request(Method::GET, &format!("/todos?limit=3&after={}", cursor), Some(&token), None).await;
+    assert_eq!(status, StatusCode::OK);
+    assert_eq!(page["items"].as_array().unwrap().len(), 2);
+    assert!(page["next_cursor"].is_null());
+}
+
+#[tokio::test]
+async fn keeps_admin_endpoints_from_regular_users() {
+    let app = app("admin").await;
+    let token = app.register("alice").await;
+
+    for uri in ["/admin/jobs", "/admin/stats"] {
+        let (status, _) = app.request(Method::GET, uri, Some(&token), None).await;
+        assert_eq!(status, StatusCode::FORBIDDEN, "{}", uri);
+    }
+}
+
+#[tokio::test]
+async fn creates_todos_in_batches() {
+    let app = app("batch").await;
+    let token = app.register("alice").await;
+
+    let batch = json!([{ "text": "Pack the tent" }, { "text": "Book the campsite", "priority": "high" }, { "text": "Buy gas" }]);
+    let (status, body) = app.request(Method::POST, "/todos/batch", Some(&token), Some(batch)).await;
+    assert_eq!(status, StatusCode::CREATED);
+    let ids: Vec<&str> = body["ids"].as_array().unwrap().iter().map(|id| id.as_str().unwrap()).collect();
+    assert_eq!(ids.len(), 3);
+
+    let (_, todo) = app.request(Method::GET, &format!("/todos/{}", ids[1]), Some(&token), None).await;
+    assert_eq!(todo["text"], "Book the campsite");
+    assert_eq!(todo["priority"], "high");
+
+    // One bad todo rejects the whole batch
+    let batch = json!([{ "text": "Fine" }, { "text": "Broken", "due": "not a date" }]);
+    let (status, _) = app.request(Method::POST, "/todos/batch", Some(&token), Some(batch)).await;
+    assert_eq!(status, StatusCode::UNPROCESSABLE_ENTITY);
+    assert_eq!(app.todos(&token).await.len(), 3);
+}
+
+#[tokio::test]
+async fn exports_todos_as_ndjson() {
+    let app = app("ndjson").await;
+    let token = app.register("alice").await;
+    let batch = json!([{ "text": "First" }, { "text": "Second" }, { "text": "Third" }]);
+    app.request(Method::POST, "/todos/batch", Some(&token), Some(batch)).await;
+
+    let request = Request::get("/todos/export.ndjson")
+        .header(header::AUTHORIZATION, format!("Bearer {}", token))
+        .body(Body::empty())
+        .unwrap();
+    let response = app.router.clone().oneshot(request).await.unwrap();
+    assert_eq!(response.status(), StatusCode::OK);
+    assert_eq!(response.headers()[header::CONTENT_TYPE], "application/x-ndjson");
+
+    let bytes = axum::body::to_bytes(response.into_body(), usize::MAX).await.unwrap();
+    let lines: Vec<Value> = std::str::from_utf8(&bytes)
+        .unwrap()
+        .lines()
+        .map(|line| serde_json::from_str(line).unwrap())
+        .collect();
+    assert_eq!(lines.len(), 3);
+    assert!(lines.iter().any(|todo| todo["text"] == "Second"));
+}
+
+#[tokio::test]
+async fn replays_todo_creation_with_the_same_idempotency_key() {
+    let app = app("idempotency").await;
+    let token = app.register("alice").await;
+
+    let create_with = |key: &'static str, body: Value| {
+        Request::post("/todos")
+            .header(header::AUTHORIZATION, format!("Bearer {}", token))
+            .header(header::CONTENT_TYPE, "application/json")
+            .header("idempotency-key", key)
+            .body(Body::from(body.to_string()))
+            .unwrap()
+    };
+    let create = |key: &'static str| create_with(key, json!({ "text": "Buy groceries", "priority": "high" }));
+
+    // A request that fails leaves its key free for the retry
+    let failed = app.router.clone().oneshot(create_with("groceries-1", json!({ "text": "Buy groceries", "priority": "urgent" }))).await.unwrap();
+    assert_eq!(failed.status(), StatusCode::UNPROCESSABLE_ENTITY);
+
+    let first = app.router.clone().oneshot(create("groceries-1")).await.unwrap();
+    assert_eq!(first.status(), StatusCode::CREATED);
+    assert!(!first.headers().contains_key("idempotent-replayed"));
+
+    let retry = app.router.clone().oneshot(create("groceries-1")).await.unwrap();
+    assert_eq!(retry.status(), StatusCode::CREATED);
+    assert_eq!(retry.headers()["idempotent-replayed"], "true");
+    assert_eq!(retry.headers()[header::LOCATION], first.headers()[header::LOCATION]);
+    assert_eq!(app.todos(&token).await.len(), 1);
+
+    // The same fields in another order are the same request; other fields aren't
+    let reordered = create_with("groceries-1", json!({ "priority": "high", "text": "Buy groceries" }));
+    assert_eq!(app.router.clone().oneshot(reordered).await.unwrap().status(), StatusCode::CREATED);
+    let changed = create_with("groceries-1", json!({ "text": "Buy groceries", "priority": "low" }));
+    assert_eq!(app.router.clone().oneshot(changed).await.unwrap().status(), StatusCode::UNPROCESSABLE_ENTITY);
+    assert_eq!(app.todos(&token).await.len(), 1);
+
+    // Retries sent together create one todo between them
+    let retries: Vec<_> = (0..5).map(|_| tokio::spawn(app.router.clone().oneshot(create("groceries-2")))).collect();
+    for retry in retries {
+        assert_eq!(retry.await.unwrap().unwrap().status(), StatusCode::CREATED);
+    }
+    assert_eq!(app.todos(&token).await.len(), 2);
+}
+
+#[tokio::test]
+async fn lists_todos_in_the_format_asked_for() {
+    let app = app("formats").await;
+    let token = app.register("alice").await;
+    let batch = json!([{ "text": "Call mum, then dad", "tags": ["family"] }, { "text": "Renew passport", "priority": "high" }]);
+    app.request(Method::POST, "/todos/batch", Some(&token), Some(batch)).await;
+
+    let list = |accept: &str| {
+        Request::get("/todos")
+            .header(header::AUTHORIZATION, format!("Bearer {}", token))
+            .header(header::ACCEPT, accept)
+            .body(Body::empty())
+            .unwrap()
+    };
+    let body = |response: axum::response::Response| async {
+        let bytes = axum::body::to_bytes(response.into_body(), usize::MAX).await.unwrap();
+        String::from_utf8(bytes.to_vec()).unwrap()
+    };
+
+    let response = app.router.clone().oneshot(list("text/csv")).await.unwrap();
+    assert_eq!(response.status(), StatusCode::OK);
+    assert!(response.headers()[header::CONTENT_TYPE].to_str().unwrap().starts_with("text/csv"));
+    let csv = body(response).await;
+    assert_eq!(csv.lines().count(), 3);
+    assert!(csv.contains("\"Call mum, then dad\""));
+
+    let response = app.router.clone().oneshot(list("text/calendar")).await.unwrap();
+    let ics = body(response).await;
+    assert!(ics.starts_with("BEGIN:VCALENDAR\r\n"));
+    assert_eq!(ics.matches("BEGIN:VTODO").count(), 2);
+    assert!(ics.contains("PRIORITY:1"));
+    assert!(ics.contains("SUMMARY:Call mum\\, then dad"));
+
+    let response = app.router.clone().oneshot(list("text/html, */*;q=0.8")).await.unwrap();
+    assert!(response.headers()[header::CONTENT_TYPE].to_str().unwrap().starts_with("application/json"));
+
+    let response = app.router.clone().oneshot(list("image/png")).await.unwrap();
+    assert_eq!(response.status(), StatusCode::NOT_ACCEPTABLE);
+}
+
+#[cfg(feature = "msgpack")]
+#[tokio::test]
+async fn answers_in_messagepack_when_preferred() {
+    let app = app("msgpack").await;
+    let token = app.register("alice").await;
+    app.request(Method::POST, "/todos", Some(&token), Some(json!({ "text": "Pack light" }))).await;
+
+    let request = |uri: &str, accept: &str| {
+        Request::get(uri)
+            .header(header::AUTHORIZATION, format!("Bearer {}", token))
+            .header(header::ACCEPT, accept)
+            .body(Body::empty())
+            .unwrap()
+    };
+
+    let response = app.router.clone().oneshot(request("/todos", "application/msgpack")).await.unwrap();
+    assert_eq!(response.status(), StatusCode::OK);
+    assert_eq!(response.headers()[header::CONTENT_TYPE], "application/msgpack");
+    let bytes = axum::body::to_bytes(response.into_body(), usize::MAX).await.unwrap();
+    let todos: Value = rmp_serde::from_slice(&bytes).unwrap();
+    assert_eq!(todos[0]["text"], "Pack light");
+
+    // Errors too
+    let response = app.router.clone().oneshot(request("/todos/missing", "application/msgpack")).await.unwrap();
+    assert_eq!(response.status(), StatusCode::NOT_FOUND);
+    assert_eq!(response.headers()[header::CONTENT_TYPE], "application/msgpack");
+
+    // JSON when it ranks higher
+    let response = app.router.clone().oneshot(request("/auth/me", "application/json, application/msgpack;q=0.5")).await.unwrap();
+    assert!(response.headers()[header::CONTENT_TYPE].to_str().unwrap().starts_with("application/json"));
+}
+
+#[tokio::test]
+async fn returns_tags_as_an_array() {
+    let app = app("tags").await;
+    let token = app.register("alice").await;
+    let new_todo = json!({ "text": "Plan the trip", "tags": ["travel", "summer"] });
+    app.request(Method::POST, "/todos", Some(&token), Some(new_todo)).await;
+
+    let todos = app.todos(&token).await;
+    assert_eq!(todos[0]["tags"], json!(["travel", "summer"]));
+    // Served from the cache the second time
+    let todos = app.todos(&token).await;
+    assert_eq!(todos[0]["tags"], json!(["travel", "summer"]));
+}
+
+#[tokio::test]
+async fn reports_possible_duplicates() {
+    let app = app("duplicates").await;
+    let token = app.register("alice").await;
+
+    let (status, body) = app.request(Method::POST, "/todos", Some(&token), Some(json!({ "text": "Buy groceries for the week" }))).await;
+    assert_eq!(status, StatusCode::CREATED);
+    assert_eq!(body["duplicates"], json!([]));
+    let original = body["id"].clone();
+
+    let (status, body) = app.request(Method::POST, "/todos", Some(&token), Some(json!({ "text": "buy groceries for the week!" }))).await;
+    assert_eq!(status, StatusCode::CREATED);
+    assert_eq!(body["duplicates"][0]["id"], original);
+    assert_eq!(body["duplicates"][0]["similarity"], 1.0);
+
+    let (status, body) = app.request(Method::POST, "/todos?strict=true", Some(&token), Some(json!({ "text": "Buy grocery for the week" }))).await;
+    assert_eq!(status, StatusCode::CONFLICT);
+    assert_eq!(body["duplicates"].as_array().unwrap().len(), 2);
+    assert_eq!(app.todos(&token).await.len(), 2);
+
+    let (status, body) = app.request(Method::POST, "/todos?strict=true", Some(&token), Some(json!({ "text": "Call the plumber" }))).await;
+    assert_eq!(status, StatusCode::CREATED);
+    assert_eq!(body["duplicates"], json!([]));
+}
+
+#[tokio::test]
+async fn duplicates_a_todo() {
+    let app = app("duplicate").await;
+    let token = app.register("alice").await;
+    let original = json!({
+        "text": "Send the monthly invoice",
+        "notes": "- [x] export hours\n- [ ] attach receipts",
+        "category": "work",
+        "tags": ["billing"],
+        "priority": "high",
+        "due_date": "2030-01-31T17:00:00Z"
+    });
+    let (_, body) = app.request(Method::POST, "/todos", Some(&token), Some(original)).await;
+    let id = body["id"].as_str().unwrap();
+    app.request(Method::POST, &format!("/toggle/{}", id), Some(&token), None).await;
+
+    let shift = json!({ "shift_days": 28 });
+    let (status, copy) = app.request(Method::POST, &format!("/todos/{}/duplicate", id), Some(&token), Some(shift)).await;
+    assert_eq!(status, StatusCode::CREATED);
+    assert_ne!(copy["id"], id);
+    assert_eq!(copy["text"], "Send the monthly invoice");
+    assert_eq!(copy["notes"], "- [ ] export hours\n- [ ] attach receipts");
+    assert_eq!(copy["tags"], json!(["billing"]));
+    assert_eq!(copy["priority"], "high");
+    assert_eq!(copy["completed"], false);
+    assert_eq!(copy["due_date"], "2030-02-28T17:00:00Z");
+
+    let (status, copy) = app.request(Method::POST, &format!("/todos/{}/duplicate", id), Some(&token), None).await;
+    assert_eq!(status, StatusCode::CREATED);
+    assert_eq!(copy["due_date"], "2030-01-31T17:00:00Z");
+
+    let (status, _) = app.request(Method::POST, "/todos/missing/duplicate", Some(&token), None).await;
+    assert_eq!(status, StatusCode::NOT_FOUND);
+}
+
+#[tokio::test]
+async fn labels_todos_with_colors() {
+    let app = app("colors").await;
+    let token = app.register("alice").await;
+
+    let (status, _) = app.request(Method::POST, "/todos", Some(&token), Some(json!({ "text": "Odd", "color": "chartreuse" }))).await;
+    assert_eq!(status, StatusCode::UNPROCESSABLE_ENTITY);
+
+    let (status, body) = app.request(Method::POST, "/todos", Some(&token), Some(json!({ "text": "Blue", "color": "#3B82F6" }))).await;
+    assert_eq!(status, StatusCode::CREATED);
+    let uri = format!("/todos/{}", body["id"].as_str().unwrap());
+    let (_, todo) = app.request(Method::GET, &uri, Some(&token), None).await;
+    assert_eq!(todo["color"], "#3b82f6");
+
+    let update = json!({ "color": "teal", "expected_version": todo["version"] });
+    let (status, todo) = app.request(Method::PATCH, &uri, Some(&token), Some(update)).await;
+    assert_eq!(status, StatusCode::OK);
+    assert_eq!(todo["color"], "teal");
+
+    let update = json!({ "color": "", "expected_version": todo["version"] });
+    let (_, todo) = app.request(Method::PATCH, &uri, Some(&token), Some(update)).await;
+    assert!(todo["color"].is_null());
+}
+
+#[tokio::test]
+async fn finds_todos_near_a_point() {
+    let app = app("nearby").await;
+    let token = app.register("alice").await;
+    let batch = json!([
+        // Around Trafalgar Square, London
+        { "text": "Buy stamps", "latitude": 51.5085, "longitude": -0.1280, "location_name": "Post office" },
+        { "text": "Pick up prints", "latitude": 51.5100, "longitude": -0.1300 },
+        { "text": "Visit the castle", "latitude": 55.9486, "longitude": -3.1999 },
+        { "text": "Anywhere" }
+    ]);
+    let (status, _) = app.request(Method::POST, "/todos/batch", Some(&token), Some(batch)).await;
+    assert_eq!(status, StatusCode::CREATED);
+
+    let (status, nearby) = app.request(Method::GET, "/todos/nearby?lat=51.5080&lng=-0.1281&radius=1000", Some(&token), None).await;
+    assert_eq!(status, StatusCode::OK);
+    let texts: Vec<&str> = nearby.as_array().unwrap().iter().map(|todo| todo["text"].as_str().unwrap()).collect();
+    assert_eq!(texts, ["Buy stamps", "Pick up prints"]);
+    assert_eq!(nearby[0]["location_name"], "Post office");
+    assert!(nearby[0]["distance_meters"].as_f64().unwrap() < 100.0);
+
+    let (status, _) = app.request(Method::GET, "/todos/nearby?lat=95&lng=0", Some(&token), None).await;
+    assert_eq!(status, StatusCode::UNPROCESSABLE_ENTITY);
+    let (status, _) = app.request(Method::POST, "/todos", Some(&token), Some(json!({ "text": "Half", "latitude": 51.5 }))).await;
+    assert_eq!(status, StatusCode::UNPROCESSABLE_ENTITY);
+}
+
+#[tokio::test]
+async fn categorizes_new_todos_by_rule() {
+    let app = app("rules").await;
+    let token = app.register("alice").await;
+    let (status, _) = app.request(Method::POST, "/todos", Some(&token), Some(json!({ "text": "Pay invoice for March" }))).await;
+    assert_eq!(status, StatusCode::CREATED);
+
+    let rule = json!({ "name": "Invoices", "if": { "text_contains": "INVOICE" }, "then": { "category": "Finance", "priority": "high", "tags": ["billing"] } });
+    let (status, preview) = app.request(Method::POST, "/rules/preview", Some(&token), Some(rule.clone())).await;
+    assert_eq!(status, StatusCode::OK);
+    assert_eq!(preview.as_array().unwrap().len(), 1);
+    assert_eq!(preview[0]["category"], "Finance");
+    assert!(app.todos(&token).await[0]["category"].is_null());
+
+    let (status, _) = app.request(Method::POST, "/rules", Some(&token), Some(json!({ "name": "Empty", "if": { "text_contains": "x" }, "then": {} }))).await;
+    assert_eq!(status, StatusCode::UNPROCESSABLE_ENTITY);
+    let (status, created) = app.request(Method::POST, "/rules", Some(&token), Some(rule)).await;
+    assert_eq!(status, StatusCode::CREATED);
+
+    let new_todo = json!({ "text": "Send invoice to Acme", "priority": "low", "tags": ["acme"] });
+    let (_, body) = app.request(Method::POST, "/todos", Some(&token), Some(new_todo)).await;
+    let (_, todo) = app.request(Method::GET, &format!("/todos/{}", body["id"].as_str().unwrap()), Some(&token), None).await;
+    assert_eq!(todo["category"], "Finance");
+    assert_eq!(todo["priority"], "low");
+    assert_eq!(todo["tags"], json!(["acme", "billing"]));
+
+    let uri = format!("/rules/{}", created["id"].as_str().unwrap());
+    let (status, _) = app.request(Method::DELETE, &uri, Some(&token), None).await;
+    assert_eq!(status, StatusCode::NO_CONTENT);
+    let (_, rules) = app.request(Method::GET, "/rules", Some(&token), None).await;
+    assert_eq!(rules, json!([]));
+}
+
+#[tokio::test]
+async fn infers_due_dates_from_the_text() {
+    let app = app("infer-due").await;
+    let token = app.register("alice").await;
+
+    let (status, body) = app.request(Method::POST, "/todos", Some(&token), Some(json!({ "text": "Send the deck to Sam by next Tuesday at 3pm" }))).await;
+    assert_eq!(status, StatusCode::CREATED);
+    assert_eq!(body["due_date_inferred"], true);
+    let uri = format!("/todos/{}", body["id"].as_str().unwrap());
+    let (_, todo) = app.request(Method::GET, &uri, Some(&token), None).await;
+    let due: chrono::DateTime<chrono::Utc> = todo["due_date"].as_str().unwrap().parse().unwrap();
+    assert_eq!(due.format("%H:%M").to_string(), "15:00");
+
+    // Undo
+    let update = json!({ "due": "", "expected_version": todo["version"] });
+    let (status, todo) = app.request(Method::PATCH, &uri, Some(&token), Some(update)).await;
+    assert_eq!(status, StatusCode::OK);
+    assert!(todo["due_date"].is_null());
+
+    for text in ["Prepare Friday standup notes", "Water the plants"] {
+        let (_, body) = app.request(Method::POST, "/todos", Some(&token), Some(json!({ "text": text }))).await;
+        assert_eq!(body["due_date_inferred"], false, "{}", text);
+    }
+    let new_todo = json!({ "text": "Pay rent by March 3", "due_date": "2030-01-01T00:00:00Z" });
+    let (_, body) = app.request(Method::POST, "/todos", Some(&token), Some(new_todo)).await;
+    assert_eq!(body["due_date_inferred"], false);
+}
+
+#[tokio::test]
+async fn manages_categories() {
+    let app = app("categories").await;
+    let alice = app.register("alice").await;
+    let bob = app.register("bob").await;
+    app.request(Method::POST, "/todos", Some(&alice), Some(json!({ "text": "File taxes", "category": "Finance" }))).await;
+    app.request(Method::POST, "/todos", Some(&bob), Some(json!({ "text": "Secret", "category": "Bob's plans" }))).await;
+
+    let (status, categories) = app.request(Method::GET, "/categories", Some(&alice), None).await;
+    assert_eq!(status, StatusCode::OK);
+    assert_eq!(categories.as_array().unwrap().len(), 1);
+    assert_eq!(categories[0]["name"], "Finance");
+
+    let (status, home) = app.request(Method::POST, "/categories", Some(&alice), Some(json!({ "name": "Home", "color": "green", "icon": "🏠" }))).await;
+    assert_eq!(status, StatusCode::CREATED);
+    assert_eq!(home["position"], 1);
+    let (status, _) = app.request(Method::POST, "/categories", Some(&alice), Some(json!({ "name": "Home" }))).await;
+    assert_eq!(status, StatusCode::CONFLICT);
+
+    let finance = format!("/categories/{}", categories[0]["id"].as_str().unwrap());
+    let (status, renamed) = app.request(Method::PATCH, &finance, Some(&alice), Some(json!({ "name": "Money" }))).await;
+    assert_eq!(status, StatusCode::OK);
+    assert_eq!(renamed["name"], "Money");
+    assert_eq!(app.todos(&alice).await[0]["category"], "Money");
+
+    let order = json!({ "ids": [home["id"]] });
+    let (_, categories) = app.request(Method::PUT, "/categories/order", Some(&alice), Some(order)).await;
+    let names: Vec<&str> = categories.as_array().unwrap().iter().map(|category| category["name"].as_str().unwrap()).collect();
+    assert_eq!(names, ["Home", "Money"]);
+
+    let (status, _) = app.request(Method::DELETE, &finance, Some(&bob), None).await;
+    assert_eq!(status, StatusCode::NOT_FOUND);
+    let (status, _) = app.request(Method::DELETE, &finance, Some(&alice), None).await;
+    assert_eq!(status, StatusCode::NO_CONTENT);
+    assert!(app.todos(&alice).await[0]["category"].is_null());
+
+    // The todos the rename and the delete changed are in the event log
+    let (_, log) = app.request(Method::GET, "/events", Some(&alice), None).await;
+    let updates: Vec<&Value> = log["events"].as_array().unwrap().iter().filter(|event| event["event"] == "todo_updated").map(|event| &event["data"]["todo"]["category"]).collect();
+    assert_eq!(updates, [&json!("Money"), &Value::Null]);
+}
+
+#[tokio::test]
+async fn autocompletes_tags_and_categories() {
+    let app = app("autocomplete").await;
+    let token = app.register("alice").await;
+    let batch = json!([
+        { "text": "Run", "category": "Workout", "tags": ["work", "outdoors"] },
+        { "text": "Plan sprint", "category": "Work", "tags": ["work"] },
+        { "text": "Review PR", "category": "Work", "tags": ["Workflow", "work"] }
+    ]);
+    app.request(Method::POST, "/todos/batch", Some(&token), Some(batch)).await;
+
+    let (status, tags) = app.request(Method::GET, "/autocomplete?q=WOR&kind=tag", Some(&token), None).await;
+    assert_eq!(status, StatusCode::OK);
+    assert_eq!(tags, json!(["work", "Workflow"]));
+    let (_, categories) = app.request(Method::GET, "/autocomplete?q=w&kind=category&limit=1", Some(&token), None).await;
+    assert_eq!(categories, json!(["Work"]));
+
+    // New todos show up straight away
+    app.request(Method::POST, "/todos", Some(&token), Some(json!({ "text": "Hike", "tags": ["outside"] }))).await;
+    let (_, tags) = app.request(Method::GET, "/autocomplete?q=out&kind=tag", Some(&token), None).await;
+    assert_eq!(tags, json!(["outdoors", "outside"]));
+
+    let other = app.register("bob").await;
+    let (_, tags) = app.request(Method::GET, "/autocomplete?q=&kind=tag", Some(&other), None).await;
+    assert_eq!(tags, json!([]));
+    let (status, _) = app.request(Method::GET, "/autocomplete?q=w&kind=color", Some(&token), None).await;
+    assert_eq!(status, StatusCode::BAD_REQUEST);
+}
+
+#[tokio::test]
+async fn awards_points_for_completions() {
+    let app = app("gamification").await;
+    let token = app.register("alice").await;
+    let batch = json!([{ "text": "Ship it", "priority": "high" }, { "text": "Tidy up" }]);
+    let (_, body) = app.request(Method::POST, "/todos/batch", Some(&token), Some(batch)).await;
+    let ids: Vec<&str> = body["ids"].as_array().unwrap().iter().map(|id| id.as_str().unwrap()).collect();
+
+    let (_, summary) = app.request(Method::GET, "/gamification/me", Some(&token), None).await;
+    assert_eq!(summary["points"], 0);
+    assert_eq!(summary["current_streak"], 0);
+
+    for id in &ids {
+        app.request(Method::POST, &format!("/toggle/{}", id), Some(&token), None).await;
+    }
+    // Reopening and completing again doesn't pay twice
+    app.request(Method::POST, &format!("/toggle/{}", ids[0]), Some(&token), None).await;
+    app.request(Method::POST, &format!("/toggle/{}", ids[0]), Some(&token), None).await;
+
+    let (status, summary) = app.request(Method::GET, "/gamification/me", Some(&token), None).await;
+    assert_eq!(status, StatusCode::OK);
+    assert_eq!(summary["points"], 6);
+    assert_eq!(summary["points_today"], 6);
+    assert_eq!(summary["completed"], 2);
+    assert_eq!(summary["current_streak"], 1);
+    assert_eq!(summary["longest_streak"], 1);
+}
+
+#[tokio::test]
+async fn unlocks_achievements() {
+    let app = app("achievements").await;
+    let token = app.register("alice").await;
+    let batch = json!([{ "text": "Write report" }, { "text": "Send report" }]);
+    let (_, body) = app.request(Method::POST, "/todos/batch", Some(&token), Some(batch)).await;
+    let ids: Vec<&str> = body["ids"].as_array().unwrap().iter().map(|id| id.as_str().unwrap()).collect();
+
+    let unlocked = |achievements: &Value| -> Vec<String> {
+        achievements.as_array().unwrap().iter().filter(|a| !a["unlocked_at"].is_null()).map(|a| a["id"].as_str().unwrap().to_string()).collect()
+    };
+    let (status, achievements) = app.request(Method::GET, "/achievements", Some(&token), None).await;
+    assert_eq!(status, StatusCode::OK);
+    assert!(achievements.as_array().unwrap().iter().any(|a| a["id"] == "completed_100"));
+    assert!(unlocked(&achievements).is_empty());
+
+    app.request(Method::POST, &format!("/toggle/{}", ids[0]), Some(&token), None).await;
+    let (_, achievements) = app.request(Method::GET, "/achievements", Some(&token), None).await;
+    assert_eq!(unlocked(&achievements), ["first_completion"]);
+
+    // Completing the last open todo empties the inbox
+    app.request(Method::POST, &format!("/toggle/{}", ids[1]), Some(&token), None).await;
+    let (_, achievements) = app.request(Method::GET, "/achievements", Some(&token), None).await;
+    assert_eq!(unlocked(&achievements), ["first_completion", "inbox_zero"]);
+}
+
+#[tokio::test]
+async fn ranks_workspace_members() {
+    let app = app("leaderboard").await;
+    let alice = app.register("alice").await;
+    let bob = app.register("bob").await;
+    let carol = app.register("carol").await;
+    let (_, workspace) = app.request(Method::POST, "/workspaces", Some(&alice), Some(json!({ "name": "Team" }))).await;
+    let id = workspace["id"].as_str().unwrap();
+    for member in ["bob", "carol"] {
+        let (status, _) = app.request(Method::POST, &format!("/workspaces/{}/members", id), Some(&alice), Some(json!({ "username": member }))).await;
+        assert_eq!(status, StatusCode::CREATED);
+    }
+
+    // Alice completes one high priority todo, Bob two low ones, Carol one but opts out
+    let work = [(&alice, vec!["high"]), (&bob, vec!["low", "low"]), (&carol, vec!["high"])];
+    for (token, priorities) in work {
+        let (_, switched) = app.request(Method::POST, "/workspaces/switch", Some(token), Some(json!({ "workspace_id": id }))).await;
+        let token = switched["token"].as_str().unwrap();
+        for priority in priorities {
+            let (_, todo) = app.request(Method::POST, "/todos", Some(token), Some(json!({ "text": "Work", "priority": priority }))).await;
+            app.request(Method::POST, &format!("/toggle/{}", todo["id"].as_str().unwrap()), Some(token), None).await;
+        }
+    }
+    app.request(Method::PUT, "/settings", Some(&carol), Some(json!({ "leaderboard": false }))).await;
+
+    let ranking = |board: &Value| -> Vec<(String, u64)> {
+        board["entries"].as_array().unwrap().iter().map(|e| (e["username"].as_str().unwrap().to_string(), e["rank"].as_u64().unwrap())).collect()
+    };
+    let (status, board) = app.request(Method::GET, &format!("/workspaces/{}/leaderboard", id), Some(&bob), None).await;
+    assert_eq!(status, StatusCode::OK);
+    assert_eq!(ranking(&board), [("alice".to_string(), 1), ("bob".to_string(), 2)]);
+    assert_eq!(board["entries"][0]["points"], 5);
+
+    let (_, board) = app.request(Method::GET, &format!("/workspaces/{}/leaderboard?period=all&by=completions", id), Some(&bob), None).await;
+    assert_eq!(ranking(&board), [("bob".to_string(), 1), ("alice".to_string(), 2)]);
+    assert!(board["since"].is_null());
+
+    let dave = app.register("dave").await;
+    let (status, _) = app.request(Method::GET, &format!("/workspaces/{}/leaderboard", id), Some(&dave), None).await;
+    assert_eq!(status, StatusCode::NOT_FOUND);
+}
+
+#[tokio::test]
+async fn plans_my_day() {
+    let app = app("myday").await;
+    let token = app.register("alice").await;
+    let batch = json!([{ "text": "Water the plants" }, { "text": "Call the bank" }]);
+    let (_, body) = app.request(Method::POST, "/todos/batch", Some(&token), Some(batch)).await;
+    let ids: Vec<&str> = body["ids"].as_array().unwrap().iter().map(|id| id.as_str().unwrap()).collect();
+
+    for id in &ids {
+        let (status, _) = app.request(Method::POST, &format!("/myday/today/todos/{}", id), Some(&token), None).await;
+        assert_eq!(status, StatusCode::CREATED);
+    }
+    let (status, _) = app.request(Method::POST, &format!("/myday/today/todos/{}", ids[0]), Some(&token), None).await;
+    assert_eq!(status, StatusCode::OK);
+    app.request(Method::POST, &format!("/toggle/{}", ids[0]), Some(&token), None).await;
+
+    // Open todos first
+    let (status, plan) = app.request(Method::GET, "/myday/today", Some(&token), None).await;
+    assert_eq!(status, StatusCode::OK);
+    let texts: Vec<&str> = plan.as_array().unwrap().iter().map(|todo| todo["text"].as_str().unwrap()).collect();
+    assert_eq!(texts, ["Call the bank", "Water the plants"]);
+    let today = chrono::Utc::now().date_naive().to_string();
+    assert_eq!(plan[0]["planned_on"], today.as_str());
+
+    let (status, _) = app.request(Method::DELETE, &format!("/myday/{}/todos/{}", today, ids[1]), Some(&token), None).await;
+    assert_eq!(status, StatusCode::NO_CONTENT);
+    let (_, plan) = app.request(Method::GET, &format!("/myday/{}", today), Some(&token), None).await;
+    assert_eq!(plan.as_array().unwrap().len(), 1);
+
+    let (status, _) = app.request(Method::POST, &format!("/myday/2000-01-01/todos/{}", ids[1]), Some(&token), None).await;
+    assert_eq!(status, StatusCode::UNPROCESSABLE_ENTITY);
+    let (status, _) = app.request(Method::GET, "/myday/someday", Some(&token), None).await;
+    assert_eq!(status, StatusCode::BAD_REQUEST);
+    let other = app.register("bob").await;
+    let (status, _) = app.request(Method::POST, &format!("/myday/today/todos/{}", ids[1]), Some(&other), None).await;
+    assert_eq!(status, StatusCode::NOT_FOUND);
+}
+
+#[tokio::test]
+async fn syncs_changes_both_ways() {
+    let app = app("sync").await;
+    let token = app.register("alice").await;
+    let batch = json!([{ "text": "Water the plants" }, { "text": "Call the bank" }]);
+    let (_, body) = app.request(Method::POST, "/todos/batch", Some(&token), Some(batch)).await;
+    let ids: Vec<String> = body["ids"].as_array().unwrap().iter().map(|id| id.as_str().unwrap().to_string()).collect();
+
+    let (status, full) = app.request(Method::GET, "/sync", Some(&token), None).await;
+    assert_eq!(status, StatusCode::OK);
+    assert_eq!(full["todos"].as_array().unwrap().len(), 2);
+    assert_eq!(full["has_more"], false);
+    let cursor = full["cursor"].as_str().unwrap().to_string();
+
+    app.request(Method::PATCH, &format!("/todos/{}", ids[0]), Some(&token), Some(json!({ "text": "Water the ferns", "expected_version": 1 }))).await;
+    let (_, delta) = app.request(Method::GET, &format!("/sync?since={}", cursor), Some(&token), None).await;
+    let todos = delta["todos"].as_array().unwrap();
+    assert_eq!(todos.len(), 1);
+    assert_eq!(todos[0]["text"], "Water the ferns");
+
+    let changes = json!({ "changes": [
+        { "client_id": "tmp-1", "text": "Buy stamps", "completed": true },
+        { "id": ids[1], "version": 1, "text": "Call the bank back" },
+        { "id": ids[0], "version": 1, "updated_at": "2000-01-01T00:00:00Z", "text": "Water the cactus" },
+        { "id": "missing", "version": 1, "text": "Nothing" },
+    ] });
+    let (status, body) = app.request(Method::POST, "/sync", Some(&token), Some(changes.clone())).await;
+    assert_eq!(status, StatusCode::OK);
+    let results = body["results"].as_array().unwrap();
+    assert_eq!(results[0]["outcome"], "created");
+    assert_eq!(results[0]["todo"]["completed"], true);
+    assert_eq!(results[1]["outcome"], "applied");
+    assert_eq!(results[1]["todo"]["text"], "Call the bank back");
+    assert_eq!(results[2]["outcome"], "conflict");
+    assert_eq!(results[2]["todo"]["text"], "Water the ferns");
+    assert_eq!(results[3]["outcome"], "rejected");
+    assert_eq!(results[3]["error"], 404);
+
+    // A retried sync doesn't create the todo again
+    let (_, body) = app.request(Method::POST, "/sync", Some(&token), Some(json!({ "changes": [changes["changes"][0]] }))).await;
+    assert_eq!(body["results"][0]["id"], results[0]["id"]);
+    assert_eq!(app.todos(&token).await.len(), 3);
+
+    let (_, delta) = app.request(Method::GET, &format!("/sync?since={}&limit=1", delta["cursor"].as_str().unwrap()), Some(&token), None).await;
+    assert_eq!(delta["todos"].as_array().unwrap().len(), 1);
+    assert_eq!(delta["has_more"], true);
+    let (status, _) = app.request(Method::GET, "/sync?since=nonsense", Some(&token), None).await;
+    assert_eq!(status, StatusCode::BAD_REQUEST);
+    let other = app.register("bob").await;
+    let (_, body) = app.request(Method::GET, "/sync", Some(&other), None).await;
+    assert!(body["todos"].as_array().unwrap().is_empty());
+}
+
+#[tokio::test]
+async fn keeps_tombstones_of_deleted_records() {
+    let app = app("tombstones").await;
+    let token = app.register("alice").await;
+    let (_, category) = app.request(Method::POST, "/categories", Some(&token), Some(json!({ "name": "Home" }))).await;
+    let (_, filter) = app.request(Method::POST, "/filters", Some(&token), Some(json!({ "name": "Open", "filter": { "completed": false } }))).await;
+    app.request(Method::DELETE, &format!("/categories/{}", category["id"].as_str().unwrap()), Some(&token), None).await;
+    app.request(Method::DELETE, &format!("/filters/{}", filter["id"].as_str().unwrap()), Some(&token), None).await;
+
+    let (status, export) = app.request(Method::GET, "/auth/me/export", Some(&token), None).await;
+    assert_eq!(status, StatusCode::OK);
+    let deleted: Vec<(&str, &str)> = export["deleted"].as_array().unwrap().iter().map(|item| (item["entity_type"].as_str().unwrap(), item["id"].as_str().unwrap())).collect();
+    assert_eq!(deleted.len(), 2);
+    assert!(deleted.contains(&("category", category["id"].as_str().unwrap())));
+    assert!(deleted.contains(&("saved_filter", filter["id"].as_str().unwrap())));
+}
+
+#[tokio::test]
+async fn completes_todos_matching_a_filter() {
+    let app = app("bulk-complete").await;
+    let token = app.register("alice").await;
+    let yesterday = (chrono::Utc::now() - chrono::Duration::days(1)).to_rfc3339();
+    let next_week = (chrono::Utc::now() + chrono::Duration::days(7)).to_rfc3339();
+    let batch = json!([
+        { "text": "Post the parcel", "category": "errands", "due_date": yesterday },
+        { "text": "Return the library books", "category": "errands", "due_date": yesterday },
+        { "text": "Renew the passport", "category": "errands", "due_date": next_week },
+        { "text": "Pay the rent", "category": "bills", "due_date": yesterday },
+    ]);
+    let (_, body) = app.request(Method::POST, "/todos/batch", Some(&token), Some(batch)).await;
+    let ids: Vec<&str> = body["ids"].as_array().unwrap().iter().map(|id| id.as_str().unwrap()).collect();
+    // Blocked until the passport is renewed
+    app.request(Method::POST, &format!("/todos/{}/blockers", ids[1]), Some(&token), Some(json!({ "blocker_id": ids[2] }))).await;
+
+    let (status, body) = app.request(Method::POST, "/todos/complete?category=errands&due_before=today", Some(&token), None).await;
+    assert_eq!(status, StatusCode::OK);
+    assert_eq!(body["completed"], 1);
+    assert_eq!(body["ids"], json!([ids[0]]));
+    let open = app.todos(&token).await.iter().filter(|todo| todo["completed"] == false).count();
+    assert_eq!(open, 3);
+
+    let (status, _) = app.request(Method::POST, "/todos/complete", Some(&token), None).await;
+    assert_eq!(status, StatusCode::BAD_REQUEST);
+    let (status, _) = app.request(Method::POST, "/todos/complete?due_before=someday", Some(&token), None).await;
+    assert_eq!(status, StatusCode::BAD_REQUEST);
+}
+
+#[tokio::test]
+async fn records_completions_as_occurrences() {
+    let app = app("occurrences").await;
+    let token = app.register("alice").await;
+    let due = "2030-01-01T09:00:00Z";
+    let (_, created) = app.request(Method::POST, "/todos", Some(&token), Some(json!({ "text": "Water the plants", "due_date": due }))).await;
+    let id = created["id"].as_str().unwrap();
+    let occurrences = format!("/todos/{}/occurrences", id);
+
+    app.request(Method::POST, &format!("/toggle/{}", id), Some(&token), None).await;
+    let (status, body) = app.request(Method::GET, &occurrences, Some(&token), None).await;
+    assert_eq!(status, StatusCode::OK);
+    assert_eq!(body.as_array().unwrap().len(), 1);
+    assert_eq!(body[0]["due_date"], due);
+
+    // Reopening takes the completion back
+    app.request(Method::POST, &format!("/toggle/{}", id), Some(&token), None).await;
+    let (_, body) = app.request(Method::GET, &occurrences, Some(&token), None).await;
+    assert!(body.as_array().unwrap().is_empty());
+    app.request(Method::POST, &format!("/toggle/{}", id), Some(&token), None).await;
+    let (_, body) = app.request(Method::GET, &occurrences, Some(&token), None).await;
+    assert_eq!(body.as_array().unwrap().len(), 1);
+
+    let other = app.register("bob").await;
+    let (status, _) = app.request(Method::GET, &occurrences, Some(&other), None).await;
+    assert_eq!(status, StatusCode::NOT_FOUND);
+}
+
+#[tokio::test]
+async fn reports_rate_limits_in_headers() {
+    let app = app("rate-limit-headers").await;
+    let login = || {
+        Request::builder()
+            .method(Method::POST)
+            .uri("/auth/login")
+            .header(header::CONTENT_TYPE, "application/json")
+            .extension(axum::extract::ConnectInfo(std::net::SocketAddr::from(([192, 0, 2, 1], 4000))))
+            .body(Body::from(json!({ "username": "nobody", "password": PASSWORD }).to_string()))
+            .unwrap()
+    };
+
+    let response = app.router.clone().oneshot(login()).await.unwrap();
+    let headers = response.headers();
+    assert_eq!(headers["x-ratelimit-limit"], "20");
+    assert_eq!(headers["x-ratelimit-remaining"], "19");
+    let reset: i64 = headers["x-ratelimit-reset"].to_str().unwrap().parse().unwrap();
+    assert!(reset > chrono::Utc::now().timestamp() && reset <= chrono::Utc::now().timestamp() + 60);
+
+    for _ in 0..20 {
+        app.router.clone().oneshot(login()).await.unwrap();
+    }
+    let response = app.router.clone().oneshot(login()).await.unwrap();
+    assert_eq!(response.status(), StatusCode::TOO_MANY_REQUESTS);
+    assert_eq!(response.headers()["x-ratelimit-remaining"], "0");
+    assert!(response.headers().contains_key(header::RETRY_AFTER));
+}
+
+#[tokio::test]
+async fn addresses_todos_by_public_id() {
+    let app = app("public-ids").await;
+    let token = app.register("alice").await;
+
+    let (status, created) = app.request(Method::POST, "/todos", Some(&token), Some(json!({ "text": "Renew the lease" }))).await;
+    assert_eq!(status, StatusCode::CREATED);
+    let public_id = created["public_id"].as_str().unwrap();
+    assert!(public_id.starts_with("td_") && public_id.len() == 19, "{}", public_id);
+
+    let (status, todo) = app.request(Method::GET, &format!("/todos/{}", public_id), Some(&token), None).await;
+    assert_eq!(status, StatusCode::OK);
+    assert_eq!(todo["id"], created["id"]);
+    assert_eq!(todo["public_id"], public_id);
+
+    let update = json!({ "text": "Renew the flat lease", "expected_version": todo["version"] });
+    let (status, todo) = app.request(Method::PATCH, &format!("/todos/{}", public_id), Some(&token), Some(update)).await;
+    assert_eq!(status, StatusCode::OK);
+    assert_eq!(todo["text"], "Renew the flat lease");
+
+    // Internal ids keep working for now
+    let (status, _) = app.request(Method::GET, &format!("/todos/{}", created["id"].as_str().unwrap()), Some(&token), None).await;
+    assert_eq!(status, StatusCode::OK);
+    let (status, _) = app.request(Method::GET, "/todos/td_0000000000000000", Some(&token), None).await;
+    assert_eq!(status, StatusCode::NOT_FOUND);
+
+    // Other users can't reach it by public id either
+    let bob = app.register("bob").await;
+    let (status, _) = app.request(Method::GET, &format!("/todos/{}", public_id), Some(&bob), None).await;
+    assert_eq!(status, StatusCode::NOT_FOUND);
+}
+
+#[tokio::test]
+async fn reports_feature_flags_per_user() {
+    let app = app("flags").await;
+    let alice = app.register("alice").await;
+    let bob = app.register("bob").await;
+
+    let (status, flags) = app.request(Method::GET, "/flags", Some(&alice), None).await;
+    assert_eq!(status, StatusCode::OK);
+    assert_eq!(flags, json!({ "llm_parser": false }));
+
+    // What `todo-app flag enable llm_parser alice` stores
+    let pool = sqlx::SqlitePool::connect(&format!("sqlite:{}", app.path.display())).await.unwrap();
+    sqlx::query("INSERT INTO feature_flags (name, user_id, enabled) SELECT 'llm_parser', id, TRUE FROM users WHERE username = 'alice'")
+        .execute(&pool)
+        .await
+        .unwrap();
+    pool.close().await;
+
+    let (_, flags) = app.request(Method::GET, "/flags", Some(&alice), None).await;
+    assert_eq!(flags, json!({ "llm_parser": true }));
+    let (_, flags) = app.request(Method::GET, "/flags", Some(&bob), None).await;
+    assert_eq!(flags, json!({ "llm_parser": false }));
+}
+
+#[tokio::test]
+async fn searches_todo_text_ignoring_case_and_accents() {
+    let app = app("search").await;
+    let token = app.register("alice").await;
+    for text in ["Café with Zoë", "Straße fegen", "Buy milk"] {
+        let (status, _) = app.request(Method::POST, "/todos", Some(&token), Some(json!({ "text": text }))).await;
+        assert_eq!(status, StatusCode::CREATED);
+    }
+
+    for (query, expected) in [("cafe", "Café with Zoë"), ("ZOE", "Café with Zoë"), ("CAF%C3%89", "Café with Zoë"), ("strasse", "Straße fegen")] {
+        let (status, todos) = app.request(Method::GET, &format!("/todos?text={}", query), Some(&token), None).await;
+        assert_eq!(status, StatusCode::OK);
+        let texts: Vec<&str> = todos.as_array().unwrap().iter().map(|todo| todo["text"].as_str().unwrap()).collect();
+        assert_eq!(texts, [expected], "?text={}", query);
+    }
+
+    // Renaming keeps the search text in step
+    let (_, todos) = app.request(Method::GET, "/todos?text=milk", Some(&token), None).await;
+    let update = json!({ "text": "Buy crème fraîche", "expected_version": todos[0]["version"] });
+    let (status, _) = app.request(Method::PATCH, &format!("/todos/{}", todos[0]["id"].as_str().unwrap()), Some(&token), Some(update)).await;
+    assert_eq!(status, StatusCode::OK);
+    let (_, todos) = app.request(Method::GET, "/todos?text=creme+fraiche", Some(&token), None).await;
+    assert_eq!(todos.as_array().unwrap().len(), 1);
+    let (_, todos) = app.request(Method::GET, "/todos?text=milk", Some(&token), None).await;
+    assert_eq!(todos, json!([]));
+}
+
+#[tokio::test]
+async fn filters_todos_due_within_days() {
+    let app = app("due-within").await;
+    let token = app.register("alice").await;
+    for (text, due) in [("Water plants", "tomorrow"), ("Renew passport", "in 30 days")] {
+        let (status, _) = app.request(Method::POST, "/todos", Some(&token), Some(json!({ "text": text, "due": due }))).await;
+        assert_eq!(status, StatusCode::CREATED);
+    }
+
+    async fn filter_todos(app: &TestApp, token: &str, days: u32) -> Value {
+        let filter = json!({ "name": format!("Due within {}", days), "filter": { "due_within_days": days } });
+        let (status, filter) = app.request(Method::POST, "/filters", Some(token), Some(filter)).await;
+        assert_eq!(status, StatusCode::CREATED);
+        let (status, todos) = app.request(Method::GET, &format!("/filters/{}/todos", filter["id"].as_str().unwrap()), Some(token), None).await;
+        assert_eq!(status, StatusCode::OK);
+        todos
+    }
+    assert_eq!(filter_todos(&app, &token, 1).await.as_array().unwrap().len(), 1);
+    // Counts past chrono's dates look as far ahead as /todos/upcoming does
+    assert_eq!(filter_todos(&app, &token, u32::MAX).await.as_array().unwrap().len(), 2);
+    let (status, todos) = app.request(Method::GET, "/todos/upcoming?days=4294967295", Some(&token), None).await;
+    assert_eq!(status, StatusCode::OK);
+    assert_eq!(todos.as_array().unwrap().len(), 2);
+    let (status, completed) = app.request(Method::POST, "/todos/complete?due_within_days=4294967295", Some(&token), None).await;
+    assert_eq!(status, StatusCode::OK);
+    assert_eq!(completed["completed"], 2);
+}
+
+#[tokio::test]
+async fn includes_link_previews_in_todos() {
+    let app = app("link-previews").await;
+    let token = app.register("alice").await;
+
+    // What the background fetch stores; already fresh, so nothing is fetched here
+    let pool = sqlx::SqlitePool::connect(&format!("sqlite:{}", app.path.display())).await.unwrap();
+    sqlx::query("INSERT INTO link_previews (url, title, favicon_url, fetched_at) VALUES ('https://example.com/recipe', 'Lemon tart', 'https://example.com/icon.png', ?)")
+        .bind(chrono::Utc::now())
+        .execute(&pool)
+        .await
+        .unwrap();
+    pool.close().await;
+
+    let new_todo = json!({ "text": "Bake (https://example.com/recipe), then https://example.com/unfetched" });
+    let (status, created) = app.request(Method::POST, "/todos", Some(&token), Some(new_todo)).await;
+    assert_eq!(status, StatusCode::CREATED);
+    let preview = json!([{ "url": "https://example.com/recipe", "title": "Lemon tart", "favicon_url": "https://example.com/icon.png" }]);
+
+    let (_, todo) = app.request(Method::GET, &format!("/todos/{}", created["public_id"].as_str().unwrap()), Some(&token), None).await;
+    assert_eq!(todo["links"], preview);
+    let todos = app.todos(&token).await;
+    assert_eq!(todos[0]["links"], preview);
+}
+
+#[tokio::test]
+async fn signs_in_devices_with_an_approved_user_code() {
+    let app = app("device").await;
+    let alice = app.register("alice").await;
+
+    let (status, code) = app.request(Method::POST, "/auth/device/code", None, None).await;
+    assert_eq!(status, StatusCode::OK);
+    let device_code = json!({ "device_code": code["device_code"] });
+    let user_code = code["user_code"].as_str().unwrap();
+    assert_eq!(user_code.len(), 9);
+    assert!(code["verification_uri_complete"].as_str().unwrap().ends_with(&format!("/device?user_code={}", user_code)));
+
+    let (status, body) = app.request(Method::POST, "/auth/device/token", None, Some(device_code.clone())).await;
+    assert_eq!((status, &body["error"]), (StatusCode::BAD_REQUEST, &json!("authorization_pending")));
+    assert!(body["request_id"].is_string());
+    let (_, body) = app.request(Method::POST, "/auth/device/token", None, Some(device_code.clone())).await;
+    assert_eq!(body["error"], "slow_down");
+
+    // Typed without the dash and in lower case
+    let approval = json!({ "user_code": user_code.replace('-', "").to_lowercase(), "approve": true });
+    let (status, _) = app.request(Method::POST, "/auth/device/approve", None, Some(approval.clone())).await;
+    assert_eq!(status, StatusCode::UNAUTHORIZED);
+    let (status, _) = app.request(Method::POST, "/auth/device/approve", Some(&alice), Some(approval.clone())).await;
+    assert_eq!(status, StatusCode::NO_CONTENT);
+    let (status, _) = app.request(Method::POST, "/auth/device/approve", Some(&alice), Some(approval)).await;
+    assert_eq!(status, StatusCode::NOT_FOUND);
+
+    let (status, body) = app.request(Method::POST, "/auth/device/token", None, Some(device_code.clone())).await;
+    assert_eq!(status, StatusCode::OK);
+    let (status, me) = app.request(Method::GET, "/auth/me", body["token"].as_str(), None).await;
+    assert_eq!(status, StatusCode::OK);
+    assert_eq!(me["username"], "alice");
+    // Exchanged only once
+    let (_, body) = app.request(Method::POST, "/auth/device/token", None, Some(device_code)).await;
+    assert_eq!(body["error"], "invalid_grant");
+
+    let (_, code) = app.request(Method::POST, "/auth/device/code", None, None).await;
+    let denial = json!({ "user_code": code["user_code"], "approve": false });
+    let (status, _) = app.request(Method::POST, "/auth/device/approve", Some(&alice), Some(denial)).await;
+    assert_eq!(status, StatusCode::NO_CONTENT);
+    let (_, body) = app.request(Method::POST, "/auth/device/token", None, Some(json!({ "device_code": code["device_code"] }))).await;
+    assert_eq!(body["error"], "access_denied");
+}
+
+#[tokio::test]
+async fn returns_only_the_requested_fields() {
+    let app = app("fields").await;
+    let token = app.register("alice").await;
+    let new_todo = json!({ "text": "Buy milk", "category": "errands", "due_date": "2030-01-01T09:00:00Z" });
+    let (status, _) = app.request(Method::POST, "/todos", Some(&token), Some(new_todo)).await;
+    assert_eq!(status, StatusCode::CREATED);
+
+    let (status, todos) = app.request(Method::GET, "/todos?fields=text,%20completed,due_date,text", Some(&token), None).await;
+    assert_eq!(status, StatusCode::OK);
+    let todo = todos[0].as_object().unwrap();
+    let mut keys: Vec<&str> = todo.keys().map(String::as_str).collect();
+    keys.sort();
+    assert_eq!(keys, ["completed", "due_date", "id", "text"]);
+    assert_eq!(todo["text"], "Buy milk");
+
+    let (status, page) = app.request(Method::GET, "/todos?fields=category&limit=10", Some(&token), None).await;
+    assert_eq!(status, StatusCode::OK);
+    assert_eq!(page["items"][0].as_object().unwrap().len(), 2);
+    assert_eq!(page["items"][0]["category"], "errands");
+
+    let (status, _) = app.request(Method::GET, "/todos?fields=text,password_hash", Some(&token), None).await;
+    assert_eq!(status, StatusCode::BAD_REQUEST);
+}
+
+#[tokio::test]
+async fn answers_in_json_api_when_asked() {
+    let app = app("jsonapi").await;
+    let token = app.register("alice").await;
+    for text in ["Buy milk", "Call the plumber"] {
+        let (status, _) = app.request(Method::POST, "/todos", Some(&token), Some(json!({ "text": text }))).await;
+        assert_eq!(status, StatusCode::CREATED);
+    }
+
+    let get = |uri: &str| {
+        let request = Request::get(uri)
+            .header(header::AUTHORIZATION, format!("Bearer {}", token))
+            .header(header::ACCEPT, "application/vnd.api+json")
+            .body(Body::empty())
+            .unwrap();
+        let router = app.router.clone();
+        async move {
+            let response = router.oneshot(request).await.unwrap();
+            let status = response.status();
+            assert_eq!(response.headers()[header::CONTENT_TYPE], "application/vnd.api+json");
+            let bytes = axum::body::to_bytes(response.into_body(), usize::MAX).await.unwrap();
+            (status, serde_json::from_slice::<Value>(&bytes).unwrap())
+        }
+    };
+
+    let (status, document) = get("/todos?limit=1&fields[todos]=text").await;
+    assert_eq!(status, StatusCode::OK);
+    let todo = &document["data"][0];
+    assert_eq!(todo["type"], "todos");
+    assert_eq!(todo["attributes"], json!({ "text": "Call the plumber" }));
+    assert_eq!(todo["relationships"]["owner"]["data"]["type"], "users");
+    assert_eq!(todo["relationships"]["assignee"]["data"], Value::Null);
+    let next = document["links"]["next"].as_str().unwrap();
+    assert!(next.starts_with("/todos?limit=1&fields[todos]=text&after="), "{}", next);
+
+    let (_, document) = get(next).await;
+    assert_eq!(document["data"][0]["attributes"]["text"], "Buy milk");
+    assert_eq!(document["links"]["next"], Value::Null);
+
+    let (status, document) = get(todo["links"]["self"].as_str().unwrap()).await;
+    assert_eq!(status, StatusCode::OK);
+    assert_eq!(document["data"]["id"], todo["id"]);
+    assert_eq!(document["data"]["relationships"]["blockers"]["data"], json!([]));
+
+    let (status, document) = get("/todos/td_missing").await;
+    assert_eq!(status, StatusCode::NOT_FOUND);
+    assert_eq!(document["errors"][0]["status"], "404");
+}
+
+#[tokio::test]
+async fn links_todos_and_pages_to_what_clients_can_do_next() {
+    let app = app("hypermedia").await;
+    let token = app.register("alice").await;
+    for text in ["Buy milk", "Call the plumber"] {
+        let (status, _) = app.request(Method::POST, "/todos", Some(&token), Some(json!({ "text": text }))).await;
+        assert_eq!(status, StatusCode::CREATED);
+    }
+
+    let (status, page) = app.request(Method::GET, "/todos?limit=1", Some(&token), None).await;
+    assert_eq!(status, StatusCode::OK);
+    let todo = &page["items"][0];
+    let public_id = todo["public_id"].as_str().unwrap();
+    assert_eq!(todo["_links"]["self"], json!({ "href": format!("/todos/{}", public_id) }));
+    assert_eq!(todo["_links"]["toggle"], json!({ "href": format!("/toggle/{}", public_id), "method": "POST" }));
+    assert_eq!(page["_links"]["first"]["href"], "/todos?limit=1");
+
+    // Following the links works without knowing any URL patterns
+    let (status, next) = app.request(Method::GET, page["_links"]["next"]["href"].as_str().unwrap(), Some(&token), None).await;
+    assert_eq!(status, StatusCode::OK);
+    assert_eq!(next["items"][0]["text"], "Buy milk");
+    assert!(next["_links"].get("next").is_none());
+    let (status, _) = app.request(Method::POST, todo["_links"]["toggle"]["href"].as_str().unwrap(), Some(&token), None).await;
+    assert_eq!(status, StatusCode::OK);
+    let (status, detail) = app.request(Method::GET, todo["_links"]["self"]["href"].as_str().unwrap(), Some(&token), None).await;
+    assert_eq!(status, StatusCode::OK);
+    assert_eq!(detail["completed"], true);
+    assert_eq!(detail["_links"], todo["_links"]);
+}
+
+#[tokio::test]
+async fn delivers_events_through_the_outbox() {
+    let app = app("outbox").await;
+    let alice = app.register("alice").await;
+    let bob = app.register("bob").await;
+    let (_, workspace) = app.request(Method::POST, "/workspaces", Some(&alice), Some(json!({ "name": "Team" }))).await;
+    let id = workspace["id"].as_str().unwrap();
+    app.request(Method::POST, &format!("/workspaces/{}/members", id), Some(&alice), Some(json!({ "username": "bob" }))).await;
+    let (_, switched) = app.request(Method::POST, "/workspaces/switch", Some(&alice), Some(json!({ "workspace_id": id }))).await;
+    let alice = switched["token"].as_str().unwrap();
+    let (_, me) = app.request(Method::GET, "/auth/me", Some(&bob), None).await;
+    let bob_id = me["id"].as_str().unwrap();
+    // Added directly, since the API only takes public URLs; deliveries to it
+    // are refused, but logged all the same
+    let pool = sqlx::SqlitePool::connect(&format!("sqlite:{}", app.path.display())).await.unwrap();
+    sqlx::query("INSERT INTO webhooks (id, user_id, url, secret, events, created_at) VALUES ('hook', ?, 'http://127.0.0.1:9/hook', 'whsec_test', '[\"todo_assigned\"]', ?)")
+        .bind(bob_id)
+        .bind(chrono::Utc::now())
+        .execute(&pool)
+        .await
+        .unwrap();
+
+    let (_, todo) = app.request(Method::POST, "/todos", Some(alice), Some(json!({ "text": "Review the budget" }))).await;
+    let assign = format!("/todos/{}/assign", todo["id"].as_str().unwrap());
+    let (status, _) = app.request(Method::POST, &assign, Some(alice), Some(json!({ "assignee_id": bob_id }))).await;
+    assert_eq!(status, StatusCode::OK);
+
+    // Notifications are sent by the relay, in the background
+    async fn notifications(app: &TestApp, token: &str, expected: usize) -> usize {
+        let mut count = 0;
+        for _ in 0..50 {
+            let (_, body) = app.request(Method::GET, "/notifications", Some(token), None).await;
+            count = body.as_array().unwrap().len();
+            if count >= expected {
+                break;
+            }
+            tokio::time::sleep(Duration::from_millis(100)).await;
+        }
+        count
+    }
+    assert_eq!(notifications(&app, &bob, 1).await, 1);
+
+    let mut events: Vec<(String, bool)> = Vec::new();
+    for _ in 0..50 {
+        events = sqlx::query_as("SELECT event, dispatched_at IS NOT NULL FROM outbox ORDER BY id").fetch_all(&pool).await.unwrap();
+        if events.iter().all(|(_, dispatched)| *dispatched) {
+            break;
+        }
+        tokio::time::sleep(Duration::from_millis(100)).await;
+    }
+    let events: Vec<(&str, bool)> = events.iter().map(|(event, dispatched)| (event.as_str(), *dispatched)).collect();
+    assert_eq!(events, [("user_registered", true), ("user_registered", true), ("todo_created", true), ("todo_assigned", true)]);
+
+    // A relay that died mid-delivery left the assignment claimed; once the claim
+    // runs out, the next pass hands it out again
+    sqlx::query("UPDATE outbox SET dispatched_at = NULL, claimed_at = ? WHERE event = 'todo_assigned'")
+        .bind(chrono::Utc::now() - chrono::Duration::minutes(10))
+        .execute(&pool)
+        .await
+        .unwrap();
+    app.request(Method::POST, "/todos", Some(alice), Some(json!({ "text": "Book the room" }))).await;
+    assert_eq!(notifications(&app, &bob, 2).await, 2);
+
+    // Posted again as the same event, so the receiver can tell it's a repeat
+    let mut event_ids: Vec<String> = Vec::new();
+    for _ in 0..50 {
+        event_ids = sqlx::query_scalar("SELECT event_id FROM webhook_deliveries WHERE webhook_id = 'hook'").fetch_all(&pool).await.unwrap();
+        if event_ids.len() >= 2 {
+            break;
+        }
+        tokio::time::sleep(Duration::from_millis(100)).await;
+    }
+    assert_eq!(event_ids.len(), 2);
+    assert_eq!(event_ids[0], event_ids[1]);
+    pool.close().await;
+}
+
+#[tokio::test]
+async fn unassigns_todos_of_a_removed_member() {
+    let app = app("remove-member").await;
+    let alice = app.register("alice").await;
+    let bob = app.register("bob").await;
+    let (_, workspace) = app.request(Method::POST, "/workspaces", Some(&alice), Some(json!({ "name": "Team" }))).await;
+    let id = workspace["id"].as_str().unwrap();
+    app.request(Method::POST, &format!("/workspaces/{}/members", id), Some(&alice), Some(json!({ "username": "bob" }))).await;
+    let (_, switched) = app.request(Method::POST, "/workspaces/switch", Some(&alice), Some(json!({ "workspace_id": id }))).await;
+    let alice = switched["token"].as_str().unwrap();
+    let (_, me) = app.request(Method::GET, "/auth/me", Some(&bob), None).await;
+    let bob_id = me["id"].as_str().unwrap();
+
+    let (_, todo) = app.request(Method::POST, "/todos", Some(alice), Some(json!({ "text": "Order chairs" }))).await;
+    let (_, todo) = app.request(Method::POST, &format!("/todos/{}/assign", todo["id"].as_str().unwrap()), Some(alice), Some(json!({ "assignee_id": bob_id }))).await;
+    // Cached with bob as the assignee
+    let (_, todos) = app.request(Method::GET, "/todos", Some(alice), None).await;
+    assert_eq!(todos[0]["assignee_id"], bob_id);
+
+    let (status, _) = app.request(Method::DELETE, &format!("/workspaces/{}/members/{}", id, bob_id), Some(alice), None).await;
+    assert_eq!(status, StatusCode::NO_CONTENT);
+    let (_, todos) = app.request(Method::GET, "/todos", Some(alice), None).await;
+    assert_eq!(todos[0]["assignee_id"], Value::Null);
+    assert_eq!(todos[0]["version"], todo["version"].as_i64().unwrap() + 1);
+
+    let (_, log) = app.request(Method::GET, "/events", Some(alice), None).await;
+    let last = log["events"].as_array().unwrap().last().unwrap().clone();
+    assert_eq!(last["event"], "todo_assigned");
+    assert_eq!(last["data"]["todo"]["assignee_id"], Value::Null);
+}
+
+#[tokio::test]
+async fn posts_workspace_events_to_current_members_webhooks() {
+    let app = app("webhook-members").await;
+    let alice = app.register("alice").await;
+    let bob = app.register("bob").await;
+    let (_, workspace) = app.request(Method::POST, "/workspaces", Some(&alice), Some(json!({ "name": "Team" }))).await;
+    let id = workspace["id"].as_str().unwrap();
+    app.request(Method::POST, &format!("/workspaces/{}/members", id), Some(&alice), Some(json!({ "username": "bob" }))).await;
+    let mut tokens = Vec::new();
+    for token in [&alice, &bob] {
+        let (_, switched) = app.request(Method::POST, "/workspaces/switch", Some(token), Some(json!({ "workspace_id": id }))).await;
+        tokens.push(switched["token"].as_str().unwrap().to_string());
+    }
+    let (alice, bob) = (&tokens[0], &tokens[1]);
+
+    // Added directly, since the API only takes public URLs; deliveries to them
+    // are refused, but logged all the same
+    let pool = sqlx::SqlitePool::connect(&format!("sqlite:{}", app.path.display())).await.unwrap();
+    for (hook, token) in [("alice-hook", alice), ("bob-hook", bob)] {
+        let (_, me) = app.request(Method::GET, "/auth/me", Some(token), None).await;
+        sqlx::query("INSERT INTO webhooks (id, user_id, url, secret, events, created_at) VALUES (?, ?, 'http://127.0.0.1:9/hook', 'whsec_test', '[]', ?)")
+            .bind(hook)
+            .bind(me["id"].as_str().unwrap())
+            .bind(chrono::Utc::now())
+            .execute(&pool)
+            .await
+            .unwrap();
+    }
+    async fn deliveries(pool: &sqlx::SqlitePool, hook: &str, expected: i64) -> i64 {
+        let mut count = 0;
+        for _ in 0..50 {
+            count = sqlx::query_scalar("SELECT COUNT(*) FROM webhook_deliveries WHERE webhook_id = ?").bind(hook).fetch_one(pool).await.unwrap();
+            if count >= expected {
+                break;
+            }
+            tokio::time::sleep(Duration::from_millis(100)).await;
+        }
+        count
+    }
+
+    // Bob's todo reaches every member
+    let (_, todo) = app.request(Method::POST, "/todos", Some(bob), Some(json!({ "text": "Plan the offsite" }))).await;
+    assert_eq!(deliveries(&pool, "alice-hook", 1).await, 1);
+    assert_eq!(deliveries(&pool, "bob-hook", 1).await, 1);
+
+    // Once bob is removed, events on it stop reaching him, though he created it
+    let (_, me) = app.request(Method::GET, "/auth/me", Some(bob), None).await;
+    let (status, _) = app.request(Method::DELETE, &format!("/workspaces/{}/members/{}", id, me["id"].as_str().unwrap()), Some(alice), None).await;
+    assert_eq!(status, StatusCode::NO_CONTENT);
+    let (status, _) = app.request(Method::POST, &format!("/toggle/{}", todo["id"].as_str().unwrap()), Some(alice), None).await;
+    assert_eq!(status, StatusCode::OK);
+    assert_eq!(deliveries(&pool, "alice-hook", 2).await, 2);
+    assert_eq!(deliveries(&pool, "bob-hook", 1).await, 1);
+    pool.close().await;
+}
+
+#[tokio::test]
+async fn records_escalations_in_the_event_log() {
+    let app = app("escalation-events").await;
+    let token = app.register("alice").await;
+    let new_todo = json!({ "text": "Submit the report", "priority": "low", "due": "in 2 hours" });
+    let (status, _) = app.request(Method::POST, "/todos", Some(&token), Some(new_todo)).await;
+    assert_eq!(status, StatusCode::CREATED);
+
+    let pool = sqlx::SqlitePool::connect(&format!("sqlite:{}", app.path.display())).await.unwrap();
+    sqlx::query("UPDATE users SET is_admin = TRUE").execute(&pool).await.unwrap();
+    pool.close().await;
+    let (status, _) = app.request(Method::POST, "/admin/jobs/escalation/run", Some(&token), None).await;
+    assert_eq!(status, StatusCode::ACCEPTED);
+
+    // The job runs in the background
+    let mut last = Value::Null;
+    for _ in 0..50 {
+        let (_, log) = app.request(Method::GET, "/events", Some(&token), None).await;
+        last = log["events"].as_array().unwrap().last().unwrap().clone();
+        if last["event"] == "todo_updated" {
+            break;
+        }
+        tokio::time::sleep(Duration::from_millis(100)).await;
+    }
+    assert_eq!(last["event"], "todo_updated");
+    assert_eq!(last["actor_id"], "escalation");
+    assert_eq!(last["data"]["todo"]["priority"], "medium");
+}
+
+#[tokio::test]
+async fn emails_weekly_reports_to_users_who_opt_in() {
+    let app = app("weekly-reports").await;
+    let alice = app.register("alice").await;
+    app.register("bob").await;
+    let (_, settings) = app.request(Method::GET, "/settings", Some(&alice), None).await;
+    assert_eq!(settings["weekly_report"], false);
+    let (status, settings) = app.request(Method::PUT, "/settings", Some(&alice), Some(json!({ "weekly_report": true }))).await;
+    assert_eq!(status, StatusCode::OK);
+    assert_eq!(settings["weekly_report"], true);
+
+    let pool = sqlx::SqlitePool::connect(&format!("sqlite:{}", app.path.display())).await.unwrap();
+    sqlx::query("UPDATE users SET is_admin = TRUE WHERE username = 'alice'").execute(&pool).await.unwrap();
+    pool.close().await;
+
+    // Both get last week's report, only alice by email; a second run has
+    // nothing new to generate or send
+    for (generated, emailed) in [(2, 1), (0, 0)] {
+        let (status, _) = app.request(Method::POST, "/admin/jobs/weekly_reports/run", Some(&alice), None).await;
+        assert_eq!(status, StatusCode::ACCEPTED);
+        let mut run = Value::Null;
+        for _ in 0..50 {
+            let (_, runs) = app.request(Method::GET, "/admin/jobs/weekly_reports/runs", Some(&alice), None).await;
+            run = runs[0].clone();
+            if run["status"] != "running" {
+                break;
+            }
+            tokio::time::sleep(Duration::from_millis(100)).await;
+        }
+        assert_eq!(run["status"], "succeeded");
+        let output = run["output"].as_str().unwrap();
+        assert!(output.starts_with(&format!("Generated {} reports", generated)), "{}", output);
+        assert!(output.ends_with(&format!("emailed {}", emailed)), "{}", output);
+    }
+    let (_, reports) = app.request(Method::GET, "/reports/weekly", Some(&alice), None).await;
+    assert_eq!(reports.as_array().unwrap().len(), 1);
+}
+
+#[tokio::test]
+async fn reads_the_event_log_since_a_sequence_number() {
+    let app = app("events").await;
+    let alice = app.register("alice").await;
+    let bob = app.register("bob").await;
+
+    let (_, todo) = app.request(Method::POST, "/todos", Some(&alice), Some(json!({ "text": "Renew passport" }))).await;
+    let id = todo["id"].as_str().unwrap();
+    app.request(Method::PATCH, &format!("/todos/{}", id), Some(&alice), Some(json!({ "text": "Renew passport and ID", "expected_version": 1 }))).await;
+    app.request(Method::POST, &format!("/toggle/{}", id), Some(&alice), None).await;
+    // Only the change that happened is logged
+    let (status, _) = app.request(Method::PATCH, &format!("/todos/{}", id), Some(&alice), Some(json!({ "text": "Stale", "expected_version": 1 }))).await;
+    assert_eq!(status, StatusCode::CONFLICT);
+
+    let (status, log) = app.request(Method::GET, "/events", Some(&alice), None).await;
+    assert_eq!(status, StatusCode::OK);
+    let kinds: Vec<&str> = log["events"].as_array().unwrap().iter().map(|event| event["event"].as_str().unwrap()).collect();
+    assert_eq!(kinds, ["user_registered", "todo_created", "todo_updated", "todo_completed"]);
+    assert_eq!(log["events"][2]["data"]["text_changed"], true);
+    assert_eq!(log["events"][2]["data"]["todo"]["text"], "Renew passport and ID");
+    assert_eq!(log["events"][3]["subject_id"], id);
+    assert_eq!(log["has_more"], false);
+    let seqs: Vec<i64> = log["events"].as_array().unwrap().iter().map(|event| event["seq"].as_i64().unwrap()).collect();
+    assert!(seqs.windows(2).all(|pair| pair[0] < pair[1]));
+    assert_eq!(log["last_seq"], seqs[3]);
+
+    // Reading on picks up only what's new
+    let (_, log) = app.request(Method::GET, &format!("/events?since={}&limit=1", seqs[1]), Some(&alice), None).await;
+    assert_eq!(log["events"].as_array().unwrap().len(), 1);
+    assert_eq!(log["events"][0]["seq"], seqs[2]);
+    assert_eq!(log["has_more"], true);
+    let (_, log) = app.request(Method::GET, &format!("/events?since={}", seqs[3]), Some(&alice), None).await;
+    assert_eq!(log["events"], json!([]));
+    assert_eq!(log["last_seq"], seqs[3]);
+
+    // Each scope has its own stream
+    let (_, log) = app.request(Method::GET, "/events", Some(&bob), None).await;
+    assert_eq!(log["events"].as_array().unwrap().len(), 1);
+    assert_eq!(log["events"][0]["event"], "user_registered");
+}
@@ -0,0 +1,58 @@
+use axum::body::Body;
+use axum::http::{header, Method, Request, StatusCode};
+use serde_json::json;
+use std::io::Write;
+use std::process::{Command, Output, Stdio};
+use tower::ServiceExt;
+
+mod common;
+
+/// Runs the CLI against `database_url`, feeding `stdin` to it.
+fn todo_app(database_url: &str, args: &[&str], env: &[(&str, &str)], stdin: &str) -> Output {
+    let mut child = Command::new(env!("CARGO_BIN_EXE_todo-app"))
+        .args(args)
+        .env("DATABASE_URL", database_url)
+        .env("JWT_SECRET", common::JWT_SECRET)
+        .env_remove("TODO_APP_PASSWORD")
+        .envs(env.iter().copied())
+        .stdin(Stdio::piped())
+        .stdout(Stdio::piped())
+        .stderr(Stdio::piped())
+        .spawn()
+        .unwrap();
+    child.stdin.take().unwrap().write_all(stdin.as_bytes()).unwrap();
+    child.wait_with_output().unwrap()
+}
+
+#[tokio::test]
+async fn takes_passwords_from_stdin_or_the_environment() {
+    let path = std::env::temp_dir().join(format!("todo-app-cli-{}.db", std::process::id()));
+    let database_url = format!("sqlite:{}", path.display());
+
+    // Not accepted as an argument any more
+    let output = todo_app(&database_url, &["user", "create", "--username", "reed", "--email", "reed@example.com", "--password", "x"], &[], "");
+    assert!(!output.status.success());
+
+    let output = todo_app(&database_url, &["user", "create", "--username", "reed", "--email", "reed@example.com"], &[], "");
+    assert!(!output.status.success());
+    assert!(String::from_utf8_lossy(&output.stderr).contains("no password given"));
+
+    let output = todo_app(&database_url, &["user", "create", "--username", "reed", "--email", "reed@example.com"], &[], "marble-harbor-violet-27\n");
+    assert!(output.status.success(), "{}", String::from_utf8_lossy(&output.stderr));
+    let output = todo_app(&database_url, &["user", "reset-password", "reed"], &[("TODO_APP_PASSWORD", "copper-thistle-orbit-64")], "");
+    assert!(output.status.success(), "{}", String::from_utf8_lossy(&output.stderr));
+
+    let config = common::config(&database_url);
+    let router = todo_app::build_app(&config).await.expect("app should start");
+    for (password, expected) in [("marble-harbor-violet-27", StatusCode::UNAUTHORIZED), ("copper-thistle-orbit-64", StatusCode::OK)] {
+        let request = Request::builder()
+            .method(Method::POST)
+            .uri("/auth/login")
+            .header(header::CONTENT_TYPE, "application/json")
+            .body(Body::from(json!({ "username": "reed", "password": password }).to_string()))
+            .unwrap();
+        assert_eq!(router.clone().oneshot(request).await.unwrap().status(), expected);
+    }
+
+    common::remove_database(&path);
+}
@@ -0,0 +1,61 @@
+//! Helpers for the integration tests that run the app in-process. Each test
+//! binary uses only some of them.
+#![allow(dead_code)]
+
+use axum::body::Body;
+use axum::http::{header, HeaderMap, Method, Request, StatusCode};
+use axum::Router;
+use serde_json::Value;
+use std::path::Path;
+use tower::ServiceExt;
+
+use todo_app::simple_auth::TokenConfig;
+use todo_app::simple_db::DatabaseConfig;
+use todo_app::Config;
+
+pub const JWT_SECRET: &str = "0123456789abcdef0123456789abcdef";
+
+/// The app's configuration for the database at `database_url`, with
+/// background jobs off.
+pub fn config(database_url: &str) -> Config {
+    Config {
+        database: DatabaseConfig { url: database_url.to_string(), ..DatabaseConfig::from_env() },
+        jwt_secret: JWT_SECRET.to_string(),
+        tokens: TokenConfig { expiry: chrono::Duration::hours(1), ..TokenConfig::from_env() },
+        background_jobs: false,
+    }
+}
+
+/// Sends a request to `router`, as JSON when there's a body, and returns the
+/// response's status and JSON body (`null` if it has none).
+pub async fn request(router: &Router, method: Method, uri: &str, token: Option<&str>, body: Option<Value>) -> (StatusCode, Value) {
+    let (status, _, body) = request_with_headers(router, method, uri, token, body).await;
+    (status, body)
+}
+
+/// Like `request`, also returning the response headers.
+pub async fn request_with_headers(router: &Router, method: Method, uri: &str, token: Option<&str>, body: Option<Value>) -> (StatusCode, HeaderMap, Value) {
+    let mut request = Request::builder().method(method).uri(uri);
+    if let Some(token) = token {
+        request = request.header(header::AUTHORIZATION, format!("Bearer {}", token));
+    }
+    let body = match body {
+        Some(body) => {
+            request = request.header(header::CONTENT_TYPE, "application/json");
+            Body::from(body.to_string())
+        }
+        None => Body::empty(),
+    };
+
+    let response = router.clone().oneshot(request.body(body).unwrap()).await.unwrap();
+    let (status, headers) = (response.status(), response.headers().clone());
+    let bytes = axum::body::to_bytes(response.into_body(), usize::MAX).await.unwrap();
+    (status, headers, serde_json::from_slice(&bytes).unwrap_or(Value::Null))
+}
+
+/// Removes a SQLite database file along with its WAL files.
+pub fn remove_database(path: &Path) {
+    for suffix in ["", "-wal", "-shm"] {
+        let _ = std::fs::remove_file(format!("{}{}", path.display(), suffix));
+    }
+}
@@ -0,0 +1,196 @@
+use axum::http::{Method, StatusCode};
+use serde_json::{json, Value};
+use sqlx::{Row, SqlitePool};
+
+use common::request;
+
+mod common;
+
+// The first app to start with a key switches encryption on for the whole
+// process, and the API tests read the rows they check as plaintext.
+
+const KEY: &str = "AAECAwQFBgcICQoLDA0ODxAREhMUFRYXGBkaGxwdHh8=";
+
+#[tokio::test]
+async fn encrypts_todo_text_and_notes_at_rest() {
+    let path = std::env::temp_dir().join(format!("todo-app-encryption-{}.db", std::process::id()));
+    let url = format!("sqlite:{}", path.display());
+    let mut config = common::config(&url);
+    config.database.encryption_key = Some(KEY.to_string());
+    let router = todo_app::build_app(&config).await.expect("app should start");
+
+    let user = json!({ "username": "vera", "email": "vera@example.com", "password": "violet-kettle-harbour-93" });
+    let (_, body) = request(&router, Method::POST, "/auth/register", None, Some(user)).await;
+    let token = body["token"].as_str().unwrap();
+
+    let new_todo = json!({ "text": "Call the bank", "notes": "About the mortgage" });
+    let (status, _) = request(&router, Method::POST, "/todos", Some(token), Some(new_todo)).await;
+    assert_eq!(status, StatusCode::CREATED);
+    let (_, todos) = request(&router, Method::GET, "/todos", Some(token), None).await;
+    let todo = &todos[0];
+    let id = todo["id"].as_str().unwrap();
+    let update = json!({ "notes": "About the loan", "expected_version": todo["version"] });
+    let (status, todo) = request(&router, Method::PATCH, &format!("/todos/{}", id), Some(token), Some(update)).await;
+    assert_eq!(status, StatusCode::OK);
+    assert_eq!(todo["text"], "Call the bank");
+    assert_eq!(todo["notes"], "About the loan");
+
+    // Stored sealed, while plaintext from before encryption still reads
+    let pool = SqlitePool::connect(&url).await.unwrap();
+    let row = sqlx::query("SELECT text, notes, user_id FROM todos WHERE id = ?").bind(id).fetch_one(&pool).await.unwrap();
+    let (text, notes): (String, String) = (row.get("text"), row.get("notes"));
+    assert!(text.starts_with("enc:v1:") && !text.contains("bank"), "{}", text);
+    assert!(notes.starts_with("enc:v1:") && !notes.contains("loan"), "{}", notes);
+    let user_id: String = row.get("user_id");
+    sqlx::query("INSERT INTO todos (id, text, user_id) VALUES ('plain', 'Water the plants', ?)").bind(&user_id).execute(&pool).await.unwrap();
+
+    let (_, todos) = request(&router, Method::GET, "/todos", Some(token), None).await;
+    let mut texts: Vec<&str> = todos.as_array().unwrap().iter().map(|todo| todo["text"].as_str().unwrap()).collect();
+    texts.sort();
+    assert_eq!(texts, ["Call the bank", "Water the plants"]);
+
+    pool.close().await;
+    common::remove_database(&path);
+}
+
+#[tokio::test]
+async fn encrypts_notification_messages_at_rest() {
+    let path = std::env::temp_dir().join(format!("todo-app-encryption-notifications-{}.db", std::process::id()));
+    let url = format!("sqlite:{}", path.display());
+    let mut config = common::config(&url);
+    config.database.encryption_key = Some(KEY.to_string());
+    let router = todo_app::build_app(&config).await.expect("app should start");
+
+    let mut tokens = Vec::new();
+    for name in ["vera", "walt"] {
+        let user = json!({ "username": name, "email": format!("{}@example.com", name), "password": "violet-kettle-harbour-93" });
+        let (_, body) = request(&router, Method::POST, "/auth/register", None, Some(user)).await;
+        tokens.push(body["token"].as_str().unwrap().to_string());
+    }
+    let (vera, walt) = (&tokens[0], &tokens[1]);
+    let (_, workspace) = request(&router, Method::POST, "/workspaces", Some(vera), Some(json!({ "name": "Home" }))).await;
+    let workspace_id = workspace["id"].as_str().unwrap();
+    request(&router, Method::POST, &format!("/workspaces/{}/members", workspace_id), Some(vera), Some(json!({ "username": "walt" }))).await;
+    let (_, switched) = request(&router, Method::POST, "/workspaces/switch", Some(vera), Some(json!({ "workspace_id": workspace_id }))).await;
+    let vera = switched["token"].as_str().unwrap();
+    let (_, me) = request(&router, Method::GET, "/auth/me", Some(walt), None).await;
+
+    let (_, todo) = request(&router, Method::POST, "/todos", Some(vera), Some(json!({ "text": "Call the bank" }))).await;
+    let assign = json!({ "assignee_id": me["id"] });
+    let (status, _) = request(&router, Method::POST, &format!("/todos/{}/assign", todo["id"].as_str().unwrap()), Some(vera), Some(assign)).await;
+    assert_eq!(status, StatusCode::OK);
+
+    // Sent by the outbox relay, in the background
+    let mut notifications = Value::Null;
+    for _ in 0..50 {
+        (_, notifications) = request(&router, Method::GET, "/notifications", Some(walt), None).await;
+        if !notifications.as_array().unwrap().is_empty() {
+            break;
+        }
+        tokio::time::sleep(std::time::Duration::from_millis(100)).await;
+    }
+    assert_eq!(notifications[0]["message"], "You were assigned \"Call the bank\"");
+
+    let pool = SqlitePool::connect(&url).await.unwrap();
+    let message: String = sqlx::query("SELECT message FROM notifications").fetch_one(&pool).await.unwrap().get("message");
+    assert!(message.starts_with("enc:v1:") && !message.contains("bank"), "{}", message);
+
+    pool.close().await;
+    common::remove_database(&path);
+}
+
+#[tokio::test]
+async fn encrypts_webhook_payloads_at_rest() {
+    let path = std::env::temp_dir().join(format!("todo-app-encryption-webhooks-{}.db", std::process::id()));
+    let url = format!("sqlite:{}", path.display());
+    let mut config = common::config(&url);
+    config.database.encryption_key = Some(KEY.to_string());
+    let router = todo_app::build_app(&config).await.expect("app should start");
+
+    let user = json!({ "username": "vera", "email": "vera@example.com", "password": "violet-kettle-harbour-93" });
+    let (_, body) = request(&router, Method::POST, "/auth/register", None, Some(user)).await;
+    let token = body["token"].as_str().unwrap();
+    let (_, me) = request(&router, Method::GET, "/auth/me", Some(token), None).await;
+
+    // Added directly, since the API only takes public URLs here; the delivery
+    // is refused, but logged with its payload all the same
+    let pool = SqlitePool::connect(&url).await.unwrap();
+    sqlx::query("INSERT INTO webhooks (id, user_id, url, secret, events, created_at) VALUES ('hook', ?, 'http://127.0.0.1:9/hook', 'whsec_test', '[]', ?)")
+        .bind(me["id"].as_str().unwrap())
+        .bind(chrono::Utc::now())
+        .execute(&pool)
+        .await
+        .unwrap();
+    let (status, _) = request(&router, Method::POST, "/todos", Some(token), Some(json!({ "text": "Call the bank" }))).await;
+    assert_eq!(status, StatusCode::CREATED);
+
+    let mut payload = None;
+    for _ in 0..50 {
+        payload = sqlx::query_scalar::<_, String>("SELECT payload FROM webhook_deliveries").fetch_optional(&pool).await.unwrap();
+        if payload.is_some() {
+            break;
+        }
+        tokio::time::sleep(std::time::Duration::from_millis(100)).await;
+    }
+    let payload = payload.expect("delivery should be logged");
+    assert!(payload.starts_with("enc:v1:") && !payload.contains("bank"), "{}", payload);
+
+    let (status, deliveries) = request(&router, Method::GET, "/webhooks/hook/deliveries", Some(token), None).await;
+    assert_eq!(status, StatusCode::OK);
+    assert_eq!(deliveries[0]["payload"]["data"]["todo"]["text"], "Call the bank");
+
+    pool.close().await;
+    common::remove_database(&path);
+}
+
+#[tokio::test]
+async fn encrypts_events_written_before_the_key() {
+    let path = std::env::temp_dir().join(format!("todo-app-encryption-events-{}.db", std::process::id()));
+    let url = format!("sqlite:{}", path.display());
+    let mut config = common::config(&url);
+    config.database.encryption_key = Some(KEY.to_string());
+    let router = todo_app::build_app(&config).await.expect("app should start");
+
+    let user = json!({ "username": "vera", "email": "vera@example.com", "password": "violet-kettle-harbour-93" });
+    let (_, body) = request(&router, Method::POST, "/auth/register", None, Some(user)).await;
+    let token = body["token"].as_str().unwrap();
+    let (status, _) = request(&router, Method::POST, "/todos", Some(token), Some(json!({ "text": "Call the bank" }))).await;
+    assert_eq!(status, StatusCode::CREATED);
+
+    // As if recorded before the key was set
+    let pool = SqlitePool::connect(&url).await.unwrap();
+    let event: (i64, String) = sqlx::query_as("SELECT seq, subject_id FROM events WHERE event = 'todo_created'").fetch_one(&pool).await.unwrap();
+    let (_, log) = request(&router, Method::GET, "/events", Some(token), None).await;
+    let data = &log["events"].as_array().unwrap().last().unwrap()["data"];
+    sqlx::query("UPDATE events SET data = ? WHERE seq = ?").bind(data.to_string()).bind(event.0).execute(&pool).await.unwrap();
+    let stored = json!({ "event": "todo_created", "todo": data["todo"], "actor_id": "someone" }).to_string();
+    sqlx::query("INSERT INTO outbox (event, subject_id, payload, created_at, dispatched_at) VALUES ('todo_created', ?, ?, ?, ?)")
+        .bind(&event.1)
+        .bind(&stored)
+        .bind(chrono::Utc::now())
+        .bind(chrono::Utc::now())
+        .execute(&pool)
+        .await
+        .unwrap();
+
+    let output = std::process::Command::new(env!("CARGO_BIN_EXE_todo-app"))
+        .args(["db", "encrypt"])
+        .env("DATABASE_URL", &url)
+        .env("JWT_SECRET", "0123456789abcdef0123456789abcdef")
+        .env("TODO_ENCRYPTION_KEY", KEY)
+        .output()
+        .unwrap();
+    assert!(output.status.success(), "{}", String::from_utf8_lossy(&output.stderr));
+
+    for query in ["SELECT data FROM events", "SELECT payload FROM outbox"] {
+        for value in sqlx::query_scalar::<_, String>(query).fetch_all(&pool).await.unwrap() {
+            assert!(value.starts_with("enc:v1:") && !value.contains("bank"), "{}: {}", query, value);
+        }
+    }
+    // Sealed the way the app reads them back
+    let (_, log) = request(&router, Method::GET, "/events", Some(token), None).await;
+    assert_eq!(log["events"].as_array().unwrap().last().unwrap()["data"]["todo"]["text"], "Call the bank");
+
+    pool.close().await;
+    common::remove_database(&path);
+}
@@ -0,0 +1,85 @@
+use std::fs;
+use std::io::{Read, Write};
+use std::net::{TcpListener, TcpStream};
+use std::path::PathBuf;
+use std::process::{Child, Command, Stdio};
+use std::sync::Arc;
+use std::time::{Duration, Instant};
+
+/// The app binary running in HTTPS mode; killed and cleaned up on drop.
+struct Server {
+    child: Child,
+    dir: PathBuf,
+}
+
+impl Drop for Server {
+    fn drop(&mut self) {
+        let _ = self.child.kill();
+        let _ = self.child.wait();
+        let _ = fs::remove_dir_all(&self.dir);
+    }
+}
+
+fn free_port() -> u16 {
+    TcpListener::bind("127.0.0.1:0").unwrap().local_addr().unwrap().port()
+}
+
+fn connect(port: u16) -> TcpStream {
+    let deadline = Instant::now() + Duration::from_secs(10);
+    loop {
+        match TcpStream::connect(("127.0.0.1", port)) {
+            Ok(stream) => return stream,
+            Err(_) if Instant::now() < deadline => std::thread::sleep(Duration::from_millis(50)),
+            Err(e) => panic!("server did not start listening: {}", e),
+        }
+    }
+}
+
+#[test]
+fn serves_requests_over_tls() {
+    let dir = std::env::temp_dir().join(format!("todo-app-https-{}", std::process::id()));
+    fs::create_dir_all(&dir).unwrap();
+
+    let cert = rcgen::generate_simple_self_signed(vec!["localhost".to_string()]).unwrap();
+    fs::write(dir.join("cert.pem"), cert.serialize_pem().unwrap()).unwrap();
+    fs::write(dir.join("key.pem"), cert.serialize_private_key_pem()).unwrap();
+    fs::File::create(dir.join("todos.db")).unwrap();
+
+    let port = free_port();
+    let child = Command::new(env!("CARGO_BIN_EXE_todo-app"))
+        .env("USE_HTTPS", "true")
+        .env("PORT", port.to_string())
+        .env("CERT_PATH", dir.join("cert.pem"))
+        .env("KEY_PATH", dir.join("key.pem"))
+        .env("DATABASE_URL", format!("sqlite:{}", dir.join("todos.db").display()))
+        .env("DATA_DIR", &dir)
+        .stdout(Stdio::null())
+        .spawn()
+        .unwrap();
+    let _server = Server { child, dir };
+
+    let mut roots = rustls::RootCertStore::empty();
+    roots.add(&rustls::Certificate(cert.serialize_der().unwrap())).unwrap();
+    let config = rustls::ClientConfig::builder()
+        .with_safe_defaults()
+        .with_root_certificates(roots)
+        .with_no_client_auth();
+    let conn = rustls::ClientConnection::new(Arc::new(config), "localhost".try_into().unwrap()).unwrap();
+    let mut tls = rustls::StreamOwned::new(conn, connect(port));
+
+    tls.write_all(b"GET / HTTP/1.1\r\nHost: localhost\r\nConnection: close\r\n\r\n").unwrap();
+
+    let mut response = Vec::new();
+    let mut buf = [0u8; 4096];
+    // The server may close without close_notify; whatever arrived before that is enough
+    while let Ok(n) = tls.read(&mut buf) {
+        if n == 0 {
+            break;
+        }
+        response.extend_from_slice(&buf[..n]);
+    }
+
+    let response = String::from_utf8_lossy(&response);
+    assert!(response.starts_with("HTTP/1.1 200"), "unexpected response: {}", response);
+    assert!(response.contains("Rust Todo App"));
+}
@@ -0,0 +1,74 @@
+use axum::http::{header, Method, StatusCode};
+use axum::Router;
+use serde_json::json;
+use std::time::Duration;
+
+use common::request_with_headers;
+
+mod common;
+
+// Lowers the login guard's free attempts and lockout threshold, and turns off
+// the auth rate limit, for the whole process; the API tests run with the
+// defaults.
+
+const PASSWORD: &str = "saffron-glacier-lantern-71";
+
+async fn login(router: &Router, username: &str, password: &str) -> (StatusCode, Option<u64>) {
+    let (status, headers, _) = request_with_headers(router, Method::POST, "/auth/login", None, Some(json!({ "username": username, "password": password }))).await;
+    let retry_after = headers.get(header::RETRY_AFTER).map(|value| value.to_str().unwrap().parse().unwrap());
+    (status, retry_after)
+}
+
+#[tokio::test(flavor = "multi_thread", worker_threads = 4)]
+async fn delays_and_locks_out_repeated_failures() {
+    // SAFETY: the only test in this binary, set before anything reads the environment
+    unsafe {
+        std::env::set_var("LOGIN_FREE_ATTEMPTS", "2");
+        std::env::set_var("LOGIN_LOCKOUT_THRESHOLD", "8");
+        std::env::set_var("LOGIN_LOCKOUT_MINUTES", "15");
+        std::env::set_var("RATE_LIMIT_AUTH_PER_MINUTE", "0");
+    }
+    let path = std::env::temp_dir().join(format!("todo-app-login-{}.db", std::process::id()));
+    let config = common::config(&format!("sqlite:{}", path.display()));
+    let router = todo_app::build_app(&config).await.expect("app should start");
+    let mut tokens = Vec::new();
+    for username in ["kit", "lou"] {
+        let user = json!({ "username": username, "email": format!("{}@example.com", username), "password": PASSWORD });
+        let (_, _, body) = request_with_headers(&router, Method::POST, "/auth/register", None, Some(user)).await;
+        tokens.push(body["token"].as_str().unwrap().to_string());
+    }
+
+    // The free attempts go through; the one after makes the next wait 2 seconds
+    assert_eq!(login(&router, "kit", "wrong").await, (StatusCode::UNAUTHORIZED, None));
+    assert_eq!(login(&router, "kit", "wrong").await, (StatusCode::UNAUTHORIZED, None));
+    assert_eq!(login(&router, "kit", "wrong").await, (StatusCode::UNAUTHORIZED, None));
+    let (status, retry_after) = login(&router, "kit", PASSWORD).await;
+    assert_eq!(status, StatusCode::TOO_MANY_REQUESTS);
+    assert!(retry_after.is_some_and(|seconds| (1..=2).contains(&seconds)));
+    tokio::time::sleep(Duration::from_millis(2100)).await;
+    assert_eq!(login(&router, "kit", PASSWORD).await.0, StatusCode::OK);
+    // Which started the count over
+    assert_eq!(login(&router, "kit", "wrong").await, (StatusCode::UNAUTHORIZED, None));
+
+    // Guesses sent in parallel all pass the check before any is counted, and
+    // each still counts: together they reach the lockout
+    let guesses: Vec<_> = (0..12)
+        .map(|_| {
+            let router = router.clone();
+            tokio::spawn(async move { login(&router, "lou", "wrong").await.0 })
+        })
+        .collect();
+    for guess in guesses {
+        assert_eq!(guess.await.unwrap(), StatusCode::UNAUTHORIZED);
+    }
+    let (status, retry_after) = login(&router, "lou", PASSWORD).await;
+    assert_eq!(status, StatusCode::TOO_MANY_REQUESTS);
+    assert!(retry_after.is_some_and(|seconds| seconds > 14 * 60));
+
+    let (_, _, activity) = request_with_headers(&router, Method::GET, "/auth/activity", Some(&tokens[1]), None).await;
+    let kinds: Vec<&str> = activity.as_array().unwrap().iter().map(|event| event["kind"].as_str().unwrap()).collect();
+    assert_eq!(kinds.iter().filter(|kind| **kind == "login_failed").count(), 12);
+    assert_eq!(kinds.iter().filter(|kind| **kind == "account_locked").count(), 1);
+
+    common::remove_database(&path);
+}
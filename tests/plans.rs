@@ -0,0 +1,99 @@
+use axum::body::Body;
+use axum::http::{header, Method, Request, StatusCode};
+use axum::Router;
+use ring::hmac;
+use serde_json::{json, Value};
+use tower::ServiceExt;
+
+use common::request;
+
+mod common;
+
+// Caps free and pro users at a few todos and sets the Stripe webhook secret
+// for the whole process, which the API tests, creating todos freely with
+// billing off, mustn't see.
+
+const STRIPE_SECRET: &str = "whsec_test";
+
+async fn stripe_event(router: &Router, event: Value, secret: &str) -> StatusCode {
+    let payload = event.to_string();
+    let timestamp = chrono::Utc::now().timestamp();
+    let key = hmac::Key::new(hmac::HMAC_SHA256, secret.as_bytes());
+    let tag = hmac::sign(&key, format!("{}.{}", timestamp, payload).as_bytes());
+    let signature: String = tag.as_ref().iter().map(|b| format!("{:02x}", b)).collect();
+    let request = Request::builder()
+        .method(Method::POST)
+        .uri("/billing/stripe/webhook")
+        .header(header::CONTENT_TYPE, "application/json")
+        .header("stripe-signature", format!("t={},v1={}", timestamp, signature))
+        .body(Body::from(payload))
+        .unwrap();
+    router.clone().oneshot(request).await.unwrap().status()
+}
+
+#[tokio::test]
+async fn enforces_quotas_and_premium_features_by_plan() {
+    // SAFETY: the only test in this binary, set before anything reads the environment
+    unsafe {
+        std::env::set_var("QUOTA_MAX_TODOS", "3");
+        std::env::set_var("QUOTA_PRO_MAX_TODOS", "10");
+        std::env::set_var("STRIPE_WEBHOOK_SECRET", STRIPE_SECRET);
+    }
+    let path = std::env::temp_dir().join(format!("todo-app-plans-{}.db", std::process::id()));
+    let config = common::config(&format!("sqlite:{}", path.display()));
+    let router = todo_app::build_app(&config).await.expect("app should start");
+
+    let user = json!({ "username": "quinn", "email": "quinn@example.com", "password": "quartz-lantern-meadow-58" });
+    let (_, body) = request(&router, Method::POST, "/auth/register", None, Some(user)).await;
+    let token = body["token"].as_str().unwrap();
+    let user_id = body["user_id"].as_str().unwrap().to_string();
+
+    let batch = json!([{ "text": "Pack" }, { "text": "Label boxes" }]);
+    let (status, _) = request(&router, Method::POST, "/todos/batch", Some(token), Some(batch)).await;
+    assert_eq!(status, StatusCode::CREATED);
+
+    // A batch that would cross the limit is refused whole
+    let batch = json!([{ "text": "Book van" }, { "text": "Return keys" }]);
+    let (status, mut body) = request(&router, Method::POST, "/todos/batch", Some(token), Some(batch)).await;
+    assert_eq!(status, StatusCode::FORBIDDEN);
+    assert!(body.as_object_mut().unwrap().remove("request_id").is_some_and(|id| id.is_string()));
+    assert_eq!(body, json!({ "error": "Quota exceeded", "quota": "todos", "limit": 3, "used": 2, "message": "Forbidden" }));
+
+    let (status, _) = request(&router, Method::POST, "/todos", Some(token), Some(json!({ "text": "Book van" }))).await;
+    assert_eq!(status, StatusCode::CREATED);
+    let (status, body) = request(&router, Method::POST, "/todos", Some(token), Some(json!({ "text": "Return keys" }))).await;
+    assert_eq!(status, StatusCode::FORBIDDEN);
+    assert_eq!(body["used"], 3);
+
+    let (status, usage) = request(&router, Method::GET, "/usage", Some(token), None).await;
+    assert_eq!(status, StatusCode::OK);
+    assert_eq!(usage, json!({ "plan": "free", "todos": { "used": 3, "limit": 3 } }));
+
+    let slack = json!({ "enabled": true, "address": "https://hooks.slack.com/services/T0/B0/x" });
+    let (status, mut body) = request(&router, Method::PUT, "/notifications/channels/slack", Some(token), Some(slack.clone())).await;
+    assert_eq!(status, StatusCode::PAYMENT_REQUIRED);
+    assert!(body.as_object_mut().unwrap().remove("request_id").is_some_and(|id| id.is_string()));
+    assert_eq!(body, json!({ "error": "Upgrade required", "plan": "free", "feature": "webhooks", "message": "Payment Required" }));
+
+    // Only events signed with the webhook secret count
+    let checkout = json!({
+        "type": "checkout.session.completed",
+        "data": { "object": { "client_reference_id": user_id, "customer": "cus_123" } }
+    });
+    assert_eq!(stripe_event(&router, checkout.clone(), "whsec_other").await, StatusCode::BAD_REQUEST);
+    assert_eq!(stripe_event(&router, checkout, STRIPE_SECRET).await, StatusCode::OK);
+
+    let (_, usage) = request(&router, Method::GET, "/usage", Some(token), None).await;
+    assert_eq!(usage, json!({ "plan": "pro", "todos": { "used": 3, "limit": 10 } }));
+    let (status, _) = request(&router, Method::POST, "/todos", Some(token), Some(json!({ "text": "Return keys" }))).await;
+    assert_eq!(status, StatusCode::CREATED);
+    let (status, _) = request(&router, Method::PUT, "/notifications/channels/slack", Some(token), Some(slack)).await;
+    assert_eq!(status, StatusCode::NO_CONTENT);
+
+    let cancelled = json!({ "type": "customer.subscription.deleted", "data": { "object": { "customer": "cus_123" } } });
+    assert_eq!(stripe_event(&router, cancelled, STRIPE_SECRET).await, StatusCode::OK);
+    let (_, usage) = request(&router, Method::GET, "/usage", Some(token), None).await;
+    assert_eq!(usage["plan"], "free");
+
+    common::remove_database(&path);
+}
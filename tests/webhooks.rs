@@ -0,0 +1,125 @@
+use axum::http::{HeaderMap, Method, StatusCode};
+use axum::routing::post;
+use axum::Router;
+use ring::hmac;
+use serde_json::{json, Value};
+use std::sync::{Arc, Mutex};
+use std::time::Duration;
+
+use common::request;
+
+mod common;
+
+// Allows webhooks to private addresses for the whole process, so the
+// receiver below can listen on 127.0.0.1; the API tests keep the default of
+// refusing them.
+
+type Received = Arc<Mutex<Vec<(HeaderMap, String)>>>;
+
+/// A receiver on a local port that answers its first request with `500`
+/// and later ones with `200`.
+async fn receiver() -> (String, Received) {
+    let received = Received::default();
+    let log = received.clone();
+    let app = Router::new().route(
+        "/hook",
+        post(move |headers: HeaderMap, body: String| async move {
+            let mut log = log.lock().unwrap();
+            log.push((headers, body));
+            if log.len() == 1 {
+                (StatusCode::INTERNAL_SERVER_ERROR, "not yet")
+            } else {
+                (StatusCode::OK, "thanks")
+            }
+        }),
+    );
+    let listener = tokio::net::TcpListener::bind("127.0.0.1:0").await.unwrap();
+    let url = format!("http://{}/hook", listener.local_addr().unwrap());
+    tokio::spawn(async move { axum::serve(listener, app).await.unwrap() });
+    (url, received)
+}
+
+#[tokio::test]
+async fn logs_and_redelivers_webhook_deliveries() {
+    // SAFETY: the only test in this binary, set before anything reads the environment
+    unsafe {
+        std::env::set_var("WEBHOOKS_ALLOW_PRIVATE_URLS", "true");
+    }
+    let path = std::env::temp_dir().join(format!("todo-app-webhooks-{}.db", std::process::id()));
+    let config = common::config(&format!("sqlite:{}", path.display()));
+    let router = todo_app::build_app(&config).await.expect("app should start");
+    let (url, received) = receiver().await;
+
+    let user = json!({ "username": "wren", "email": "wren@example.com", "password": "quartz-lantern-meadow-58" });
+    let (_, body) = request(&router, Method::POST, "/auth/register", None, Some(user)).await;
+    let token = body["token"].as_str().unwrap();
+
+    let (status, _) = request(&router, Method::POST, "/webhooks", Some(token), Some(json!({ "url": url, "events": ["todo_hatched"] }))).await;
+    assert_eq!(status, StatusCode::UNPROCESSABLE_ENTITY);
+    let (status, webhook) = request(&router, Method::POST, "/webhooks", Some(token), Some(json!({ "url": url, "events": ["todo_created"] }))).await;
+    assert_eq!(status, StatusCode::CREATED);
+    let id = webhook["id"].as_str().unwrap();
+    let secret = webhook["secret"].as_str().unwrap();
+    let (_, webhooks) = request(&router, Method::GET, "/webhooks", Some(token), None).await;
+    assert_eq!(webhooks.as_array().unwrap().len(), 1);
+    assert!(webhooks[0].get("secret").is_none());
+
+    let (status, _) = request(&router, Method::POST, "/todos", Some(token), Some(json!({ "text": "Water the ferns" }))).await;
+    assert_eq!(status, StatusCode::CREATED);
+
+    // Handed to the webhook and sent in the background; the receiver refuses it
+    let deliveries_uri = format!("/webhooks/{}/deliveries", id);
+    let mut deliveries = Value::Null;
+    for _ in 0..50 {
+        (_, deliveries) = request(&router, Method::GET, &deliveries_uri, Some(token), None).await;
+        if deliveries[0]["status"].as_str().is_some_and(|status| status != "pending") {
+            break;
+        }
+        tokio::time::sleep(Duration::from_millis(100)).await;
+    }
+    let first = &deliveries[0];
+    assert_eq!(deliveries.as_array().unwrap().len(), 1);
+    assert_eq!(first["event"], "todo_created");
+    assert_eq!(first["status"], "failed");
+    assert_eq!(first["response_status"], 500);
+    assert_eq!(first["response_body"], "not yet");
+    assert!(first["duration_ms"].as_i64().is_some());
+    assert_eq!(first["payload"]["data"]["todo"]["text"], "Water the ferns");
+
+    let redeliver_uri = format!("{}/{}/redeliver", deliveries_uri, first["id"].as_str().unwrap());
+    let (status, redelivery) = request(&router, Method::POST, &redeliver_uri, Some(token), None).await;
+    assert_eq!(status, StatusCode::CREATED);
+    assert_eq!(redelivery["status"], "succeeded");
+    assert_eq!(redelivery["response_status"], 200);
+    assert_eq!(redelivery["redelivery_of"], first["id"]);
+    assert_eq!(redelivery["event_id"], first["event_id"]);
+
+    // Both attempts carried the same event, each signed with the webhook's secret
+    let received = received.lock().unwrap().clone();
+    assert_eq!(received.len(), 2);
+    assert_eq!(received[0].1, received[1].1);
+    for (headers, body) in &received {
+        assert_eq!(headers["x-webhook-event"], "todo_created");
+        let signature = headers["x-webhook-signature"].to_str().unwrap();
+        let (timestamp, signature) = signature.strip_prefix("t=").unwrap().split_once(",v1=").unwrap();
+        let key = hmac::Key::new(hmac::HMAC_SHA256, secret.as_bytes());
+        let expected: String = hmac::sign(&key, format!("{}.{}", timestamp, body).as_bytes()).as_ref().iter().map(|b| format!("{:02x}", b)).collect();
+        assert_eq!(signature, expected);
+    }
+    assert_eq!(received[1].0["x-webhook-delivery"], redelivery["id"].as_str().unwrap());
+
+    // Completing isn't an event this webhook wants
+    let (_, todos) = request(&router, Method::GET, "/todos", Some(token), None).await;
+    let toggle = format!("/toggle/{}", todos[0]["public_id"].as_str().unwrap());
+    request(&router, Method::POST, &toggle, Some(token), None).await;
+    let (_, body) = request(&router, Method::GET, &format!("{}?limit=1", deliveries_uri), Some(token), None).await;
+    assert_eq!(body["items"][0]["id"], redelivery["id"]);
+    assert!(body["next_cursor"].is_string());
+
+    let (status, _) = request(&router, Method::DELETE, &format!("/webhooks/{}", id), Some(token), None).await;
+    assert_eq!(status, StatusCode::NO_CONTENT);
+    let (status, _) = request(&router, Method::GET, &deliveries_uri, Some(token), None).await;
+    assert_eq!(status, StatusCode::NOT_FOUND);
+
+    common::remove_database(&path);
+}